@@ -1,12 +1,10 @@
-use chrono;
 use clap::Parser;
-use integrity_common::{Baseline, FileIntegrityEntry, Result, IntegrityError};
-use sha2::{Digest, Sha512};
+use integrity_client::{ClientArgs, MetadataClient};
+use integrity_common::{AccountRecords, Baseline, BaselineSigner, DerivedBaseline, HashAlgorithm, ScanConfig, MacPolicy, Result, IntegrityError, SECURITY_SYSCTLS, SignerRole, TrustStore, parse_cmdline, read_listeners, read_sysctls};
+use integrity_scanner::Scanner;
 use std::fs;
-use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use tracing::{info, error, warn};
-use walkdir::{DirEntry, WalkDir};
 
 #[derive(Parser, Debug)]
 #[command(name = "baseline-collector")]
@@ -18,136 +16,183 @@ struct Args {
     #[arg(long)]
     image_id: String,
 
-    #[arg(long, default_value = "http://localhost:8080")]
-    metadata_url: String,
-}
+    /// TOML file of paths to exclude, include and record metadata-only; give agents the same file
+    #[arg(long)]
+    scan_config: Option<PathBuf>,
 
-/// Directories to exclude from scanning
-const EXCLUDED_DIRS: &[&str] = &[
-    "/proc", "/sys", "/dev", "/run", "/tmp", "/var/tmp", "/var/log",
-];
+    /// Digests to record, comma separated (sha512, sha256, blake3); agents check files by the first
+    #[arg(long, value_delimiter = ',', default_value = "sha512")]
+    hash_algo: Vec<HashAlgorithm>,
 
-fn should_exclude(entry: &DirEntry) -> bool {
-    let path = entry.path();
+    /// Store the baseline as a delta on this image's baseline (e.g. the base OS image)
+    #[arg(long)]
+    extends: Option<String>,
 
-    // Skip if it's a directory and matches excluded paths
-    if path.is_dir() {
-        let path_str = path.to_string_lossy();
-        return EXCLUDED_DIRS.iter().any(|&excluded| path_str.starts_with(excluded));
-    }
+    /// Extend this stored version of --extends (see `integrity-ctl versions`) instead of its current baseline
+    #[arg(long, requires = "extends")]
+    extends_version: Option<u64>,
 
-    // Skip special files (devices, sockets, etc.)
-    if let Ok(metadata) = entry.metadata() {
-        use std::os::unix::fs::FileTypeExt;
-        let file_type = metadata.file_type();
-        if file_type.is_block_device() || file_type.is_char_device() || file_type.is_fifo() || file_type.is_socket() {
-            return true;
-        }
-    }
+    /// Upload only the entries that changed since the stored baseline of --image-id, comparing entry fingerprints; the service rebuilds the rest
+    #[arg(long, conflicts_with = "extends")]
+    delta_upload: bool,
 
-    false
-}
+    /// Stage the baseline to take effect at this time (Unix seconds or RFC 3339); the service keeps serving the current one until then
+    #[arg(long, value_parser = parse_time, conflicts_with_all = ["extends", "delta_upload"])]
+    effective_from: Option<i64>,
 
-fn compute_sha512(path: &Path) -> Result<String> {
-    let mut hasher = Sha512::new();
-    let mut file = fs::File::open(path)?;
-    std::io::copy(&mut file, &mut hasher)?;
-    let result = hasher.finalize();
-    Ok(hex::encode(result))
-}
+    /// Sign the uploaded baseline as its collector with this Ed25519 private key (32 bytes, raw or hex)
+    #[arg(long, visible_alias = "signing-key", conflicts_with_all = ["extends", "delta_upload"])]
+    signing_key_file: Option<PathBuf>,
 
-fn scan_filesystem(root_path: &Path, image_id: &str) -> Result<Baseline> {
-    info!("Starting filesystem scan from: {:?}", root_path);
-    info!("Image ID: {}", image_id);
+    /// Record the loaded SELinux/AppArmor policy state of this host, for images collected on a running instance
+    #[arg(long)]
+    record_mac_policy: bool,
 
-    let mut entries = Vec::new();
-    let walker = WalkDir::new(root_path)
-        .follow_links(false)
-        .into_iter()
-        .filter_entry(|e| !should_exclude(e));
+    /// Record this host's security-relevant sysctls (kernel.*, fs.protected_*, net.* hardening)
+    #[arg(long)]
+    record_sysctls: bool,
 
-    for entry in walker {
-        let entry = entry.map_err(|e| IntegrityError::Walkdir(e.to_string()))?;
-        let path = entry.path();
+    /// Sysctls recorded by --record-sysctls in addition to the built-in list
+    #[arg(long, value_delimiter = ',')]
+    extra_sysctls: Vec<String>,
 
-        // Skip directories
-        if path.is_dir() {
-            continue;
-        }
+    /// Record the kernel command line this host booted with
+    #[arg(long)]
+    record_cmdline: bool,
 
-        // Get relative path from root
-        let relative_path = path.strip_prefix(root_path)
-            .unwrap_or(path)
-            .to_string_lossy()
-            .to_string();
+    /// Record the sockets listening on this host and their binaries; run on the golden image under load
+    #[arg(long)]
+    record_listeners: bool,
 
-        // Skip if path is empty (shouldn't happen, but safety check)
-        if relative_path.is_empty() {
-            continue;
-        }
+    #[command(flatten)]
+    client: ClientArgs,
+}
 
-        match entry.metadata() {
-            Ok(metadata) => {
-                match compute_sha512(path) {
-                    Ok(sha512) => {
-                        let file_entry = FileIntegrityEntry {
-                            path: relative_path,
-                            sha512,
-                            mode: metadata.mode() & 0o7777, // Get permission bits
-                            uid: metadata.uid(),
-                            gid: metadata.gid(),
-                        };
-                        entries.push(file_entry);
-
-                        if entries.len() % 1000 == 0 {
-                            info!("Scanned {} files...", entries.len());
-                        }
-                    }
-                    Err(e) => {
-                        warn!("Failed to hash file {:?}: {}", path, e);
-                    }
-                }
-            }
-            Err(e) => {
-                warn!("Failed to get metadata for {:?}: {}", path, e);
-            }
-        }
+/// Unix seconds, or an RFC 3339 time such as `2026-11-02T03:00:00Z`.
+fn parse_time(value: &str) -> std::result::Result<i64, String> {
+    if let Ok(seconds) = value.parse() {
+        return Ok(seconds);
     }
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|time| time.timestamp())
+        .map_err(|e| format!("expected Unix seconds or an RFC 3339 time: {}", e))
+}
+
+fn scan_filesystem(root_path: &Path, image_id: &str, config: &ScanConfig, algorithms: &[HashAlgorithm]) -> Result<Baseline> {
+    let scanner = Scanner::new(config)
+        .with_algorithms(algorithms)
+        .on_progress(|files| info!("Scanned {} files...", files));
+    let entries = scanner.scan(root_path)?;
 
     let timestamp = chrono::Utc::now().to_rfc3339();
     let baseline = Baseline {
         image_id: image_id.to_string(),
         timestamp,
         entries,
+        hash_algorithm: algorithms.first().copied().unwrap_or_default(),
+        ..Default::default()
     };
 
-    info!("Scan complete. Found {} files", baseline.entries.len());
     Ok(baseline)
 }
 
-async fn upload_baseline(baseline: &Baseline, metadata_url: &str) -> Result<()> {
-    let client = reqwest::Client::new();
-    let url = format!("{}/baselines", metadata_url);
-
-    info!("Uploading baseline to: {}", url);
-
-    let response = client
-        .post(&url)
-        .json(baseline)
-        .send()
-        .await
-        .map_err(|e| integrity_common::IntegrityError::Storage(e.to_string()))?;
-
-    if response.status().is_success() {
-        info!("Baseline uploaded successfully");
-        Ok(())
-    } else {
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        error!("Failed to upload baseline: {}", error_text);
-        Err(integrity_common::IntegrityError::Storage(format!("Upload failed: {}", error_text)))
+async fn upload_baseline(baseline: &Baseline, client: &MetadataClient) -> Result<()> {
+    info!("Uploading baseline to: {}/baselines", client.base_url());
+
+    match client.store_baseline(baseline).await {
+        Ok(()) => {
+            info!("Baseline uploaded successfully");
+            Ok(())
+        }
+        Err(e) => {
+            error!("Failed to upload baseline: {}", e);
+            Err(e)
+        }
+    }
+}
+
+async fn schedule_baseline(baseline: &Baseline, effective_from: i64, client: &MetadataClient) -> Result<()> {
+    info!("Uploading baseline to: {}/baselines, effective from {}", client.base_url(), effective_from);
+
+    match client.schedule_baseline(baseline, effective_from).await {
+        Ok(_) => {
+            info!("Baseline uploaded successfully");
+            Ok(())
+        }
+        Err(e) => {
+            error!("Failed to upload baseline: {}", e);
+            Err(e)
+        }
+    }
+}
+
+async fn upload_derived_baseline(baseline: &Baseline, parent_id: &str, version: Option<u64>, client: &MetadataClient) -> Result<()> {
+    let parent = match version {
+        Some(version) => client.get_baseline_version(parent_id, version).await?,
+        None => client.get_baseline(parent_id).await?,
+    };
+    let derived = DerivedBaseline { extends_version: version, ..DerivedBaseline::from_parent(&parent, baseline) };
+    info!(
+        "Uploading baseline as a delta on {}: {} entries added or changed, {} removed",
+        parent_id,
+        derived.entries.len(),
+        derived.removed.len()
+    );
+
+    match client.store_derived_baseline(&derived).await {
+        Ok(()) => {
+            info!("Baseline uploaded successfully");
+            Ok(())
+        }
+        Err(e) => {
+            error!("Failed to upload baseline: {}", e);
+            Err(e)
+        }
+    }
+}
+
+async fn upload_baseline_delta(baseline: &Baseline, client: &MetadataClient) -> Result<()> {
+    let previous = match client.entry_digests(&baseline.image_id).await {
+        Ok(previous) => previous,
+        Err(IntegrityError::BaselineNotFound(_)) => {
+            info!("No baseline stored for {} yet, uploading it in full", baseline.image_id);
+            return upload_baseline(baseline, client).await;
+        }
+        Err(e) => return Err(e),
+    };
+    let delta = DerivedBaseline::from_digests(&previous, baseline)?;
+    info!(
+        "Uploading changes since the stored baseline of {}: {} entries added or changed, {} removed",
+        baseline.image_id,
+        delta.entries.len(),
+        delta.removed.len()
+    );
+
+    match client.store_baseline_delta(&delta, &previous.digest).await {
+        Ok(true) => {
+            info!("Baseline uploaded successfully");
+            Ok(())
+        }
+        Ok(false) => {
+            warn!("Delta on the baseline of {} refused (it changed while collecting, or the delta is too large), uploading it in full", baseline.image_id);
+            upload_baseline(baseline, client).await
+        }
+        Err(e) => {
+            error!("Failed to upload baseline: {}", e);
+            Err(e)
+        }
     }
 }
 
+/// Starts the signature chain of the uploaded baseline.
+async fn sign_baseline(baseline: &Baseline, signer: &BaselineSigner, client: &MetadataClient) -> Result<()> {
+    let digest = baseline.digest()?;
+    let signature = signer.sign(SignerRole::Collector, &digest, chrono::Utc::now().timestamp(), &[]);
+    let chain = client.sign_baseline(&baseline.image_id, &signature).await?;
+    info!("Signed baseline {} as collector with key {} ({} signatures)", digest, signer.public_key(), chain.len());
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
@@ -157,7 +202,14 @@ async fn main() -> Result<()> {
     info!("Starting baseline collector");
     info!("Scan path: {:?}", args.scan_path);
     info!("Image ID: {}", args.image_id);
-    info!("Metadata service URL: {}", args.metadata_url);
+    info!("Metadata service URL: {}", args.client.metadata_url());
+
+    let client = MetadataClient::from_args(&args.client)?;
+    let scan_config = ScanConfig::load(args.scan_config.as_deref())?;
+    let signer = match &args.signing_key_file {
+        Some(path) => Some(BaselineSigner::from_key_material(&fs::read(path)?)?),
+        None => None,
+    };
 
     // Validate scan path exists
     if !args.scan_path.exists() {
@@ -169,10 +221,83 @@ async fn main() -> Result<()> {
     }
 
     // Scan filesystem
-    let baseline = scan_filesystem(&args.scan_path, &args.image_id)?;
+    let mut baseline = scan_filesystem(&args.scan_path, &args.image_id, &scan_config, &args.hash_algo)?;
+
+    // Lets agents say which account or sudo rule changed, not just that a file did
+    let accounts = AccountRecords::read(&args.scan_path)?;
+    if !accounts.is_empty() {
+        info!(
+            "Recorded {} users, {} groups and {} sudoers files",
+            accounts.users.len(),
+            accounts.groups.len(),
+            accounts.sudo_rules.len()
+        );
+        baseline.accounts = Some(accounts);
+    }
+    let trust_store = TrustStore::read(&args.scan_path);
+    if !trust_store.certificates.is_empty() {
+        info!("Recorded {} trusted CA certificates", trust_store.certificates.len());
+        baseline.trust_store = Some(trust_store);
+    }
+
+    // The policy state lives in the running kernel, not in the scanned tree
+    if args.record_mac_policy {
+        let mac_policy = MacPolicy::read(Path::new("/"))?;
+        if mac_policy.is_empty() {
+            warn!("Neither SELinux nor AppArmor is enabled on this host, no MAC policy state recorded");
+        } else {
+            info!(
+                "Recorded MAC policy state: SELinux {}, AppArmor {}",
+                mac_policy.selinux.as_ref().map_or("disabled", |s| if s.enforcing { "enforcing" } else { "permissive" }),
+                mac_policy.apparmor.as_ref().map_or("disabled".to_string(), |a| format!("{} profiles", a.profiles.len()))
+            );
+            baseline.mac_policy = Some(mac_policy);
+        }
+    }
+    if args.record_sysctls {
+        let names = SECURITY_SYSCTLS.iter().copied().chain(args.extra_sysctls.iter().map(String::as_str));
+        baseline.sysctls = read_sysctls(Path::new("/"), names);
+        info!("Recorded {} sysctls", baseline.sysctls.len());
+    }
+    if args.record_cmdline {
+        baseline.kernel_cmdline = parse_cmdline(&fs::read_to_string("/proc/cmdline")?);
+        info!("Recorded kernel command line: {}", baseline.kernel_cmdline.join(" "));
+    }
+    if args.record_listeners {
+        let mut listeners: Vec<_> = read_listeners(Path::new("/proc"))?.into_iter().map(|open| open.listener).collect();
+        listeners.sort();
+        listeners.dedup();
+        for listener in listeners.iter().filter(|l| l.exe.is_empty()) {
+            warn!("No process found owning {} port {} (a kernel socket, or not running as root)", listener.protocol, listener.port);
+        }
+        info!("Recorded {} listening sockets", listeners.len());
+        baseline.listeners = listeners;
+    }
+
+    // Refuse to upload a baseline the service would reject anyway
+    let violations = baseline.validate();
+    if !violations.is_empty() {
+        for violation in &violations {
+            error!("Invalid baseline: {}", violation);
+        }
+        return Err(IntegrityError::Validation(format!(
+            "{} violation(s) found in collected baseline",
+            violations.len()
+        )));
+    }
 
     // Upload to metadata service
-    upload_baseline(&baseline, &args.metadata_url).await?;
+    match &args.extends {
+        Some(parent_id) => upload_derived_baseline(&baseline, parent_id, args.extends_version, &client).await?,
+        None if args.delta_upload => upload_baseline_delta(&baseline, &client).await?,
+        None => match args.effective_from {
+            Some(effective_from) => schedule_baseline(&baseline, effective_from, &client).await?,
+            None => upload_baseline(&baseline, &client).await?,
+        },
+    }
+    if let Some(signer) = &signer {
+        sign_baseline(&baseline, signer, &client).await?;
+    }
 
     info!("Baseline collection completed successfully");
     Ok(())
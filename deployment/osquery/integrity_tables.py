@@ -0,0 +1,71 @@
+#!/usr/bin/env python3
+"""osquery extension exposing integrity-agent tables.
+
+Requires the osquery-python package and an agent started with
+--mode monitor --osquery-socket /var/run/integrity-agent/osquery.sock
+
+    osqueryi --extension deployment/osquery/integrity_tables.py
+    osquery> SELECT kind, path FROM integrity_anomalies;
+"""
+import json
+import socket
+
+import osquery
+
+AGENT_SOCKET = "/var/run/integrity-agent/osquery.sock"
+
+
+def query_agent(table):
+    with socket.socket(socket.AF_UNIX, socket.SOCK_STREAM) as sock:
+        sock.connect(AGENT_SOCKET)
+        sock.sendall(json.dumps({"table": table}).encode() + b"\n")
+        data = b""
+        while chunk := sock.recv(65536):
+            data += chunk
+    rows = json.loads(data)
+    if isinstance(rows, dict):
+        raise RuntimeError(rows.get("error", "agent error"))
+    return rows
+
+
+@osquery.register_plugin
+class IntegrityBaselineEntries(osquery.TablePlugin):
+    def name(self):
+        return "integrity_baseline_entries"
+
+    def columns(self):
+        return [osquery.TableColumn(name=c, type=osquery.STRING)
+                for c in ("path", "sha512", "mode", "uid", "gid")]
+
+    def generate(self, context):
+        return query_agent(self.name())
+
+
+@osquery.register_plugin
+class IntegrityAnomalies(osquery.TablePlugin):
+    def name(self):
+        return "integrity_anomalies"
+
+    def columns(self):
+        return [osquery.TableColumn(name=c, type=osquery.STRING)
+                for c in ("time", "kind", "path", "detail", "resolved_at")]
+
+    def generate(self, context):
+        return query_agent(self.name())
+
+
+@osquery.register_plugin
+class IntegrityAgentMetrics(osquery.TablePlugin):
+    def name(self):
+        return "integrity_agent_metrics"
+
+    def columns(self):
+        return [osquery.TableColumn(name=c, type=osquery.STRING)
+                for c in ("metric", "value")]
+
+    def generate(self, context):
+        return query_agent(self.name())
+
+
+if __name__ == "__main__":
+    osquery.start_extension(name="acropole_integrity", version="0.1.0")
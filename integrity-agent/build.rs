@@ -0,0 +1,41 @@
+//! Embeds deployment trust anchors into the agent binary.
+//!
+//! Set at build time (all optional):
+//! - `ACROPOLE_SIGNING_KEY`: path to the baseline-signing public key
+//! - `ACROPOLE_CA_CERT`: path to the metadata-service CA certificate (PEM)
+//! - `ACROPOLE_METADATA_URL`: metadata-service URL
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn embed_file(var: &str) -> String {
+    println!("cargo:rerun-if-env-changed={}", var);
+    match env::var(var) {
+        Ok(path) => {
+            println!("cargo:rerun-if-changed={}", path);
+            let path = fs::canonicalize(&path).unwrap_or_else(|e| panic!("{}={}: {}", var, path, e));
+            format!("Some(include_bytes!({:?}))", path)
+        }
+        Err(_) => "None".to_string(),
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=ACROPOLE_METADATA_URL");
+    let metadata_url = match env::var("ACROPOLE_METADATA_URL") {
+        Ok(url) => format!("Some({:?})", url),
+        Err(_) => "None".to_string(),
+    };
+
+    let generated = format!(
+        "pub const SIGNING_PUBLIC_KEY: Option<&[u8]> = {};\n\
+         pub const CA_CERT: Option<&[u8]> = {};\n\
+         pub const METADATA_URL: Option<&str> = {};\n",
+        embed_file("ACROPOLE_SIGNING_KEY"),
+        embed_file("ACROPOLE_CA_CERT"),
+        metadata_url,
+    );
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("trust_anchors.rs");
+    fs::write(out, generated).unwrap();
+}
@@ -0,0 +1,24 @@
+//! What changed in passwd, group, shadow and sudoers.
+//!
+//! An anomaly on one of these files is followed by one anomaly per user,
+//! group, password or sudo rule that differs from the records kept in the
+//! baseline, e.g. "USER_ADDED: etc/passwd (mallory: uid 0, ...)".
+
+use integrity_common::{AccountRecords, AnomalyReport, Baseline};
+use std::path::Path;
+
+/// Anomalies describing the account changes on the filesystem at `root`,
+/// if the baseline has account records.
+pub fn describe(baseline: &Baseline, root: &Path) -> Vec<AnomalyReport> {
+    let Some(recorded) = &baseline.accounts else {
+        return Vec::new();
+    };
+    match AccountRecords::read(root) {
+        Ok(current) => recorded
+            .changes(&current)
+            .into_iter()
+            .map(|change| AnomalyReport::other(change.kind, change.path, change.detail))
+            .collect(),
+        Err(e) => vec![AnomalyReport::other("ACCOUNT_CHECK_FAILED", "etc", format!("cannot read account files: {}", e))],
+    }
+}
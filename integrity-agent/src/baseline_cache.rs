@@ -0,0 +1,133 @@
+//! Fetched baselines kept on disk for when the service is unreachable.
+//!
+//! With `--baseline-cache-dir`, every baseline the agent fetches (each layer,
+//! for `--overlay`) is written there with its signature chain once it passed
+//! the signature checks. With `--offline` as well, a fetch that fails for any
+//! reason but the service not knowing the image falls back to the cached copy,
+//! with a warning. The cached chain is checked again before the copy is used,
+//! so a baseline altered on disk is refused like one altered in transit.
+
+use crate::signatures::{self, SignatureArgs};
+use integrity_client::MetadataClient;
+use integrity_common::{Baseline, BaselineSignature, IntegrityError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct BaselineCacheArgs {
+    /// Directory fetched baselines are kept in, with their signature chains
+    #[arg(long)]
+    pub baseline_cache_dir: Option<PathBuf>,
+
+    /// Verify against the cached baseline, with a warning, when it cannot be fetched
+    #[arg(long, requires = "baseline_cache_dir")]
+    pub offline: bool,
+}
+
+/// A baseline as it was fetched.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedBaseline {
+    /// Unix time it was fetched at
+    fetched_at: i64,
+    baseline: Baseline,
+    #[serde(default)]
+    signatures: Vec<BaselineSignature>,
+}
+
+fn cache_path(dir: &Path, image_id: &str) -> PathBuf {
+    dir.join(format!("{}.json", image_id.replace('/', "%2F")))
+}
+
+fn store(dir: &Path, cached: &CachedBaseline) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let path = cache_path(dir, &cached.baseline.image_id);
+    let temporary = path.with_extension("json.tmp");
+    let mut file = BufWriter::new(File::create(&temporary)?);
+    serde_json::to_writer(&mut file, cached)?;
+    file.flush()?;
+    file.get_ref().sync_data()?;
+    std::fs::rename(&temporary, &path)?;
+    Ok(())
+}
+
+fn load_cached(dir: &Path, image_id: &str) -> Result<CachedBaseline> {
+    let file = File::open(cache_path(dir, image_id))?;
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+}
+
+async fn fetch(signature_args: &SignatureArgs, trusted_pubkeys: &[String], client: &MetadataClient, image_id: &str) -> Result<CachedBaseline> {
+    info!("Fetching baseline from: {}/baselines/{}", client.base_url(), image_id);
+    let baseline = client.get_baseline(image_id).await?;
+    info!("Baseline fetched successfully ({} files)", baseline.entries.len());
+    let signatures = signatures::fetch_chain(signature_args, trusted_pubkeys, client, &baseline).await?;
+    Ok(CachedBaseline { fetched_at: chrono::Utc::now().timestamp(), baseline, signatures })
+}
+
+/// The baseline of `image_id`, checked against its signature chain: fetched,
+/// and cached if a cache directory is set, or the cached one when it cannot
+/// be fetched in offline mode.
+pub async fn load(
+    args: &BaselineCacheArgs,
+    signature_args: &SignatureArgs,
+    trusted_pubkeys: &[String],
+    client: &MetadataClient,
+    image_id: &str,
+) -> Result<Baseline> {
+    let (cached, fetched) = match (fetch(signature_args, trusted_pubkeys, client, image_id).await, &args.baseline_cache_dir) {
+        (Ok(fetched), _) => (fetched, true),
+        (Err(e), Some(dir)) if args.offline && !matches!(e, IntegrityError::BaselineNotFound(_)) => {
+            let cached = load_cached(dir, image_id).map_err(|cache_error| {
+                error!("Failed to fetch baseline: {}", e);
+                IntegrityError::Storage(format!("{} (and no usable cached baseline: {})", e, cache_error))
+            })?;
+            let age = chrono::Utc::now().timestamp() - cached.fetched_at;
+            warn!("Failed to fetch baseline ({}), OFFLINE: verifying against the copy of {} cached {}s ago", e, image_id, age);
+            (cached, false)
+        }
+        (Err(e), _) => {
+            error!("Failed to fetch baseline: {}", e);
+            return Err(e);
+        }
+    };
+    signatures::check(signature_args, trusted_pubkeys, &cached.baseline, &cached.signatures)?;
+
+    if let (true, Some(dir)) = (fetched, &args.baseline_cache_dir) {
+        if let Err(e) = store(dir, &cached) {
+            warn!("Cannot keep the baseline of {} in {:?}: {}", image_id, dir, e);
+        }
+    }
+    Ok(cached.baseline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::test_util::BaselineBuilder;
+
+    #[tokio::test]
+    async fn test_offline_fallback() {
+        let dir = std::env::temp_dir().join(format!("acropole-baseline-cache-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let baseline = BaselineBuilder::new("team/app").file("etc/passwd", 0o644).build();
+        store(&dir, &CachedBaseline { fetched_at: 0, baseline: baseline.clone(), signatures: Vec::new() }).unwrap();
+        assert!(dir.join("team%2Fapp.json").exists());
+
+        // Nothing listens on the discard port
+        let client = MetadataClient::new(integrity_client::ClientConfig { max_retries: 0, ..integrity_client::ClientConfig::new("http://127.0.0.1:9") }).unwrap();
+        let signature_args = SignatureArgs { require_signers: Vec::new(), trusted_signer: Vec::new(), trusted_pubkey: Vec::new() };
+        let args = BaselineCacheArgs { baseline_cache_dir: Some(dir.clone()), offline: false };
+        assert!(load(&args, &signature_args, &[], &client, "team/app").await.is_err());
+
+        let args = BaselineCacheArgs { offline: true, ..args };
+        assert_eq!(load(&args, &signature_args, &[], &client, "team/app").await.unwrap(), baseline);
+        assert!(load(&args, &signature_args, &[], &client, "other").await.is_err());
+
+        // The cached chain is checked like a fetched one
+        let trusted = ["ab".repeat(32)];
+        assert!(matches!(load(&args, &signature_args, &trusted, &client, "team/app").await, Err(IntegrityError::Signature(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
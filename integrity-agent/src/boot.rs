@@ -0,0 +1,81 @@
+//! Boot chain verification (Linux): the kernel command line and the kernel
+//! image the host booted from.
+//!
+//! Files under /boot are in the baseline like any other; this checks what
+//! was actually booted. The command line parameters are compared against the
+//! recorded ones, and the image named by `BOOT_IMAGE` (set by GRUB) must be a
+//! baselined kernel that still matches. Both only change with a reboot, so
+//! they are checked once per run.
+
+use integrity_common::{cmdline_changes, parameter_name, parse_cmdline, AnomalyReport, BaselineIndex};
+use std::path::Path;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct BootArgs {
+    /// Kernel command line parameters not compared against the baseline, e.g. instance-specific ones
+    #[arg(long, value_delimiter = ',', default_value = "BOOT_IMAGE,initrd")]
+    pub cmdline_ignore: Vec<String>,
+}
+
+const PROC_CMDLINE: &str = "/proc/cmdline";
+
+/// Baselined path of the booted image, e.g. "(hd0,gpt2)/vmlinuz-6.1.0" -> "boot/vmlinuz-6.1.0"
+/// when /boot is its own partition.
+fn boot_image_path(boot_image: &str, baseline: &BaselineIndex) -> String {
+    // GRUB prefixes the device when /boot is not on the root filesystem
+    let image = boot_image.rsplit(')').next().unwrap_or(boot_image).trim_start_matches('/');
+    let candidates = if image.starts_with("boot/") {
+        vec![image.to_string()]
+    } else {
+        vec![format!("boot/{}", image), image.to_string()]
+    };
+    candidates.iter().find(|path| baseline.contains(path)).unwrap_or(&candidates[0]).clone()
+}
+
+/// Anomalies for the running boot: command line drift from `recorded` (if
+/// recorded), and a booted kernel image that is not a baselined one.
+pub async fn verify(recorded: &[String], baseline: &BaselineIndex, root: &Path, args: &BootArgs) -> Vec<AnomalyReport> {
+    let current = match std::fs::read_to_string(PROC_CMDLINE) {
+        Ok(cmdline) => parse_cmdline(&cmdline),
+        Err(e) => return vec![AnomalyReport::other("BOOT_CHECK_FAILED", "proc/cmdline", e.to_string())],
+    };
+    let mut anomalies = Vec::new();
+
+    if !recorded.is_empty() {
+        let (added, removed) = cmdline_changes(recorded, &current, &args.cmdline_ignore);
+        for parameter in added {
+            anomalies.push(AnomalyReport::other("BOOT_CMDLINE_CHANGED", "proc/cmdline", format!("parameter added: {}", parameter)));
+        }
+        for parameter in removed {
+            anomalies.push(AnomalyReport::other("BOOT_CMDLINE_CHANGED", "proc/cmdline", format!("parameter removed: {}", parameter)));
+        }
+    }
+
+    // Images without /boot (containers) have no kernel to compare against
+    let has_boot = baseline.entries().any(|e| e.path.trim_start_matches('/').starts_with("boot/"));
+    let boot_image = current.iter().find(|p| parameter_name(p) == "BOOT_IMAGE").and_then(|p| p.split_once('='));
+    if let (true, Some((_, boot_image))) = (has_boot, boot_image) {
+        let path = boot_image_path(boot_image, baseline);
+        if !baseline.contains(&path) {
+            anomalies.push(AnomalyReport::other("BOOT_KERNEL_UNKNOWN", path, "booted kernel image not in baseline"));
+        } else if let Some(anomaly) = crate::verify_file(&root.join(&path), root, baseline, None).await {
+            anomalies.push(AnomalyReport::other("BOOT_KERNEL_MISMATCH", path, format!("booted kernel image, {}", anomaly)));
+        }
+    }
+    anomalies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::test_util::BaselineBuilder;
+
+    #[test]
+    fn test_boot_image_path() {
+        let baseline = BaselineBuilder::new("img").size(0).file("boot/vmlinuz-6.1.0", 0o644).build();
+        let index = BaselineIndex::new(&baseline);
+        assert_eq!(boot_image_path("/boot/vmlinuz-6.1.0", &index), "boot/vmlinuz-6.1.0");
+        assert_eq!(boot_image_path("(hd0,gpt2)/vmlinuz-6.1.0", &index), "boot/vmlinuz-6.1.0");
+        assert_eq!(boot_image_path("/vmlinuz-evil", &index), "boot/vmlinuz-evil");
+    }
+}
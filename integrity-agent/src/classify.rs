@@ -0,0 +1,119 @@
+//! Escalation of anomalies on security-sensitive paths.
+//!
+//! Some files matter more than their anomaly kind says: a one-line change to
+//! `/etc/ld.so.preload` subverts every userland check on the host, a new
+//! unit file or cron job survives reboots, and a changed kernel, initrd or
+//! bootloader configuration controls the next boot. Anomalies on
+//! these paths are re-labelled with a dedicated category, keeping the original
+//! kind in the detail ("PRELOAD_INJECTION: etc/ld.so.preload (ADDED)"), so
+//! outputs and response hooks can treat them separately.
+
+use integrity_common::{AnomalyReport, Severity};
+
+/// Categories and the paths (relative, a trailing `/` for everything below) they cover.
+const CATEGORIES: &[(&str, &[&str])] = &[
+    (
+        "PRELOAD_INJECTION",
+        &["etc/ld.so.preload", "etc/ld.so.conf", "etc/ld.so.conf.d/"],
+    ),
+    (
+        "PERSISTENCE_MECHANISM",
+        &[
+            // systemd units, drop-ins and .wants/.requires links
+            "etc/systemd/system/",
+            "etc/systemd/user/",
+            "lib/systemd/system/",
+            "usr/lib/systemd/system/",
+            "usr/lib/systemd/user/",
+            // cron and anacron
+            "etc/crontab",
+            "etc/anacrontab",
+            "etc/cron.d/",
+            "etc/cron.hourly/",
+            "etc/cron.daily/",
+            "etc/cron.weekly/",
+            "etc/cron.monthly/",
+            "var/spool/cron/",
+            // SysV init
+            "etc/init.d/",
+            "etc/rc.local",
+            "etc/rc0.d/",
+            "etc/rc1.d/",
+            "etc/rc2.d/",
+            "etc/rc3.d/",
+            "etc/rc4.d/",
+            "etc/rc5.d/",
+            "etc/rc6.d/",
+            "etc/rcS.d/",
+        ],
+    ),
+    (
+        "BOOT_INTEGRITY",
+        &[
+            // kernels, initrds, bootloader and its configuration
+            "boot/",
+            "etc/default/grub",
+            "etc/default/grub.d/",
+            "etc/grub.d/",
+            "etc/kernel/cmdline",
+        ],
+    ),
+];
+
+fn category_for(path: &str) -> Option<&'static str> {
+    let path = path.trim_start_matches('/');
+    CATEGORIES
+        .iter()
+        .find(|(_, paths)| {
+            paths.iter().any(|p| match p.strip_suffix('/') {
+                Some(dir) => path.starts_with(p) || path == dir,
+                None => path == *p,
+            })
+        })
+        .map(|(category, _)| *category)
+}
+
+/// The category an anomaly was re-labelled with, if any.
+pub fn category(anomaly: &AnomalyReport) -> Option<&'static str> {
+    CATEGORIES.iter().map(|(category, _)| *category).find(|c| *c == anomaly.kind())
+}
+
+/// Re-labels an anomaly on a sensitive path with its category.
+pub fn classify(mut anomaly: AnomalyReport) -> AnomalyReport {
+    if let Some(category) = category_for(&anomaly.path) {
+        if anomaly.kind() != category {
+            anomaly.relabel(category, Severity::Critical);
+        }
+    }
+    anomaly
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classified(anomaly: &str) -> String {
+        classify(AnomalyReport::parse(anomaly)).to_string()
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classified("ADDED: etc/ld.so.preload"), "PRELOAD_INJECTION: etc/ld.so.preload (ADDED)");
+        assert_eq!(
+            classified("MODIFIED: etc/ld.so.conf.d/evil.conf (hash mismatch: a != b)"),
+            "PRELOAD_INJECTION: etc/ld.so.conf.d/evil.conf (MODIFIED: hash mismatch: a != b)"
+        );
+        assert_eq!(classified("ADDED: etc/ld.so.preload.bak"), "ADDED: etc/ld.so.preload.bak");
+        assert_eq!(classified("MODIFIED: etc/passwd (x)"), "MODIFIED: etc/passwd (x)");
+        assert_eq!(
+            classified("ADDED: etc/systemd/system/multi-user.target.wants/x.service"),
+            "PERSISTENCE_MECHANISM: etc/systemd/system/multi-user.target.wants/x.service (ADDED)"
+        );
+        assert_eq!(
+            classified("MODIFIED: boot/grub/grub.cfg (hash mismatch: a != b)"),
+            "BOOT_INTEGRITY: boot/grub/grub.cfg (MODIFIED: hash mismatch: a != b)"
+        );
+        assert_eq!(category(&AnomalyReport::parse("PERSISTENCE_MECHANISM: etc/crontab (MODIFIED)")), Some("PERSISTENCE_MECHANISM"));
+        assert_eq!(category(&AnomalyReport::parse("MODIFIED: etc/crontab")), None);
+    }
+}
@@ -0,0 +1,277 @@
+use async_trait::async_trait;
+use integrity_common::{InstanceIdentity, IntegrityError, Result};
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// Link-local metadata endpoint shared by EC2 (IMDSv2) and Azure (IMDS).
+const LINK_LOCAL_METADATA_URL: &str = "http://169.254.169.254";
+/// GCE metadata server.
+const GCE_METADATA_URL: &str = "http://metadata.google.internal/computeMetadata/v1";
+
+fn metadata_error(e: reqwest::Error) -> IntegrityError {
+    IntegrityError::Storage(format!("Instance metadata service: {}", e))
+}
+
+/// A cloud provider whose metadata service can identify the running instance.
+#[async_trait]
+pub trait CloudIdentity: Send + Sync {
+    fn provider(&self) -> &'static str;
+
+    /// Queries the metadata service. Fails quickly when not running on this cloud.
+    async fn fetch(&self, http: &reqwest::Client) -> Result<InstanceIdentity>;
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum CloudProvider {
+    /// Try every provider in turn
+    Auto,
+    Aws,
+    Gcp,
+    Azure,
+}
+
+impl CloudProvider {
+    fn backends(self) -> Vec<Box<dyn CloudIdentity>> {
+        match self {
+            CloudProvider::Auto => vec![Box::new(Aws), Box::new(Gcp), Box::new(Azure)],
+            CloudProvider::Aws => vec![Box::new(Aws)],
+            CloudProvider::Gcp => vec![Box::new(Gcp)],
+            CloudProvider::Azure => vec![Box::new(Azure)],
+        }
+    }
+}
+
+/// Detects the instance identity using the selected provider(s).
+pub async fn detect_identity(provider: CloudProvider) -> Result<InstanceIdentity> {
+    // Metadata services are link-local; anything slower means we're not on that cloud
+    let http = reqwest::Client::builder()
+        .timeout(Duration::from_secs(2))
+        .build()
+        .map_err(metadata_error)?;
+
+    let mut errors = Vec::new();
+    for backend in provider.backends() {
+        match backend.fetch(&http).await {
+            Ok(identity) => {
+                info!(
+                    "Detected {} instance {} running image {}",
+                    identity.provider, identity.instance_id, identity.image
+                );
+                return Ok(identity);
+            }
+            Err(e) => {
+                debug!("{} identity detection failed: {}", backend.provider(), e);
+                errors.push(format!("{}: {}", backend.provider(), e));
+            }
+        }
+    }
+    Err(IntegrityError::Storage(format!(
+        "Could not detect cloud instance identity ({})",
+        errors.join("; ")
+    )))
+}
+
+/// Last component of a slash-separated resource path, so it can be used as a mapping key.
+fn resource_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Amazon EC2 via IMDSv2.
+pub struct Aws;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AwsIdentityDocument {
+    instance_id: String,
+    image_id: String,
+    region: Option<String>,
+    account_id: Option<String>,
+}
+
+#[async_trait]
+impl CloudIdentity for Aws {
+    fn provider(&self) -> &'static str {
+        "aws"
+    }
+
+    async fn fetch(&self, http: &reqwest::Client) -> Result<InstanceIdentity> {
+        let token = http
+            .put(format!("{}/latest/api/token", LINK_LOCAL_METADATA_URL))
+            .header("X-aws-ec2-metadata-token-ttl-seconds", "300")
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(metadata_error)?
+            .text()
+            .await
+            .map_err(metadata_error)?;
+
+        let document: AwsIdentityDocument = http
+            .get(format!("{}/latest/dynamic/instance-identity/document", LINK_LOCAL_METADATA_URL))
+            .header("X-aws-ec2-metadata-token", token)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(metadata_error)?
+            .json()
+            .await
+            .map_err(metadata_error)?;
+
+        Ok(InstanceIdentity {
+            provider: self.provider().to_string(),
+            instance_id: document.instance_id,
+            image: document.image_id,
+            region: document.region,
+            account_id: document.account_id,
+        })
+    }
+}
+
+/// Google Compute Engine via the metadata server.
+pub struct Gcp;
+
+impl Gcp {
+    async fn get(http: &reqwest::Client, path: &str) -> Result<String> {
+        http.get(format!("{}/{}", GCE_METADATA_URL, path))
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(metadata_error)?
+            .text()
+            .await
+            .map_err(metadata_error)
+    }
+}
+
+#[async_trait]
+impl CloudIdentity for Gcp {
+    fn provider(&self) -> &'static str {
+        "gcp"
+    }
+
+    async fn fetch(&self, http: &reqwest::Client) -> Result<InstanceIdentity> {
+        // e.g. "projects/debian-cloud/global/images/debian-12-bookworm-v20240110"
+        // or "projects/debian-cloud/global/images/family/debian-12"
+        let image = Self::get(http, "instance/image").await?;
+        let instance_id = Self::get(http, "instance/id").await?;
+        // "projects/123456/zones/us-central1-a"
+        let zone = Self::get(http, "instance/zone").await.ok();
+        let project = Self::get(http, "project/project-id").await.ok();
+
+        Ok(InstanceIdentity {
+            provider: self.provider().to_string(),
+            instance_id,
+            image: resource_name(&image).to_string(),
+            region: zone.as_deref().map(|z| resource_name(z).to_string()),
+            account_id: project,
+        })
+    }
+}
+
+/// Microsoft Azure via the instance metadata service.
+pub struct Azure;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureInstance {
+    compute: AzureCompute,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureCompute {
+    vm_id: String,
+    location: Option<String>,
+    subscription_id: Option<String>,
+    storage_profile: AzureStorageProfile,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureStorageProfile {
+    image_reference: AzureImageReference,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct AzureImageReference {
+    id: String,
+    publisher: String,
+    offer: String,
+    sku: String,
+    version: String,
+}
+
+impl AzureImageReference {
+    /// Marketplace images are identified by publisher:offer:sku:version, gallery
+    /// images by image:version, and other custom images by their resource name.
+    fn name(&self) -> String {
+        if self.id.is_empty() {
+            return format!("{}:{}:{}:{}", self.publisher, self.offer, self.sku, self.version);
+        }
+        match self.id.rsplit_once("/images/") {
+            Some((_, image)) => image.replace("/versions/", ":"),
+            None => resource_name(&self.id).to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl CloudIdentity for Azure {
+    fn provider(&self) -> &'static str {
+        "azure"
+    }
+
+    async fn fetch(&self, http: &reqwest::Client) -> Result<InstanceIdentity> {
+        let instance: AzureInstance = http
+            .get(format!("{}/metadata/instance?api-version=2021-02-01", LINK_LOCAL_METADATA_URL))
+            .header("Metadata", "true")
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(metadata_error)?
+            .json()
+            .await
+            .map_err(metadata_error)?;
+
+        let compute = instance.compute;
+        Ok(InstanceIdentity {
+            provider: self.provider().to_string(),
+            instance_id: compute.vm_id,
+            image: compute.storage_profile.image_reference.name(),
+            region: compute.location,
+            account_id: compute.subscription_id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_names() {
+        assert_eq!(
+            resource_name("projects/debian-cloud/global/images/debian-12-bookworm-v20240110"),
+            "debian-12-bookworm-v20240110"
+        );
+
+        let marketplace = AzureImageReference {
+            publisher: "Canonical".to_string(),
+            offer: "0001-com-ubuntu-server-jammy".to_string(),
+            sku: "22_04-lts".to_string(),
+            version: "22.04.202401010".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(marketplace.name(), "Canonical:0001-com-ubuntu-server-jammy:22_04-lts:22.04.202401010");
+
+        let gallery = AzureImageReference {
+            id: "/subscriptions/x/resourceGroups/rg/providers/Microsoft.Compute/galleries/g/images/golden/versions/1.0.0"
+                .to_string(),
+            ..Default::default()
+        };
+        assert_eq!(gallery.name(), "golden:1.0.0");
+    }
+}
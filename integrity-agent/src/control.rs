@@ -0,0 +1,243 @@
+//! Local control socket for host administrators (monitor mode).
+//!
+//! `integrity-agentctl` talks to the running agent over a Unix socket (see
+//! `integrity_common::ControlRequest` for the protocol) to show its status,
+//! queue a re-scan, reload the baseline, dump the effective value of every
+//! flag, and enter or exit maintenance mode. In maintenance mode anomalies
+//! are still logged and recorded in the local history but not sent to the
+//! alert sinks, and they do not count towards fail-closed, so planned changes
+//! do not page anyone. The socket is only accessible to its owner (mode 0600)
+//! and connections from other users than root and the agent's are refused.
+
+use crate::pipeline::Metrics;
+use crate::state::{self, HistoryQuery};
+use integrity_common::{AgentStatus, ControlRequest, ControlResponse, MaintenanceWindow, DEFAULT_CONTROL_SOCKET};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+#[cfg(unix)]
+use {
+    tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    tokio::net::{UnixListener, UnixStream},
+    tracing::debug,
+};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ControlArgs {
+    /// Unix socket integrity-agentctl connects to in monitor mode ("" disables it)
+    #[arg(long, default_value = DEFAULT_CONTROL_SOCKET)]
+    pub control_socket: PathBuf,
+}
+
+/// Requests the monitor loop carries out.
+pub enum ControlAction {
+    /// Re-scan these paths (below the monitored root), or the watch paths if empty
+    Rescan(Vec<PathBuf>),
+    ReloadBaseline,
+}
+
+/// Whether alerts are suppressed for planned changes.
+#[derive(Default)]
+pub struct Maintenance(Mutex<Option<MaintenanceWindow>>);
+
+impl Maintenance {
+    /// The window in effect at `now`; one that has run out ends.
+    pub fn current(&self, now: i64) -> Option<MaintenanceWindow> {
+        let mut window = self.0.lock().unwrap();
+        if window.as_ref().is_some_and(|w| w.until.is_some_and(|until| until <= now)) {
+            info!("Maintenance window ended, alerting again");
+            *window = None;
+        }
+        window.clone()
+    }
+
+    pub fn enter(&self, window: MaintenanceWindow) {
+        *self.0.lock().unwrap() = Some(window);
+    }
+
+    pub fn exit(&self) -> Option<MaintenanceWindow> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+/// What the socket reports and acts on.
+pub struct ControlState {
+    pub mode: String,
+    pub image_id: String,
+    pub baseline_entries: usize,
+    pub started_at: i64,
+    pub watch_paths: Vec<PathBuf>,
+    pub state_dir: PathBuf,
+    pub metrics: Arc<Metrics>,
+    pub maintenance: Arc<Maintenance>,
+    /// Effective value of every flag, secrets redacted
+    pub policy: BTreeMap<String, String>,
+    pub actions: mpsc::Sender<ControlAction>,
+}
+
+impl ControlState {
+    fn status(&self, now: i64) -> AgentStatus {
+        let open_anomalies = state::read_history(&self.state_dir, &HistoryQuery { unresolved_only: true, ..Default::default() })
+            .ok()
+            .map(|records| records.len());
+        AgentStatus {
+            pid: std::process::id(),
+            mode: self.mode.clone(),
+            image_id: self.image_id.clone(),
+            baseline_entries: self.baseline_entries,
+            started_at: self.started_at,
+            watch_paths: self.watch_paths.iter().map(|p| p.to_string_lossy().into_owned()).collect(),
+            maintenance: self.maintenance.current(now),
+            open_anomalies,
+            metrics: self.metrics.snapshot().into_iter().map(|(name, value)| (name.to_string(), value)).collect(),
+        }
+    }
+
+    async fn handle(&self, request: ControlRequest, uid: Option<u32>) -> ControlResponse {
+        let now = chrono::Utc::now().timestamp();
+        let done = |message: String| ControlResponse::Done { message };
+        match request {
+            ControlRequest::Status => ControlResponse::Status(self.status(now)),
+            ControlRequest::Policy => ControlResponse::Policy { flags: self.policy.clone() },
+            ControlRequest::Rescan { paths } => {
+                let message = if paths.is_empty() {
+                    "re-scan of the watch paths queued".to_string()
+                } else {
+                    format!("re-scan of {} queued", paths.join(", "))
+                };
+                info!("Re-scan requested over the control socket: {:?}", paths);
+                let paths = paths.iter().map(|path| PathBuf::from(path.trim_start_matches('/'))).collect();
+                match self.actions.send(ControlAction::Rescan(paths)).await {
+                    Ok(()) => done(message),
+                    Err(_) => ControlResponse::Error { message: "monitoring is stopping".to_string() },
+                }
+            }
+            ControlRequest::ReloadBaseline => {
+                info!("Baseline reload requested over the control socket");
+                match self.actions.send(ControlAction::ReloadBaseline).await {
+                    Ok(()) => done("reloading the baseline, the agent restarts".to_string()),
+                    Err(_) => ControlResponse::Error { message: "monitoring is stopping".to_string() },
+                }
+            }
+            ControlRequest::EnterMaintenance { duration_secs, reason } => {
+                let until = duration_secs.map(|secs| now + secs as i64);
+                warn!(
+                    "Maintenance mode entered by uid {}{}, alerts suppressed{}",
+                    uid.map_or_else(|| "?".to_string(), |uid| uid.to_string()),
+                    until.map_or_else(String::new, |until| format!(" for {}s", until - now)),
+                    reason.as_deref().map_or_else(String::new, |reason| format!(": {}", reason))
+                );
+                self.maintenance.enter(MaintenanceWindow { since: now, until, reason, uid });
+                done("maintenance mode entered".to_string())
+            }
+            ControlRequest::ExitMaintenance => match self.maintenance.exit() {
+                Some(_) => {
+                    warn!("Maintenance mode exited, alerting again");
+                    done("maintenance mode exited".to_string())
+                }
+                None => ControlResponse::Error { message: "not in maintenance mode".to_string() },
+            },
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn handle_connection(stream: UnixStream, state: Arc<ControlState>, owner: u32) -> std::io::Result<()> {
+    let uid = stream.peer_cred().ok().map(|cred| cred.uid());
+    let (reader, mut writer) = stream.into_split();
+
+    let response = if !uid.is_some_and(|uid| uid == 0 || uid == owner) {
+        warn!("Refused control connection from uid {:?}", uid);
+        ControlResponse::Error { message: "permission denied".to_string() }
+    } else {
+        let mut line = String::new();
+        BufReader::new(reader).read_line(&mut line).await?;
+        match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => state.handle(request, uid).await,
+            Err(e) => ControlResponse::Error { message: format!("invalid request: {}", e) },
+        }
+    };
+
+    let mut response = serde_json::to_vec(&response).map_err(std::io::Error::other)?;
+    response.push(b'\n');
+    writer.write_all(&response).await?;
+    writer.shutdown().await
+}
+
+/// Binds the socket and serves control requests in the background.
+#[cfg(unix)]
+pub fn serve(socket_path: &Path, state: Arc<ControlState>) -> std::io::Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    if let Some(dir) = socket_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // A stale socket from a previous run would make bind fail
+    if socket_path.exists() {
+        std::fs::remove_file(socket_path)?;
+    }
+    let listener = UnixListener::bind(socket_path)?;
+    std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o600))?;
+    let owner = std::fs::metadata(socket_path)?.uid();
+    info!("Control socket listening on {:?}", socket_path);
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(stream, state, owner).await {
+                            debug!("Control connection error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Control socket accept failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn serve(_socket_path: &Path, _state: Arc<ControlState>) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "the control interface is a Unix socket, which this platform lacks",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_maintenance_and_actions() {
+        let (actions, mut action_rx) = mpsc::channel(4);
+        let state = ControlState {
+            mode: "Monitor".to_string(),
+            image_id: "app-v1".to_string(),
+            baseline_entries: 3,
+            started_at: 0,
+            watch_paths: vec![PathBuf::from("/etc")],
+            state_dir: std::env::temp_dir().join(format!("acropole-control-{}", std::process::id())),
+            metrics: Arc::new(Metrics::default()),
+            maintenance: Arc::new(Maintenance::default()),
+            policy: BTreeMap::new(),
+            actions,
+        };
+
+        let request = ControlRequest::EnterMaintenance { duration_secs: Some(60), reason: Some("patching".to_string()) };
+        assert!(matches!(state.handle(request, Some(0)).await, ControlResponse::Done { .. }));
+        let ControlResponse::Status(status) = state.handle(ControlRequest::Status, Some(0)).await else {
+            panic!("expected a status");
+        };
+        assert_eq!(status.maintenance.as_ref().and_then(|w| w.reason.as_deref()), Some("patching"));
+        assert!(state.maintenance.current(status.maintenance.unwrap().since + 60).is_none());
+        assert!(matches!(state.handle(ControlRequest::ExitMaintenance, Some(0)).await, ControlResponse::Error { .. }));
+
+        state.handle(ControlRequest::Rescan { paths: vec!["/etc/ssh".to_string()] }, Some(0)).await;
+        assert!(matches!(action_rx.recv().await, Some(ControlAction::Rescan(paths)) if paths == vec![PathBuf::from("etc/ssh")]));
+    }
+}
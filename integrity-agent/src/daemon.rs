@@ -0,0 +1,63 @@
+//! Scheduled full scans for daemon mode.
+//!
+//! In daemon mode the agent runs a scan pass, reports it like scan mode does
+//! (sinks, `--summary-file`), and then waits for the next pass instead of
+//! exiting: every `--scan-interval` from the start of the previous pass, or
+//! at the times of a `--scan-schedule` cron expression (minute, hour, day of
+//! month, month, day of week, in local time). A pass that fails, e.g. because
+//! the service is unreachable, is logged and retried at the next pass; only an
+//! invalid configuration stops the daemon.
+
+use chrono::{DateTime, Local};
+use croner::Cron;
+use std::time::Duration;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DaemonArgs {
+    /// Time between the starts of scan passes in daemon mode, e.g. 1h
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "1h")]
+    pub scan_interval: Duration,
+
+    /// Cron expression scan passes start at in daemon mode, e.g. "0 3 * * *", instead of --scan-interval
+    #[arg(long, value_parser = parse_schedule)]
+    pub scan_schedule: Option<Cron>,
+}
+
+fn parse_schedule(expression: &str) -> Result<Cron, String> {
+    Cron::new(expression).parse().map_err(|e| format!("invalid cron expression {:?}: {}", expression, e))
+}
+
+impl DaemonArgs {
+    /// How long to wait, at `now`, for the pass after one that started at
+    /// `started`. Passes that overran their interval are followed right away.
+    pub fn next_pass(&self, started: DateTime<Local>, now: DateTime<Local>) -> Duration {
+        let next = match &self.scan_schedule {
+            Some(schedule) => match schedule.find_next_occurrence(&now, false) {
+                Ok(next) => next,
+                // A pattern with no future match, e.g. February 30th
+                Err(_) => return Duration::MAX,
+            },
+            None => started + self.scan_interval,
+        };
+        (next - now).to_std().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_next_pass() {
+        let at = |h, m, s| Local.with_ymd_and_hms(2026, 3, 10, h, m, s).unwrap();
+        let interval = DaemonArgs { scan_interval: Duration::from_secs(3600), scan_schedule: None };
+        assert_eq!(interval.next_pass(at(10, 0, 0), at(10, 20, 0)), Duration::from_secs(40 * 60));
+        assert_eq!(interval.next_pass(at(10, 0, 0), at(11, 5, 0)), Duration::ZERO);
+
+        let nightly = DaemonArgs { scan_schedule: Some(parse_schedule("30 3 * * *").unwrap()), ..interval };
+        assert_eq!(nightly.next_pass(at(2, 0, 0), at(3, 0, 0)), Duration::from_secs(30 * 60));
+        assert_eq!(nightly.next_pass(at(3, 30, 0), at(3, 31, 0)), Duration::from_secs(24 * 3600 - 60));
+        assert!(parse_schedule("61 * * * *").is_err());
+    }
+}
@@ -0,0 +1,131 @@
+//! `--early-boot`: offline verification from the initramfs or an early unit.
+//!
+//! Runs before the network and before services start, so it only uses a
+//! baseline file shipped with it and blocking file I/O. The critical paths
+//! of the real root (mounted at `--sysroot`) are compared against the baseline,
+//! and the verdict is written to `--verdict-file` under /run, which survives
+//! switch_root. The full agent reads it on startup and forwards its anomalies
+//! to the configured outputs.
+
+use crate::selfcheck::SelfCheckArgs;
+use integrity_common::{Baseline, BaselineIndex, IntegrityError, Result, ScanConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct EarlyBootArgs {
+    /// Verify the critical paths against a local baseline, without network access, then exit
+    #[arg(long)]
+    pub early_boot: bool,
+
+    /// Baseline JSON file for --early-boot, e.g. shipped in the initramfs
+    #[arg(long, default_value = "/etc/acropole/baseline.json")]
+    pub baseline_file: PathBuf,
+
+    /// Where the real root filesystem is mounted during --early-boot
+    #[arg(long, default_value = "/sysroot")]
+    pub sysroot: PathBuf,
+
+    /// Paths verified by --early-boot
+    #[arg(long, value_delimiter = ',', default_value = "/bin,/sbin,/usr/bin,/usr/sbin,/usr/lib/systemd,/etc")]
+    pub critical_paths: Vec<PathBuf>,
+
+    /// Where --early-boot writes its verdict for the full agent
+    #[arg(long, default_value = "/run/acropole-agent/early-boot.json")]
+    pub verdict_file: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Verdict {
+    pub time: i64,
+    pub image_id: String,
+    pub ok: bool,
+    pub files_checked: usize,
+    pub anomalies: Vec<String>,
+}
+
+fn load_baseline(args: &EarlyBootArgs, self_check: &SelfCheckArgs) -> Result<Baseline> {
+    let baseline: Baseline = serde_json::from_slice(&std::fs::read(&args.baseline_file)?)?;
+    // The baseline is only as trustworthy as the medium it came on
+    if let Some(expected) = &self_check.baseline_digest {
+        let digest = baseline.digest()?;
+        if !digest.eq_ignore_ascii_case(expected) {
+            return Err(IntegrityError::Validation(format!(
+                "baseline file {:?} digest {} != {}",
+                args.baseline_file, digest, expected
+            )));
+        }
+    }
+    Ok(baseline)
+}
+
+/// Verifies the critical paths and writes the verdict.
+pub fn run(args: &EarlyBootArgs, self_check: &SelfCheckArgs) -> Result<Verdict> {
+    info!("Early-boot verification of {:?} under {:?}", args.critical_paths, args.sysroot);
+    let baseline = load_baseline(args, self_check)?;
+
+    let relative: Vec<String> = args
+        .critical_paths
+        .iter()
+        .map(|p| p.to_string_lossy().trim_matches('/').to_string())
+        .collect();
+    let under_critical = |path: &str| {
+        let path = path.trim_start_matches('/');
+        relative.iter().any(|p| path == p || path.starts_with(&format!("{}/", p)))
+    };
+
+    // Only the critical part of the baseline is expected to be there
+    let index = BaselineIndex::from_entries(baseline.entries.iter().filter(|e| under_critical(&e.path)).cloned())
+        .with_hash_algorithm(baseline.hash_algorithm);
+    let mut current = HashMap::new();
+    for path in &relative {
+        let start = args.sysroot.join(path);
+        if start.exists() {
+            current.extend(crate::scan_subtree(&args.sysroot, &start, &ScanConfig::default(), baseline.hash_algorithm, None)?);
+        }
+    }
+
+    let anomalies = crate::compare_filesystems(&index, &current);
+    let verdict = Verdict {
+        time: chrono::Utc::now().timestamp(),
+        image_id: baseline.image_id,
+        ok: anomalies.is_empty(),
+        files_checked: current.len(),
+        anomalies: anomalies.into_iter().map(|anomaly| crate::classify::classify(anomaly).to_string()).collect(),
+    };
+    for anomaly in &verdict.anomalies {
+        error!("EARLY BOOT ANOMALY: {}", anomaly);
+    }
+    info!("Early-boot verification checked {} files: {}", verdict.files_checked, if verdict.ok { "ok" } else { "FAILED" });
+
+    if let Some(dir) = args.verdict_file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&args.verdict_file, serde_json::to_vec_pretty(&verdict)?)?;
+    Ok(verdict)
+}
+
+/// Reads and removes the verdict left by an early-boot run, if any.
+pub fn take_verdict(path: &Path) -> Option<Verdict> {
+    let contents = std::fs::read(path).ok()?;
+    if let Err(e) = std::fs::remove_file(path) {
+        warn!("Cannot remove early-boot verdict {:?}, it will be reported again: {}", path, e);
+    }
+    match serde_json::from_slice::<Verdict>(&contents) {
+        Ok(verdict) => {
+            info!(
+                "Early-boot verification of {} found {} anomalies in {} files",
+                verdict.image_id,
+                verdict.anomalies.len(),
+                verdict.files_checked
+            );
+            Some(verdict)
+        }
+        Err(e) => {
+            warn!("Ignoring unreadable early-boot verdict {:?}: {}", path, e);
+            None
+        }
+    }
+}
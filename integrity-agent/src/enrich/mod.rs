@@ -0,0 +1,107 @@
+//! Enrichment of ADDED and MODIFIED anomalies with what is known about the file.
+//!
+//! The baseline comparison can only say that a file is new or changed.
+//! Enrichers look at the file itself and attach what they find to the
+//! anomaly's detail ("ADDED: tmp/x (yara: Mirai_Botnet)"), and can re-label
+//! it when that makes it a classified detection, keeping the original kind in
+//! the detail ("YARA_MATCH: tmp/x (ADDED; yara: Mirai_Botnet)").
+
+pub mod reputation;
+pub mod yara;
+
+use integrity_common::{Anomaly, AnomalyReport, Severity};
+use async_trait::async_trait;
+use std::path::Path;
+
+/// The file an enricher is asked about.
+#[derive(Debug, Clone, Copy)]
+pub struct EnrichRequest<'a> {
+    /// Where the file is visible to the agent
+    pub path: &'a Path,
+    /// Path relative to the baselined filesystem root
+    pub relative_path: &'a str,
+    /// Kind of the anomaly, ADDED or MODIFIED
+    pub kind: &'a str,
+}
+
+/// What an enricher found out about a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    /// Kind the anomaly is re-labelled with, if any
+    pub kind: Option<&'static str>,
+    /// Appended to the anomaly's detail
+    pub note: String,
+}
+
+#[async_trait]
+pub trait Enricher: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn enrich(&self, request: &EnrichRequest<'_>) -> Option<Annotation>;
+}
+
+/// Adds an annotation to `anomaly`.
+fn annotate(anomaly: &mut AnomalyReport, annotation: Annotation) {
+    if let Some(kind) = annotation.kind {
+        anomaly.relabel(kind, Severity::High);
+    }
+    anomaly.notes.push(annotation.note);
+}
+
+/// The enrichers configured for this agent, all run on every eligible anomaly.
+#[derive(Default)]
+pub struct EnricherRegistry {
+    enrichers: Vec<Box<dyn Enricher>>,
+}
+
+impl EnricherRegistry {
+    pub fn register(&mut self, enricher: Box<dyn Enricher>) {
+        tracing::info!("Registered enricher {}", enricher.name());
+        self.enrichers.push(enricher);
+    }
+
+    /// Annotates an ADDED or MODIFIED anomaly on a file under `root`; others pass through.
+    pub async fn enrich(&self, mut anomaly: AnomalyReport, root: &Path) -> AnomalyReport {
+        if self.enrichers.is_empty() || !matches!(anomaly.anomaly, Anomaly::Added | Anomaly::Modified { .. }) {
+            return anomaly;
+        }
+        let path = root.join(&anomaly.path);
+        let request = EnrichRequest { path: &path, relative_path: &anomaly.path, kind: anomaly.anomaly.kind() };
+
+        let mut annotations = Vec::new();
+        for enricher in &self.enrichers {
+            annotations.extend(enricher.enrich(&request).await);
+        }
+        // Re-labelled once, by the first enricher that does; the other notes are kept
+        let mut relabelled = false;
+        for mut annotation in annotations {
+            if relabelled {
+                annotation.kind = None;
+            }
+            relabelled |= annotation.kind.is_some();
+            annotate(&mut anomaly, annotation);
+        }
+        anomaly
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotate() {
+        let annotated = |anomaly: &str, kind| {
+            let mut anomaly = AnomalyReport::parse(anomaly);
+            annotate(&mut anomaly, Annotation { kind, note: "yara: Mirai".to_string() });
+            anomaly.to_string()
+        };
+        assert_eq!(annotated("ADDED: tmp/x", None), "ADDED: tmp/x (yara: Mirai)");
+        assert_eq!(annotated("ADDED: tmp/x", Some("YARA_MATCH")), "YARA_MATCH: tmp/x (ADDED; yara: Mirai)");
+        assert_eq!(
+            annotated("MODIFIED: bin/ls (hash mismatch: a != b)", Some("YARA_MATCH")),
+            "YARA_MATCH: bin/ls (MODIFIED: hash mismatch: a != b; yara: Mirai)"
+        );
+        assert_eq!(annotated("MODIFIED: bin/ls (hash mismatch: a != b)", None), "MODIFIED: bin/ls (hash mismatch: a != b; yara: Mirai)");
+    }
+}
@@ -0,0 +1,452 @@
+//! Hash reputation of added files.
+//!
+//! Most added files are package updates and other known software; a few are
+//! known malware. Each added file's digests are looked up, cheapest source
+//! first, in a local NSRL-derived hash list (`--nsrl-hashes`), an internal
+//! allowlist service (`--reputation-allowlist-url`) and VirusTotal
+//! (`--virustotal-api-key-file`), and the anomaly is annotated known-good,
+//! known-bad (re-labelled `KNOWN_BAD`) or unknown.
+//!
+//! Results are cached in the state dir for `--reputation-cache-ttl`, and
+//! VirusTotal lookups beyond `--virustotal-rate` per minute are skipped
+//! rather than queued, leaving the file unknown (and uncached) for now.
+
+use super::{Annotation, EnrichRequest, Enricher};
+use async_trait::async_trait;
+use integrity_common::{IntegrityError, Result};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const CACHE_FILE: &str = "reputation.jsonl";
+/// Per request to the allowlist service or VirusTotal
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ReputationArgs {
+    /// Known-good file list: hex SHA-1 or SHA-256 digests, one per line, lowercase and sorted
+    /// (e.g. the NSRL RDS hashes)
+    #[arg(long)]
+    pub nsrl_hashes: Option<PathBuf>,
+
+    /// Allowlist service queried with each added file's SHA-512; 200 means known-good
+    #[arg(long)]
+    pub reputation_allowlist_url: Option<String>,
+
+    /// File holding a VirusTotal API key, to look added files up by SHA-256
+    #[arg(long)]
+    pub virustotal_api_key_file: Option<PathBuf>,
+
+    #[arg(long, default_value = "https://www.virustotal.com/api/v3")]
+    pub virustotal_url: String,
+
+    /// VirusTotal lookups per minute (4 with a public API key)
+    #[arg(long, default_value = "4")]
+    pub virustotal_rate: u32,
+
+    /// Engines that must flag a file on VirusTotal for it to be known-bad
+    #[arg(long, default_value = "3")]
+    pub virustotal_min_detections: u64,
+
+    /// How long a lookup result is reused, e.g. 24h
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "24h")]
+    pub reputation_cache_ttl: Duration,
+}
+
+impl ReputationArgs {
+    /// The configured lookup, if any source is.
+    pub fn reputation(&self, state_dir: &Path) -> Result<Option<Reputation>> {
+        if self.nsrl_hashes.is_none() && self.reputation_allowlist_url.is_none() && self.virustotal_api_key_file.is_none() {
+            return Ok(None);
+        }
+        let http = reqwest::Client::builder()
+            .timeout(LOOKUP_TIMEOUT)
+            .build()
+            .map_err(|e| IntegrityError::Storage(format!("Reputation client: {}", e)))?;
+        let nsrl = self.nsrl_hashes.as_deref().map(HashList::open).transpose()?;
+        let virustotal = match &self.virustotal_api_key_file {
+            Some(key_file) => Some(VirusTotal {
+                url: self.virustotal_url.trim_end_matches('/').to_string(),
+                api_key: std::fs::read_to_string(key_file)?.trim().to_string(),
+                min_detections: self.virustotal_min_detections,
+                limiter: RateLimiter::per_minute(self.virustotal_rate),
+            }),
+            None => None,
+        };
+        let cache = Cache::open(state_dir, self.reputation_cache_ttl)?;
+        info!("Looking up the reputation of added files ({} cached)", cache.entries.lock().unwrap().len());
+        Ok(Some(Reputation {
+            http,
+            nsrl,
+            allowlist_url: self.reputation_allowlist_url.as_ref().map(|url| url.trim_end_matches('/').to_string()),
+            virustotal,
+            cache,
+        }))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "verdict", rename_all = "kebab-case")]
+pub enum Verdict {
+    KnownGood { source: String },
+    KnownBad { source: String, detail: String },
+    Unknown,
+}
+
+impl Verdict {
+    fn annotation(&self) -> Annotation {
+        match self {
+            Verdict::KnownGood { source } => Annotation { kind: None, note: format!("reputation: known-good ({})", source) },
+            Verdict::KnownBad { source, detail } => {
+                Annotation { kind: Some("KNOWN_BAD"), note: format!("reputation: known-bad ({}: {})", source, detail) }
+            }
+            Verdict::Unknown => Annotation { kind: None, note: "reputation: unknown".to_string() },
+        }
+    }
+}
+
+struct Digests {
+    sha1: String,
+    sha256: String,
+    sha512: String,
+}
+
+fn digest(path: &Path) -> std::io::Result<Digests> {
+    let mut file = File::open(path)?;
+    let (mut sha1, mut sha256, mut sha512) = (Sha1::new(), Sha256::new(), Sha512::new());
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        sha1.update(&buffer[..read]);
+        sha256.update(&buffer[..read]);
+        sha512.update(&buffer[..read]);
+    }
+    Ok(Digests {
+        sha1: hex::encode(sha1.finalize()),
+        sha256: hex::encode(sha256.finalize()),
+        sha512: hex::encode(sha512.finalize()),
+    })
+}
+
+/// A sorted file of fixed-width hex digests, binary searched on disk: the
+/// NSRL has far more hashes than are worth holding in memory.
+struct HashList {
+    file: Mutex<File>,
+    /// Hex digits per digest: 40 for SHA-1, 64 for SHA-256
+    digits: usize,
+    lines: u64,
+}
+
+impl HashList {
+    fn open(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let mut first = String::new();
+        BufReader::new(&mut file).read_line(&mut first)?;
+        let digits = first.trim_end_matches('\n').len();
+        if digits != 40 && digits != 64 {
+            return Err(IntegrityError::Validation(format!("{:?} is not a list of SHA-1 or SHA-256 digests", path)));
+        }
+        let size = file.metadata()?.len();
+        if size % (digits as u64 + 1) != 0 {
+            return Err(IntegrityError::Validation(format!("{:?} has lines that are not {} hex digits", path, digits)));
+        }
+        Ok(Self { file: Mutex::new(file), digits, lines: size / (digits as u64 + 1) })
+    }
+
+    fn contains(&self, digests: &Digests) -> std::io::Result<bool> {
+        let wanted = if self.digits == 40 { &digests.sha1 } else { &digests.sha256 };
+        let mut file = self.file.lock().unwrap();
+        let mut line = vec![0u8; self.digits];
+        let (mut low, mut high) = (0, self.lines);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            file.seek(SeekFrom::Start(middle * (self.digits as u64 + 1)))?;
+            file.read_exact(&mut line)?;
+            match line.as_slice().cmp(wanted.as_bytes()) {
+                std::cmp::Ordering::Equal => return Ok(true),
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Allows one call per interval, without waiting.
+struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    fn per_minute(calls: u32) -> Self {
+        Self { interval: Duration::from_secs(60) / calls.max(1), next: Mutex::new(None) }
+    }
+
+    fn try_acquire(&self) -> bool {
+        let mut next = self.next.lock().unwrap();
+        let now = Instant::now();
+        if next.is_some_and(|next| now < next) {
+            return false;
+        }
+        *next = Some(now + self.interval);
+        true
+    }
+}
+
+struct VirusTotal {
+    url: String,
+    api_key: String,
+    min_detections: u64,
+    limiter: RateLimiter,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheRecord {
+    sha256: String,
+    checked_at: i64,
+    #[serde(flatten)]
+    verdict: Verdict,
+}
+
+/// Verdicts by SHA-256, kept in the state dir as a JSON Lines journal.
+struct Cache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (i64, Verdict)>>,
+    journal: Mutex<File>,
+}
+
+impl Cache {
+    /// Loads the unexpired verdicts and rewrites the journal with only those.
+    fn open(dir: &Path, ttl: Duration) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(CACHE_FILE);
+        let oldest = chrono::Utc::now().timestamp() - ttl.as_secs() as i64;
+        let mut entries = HashMap::new();
+        if let Ok(file) = File::open(&path) {
+            for line in BufReader::new(file).lines() {
+                let Ok(record) = serde_json::from_str::<CacheRecord>(&line?) else {
+                    continue;
+                };
+                if record.checked_at >= oldest {
+                    entries.insert(record.sha256, (record.checked_at, record.verdict));
+                }
+            }
+        }
+
+        let temporary = dir.join(format!("{}.tmp", CACHE_FILE));
+        let mut file = File::create(&temporary)?;
+        for (sha256, (checked_at, verdict)) in &entries {
+            file.write_all(&record_line(sha256, *checked_at, verdict)?)?;
+        }
+        file.sync_data()?;
+        std::fs::rename(&temporary, &path)?;
+
+        let journal = OpenOptions::new().append(true).open(&path)?;
+        Ok(Self { ttl, entries: Mutex::new(entries), journal: Mutex::new(journal) })
+    }
+
+    fn get(&self, sha256: &str) -> Option<Verdict> {
+        let oldest = chrono::Utc::now().timestamp() - self.ttl.as_secs() as i64;
+        match self.entries.lock().unwrap().get(sha256) {
+            Some((checked_at, verdict)) if *checked_at >= oldest => Some(verdict.clone()),
+            _ => None,
+        }
+    }
+
+    fn insert(&self, sha256: &str, verdict: &Verdict) {
+        let checked_at = chrono::Utc::now().timestamp();
+        self.entries.lock().unwrap().insert(sha256.to_string(), (checked_at, verdict.clone()));
+        let written = record_line(sha256, checked_at, verdict).and_then(|line| Ok(self.journal.lock().unwrap().write_all(&line)?));
+        if let Err(e) = written {
+            warn!("Failed to record reputation of {}: {}", sha256, e);
+        }
+    }
+}
+
+fn record_line(sha256: &str, checked_at: i64, verdict: &Verdict) -> Result<Vec<u8>> {
+    let record = CacheRecord { sha256: sha256.to_string(), checked_at, verdict: verdict.clone() };
+    let mut line = serde_json::to_vec(&record)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+pub struct Reputation {
+    http: reqwest::Client,
+    nsrl: Option<HashList>,
+    allowlist_url: Option<String>,
+    virustotal: Option<VirusTotal>,
+    cache: Cache,
+}
+
+/// A source's answer: a verdict if it knows the file, an error if it could not be asked.
+type Lookup = std::result::Result<Option<Verdict>, String>;
+
+impl Reputation {
+    fn lookup_nsrl(&self, digests: &Digests) -> Lookup {
+        let Some(nsrl) = &self.nsrl else {
+            return Ok(None);
+        };
+        match nsrl.contains(digests) {
+            Ok(true) => Ok(Some(Verdict::KnownGood { source: "nsrl".to_string() })),
+            Ok(false) => Ok(None),
+            Err(e) => Err(format!("nsrl: {}", e)),
+        }
+    }
+
+    async fn lookup_allowlist(&self, digests: &Digests) -> Lookup {
+        let Some(url) = &self.allowlist_url else {
+            return Ok(None);
+        };
+        match self.http.get(format!("{}/{}", url, digests.sha512)).send().await {
+            Ok(response) if response.status() == StatusCode::OK => Ok(Some(Verdict::KnownGood { source: "allowlist".to_string() })),
+            Ok(response) if response.status() == StatusCode::NOT_FOUND => Ok(None),
+            Ok(response) => Err(format!("allowlist: {}", response.status())),
+            Err(e) => Err(format!("allowlist: {}", e)),
+        }
+    }
+
+    async fn lookup_virustotal(&self, digests: &Digests) -> Lookup {
+        let Some(virustotal) = &self.virustotal else {
+            return Ok(None);
+        };
+        if !virustotal.limiter.try_acquire() {
+            return Err("virustotal: rate limited".to_string());
+        }
+        let response = self
+            .http
+            .get(format!("{}/files/{}", virustotal.url, digests.sha256))
+            .header("x-apikey", &virustotal.api_key)
+            .send()
+            .await
+            .map_err(|e| format!("virustotal: {}", e))?;
+        match response.status() {
+            StatusCode::NOT_FOUND => return Ok(None),
+            StatusCode::OK => {}
+            status => return Err(format!("virustotal: {}", status)),
+        }
+        let report: serde_json::Value = response.json().await.map_err(|e| format!("virustotal: {}", e))?;
+        let stats = report.pointer("/data/attributes/last_analysis_stats").and_then(|s| s.as_object());
+        let engines: u64 = stats.map_or(0, |stats| stats.values().filter_map(|count| count.as_u64()).sum());
+        let malicious = stats.and_then(|stats| stats.get("malicious")).and_then(|count| count.as_u64()).unwrap_or(0);
+        if malicious >= virustotal.min_detections.max(1) {
+            let detail = format!("{}/{} engines", malicious, engines);
+            return Ok(Some(Verdict::KnownBad { source: "virustotal".to_string(), detail }));
+        }
+        Ok(None)
+    }
+
+    /// The verdict on a file, and whether every source could be asked.
+    async fn verdict(&self, digests: &Digests) -> (Verdict, bool) {
+        if let Some(verdict) = self.cache.get(&digests.sha256) {
+            return (verdict, true);
+        }
+        let mut complete = true;
+        for lookup in [
+            self.lookup_nsrl(digests),
+            self.lookup_allowlist(digests).await,
+            self.lookup_virustotal(digests).await,
+        ] {
+            match lookup {
+                Ok(Some(verdict)) => {
+                    self.cache.insert(&digests.sha256, &verdict);
+                    return (verdict, true);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("Reputation lookup of {} incomplete: {}", digests.sha256, e);
+                    complete = false;
+                }
+            }
+        }
+        if complete {
+            self.cache.insert(&digests.sha256, &Verdict::Unknown);
+        }
+        (Verdict::Unknown, complete)
+    }
+}
+
+#[async_trait]
+impl Enricher for Reputation {
+    fn name(&self) -> &str {
+        "reputation"
+    }
+
+    async fn enrich(&self, request: &EnrichRequest<'_>) -> Option<Annotation> {
+        if request.kind != "ADDED" || !request.path.is_file() {
+            return None;
+        }
+        let path = request.path.to_path_buf();
+        let digests = match tokio::task::spawn_blocking(move || digest(&path)).await {
+            Ok(Ok(digests)) => digests,
+            Ok(Err(e)) => {
+                warn!("Cannot hash {} for reputation lookup: {}", request.relative_path, e);
+                return None;
+            }
+            Err(_) => return None,
+        };
+        let (verdict, complete) = self.verdict(&digests).await;
+        let mut annotation = verdict.annotation();
+        if !complete {
+            annotation.note.push_str(" so far");
+        }
+        Some(annotation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_nsrl_lookup_and_cache() {
+        let dir = std::env::temp_dir().join(format!("reputation-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("root/usr/bin")).unwrap();
+        std::fs::write(dir.join("root/usr/bin/known"), "known software").unwrap();
+        std::fs::write(dir.join("root/usr/bin/other"), "something else").unwrap();
+        let known = digest(&dir.join("root/usr/bin/known")).unwrap();
+        let mut hashes = [known.sha1.clone(), "0".repeat(40), "f".repeat(40), "a".repeat(40)];
+        hashes.sort();
+        std::fs::write(dir.join("nsrl.txt"), hashes.iter().map(|h| format!("{}\n", h)).collect::<String>()).unwrap();
+
+        let args = ReputationArgs {
+            nsrl_hashes: Some(dir.join("nsrl.txt")),
+            reputation_allowlist_url: None,
+            virustotal_api_key_file: None,
+            virustotal_url: String::new(),
+            virustotal_rate: 4,
+            virustotal_min_detections: 3,
+            reputation_cache_ttl: Duration::from_secs(3600),
+        };
+        let reputation = args.reputation(&dir.join("state")).unwrap().unwrap();
+        let (reputation, root) = (&reputation, dir.join("root"));
+        let request = |path: &'static str, kind| {
+            let full = root.join(path);
+            async move { reputation.enrich(&EnrichRequest { path: &full, relative_path: path, kind }).await }
+        };
+        assert_eq!(request("usr/bin/known", "ADDED").await.unwrap().note, "reputation: known-good (nsrl)");
+        assert_eq!(request("usr/bin/other", "ADDED").await.unwrap().note, "reputation: unknown");
+        assert_eq!(request("usr/bin/known", "MODIFIED").await, None);
+
+        // Verdicts outlive the agent
+        let cache = Cache::open(&dir.join("state"), Duration::from_secs(3600)).unwrap();
+        assert_eq!(cache.get(&known.sha256), Some(Verdict::KnownGood { source: "nsrl".to_string() }));
+        assert_eq!(cache.entries.lock().unwrap().len(), 2);
+
+        let limiter = RateLimiter::per_minute(4);
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
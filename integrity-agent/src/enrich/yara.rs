@@ -0,0 +1,184 @@
+//! YARA scanning of added and modified files.
+//!
+//! Files are scanned with the `yara` command-line scanner (`--yara-binary`)
+//! against the rule files of `--yara-rules`, or the rule set the metadata
+//! service distributes (`--yara-rules-from-service`, cached in the state dir
+//! for when the service is unreachable). A match re-labels the anomaly
+//! `YARA_MATCH` and names the matching rules.
+
+use super::{Annotation, EnrichRequest, Enricher};
+use async_trait::async_trait;
+use integrity_client::MetadataClient;
+use integrity_common::{IntegrityError, Result};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+/// Rules fetched from the service, kept for restarts while it is unreachable
+const SERVICE_RULES_FILE: &str = "yara-rules.yar";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct YaraArgs {
+    /// YARA rule file, or directory of .yar/.yara files, to scan added and modified files with
+    #[arg(long, conflicts_with = "yara_rules_from_service")]
+    pub yara_rules: Option<PathBuf>,
+
+    /// Scan with the YARA rules distributed by the metadata service
+    #[arg(long)]
+    pub yara_rules_from_service: bool,
+
+    /// Only scan files under these paths [default: everywhere]
+    #[arg(long, value_delimiter = ',')]
+    pub yara_paths: Vec<PathBuf>,
+
+    #[arg(long, default_value = "yara")]
+    pub yara_binary: PathBuf,
+
+    /// Seconds a single file scan may take
+    #[arg(long, default_value = "30")]
+    pub yara_timeout: u64,
+}
+
+impl YaraArgs {
+    /// The configured scanner, if any, with its rules in place.
+    pub async fn scanner(&self, client: &MetadataClient, state_dir: &Path) -> Result<Option<YaraScanner>> {
+        let rules = if let Some(rules) = &self.yara_rules {
+            rule_files(rules)?
+        } else if self.yara_rules_from_service {
+            vec![fetch_rules(client, state_dir).await?]
+        } else {
+            return Ok(None);
+        };
+        if rules.is_empty() {
+            return Err(IntegrityError::Validation(format!("No YARA rule files in {:?}", self.yara_rules)));
+        }
+        info!("Scanning added and modified files with {} YARA rule file(s)", rules.len());
+        Ok(Some(YaraScanner {
+            binary: self.yara_binary.clone(),
+            rules,
+            paths: self.yara_paths.iter().map(|p| p.to_string_lossy().trim_matches('/').to_string()).collect(),
+            timeout: Duration::from_secs(self.yara_timeout),
+        }))
+    }
+}
+
+fn rule_files(rules: &Path) -> Result<Vec<PathBuf>> {
+    if !rules.is_dir() {
+        return Ok(vec![rules.to_path_buf()]);
+    }
+    let mut files: Vec<PathBuf> = std::fs::read_dir(rules)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "yar" || ext == "yara"))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+async fn fetch_rules(client: &MetadataClient, state_dir: &Path) -> Result<PathBuf> {
+    let cached = state_dir.join(SERVICE_RULES_FILE);
+    match client.get_yara_rules().await {
+        Ok(rules) => {
+            std::fs::create_dir_all(state_dir)?;
+            std::fs::write(&cached, rules)?;
+        }
+        Err(e) if cached.exists() => warn!("Cannot fetch YARA rules, using the ones fetched before: {}", e),
+        Err(e) => return Err(e),
+    }
+    Ok(cached)
+}
+
+/// Rule names in `yara` output, one "<rule> <file>" line per match.
+fn matched_rules(output: &str) -> Vec<String> {
+    let mut rules: Vec<String> = output.lines().filter_map(|line| line.split_whitespace().next()).map(str::to_string).collect();
+    rules.dedup();
+    rules
+}
+
+pub struct YaraScanner {
+    binary: PathBuf,
+    rules: Vec<PathBuf>,
+    /// Relative path prefixes to scan under; empty for all
+    paths: Vec<String>,
+    timeout: Duration,
+}
+
+impl YaraScanner {
+    fn in_scope(&self, relative_path: &str) -> bool {
+        self.paths.is_empty()
+            || self.paths.iter().any(|prefix| {
+                prefix.is_empty() || relative_path == prefix || relative_path.starts_with(&format!("{}/", prefix))
+            })
+    }
+}
+
+#[async_trait]
+impl Enricher for YaraScanner {
+    fn name(&self) -> &str {
+        "yara"
+    }
+
+    async fn enrich(&self, request: &EnrichRequest<'_>) -> Option<Annotation> {
+        if !self.in_scope(request.relative_path) || !request.path.is_file() {
+            return None;
+        }
+        let scan = Command::new(&self.binary)
+            .arg("--no-warnings")
+            .arg(format!("--timeout={}", self.timeout.as_secs()))
+            .args(&self.rules)
+            .arg(request.path)
+            .kill_on_drop(true)
+            .output();
+        let output = match tokio::time::timeout(self.timeout + Duration::from_secs(5), scan).await {
+            Ok(Ok(output)) if output.status.success() => output,
+            Ok(Ok(output)) => {
+                warn!("YARA scan of {} failed: {}", request.relative_path, String::from_utf8_lossy(&output.stderr).trim());
+                return None;
+            }
+            Ok(Err(e)) => {
+                warn!("Cannot run {:?}: {}", self.binary, e);
+                return None;
+            }
+            Err(_) => {
+                warn!("YARA scan of {} timed out", request.relative_path);
+                return None;
+            }
+        };
+        let rules = matched_rules(&String::from_utf8_lossy(&output.stdout));
+        if rules.is_empty() {
+            return None;
+        }
+        Some(Annotation { kind: Some("YARA_MATCH"), note: format!("yara: {}", rules.join(", ")) })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn test_scan_with_yara_cli() {
+        let dir = std::env::temp_dir().join(format!("yara-scan-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("tmp")).unwrap();
+        // Stands in for yara: reports two rules for files containing "evil"
+        let binary = dir.join("yara");
+        std::fs::write(&binary, "#!/bin/sh\nfor f; do :; done\ngrep -q evil \"$f\" && echo \"Mirai $f\" && echo \"Mirai $f\" && echo \"Xmrig $f\"\nexit 0\n").unwrap();
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(dir.join("tmp/bad"), "evil").unwrap();
+        std::fs::write(dir.join("tmp/good"), "fine").unwrap();
+
+        let scanner = YaraScanner {
+            binary,
+            rules: vec![dir.join("rules.yar")],
+            paths: vec!["tmp".to_string()],
+            timeout: Duration::from_secs(5),
+        };
+        let (bad, good) = (dir.join("tmp/bad"), dir.join("tmp/good"));
+        let annotation = scanner.enrich(&EnrichRequest { path: &bad, relative_path: "tmp/bad", kind: "ADDED" }).await.unwrap();
+        assert_eq!(annotation, Annotation { kind: Some("YARA_MATCH"), note: "yara: Mirai, Xmrig".to_string() });
+        assert_eq!(scanner.enrich(&EnrichRequest { path: &good, relative_path: "tmp/good", kind: "ADDED" }).await, None);
+        assert!(!scanner.in_scope("tmpfile"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,313 @@
+use crate::monitor::{EventType, FileEvent, Monitor};
+use async_trait::async_trait;
+use fsevent_sys as fs;
+use fsevent_sys::core_foundation as cf;
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::c_char;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+/// Seconds FSEvents may coalesce changes before delivering them.
+const LATENCY: f64 = 0.1;
+
+/// An FSEvents-based file system monitor for macOS.
+///
+/// File-level FSEvents report content, metadata and namespace changes under the
+/// watch paths. When the agent holds the Endpoint Security entitlement, exec
+/// events for binaries under the watch paths are reported as well, so a tampered
+/// binary is caught when it is run rather than only when it is written.
+pub struct FsEventsMonitor {
+    watch_paths: Vec<PathBuf>,
+    channel_capacity: usize,
+    run_loop: Option<RunLoop>,
+    exec_client: Option<endpoint_security::ExecClient>,
+}
+
+/// The FSEvents thread's run loop, kept so `stop` can end it.
+struct RunLoop(cf::CFRunLoopRef);
+
+// CFRunLoopStop may be called from any thread
+unsafe impl Send for RunLoop {}
+unsafe impl Sync for RunLoop {}
+
+impl FsEventsMonitor {
+    pub fn new(watch_paths: Vec<PathBuf>, channel_capacity: usize) -> Self {
+        Self {
+            watch_paths,
+            channel_capacity,
+            run_loop: None,
+            exec_client: None,
+        }
+    }
+}
+
+#[async_trait]
+impl Monitor for FsEventsMonitor {
+    async fn start(&mut self) -> Result<mpsc::Receiver<FileEvent>, Box<dyn std::error::Error + Send + Sync>> {
+        let (tx, rx) = mpsc::channel(self.channel_capacity);
+
+        match endpoint_security::ExecClient::new(self.watch_paths.clone(), tx.clone()) {
+            Ok(client) => {
+                tracing::info!("Endpoint Security exec monitoring enabled");
+                self.exec_client = Some(client);
+            }
+            Err(e) => tracing::info!("Endpoint Security unavailable ({}), monitoring file changes only", e),
+        }
+
+        let (loop_tx, loop_rx) = std::sync::mpsc::channel();
+        let watch_paths = self.watch_paths.clone();
+        std::thread::spawn(move || run_stream(&watch_paths, tx, loop_tx));
+
+        let run_loop = loop_rx
+            .recv()
+            .map_err(|_| "FSEvents thread exited before starting")??;
+        tracing::info!("Watching {:?} with FSEvents", self.watch_paths);
+        self.run_loop = Some(run_loop);
+        Ok(rx)
+    }
+
+    async fn stop(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        tracing::info!("Stopping FSEvents monitor");
+        if let Some(run_loop) = self.run_loop.take() {
+            unsafe { cf::CFRunLoopStop(run_loop.0) };
+        }
+        self.exec_client.take();
+        Ok(())
+    }
+}
+
+type StartResult = Result<RunLoop, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Creates the event stream and runs it on this thread's run loop until stopped.
+fn run_stream(watch_paths: &[PathBuf], tx: mpsc::Sender<FileEvent>, started: std::sync::mpsc::Sender<StartResult>) {
+    // Leaked into the stream context and reclaimed once the run loop returns
+    let info = Box::into_raw(Box::new(tx));
+
+    let stream = unsafe {
+        let paths = cf::CFArrayCreateMutable(cf::kCFAllocatorDefault, 0, &cf::kCFTypeArrayCallBacks);
+        for path in watch_paths {
+            let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+                continue;
+            };
+            let cf_path = cf::CFStringCreateWithCString(cf::kCFAllocatorDefault, c_path.as_ptr(), cf::kCFStringEncodingUTF8);
+            cf::CFArrayAppendValue(paths, cf_path);
+            cf::CFRelease(cf_path);
+        }
+
+        let context = fs::FSEventStreamContext {
+            version: 0,
+            info: info as *mut c_void,
+            retain: None,
+            release: None,
+            copy_description: None,
+        };
+        let stream = fs::FSEventStreamCreate(
+            cf::kCFAllocatorDefault,
+            callback,
+            &context,
+            paths,
+            fs::kFSEventStreamEventIdSinceNow,
+            LATENCY,
+            fs::kFSEventStreamCreateFlagFileEvents | fs::kFSEventStreamCreateFlagNoDefer,
+        );
+        cf::CFRelease(paths);
+        stream
+    };
+
+    if stream.is_null() {
+        drop(unsafe { Box::from_raw(info) });
+        let _ = started.send(Err("FSEventStreamCreate failed".into()));
+        return;
+    }
+
+    unsafe {
+        let run_loop = cf::CFRunLoopGetCurrent();
+        fs::FSEventStreamScheduleWithRunLoop(stream, run_loop, cf::kCFRunLoopDefaultMode);
+        if fs::FSEventStreamStart(stream) == 0 {
+            let _ = started.send(Err("FSEventStreamStart failed".into()));
+        } else {
+            let _ = started.send(Ok(RunLoop(run_loop)));
+            cf::CFRunLoopRun();
+            fs::FSEventStreamStop(stream);
+        }
+        fs::FSEventStreamInvalidate(stream);
+        fs::FSEventStreamRelease(stream);
+        drop(Box::from_raw(info));
+    }
+}
+
+extern "C" fn callback(
+    _stream: fs::FSEventStreamRef,
+    info: *mut c_void,
+    num_events: usize,
+    event_paths: *mut c_void,
+    event_flags: *const fs::FSEventStreamEventFlags,
+    _event_ids: *const fs::FSEventStreamEventId,
+) {
+    let tx = unsafe { &*(info as *const mpsc::Sender<FileEvent>) };
+    let paths = unsafe { std::slice::from_raw_parts(event_paths as *const *const c_char, num_events) };
+    let flags = unsafe { std::slice::from_raw_parts(event_flags, num_events) };
+
+    for (&path, &flags) in paths.iter().zip(flags) {
+        let path = Path::new(std::ffi::OsStr::from_bytes(unsafe { CStr::from_ptr(path) }.to_bytes())).to_path_buf();
+        if flags & fs::kFSEventStreamEventFlagMustScanSubDirs != 0 {
+            // Events under `path` were coalesced or dropped (user or kernel queue full)
+            if tx.blocking_send(FileEvent { path, event_type: EventType::Overflow }).is_err() {
+                return;
+            }
+            continue;
+        }
+        if flags & fs::kFSEventStreamEventFlagItemIsDir != 0 {
+            continue;
+        }
+
+        // Flags accumulate within the latency window; a removal wins
+        let event_type = if flags & fs::kFSEventStreamEventFlagItemRemoved != 0 {
+            EventType::Deleted
+        } else if flags & fs::kFSEventStreamEventFlagItemCreated != 0 {
+            EventType::Created
+        } else {
+            EventType::Modified
+        };
+
+        // Runs on the FSEvents thread, so blocking here only delays later callbacks
+        if tx.blocking_send(FileEvent { path, event_type }).is_err() {
+            return;
+        }
+    }
+}
+
+/// Minimal Endpoint Security bindings for NOTIFY_EXEC.
+///
+/// Only the struct prefixes needed to reach the executed file's path are
+/// declared; messages are only ever read through pointers handed out by the
+/// framework. Requires the com.apple.developer.endpoint-security.client
+/// entitlement and root.
+mod endpoint_security {
+    use crate::monitor::{EventType, FileEvent};
+    use block::{ConcreteBlock, RcBlock};
+    use std::ffi::c_void;
+    use std::os::raw::c_char;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+    use tokio::sync::mpsc;
+
+    const ES_EVENT_TYPE_NOTIFY_EXEC: u32 = 9;
+    const ES_NEW_CLIENT_RESULT_SUCCESS: u32 = 0;
+
+    #[repr(C)]
+    struct EsStringToken {
+        length: usize,
+        data: *const c_char,
+    }
+
+    #[repr(C)]
+    struct EsFile {
+        path: EsStringToken,
+    }
+
+    #[repr(C)]
+    struct EsProcess {
+        audit_token: [u32; 8],
+        ppid: i32,
+        original_ppid: i32,
+        group_id: i32,
+        session_id: i32,
+        codesigning_flags: u32,
+        is_platform_binary: bool,
+        is_es_client: bool,
+        cdhash: [u8; 20],
+        signing_id: EsStringToken,
+        team_id: EsStringToken,
+        executable: *const EsFile,
+    }
+
+    #[repr(C)]
+    struct EsEventExec {
+        target: *const EsProcess,
+    }
+
+    #[repr(C)]
+    struct EsMessage {
+        version: u32,
+        time: [i64; 2],
+        mach_time: u64,
+        deadline: u64,
+        process: *const EsProcess,
+        seq_num: u64,
+        action_type: u32,
+        action: [u32; 9],
+        event_type: u32,
+        event: EsEventExec,
+    }
+
+    type EsClient = c_void;
+    type Handler = block::Block<(*mut EsClient, *const EsMessage), ()>;
+
+    #[link(name = "EndpointSecurity")]
+    extern "C" {
+        fn es_new_client(client: *mut *mut EsClient, handler: &Handler) -> u32;
+        fn es_subscribe(client: *mut EsClient, events: *const u32, event_count: u32) -> u32;
+        fn es_delete_client(client: *mut EsClient) -> u32;
+    }
+
+    /// An Endpoint Security client subscribed to exec notifications.
+    pub struct ExecClient {
+        client: *mut EsClient,
+        // Must outlive the client, which holds a reference to it
+        _handler: RcBlock<(*mut EsClient, *const EsMessage), ()>,
+    }
+
+    // The client handle is only passed back to es_delete_client
+    unsafe impl Send for ExecClient {}
+    unsafe impl Sync for ExecClient {}
+
+    impl ExecClient {
+        pub fn new(watch_paths: Vec<PathBuf>, tx: mpsc::Sender<FileEvent>) -> Result<Self, String> {
+            let handler = ConcreteBlock::new(move |_client: *mut EsClient, message: *const EsMessage| {
+                let message = unsafe { &*message };
+                if message.event_type != ES_EVENT_TYPE_NOTIFY_EXEC {
+                    return;
+                }
+                let path = unsafe {
+                    let file = &*(*message.event.target).executable;
+                    std::slice::from_raw_parts(file.path.data as *const u8, file.path.length)
+                };
+                let path = PathBuf::from(std::ffi::OsStr::from_bytes(path));
+                if !watch_paths.iter().any(|w| path.starts_with(w)) {
+                    return;
+                }
+                // Never block the Endpoint Security queue
+                if tx.try_send(FileEvent { path, event_type: EventType::Accessed }).is_err() {
+                    tracing::debug!("Dropped exec event, monitor channel full or closed");
+                }
+            })
+            .copy();
+
+            let mut client = std::ptr::null_mut();
+            let result = unsafe { es_new_client(&mut client, &handler) };
+            if result != ES_NEW_CLIENT_RESULT_SUCCESS {
+                // Typically not entitled (3) or not running as root (5)
+                return Err(format!("es_new_client returned {}", result));
+            }
+
+            let events = [ES_EVENT_TYPE_NOTIFY_EXEC];
+            if unsafe { es_subscribe(client, events.as_ptr(), events.len() as u32) } != 0 {
+                unsafe { es_delete_client(client) };
+                return Err("es_subscribe failed".to_string());
+            }
+
+            Ok(Self {
+                client,
+                _handler: handler,
+            })
+        }
+    }
+
+    impl Drop for ExecClient {
+        fn drop(&mut self) {
+            unsafe { es_delete_client(self.client) };
+        }
+    }
+}
@@ -0,0 +1,363 @@
+//! Point-in-time scans from a filesystem snapshot (Linux).
+//!
+//! A scan of a live filesystem reads files over minutes while they keep
+//! changing, so a package upgrade in the middle shows up as a mix of old and
+//! new files. With `--fs-snapshot`, scan mode first snapshots the filesystem
+//! holding `--scan-path` and scans the read-only snapshot instead:
+//!
+//! - btrfs: a read-only snapshot of the mounted subvolume, next to it
+//! - ZFS: a snapshot of the dataset, read through its `.zfs/snapshot` directory
+//! - LVM: a copy-on-write snapshot volume of `--fs-snapshot-size`, mounted
+//!   read-only under the state directory
+//!
+//! Other filesystems mounted below the scan path are not in the snapshot and
+//! are scanned live. The snapshot is removed when the scan ends, and
+//! snapshots left behind by an agent that was killed are removed by the next
+//! run. `auto` picks the kind from the filesystem and scans live when none
+//! applies.
+
+use crate::hashcache::HashCache;
+use integrity_common::{FileIntegrityEntry, HashAlgorithm, IntegrityError, Result, ScanConfig};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, warn};
+
+/// Names of the snapshots the agent creates start with this, followed by its pid.
+const SNAPSHOT_PREFIX: &str = "acropole-scan-";
+
+/// Filesystems with nothing to verify, never scanned live below the scan path.
+const PSEUDO_FILESYSTEMS: &[&str] = &[
+    "proc", "sysfs", "devtmpfs", "devpts", "tmpfs", "cgroup", "cgroup2", "securityfs", "debugfs", "tracefs", "pstore",
+    "bpf", "mqueue", "hugetlbfs", "configfs", "fusectl", "autofs", "binfmt_misc", "efivarfs", "overlay", "nsfs",
+];
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum SnapshotKind {
+    /// Scan the live filesystem
+    None,
+    /// Snapshot with whatever the filesystem supports, or scan live
+    Auto,
+    Btrfs,
+    Zfs,
+    Lvm,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct FsSnapshotArgs {
+    /// Scan a snapshot of the filesystem holding --scan-path, so scan results are a single point in time
+    #[arg(long, value_enum, default_value = "none")]
+    pub fs_snapshot: SnapshotKind,
+
+    /// Copy-on-write space of LVM snapshots; writes during the scan beyond it invalidate the snapshot
+    #[arg(long, default_value = "2G")]
+    pub fs_snapshot_size: String,
+}
+
+/// A line of /proc/self/mountinfo.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+    pub mount_point: PathBuf,
+    pub fstype: String,
+    pub source: String,
+}
+
+/// Undoes the `\040`-style escapes of mountinfo fields.
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4).and_then(|d| std::str::from_utf8(d).ok()).and_then(|d| u8::from_str_radix(d, 8).ok());
+        match (bytes[i], octal) {
+            (b'\\', Some(byte)) => {
+                out.push(byte);
+                i += 4;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+pub fn parse_mountinfo(mountinfo: &str) -> Vec<Mount> {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let (mount, filesystem) = line.split_once(" - ")?;
+            let mount_point = mount.split_whitespace().nth(4)?;
+            let mut filesystem = filesystem.split_whitespace();
+            Some(Mount {
+                mount_point: PathBuf::from(unescape(mount_point)),
+                fstype: filesystem.next()?.to_string(),
+                source: unescape(filesystem.next()?),
+            })
+        })
+        .collect()
+}
+
+/// The mount holding `path`: the last one mounted at its longest mounted prefix.
+fn mount_of<'a>(mounts: &'a [Mount], path: &Path) -> Option<&'a Mount> {
+    mounts.iter().filter(|m| path.starts_with(&m.mount_point)).max_by_key(|m| m.mount_point.components().count())
+}
+
+/// Real filesystems mounted below `path`, which a snapshot of its own
+/// filesystem does not include. Scanning one scans those below it too.
+fn mounts_below<'a>(mounts: &'a [Mount], path: &Path) -> Vec<&'a Mount> {
+    let below: Vec<&Mount> = mounts
+        .iter()
+        .filter(|m| m.mount_point != path && m.mount_point.starts_with(path))
+        .filter(|m| !PSEUDO_FILESYSTEMS.contains(&m.fstype.as_str()))
+        .collect();
+    let mut outermost: Vec<&Mount> = below
+        .iter()
+        .filter(|m| !below.iter().any(|outer| outer.mount_point != m.mount_point && m.mount_point.starts_with(&outer.mount_point)))
+        .copied()
+        .collect();
+    outermost.dedup_by(|a, b| a.mount_point == b.mount_point);
+    outermost
+}
+
+fn run(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| IntegrityError::Io(std::io::Error::new(e.kind(), format!("running {}: {}", program, e))))?;
+    if !output.status.success() {
+        return Err(IntegrityError::Io(std::io::Error::other(format!(
+            "{} {} failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// How to remove a snapshot.
+#[derive(Debug)]
+enum Cleanup {
+    Btrfs { path: PathBuf },
+    Zfs { name: String },
+    Lvm { volume: String, mount_point: PathBuf },
+}
+
+impl Cleanup {
+    fn run(&self) -> Result<()> {
+        match self {
+            Cleanup::Btrfs { path } => run("btrfs", &["subvolume", "delete", &path.to_string_lossy()]).map(drop),
+            Cleanup::Zfs { name } => run("zfs", &["destroy", name]).map(drop),
+            Cleanup::Lvm { volume, mount_point } => {
+                let unmounted = run("umount", &[&mount_point.to_string_lossy()]).map(drop);
+                run("lvremove", &["-f", volume])?;
+                unmounted
+            }
+        }
+    }
+}
+
+/// A snapshot being scanned; removed when dropped.
+pub struct FsSnapshot {
+    /// Where the scan path's contents are in the snapshot
+    root: PathBuf,
+    /// Filesystems below the scan path to scan live
+    live_mounts: Vec<PathBuf>,
+    cleanup: Cleanup,
+}
+
+impl FsSnapshot {
+    /// `base` is the snapshot of the mount point `relative` is below.
+    fn new(base: PathBuf, relative: &Path, cleanup: Cleanup) -> Self {
+        Self { root: base.join(relative), live_mounts: Vec::new(), cleanup }
+    }
+
+    /// Scans the snapshot, and the filesystems below `scan_path` live,
+    /// recording paths as if all of it were scanned at `scan_path`. File
+    /// identities in a snapshot change with every snapshot, so `hashes` is
+    /// only used for the live filesystems.
+    pub fn scan(
+        &self,
+        scan_path: &Path,
+        config: &ScanConfig,
+        algorithm: HashAlgorithm,
+        hashes: Option<&HashCache>,
+    ) -> Result<HashMap<String, FileIntegrityEntry>> {
+        let mut entries = crate::scan_subtree(&self.root, &self.root, config, algorithm, None)?;
+        for mount_point in &self.live_mounts {
+            entries.extend(crate::scan_subtree(scan_path, mount_point, config, algorithm, hashes)?);
+        }
+        Ok(entries)
+    }
+}
+
+impl Drop for FsSnapshot {
+    fn drop(&mut self) {
+        match self.cleanup.run() {
+            Ok(()) => info!("Removed filesystem snapshot {:?}", self.cleanup),
+            Err(e) => warn!("Failed to remove filesystem snapshot {:?}: {}", self.cleanup, e),
+        }
+    }
+}
+
+/// Removes snapshots an earlier, killed agent left behind.
+fn remove_stale(kind: SnapshotKind, mount: &Mount) {
+    let stale: Vec<Cleanup> = match kind {
+        SnapshotKind::Btrfs => std::fs::read_dir(&mount.mount_point)
+            .map(|dir| {
+                dir.flatten()
+                    .filter(|e| e.file_name().to_string_lossy().starts_with(&format!(".{}", SNAPSHOT_PREFIX)))
+                    .map(|e| Cleanup::Btrfs { path: e.path() })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        SnapshotKind::Zfs => run("zfs", &["list", "-H", "-t", "snapshot", "-o", "name", &mount.source])
+            .map(|names| {
+                names
+                    .lines()
+                    .filter(|name| name.contains(&format!("@{}", SNAPSHOT_PREFIX)))
+                    .map(|name| Cleanup::Zfs { name: name.to_string() })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        // Stale LVM snapshots are removed with their mount point, see `lvm`
+        _ => Vec::new(),
+    };
+    for cleanup in stale {
+        warn!("Removing stale filesystem snapshot {:?}", cleanup);
+        if let Err(e) = cleanup.run() {
+            warn!("Failed to remove stale snapshot: {}", e);
+        }
+    }
+}
+
+fn btrfs(mount: &Mount, relative: &Path) -> Result<FsSnapshot> {
+    let path = mount.mount_point.join(format!(".{}{}", SNAPSHOT_PREFIX, std::process::id()));
+    run("btrfs", &["subvolume", "snapshot", "-r", &mount.mount_point.to_string_lossy(), &path.to_string_lossy()])?;
+    Ok(FsSnapshot::new(path.clone(), relative, Cleanup::Btrfs { path }))
+}
+
+fn zfs(mount: &Mount, relative: &Path) -> Result<FsSnapshot> {
+    let snapshot = format!("{}{}", SNAPSHOT_PREFIX, std::process::id());
+    let name = format!("{}@{}", mount.source, snapshot);
+    run("zfs", &["snapshot", &name])?;
+    let base = mount.mount_point.join(".zfs/snapshot").join(&snapshot);
+    Ok(FsSnapshot::new(base, relative, Cleanup::Zfs { name }))
+}
+
+fn lvm(mount: &Mount, relative: &Path, size: &str, state_dir: &Path) -> Result<FsSnapshot> {
+    let names = run("lvs", &["--noheadings", "-o", "vg_name,lv_name", &mount.source])?;
+    let (vg, lv) = names
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .try_into()
+        .map(|[vg, lv]: [&str; 2]| (vg.to_string(), lv.to_string()))
+        .map_err(|_| IntegrityError::Validation(format!("{} is not a logical volume", mount.source)))?;
+
+    // A previous run killed mid-scan leaves its snapshot mounted here
+    let mount_point = state_dir.join("fs-snapshot");
+    std::fs::create_dir_all(&mount_point)?;
+    if let Ok(mounts) = std::fs::read_to_string("/proc/self/mountinfo") {
+        if let Some(stale) = parse_mountinfo(&mounts).into_iter().find(|m| m.mount_point == mount_point) {
+            let cleanup = Cleanup::Lvm { volume: stale.source, mount_point: mount_point.clone() };
+            warn!("Removing stale filesystem snapshot {:?}", cleanup);
+            if let Err(e) = cleanup.run() {
+                warn!("Failed to remove stale snapshot: {}", e);
+            }
+        }
+    }
+
+    let snapshot = format!("{}-{}{}", lv, SNAPSHOT_PREFIX, std::process::id());
+    run("lvcreate", &["--snapshot", "--name", &snapshot, "--size", size, &format!("{}/{}", vg, lv)])?;
+    let volume = format!("/dev/{}/{}", vg, snapshot);
+    // The snapshot of a mounted filesystem needs journal recovery (ext4) or has the same UUID (XFS)
+    let options = match mount.fstype.as_str() {
+        "ext3" | "ext4" => "ro,noload",
+        "xfs" => "ro,nouuid,norecovery",
+        _ => "ro",
+    };
+    if let Err(e) = run("mount", &["-t", &mount.fstype, "-o", options, &volume, &mount_point.to_string_lossy()]) {
+        let _ = run("lvremove", &["-f", &volume]);
+        return Err(e);
+    }
+    Ok(FsSnapshot::new(mount_point.clone(), relative, Cleanup::Lvm { volume, mount_point }))
+}
+
+/// Snapshots the filesystem holding `scan_path` as `args` asks. Returns None
+/// to scan live: with `none`, or with `auto` when the filesystem supports no
+/// snapshots.
+pub fn create(args: &FsSnapshotArgs, scan_path: &Path, state_dir: &Path) -> Result<Option<FsSnapshot>> {
+    if args.fs_snapshot == SnapshotKind::None {
+        return Ok(None);
+    }
+    let scan_path = scan_path.canonicalize()?;
+    let mounts = parse_mountinfo(&std::fs::read_to_string("/proc/self/mountinfo")?);
+    let mount = mount_of(&mounts, &scan_path)
+        .ok_or_else(|| IntegrityError::Validation(format!("no filesystem mounted at {:?}", scan_path)))?;
+    let relative = scan_path.strip_prefix(&mount.mount_point).unwrap_or(Path::new(""));
+
+    let kind = match args.fs_snapshot {
+        SnapshotKind::Auto => match mount.fstype.as_str() {
+            "btrfs" => SnapshotKind::Btrfs,
+            "zfs" => SnapshotKind::Zfs,
+            _ if mount.source.starts_with("/dev/mapper/") => SnapshotKind::Lvm,
+            _ => {
+                warn!("{} filesystem at {:?} has no snapshot support, scanning it live", mount.fstype, mount.mount_point);
+                return Ok(None);
+            }
+        },
+        kind => kind,
+    };
+    let expected = match kind {
+        SnapshotKind::Btrfs => Some("btrfs"),
+        SnapshotKind::Zfs => Some("zfs"),
+        _ => None,
+    };
+    if let Some(expected) = expected.filter(|expected| *expected != mount.fstype) {
+        return Err(IntegrityError::Validation(format!(
+            "--fs-snapshot {} needs a {} filesystem, {:?} is {}",
+            expected, expected, mount.mount_point, mount.fstype
+        )));
+    }
+
+    remove_stale(kind, mount);
+    let mut snapshot = match kind {
+        SnapshotKind::Btrfs => btrfs(mount, relative)?,
+        SnapshotKind::Zfs => zfs(mount, relative)?,
+        _ => lvm(mount, relative, &args.fs_snapshot_size, state_dir)?,
+    };
+    snapshot.live_mounts = mounts_below(&mounts, &scan_path).into_iter().map(|m| m.mount_point.clone()).collect();
+    info!(
+        "Scanning {:?} from a {:?} snapshot at {:?}; {} filesystems below it live",
+        scan_path,
+        kind,
+        snapshot.root,
+        snapshot.live_mounts.len()
+    );
+    Ok(Some(snapshot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mounts() {
+        let mountinfo = "\
+22 1 253:0 / / rw,relatime shared:1 - ext4 /dev/mapper/vg0-root rw
+23 22 0:21 / /proc rw,nosuid shared:12 - proc proc rw
+24 22 8:1 / /boot rw,relatime shared:2 - ext4 /dev/sda1 rw
+25 22 0:45 / /srv/my\\040data rw,relatime shared:3 - btrfs /dev/sdb rw,subvol=/data
+26 22 0:25 / /run rw,nosuid shared:5 - tmpfs tmpfs rw";
+        let mounts = parse_mountinfo(mountinfo);
+        assert_eq!(mounts.len(), 5);
+        assert_eq!(mounts[3].mount_point, PathBuf::from("/srv/my data"));
+
+        assert_eq!(mount_of(&mounts, Path::new("/etc/ssh")).unwrap().source, "/dev/mapper/vg0-root");
+        assert_eq!(mount_of(&mounts, Path::new("/srv/my data/x")).unwrap().fstype, "btrfs");
+        let below: Vec<_> = mounts_below(&mounts, Path::new("/")).iter().map(|m| m.mount_point.clone()).collect();
+        assert_eq!(below, vec![PathBuf::from("/boot"), PathBuf::from("/srv/my data")]);
+    }
+}
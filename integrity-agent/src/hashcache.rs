@@ -0,0 +1,233 @@
+//! Cross-run cache of file digests.
+//!
+//! Every close of a large file would otherwise re-read all of it. A digest
+//! is reused while the file's device, inode, size, mtime and ctime are
+//! unchanged. ctime cannot be set from userspace, so rewriting a file and
+//! restoring its mtime (`touch -r`) still invalidates the entry; writing to
+//! the block device directly is not seen, as by any other event-based check.
+//!
+//! The cache lives in the state directory as a JSON Lines journal of
+//! digests, later lines superseding earlier ones for the same file, and is
+//! compacted when opened. Only regular files of at least
+//! `--hash-cache-min-size` bytes are cached, each digest algorithm separately.
+
+use crate::compute_digest;
+use integrity_scanner::FileHasher;
+use integrity_common::{HashAlgorithm, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, Metadata, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+const CACHE_FILE: &str = "hashes.jsonl";
+
+/// Files changed this recently are not cached: coarse filesystem timestamps
+/// could hide a write right after hashing.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct HashCacheArgs {
+    /// Hash every file on every check instead of reusing digests of unchanged files
+    #[arg(long)]
+    pub no_hash_cache: bool,
+
+    /// Smallest file, in bytes, whose digest is cached
+    #[arg(long, default_value = "65536")]
+    pub hash_cache_min_size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FileId {
+    dev: u64,
+    ino: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    size: u64,
+    mtime_ns: i64,
+    ctime_ns: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheRecord {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime_ns: i64,
+    ctime_ns: i64,
+    #[serde(default, skip_serializing_if = "HashAlgorithm::is_default")]
+    algorithm: HashAlgorithm,
+    /// Journals written before other algorithms were supported call it `sha512`
+    #[serde(alias = "sha512")]
+    digest: String,
+}
+
+#[cfg(unix)]
+fn identify(metadata: &Metadata) -> Option<(FileId, Stamp)> {
+    use std::os::unix::fs::MetadataExt;
+    let id = FileId { dev: metadata.dev(), ino: metadata.ino() };
+    let stamp = Stamp {
+        size: metadata.size(),
+        mtime_ns: metadata.mtime() * 1_000_000_000 + metadata.mtime_nsec(),
+        ctime_ns: metadata.ctime() * 1_000_000_000 + metadata.ctime_nsec(),
+    };
+    Some((id, stamp))
+}
+
+/// Without a stable file id and ctime there is nothing safe to key on.
+#[cfg(not(unix))]
+fn identify(_metadata: &Metadata) -> Option<(FileId, Stamp)> {
+    None
+}
+
+/// Digest of each file in each algorithm, as of its stamp.
+type Entries = HashMap<(FileId, HashAlgorithm), (Stamp, String)>;
+
+pub struct HashCache {
+    min_size: u64,
+    entries: Mutex<Entries>,
+    journal: Mutex<File>,
+}
+
+/// The entries of the journal at `path`, and its number of lines.
+fn load(path: &Path) -> Result<(Entries, usize)> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((HashMap::new(), 0)),
+        Err(e) => return Err(e.into()),
+    };
+    let mut entries = HashMap::new();
+    let mut lines = 0;
+    for line in BufReader::new(file).lines() {
+        lines += 1;
+        // A torn final line from a crash mid-write is skipped, not fatal
+        let Ok(record) = serde_json::from_str::<CacheRecord>(&line?) else {
+            continue;
+        };
+        let stamp = Stamp { size: record.size, mtime_ns: record.mtime_ns, ctime_ns: record.ctime_ns };
+        entries.insert((FileId { dev: record.dev, ino: record.ino }, record.algorithm), (stamp, record.digest));
+    }
+    Ok((entries, lines))
+}
+
+fn record_line((id, algorithm): (FileId, HashAlgorithm), stamp: Stamp, digest: &str) -> Result<Vec<u8>> {
+    let record = CacheRecord {
+        dev: id.dev,
+        ino: id.ino,
+        size: stamp.size,
+        mtime_ns: stamp.mtime_ns,
+        ctime_ns: stamp.ctime_ns,
+        algorithm,
+        digest: digest.to_string(),
+    };
+    let mut line = serde_json::to_vec(&record)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+impl HashCache {
+    pub fn open(dir: &Path, args: &HashCacheArgs) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(CACHE_FILE);
+        let (entries, lines) = load(&path)?;
+
+        // Superseded lines pile up as files change; rewrite once they dominate
+        if lines > 2 * entries.len() + 1000 {
+            let temporary = dir.join(format!("{}.tmp", CACHE_FILE));
+            let mut file = File::create(&temporary)?;
+            for (key, (stamp, digest)) in &entries {
+                file.write_all(&record_line(*key, *stamp, digest)?)?;
+            }
+            file.sync_data()?;
+            std::fs::rename(&temporary, &path)?;
+            info!("Compacted hash cache from {} to {} entries", lines, entries.len());
+        }
+
+        let journal = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            min_size: args.hash_cache_min_size,
+            entries: Mutex::new(entries),
+            journal: Mutex::new(journal),
+        })
+    }
+
+    /// Digest in `algorithm` of the file at `path`, whose (followed)
+    /// `metadata` the caller already has, from the cache if the file has not
+    /// changed since it was last hashed.
+    pub fn digest(&self, path: &Path, metadata: &Metadata, algorithm: HashAlgorithm) -> Result<String> {
+        let identified = identify(metadata).filter(|_| metadata.is_file() && metadata.len() >= self.min_size);
+        let Some((id, stamp)) = identified else {
+            return compute_digest(path, algorithm);
+        };
+        if let Some((cached, digest)) = self.entries.lock().unwrap().get(&(id, algorithm)) {
+            if *cached == stamp {
+                return Ok(digest.clone());
+            }
+        }
+
+        let digest = compute_digest(path, algorithm)?;
+        // Not cached if the file changed while it was read, or may change unseen
+        let unchanged = std::fs::metadata(path).ok().and_then(|m| identify(&m)) == Some((id, stamp));
+        let settled = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .is_ok_and(|now| now.as_nanos() as i64 - stamp.ctime_ns >= SETTLE_TIME.as_nanos() as i64);
+        if unchanged && settled {
+            self.entries.lock().unwrap().insert((id, algorithm), (stamp, digest.clone()));
+            let written = record_line((id, algorithm), stamp, &digest).and_then(|line| Ok(self.journal.lock().unwrap().write_all(&line)?));
+            if let Err(e) = written {
+                warn!("Failed to persist hash cache entry: {}", e);
+            }
+        }
+        Ok(digest)
+    }
+}
+
+impl FileHasher for HashCache {
+    fn digest(&self, path: &Path, metadata: &Metadata, algorithm: HashAlgorithm) -> Result<String> {
+        HashCache::digest(self, path, metadata, algorithm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuses_digest_until_file_changes() {
+        let dir = std::env::temp_dir().join(format!("acropole-hashcache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("large");
+        std::fs::write(&file, b"original").unwrap();
+        let args = HashCacheArgs { no_hash_cache: false, hash_cache_min_size: 0 };
+
+        // Just written: not settled, so not cached
+        let cache = HashCache::open(&dir, &args).unwrap();
+        let metadata = std::fs::metadata(&file).unwrap();
+        let original = cache.digest(&file, &metadata, HashAlgorithm::Sha512).unwrap();
+        assert!(cache.entries.lock().unwrap().is_empty());
+
+        // Pretend it settled, and survives a restart
+        let (id, stamp) = identify(&metadata).unwrap();
+        let key = (id, HashAlgorithm::Sha512);
+        cache.entries.lock().unwrap().insert(key, (stamp, "cached".to_string()));
+        cache.journal.lock().unwrap().write_all(&record_line(key, stamp, "cached").unwrap()).unwrap();
+        drop(cache);
+        let cache = HashCache::open(&dir, &args).unwrap();
+        assert_eq!(cache.digest(&file, &metadata, HashAlgorithm::Sha512).unwrap(), "cached");
+        // Other algorithms are cached separately
+        assert_ne!(cache.digest(&file, &metadata, HashAlgorithm::Blake3).unwrap(), "cached");
+
+        // Any change to the stamp is a miss
+        std::fs::write(&file, b"modified content").unwrap();
+        let modified = cache.digest(&file, &std::fs::metadata(&file).unwrap(), HashAlgorithm::Sha512).unwrap();
+        assert_ne!(modified, "cached");
+        assert_ne!(modified, original);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
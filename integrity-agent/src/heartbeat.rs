@@ -0,0 +1,112 @@
+//! Registration and heartbeats with the metadata service.
+//!
+//! Once a run has resolved its image, the agent registers (host, image_id,
+//! agent version and mode) with the service, and then sends a heartbeat every
+//! `--heartbeat-interval` for as long as the process lives, so daemon mode
+//! keeps checking in between passes. Heartbeats carry how the last run ended
+//! (outcome and anomaly count, see `summary`), and one goes out as soon as a
+//! run finishes, so a scan-mode run reports its result before exiting. An
+//! agent the service does not know registers again. The service lists the
+//! agents and flags those that stopped checking in; failures here are logged
+//! and never stop a run.
+
+use crate::summary::Outcome;
+use integrity_client::MetadataClient;
+use integrity_common::{AgentHeartbeat, AgentRegistration, IntegrityError, ScanStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct HeartbeatArgs {
+    /// Time between heartbeats to the metadata service, e.g. 1m (0s: do not register with it)
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "1m")]
+    pub heartbeat_interval: Duration,
+}
+
+/// Where heartbeats go, as of the latest run.
+#[derive(Clone)]
+struct Registration {
+    client: MetadataClient,
+    registration: AgentRegistration,
+}
+
+static REGISTRATION: Mutex<Option<Registration>> = Mutex::new(None);
+static LAST_SCAN: Mutex<Option<ScanStatus>> = Mutex::new(None);
+static SENDING: AtomicBool = AtomicBool::new(false);
+
+/// Registers the agent of `host` with the service and keeps sending heartbeats.
+pub async fn register(args: &HeartbeatArgs, client: &MetadataClient, host: &str, image_id: &str, mode: &str) {
+    if args.heartbeat_interval.is_zero() {
+        return;
+    }
+    let registration = Registration {
+        client: client.clone(),
+        registration: AgentRegistration {
+            host: host.to_string(),
+            image_id: image_id.to_string(),
+            agent_version: env!("CARGO_PKG_VERSION").to_string(),
+            mode: mode.to_string(),
+        },
+    };
+    send_registration(&registration).await;
+    *REGISTRATION.lock().unwrap() = Some(registration);
+
+    if !SENDING.swap(true, Ordering::Relaxed) {
+        let interval = args.heartbeat_interval;
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                send().await;
+            }
+        });
+    }
+}
+
+/// Records how a run ended and reports it right away.
+pub async fn run_finished(outcome: Outcome, anomalies: usize) {
+    *LAST_SCAN.lock().unwrap() = Some(ScanStatus {
+        finished_at: chrono::Utc::now().timestamp(),
+        outcome: outcome.name().to_string(),
+        anomalies,
+    });
+    send().await;
+}
+
+async fn send_registration(registration: &Registration) {
+    match registration.client.register_agent(&registration.registration).await {
+        Ok(_) => info!("Registered with the metadata service as {}", registration.registration.host),
+        Err(e) => warn!("Cannot register with the metadata service: {}", e),
+    }
+}
+
+async fn send() {
+    let Some(registration) = REGISTRATION.lock().unwrap().clone() else {
+        return;
+    };
+    let heartbeat = AgentHeartbeat {
+        image_id: registration.registration.image_id.clone(),
+        last_scan: LAST_SCAN.lock().unwrap().clone(),
+    };
+    match registration.client.agent_heartbeat(&registration.registration.host, &heartbeat).await {
+        Ok(()) => debug!("Heartbeat sent"),
+        // The service lost the registration, e.g. its database was restored
+        Err(IntegrityError::BaselineNotFound(_)) => send_registration(&registration).await,
+        Err(e) => warn!("Cannot send a heartbeat to the metadata service: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_finished_before_registering() {
+        // Nothing to send to yet; the scan goes out with the first heartbeat
+        run_finished(Outcome::AnomaliesFound, 2).await;
+        let scan = LAST_SCAN.lock().unwrap().clone().unwrap();
+        assert_eq!((scan.outcome.as_str(), scan.anomalies), ("anomalies_found", 2));
+        assert!(REGISTRATION.lock().unwrap().is_none());
+    }
+}
@@ -0,0 +1,256 @@
+use crate::monitor::{EventType, FileEvent, Monitor};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use walkdir::WalkDir;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Changes to a watched directory's entries, and to a watch path that is a file.
+const WATCH_MASK: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_ATTRIB
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_DELETE_SELF
+    | libc::IN_MOVE_SELF
+    | libc::IN_DONT_FOLLOW
+    | libc::IN_EXCL_UNLINK;
+
+/// An inotify(7) file system monitor for Linux, used where fanotify is not
+/// permitted (no `CAP_SYS_ADMIN`, e.g. in unprivileged containers).
+///
+/// inotify watches single directories, so every directory under the watch
+/// paths gets a watch, and directories created or moved in later are watched
+/// (and their files reported) as they appear. Writes are seen when the writer
+/// closes the file. Unlike fanotify it reports creations, deletions and
+/// renames, but not executions. Each directory counts against
+/// `fs.inotify.max_user_watches`; directories beyond it are left unwatched
+/// with a warning.
+pub struct InotifyMonitor {
+    watch_paths: Vec<PathBuf>,
+    channel_capacity: usize,
+    stop: Arc<AtomicBool>,
+}
+
+impl InotifyMonitor {
+    pub fn new(watch_paths: Vec<PathBuf>, channel_capacity: usize) -> Self {
+        Self {
+            watch_paths,
+            channel_capacity,
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+#[async_trait]
+impl Monitor for InotifyMonitor {
+    async fn start(&mut self) -> Result<mpsc::Receiver<FileEvent>, Box<dyn std::error::Error + Send + Sync>> {
+        let (tx, rx) = mpsc::channel(self.channel_capacity);
+        self.stop.store(false, Ordering::SeqCst);
+
+        let mut watches = Watches::new()?;
+        for path in &self.watch_paths {
+            // Report the paths files have in the baseline, e.g. /usr/bin/ls for /bin/ls on merged-/usr systems
+            watches.add_tree(&path.canonicalize().unwrap_or_else(|_| path.clone()), &mut Vec::new());
+        }
+        tracing::info!("Watching {} directories with inotify", watches.paths.len());
+
+        let stop = self.stop.clone();
+        std::thread::spawn(move || watches.run(&tx, &stop));
+        Ok(rx)
+    }
+
+    async fn stop(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        tracing::info!("Stopping inotify monitor");
+        self.stop.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+struct Watches {
+    fd: OwnedFd,
+    /// Watched path of each watch descriptor
+    paths: HashMap<i32, PathBuf>,
+    /// Set once the watch limit was hit, so it is only warned about once
+    exhausted: bool,
+}
+
+impl Watches {
+    fn new() -> std::io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self { fd: unsafe { OwnedFd::from_raw_fd(fd) }, paths: HashMap::new(), exhausted: false })
+    }
+
+    fn add(&mut self, path: &Path) -> bool {
+        let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+            return false;
+        };
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), c_path.as_ptr(), WATCH_MASK) };
+        if wd < 0 {
+            let e = std::io::Error::last_os_error();
+            if e.raw_os_error() == Some(libc::ENOSPC) {
+                if !self.exhausted {
+                    tracing::warn!("inotify watch limit reached (fs.inotify.max_user_watches), {:?} and further directories are not watched", path);
+                    self.exhausted = true;
+                }
+            } else {
+                tracing::debug!("Cannot watch {:?}: {}", path, e);
+            }
+            return false;
+        }
+        self.paths.insert(wd, path.to_path_buf());
+        true
+    }
+
+    /// Watches `root` and the directories below it, collecting the files
+    /// found in `files`.
+    fn add_tree(&mut self, root: &Path, files: &mut Vec<PathBuf>) {
+        for entry in WalkDir::new(root).follow_links(false).into_iter().filter_map(|e| e.ok()) {
+            if entry.file_type().is_dir() || entry.depth() == 0 {
+                self.add(entry.path());
+            } else {
+                files.push(entry.into_path());
+            }
+        }
+    }
+
+    /// Drops the watches on `dir` and below, which moved away.
+    fn remove_tree(&mut self, dir: &Path) {
+        let fd = self.fd.as_raw_fd();
+        self.paths.retain(|wd, path| {
+            let moved = path.starts_with(dir);
+            if moved {
+                unsafe { libc::inotify_rm_watch(fd, *wd) };
+            }
+            !moved
+        });
+    }
+
+    /// The events for one inotify event on `path`.
+    fn events(&mut self, wd: i32, mask: u32, path: PathBuf) -> Vec<FileEvent> {
+        let event = |path, event_type| vec![FileEvent { path, event_type }];
+        if mask & libc::IN_IGNORED != 0 {
+            self.paths.remove(&wd);
+            return Vec::new();
+        }
+        // Directories themselves are not verified, only their watches maintained
+        if mask & libc::IN_ISDIR != 0 {
+            if mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+                let mut files = Vec::new();
+                self.add_tree(&path, &mut files);
+                // Files created before the watch was in place are reported too
+                return files.into_iter().map(|path| FileEvent { path, event_type: EventType::Created }).collect();
+            }
+            if mask & libc::IN_MOVED_FROM != 0 {
+                self.remove_tree(&path);
+            }
+            return Vec::new();
+        }
+        if mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+            event(path, EventType::Created)
+        } else if mask & (libc::IN_DELETE | libc::IN_MOVED_FROM | libc::IN_DELETE_SELF | libc::IN_MOVE_SELF) != 0 {
+            event(path, EventType::Deleted)
+        } else if mask & (libc::IN_CLOSE_WRITE | libc::IN_ATTRIB) != 0 {
+            event(path, EventType::Modified)
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn run(mut self, tx: &mpsc::Sender<FileEvent>, stop: &AtomicBool) {
+        let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+        let header = std::mem::size_of::<libc::inotify_event>();
+
+        while !stop.load(Ordering::SeqCst) && !tx.is_closed() {
+            let mut pollfd = libc::pollfd { fd: self.fd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+            if unsafe { libc::poll(&mut pollfd, 1, POLL_INTERVAL.as_millis() as i32) } <= 0 {
+                continue;
+            }
+            let read = unsafe { libc::read(self.fd.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len()) };
+            if read < 0 {
+                let e = std::io::Error::last_os_error();
+                if !matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted) {
+                    tracing::error!("Reading inotify events failed: {}", e);
+                    return;
+                }
+                continue;
+            }
+
+            let mut events = Vec::new();
+            let mut offset = 0;
+            while offset + header <= read as usize {
+                let event: libc::inotify_event = unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr().cast()) };
+                let name = &buffer[offset + header..(offset + header + event.len as usize).min(read as usize)];
+                offset += header + event.len as usize;
+
+                if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                    events.push(FileEvent { path: PathBuf::new(), event_type: EventType::Overflow });
+                    continue;
+                }
+                let Some(dir) = self.paths.get(&event.wd) else {
+                    continue;
+                };
+                // Events without a name are about the watched path itself
+                let name = name.split(|&b| b == 0).next().unwrap_or_default();
+                let path = if name.is_empty() { dir.clone() } else { dir.join(OsStr::from_bytes(name)) };
+                events.extend(self.events(event.wd, event.mask, path));
+            }
+            for event in events {
+                if tx.blocking_send(event).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Events arriving until none came for half a second.
+    async fn drain(events: &mut mpsc::Receiver<FileEvent>) -> Vec<(PathBuf, EventType)> {
+        let mut received = Vec::new();
+        while let Ok(Some(event)) = tokio::time::timeout(Duration::from_millis(500), events.recv()).await {
+            received.push((event.path, event.event_type));
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn test_new_directories_are_watched() {
+        let root = std::env::temp_dir().join(format!("acropole-inotify-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        let file = root.join("sub/file");
+        let mut monitor = InotifyMonitor::new(vec![root.clone()], 16);
+        let mut events = monitor.start().await.unwrap();
+
+        // Whether or not the file was written before the new directory's watch was in place
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(&file, "a").unwrap();
+        assert!(drain(&mut events).await.contains(&(file.clone(), EventType::Created)));
+
+        std::fs::write(&file, "b").unwrap();
+        assert!(drain(&mut events).await.contains(&(file.clone(), EventType::Modified)));
+
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(drain(&mut events).await, vec![(file, EventType::Deleted)]);
+
+        monitor.stop().await.unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
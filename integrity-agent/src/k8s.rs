@@ -0,0 +1,68 @@
+use integrity_common::{IntegrityError, Result};
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Context for running as a Kubernetes DaemonSet.
+/// The host filesystem is mounted at `host_root` via hostPath, and the node name
+/// is injected through the downward API (`spec.nodeName` as `NODE_NAME`).
+#[derive(Debug, Clone)]
+pub struct K8sContext {
+    pub node_name: String,
+    pub cluster_name: Option<String>,
+    pub namespace: Option<String>,
+    pub pod_name: Option<String>,
+    pub host_root: PathBuf,
+}
+
+impl K8sContext {
+    /// Detects the DaemonSet environment from the variables Kubernetes injects.
+    pub fn detect(host_root: &Path, cluster_name: Option<String>) -> Result<Self> {
+        if env::var_os("KUBERNETES_SERVICE_HOST").is_none() {
+            return Err(IntegrityError::Storage(
+                "--k8s set but KUBERNETES_SERVICE_HOST is not defined; not running in a pod".to_string(),
+            ));
+        }
+        let node_name = env::var("NODE_NAME").map_err(|_| {
+            IntegrityError::Storage(
+                "NODE_NAME is not set; expose spec.nodeName through the downward API".to_string(),
+            )
+        })?;
+        if !host_root.is_dir() {
+            return Err(IntegrityError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Host root {:?} is not mounted", host_root),
+            )));
+        }
+
+        Ok(Self {
+            node_name,
+            cluster_name: cluster_name.or_else(|| env::var("CLUSTER_NAME").ok()),
+            namespace: env::var("POD_NAMESPACE").ok(),
+            pod_name: env::var("POD_NAME").ok().or_else(|| env::var("HOSTNAME").ok()),
+            host_root: host_root.to_path_buf(),
+        })
+    }
+
+    /// Maps a host path (e.g. "/etc") to where it is visible in the container ("/host/etc").
+    pub fn to_container_path(&self, host_path: &Path) -> PathBuf {
+        self.host_root.join(host_path.strip_prefix("/").unwrap_or(host_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_mapping() {
+        let ctx = K8sContext {
+            node_name: "node-1".to_string(),
+            cluster_name: None,
+            namespace: None,
+            pod_name: None,
+            host_root: PathBuf::from("/host"),
+        };
+        assert_eq!(ctx.to_container_path(Path::new("/etc")), PathBuf::from("/host/etc"));
+        assert_eq!(ctx.to_container_path(Path::new("etc/ssh")), PathBuf::from("/host/etc/ssh"));
+    }
+}
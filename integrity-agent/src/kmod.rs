@@ -0,0 +1,155 @@
+//! Kernel module verification (Linux).
+//!
+//! The baseline already covers the module files under `lib/modules/<release>`,
+//! which makes it the expected set of loadable modules. `/proc/modules` cannot
+//! be watched, so it is polled: every newly loaded module must map to a
+//! baselined module file, and that file must still match the baseline.
+//! Changes to the module files themselves are caught by watching the module
+//! directory like any other watch path.
+
+use integrity_common::{AnomalyReport, BaselineIndex};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+const PROC_MODULES: &str = "/proc/modules";
+const MODULE_DIRS: &[&str] = &["lib/modules", "usr/lib/modules"];
+/// Compressed modules keep the `.ko` before the compression suffix
+const MODULE_SUFFIX: &str = ".ko";
+
+/// Module names treat `-` and `_` as the same character.
+fn normalize_name(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+/// Module name for a baselined file name, e.g. "nf_tables.ko.zst" -> "nf_tables".
+fn module_name(file_name: &str) -> Option<String> {
+    let end = file_name.find(MODULE_SUFFIX)?;
+    let rest = &file_name[end + MODULE_SUFFIX.len()..];
+    (rest.is_empty() || rest.starts_with('.')).then(|| normalize_name(&file_name[..end]))
+}
+
+/// Names of the loaded modules, from the first column of /proc/modules.
+fn loaded_modules(proc_modules: &str) -> HashSet<String> {
+    proc_modules
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(normalize_name)
+        .collect()
+}
+
+/// The running kernel's release, which names its module directory.
+pub fn kernel_release() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/osrelease")
+        .ok()
+        .map(|release| release.trim().to_string())
+}
+
+/// Module directories for the running kernel, relative to the filesystem root.
+pub fn module_dirs(release: &str) -> Vec<String> {
+    MODULE_DIRS.iter().map(|dir| format!("{}/{}", dir, release)).collect()
+}
+
+/// Baselined module files for one kernel release, by module name.
+fn baseline_modules(baseline: &BaselineIndex, release: &str) -> HashMap<String, String> {
+    let dirs: Vec<String> = module_dirs(release).into_iter().map(|dir| format!("{}/", dir)).collect();
+    baseline
+        .entries()
+        .map(|e| e.path.trim_start_matches('/'))
+        .filter(|path| dirs.iter().any(|dir| path.starts_with(dir.as_str())))
+        .filter_map(|path| {
+            let file_name = path.rsplit('/').next()?;
+            Some((module_name(file_name)?, path.to_string()))
+        })
+        .collect()
+}
+
+/// Polls /proc/modules and sends an anomaly for every loaded module that is
+/// not in the baseline or whose file no longer matches it. Modules loaded at
+/// startup are checked on the first poll.
+pub fn spawn(baseline: Arc<BaselineIndex>, root: PathBuf, interval: Duration, tx: mpsc::Sender<AnomalyReport>) {
+    tokio::spawn(async move {
+        let Some(release) = kernel_release() else {
+            warn!("Cannot determine the kernel release, kernel module checks disabled");
+            return;
+        };
+        let modules = baseline_modules(&baseline, &release);
+        if modules.is_empty() {
+            warn!("The baseline has no module files for kernel {}, every loaded module will be reported", release);
+        }
+
+        let mut checked = HashSet::new();
+        loop {
+            match tokio::fs::read_to_string(PROC_MODULES).await {
+                Ok(contents) => {
+                    let loaded = loaded_modules(&contents);
+                    for name in loaded.difference(&checked) {
+                        if let Some(anomaly) = check_module(name, &modules, &baseline, &root).await {
+                            if tx.send(anomaly).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    // Forget unloaded modules, so loading them again is checked again
+                    checked = loaded;
+                }
+                Err(e) => warn!("Failed to read {}: {}", PROC_MODULES, e),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+async fn check_module(
+    name: &str,
+    modules: &HashMap<String, String>,
+    baseline: &BaselineIndex,
+    root: &Path,
+) -> Option<AnomalyReport> {
+    let Some(path) = modules.get(name) else {
+        return Some(AnomalyReport::other("KERNEL_MODULE_UNKNOWN", name, "loaded module not in baseline"));
+    };
+    match crate::verify_file(&root.join(path), root, baseline, None).await {
+        Some(anomaly) => Some(AnomalyReport::other("KERNEL_MODULE_MISMATCH", path, format!("module {} loaded, {}", name, anomaly))),
+        None => {
+            debug!("Loaded kernel module {} matches the baseline", name);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::test_util::BaselineBuilder;
+
+    #[test]
+    fn test_module_names() {
+        assert_eq!(module_name("nf_tables.ko"), Some("nf_tables".to_string()));
+        assert_eq!(module_name("snd-hda-intel.ko.zst"), Some("snd_hda_intel".to_string()));
+        assert_eq!(module_name("modules.dep"), None);
+        assert_eq!(module_name("foo.kobj"), None);
+
+        let proc_modules = "nf_tables 286720 0 - Live 0x0000000000000000\nsnd_hda_intel 57344 3 - Live 0x0\n";
+        assert_eq!(
+            loaded_modules(proc_modules),
+            HashSet::from(["nf_tables".to_string(), "snd_hda_intel".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_baseline_modules_for_release() {
+        let baseline = BaselineBuilder::new("img")
+            .size(0)
+            .file("lib/modules/6.1.0/kernel/net/nf_tables.ko.xz", 0o644)
+            .file("lib/modules/5.10.0/kernel/net/old.ko", 0o644)
+            .file("lib/modules/6.1.0/modules.dep", 0o644)
+            .build();
+        let modules = baseline_modules(&BaselineIndex::new(&baseline), "6.1.0");
+        assert_eq!(modules.len(), 1);
+        assert_eq!(modules["nf_tables"], "lib/modules/6.1.0/kernel/net/nf_tables.ko.xz");
+    }
+}
@@ -0,0 +1,263 @@
+use crate::monitor::{EventType, FileEvent, Monitor};
+use async_trait::async_trait;
+use kqueue::{EventData, EventFilter, FilterFlag, Ident, Vnode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use walkdir::WalkDir;
+
+/// Descriptors left for sockets, log files and hashing.
+const RESERVED_FDS: usize = 256;
+/// Upper bound when RLIMIT_NOFILE is unlimited.
+const MAX_FD_BUDGET: usize = 65_536;
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A kqueue (EVFILT_VNODE) file system monitor for the BSDs.
+///
+/// kqueue needs an open descriptor per watched vnode, so watches are handed out
+/// from a fixed budget: every directory under the watch paths first (so created
+/// and deleted files are always seen), then files until the budget runs out.
+/// Files beyond the budget are only covered by their directory's events.
+pub struct KqueueMonitor {
+    watch_paths: Vec<PathBuf>,
+    channel_capacity: usize,
+    fd_budget: usize,
+    stop: Arc<AtomicBool>,
+}
+
+impl KqueueMonitor {
+    pub fn new(watch_paths: Vec<PathBuf>, channel_capacity: usize) -> Self {
+        Self {
+            watch_paths,
+            channel_capacity,
+            fd_budget: default_fd_budget(),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+/// Three quarters of the soft RLIMIT_NOFILE, minus a reserve for everything else.
+fn default_fd_budget() -> usize {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    let soft = if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } == 0 {
+        limit.rlim_cur as usize
+    } else {
+        1024
+    };
+    (soft.min(MAX_FD_BUDGET) / 4 * 3).saturating_sub(RESERVED_FDS).max(64)
+}
+
+#[async_trait]
+impl Monitor for KqueueMonitor {
+    async fn start(&mut self) -> Result<mpsc::Receiver<FileEvent>, Box<dyn std::error::Error + Send + Sync>> {
+        let (tx, rx) = mpsc::channel(self.channel_capacity);
+        self.stop.store(false, Ordering::SeqCst);
+
+        let mut watches = Watches::new(self.fd_budget)?;
+        watches.register(&self.watch_paths);
+        watches.watcher.watch()?;
+        tracing::info!(
+            "Watching {} directories and {} files with kqueue ({} descriptor budget)",
+            watches.dirs.len(),
+            watches.files.len(),
+            self.fd_budget
+        );
+
+        let stop = self.stop.clone();
+        std::thread::spawn(move || watches.run(&tx, &stop));
+        Ok(rx)
+    }
+
+    async fn stop(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        tracing::info!("Stopping kqueue monitor");
+        self.stop.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+fn dir_flags() -> FilterFlag {
+    FilterFlag::NOTE_WRITE | FilterFlag::NOTE_DELETE | FilterFlag::NOTE_RENAME
+}
+
+fn file_flags() -> FilterFlag {
+    FilterFlag::NOTE_WRITE
+        | FilterFlag::NOTE_EXTEND
+        | FilterFlag::NOTE_ATTRIB
+        | FilterFlag::NOTE_DELETE
+        | FilterFlag::NOTE_RENAME
+        | FilterFlag::NOTE_REVOKE
+}
+
+struct Watches {
+    watcher: Watcher,
+    budget: usize,
+    dirs: HashSet<PathBuf>,
+    files: HashSet<PathBuf>,
+    /// Files seen but not watched because the budget was exhausted
+    unwatched: HashSet<PathBuf>,
+}
+
+impl Watches {
+    fn new(budget: usize) -> std::io::Result<Self> {
+        Ok(Self {
+            watcher: Watcher::new()?,
+            budget,
+            dirs: HashSet::new(),
+            files: HashSet::new(),
+            unwatched: HashSet::new(),
+        })
+    }
+
+    fn in_use(&self) -> usize {
+        self.dirs.len() + self.files.len()
+    }
+
+    /// Registers directories before files so the budget always covers the tree.
+    fn register(&mut self, roots: &[PathBuf]) {
+        let mut files = Vec::new();
+        for root in roots {
+            for entry in WalkDir::new(root).follow_links(false).into_iter().filter_map(|e| e.ok()) {
+                if entry.file_type().is_dir() {
+                    self.add(entry.path(), true);
+                } else if entry.file_type().is_file() {
+                    files.push(entry.into_path());
+                }
+            }
+        }
+        for file in files {
+            self.add(&file, false);
+        }
+        if !self.unwatched.is_empty() {
+            tracing::warn!(
+                "kqueue descriptor budget of {} exhausted, {} files are only covered by directory events",
+                self.budget,
+                self.unwatched.len()
+            );
+        }
+    }
+
+    fn add(&mut self, path: &Path, is_dir: bool) -> bool {
+        if self.in_use() >= self.budget {
+            if !is_dir {
+                self.unwatched.insert(path.to_path_buf());
+            }
+            return false;
+        }
+        let flags = if is_dir { dir_flags() } else { file_flags() };
+        match self.watcher.add_filename(path, EventFilter::EVFILT_VNODE, flags) {
+            Ok(()) => {
+                if is_dir {
+                    self.dirs.insert(path.to_path_buf());
+                } else {
+                    self.files.insert(path.to_path_buf());
+                }
+                true
+            }
+            Err(e) => {
+                tracing::debug!("Cannot watch {:?}: {}", path, e);
+                false
+            }
+        }
+    }
+
+    /// Drops a watch, returning its descriptor to the budget.
+    fn remove(&mut self, path: &Path) {
+        if self.dirs.remove(path) || self.files.remove(path) {
+            let _ = self.watcher.remove_filename(path, EventFilter::EVFILT_VNODE);
+        }
+        self.unwatched.remove(path);
+    }
+
+    fn run(mut self, tx: &mpsc::Sender<FileEvent>, stop: &AtomicBool) {
+        while !stop.load(Ordering::SeqCst) && !tx.is_closed() {
+            let Some(event) = self.watcher.poll(Some(POLL_INTERVAL)) else {
+                continue;
+            };
+            let Ident::Filename(_, name) = event.ident else {
+                continue;
+            };
+            let path = PathBuf::from(name);
+            let vnode = match event.data {
+                EventData::Vnode(vnode) => vnode,
+                EventData::Error(e) => {
+                    tracing::warn!("kqueue error for {:?}: {}", path, e);
+                    continue;
+                }
+                _ => continue,
+            };
+
+            let events = if self.dirs.contains(&path) {
+                self.directory_event(&path, vnode)
+            } else {
+                self.file_event(&path, vnode)
+            };
+            for event in events {
+                if tx.blocking_send(event).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn file_event(&mut self, path: &Path, vnode: Vnode) -> Vec<FileEvent> {
+        let event_type = match vnode {
+            Vnode::Delete | Vnode::Rename | Vnode::Revoke => {
+                // The vnode is gone; if the path was replaced (e.g. rename over it), watch the new file
+                self.remove(path);
+                if path.is_file() && self.add(path, false) {
+                    let _ = self.watcher.watch();
+                }
+                EventType::Deleted
+            }
+            _ => EventType::Modified,
+        };
+        vec![FileEvent { path: path.to_path_buf(), event_type }]
+    }
+
+    /// A directory changed: report and watch files that were not there before,
+    /// and report unwatched files that disappeared from it.
+    fn directory_event(&mut self, path: &Path, vnode: Vnode) -> Vec<FileEvent> {
+        if matches!(vnode, Vnode::Delete | Vnode::Rename | Vnode::Revoke) {
+            self.remove(path);
+            return Vec::new();
+        }
+
+        let Ok(entries) = std::fs::read_dir(path) else {
+            return Vec::new();
+        };
+        let mut events = Vec::new();
+        let mut added = false;
+        for entry in entries.filter_map(|e| e.ok()) {
+            let child = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() && !self.dirs.contains(&child) {
+                added |= self.add(&child, true);
+            } else if file_type.is_file() && !self.files.contains(&child) && !self.unwatched.contains(&child) {
+                added |= self.add(&child, false);
+                events.push(FileEvent { path: child, event_type: EventType::Created });
+            }
+        }
+        let gone: Vec<PathBuf> = self
+            .unwatched
+            .iter()
+            .filter(|f| f.parent() == Some(path) && !f.exists())
+            .cloned()
+            .collect();
+        for file in gone {
+            self.unwatched.remove(&file);
+            events.push(FileEvent { path: file, event_type: EventType::Deleted });
+        }
+
+        if added {
+            if let Err(e) = self.watcher.watch() {
+                tracing::warn!("Failed to register new kqueue watches: {}", e);
+            }
+        }
+        events
+    }
+}
@@ -0,0 +1,71 @@
+//! Unexpected listening sockets (Linux).
+//!
+//! Compares the sockets listening on the host against those recorded in the
+//! baseline, and the binary owning each expected one against the file
+//! baseline: once in scan mode, and by polling in monitor mode.
+
+use crate::compute_digest;
+use integrity_common::{read_listeners, AnomalyReport, BaselineIndex, Listener, OpenListener};
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const PROC: &str = "/proc";
+
+fn check_listener(open: &OpenListener, expected: &[Listener], baseline: &BaselineIndex) -> Option<AnomalyReport> {
+    let listener = &open.listener;
+    let owner = open.pid.map_or_else(|| "no owning process".to_string(), |pid| format!("pid {}", pid));
+    let what = format!("{} port {}, {}", listener.protocol, listener.port, owner);
+
+    if !expected.contains(listener) {
+        let path = if listener.exe.is_empty() { format!("proc/net/{}", listener.protocol) } else { listener.exe.clone() };
+        return Some(AnomalyReport::other("LISTENER_UNEXPECTED", path, format!("{}: not in baseline", what)));
+    }
+    let pid = open.pid?;
+    let Some(entry) = baseline.get(&listener.exe) else {
+        return Some(AnomalyReport::other("LISTENER_BINARY_UNKNOWN", &listener.exe, format!("{}: binary not in baseline", what)));
+    };
+    let (algorithm, expected) = entry.content_digest(baseline.hash_algorithm())?;
+    // The running binary, even if the file on disk was replaced since
+    let digest = compute_digest(&Path::new(PROC).join(pid.to_string()).join("exe"), algorithm).ok()?;
+    (digest != expected).then(|| {
+        AnomalyReport::other("LISTENER_BINARY_MODIFIED", &listener.exe, format!("{}: hash mismatch: {} != {}", what, expected, digest))
+    })
+}
+
+/// Anomalies for the listeners not in `seen`, which is then updated to the
+/// current listeners, so each is checked once for as long as it listens.
+pub fn check(expected: &[Listener], baseline: &BaselineIndex, seen: &mut HashSet<(Listener, Option<u32>)>) -> Vec<AnomalyReport> {
+    let current = match read_listeners(Path::new(PROC)) {
+        Ok(current) => current,
+        Err(e) => return vec![AnomalyReport::other("LISTENER_CHECK_FAILED", "proc/net", format!("cannot list sockets: {}", e))],
+    };
+
+    let mut anomalies = Vec::new();
+    let mut now = HashSet::new();
+    for open in &current {
+        let key = (open.listener.clone(), open.pid);
+        if !seen.contains(&key) {
+            anomalies.extend(check_listener(open, expected, baseline));
+        }
+        now.insert(key);
+    }
+    *seen = now;
+    anomalies
+}
+
+/// Polls the listening sockets and sends an anomaly for every new unexpected one.
+pub fn spawn(expected: Vec<Listener>, baseline: std::sync::Arc<BaselineIndex>, interval: Duration, tx: mpsc::Sender<AnomalyReport>) {
+    tokio::spawn(async move {
+        let mut seen = HashSet::new();
+        loop {
+            for anomaly in check(&expected, &baseline, &mut seen) {
+                if tx.send(anomaly).await.is_err() {
+                    return;
+                }
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
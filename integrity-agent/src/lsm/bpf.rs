@@ -0,0 +1,336 @@
+//! The bpf(2) commands the enforcer needs, and a small assembler for the
+//! programs it loads. No BPF toolchain or object file is involved: the
+//! programs are a few dozen instructions, built at startup with the struct
+//! offsets of the running kernel.
+
+use std::collections::HashMap;
+use std::io;
+use std::os::fd::{FromRawFd, OwnedFd};
+
+const BPF_MAP_CREATE: libc::c_long = 0;
+const BPF_MAP_LOOKUP_ELEM: libc::c_long = 1;
+const BPF_MAP_UPDATE_ELEM: libc::c_long = 2;
+const BPF_MAP_DELETE_ELEM: libc::c_long = 3;
+const BPF_MAP_GET_NEXT_KEY: libc::c_long = 4;
+const BPF_PROG_LOAD: libc::c_long = 5;
+const BPF_RAW_TRACEPOINT_OPEN: libc::c_long = 17;
+
+const BPF_MAP_TYPE_HASH: u32 = 1;
+const BPF_PROG_TYPE_LSM: u32 = 29;
+const BPF_LSM_MAC: u32 = 27;
+
+/// Kernel's verifier log for a rejected program; its tail says why.
+const LOG_SIZE: usize = 64 * 1024;
+
+pub const R0: u8 = 0;
+pub const R1: u8 = 1;
+pub const R2: u8 = 2;
+pub const R3: u8 = 3;
+pub const R4: u8 = 4;
+pub const R6: u8 = 6;
+pub const R7: u8 = 7;
+pub const R9: u8 = 9;
+pub const FP: u8 = 10;
+
+pub const HELPER_MAP_LOOKUP_ELEM: i32 = 1;
+pub const HELPER_MAP_UPDATE_ELEM: i32 = 2;
+pub const HELPER_MAP_DELETE_ELEM: i32 = 3;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Insn {
+    code: u8,
+    /// Destination register in the low nibble, source in the high one
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Size {
+    W,
+    DW,
+}
+
+impl Size {
+    fn bits(self) -> u8 {
+        match self {
+            Size::W => 0x00,
+            Size::DW => 0x18,
+        }
+    }
+}
+
+/// Instructions with forward jumps to named labels.
+#[derive(Default)]
+pub struct Asm {
+    insns: Vec<Insn>,
+    labels: HashMap<String, usize>,
+    jumps: Vec<(usize, String)>,
+}
+
+impl Asm {
+    fn push(&mut self, code: u8, dst: u8, src: u8, off: i16, imm: i32) -> &mut Self {
+        self.insns.push(Insn { code, regs: dst | (src << 4), off, imm });
+        self
+    }
+
+    /// dst = *(size *)(src + off)
+    pub fn load(&mut self, size: Size, dst: u8, src: u8, off: i16) -> &mut Self {
+        self.push(0x61 | size.bits(), dst, src, off, 0)
+    }
+
+    /// *(size *)(dst + off) = src
+    pub fn store(&mut self, size: Size, dst: u8, off: i16, src: u8) -> &mut Self {
+        self.push(0x63 | size.bits(), dst, src, off, 0)
+    }
+
+    /// *(size *)(dst + off) = imm
+    pub fn store_imm(&mut self, size: Size, dst: u8, off: i16, imm: i32) -> &mut Self {
+        self.push(0x62 | size.bits(), dst, 0, off, imm)
+    }
+
+    /// Atomic *(u64 *)(dst + off) += src
+    pub fn atomic_add(&mut self, dst: u8, off: i16, src: u8) -> &mut Self {
+        self.push(0xdb, dst, src, off, 0)
+    }
+
+    pub fn mov(&mut self, dst: u8, src: u8) -> &mut Self {
+        self.push(0xbf, dst, src, 0, 0)
+    }
+
+    pub fn mov_imm(&mut self, dst: u8, imm: i32) -> &mut Self {
+        self.push(0xb7, dst, 0, 0, imm)
+    }
+
+    pub fn add_imm(&mut self, dst: u8, imm: i32) -> &mut Self {
+        self.push(0x07, dst, 0, 0, imm)
+    }
+
+    pub fn and_imm(&mut self, dst: u8, imm: i32) -> &mut Self {
+        self.push(0x57, dst, 0, 0, imm)
+    }
+
+    /// dst = the map referred to by `map_fd`
+    pub fn load_map(&mut self, dst: u8, map_fd: i32) -> &mut Self {
+        // BPF_PSEUDO_MAP_FD; the 64-bit immediate takes two instructions
+        self.push(0x18, dst, 1, 0, map_fd).push(0, 0, 0, 0, 0)
+    }
+
+    /// if dst == imm goto label
+    pub fn jump_if_eq(&mut self, dst: u8, imm: i32, label: &str) -> &mut Self {
+        self.jumps.push((self.insns.len(), label.to_string()));
+        self.push(0x15, dst, 0, 0, imm)
+    }
+
+    /// goto label
+    pub fn jump(&mut self, label: &str) -> &mut Self {
+        self.jumps.push((self.insns.len(), label.to_string()));
+        self.push(0x05, 0, 0, 0, 0)
+    }
+
+    pub fn call(&mut self, helper: i32) -> &mut Self {
+        self.push(0x85, 0, 0, 0, helper)
+    }
+
+    pub fn exit(&mut self) -> &mut Self {
+        self.push(0x95, 0, 0, 0, 0)
+    }
+
+    pub fn label(&mut self, label: &str) -> &mut Self {
+        self.labels.insert(label.to_string(), self.insns.len());
+        self
+    }
+
+    pub fn finish(mut self) -> Vec<Insn> {
+        for (at, label) in &self.jumps {
+            let target = self.labels[label];
+            self.insns[*at].off = (target - at - 1) as i16;
+        }
+        self.insns
+    }
+}
+
+fn bpf<T>(cmd: libc::c_long, attr: &mut T) -> io::Result<libc::c_long> {
+    let result = unsafe { libc::syscall(libc::SYS_bpf, cmd, attr as *mut T, std::mem::size_of::<T>()) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(result)
+}
+
+fn bpf_fd<T>(cmd: libc::c_long, attr: &mut T) -> io::Result<OwnedFd> {
+    let fd = bpf(cmd, attr)?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct MapCreateAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct MapElemAttr {
+    map_fd: u32,
+    _pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    prog_name: [u8; 16],
+    prog_ifindex: u32,
+    expected_attach_type: u32,
+    prog_btf_fd: u32,
+    func_info_rec_size: u32,
+    func_info: u64,
+    func_info_cnt: u32,
+    line_info_rec_size: u32,
+    line_info: u64,
+    line_info_cnt: u32,
+    attach_btf_id: u32,
+    attach_prog_fd: u32,
+    _pad: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct RawTracepointAttr {
+    name: u64,
+    prog_fd: u32,
+    _pad: u32,
+}
+
+/// A hash map from `K` to `V`; both must be plain data without padding.
+pub struct BpfHashMap<K, V> {
+    fd: OwnedFd,
+    _types: std::marker::PhantomData<(K, V)>,
+}
+
+impl<K: Copy, V: Copy + Default> BpfHashMap<K, V> {
+    pub fn create(max_entries: u32) -> io::Result<Self> {
+        let mut attr = MapCreateAttr {
+            map_type: BPF_MAP_TYPE_HASH,
+            key_size: std::mem::size_of::<K>() as u32,
+            value_size: std::mem::size_of::<V>() as u32,
+            max_entries: max_entries.max(1),
+            ..Default::default()
+        };
+        Ok(Self { fd: bpf_fd(BPF_MAP_CREATE, &mut attr)?, _types: std::marker::PhantomData })
+    }
+
+    pub fn raw_fd(&self) -> i32 {
+        use std::os::fd::AsRawFd;
+        self.fd.as_raw_fd()
+    }
+
+    fn elem_attr(&self, key: &K, value: &mut V) -> MapElemAttr {
+        MapElemAttr {
+            map_fd: self.raw_fd() as u32,
+            key: key as *const K as u64,
+            value: value as *mut V as u64,
+            ..Default::default()
+        }
+    }
+
+    pub fn insert(&self, key: &K, mut value: V) -> io::Result<()> {
+        bpf(BPF_MAP_UPDATE_ELEM, &mut self.elem_attr(key, &mut value)).map(|_| ())
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut value = V::default();
+        bpf(BPF_MAP_LOOKUP_ELEM, &mut self.elem_attr(key, &mut value)).ok().map(|_| value)
+    }
+
+    pub fn remove(&self, key: &K) {
+        let _ = bpf(BPF_MAP_DELETE_ELEM, &mut self.elem_attr(key, &mut V::default()));
+    }
+
+    /// The keys in the map, as far as they are stable while it is walked.
+    pub fn keys(&self) -> Vec<K> {
+        let mut keys: Vec<K> = Vec::new();
+        loop {
+            let mut next = std::mem::MaybeUninit::<K>::uninit();
+            let mut attr = MapElemAttr {
+                map_fd: self.raw_fd() as u32,
+                key: keys.last().map_or(0, |key| key as *const K as u64),
+                value: next.as_mut_ptr() as u64,
+                ..Default::default()
+            };
+            if bpf(BPF_MAP_GET_NEXT_KEY, &mut attr).is_err() {
+                return keys;
+            }
+            keys.push(unsafe { next.assume_init() });
+        }
+    }
+}
+
+/// Loads `insns` as an LSM program for the hook function with BTF type id
+/// `attach_btf_id` and attaches it. The program stays attached as long as
+/// the returned link is open.
+pub fn attach_lsm(name: &str, insns: &[Insn], attach_btf_id: u32) -> io::Result<OwnedFd> {
+    // LSM programs must be GPL compatible
+    let license = b"GPL\0";
+    let mut log = vec![0u8; LOG_SIZE];
+    let mut prog_name = [0u8; 16];
+    prog_name[..name.len().min(15)].copy_from_slice(&name.as_bytes()[..name.len().min(15)]);
+    let mut attr = ProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_LSM,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        log_level: 1,
+        log_size: LOG_SIZE as u32,
+        log_buf: log.as_mut_ptr() as u64,
+        prog_name,
+        expected_attach_type: BPF_LSM_MAC,
+        attach_btf_id,
+        ..Default::default()
+    };
+    let program = match bpf_fd(BPF_PROG_LOAD, &mut attr) {
+        Ok(program) => program,
+        Err(e) => {
+            let log = String::from_utf8_lossy(&log[..log.iter().position(|&b| b == 0).unwrap_or(0)]).to_string();
+            let tail: Vec<&str> = log.lines().rev().take(3).collect();
+            return Err(io::Error::new(e.kind(), format!("loading {}: {} {}", name, e, tail.join(" / "))));
+        }
+    };
+
+    use std::os::fd::AsRawFd;
+    let mut attr = RawTracepointAttr { prog_fd: program.as_raw_fd() as u32, ..Default::default() };
+    bpf_fd(BPF_RAW_TRACEPOINT_OPEN, &mut attr).map_err(|e| io::Error::new(e.kind(), format!("attaching {}: {}", name, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jumps() {
+        let mut asm = Asm::default();
+        asm.jump_if_eq(R1, 0, "out").load_map(R1, 3).mov_imm(R0, -1).exit().label("out").mov_imm(R0, 0).exit();
+        let insns = asm.finish();
+        assert_eq!(insns.len(), 7);
+        // Skips the two-instruction map load, mov and exit
+        assert_eq!(insns[0], Insn { code: 0x15, regs: 1, off: 4, imm: 0 });
+        assert_eq!(insns[1], Insn { code: 0x18, regs: 0x11, off: 0, imm: 3 });
+    }
+}
@@ -0,0 +1,214 @@
+//! Just enough of the kernel's BTF (/sys/kernel/btf/vmlinux) to attach LSM
+//! programs: the type id of a hook's `bpf_lsm_*` function, its parameter
+//! count, and the byte offset of struct members, which differ between
+//! kernel builds.
+
+use std::io;
+
+const BTF_MAGIC: u16 = 0xeb9f;
+const HEADER_LEN: usize = 24;
+
+const KIND_STRUCT: u32 = 4;
+const KIND_UNION: u32 = 5;
+const KIND_FUNC: u32 = 12;
+const KIND_FUNC_PROTO: u32 = 13;
+
+struct Type {
+    name_off: u32,
+    info: u32,
+    size_or_type: u32,
+    /// Offset of the kind-specific data following the type in the type section
+    data: usize,
+}
+
+impl Type {
+    fn kind(&self) -> u32 {
+        (self.info >> 24) & 0x1f
+    }
+
+    fn vlen(&self) -> usize {
+        (self.info & 0xffff) as usize
+    }
+
+    fn kind_flag(&self) -> bool {
+        self.info >> 31 == 1
+    }
+}
+
+pub struct Btf {
+    /// Type id 0 is void, so `types[0]` has id 1
+    types: Vec<Type>,
+    type_section: Vec<u8>,
+    strings: Vec<u8>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("BTF: {}", message))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> io::Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_ne_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| invalid("truncated"))
+}
+
+/// Size of the data following a type of `kind` with `vlen` entries.
+fn data_len(kind: u32, vlen: usize) -> io::Result<usize> {
+    Ok(match kind {
+        // int, var, decl_tag
+        1 | 14 | 17 => 4,
+        // ptr, fwd, typedef, volatile, const, restrict, func, float, type_tag
+        2 | 7 | 8 | 9 | 10 | 11 | 12 | 16 | 18 => 0,
+        // array
+        3 => 12,
+        // struct, union, datasec, enum64
+        4 | 5 | 15 | 19 => 12 * vlen,
+        // enum, func_proto
+        6 | 13 => 8 * vlen,
+        _ => return Err(invalid(&format!("unknown kind {}", kind))),
+    })
+}
+
+impl Btf {
+    pub fn read() -> io::Result<Btf> {
+        Btf::parse(&std::fs::read("/sys/kernel/btf/vmlinux")?)
+    }
+
+    pub fn parse(data: &[u8]) -> io::Result<Btf> {
+        if data.len() < HEADER_LEN || u16::from_ne_bytes([data[0], data[1]]) != BTF_MAGIC {
+            return Err(invalid("bad magic"));
+        }
+        let header_len = u32_at(data, 4)? as usize;
+        let section = |offset: usize| -> io::Result<Vec<u8>> {
+            let start = header_len + u32_at(data, offset)? as usize;
+            let len = u32_at(data, offset + 4)? as usize;
+            data.get(start..start + len).map(<[u8]>::to_vec).ok_or_else(|| invalid("truncated"))
+        };
+        let type_section = section(8)?;
+        let strings = section(16)?;
+
+        let mut types = Vec::new();
+        let mut offset = 0;
+        while offset < type_section.len() {
+            let t = Type {
+                name_off: u32_at(&type_section, offset)?,
+                info: u32_at(&type_section, offset + 4)?,
+                size_or_type: u32_at(&type_section, offset + 8)?,
+                data: offset + 12,
+            };
+            offset = t.data + data_len(t.kind(), t.vlen())?;
+            if offset > type_section.len() {
+                return Err(invalid("truncated"));
+            }
+            types.push(t);
+        }
+        Ok(Btf { types, type_section, strings })
+    }
+
+    fn name(&self, name_off: u32) -> &[u8] {
+        let rest = self.strings.get(name_off as usize..).unwrap_or_default();
+        &rest[..rest.iter().position(|&b| b == 0).unwrap_or(rest.len())]
+    }
+
+    fn get(&self, id: u32) -> Option<&Type> {
+        self.types.get((id as usize).checked_sub(1)?)
+    }
+
+    fn find(&self, kind: u32, name: &str) -> Option<(u32, &Type)> {
+        self.types
+            .iter()
+            .enumerate()
+            .find(|(_, t)| t.kind() == kind && self.name(t.name_off) == name.as_bytes())
+            .map(|(i, t)| (i as u32 + 1, t))
+    }
+
+    /// Type id of function `name` and its number of parameters.
+    pub fn function(&self, name: &str) -> io::Result<(u32, usize)> {
+        let (id, func) = self.find(KIND_FUNC, name).ok_or_else(|| invalid(&format!("no function {}", name)))?;
+        let proto = self
+            .get(func.size_or_type)
+            .filter(|t| t.kind() == KIND_FUNC_PROTO)
+            .ok_or_else(|| invalid(&format!("no prototype for {}", name)))?;
+        Ok((id, proto.vlen()))
+    }
+
+    /// Byte offset of `member` in `struct name`, looking into anonymous
+    /// structs and unions.
+    pub fn member_offset(&self, name: &str, member: &str) -> io::Result<u32> {
+        let (_, t) = self.find(KIND_STRUCT, name).ok_or_else(|| invalid(&format!("no struct {}", name)))?;
+        self.find_member(t, member)
+            .map(|bits| bits / 8)
+            .ok_or_else(|| invalid(&format!("no member {} in struct {}", member, name)))
+    }
+
+    /// Bit offset of `member` in the struct or union `t`.
+    fn find_member(&self, t: &Type, member: &str) -> Option<u32> {
+        for i in 0..t.vlen() {
+            let at = t.data + 12 * i;
+            let name_off = u32_at(&self.type_section, at).ok()?;
+            let type_id = u32_at(&self.type_section, at + 4).ok()?;
+            let mut offset = u32_at(&self.type_section, at + 8).ok()?;
+            if t.kind_flag() {
+                // High 8 bits are the bitfield size
+                offset &= 0xff_ffff;
+            }
+            let name = self.name(name_off);
+            if name == member.as_bytes() {
+                return Some(offset);
+            }
+            if name.is_empty() {
+                let inner = self.get(type_id).filter(|t| matches!(t.kind(), KIND_STRUCT | KIND_UNION));
+                if let Some(found) = inner.and_then(|inner| self.find_member(inner, member)) {
+                    return Some(offset + found);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn btf_type(name_off: u32, kind: u32, vlen: u32, size_or_type: u32) -> Vec<u8> {
+        [name_off, kind << 24 | vlen, size_or_type].iter().flat_map(|v| v.to_ne_bytes()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let strings = b"\0int\0inode\0i_ino\0i_sb\0bpf_lsm_inode_unlink\0dir\0".to_vec();
+        let mut types = Vec::new();
+        // 1: int
+        types.extend(btf_type(1, 1, 0, 4));
+        types.extend(64u32.to_ne_bytes());
+        // 2: struct inode { int pad; union { int i_ino; }; int i_sb; }
+        types.extend(btf_type(5, KIND_STRUCT, 3, 12));
+        for (name_off, type_id, offset) in [(1u32, 1u32, 0u32), (0, 3, 32), (17, 1, 64)] {
+            types.extend([name_off, type_id, offset].iter().flat_map(|v| v.to_ne_bytes()));
+        }
+        // 3: anonymous union { int i_ino; }
+        types.extend(btf_type(0, KIND_UNION, 1, 4));
+        types.extend([11u32, 1, 0].iter().flat_map(|v| v.to_ne_bytes()));
+        // 4: int (int dir, int), 5: bpf_lsm_inode_unlink
+        types.extend(btf_type(0, KIND_FUNC_PROTO, 2, 1));
+        types.extend([43u32, 1, 0, 1].iter().flat_map(|v| v.to_ne_bytes()));
+        types.extend(btf_type(22, KIND_FUNC, 0, 4));
+
+        let mut data = Vec::new();
+        data.extend(BTF_MAGIC.to_ne_bytes());
+        data.extend([1u8, 0]);
+        for v in [HEADER_LEN as u32, 0, types.len() as u32, types.len() as u32, strings.len() as u32] {
+            data.extend(v.to_ne_bytes());
+        }
+        data.extend(types);
+        data.extend(strings);
+
+        let btf = Btf::parse(&data).unwrap();
+        assert_eq!(btf.function("bpf_lsm_inode_unlink").unwrap(), (5, 2));
+        assert_eq!(btf.member_offset("inode", "i_ino").unwrap(), 4);
+        assert_eq!(btf.member_offset("inode", "i_sb").unwrap(), 8);
+        assert!(btf.member_offset("inode", "i_mode").is_err());
+        assert!(btf.function("bpf_lsm_inode_rename").is_err());
+    }
+}
@@ -0,0 +1,336 @@
+//! Execution monitoring (Linux, `ebpf-exec` feature): eBPF LSM programs on
+//! `bprm_check_security` (execve) and `mmap_file` (executable mappings, i.e.
+//! shared libraries) check every binary about to run against the baseline.
+//!
+//! The baselined executables and libraries that verify at startup are
+//! trusted by (device, inode) in a BPF map. Running anything else, a baselined
+//! file that no longer matches or a binary the baseline does not have, is
+//! counted in a second map, collected here and reported as `UNTRUSTED_EXEC`;
+//! with `--exec-enforce` the kernel also refuses it with EPERM, before it
+//! runs. A program on `inode_permission` stops trusting an inode as soon as
+//! it is opened for writing or appending (or truncated), in the kernel, so a
+//! trusted binary rewritten in place cannot run as trusted before the file
+//! monitor gets to it; the kernel already refuses to execute a file that is
+//! still open for writing. Verifications by the file monitor then keep the
+//! trusted map current: a baselined file found modified stays untrusted, one
+//! verifying again (e.g. after an update) is trusted again. A verification
+//! that was already reading the file when it was rewritten can trust it
+//! again until the next one.
+
+use super::bpf::{Asm, BpfHashMap, Size, FP, HELPER_MAP_DELETE_ELEM, HELPER_MAP_LOOKUP_ELEM, HELPER_MAP_UPDATE_ELEM, R0, R1, R2, R3, R4, R6, R7, R9};
+use super::{btf, InodeKey, Offsets, EPERM, MAY_APPEND, MAY_WRITE};
+use crate::hashcache::HashCache;
+use integrity_common::{AnomalyReport, BaselineIndex, Severity};
+use std::collections::HashMap;
+use std::io;
+use std::os::fd::OwnedFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ExecArgs {
+    /// Check every executed binary and mapped library against the baseline with eBPF LSM programs (monitor mode)
+    #[arg(long)]
+    pub exec_monitor: bool,
+
+    /// Refuse to run binaries and libraries --exec-monitor does not trust
+    #[arg(long, requires = "exec_monitor")]
+    pub exec_enforce: bool,
+}
+
+/// Distinct untrusted binaries counted between two collections.
+const MAX_UNTRUSTED: u32 = 4096;
+const PROT_EXEC: i32 = 0x4;
+
+/// The file of a hook's arguments.
+#[derive(Clone, Copy)]
+enum Target {
+    /// `struct linux_binprm *` at argument 0
+    Binprm,
+    /// `struct file *` at argument 0, with the `prot` argument at this index
+    MappedFile { prot: usize },
+}
+
+struct Hook {
+    name: &'static str,
+    parameters: usize,
+    target: Target,
+}
+
+const HOOKS: &[Hook] = &[
+    Hook { name: "bprm_check_security", parameters: 1, target: Target::Binprm },
+    Hook { name: "mmap_file", parameters: 4, target: Target::MappedFile { prot: 2 } },
+];
+
+struct ExecOffsets {
+    inode: Offsets,
+    bprm_file: i16,
+    f_inode: i16,
+}
+
+impl ExecOffsets {
+    fn read(btf: &btf::Btf) -> io::Result<Self> {
+        let offset = |name: &str, member: &str| -> io::Result<i16> {
+            let offset = btf.member_offset(name, member)?;
+            i16::try_from(offset).map_err(|_| io::Error::other(format!("{}.{} out of range", name, member)))
+        };
+        Ok(Self { inode: Offsets::read(btf)?, bprm_file: offset("linux_binprm", "file")?, f_inode: offset("file", "f_inode")? })
+    }
+}
+
+/// Allows files whose inode is in `trusted`; counts the others in
+/// `untrusted` and, with `enforce`, returns -EPERM for them.
+fn program(hook: &Hook, offsets: &ExecOffsets, trusted_fd: i32, untrusted_fd: i32, enforce: bool) -> Vec<super::bpf::Insn> {
+    let mut asm = Asm::default();
+    // The context is the array of hook arguments, 8 bytes each
+    asm.mov(R9, R1).load(Size::DW, R6, R9, 0).jump_if_eq(R6, 0, "allow");
+    match hook.target {
+        Target::Binprm => {
+            asm.load(Size::DW, R6, R6, offsets.bprm_file).jump_if_eq(R6, 0, "allow");
+        }
+        Target::MappedFile { prot } => {
+            asm.load(Size::DW, R7, R9, 8 * prot as i16).and_imm(R7, PROT_EXEC).jump_if_eq(R7, 0, "allow");
+        }
+    }
+    asm.load(Size::DW, R6, R6, offsets.f_inode)
+        .jump_if_eq(R6, 0, "allow")
+        .load(Size::DW, R1, R6, offsets.inode.i_ino)
+        .store(Size::DW, FP, -16, R1)
+        .load(Size::DW, R1, R6, offsets.inode.i_sb)
+        .load(Size::W, R1, R1, offsets.inode.s_dev)
+        .store(Size::W, FP, -8, R1)
+        .store_imm(Size::W, FP, -4, 0)
+        .load_map(R1, trusted_fd)
+        .mov(R2, FP)
+        .add_imm(R2, -16)
+        .call(HELPER_MAP_LOOKUP_ELEM)
+        .jump_if_eq(R0, 0, "untrusted")
+        .jump("allow")
+        .label("untrusted")
+        .load_map(R1, untrusted_fd)
+        .mov(R2, FP)
+        .add_imm(R2, -16)
+        .call(HELPER_MAP_LOOKUP_ELEM)
+        .jump_if_eq(R0, 0, "first")
+        .mov_imm(R1, 1)
+        .atomic_add(R0, 0, R1)
+        .jump("verdict")
+        .label("first")
+        .store_imm(Size::DW, FP, -24, 1)
+        .load_map(R1, untrusted_fd)
+        .mov(R2, FP)
+        .add_imm(R2, -16)
+        .mov(R3, FP)
+        .add_imm(R3, -24)
+        .mov_imm(R4, 0)
+        .call(HELPER_MAP_UPDATE_ELEM)
+        .label("verdict");
+    if enforce {
+        asm.mov_imm(R0, -EPERM).exit();
+    }
+    asm.label("allow").mov_imm(R0, 0).exit();
+    asm.finish()
+}
+
+/// Removes inodes opened for writing from `trusted`, on `inode_permission`;
+/// never denies anything.
+fn untrust_program(offsets: &ExecOffsets, trusted_fd: i32) -> Vec<super::bpf::Insn> {
+    let mut asm = Asm::default();
+    // inode_permission(struct inode *inode, int mask)
+    asm.mov(R9, R1)
+        .load(Size::DW, R7, R9, 8)
+        .and_imm(R7, MAY_WRITE | MAY_APPEND)
+        .jump_if_eq(R7, 0, "allow")
+        .load(Size::DW, R6, R9, 0)
+        .jump_if_eq(R6, 0, "allow")
+        .load(Size::DW, R1, R6, offsets.inode.i_ino)
+        .store(Size::DW, FP, -16, R1)
+        .load(Size::DW, R1, R6, offsets.inode.i_sb)
+        .load(Size::W, R1, R1, offsets.inode.s_dev)
+        .store(Size::W, FP, -8, R1)
+        .store_imm(Size::W, FP, -4, 0)
+        .load_map(R1, trusted_fd)
+        .mov(R2, FP)
+        .add_imm(R2, -16)
+        .call(HELPER_MAP_DELETE_ELEM)
+        .label("allow")
+        .mov_imm(R0, 0)
+        .exit();
+    asm.finish()
+}
+
+/// Whether the baseline entry is something that can be run or mapped executable.
+fn is_executable(path: &str, mode: u32) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    mode & 0o111 != 0 || name.ends_with(".so") || name.contains(".so.")
+}
+
+/// The attached programs, and what they trust.
+pub struct ExecMonitor {
+    trusted: BpfHashMap<InodeKey, u8>,
+    untrusted: BpfHashMap<InodeKey, u64>,
+    /// Inode of each baselined executable, and the reverse
+    inodes: Mutex<(HashMap<String, InodeKey>, HashMap<InodeKey, String>)>,
+    enforce: bool,
+    /// The programs stay attached while these are open
+    _links: Vec<OwnedFd>,
+}
+
+impl ExecMonitor {
+    /// Trusts the baselined executables below `root` that verify, and attaches the programs.
+    pub fn attach(baseline: &BaselineIndex, root: &Path, hashes: Option<&HashCache>, args: &ExecArgs) -> io::Result<Arc<ExecMonitor>> {
+        let btf = btf::Btf::read()?;
+        let offsets = ExecOffsets::read(&btf)?;
+
+        let executables: Vec<&str> =
+            baseline.entries().filter(|e| is_executable(&e.path, e.mode)).map(|e| e.path.trim_start_matches('/')).collect();
+        let trusted = BpfHashMap::create(executables.len() as u32 * 2)?;
+        let untrusted = BpfHashMap::create(MAX_UNTRUSTED)?;
+        let (mut by_path, mut by_inode) = (HashMap::new(), HashMap::new());
+        for relative in executables {
+            let path = root.join(relative);
+            let Ok(metadata) = std::fs::symlink_metadata(&path) else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            let key = InodeKey::new(&metadata);
+            by_path.insert(relative.to_string(), key);
+            by_inode.insert(key, relative.to_string());
+            if crate::check_file(&path, root, baseline, hashes).is_none() {
+                trusted.insert(&key, 1)?;
+            }
+        }
+
+        let mut links = Vec::new();
+        for hook in HOOKS {
+            let function = format!("bpf_lsm_{}", hook.name);
+            let (id, parameters) = btf.function(&function)?;
+            if parameters != hook.parameters {
+                return Err(io::Error::other(format!("{} takes {} arguments, expected {}", function, parameters, hook.parameters)));
+            }
+            let name = format!("acropole_{}", hook.name.trim_end_matches("_security"));
+            let insns = program(hook, &offsets, trusted.raw_fd(), untrusted.raw_fd(), args.exec_enforce);
+            links.push(super::bpf::attach_lsm(&name, &insns, id)?);
+        }
+        let (id, _) = btf.function("bpf_lsm_inode_permission")?;
+        links.push(super::bpf::attach_lsm("acropole_untrust", &untrust_program(&offsets, trusted.raw_fd()), id)?);
+        Ok(Arc::new(ExecMonitor {
+            trusted,
+            untrusted,
+            inodes: Mutex::new((by_path, by_inode)),
+            enforce: args.exec_enforce,
+            _links: links,
+        }))
+    }
+
+    pub fn baselined_executables(&self) -> usize {
+        self.inodes.lock().unwrap().0.len()
+    }
+
+    /// Records the monitor's verdict on the baselined file `relative_path`,
+    /// found at `path`: it is trusted to run from now on only if it verified.
+    pub fn verified(&self, path: &Path, relative_path: &str, ok: bool) {
+        let mut inodes = self.inodes.lock().unwrap();
+        let Some(previous) = inodes.0.get(relative_path).copied() else {
+            return;
+        };
+        self.trusted.remove(&previous);
+        inodes.1.remove(&previous);
+        let Ok(metadata) = std::fs::symlink_metadata(path) else {
+            return;
+        };
+        let key = InodeKey::new(&metadata);
+        inodes.0.insert(relative_path.to_string(), key);
+        inodes.1.insert(key, relative_path.to_string());
+        if ok && metadata.is_file() {
+            if let Err(e) = self.trusted.insert(&key, 1) {
+                tracing::warn!("Cannot trust {} for execution again: {}", relative_path, e);
+            }
+        }
+    }
+
+    /// Where the untrusted `key` is: a baselined path, or a path a process
+    /// has it mapped from.
+    fn locate(&self, key: &InodeKey) -> String {
+        if let Some(path) = self.inodes.lock().unwrap().1.get(key) {
+            return path.clone();
+        }
+        mapped_path(key).map_or_else(
+            || format!("inode {} on device {}:{}", key.ino, key.dev >> 20, key.dev & 0xf_ffff),
+            |path| path.to_string_lossy().trim_start_matches('/').to_string(),
+        )
+    }
+
+    /// Untrusted executions since the last collection.
+    fn collect(&self) -> Vec<AnomalyReport> {
+        let outcome = if self.enforce { "denied" } else { "allowed" };
+        let mut anomalies = Vec::new();
+        for key in self.untrusted.keys() {
+            let count = self.untrusted.get(&key).unwrap_or(0);
+            self.untrusted.remove(&key);
+            if count > 0 {
+                anomalies.push(
+                    AnomalyReport::other("UNTRUSTED_EXEC", self.locate(&key), format!("{} executions {}, not matching the baseline", count, outcome))
+                        .with_severity(Severity::High),
+                );
+            }
+        }
+        anomalies
+    }
+}
+
+/// A path some process maps the file with inode `key` from, as /proc/*/maps
+/// shows it. Denied executions never get mapped.
+fn mapped_path(key: &InodeKey) -> Option<PathBuf> {
+    let device = format!("{:02x}:{:02x}", key.dev >> 20, key.dev & 0xf_ffff);
+    let inode = key.ino.to_string();
+    for process in std::fs::read_dir("/proc").ok()?.filter_map(|e| e.ok()) {
+        let Ok(maps) = std::fs::read_to_string(process.path().join("maps")) else {
+            continue;
+        };
+        for line in maps.lines() {
+            let fields: Vec<&str> = line.splitn(6, ' ').collect();
+            if fields.len() == 6 && fields[3] == device && fields[4] == inode {
+                return Some(PathBuf::from(fields[5].trim_start()));
+            }
+        }
+    }
+    None
+}
+
+/// Reports untrusted executions every `interval`, keeping the programs
+/// attached for as long as the agent runs.
+pub fn spawn(monitor: Arc<ExecMonitor>, interval: Duration, tx: mpsc::Sender<AnomalyReport>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            let collecting = monitor.clone();
+            let Ok(anomalies) = tokio::task::spawn_blocking(move || collecting.collect()).await else {
+                return;
+            };
+            for anomaly in anomalies {
+                if tx.send(anomaly).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_executable() {
+        assert!(is_executable("usr/bin/ls", 0o755));
+        assert!(is_executable("usr/lib/x86_64-linux-gnu/libc.so.6", 0o644));
+        assert!(is_executable("usr/lib/libfoo.so", 0o644));
+        assert!(!is_executable("etc/passwd", 0o644));
+        assert!(!is_executable("usr/share/doc/solo.sonnet", 0o644));
+    }
+}
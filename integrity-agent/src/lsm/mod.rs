@@ -0,0 +1,249 @@
+//! Preventive enforcement (Linux, `ebpf-lsm` feature): eBPF LSM programs
+//! denying writes, unlinks and renames of critical baseline files.
+//!
+//! Monitoring reports a modified binary after the fact; with `--lsm-enforce`
+//! the kernel refuses the modification, for root too. The inodes of the
+//! baselined files under `--lsm-protect` are put in a BPF hash map keyed by
+//! (device, inode), and programs on the `inode_permission` (open for write
+//! or append, truncate), `inode_unlink` and `inode_rename` hooks return
+//! -EPERM for them. Each denial is counted in the map and reported as
+//! `WRITE_BLOCKED`.
+//!
+//! Needs a kernel with `CONFIG_BPF_LSM`, "bpf" in the active LSMs (`lsm=`
+//! boot parameter) and BTF, and `CAP_BPF` plus `CAP_SYS_ADMIN`. The programs
+//! are detached when the agent exits, which is also how package updates of
+//! protected files are let through.
+//!
+//! The `exec` module checks executions against the baseline the same way.
+
+mod bpf;
+mod btf;
+#[cfg(feature = "ebpf-exec")]
+pub mod exec;
+
+use bpf::{Asm, BpfHashMap, Size, FP, HELPER_MAP_LOOKUP_ELEM, R0, R1, R2, R6, R7, R9};
+use integrity_common::{AnomalyReport, BaselineIndex};
+use std::collections::HashMap;
+use std::io;
+use std::os::fd::OwnedFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct LsmArgs {
+    /// Deny writes, unlinks and renames of baselined files under --lsm-protect with eBPF LSM programs (monitor mode)
+    #[arg(long)]
+    pub lsm_enforce: bool,
+
+    /// Directories whose baselined files --lsm-enforce protects
+    #[arg(long, value_delimiter = ',', default_value = "/bin,/sbin,/usr/bin,/usr/sbin,/lib,/lib64,/usr/lib,/usr/lib64,/boot")]
+    pub lsm_protect: Vec<PathBuf>,
+}
+
+/// Map key; `dev` is the kernel's `s_dev` encoding (major << 20 | minor),
+/// not the one stat(2) returns.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct InodeKey {
+    ino: u64,
+    dev: u32,
+    _pad: u32,
+}
+
+impl InodeKey {
+    fn new(metadata: &std::fs::Metadata) -> Self {
+        let dev = metadata.dev();
+        Self {
+            ino: metadata.ino(),
+            dev: (libc::major(dev) << 20) | libc::minor(dev),
+            _pad: 0,
+        }
+    }
+}
+
+/// Struct member offsets of the running kernel.
+struct Offsets {
+    i_ino: i16,
+    i_sb: i16,
+    s_dev: i16,
+    d_inode: i16,
+}
+
+impl Offsets {
+    fn read(btf: &btf::Btf) -> io::Result<Self> {
+        let offset = |name: &str, member: &str| -> io::Result<i16> {
+            let offset = btf.member_offset(name, member)?;
+            i16::try_from(offset).map_err(|_| io::Error::other(format!("{}.{} out of range", name, member)))
+        };
+        Ok(Self {
+            i_ino: offset("inode", "i_ino")?,
+            i_sb: offset("inode", "i_sb")?,
+            s_dev: offset("super_block", "s_dev")?,
+            d_inode: offset("dentry", "d_inode")?,
+        })
+    }
+}
+
+/// A hook argument whose inode is checked.
+#[derive(Clone, Copy)]
+enum Checked {
+    /// `struct inode *` at this argument index
+    Inode(usize),
+    /// `struct dentry *` at this argument index
+    Dentry(usize),
+}
+
+struct Hook {
+    name: &'static str,
+    parameters: usize,
+    checked: &'static [Checked],
+    /// Index of the MAY_* mask argument, for hooks also called on reads
+    mask: Option<usize>,
+}
+
+const HOOKS: &[Hook] = &[
+    Hook { name: "inode_permission", parameters: 2, checked: &[Checked::Inode(0)], mask: Some(1) },
+    Hook { name: "inode_unlink", parameters: 2, checked: &[Checked::Dentry(1)], mask: None },
+    // Both the renamed file and the one it would replace
+    Hook { name: "inode_rename", parameters: 4, checked: &[Checked::Dentry(1), Checked::Dentry(3)], mask: None },
+];
+
+const MAY_WRITE: i32 = 0x02;
+const MAY_APPEND: i32 = 0x08;
+const EPERM: i32 = 1;
+
+/// Returns -EPERM if one of the checked inodes is in the map, after counting the denial.
+fn program(hook: &Hook, offsets: &Offsets, map_fd: i32) -> Vec<bpf::Insn> {
+    let mut asm = Asm::default();
+    // The context is the array of hook arguments, 8 bytes each
+    asm.mov(R9, R1);
+    if let Some(mask) = hook.mask {
+        asm.load(Size::DW, R7, R9, 8 * mask as i16).and_imm(R7, MAY_WRITE | MAY_APPEND).jump_if_eq(R7, 0, "allow");
+    }
+    for (i, checked) in hook.checked.iter().enumerate() {
+        let next = format!("next{}", i);
+        match *checked {
+            Checked::Inode(arg) => asm.load(Size::DW, R6, R9, 8 * arg as i16),
+            Checked::Dentry(arg) => asm.load(Size::DW, R6, R9, 8 * arg as i16).jump_if_eq(R6, 0, &next).load(Size::DW, R6, R6, offsets.d_inode),
+        };
+        // Negative dentries have no inode
+        asm.jump_if_eq(R6, 0, &next)
+            .load(Size::DW, R1, R6, offsets.i_ino)
+            .store(Size::DW, FP, -16, R1)
+            .load(Size::DW, R1, R6, offsets.i_sb)
+            .load(Size::W, R1, R1, offsets.s_dev)
+            .store(Size::W, FP, -8, R1)
+            .store_imm(Size::W, FP, -4, 0)
+            .load_map(R1, map_fd)
+            .mov(R2, FP)
+            .add_imm(R2, -16)
+            .call(HELPER_MAP_LOOKUP_ELEM)
+            .jump_if_eq(R0, 0, &next)
+            .mov_imm(R1, 1)
+            .atomic_add(R0, 0, R1)
+            .mov_imm(R0, -EPERM)
+            .exit()
+            .label(&next);
+    }
+    asm.label("allow").mov_imm(R0, 0).exit();
+    asm.finish()
+}
+
+/// Whether the baselined `path` is below one of the `protected` directories.
+fn is_protected(path: &str, protected: &[PathBuf]) -> bool {
+    let path = Path::new(path.trim_start_matches('/'));
+    protected.iter().any(|dir| path.starts_with(dir.strip_prefix("/").unwrap_or(dir)))
+}
+
+/// The attached programs and the inodes they protect.
+pub struct Enforcer {
+    map: BpfHashMap<InodeKey, u64>,
+    protected: Vec<(InodeKey, String)>,
+    /// The programs stay attached while these are open
+    _links: Vec<OwnedFd>,
+}
+
+impl Enforcer {
+    /// Protects the regular files of `baseline` under `args.lsm_protect`, as found below `root`.
+    pub fn attach(baseline: &BaselineIndex, root: &Path, args: &LsmArgs) -> io::Result<Enforcer> {
+        let btf = btf::Btf::read()?;
+        let offsets = Offsets::read(&btf)?;
+
+        let mut protected = Vec::new();
+        // Hard links, and /bin/ls and /usr/bin/ls on merged-/usr systems, are one inode
+        let mut seen = std::collections::HashSet::new();
+        for entry in baseline.entries().filter(|e| is_protected(&e.path, &args.lsm_protect)) {
+            let relative = entry.path.trim_start_matches('/');
+            match std::fs::symlink_metadata(root.join(relative)) {
+                Ok(metadata) if metadata.is_file() && seen.insert(InodeKey::new(&metadata)) => {
+                    protected.push((InodeKey::new(&metadata), relative.to_string()))
+                }
+                _ => {}
+            }
+        }
+        let map = BpfHashMap::create(protected.len() as u32)?;
+        for (key, _) in &protected {
+            map.insert(key, 0)?;
+        }
+
+        let mut links = Vec::new();
+        for hook in HOOKS {
+            let function = format!("bpf_lsm_{}", hook.name);
+            let (id, parameters) = btf.function(&function)?;
+            if parameters != hook.parameters {
+                return Err(io::Error::other(format!("{} takes {} arguments, expected {}", function, parameters, hook.parameters)));
+            }
+            let name = format!("acropole_{}", hook.name.trim_start_matches("inode_"));
+            links.push(bpf::attach_lsm(&name, &program(hook, &offsets, map.raw_fd()), id)?);
+        }
+        Ok(Enforcer { map, protected, _links: links })
+    }
+
+    pub fn protected_files(&self) -> usize {
+        self.protected.len()
+    }
+
+    /// Denials per protected path so far.
+    fn denials(&self) -> impl Iterator<Item = (&InodeKey, &str, u64)> {
+        self.protected.iter().map(|(key, path)| (key, path.as_str(), self.map.get(key).unwrap_or(0)))
+    }
+}
+
+/// Reports new denials as `WRITE_BLOCKED` every `interval`, keeping the
+/// programs attached for as long as the agent runs.
+pub fn spawn(enforcer: Enforcer, interval: Duration, tx: mpsc::Sender<AnomalyReport>) {
+    tokio::spawn(async move {
+        let mut reported: HashMap<InodeKey, u64> = HashMap::new();
+        loop {
+            tokio::time::sleep(interval).await;
+            for (key, path, count) in enforcer.denials() {
+                let previous = reported.get(key).copied().unwrap_or(0);
+                if count <= previous {
+                    continue;
+                }
+                reported.insert(*key, count);
+                let anomaly = AnomalyReport::other("WRITE_BLOCKED", path, format!("{} modification attempts denied", count - previous));
+                if tx.send(anomaly).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_protected() {
+        let protected = vec![PathBuf::from("/usr/bin"), PathBuf::from("/boot")];
+        assert!(is_protected("usr/bin/ls", &protected));
+        assert!(is_protected("/boot/vmlinuz", &protected));
+        assert!(!is_protected("usr/binaries/ls", &protected));
+        assert!(!is_protected("etc/passwd", &protected));
+        assert!(is_protected("etc/passwd", &[PathBuf::from("/")]));
+    }
+}
@@ -1,367 +1,1899 @@
+mod accounts;
+mod baseline_cache;
+#[cfg(target_os = "linux")]
+mod boot;
+mod classify;
+mod cloud;
+mod control;
+mod daemon;
+#[cfg(target_os = "linux")]
+mod decoy;
+mod early_boot;
+mod enrich;
+#[cfg(target_os = "linux")]
+mod fssnapshot;
+mod hashcache;
+mod heartbeat;
+#[cfg(target_os = "linux")]
+mod inotify_monitor;
+mod k8s;
+#[cfg(target_os = "linux")]
+mod listeners;
+#[cfg(all(target_os = "linux", feature = "ebpf-lsm"))]
+mod lsm;
+#[cfg(target_os = "linux")]
+mod kmod;
 mod monitor;
+mod osquery;
+mod osrelease;
+#[cfg(target_os = "linux")]
+mod ostree;
+mod output;
+mod persistence;
+mod pipeline;
+mod platform;
+mod policy;
+#[cfg(target_os = "linux")]
+mod procverify;
+mod prometheus;
+mod remote_config;
+mod remote_lookup;
+mod rescan;
+mod schedule;
+mod selfcheck;
+mod signatures;
+mod state;
+mod summary;
+#[cfg(target_os = "linux")]
+mod sysctl;
+mod trust;
+mod truststore;
+mod verifier;
 #[cfg(target_os = "linux")]
 mod fanotify_monitor;
+#[cfg(windows)]
+mod usn_monitor;
+#[cfg(target_os = "macos")]
+mod fsevents_monitor;
+#[cfg(any(target_os = "freebsd", target_os = "openbsd", target_os = "netbsd", target_os = "dragonfly"))]
+mod kqueue_monitor;
 
 use clap::Parser;
-use integrity_common::{Baseline, FileIntegrityEntry, Result, IntegrityError};
-use monitor::Monitor;
-use sha2::{Digest, Sha512};
-use std::collections::HashMap;
+use integrity_client::{ClientArgs, ClientConfig, MetadataClient};
+use integrity_common::{is_account_file, is_trust_store_file, Anomaly, AnomalyReport, Baseline, BaselineIndex, EffectiveAgentConfig, FileIntegrityEntry, HashAlgorithm, MacPolicy, Result, IntegrityError, ScanConfig, ScanSnapshot, Severity, read_xattrs, xattr_changes};
+use integrity_scanner::{compute_digest, compute_sha512, symlink_target, Scanner};
+use k8s::K8sContext;
+use monitor::{EventType, Monitor};
+use output::AnomalySink;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
 use std::fs;
-use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use tracing::{info, error, warn};
-use walkdir::{DirEntry, WalkDir};
+use std::sync::Arc;
+use summary::RunSummary;
+use tracing::{info, error, warn, Instrument};
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(name = "integrity-agent")]
 #[command(about = "Golden Image Integrity Agent", long_about = None)]
+#[command(subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(long, default_value = "/")]
     scan_path: PathBuf,
 
+    /// TOML file of paths to exclude, include and verify metadata-only, the one the baseline was collected with
+    #[arg(long)]
+    scan_config: Option<PathBuf>,
+
+    /// Baseline image ID, "auto" to detect it from the cloud instance metadata,
+    /// "ostree" to follow the booted ostree deployment (Linux), or "os-release"
+    /// to follow the OS version of A/B updated hosts
+    #[arg(long, required_unless_present = "early_boot")]
+    image_id: Option<String>,
+
+    /// Baselines layered on top of --image-id, lowest first (e.g. site,app).
+    /// A file must match the topmost layer that defines it.
+    #[arg(long, value_delimiter = ',')]
+    overlay: Vec<String>,
+
+    /// After a scan, upload every file it saw to the metadata service as a snapshot, for later forensics
+    #[arg(long)]
+    upload_snapshot: bool,
+
+    /// Report the anomalies found to the metadata service, where the fleet's findings are listed and triaged
+    #[arg(long)]
+    report_anomalies: bool,
+
+    /// Directory holding the agent's local state (anomaly history)
+    #[arg(long, global = true, default_value = "/var/lib/acropole-agent")]
+    state_dir: PathBuf,
+
+    /// JSON summary of the run (outcome, duration, baseline, anomaly counts), <state-dir>/last-run.json by default
     #[arg(long)]
-    image_id: String,
+    summary_file: Option<PathBuf>,
 
-    #[arg(long, default_value = "http://localhost:8080")]
-    metadata_url: String,
+    /// Cloud metadata service(s) to query with --image-id auto
+    #[arg(long, value_enum, default_value = "auto")]
+    cloud_provider: cloud::CloudProvider,
+
+    #[command(flatten)]
+    client: ClientArgs,
+
+    /// Allow --metadata-url/--ca-cert/--trusted-pubkey to replace the trust anchors embedded at build time
+    #[arg(long)]
+    insecure_override: bool,
 
     #[arg(long, value_enum, default_value = "scan")]
     mode: RunMode,
 
+    /// File monitor in monitor mode: fanotify, falling back to inotify where it is not permitted, or either one only (Linux)
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_enum, default_value = "auto")]
+    monitor_backend: MonitorBackend,
+
     #[arg(long, value_delimiter = ',', default_value = "/bin,/sbin,/usr/bin,/usr/sbin,/etc")]
     watch_paths: Vec<PathBuf>,
-}
 
-#[derive(clap::ValueEnum, Clone, Debug)]
-enum RunMode {
-    /// Run a one-time scan and compare with baseline
-    Scan,
-    /// Monitor filesystem events in real-time
-    Monitor,
-}
+    /// Libraries legitimately set in LD_PRELOAD/LD_AUDIT of processes (path or file name), for ps-verify
+    #[arg(long, value_delimiter = ',')]
+    allowed_preload: Vec<String>,
 
-/// Directories to exclude from scanning
-const EXCLUDED_DIRS: &[&str] = &[
-    "/proc", "/sys", "/dev", "/run", "/tmp", "/var/tmp", "/var/log",
-];
+    /// After an anomaly on a unit, init or cron path, report the units and jobs enabled outside the baseline
+    #[arg(long)]
+    enumerate_persistence: bool,
 
-fn should_exclude(entry: &DirEntry) -> bool {
-    let path = entry.path();
+    /// Flag loaded kernel modules missing from or not matching the baseline (Linux, monitor mode)
+    #[arg(long)]
+    kernel_modules: bool,
 
-    // Skip if it's a directory and matches excluded paths
-    if path.is_dir() {
-        let path_str = path.to_string_lossy();
-        return EXCLUDED_DIRS.iter().any(|&excluded| path_str.starts_with(excluded));
-    }
+    /// Run as a Kubernetes DaemonSet, scanning the host through a hostPath mount
+    #[arg(long)]
+    k8s: bool,
 
-    // Skip special files (devices, sockets, etc.)
-    if let Ok(metadata) = entry.metadata() {
-        use std::os::unix::fs::FileTypeExt;
-        let file_type = metadata.file_type();
-        if file_type.is_block_device() || file_type.is_char_device() || file_type.is_fifo() || file_type.is_socket() {
-            return true;
-        }
-    }
+    /// Where the host root filesystem is mounted in --k8s mode
+    #[arg(long, default_value = "/host")]
+    host_root: PathBuf,
+
+    /// Cluster name attached to reports in --k8s mode (defaults to $CLUSTER_NAME)
+    #[arg(long)]
+    cluster_name: Option<String>,
+
+    /// Unix socket serving integrity tables to an osquery extension (monitor mode)
+    #[arg(long)]
+    osquery_socket: Option<PathBuf>,
+
+    /// Forward anomalies as Wazuh FIM events: socket[:<path>] or file:<path>
+    #[arg(long)]
+    wazuh_output: Option<output::wazuh::WazuhTarget>,
+
+    /// Emit anomalies as Falco alerts: stdout, or a falcosidekick URL
+    #[arg(long)]
+    falco_output: Option<output::falco::FalcoTarget>,
+
+    /// Write anomalies to stdout as text lines, JSON documents or NDJSON records; the log goes to stderr then
+    #[arg(long, value_enum)]
+    output_format: Option<output::stdout::OutputFormat>,
+
+    /// JSON file of response hooks to run on matching anomalies
+    #[arg(long)]
+    response_hooks: Option<PathBuf>,
+
+    /// Maximum number of response hooks running at once
+    #[arg(long, default_value = "4")]
+    hook_concurrency: usize,
+
+    #[command(flatten)]
+    remount: output::remount::RemountArgs,
+
+    #[command(flatten)]
+    anomaly_policy: policy::PolicyArgs,
+
+    #[cfg(target_os = "linux")]
+    #[command(flatten)]
+    boot: boot::BootArgs,
+
+    #[cfg(target_os = "linux")]
+    #[command(flatten)]
+    decoy: decoy::DecoyArgs,
+
+    #[cfg(target_os = "linux")]
+    #[command(flatten)]
+    fs_snapshot: fssnapshot::FsSnapshotArgs,
+
+    #[cfg(all(target_os = "linux", feature = "ebpf-lsm"))]
+    #[command(flatten)]
+    lsm: lsm::LsmArgs,
+
+    #[cfg(all(target_os = "linux", feature = "ebpf-exec"))]
+    #[command(flatten)]
+    exec: lsm::exec::ExecArgs,
 
-    false
+    #[command(flatten)]
+    rescan: rescan::RescanArgs,
+
+    #[command(flatten)]
+    daemon: daemon::DaemonArgs,
+
+    #[command(flatten)]
+    schedule: schedule::ScheduleArgs,
+
+    #[command(flatten)]
+    hash_cache: hashcache::HashCacheArgs,
+
+    #[command(flatten)]
+    pipeline: pipeline::PipelineArgs,
+
+    #[command(flatten)]
+    self_check: selfcheck::SelfCheckArgs,
+
+    #[command(flatten)]
+    signatures: signatures::SignatureArgs,
+
+    #[command(flatten)]
+    baseline_cache: baseline_cache::BaselineCacheArgs,
+
+    #[command(flatten)]
+    early_boot: early_boot::EarlyBootArgs,
+
+    #[command(flatten)]
+    yara: enrich::yara::YaraArgs,
+
+    #[command(flatten)]
+    reputation: enrich::reputation::ReputationArgs,
+
+    #[command(flatten)]
+    remote: remote_config::RemoteConfigArgs,
+
+    #[command(flatten)]
+    control: control::ControlArgs,
+
+    #[command(flatten)]
+    heartbeat: heartbeat::HeartbeatArgs,
+
+    #[command(flatten)]
+    prometheus: prometheus::PrometheusArgs,
+
+    #[command(flatten)]
+    remote_lookup: remote_lookup::RemoteLookupArgs,
+
+    /// External verifier plugin run on every file event that matches the baseline (repeatable)
+    #[arg(long)]
+    verifier_plugin: Vec<PathBuf>,
+
+    /// Seconds a verifier may take per file before the check fails
+    #[arg(long, default_value = "5")]
+    verifier_timeout: u64,
+
+    /// Allowlist service queried with each file's SHA-512
+    #[cfg(feature = "allowlist-verifier")]
+    #[arg(long)]
+    allowlist_url: Option<String>,
 }
 
-fn compute_sha512(path: &Path) -> Result<String> {
-    let mut hasher = Sha512::new();
-    let mut file = fs::File::open(path)?;
-    std::io::copy(&mut file, &mut hasher)?;
-    let result = hasher.finalize();
-    Ok(hex::encode(result))
+#[derive(clap::Subcommand, Debug, Clone)]
+enum Command {
+    /// Query the anomalies recorded in monitor mode
+    History(HistoryArgs),
 }
 
-fn scan_filesystem(root_path: &Path) -> Result<HashMap<String, FileIntegrityEntry>> {
-    info!("Starting filesystem scan from: {:?}", root_path);
+#[derive(clap::Args, Debug, Clone)]
+struct HistoryArgs {
+    /// Only anomalies from this long ago onwards, e.g. 24h or 7d
+    #[arg(long, value_parser = humantime::parse_duration)]
+    since: Option<std::time::Duration>,
+
+    /// Only anomalies at or below this path
+    #[arg(long)]
+    path: Option<String>,
 
-    let mut entries = HashMap::new();
-    let walker = WalkDir::new(root_path)
-        .follow_links(false)
-        .into_iter()
-        .filter_entry(|e| !should_exclude(e));
+    /// Only anomalies not yet resolved
+    #[arg(long)]
+    unresolved: bool,
 
-    for entry in walker {
-        let entry = entry.map_err(|e| IntegrityError::Walkdir(e.to_string()))?;
-        let path = entry.path();
+    /// Print JSON instead of a table
+    #[arg(long)]
+    json: bool,
+}
 
-        // Skip directories
-        if path.is_dir() {
-            continue;
-        }
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq)]
+enum RunMode {
+    /// Run a one-time scan and compare with baseline
+    Scan,
+    /// Run scans on a schedule, reporting after each pass
+    Daemon,
+    /// Monitor filesystem events in real-time
+    Monitor,
+    /// Monitor, plus rolling re-scans of the watch paths to catch missed events
+    Hybrid,
+    /// Verify the executables and libraries of running processes (Linux)
+    PsVerify,
+}
 
-        // Get relative path from root
-        let relative_path = path.strip_prefix(root_path)
-            .unwrap_or(path)
-            .to_string_lossy()
-            .to_string();
+#[cfg(target_os = "linux")]
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum MonitorBackend {
+    /// fanotify, or inotify without CAP_SYS_ADMIN
+    Auto,
+    /// fanotify only: writes and executions on whole mounts
+    Fanotify,
+    /// inotify only: creations, writes and deletions in each watched directory
+    Inotify,
+}
 
-        // Skip if path is empty (shouldn't happen, but safety check)
-        if relative_path.is_empty() {
-            continue;
-        }
+/// How often /proc/modules is polled with --kernel-modules.
+#[cfg(target_os = "linux")]
+const KERNEL_MODULE_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
 
-        match entry.metadata() {
-            Ok(metadata) => {
-                match compute_sha512(path) {
-                    Ok(sha512) => {
-                        let file_entry = FileIntegrityEntry {
-                            path: relative_path.clone(),
-                            sha512,
-                            mode: metadata.mode() & 0o7777, // Get permission bits
-                            uid: metadata.uid(),
-                            gid: metadata.gid(),
-                        };
-                        entries.insert(relative_path, file_entry);
+/// How often /proc/sys is polled when the baseline records sysctls.
+#[cfg(target_os = "linux")]
+const SYSCTL_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
 
-                        if entries.len() % 1000 == 0 {
-                            info!("Scanned {} files...", entries.len());
-                        }
-                    }
-                    Err(e) => {
-                        warn!("Failed to hash file {:?}: {}", path, e);
-                    }
-                }
-            }
-            Err(e) => {
-                warn!("Failed to get metadata for {:?}: {}", path, e);
-            }
-        }
-    }
+/// How often listening sockets are listed when the baseline records them.
+#[cfg(target_os = "linux")]
+const LISTENER_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
 
-    info!("Scan complete. Found {} files", entries.len());
-    Ok(entries)
-}
+/// How often denials of --lsm-enforce are collected.
+#[cfg(all(target_os = "linux", feature = "ebpf-lsm"))]
+const LSM_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
 
-async fn fetch_baseline(metadata_url: &str, image_id: &str) -> Result<Baseline> {
-    let client = reqwest::Client::new();
-    let url = format!("{}/baselines/{}", metadata_url, image_id);
+/// How often deferred and due re-verifications are picked up in monitor mode.
+const SCHEDULE_TICK: std::time::Duration = std::time::Duration::from_secs(1);
 
-    info!("Fetching baseline from: {}", url);
+/// Logs how far a scan got.
+fn log_progress(files: usize) {
+    info!("Scanned {} files...", files);
+}
 
-    let response = client
-        .get(&url)
-        .send()
-        .await
-        .map_err(|e| integrity_common::IntegrityError::Storage(e.to_string()))?;
+fn scan_filesystem(
+    root_path: &Path,
+    config: &ScanConfig,
+    algorithm: HashAlgorithm,
+    hashes: Option<&hashcache::HashCache>,
+) -> Result<HashMap<String, FileIntegrityEntry>> {
+    scan_subtree(root_path, root_path, config, algorithm, hashes)
+}
 
-    if response.status().is_success() {
-        let baseline: Baseline = response
-            .json()
-            .await
-            .map_err(|e| integrity_common::IntegrityError::Storage(e.to_string()))?;
-        info!("Baseline fetched successfully ({} files)", baseline.entries.len());
-        Ok(baseline)
+/// Scans the tree at `start`, recording paths relative to `root_path`, which
+/// `config` matches them by, and digests in the baseline's `algorithm`.
+fn scan_subtree(
+    root_path: &Path,
+    start: &Path,
+    config: &ScanConfig,
+    algorithm: HashAlgorithm,
+    hashes: Option<&hashcache::HashCache>,
+) -> Result<HashMap<String, FileIntegrityEntry>> {
+    let mut scanner = Scanner::new(config).with_algorithms(&[algorithm]).on_progress(log_progress);
+    if let Some(cache) = hashes {
+        scanner = scanner.with_hasher(cache);
+    }
+    let entries = scanner.scan_subtree(root_path, start)?;
+    Ok(entries.into_iter().map(|entry| (entry.path.clone(), entry)).collect())
+}
+
+/// Anomalies describing what changed inside an account or trust store file,
+/// from the records the baseline keeps of them.
+fn describe_changes(baseline: &Baseline, root: &Path, relative_path: &str) -> Vec<AnomalyReport> {
+    if is_account_file(relative_path) {
+        accounts::describe(baseline, root)
+    } else if is_trust_store_file(relative_path) {
+        truststore::describe(baseline, root)
     } else {
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        error!("Failed to fetch baseline: {}", error_text);
-        Err(integrity_common::IntegrityError::BaselineNotFound(format!("Fetch failed: {}", error_text)))
+        Vec::new()
+    }
+}
+
+/// MAC_POLICY_CHANGED anomalies for the host's SELinux/AppArmor state, when
+/// the baseline recorded one. The state is kernel-wide, so /sys is read even
+/// from a DaemonSet pod.
+fn verify_mac_policy(baseline: &Baseline) -> Vec<AnomalyReport> {
+    let Some(expected) = &baseline.mac_policy else {
+        return Vec::new();
+    };
+    match MacPolicy::read(Path::new("/")) {
+        Ok(current) => expected
+            .changes(&current)
+            .into_iter()
+            .map(|change| AnomalyReport::other("MAC_POLICY_CHANGED", change.path, change.detail))
+            .collect(),
+        Err(e) => vec![AnomalyReport::other("MAC_POLICY_CHECK_FAILED", "sys", e.to_string())],
     }
 }
 
-fn compare_filesystems(baseline: &Baseline, current: &HashMap<String, FileIntegrityEntry>) -> Vec<String> {
+fn compare_filesystems(baseline: &BaselineIndex, current: &HashMap<String, FileIntegrityEntry>) -> Vec<AnomalyReport> {
     let mut anomalies = Vec::new();
-    let baseline_map: HashMap<String, &FileIntegrityEntry> = baseline.entries
-        .iter()
-        .map(|entry| (entry.path.clone(), entry))
-        .collect();
 
     // Check for modified/deleted files
-    for (path, baseline_entry) in &baseline_map {
+    for baseline_entry in baseline.entries() {
+        let path = &baseline_entry.path;
         match current.get(path) {
             Some(current_entry) => {
-                // File exists, check for modifications
-                if current_entry.sha512 != baseline_entry.sha512 {
-                    anomalies.push(format!("MODIFIED: {} (hash mismatch: {} != {})",
-                        path, baseline_entry.sha512, current_entry.sha512));
+                // A symlink is only compared by its target. Baselines collected
+                // before types were recorded have no targets to compare.
+                if current_entry.is_symlink() || baseline_entry.is_symlink() {
+                    if baseline_entry.entry_type.is_some() && current_entry.symlink_target != baseline_entry.symlink_target {
+                        anomalies.push(AnomalyReport::new(Anomaly::SymlinkTargetChanged {
+                            expected: baseline_entry.symlink_target.clone(),
+                            actual: current_entry.symlink_target.clone(),
+                        }, path));
+                    }
+                    continue;
+                }
+
+                // File exists, check for modifications, unless either side is metadata-only
+                let content_change = baseline_entry.content_change(current_entry, baseline.hash_algorithm());
+                if let Some((expected, actual)) = content_change {
+                    anomalies.push(AnomalyReport::new(Anomaly::Modified {
+                        expected_sha512: expected.to_string(),
+                        actual_sha512: actual.to_string(),
+                    }, path));
                 }
                 if current_entry.mode != baseline_entry.mode {
-                    anomalies.push(format!("PERMISSION_CHANGED: {} ({} != {})",
-                        path, format!("{:o}", baseline_entry.mode), format!("{:o}", current_entry.mode)));
+                    anomalies.push(AnomalyReport::new(Anomaly::PermissionChanged {
+                        expected: baseline_entry.mode,
+                        actual: current_entry.mode,
+                    }, path));
                 }
                 if current_entry.uid != baseline_entry.uid {
-                    anomalies.push(format!("UID_CHANGED: {} ({} != {})",
-                        path, baseline_entry.uid, current_entry.uid));
+                    anomalies.push(AnomalyReport::new(Anomaly::UidChanged {
+                        expected: baseline_entry.uid,
+                        actual: current_entry.uid,
+                    }, path));
                 }
                 if current_entry.gid != baseline_entry.gid {
-                    anomalies.push(format!("GID_CHANGED: {} ({} != {})",
-                        path, baseline_entry.gid, current_entry.gid));
+                    anomalies.push(AnomalyReport::new(Anomaly::GidChanged {
+                        expected: baseline_entry.gid,
+                        actual: current_entry.gid,
+                    }, path));
+                }
+                if let Some((recorded, current_xattrs)) = baseline_entry.xattrs.as_ref().zip(current_entry.xattrs.as_ref()) {
+                    for change in xattr_changes(recorded, current_xattrs) {
+                        anomalies.push(AnomalyReport::new(change.into(), path));
+                    }
+                }
+                // Size, mtime and link count only when the baseline recorded them
+                if let Some((expected, actual)) = baseline_entry.size.zip(current_entry.size).filter(|(expected, actual)| expected != actual) {
+                    anomalies.push(AnomalyReport::new(Anomaly::SizeChanged { expected, actual }, path));
+                }
+                if let Some((expected, actual)) = baseline_entry.nlink.zip(current_entry.nlink).filter(|(expected, actual)| expected != actual) {
+                    anomalies.push(AnomalyReport::new(Anomaly::LinkCountChanged { expected, actual }, path));
+                }
+                // New content has a new mtime; only a changed mtime on the same content is reported
+                let mtimes = baseline_entry.mtime.zip(current_entry.mtime).filter(|(expected, actual)| expected != actual);
+                if let Some((expected, actual)) = mtimes.filter(|_| content_change.is_none()) {
+                    anomalies.push(AnomalyReport::new(Anomaly::MtimeChanged { expected, actual }, path));
                 }
             }
             None => {
                 // File deleted
-                anomalies.push(format!("DELETED: {}", path));
+                anomalies.push(AnomalyReport::new(Anomaly::Deleted { reason: None }, path));
             }
         }
     }
 
     // Check for added files
-    for (path, _) in current {
-        if !baseline_map.contains_key(path) {
-            anomalies.push(format!("ADDED: {}", path));
+    for path in current.keys() {
+        if !baseline.contains(path) {
+            anomalies.push(AnomalyReport::new(Anomaly::Added, path));
         }
     }
 
     anomalies
 }
 
-async fn verify_file(path: &Path, baseline_map: &HashMap<String, &FileIntegrityEntry>) -> Option<String> {
-    let relative_path = path.strip_prefix("/").unwrap_or(path).to_string_lossy().to_string();
+/// Builds the configured external anomaly outputs, reporting to `report_to`
+/// with --report-anomalies.
+fn build_sinks(args: &Args, report_to: Option<&output::service::ReportTarget>) -> Result<Vec<Box<dyn AnomalySink>>> {
+    let mut sinks: Vec<Box<dyn AnomalySink>> = Vec::new();
+    if let Some(target) = report_to.filter(|_| args.report_anomalies) {
+        sinks.push(Box::new(output::service::ServiceOutput::new(target.clone())));
+    }
+    if let Some(format) = args.output_format {
+        sinks.push(Box::new(output::stdout::StdoutOutput::new(format)));
+    }
+    if let Some(target) = &args.wazuh_output {
+        sinks.push(Box::new(output::wazuh::WazuhOutput::new(target.clone(), args.mode == RunMode::Monitor)));
+    }
+    if let Some(target) = &args.falco_output {
+        sinks.push(Box::new(output::falco::FalcoOutput::new(target.clone())));
+    }
+    if let Some(path) = &args.response_hooks {
+        sinks.push(Box::new(output::hooks::HookRunner::load(path, args.hook_concurrency)?));
+    }
+    if !args.remount.remount_ro.is_empty() {
+        sinks.push(Box::new(output::remount::ReadOnlyRemount::new(&args.remount, &host_root(args))));
+    }
+    Ok(sinks)
+}
+
+/// Builds the configured custom verifiers.
+fn build_verifiers(args: &Args) -> Result<verifier::VerifierRegistry> {
+    let timeout = std::time::Duration::from_secs(args.verifier_timeout);
+    let mut verifiers = verifier::VerifierRegistry::default();
+    #[cfg(feature = "allowlist-verifier")]
+    if let Some(url) = &args.allowlist_url {
+        let allowlist = verifier::allowlist::AllowlistVerifier::new(url, timeout)
+            .map_err(|e| IntegrityError::Storage(format!("Allowlist client: {}", e)))?;
+        verifiers.register(Box::new(allowlist));
+    }
+    for program in &args.verifier_plugin {
+        verifiers.register(Box::new(verifier::process::ProcessVerifier::new(program.clone(), timeout)));
+    }
+    Ok(verifiers)
+}
 
-    match baseline_map.get(&relative_path) {
+/// Enrichers of ADDED and MODIFIED anomalies.
+async fn build_enrichers(args: &Args, client: &MetadataClient) -> Result<enrich::EnricherRegistry> {
+    let mut enrichers = enrich::EnricherRegistry::default();
+    if let Some(scanner) = args.yara.scanner(client, &args.state_dir).await? {
+        enrichers.register(Box::new(scanner));
+    }
+    if let Some(reputation) = args.reputation.reputation(&args.state_dir)? {
+        enrichers.register(Box::new(reputation));
+    }
+    Ok(enrichers)
+}
+
+async fn emit_to_sinks(sinks: &[Box<dyn AnomalySink>], anomaly: &AnomalyReport) {
+    for sink in sinks {
+        if let Err(e) = sink.emit(anomaly).await {
+            warn!("Failed to forward anomaly: {}", e);
+        }
+    }
+}
+
+/// Verifies a single file. `root` is the prefix under which the baselined
+/// filesystem is visible ("/" normally, the hostPath mount in --k8s mode).
+async fn verify_file(path: &Path, root: &Path, baseline: &BaselineIndex, hashes: Option<&hashcache::HashCache>) -> Option<AnomalyReport> {
+    check_file(path, root, baseline, hashes)
+}
+
+/// [`verify_file`] for blocking contexts, such as the verification workers.
+fn check_file(path: &Path, root: &Path, baseline: &BaselineIndex, hashes: Option<&hashcache::HashCache>) -> Option<AnomalyReport> {
+    let relative_path = path.strip_prefix(root).unwrap_or(path).to_string_lossy().to_string();
+
+    match baseline.get(&relative_path) {
         Some(baseline_entry) => {
             // File exists in baseline, check integrity
-            match fs::metadata(path) {
+            match fs::symlink_metadata(path) {
                 Ok(metadata) => {
+                    // A symlink is only compared by its target, as in full scans
+                    let is_symlink = metadata.file_type().is_symlink();
+                    if is_symlink || baseline_entry.is_symlink() {
+                        let target = match is_symlink.then(|| symlink_target(path)).transpose() {
+                            Ok(target) => target,
+                            Err(e) => return Some(AnomalyReport::new(Anomaly::HashError { error: e.to_string() }, relative_path)),
+                        };
+                        if baseline_entry.entry_type.is_some() && target != baseline_entry.symlink_target {
+                            return Some(AnomalyReport::new(Anomaly::SymlinkTargetChanged {
+                                expected: baseline_entry.symlink_target.clone(),
+                                actual: target,
+                            }, relative_path));
+                        }
+                        return None;
+                    }
+
+                    let meta = platform::file_meta(&metadata);
                     // Check permissions
-                    if metadata.mode() & 0o7777 != baseline_entry.mode {
-                        return Some(format!("PERMISSION_CHANGED: {} ({} != {})",
-                            relative_path, format!("{:o}", baseline_entry.mode), format!("{:o}", metadata.mode() & 0o7777)));
+                    if meta.mode != baseline_entry.mode {
+                        return Some(AnomalyReport::new(Anomaly::PermissionChanged {
+                            expected: baseline_entry.mode,
+                            actual: meta.mode,
+                        }, relative_path));
+                    }
+                    if meta.uid != baseline_entry.uid {
+                        return Some(AnomalyReport::new(Anomaly::UidChanged {
+                            expected: baseline_entry.uid,
+                            actual: meta.uid,
+                        }, relative_path));
                     }
-                    if metadata.uid() != baseline_entry.uid {
-                        return Some(format!("UID_CHANGED: {} ({} != {})",
-                            relative_path, baseline_entry.uid, metadata.uid()));
+                    if meta.gid != baseline_entry.gid {
+                        return Some(AnomalyReport::new(Anomaly::GidChanged {
+                            expected: baseline_entry.gid,
+                            actual: meta.gid,
+                        }, relative_path));
+                    }
+                    if let Some((recorded, current)) = baseline_entry.xattrs.as_ref().zip(read_xattrs(path).ok()) {
+                        if let Some(change) = xattr_changes(recorded, &current).into_iter().next() {
+                            return Some(AnomalyReport::new(change.into(), relative_path));
+                        }
                     }
-                    if metadata.gid() != baseline_entry.gid {
-                        return Some(format!("GID_CHANGED: {} ({} != {})",
-                            relative_path, baseline_entry.gid, metadata.gid()));
+                    // A different size or link count needs no hashing
+                    if let Some(expected) = baseline_entry.size.filter(|size| *size != meta.size) {
+                        return Some(AnomalyReport::new(Anomaly::SizeChanged { expected, actual: meta.size }, relative_path));
                     }
+                    if let Some((expected, actual)) = baseline_entry.nlink.zip(meta.nlink).filter(|(expected, actual)| expected != actual) {
+                        return Some(AnomalyReport::new(Anomaly::LinkCountChanged { expected, actual }, relative_path));
+                    }
+                    // Metadata-only entries have no content to check
+                    let (algorithm, expected) = baseline_entry.content_digest(baseline.hash_algorithm())?;
 
                     // Check hash
-                    match compute_sha512(path) {
-                        Ok(sha512) => {
-                            if sha512 != baseline_entry.sha512 {
-                                return Some(format!("MODIFIED: {} (hash mismatch: {} != {})",
-                                    relative_path, baseline_entry.sha512, sha512));
+                    let digest = match hashes {
+                        Some(cache) => cache.digest(path, &metadata, algorithm),
+                        None => compute_digest(path, algorithm),
+                    };
+                    match digest {
+                        Ok(actual) => {
+                            if actual != expected {
+                                return Some(AnomalyReport::new(Anomaly::Modified {
+                                    expected_sha512: expected.to_string(),
+                                    actual_sha512: actual,
+                                }, relative_path));
+                            }
+                            if let Some(expected) = baseline_entry.mtime.filter(|mtime| *mtime != meta.mtime) {
+                                return Some(AnomalyReport::new(Anomaly::MtimeChanged { expected, actual: meta.mtime }, relative_path));
                             }
                         }
                         Err(e) => {
-                            return Some(format!("ERROR_HASHING: {} ({})", relative_path, e));
+                            return Some(AnomalyReport::new(Anomaly::HashError { error: e.to_string() }, relative_path));
                         }
                     }
                 }
                 Err(e) => {
-                    return Some(format!("DELETED: {} ({})", relative_path, e));
+                    return Some(AnomalyReport::new(Anomaly::Deleted { reason: Some(e.to_string()) }, relative_path));
                 }
             }
         }
         None => {
             // File not in baseline, this is an addition
-            return Some(format!("ADDED: {}", relative_path));
+            return Some(AnomalyReport::new(Anomaly::Added, relative_path));
         }
     }
     None
 }
 
+/// Options that need the whole baseline, which `--remote-lookup` does not fetch.
+fn remote_lookup_conflicts(args: &Args) -> Vec<&'static str> {
+    let mut conflicts = Vec::new();
+    if args.mode != RunMode::Monitor {
+        conflicts.push("modes other than monitor");
+    }
+    if !args.overlay.is_empty() {
+        conflicts.push("--overlay");
+    }
+    if args.kernel_modules {
+        conflicts.push("--kernel-modules");
+    }
+    if args.enumerate_persistence {
+        conflicts.push("--enumerate-persistence");
+    }
+    if !args.self_check.self_check_paths.is_empty() {
+        conflicts.push("--self-check-paths");
+    }
+    if args.self_check.baseline_digest.is_some() {
+        conflicts.push("--baseline-digest");
+    }
+    #[cfg(all(target_os = "linux", feature = "ebpf-lsm"))]
+    if args.lsm.lsm_enforce {
+        conflicts.push("--lsm-enforce");
+    }
+    #[cfg(all(target_os = "linux", feature = "ebpf-exec"))]
+    if args.exec.exec_monitor {
+        conflicts.push("--exec-monitor");
+    }
+    conflicts
+}
+
+/// The digest cache in the state dir, unless disabled. Like the history it
+/// is best effort: without it every check hashes the file.
+fn open_hash_cache(args: &Args) -> Option<hashcache::HashCache> {
+    if args.hash_cache.no_hash_cache {
+        return None;
+    }
+    match hashcache::HashCache::open(&args.state_dir, &args.hash_cache) {
+        Ok(cache) => Some(cache),
+        Err(e) => {
+            warn!("Hash cache disabled, cannot open it in {:?}: {}", args.state_dir, e);
+            None
+        }
+    }
+}
+
+/// Paths of the planted decoy files, which are not in the baseline.
+fn decoy_paths(args: &Args) -> std::collections::HashSet<String> {
+    #[cfg(target_os = "linux")]
+    return decoy::paths(&args.state_dir);
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = args;
+        Default::default()
+    }
+}
+
+/// The anomaly policy, evaluated with what it knows of this host.
+fn build_policy(args: &Args, baseline: &Baseline, k8s: Option<&K8sContext>) -> Result<Option<policy::PolicyEngine>> {
+    let host = policy::HostContext {
+        hostname: k8s.map_or_else(remote_config::host_name, |ctx| ctx.node_name.clone()),
+        image_id: baseline.image_id.clone(),
+        mode: format!("{:?}", args.mode),
+    };
+    policy::PolicyEngine::new(&args.anomaly_policy, host)
+}
+
+/// What the policy decides for `anomaly`; without one, sinks apply their own rules.
+async fn decide(policy: Option<&policy::PolicyEngine>, anomaly: &AnomalyReport) -> policy::Action {
+    let Some(policy) = policy else {
+        return policy::Action::Remediate;
+    };
+    let action = policy.evaluate(anomaly).await;
+    if action == policy::Action::Ignore {
+        info!("Policy ignores {}: {}", anomaly.kind(), anomaly.path);
+    }
+    action
+}
+
+/// Forwards an anomaly to the sinks the policy's `action` calls for.
+async fn emit_for_action(sinks: &[Box<dyn AnomalySink>], anomaly: &AnomalyReport, action: policy::Action) {
+    for sink in sinks {
+        if action == policy::Action::Alert && sink.remediates() {
+            continue;
+        }
+        if let Err(e) = sink.emit(anomaly).await {
+            warn!("Failed to forward anomaly: {}", e);
+        }
+    }
+}
+
+/// Records an anomaly locally and forwards it to the configured outputs,
+/// unless the policy ignores it or the agent is in maintenance mode. Returns
+/// the policy's action.
+async fn report_anomaly(
+    anomaly: &AnomalyReport,
+    history: Option<&state::StateStore>,
+    sinks: &[Box<dyn AnomalySink>],
+    maintenance: &control::Maintenance,
+    summary: &RunSummary,
+    policy: Option<&policy::PolicyEngine>,
+) -> policy::Action {
+    let action = decide(policy, anomaly).await;
+    if action == policy::Action::Ignore {
+        return action;
+    }
+    summary.count(anomaly);
+    if let Some(store) = history {
+        if let Err(e) = store.record_anomaly(anomaly) {
+            warn!("Failed to record anomaly in history: {}", e);
+        }
+    }
+    if maintenance.current(anomaly.timestamp).is_some() {
+        info!("Not alerting during maintenance: {}", anomaly);
+        // Planned changes do not stop monitoring either
+        return action.min(policy::Action::Alert);
+    }
+    emit_for_action(sinks, anomaly, action).await;
+    action
+}
+
+/// What to re-scan after the monitor dropped events under `event.path`
+/// (empty: anywhere), and the MONITOR_OVERFLOW anomalies reporting it.
+fn overflowed(event: &monitor::FileEvent, root: &Path, rescan_paths: &[PathBuf]) -> (Vec<PathBuf>, Vec<AnomalyReport>) {
+    let targets = if event.path.as_os_str().is_empty() { rescan_paths.to_vec() } else { vec![event.path.clone()] };
+    let anomalies = targets
+        .iter()
+        .map(|target| {
+            let relative = target.strip_prefix(root).unwrap_or(target).to_string_lossy();
+            AnomalyReport::other("MONITOR_OVERFLOW", relative, "events dropped, re-scanning")
+        })
+        .collect();
+    (targets, anomalies)
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn run_monitor_mode(
     args: &Args,
     baseline: &Baseline,
+    remote_lookup: Option<Arc<remote_lookup::RemoteLookup>>,
+    scan_path: &Path,
+    k8s: Option<&K8sContext>,
+    report_to: &output::service::ReportTarget,
+    enrichers: enrich::EnricherRegistry,
+    mut config_changes: Option<tokio::sync::mpsc::Receiver<EffectiveAgentConfig>>,
+    policy: &BTreeMap<String, String>,
+    summary: &RunSummary,
 ) -> Result<()> {
-    info!("Starting integrity agent in MONITOR mode");
+    info!("Starting integrity agent in {:?} mode", args.mode);
     info!("Watch paths: {:?}", args.watch_paths);
 
-    let baseline_map: HashMap<String, &FileIntegrityEntry> = baseline.entries
-        .iter()
-        .map(|entry| (entry.path.clone(), entry))
-        .collect();
+    // In a DaemonSet the host paths are only reachable below the hostPath mount
+    let (root, watch_paths) = match k8s {
+        Some(ctx) => (
+            ctx.host_root.clone(),
+            args.watch_paths.iter().map(|p| ctx.to_container_path(p)).collect(),
+        ),
+        None => (PathBuf::from(platform::FILESYSTEM_ROOT), args.watch_paths.clone()),
+    };
+
+    // Loaded modules are checked against their files, so watch those as well
+    #[cfg(target_os = "linux")]
+    let watch_paths = {
+        let mut watch_paths = watch_paths;
+        if args.kernel_modules {
+            if let Some(release) = kmod::kernel_release() {
+                watch_paths.extend(kmod::module_dirs(&release).iter().map(|dir| root.join(dir)).filter(|dir| dir.is_dir()));
+            }
+        }
+        watch_paths
+    };
+    let rescan_paths: Vec<PathBuf> = watch_paths.clone();
+
+    let baseline_index = Arc::new(BaselineIndex::new(baseline).with_bloom_filter(0.01));
+
+    let sinks = build_sinks(args, Some(report_to))?;
+    let anomaly_policy = build_policy(args, baseline, k8s)?;
+    let verifiers = build_verifiers(args)?;
+    let metrics = Arc::new(pipeline::Metrics::default());
+    prometheus::METRICS.monitor(metrics.clone());
+
+    // History is best effort: a read-only or missing state dir must not stop monitoring
+    let history = match state::StateStore::open(&args.state_dir) {
+        Ok(store) => Some(store),
+        Err(e) => {
+            warn!("Anomaly history disabled, cannot open state store in {:?}: {}", args.state_dir, e);
+            None
+        }
+    };
+
+    let hashes = open_hash_cache(args).map(Arc::new);
+
+    let maintenance = Arc::new(control::Maintenance::default());
+    let (control_tx, mut control_rx) = tokio::sync::mpsc::channel(8);
+    if !args.control.control_socket.as_os_str().is_empty() {
+        let state = Arc::new(control::ControlState {
+            mode: format!("{:?}", args.mode),
+            image_id: baseline.image_id.clone(),
+            baseline_entries: baseline.entries.len(),
+            started_at: chrono::Utc::now().timestamp(),
+            watch_paths: args.watch_paths.clone(),
+            state_dir: args.state_dir.clone(),
+            metrics: metrics.clone(),
+            maintenance: maintenance.clone(),
+            policy: policy.clone(),
+            actions: control_tx,
+        });
+        // Monitoring goes on without it, e.g. when /run is read-only
+        if let Err(e) = control::serve(&args.control.control_socket, state) {
+            warn!("Control socket {:?} disabled: {}", args.control.control_socket, e);
+        }
+    }
+
+    if let Some(socket_path) = &args.osquery_socket {
+        let state = Arc::new(osquery::QueryState::new(baseline_index.clone(), metrics.clone(), args.state_dir.clone()));
+        osquery::serve(socket_path, state)?;
+    }
+
+    for watch_path in args.watch_paths.iter().filter(|_| remote_lookup.is_none()) {
+        if !baseline_index.has_entries_under(&watch_path.to_string_lossy()) {
+            warn!("Watch path {:?} has no baselined files, every event under it will be reported as ADDED", watch_path);
+        }
+    }
 
     // Create monitor based on OS
     #[cfg(target_os = "linux")]
-    let mut monitor = {
+    let mut monitor: Box<dyn Monitor> = {
         use crate::fanotify_monitor::FanotifyMonitor;
-        FanotifyMonitor::new(args.watch_paths.clone())
+        use crate::inotify_monitor::InotifyMonitor;
+        match args.monitor_backend {
+            MonitorBackend::Inotify => Box::new(InotifyMonitor::new(watch_paths.clone(), args.pipeline.event_channel_capacity)),
+            _ => Box::new(FanotifyMonitor::new(watch_paths.clone(), args.pipeline.event_channel_capacity)),
+        }
     };
 
-    #[cfg(not(target_os = "linux"))]
+    #[cfg(windows)]
     let mut monitor = {
-        crate::monitor::MockMonitor::new(5) // 5 second interval for testing
+        use crate::usn_monitor::UsnMonitor;
+        UsnMonitor::new(watch_paths, args.pipeline.event_channel_capacity)
     };
 
-    let mut event_rx = monitor.start().await.map_err(|e| {
+    #[cfg(target_os = "macos")]
+    let mut monitor = {
+        use crate::fsevents_monitor::FsEventsMonitor;
+        FsEventsMonitor::new(watch_paths, args.pipeline.event_channel_capacity)
+    };
+
+    #[cfg(any(target_os = "freebsd", target_os = "openbsd", target_os = "netbsd", target_os = "dragonfly"))]
+    let mut monitor = {
+        use crate::kqueue_monitor::KqueueMonitor;
+        KqueueMonitor::new(watch_paths, args.pipeline.event_channel_capacity)
+    };
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "macos",
+        windows,
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    )))]
+    let mut monitor = {
+        crate::monitor::MockMonitor::new(5, args.pipeline.event_channel_capacity) // 5 second interval for testing
+    };
+
+    let started = monitor.start().await;
+    #[cfg(target_os = "linux")]
+    let started = match started {
+        Err(e) if args.monitor_backend == MonitorBackend::Auto => {
+            warn!("Cannot monitor with fanotify ({}), falling back to inotify", e);
+            monitor = Box::new(crate::inotify_monitor::InotifyMonitor::new(watch_paths, args.pipeline.event_channel_capacity));
+            monitor.start().await
+        }
+        started => started,
+    };
+    let mut event_rx = started.map_err(|e| {
         IntegrityError::Storage(format!("Failed to start monitor: {}", e))
     })?;
     info!("Monitor started, waiting for events...");
 
+    // Hybrid mode re-scans on a schedule; any mode re-scans after the monitor drops events
+    let hybrid = args.mode == RunMode::Hybrid;
+    if hybrid {
+        info!("Re-scanning watch paths every {:?}", args.rescan.rescan_interval);
+    }
+    let (rescanner, mut rescan_rx) =
+        rescan::spawn(&args.rescan, rescan_paths.clone(), root.clone(), baseline_index.clone(), hybrid);
+
+    // Checks of system state that is not a file event report anomalies here
+    let (check_tx, mut check_rx) = tokio::sync::mpsc::channel::<AnomalyReport>(args.pipeline.event_channel_capacity);
+    #[cfg(target_os = "linux")]
+    if args.kernel_modules {
+        kmod::spawn(baseline_index.clone(), root.clone(), KERNEL_MODULE_POLL_INTERVAL, check_tx.clone());
+    }
+    #[cfg(target_os = "linux")]
+    if !baseline.sysctls.is_empty() {
+        sysctl::spawn(baseline.sysctls.clone(), SYSCTL_POLL_INTERVAL, check_tx.clone());
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let (cmdline, index, root, boot_args, tx) =
+            (baseline.kernel_cmdline.clone(), baseline_index.clone(), root.clone(), args.boot.clone(), check_tx.clone());
+        tokio::spawn(async move {
+            for anomaly in boot::verify(&cmdline, &index, &root, &boot_args).await {
+                let _ = tx.send(anomaly).await;
+            }
+        });
+    }
+    #[cfg(target_os = "linux")]
+    if !baseline.listeners.is_empty() {
+        listeners::spawn(baseline.listeners.clone(), baseline_index.clone(), LISTENER_POLL_INTERVAL, check_tx.clone());
+    }
+    // Enforcement is an addition to monitoring, which goes on without it
+    #[cfg(all(target_os = "linux", feature = "ebpf-lsm"))]
+    if args.lsm.lsm_enforce {
+        match lsm::Enforcer::attach(&baseline_index, &root, &args.lsm) {
+            Ok(enforcer) => {
+                info!("eBPF LSM enforcement protecting {} baselined files", enforcer.protected_files());
+                lsm::spawn(enforcer, LSM_POLL_INTERVAL, check_tx.clone());
+            }
+            Err(e) => error!("eBPF LSM enforcement not enabled: {}", e),
+        }
+    }
+    #[cfg(all(target_os = "linux", feature = "ebpf-exec"))]
+    let exec_monitor = if !args.exec.exec_monitor {
+        None
+    } else {
+        match lsm::exec::ExecMonitor::attach(&baseline_index, &root, hashes.as_deref(), &args.exec) {
+            Ok(exec_monitor) => {
+                info!("eBPF execution monitoring of {} baselined executables", exec_monitor.baselined_executables());
+                lsm::exec::spawn(exec_monitor.clone(), LSM_POLL_INTERVAL, check_tx.clone());
+                Some(exec_monitor)
+            }
+            Err(e) => {
+                error!("eBPF execution monitoring not enabled: {}", e);
+                None
+            }
+        }
+    };
+    #[cfg(target_os = "linux")]
+    if !args.decoy.decoys.is_empty() {
+        let (decoys, tampered) = decoy::plant_all(&args.decoy, &root, &args.state_dir)?;
+        for anomaly in tampered {
+            let _ = check_tx.send(anomaly).await;
+        }
+        decoy::spawn(decoys, root.clone(), check_tx.clone());
+    }
+    // Hybrid mode starts with a full scan, for what changed while the agent was not running
+    if hybrid {
+        let scan_config = ScanConfig::load(args.scan_config.as_deref())?;
+        let mac_policy = verify_mac_policy(baseline);
+        let (scan_path, index, hashes, decoys, tx) =
+            (scan_path.to_path_buf(), baseline_index.clone(), hashes.clone(), decoy_paths(args), check_tx.clone());
+        tokio::task::spawn_blocking(move || {
+            let started = std::time::Instant::now();
+            let current = match scan_filesystem(&scan_path, &scan_config, index.hash_algorithm(), hashes.as_deref()) {
+                Ok(current) => current,
+                Err(e) => {
+                    error!("Initial full scan of {:?} failed: {}", scan_path, e);
+                    return;
+                }
+            };
+            prometheus::METRICS.scan_finished(&current, started.elapsed());
+            let mut anomalies = compare_filesystems(&index, &current);
+            anomalies.retain(|anomaly| !decoys.contains(&anomaly.path));
+            anomalies.extend(mac_policy);
+            info!("Initial full scan of {} files finished in {:?}, {} anomalies", current.len(), started.elapsed(), anomalies.len());
+            for anomaly in anomalies.into_iter().map(classify::classify) {
+                if tx.blocking_send(anomaly).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+    drop(check_tx);
+    let decoy_paths = decoy_paths(args);
+
     let mut consecutive_anomalies = 0;
+    // Enumeration runs on every persistence anomaly; each finding is reported once
+    let mut reported_persistence = std::collections::HashSet::new();
+    // Likewise for account and trust store changes, which every later write to the same file would repeat
+    let mut reported_changes = std::collections::HashSet::new();
     const MAX_CONSECUTIVE_ANOMALIES: usize = 5;
 
-    while let Some(event) = event_rx.recv().await {
-        tracing::debug!("Received event: {:?}", event);
+    // Per-path verification budgets and re-verification of changed and anomalous paths
+    let mut scheduler = schedule::Scheduler::new(&args.schedule);
+    let mut scheduled = std::collections::VecDeque::new();
+    let mut schedule_tick = tokio::time::interval(SCHEDULE_TICK);
+
+    // Files are verified concurrently; results come back in completion order
+    let entries = match remote_lookup {
+        Some(remote) => pipeline::Entries::Remote(remote),
+        None => pipeline::Entries::Local(baseline_index.clone()),
+    };
+    info!("Verifying up to {} files at once", args.pipeline.workers());
+    let (mut pipeline, mut verified_rx) =
+        pipeline::Pipeline::new(&args.pipeline, root.clone(), entries, hashes, verifiers, enrichers, metrics.clone());
+    let mut metrics_tick = (!args.pipeline.metrics_interval.is_zero()).then(|| tokio::time::interval(args.pipeline.metrics_interval));
 
-        if let Some(anomaly) = verify_file(&event.path, &baseline_map).await {
-            warn!("ANOMALY DETECTED: {}", anomaly);
+    enum Next {
+        Event(monitor::FileEvent),
+        Verified(pipeline::Verified),
+    }
+    // Set when changed remote settings or a baseline reload call for a restart
+    let mut restart_reason = None;
+    let mut failed_closed = false;
+
+    loop {
+        let next = match scheduled.pop_front() {
+            Some(event) => Next::Event(event),
+            None => tokio::select! {
+                Some(verified) = verified_rx.recv() => Next::Verified(verified),
+                event = event_rx.recv() => match event {
+                    Some(event) => Next::Event(event),
+                    None => break,
+                },
+                Some(event) = rescan_rx.recv() => Next::Event(event),
+                Some(anomaly) = check_rx.recv() => {
+                    warn!("ANOMALY DETECTED: {}", anomaly);
+                    let action = report_anomaly(&anomaly, history.as_ref(), &sinks, &maintenance, summary, anomaly_policy.as_ref()).await;
+                    if action == policy::Action::FailClosed {
+                        error!("Policy requires failing closed on {}", anomaly);
+                        failed_closed = true;
+                        break;
+                    }
+                    continue;
+                }
+                _ = schedule_tick.tick() => {
+                    let due = scheduler.due(std::time::Instant::now());
+                    scheduled.extend(due.into_iter().map(|path| monitor::FileEvent { path, event_type: EventType::Rescan }));
+                    continue;
+                }
+                _ = async { metrics_tick.as_mut().unwrap().tick().await }, if metrics_tick.is_some() => {
+                    let snapshot: Vec<String> = pipeline.metrics().snapshot().iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+                    info!("Verification metrics: {}", snapshot.join(" "));
+                    continue;
+                }
+                Some(config) = async { config_changes.as_mut().unwrap().recv().await }, if config_changes.is_some() => {
+                    restart_reason = Some(format!("apply remote settings {}", config.revision()));
+                    break;
+                }
+                Some(action) = control_rx.recv() => match action {
+                    control::ControlAction::Rescan(paths) => {
+                        let targets = if paths.is_empty() { rescan_paths.clone() } else { paths.iter().map(|path| root.join(path)).collect() };
+                        rescanner.request(targets);
+                        continue;
+                    }
+                    control::ControlAction::ReloadBaseline => {
+                        restart_reason = Some("reload the baseline".to_string());
+                        break;
+                    }
+                },
+            },
+        };
+        let (event, anomaly) = match next {
+            Next::Event(event) => {
+                if matches!(event.event_type, EventType::Overflow) {
+                    let (targets, anomalies) = overflowed(&event, &root, &rescan_paths);
+                    for anomaly in &anomalies {
+                        warn!("{}", anomaly);
+                        // Not a property of any file, so there is nothing to resolve in the history
+                        report_anomaly(anomaly, None, &sinks, &maintenance, summary, anomaly_policy.as_ref()).await;
+                    }
+                    rescanner.request(targets);
+                    continue;
+                }
+                tracing::debug!("Received {:?} event for {:?}", event.event_type, event.path);
+                if event.event_type != EventType::Rescan && !scheduler.admit(&event.path, std::time::Instant::now()) {
+                    tracing::debug!("Verification of {:?} deferred, over its budget", event.path);
+                    continue;
+                }
+                pipeline.submit(event).await;
+                continue;
+            }
+            Next::Verified(verified) => {
+                pipeline.finished(&verified.event.path).await;
+                (verified.event, verified.anomaly)
+            }
+        };
+        let rescanned = matches!(event.event_type, EventType::Rescan);
+        let relative_path = event.path.strip_prefix(&root).unwrap_or(&event.path).to_string_lossy();
+        // Decoys are not baselined; their watcher reports on them
+        if decoy_paths.contains(relative_path.as_ref()) {
+            continue;
+        }
+
+        let was_anomalous = scheduler.is_anomalous(&event.path);
+        scheduler.record(&event.path, anomaly.is_some(), !rescanned, std::time::Instant::now());
+        #[cfg(all(target_os = "linux", feature = "ebpf-exec"))]
+        if let Some(exec_monitor) = &exec_monitor {
+            exec_monitor.verified(&event.path, &relative_path, anomaly.is_none());
+        }
+
+        if let Some(anomaly) = anomaly.map(classify::classify) {
+            if rescanned {
+                // Re-scans keep finding what is already open; report only what the monitor missed
+                if was_anomalous || history.as_ref().is_some_and(|store| store.is_open(&relative_path)) {
+                    continue;
+                }
+                warn!("ANOMALY DETECTED by re-scan: {}", anomaly);
+            } else {
+                warn!("ANOMALY DETECTED: {}", anomaly);
+            }
+            let mut action = report_anomaly(&anomaly, history.as_ref(), &sinks, &maintenance, summary, anomaly_policy.as_ref()).await;
+            if args.enumerate_persistence && classify::category(&anomaly) == Some("PERSISTENCE_MECHANISM") {
+                for enabled in persistence::enumerate(&root, &baseline_index) {
+                    if reported_persistence.insert(enabled.to_string()) {
+                        warn!("ANOMALY DETECTED: {}", enabled);
+                        action = action.max(report_anomaly(&enabled, history.as_ref(), &sinks, &maintenance, summary, anomaly_policy.as_ref()).await);
+                    }
+                }
+            }
+            for change in describe_changes(baseline, &root, &relative_path) {
+                if reported_changes.insert(change.to_string()) {
+                    warn!("ANOMALY DETECTED: {}", change);
+                    action = action.max(report_anomaly(&change, history.as_ref(), &sinks, &maintenance, summary, anomaly_policy.as_ref()).await);
+                }
+            }
+            if action == policy::Action::FailClosed {
+                error!("Policy requires failing closed on {}", anomaly);
+                failed_closed = true;
+                break;
+            }
+            // Anomalies the policy ignores do not add up to a burst either
+            if action == policy::Action::Ignore {
+                continue;
+            }
+            // A re-scan working through a backlog is not a burst of live tampering, nor is planned maintenance
+            if rescanned || maintenance.current(chrono::Utc::now().timestamp()).is_some() {
+                continue;
+            }
             consecutive_anomalies += 1;
 
             if consecutive_anomalies >= MAX_CONSECUTIVE_ANOMALIES {
                 error!("Too many consecutive anomalies detected ({}). Triggering fail-closed.", consecutive_anomalies);
-                // In a real implementation, this would trigger emergency mode or shutdown
-                // For now, we just exit with an error
-                std::process::exit(1);
+                // Stop monitoring; the anomalies found make the run fail
+                failed_closed = true;
+                break;
             }
         } else {
-            consecutive_anomalies = 0; // Reset on successful verification
+            if !rescanned {
+                consecutive_anomalies = 0; // Reset on successful verification
+            }
+            if let Some(store) = &history {
+                match store.resolve_path(&relative_path, chrono::Utc::now().timestamp()) {
+                    Ok(true) => info!("{} matches the baseline again, anomalies resolved", relative_path),
+                    Ok(false) => {}
+                    Err(e) => warn!("Failed to update anomaly history: {}", e),
+                }
+            }
         }
     }
 
-    info!("Monitor event channel closed");
+    if restart_reason.is_none() && !failed_closed {
+        info!("Monitor event channel closed");
+    }
+    if let Some(store) = &history {
+        store.flush()?;
+    }
     monitor.stop().await.map_err(|e| {
         IntegrityError::Storage(format!("Failed to stop monitor: {}", e))
     })?;
+    if let Some(reason) = restart_reason {
+        info!("Restarting to {}", reason);
+        for sink in &sinks {
+            sink.flush().await;
+        }
+        return Err(IntegrityError::Io(restart()));
+    }
+    for sink in &sinks {
+        sink.flush().await;
+    }
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
+async fn run_agent(
+    args: &Args,
+    client: &MetadataClient,
+    image_id: &str,
+    k8s: Option<&K8sContext>,
+    config_changes: Option<tokio::sync::mpsc::Receiver<EffectiveAgentConfig>>,
+    policy: &BTreeMap<String, String>,
+    summary: &RunSummary,
+) -> Result<()> {
+    let scan_path = match k8s {
+        Some(ctx) => ctx.host_root.clone(),
+        None => args.scan_path.clone(),
+    };
 
-    let args = Args::parse();
+    // Validate scan path exists
+    if !scan_path.exists() {
+        error!("Scan path does not exist: {:?}", scan_path);
+        return Err(IntegrityError::Validation(format!("scan path {:?} does not exist", scan_path)));
+    }
 
-    info!("Starting integrity agent");
-    info!("Mode: {:?}", args.mode);
-    info!("Scan path: {:?}", args.scan_path);
-    info!("Image ID: {}", args.image_id);
-    info!("Metadata service URL: {}", args.metadata_url);
+    // Fetch baseline from metadata service, with any overlays stacked on top
+    let trusted_pubkeys = trust::baseline_keys(&args.signatures.trusted_pubkey, args.insecure_override)?;
+    let remote_lookup = if args.remote_lookup.remote_lookup {
+        let conflicts = remote_lookup_conflicts(args);
+        if !conflicts.is_empty() {
+            return Err(IntegrityError::Validation(format!("--remote-lookup cannot be used with {}", conflicts.join(", "))));
+        }
+        if !trusted_pubkeys.is_empty() && !args.insecure_override {
+            return Err(IntegrityError::Validation(
+                "--remote-lookup cannot check baseline signatures (use --insecure-override)".to_string(),
+            ));
+        }
+        Some(Arc::new(remote_lookup::RemoteLookup::new(&args.remote_lookup, client.clone(), image_id)))
+    } else {
+        None
+    };
+    let mut baseline = match &remote_lookup {
+        Some(_) => remote_lookup::stand_in(client, image_id).await?,
+        None => baseline_cache::load(&args.baseline_cache, &args.signatures, &trusted_pubkeys, client, image_id).await?,
+    };
+    for overlay in &args.overlay {
+        let layer = baseline_cache::load(&args.baseline_cache, &args.signatures, &trusted_pubkeys, client, overlay).await?;
+        baseline = baseline.overlay(&layer);
+    }
+    if !args.overlay.is_empty() {
+        info!("Verifying against layered baseline {} ({} files)", baseline.image_id, baseline.entries.len());
+    }
+    summary.baseline(&baseline);
 
-    // Validate scan path exists
-    if !args.scan_path.exists() {
-        error!("Scan path does not exist: {:?}", args.scan_path);
-        return Err(integrity_common::IntegrityError::Io(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "Scan path does not exist",
-        )));
+    let host = k8s.map_or_else(remote_config::host_name, |ctx| ctx.node_name.clone());
+    let report_to = output::service::ReportTarget { client: client.clone(), host, image_id: image_id.to_string() };
+    heartbeat::register(&args.heartbeat, client, &report_to.host, image_id, &format!("{:?}", args.mode)).await;
+
+    // Don't trust our own results until our own components check out.
+    // In a DaemonSet the agent binary comes from the container image, not the host.
+    let root = k8s.map_or_else(|| PathBuf::from(platform::FILESYSTEM_ROOT), |ctx| ctx.host_root.clone());
+    let failures = selfcheck::verify(&args.self_check, &baseline, &root, k8s.is_none()).await;
+    if !failures.is_empty() {
+        for failure in &failures {
+            error!("SELF-INTEGRITY CHECK FAILED: {}", failure);
+        }
+        if args.self_check.self_check_failure == selfcheck::FailureAction::Refuse {
+            return Err(IntegrityError::Validation(format!(
+                "agent self-integrity check failed ({} problems)",
+                failures.len()
+            )));
+        }
+        warn!("Continuing in alarm mode, results may not be trustworthy");
+        let sinks = build_sinks(args, Some(&report_to))?;
+        for failure in &failures {
+            emit_to_sinks(&sinks, failure).await;
+        }
     }
 
-    // Fetch baseline from metadata service
-    let baseline = fetch_baseline(&args.metadata_url, &args.image_id).await?;
+    // Forward what the early-boot check found before this agent started
+    if let Some(verdict) = early_boot::take_verdict(&args.early_boot.verdict_file) {
+        if !verdict.ok {
+            let sinks = build_sinks(args, Some(&report_to))?;
+            for anomaly in &verdict.anomalies {
+                error!("EARLY BOOT ANOMALY: {}", anomaly);
+                emit_to_sinks(&sinks, &AnomalyReport::parse(anomaly)).await;
+            }
+            for sink in &sinks {
+                sink.flush().await;
+            }
+        }
+    }
 
-    match args.mode {
-        RunMode::Scan => {
+    let enrichers = build_enrichers(args, client).await?;
+    let anomalies = match args.mode {
+        RunMode::Scan | RunMode::Daemon => {
             info!("Running in SCAN mode");
-            // Scan current filesystem
-            let current_state = scan_filesystem(&args.scan_path)?;
+            let scan_config = ScanConfig::load(args.scan_config.as_deref())?;
+            let started = std::time::Instant::now();
+            // Scan current filesystem, or a snapshot of it
+            #[cfg(target_os = "linux")]
+            let current_state = match fssnapshot::create(&args.fs_snapshot, &scan_path, &args.state_dir)? {
+                Some(snapshot) => snapshot.scan(&scan_path, &scan_config, baseline.hash_algorithm, open_hash_cache(args).as_ref())?,
+                None => scan_filesystem(&scan_path, &scan_config, baseline.hash_algorithm, open_hash_cache(args).as_ref())?,
+            };
+            #[cfg(not(target_os = "linux"))]
+            let current_state = scan_filesystem(&scan_path, &scan_config, baseline.hash_algorithm, open_hash_cache(args).as_ref())?;
+            prometheus::METRICS.scan_finished(&current_state, started.elapsed());
+            if args.upload_snapshot {
+                upload_snapshot(client, report_to.host.clone(), image_id, &current_state).await;
+            }
 
             // Compare and report anomalies
-            let anomalies = compare_filesystems(&baseline, &current_state);
-
-            if anomalies.is_empty() {
-                info!("No anomalies detected. System integrity verified.");
-            } else {
-                warn!("Integrity check failed! Found {} anomalies:", anomalies.len());
-                for anomaly in &anomalies {
-                    warn!("  {}", anomaly);
+            let index = BaselineIndex::new(&baseline);
+            let mut anomalies = compare_filesystems(&index, &current_state);
+            let decoy_paths = decoy_paths(args);
+            anomalies.retain(|anomaly| !decoy_paths.contains(&anomaly.path));
+            let mut enriched = Vec::with_capacity(anomalies.len());
+            for anomaly in anomalies {
+                enriched.push(enrichers.enrich(anomaly, &scan_path).await);
+            }
+            let mut anomalies = enriched;
+            anomalies.extend(verify_mac_policy(&baseline));
+            #[cfg(target_os = "linux")]
+            {
+                anomalies.extend(sysctl::check(&baseline.sysctls));
+                if !baseline.listeners.is_empty() {
+                    anomalies.extend(listeners::check(&baseline.listeners, &index, &mut Default::default()));
                 }
+                anomalies.extend(boot::verify(&baseline.kernel_cmdline, &index, &root, &args.boot).await);
+            }
+            anomalies
+        }
+        #[cfg(target_os = "linux")]
+        RunMode::PsVerify => {
+            info!("Running in PS-VERIFY mode");
+            procverify::verify_processes(&BaselineIndex::new(&baseline), &args.allowed_preload)
+        }
+        #[cfg(not(target_os = "linux"))]
+        RunMode::PsVerify => {
+            return Err(IntegrityError::Validation("ps-verify mode is only supported on Linux".to_string()));
+        }
+        RunMode::Monitor | RunMode::Hybrid => {
+            return run_monitor_mode(args, &baseline, remote_lookup, &scan_path, k8s, &report_to, enrichers, config_changes, policy, summary).await;
+        }
+    };
 
-                // Exit with error code if anomalies found
-                std::process::exit(1);
+    let mut anomalies: Vec<AnomalyReport> = anomalies.into_iter().map(classify::classify).collect();
+    if args.enumerate_persistence
+        && anomalies.iter().any(|a| classify::category(a) == Some("PERSISTENCE_MECHANISM"))
+    {
+        anomalies.extend(persistence::enumerate(&root, &BaselineIndex::new(&baseline)));
+    }
+    let paths: std::collections::HashSet<String> = anomalies.iter().map(|a| a.path.clone()).collect();
+    if paths.iter().any(|path| is_account_file(path)) {
+        anomalies.extend(accounts::describe(&baseline, &root));
+    }
+    if paths.iter().any(|path| is_trust_store_file(path)) {
+        anomalies.extend(truststore::describe(&baseline, &root));
+    }
+    if anomalies.is_empty() {
+        info!("No anomalies detected. System integrity verified.");
+    } else {
+        warn!("Integrity check failed! Found {} anomalies:", anomalies.len());
+        let sinks = build_sinks(args, Some(&report_to))?;
+        // A scan fails on any anomaly reported, so fail-closed acts like remediate
+        let anomaly_policy = build_policy(args, &baseline, k8s)?;
+        for anomaly in &anomalies {
+            let action = decide(anomaly_policy.as_ref(), anomaly).await;
+            if action == policy::Action::Ignore {
+                continue;
             }
+            warn!("  {}", anomaly);
+            summary.count(anomaly);
+            emit_for_action(&sinks, anomaly, action).await;
         }
-        RunMode::Monitor => {
-            run_monitor_mode(&args, &baseline).await?;
+        for sink in &sinks {
+            sink.flush().await;
         }
     }
 
     Ok(())
 }
+
+/// Uploads the state a scan saw; the scan's verdict does not depend on it.
+async fn upload_snapshot(client: &MetadataClient, host: String, image_id: &str, current_state: &HashMap<String, FileIntegrityEntry>) {
+    let mut entries: Vec<FileIntegrityEntry> = current_state.values().cloned().collect();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let snapshot = ScanSnapshot {
+        host,
+        image_id: image_id.to_string(),
+        timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        entries,
+    };
+    match client.upload_snapshot(&snapshot).await {
+        Ok(info) => info!("Uploaded snapshot {} ({} files)", info.id, info.entries),
+        Err(e) => warn!("Cannot upload the scan snapshot: {}", e),
+    }
+}
+
+/// Where the host filesystem is: the hostPath mount in --k8s mode.
+fn host_root(args: &Args) -> PathBuf {
+    if args.k8s { args.host_root.clone() } else { PathBuf::from(platform::FILESYSTEM_ROOT) }
+}
+
+/// Reports events found before the baseline is known, outside of any scan.
+async fn report_now(args: &Args, events: &[AnomalyReport]) -> Result<()> {
+    let sinks = build_sinks(args, None)?;
+    for event in events {
+        emit_to_sinks(&sinks, event).await;
+    }
+    for sink in &sinks {
+        sink.flush().await;
+    }
+    Ok(())
+}
+
+/// Baseline of the booted ostree deployment, reporting a deployment whose
+/// commit does not verify.
+#[cfg(target_os = "linux")]
+async fn resolve_ostree_baseline(args: &Args, client: &MetadataClient) -> Result<String> {
+    let root = host_root(args);
+    let deployment = ostree::booted(&root)?;
+    if let Some(anomaly) = deployment.verify(&root) {
+        error!("OSTREE DEPLOYMENT ANOMALY: {}", anomaly);
+        report_now(args, &[anomaly]).await?;
+    }
+    let image_id = client.resolve_image(&deployment.mapping_key()).await?;
+    info!("ostree commit {} maps to baseline {}", deployment.commit, image_id);
+    Ok(image_id)
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn resolve_ostree_baseline(_args: &Args, _client: &MetadataClient) -> Result<String> {
+    Err(IntegrityError::Validation("--image-id ostree is only supported on Linux".to_string()))
+}
+
+/// `integrity-agent history`: prints recorded anomalies.
+fn print_history(state_dir: &Path, args: &HistoryArgs) -> Result<()> {
+    let query = state::HistoryQuery {
+        since: args.since.map(|since| chrono::Utc::now().timestamp() - since.as_secs() as i64),
+        path: args.path.clone(),
+        unresolved_only: args.unresolved,
+    };
+    let records = state::read_history(state_dir, &query)?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&records)?);
+        return Ok(());
+    }
+
+    let format_time = |time: i64| {
+        chrono::DateTime::from_timestamp(time, 0)
+            .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+            .unwrap_or_else(|| time.to_string())
+    };
+    for record in &records {
+        let status = match record.resolved_at {
+            Some(time) => format!("resolved {}", format_time(time)),
+            None => "open".to_string(),
+        };
+        println!(
+            "{}  {:<20} /{}  [{}]  {}",
+            format_time(record.time),
+            record.kind,
+            record.path.trim_start_matches('/'),
+            status,
+            record.detail
+        );
+    }
+    println!("{} anomalies", records.len());
+    Ok(())
+}
+
+/// Flags whose values are secrets, redacted by `effective_flags`.
+const SECRET_FLAGS: &[&str] = &["api-token"];
+
+/// The value of every flag `command_line` gives or defaults, for the control socket's policy dump.
+fn effective_flags(command_line: &[OsString]) -> BTreeMap<String, String> {
+    use clap::CommandFactory;
+    let command = Args::command();
+    let Ok(matches) = command.clone().try_get_matches_from(command_line) else {
+        return BTreeMap::new();
+    };
+    command
+        .get_arguments()
+        .filter_map(|arg| {
+            let flag = arg.get_long()?;
+            let values = matches.get_raw(arg.get_id().as_str())?;
+            let value = if SECRET_FLAGS.contains(&flag) {
+                "<redacted>".to_string()
+            } else {
+                values.map(|value| value.to_string_lossy()).collect::<Vec<_>>().join(",")
+            };
+            Some((flag.to_string(), value))
+        })
+        .collect()
+}
+
+/// Replaces the process with a new run of the same command line, which
+/// fetches the baseline and remote settings afresh. Only returns if that fails.
+#[cfg(unix)]
+fn restart() -> std::io::Error {
+    use std::os::unix::process::CommandExt;
+    let program = match std::env::current_exe() {
+        Ok(program) => program,
+        Err(e) => return e,
+    };
+    std::process::Command::new(program).args(std::env::args_os().skip(1)).exec()
+}
+
+#[cfg(not(unix))]
+fn restart() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Unsupported, "the agent cannot restart itself on this platform")
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    // Anomaly records keep stdout to themselves
+    let logging = tracing_subscriber::fmt().with_env_filter(tracing_subscriber::EnvFilter::from_default_env());
+    if args.output_format.is_some() {
+        logging.with_writer(std::io::stderr).init();
+    } else {
+        logging.init();
+    }
+
+    if let Some(Command::History(history_args)) = &args.command {
+        return print_history(&args.state_dir, history_args);
+    }
+
+    let summary_file = args.summary_file.clone().unwrap_or_else(|| args.state_dir.join(summary::SUMMARY_FILE));
+    let mode = if args.early_boot.early_boot { "EarlyBoot".to_string() } else { format!("{:?}", args.mode) };
+    if let Some(addr) = args.prometheus.metrics_listen {
+        // Like the control socket, metrics are not worth failing the run over
+        if let Err(e) = prometheus::serve(addr).await {
+            warn!("Prometheus metrics disabled, cannot listen on {}: {}", addr, e);
+        }
+    }
+    if args.mode == RunMode::Daemon && !args.early_boot.early_boot {
+        run_daemon(args, summary_file, mode).await;
+    }
+    let summary = RunSummary::new(summary_file, mode);
+    let (outcome, error) = match run(args, &summary).await {
+        Ok(()) => (summary.outcome(), None),
+        Err(e) => {
+            error!("{}", e);
+            (summary.outcome_of(&e), Some(e))
+        }
+    };
+    let code = summary.finish(outcome, error.as_ref());
+    heartbeat::run_finished(outcome, summary.anomalies()).await;
+    std::process::exit(code);
+}
+
+/// Runs scan passes on the daemon schedule, each with its own summary,
+/// until one fails on the configuration.
+async fn run_daemon(args: Args, summary_file: PathBuf, mode: String) -> ! {
+    loop {
+        let started = chrono::Local::now();
+        let summary = RunSummary::new(summary_file.clone(), mode.clone());
+        let (outcome, error) = match run(args.clone(), &summary).await {
+            Ok(()) => (summary.outcome(), None),
+            Err(e) => {
+                error!("Scan pass failed: {}", e);
+                (summary.outcome_of(&e), Some(e))
+            }
+        };
+        let code = summary.finish(outcome, error.as_ref());
+        heartbeat::run_finished(outcome, summary.anomalies()).await;
+        if outcome == summary::Outcome::ConfigError {
+            std::process::exit(code);
+        }
+        let wait = args.daemon.next_pass(started, chrono::Local::now());
+        info!("Scan pass finished ({:?}), next one in {}", outcome, humantime::format_duration(std::time::Duration::from_secs(wait.as_secs())));
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// One scan, monitoring run or early-boot check; its outcome is in `summary`.
+async fn run(mut args: Args, summary: &RunSummary) -> Result<()> {
+    // Runs before anything touches the network
+    if args.early_boot.early_boot {
+        let verdict = early_boot::run(&args.early_boot, &args.self_check)?;
+        for anomaly in &verdict.anomalies {
+            summary.count(&AnomalyReport::parse(anomaly));
+        }
+        return Ok(());
+    }
+
+    // Required unless a subcommand or --early-boot was given
+    let image_id_arg = args.image_id.clone().unwrap_or_default();
+
+    info!("Starting integrity agent");
+    info!("Mode: {:?}", args.mode);
+    info!("Scan path: {:?}", args.scan_path);
+    info!("Image ID: {}", image_id_arg);
+
+    let mut client_config = ClientConfig::from(&args.client);
+    trust::apply(&mut client_config, args.client.metadata_url.as_deref(), args.insecure_override)?;
+    let client = MetadataClient::new(client_config)?;
+    info!("Metadata service URL: {}", client.base_url());
+
+    // Resolve the baseline from the cloud image when asked to
+    let (image_id, identity) = if image_id_arg == "auto" {
+        let identity = cloud::detect_identity(args.cloud_provider).await?;
+        let image_id = client.resolve_image(&identity.mapping_key()).await?;
+        info!("Cloud image {} maps to baseline {}", identity.mapping_key(), image_id);
+        (image_id, Some(identity))
+    } else if image_id_arg == "ostree" {
+        (resolve_ostree_baseline(&args, &client).await?, None)
+    } else if image_id_arg == "os-release" {
+        let version = osrelease::active(&host_root(&args))?;
+        let image_id = client.resolve_image(&version.mapping_key()).await?;
+        info!("{} maps to baseline {}", version.mapping_key(), image_id);
+        (image_id, None)
+    } else {
+        (image_id_arg, None)
+    };
+
+    // Settings managed on the service override the command line
+    let mut command_line: Vec<OsString> = std::env::args_os().collect();
+    let config_changes = if args.remote.remote_config {
+        let host = remote_config::host_name();
+        let applied = remote_config::fetch(&client, &host, &image_id, &args.state_dir).await.unwrap_or_default();
+        if !applied.settings.is_empty() {
+            let remote_line = remote_config::override_args(command_line.clone(), &applied.settings);
+            match Args::try_parse_from(&remote_line) {
+                Ok(remote) => {
+                    args = remote;
+                    command_line = remote_line;
+                    info!("Applied remote settings {}", applied.revision());
+                }
+                Err(e) => warn!("Cannot apply remote settings {}: {}", applied.revision(), e),
+            }
+        }
+        let interval = args.remote.remote_config_interval;
+        matches!(args.mode, RunMode::Monitor | RunMode::Hybrid)
+            .then(|| remote_config::watch(client.clone(), host, image_id.clone(), args.state_dir.clone(), applied, interval))
+    } else {
+        None
+    };
+    let policy = effective_flags(&command_line);
+
+    // A host that came up on a new image version needs the new baseline, not
+    // an anomaly for every file the update changed
+    match state::switch_baseline(&args.state_dir, &image_id) {
+        Ok(Some(previous)) => {
+            let event = AnomalyReport::other("BASELINE_SWITCHED", &image_id, format!("was {}", previous)).with_severity(Severity::Info);
+            info!("{}", event);
+            report_now(&args, &[event]).await?;
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Cannot record the active baseline in {:?}: {}", args.state_dir, e),
+    }
+
+    // Every log line, including anomalies, carries the instance identity
+    let instance_span = match &identity {
+        Some(identity) => tracing::info_span!(
+            "instance",
+            provider = %identity.provider,
+            instance_id = %identity.instance_id,
+            cloud_image = %identity.image,
+            region = identity.region.as_deref().unwrap_or("unknown"),
+        ),
+        None => tracing::Span::none(),
+    };
+
+    if args.k8s {
+        let ctx = K8sContext::detect(&args.host_root, args.cluster_name.clone())?;
+        info!("Running as DaemonSet on node {} (host root {:?})", ctx.node_name, ctx.host_root);
+
+        // ...and the cluster/node labels
+        let span = tracing::info_span!(
+            "k8s",
+            cluster = ctx.cluster_name.as_deref().unwrap_or("unknown"),
+            node = %ctx.node_name,
+            namespace = ctx.namespace.as_deref().unwrap_or("unknown"),
+            pod = ctx.pod_name.as_deref().unwrap_or("unknown"),
+        );
+        run_agent(&args, &client, &image_id, Some(&ctx), config_changes, &policy, summary)
+            .instrument(span)
+            .instrument(instance_span)
+            .await
+    } else {
+        run_agent(&args, &client, &image_id, None, config_changes, &policy, summary).instrument(instance_span).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::test_util::{BaselineBuilder, Drift};
+    use integrity_common::EntryType;
+    use sha2::{Digest, Sha512};
+
+    fn as_map(entries: Vec<FileIntegrityEntry>) -> HashMap<String, FileIntegrityEntry> {
+        entries.into_iter().map(|e| (e.path.clone(), e)).collect()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_fanotify_overflow_rescans() {
+        let size = std::mem::size_of::<libc::fanotify_event_metadata>();
+        let metadata = libc::fanotify_event_metadata {
+            event_len: size as u32,
+            vers: libc::FANOTIFY_METADATA_VERSION,
+            reserved: 0,
+            metadata_len: size as u16,
+            mask: libc::FAN_Q_OVERFLOW,
+            fd: libc::FAN_NOFD,
+            pid: 0,
+        };
+        let buffer = unsafe { std::slice::from_raw_parts((&metadata as *const libc::fanotify_event_metadata).cast::<u8>(), size) };
+        let events = fanotify_monitor::parse_events(buffer, &[PathBuf::from("/host/etc")], 1, &mut HashMap::new(), std::time::Instant::now()).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, EventType::Overflow);
+
+        // Dropped anywhere, so every watched tree is re-scanned
+        let rescan_paths = [PathBuf::from("/host/etc"), PathBuf::from("/host/usr/bin")];
+        let (targets, anomalies) = overflowed(&events[0], Path::new("/host"), &rescan_paths);
+        assert_eq!(targets, rescan_paths);
+        assert_eq!(
+            anomalies.iter().map(|anomaly| (anomaly.kind(), anomaly.path.as_str())).collect::<Vec<_>>(),
+            [("MONITOR_OVERFLOW", "etc"), ("MONITOR_OVERFLOW", "usr/bin")]
+        );
+    }
+
+    #[test]
+    fn test_remote_settings_and_effective_flags() {
+        let command_line = ["integrity-agent", "--image-id", "app-v1", "--watch-paths", "/etc", "--falco-output", "stdout"];
+        let settings = [("watch-paths", "/etc,/opt/app"), ("kernel-modules", "true"), ("rescan-interval", "30m")]
+            .into_iter()
+            .map(|(flag, value)| (flag.to_string(), value.to_string()))
+            .collect();
+        let args = Args::try_parse_from(remote_config::override_args(command_line.map(OsString::from), &settings)).unwrap();
+        assert_eq!(args.watch_paths, vec![PathBuf::from("/etc"), PathBuf::from("/opt/app")]);
+        assert!(args.kernel_modules);
+        assert_eq!(args.rescan.rescan_interval, std::time::Duration::from_secs(1800));
+        assert_eq!(args.image_id.as_deref(), Some("app-v1"));
+        assert!(args.falco_output.is_some());
+
+        let flags = effective_flags(&["integrity-agent", "--image-id", "app-v1", "--api-token", "s3cret"].map(OsString::from));
+        assert_eq!(flags["image-id"], "app-v1");
+        assert_eq!(flags["api-token"], "<redacted>");
+        assert_eq!(flags["rescan-interval"], "6h");
+    }
+
+    #[test]
+    fn test_compare_identical_filesystem() {
+        let baseline = BaselineBuilder::new("img").size(200).build();
+        let current = as_map(baseline.entries.clone());
+        assert!(compare_filesystems(&BaselineIndex::new(&baseline), &current).is_empty());
+    }
+
+    #[test]
+    fn test_compare_reports_injected_drift() {
+        let baseline = BaselineBuilder::new("img").size(200).build();
+        let drift = Drift {
+            modified: 3,
+            added: 2,
+            deleted: 4,
+            permissions: 1,
+            ownership: 5,
+        };
+        let current = as_map(drift.apply(&baseline));
+        let anomalies = compare_filesystems(&BaselineIndex::new(&baseline), &current);
+
+        assert_eq!(anomalies.len(), drift.expected_anomalies());
+        assert_eq!(anomalies.iter().filter(|a| matches!(a.anomaly, Anomaly::Modified { .. })).count(), 3);
+        assert_eq!(anomalies.iter().filter(|a| a.anomaly == Anomaly::Added).count(), 2);
+        assert_eq!(anomalies.iter().filter(|a| matches!(a.anomaly, Anomaly::Deleted { .. })).count(), 4);
+    }
+
+    #[test]
+    fn test_compare_stat_metadata() {
+        let mut baseline = BaselineBuilder::new("img").size(4).build();
+        // The first entry was collected before stat metadata and attributes were recorded
+        for entry in baseline.entries.iter_mut().skip(1) {
+            (entry.size, entry.mtime, entry.nlink, entry.xattrs) = (Some(100), Some(1_700_000_000), Some(1), Some(BTreeMap::new()));
+        }
+        let mut current = baseline.entries.clone();
+        (current[0].size, current[0].mtime, current[0].nlink) = (Some(5), Some(1), Some(3));
+        current[0].xattrs = Some([("user.x".to_string(), "00".to_string())].into());
+        // Truncated, touched and hard-linked
+        (current[1].sha512, current[1].size, current[1].mtime) = ("0".repeat(128), Some(0), Some(1_800_000_000));
+        current[2].mtime = Some(1_500_000_000);
+        current[3].nlink = Some(2);
+        // cap_net_raw=ep granted
+        current[3].xattrs = Some([("security.capability".to_string(), "0100000200200000000000000000000000000000".to_string())].into());
+
+        let anomalies = compare_filesystems(&BaselineIndex::new(&baseline), &as_map(current));
+        assert!(anomalies.iter().any(|a| a.detail() == "security.capability: (none) != cap_net_raw=ep"));
+        let mut anomalies: Vec<&str> = anomalies.iter().map(|a| a.kind()).collect();
+        anomalies.sort();
+        assert_eq!(anomalies, ["LINK_COUNT_CHANGED", "MODIFIED", "MTIME_CHANGED", "SIZE_CHANGED", "XATTR_CHANGED"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks() {
+        use std::os::unix::fs::symlink;
+        let root = std::env::temp_dir().join(format!("acropole-symlinks-{}", std::process::id()));
+        fs::create_dir_all(root.join("usr/bin")).unwrap();
+        fs::write(root.join("usr/bin/vim.basic"), "vim").unwrap();
+        symlink("vim.basic", root.join("usr/bin/vi")).unwrap();
+        symlink("/nonexistent", root.join("usr/bin/dangling")).unwrap();
+        symlink("usr/bin", root.join("bin")).unwrap();
+        let scan = || scan_filesystem(&root, &ScanConfig::default(), HashAlgorithm::Sha512, None).unwrap();
+
+        let scanned = scan();
+        let vi = &scanned["usr/bin/vi"];
+        assert!(vi.is_symlink());
+        assert_eq!(vi.symlink_target.as_deref(), Some("vim.basic"));
+        assert_eq!(vi.sha512, hex::encode(Sha512::digest(b"vim.basic")));
+        assert_eq!(scanned["usr/bin/dangling"].symlink_target.as_deref(), Some("/nonexistent"));
+        assert_eq!(scanned["bin"].symlink_target.as_deref(), Some("usr/bin"));
+        assert_eq!(scanned["usr/bin/vim.basic"].entry_type, Some(EntryType::Regular));
+
+        let baseline = Baseline {
+            image_id: "img".to_string(),
+            timestamp: "t".to_string(),
+            entries: scanned.values().cloned().collect(),
+            ..Default::default()
+        };
+        let index = BaselineIndex::new(&baseline);
+        fs::remove_file(root.join("usr/bin/vi")).unwrap();
+        symlink("/tmp/.x/vi", root.join("usr/bin/vi")).unwrap();
+        let anomalies = compare_filesystems(&index, &scan());
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(
+            anomalies[0].anomaly,
+            Anomaly::SymlinkTargetChanged { expected: Some("vim.basic".to_string()), actual: Some("/tmp/.x/vi".to_string()) }
+        );
+
+        // Monitor mode: a symlink replaced by a file
+        fs::remove_file(root.join("usr/bin/vi")).unwrap();
+        fs::write(root.join("usr/bin/vi"), "vim").unwrap();
+        let anomaly = check_file(&root.join("usr/bin/vi"), &root, &index, None).unwrap();
+        assert_eq!(anomaly.anomaly, Anomaly::SymlinkTargetChanged { expected: Some("vim.basic".to_string()), actual: None });
+        assert!(check_file(&root.join("usr/bin/dangling"), &root, &index, None).is_none());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_config() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = std::env::temp_dir().join(format!("acropole-scan-config-{}", std::process::id()));
+        fs::create_dir_all(root.join("var/lib/db")).unwrap();
+        fs::create_dir_all(root.join("var/cache")).unwrap();
+        fs::create_dir_all(root.join("tmp")).unwrap();
+        fs::write(root.join("var/lib/db/data"), "rows").unwrap();
+        fs::write(root.join("var/cache/x"), "x").unwrap();
+        fs::write(root.join("tmp/y"), "y").unwrap();
+        let config = ScanConfig::parse(
+            "exclude = [\"var/cache\"]\n[[policy]]\npaths = [\"var/lib/db/**\"]\ncontent = \"metadata-only\"\n",
+        )
+        .unwrap();
+
+        let scanned = scan_filesystem(&root, &config, HashAlgorithm::Sha512, None).unwrap();
+        let mut paths: Vec<&str> = scanned.keys().map(String::as_str).collect();
+        paths.sort();
+        assert_eq!(paths, ["tmp/y", "var/lib/db/data"]);
+        let data = &scanned["var/lib/db/data"];
+        assert!(!data.is_hashed());
+        assert_eq!((data.size, data.mtime), (None, None));
+
+        // Content changes of metadata-only files are not reported, other changes are
+        let baseline = BaselineIndex::from_entries(scanned.into_values());
+        fs::write(root.join("var/lib/db/data"), "more rows").unwrap();
+        assert!(compare_filesystems(&baseline, &scan_filesystem(&root, &config, HashAlgorithm::Sha512, None).unwrap()).is_empty());
+        assert!(check_file(&root.join("var/lib/db/data"), &root, &baseline, None).is_none());
+        fs::set_permissions(root.join("var/lib/db/data"), fs::Permissions::from_mode(0o666)).unwrap();
+        let anomaly = check_file(&root.join("var/lib/db/data"), &root, &baseline, None).unwrap();
+        assert_eq!(anomaly.kind(), "PERMISSION_CHANGED");
+        fs::remove_dir_all(root).unwrap();
+    }
+}
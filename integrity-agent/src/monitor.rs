@@ -9,12 +9,15 @@ pub struct FileEvent {
     pub event_type: EventType,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code)] // Not every backend produces every event type
 pub enum EventType {
     Modified,
     Created,
     Deleted,
     Accessed, // For execution events
+    Rescan,   // Synthetic, from a rolling re-scan in hybrid mode
+    Overflow, // The backend dropped events under `path` (empty: anywhere)
 }
 
 /// Trait for file system monitors.
@@ -27,26 +30,29 @@ pub trait Monitor: Send + Sync {
     async fn stop(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
 }
 
-/// Mock monitor for development/testing on non-Linux systems.
+/// Mock monitor for platforms without a native backend.
 /// Generates synthetic events for testing.
+#[allow(dead_code)] // Only built into the agent on those platforms
 pub struct MockMonitor {
     interval_secs: u64,
+    channel_capacity: usize,
 }
 
+#[allow(dead_code)]
 impl MockMonitor {
-    pub fn new(interval_secs: u64) -> Self {
-        Self { interval_secs }
+    pub fn new(interval_secs: u64, channel_capacity: usize) -> Self {
+        Self { interval_secs, channel_capacity }
     }
 }
 
 #[async_trait]
 impl Monitor for MockMonitor {
     async fn start(&mut self) -> Result<mpsc::Receiver<FileEvent>, Box<dyn std::error::Error + Send + Sync>> {
-        let (tx, rx) = mpsc::channel(100);
+        let (tx, rx) = mpsc::channel(self.channel_capacity);
         let interval = self.interval_secs;
 
         tokio::spawn(async move {
-            let test_paths = vec![
+            let test_paths = [
                 PathBuf::from("/etc/passwd"),
                 PathBuf::from("/bin/ls"),
                 PathBuf::from("/usr/bin/python3"),
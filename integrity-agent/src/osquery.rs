@@ -0,0 +1,173 @@
+//! Local socket backing osquery virtual tables.
+//!
+//! An osquery extension connects to the Unix socket, writes one JSON request line
+//! such as `{"table": "integrity_anomalies"}`, and reads back a JSON array of rows
+//! whose values are all strings, as osquery table plugins expect. Supported tables:
+//!
+//! - `integrity_baseline_entries`: path, sha512, mode, uid, gid
+//! - `integrity_anomalies`: time, kind, path, detail, resolved_at (empty while
+//!   open), read from the anomaly history in the state dir, so it lists what
+//!   earlier runs found as well
+//! - `integrity_agent_metrics`: metric, value (monitor mode verification counters)
+
+use crate::pipeline::Metrics;
+use crate::state::{self, HistoryQuery};
+use integrity_common::BaselineIndex;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+#[cfg(unix)]
+use {
+    serde::Deserialize,
+    tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    tokio::net::{UnixListener, UnixStream},
+    tracing::{debug, info, warn},
+};
+
+/// Agent state exposed to osquery.
+pub struct QueryState {
+    baseline: Arc<BaselineIndex>,
+    metrics: Arc<Metrics>,
+    /// Where the anomaly history is kept
+    state_dir: PathBuf,
+}
+
+impl QueryState {
+    pub fn new(baseline: Arc<BaselineIndex>, metrics: Arc<Metrics>, state_dir: PathBuf) -> Self {
+        Self { baseline, metrics, state_dir }
+    }
+
+    fn rows(&self, table: &str) -> Result<Vec<Value>, String> {
+        match table {
+            "integrity_baseline_entries" => Ok(
+                self.baseline
+                    .entries()
+                    .map(|e| {
+                        json!({
+                            "path": e.path,
+                            "sha512": e.sha512,
+                            "mode": format!("{:o}", e.mode),
+                            "uid": e.uid.to_string(),
+                            "gid": e.gid.to_string(),
+                        })
+                    })
+                    .collect(),
+            ),
+            "integrity_anomalies" => Ok(
+                state::read_history(&self.state_dir, &HistoryQuery::default())
+                    .map_err(|e| format!("reading the anomaly history: {}", e))?
+                    .into_iter()
+                    .map(|record| {
+                        json!({
+                            "time": record.time.to_string(),
+                            "kind": record.kind,
+                            "path": record.path,
+                            "detail": record.detail,
+                            "resolved_at": record.resolved_at.map(|time| time.to_string()).unwrap_or_default(),
+                        })
+                    })
+                    .collect(),
+            ),
+            "integrity_agent_metrics" => Ok(
+                self.metrics
+                    .snapshot()
+                    .into_iter()
+                    .map(|(metric, value)| json!({ "metric": metric, "value": value.to_string() }))
+                    .collect(),
+            ),
+            _ => Err(format!("unknown table: {}", table)),
+        }
+    }
+}
+
+#[cfg(unix)]
+#[derive(Debug, Deserialize)]
+struct TableRequest {
+    table: String,
+}
+
+#[cfg(unix)]
+async fn handle_connection(stream: UnixStream, state: Arc<QueryState>) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+
+    let response = match serde_json::from_str::<TableRequest>(&line) {
+        Ok(request) => match state.rows(&request.table) {
+            Ok(rows) => Value::Array(rows),
+            Err(e) => json!({ "error": e }),
+        },
+        Err(e) => json!({ "error": format!("invalid request: {}", e) }),
+    };
+
+    writer.write_all(response.to_string().as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.shutdown().await
+}
+
+/// Binds the socket and serves table requests in the background.
+#[cfg(unix)]
+pub fn serve(socket_path: &Path, state: Arc<QueryState>) -> std::io::Result<()> {
+    // A stale socket from a previous run would make bind fail
+    if socket_path.exists() {
+        std::fs::remove_file(socket_path)?;
+    }
+    let listener = UnixListener::bind(socket_path)?;
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    info!("Serving osquery tables on {:?}", socket_path);
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(stream, state).await {
+                            debug!("osquery connection error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("osquery socket accept failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn serve(_socket_path: &Path, _state: Arc<QueryState>) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "osquery tables are served over a Unix socket, which this platform lacks",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::test_util::BaselineBuilder;
+    use integrity_common::AnomalyReport;
+
+    #[test]
+    fn test_anomalies_from_history() {
+        let dir = std::env::temp_dir().join(format!("acropole-osquery-{}", std::process::id()));
+        let store = state::StateStore::open(&dir).unwrap();
+        let mut passwd = AnomalyReport::parse("MODIFIED: etc/passwd (hash mismatch)");
+        passwd.timestamp = 1_000;
+        store.record_anomaly(&passwd).unwrap();
+        store.resolve_path("etc/passwd", 2_000).unwrap();
+        store.record_anomaly(&AnomalyReport::parse("ADDED: tmp/x")).unwrap();
+
+        let baseline = Arc::new(BaselineIndex::new(&BaselineBuilder::new("img").size(1).build()));
+        let state = QueryState::new(baseline, Arc::new(Metrics::default()), dir.clone());
+        let rows = state.rows("integrity_anomalies").unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(rows.len(), 2);
+        assert_eq!((rows[0]["kind"].as_str(), rows[0]["resolved_at"].as_str()), (Some("MODIFIED"), Some("2000")));
+        assert_eq!(rows[1]["resolved_at"], "");
+        assert!(state.rows("integrity_missing").is_err());
+    }
+}
@@ -0,0 +1,86 @@
+//! A/B updated hosts (Flatcar, Talos-like): the baseline follows the OS
+//! version in os-release.
+//!
+//! These hosts update by writing the new image to the inactive partition and
+//! rebooting into it, so the running version only changes with a reboot, and
+//! with it every file of /usr. With `--image-id os-release` the agent reads
+//! the active version at startup and resolves its baseline through the
+//! service's mapping table (`os-release:<ID>:<VERSION_ID>`).
+
+use integrity_common::{IntegrityError, Result};
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+/// os-release locations, in the order the specification reads them.
+const OS_RELEASE: &[&str] = &["etc/os-release", "usr/lib/os-release"];
+const PROC_CMDLINE: &str = "/proc/cmdline";
+/// Kernel arguments naming the /usr partition booted from, e.g. Flatcar's "mount.usr=PARTLABEL=USR-B".
+const USR_PARTITION_ARGS: &[&str] = &["verity.usr", "mount.usr", "usr"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OsVersion {
+    pub id: String,
+    pub version_id: String,
+}
+
+impl OsVersion {
+    /// Key used in the service's image mapping table.
+    pub fn mapping_key(&self) -> String {
+        format!("os-release:{}:{}", self.id, self.version_id)
+    }
+}
+
+fn parse_os_release(content: &str) -> Option<OsVersion> {
+    let field = |name: &str| {
+        content.lines().find_map(|line| {
+            let value = line.trim().strip_prefix(name)?.strip_prefix('=')?;
+            Some(value.trim_matches(|c| c == '"' || c == '\'').to_string())
+        })
+    };
+    Some(OsVersion { id: field("ID")?, version_id: field("VERSION_ID")? })
+}
+
+/// The partition /usr was booted from, if the kernel command line names one.
+fn usr_partition(cmdline: &str) -> Option<&str> {
+    USR_PARTITION_ARGS.iter().find_map(|name| {
+        cmdline.split_whitespace().find_map(|arg| arg.strip_prefix(name)?.strip_prefix('='))
+    })
+}
+
+/// The OS version `root` is running.
+pub fn active(root: &Path) -> Result<OsVersion> {
+    let (path, content) = OS_RELEASE
+        .iter()
+        .find_map(|path| fs::read_to_string(root.join(path)).ok().map(|content| (path, content)))
+        .ok_or_else(|| IntegrityError::Validation(format!("No os-release file under {:?}", root)))?;
+    let version = parse_os_release(&content)
+        .ok_or_else(|| IntegrityError::Validation(format!("{} has no ID and VERSION_ID", path)))?;
+    let cmdline = fs::read_to_string(PROC_CMDLINE).unwrap_or_default();
+    info!(
+        "Running {} {} from {}",
+        version.id,
+        version.version_id,
+        usr_partition(&cmdline).unwrap_or("an unnamed partition")
+    );
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_os_release() {
+        let flatcar = "NAME=\"Flatcar Container Linux by Kinvolk\"\nID=flatcar\nID_LIKE=coreos\nVERSION_ID=3815.2.0\n";
+        let version = parse_os_release(flatcar).unwrap();
+        assert_eq!(version.mapping_key(), "os-release:flatcar:3815.2.0");
+        // ID_LIKE and VERSION don't shadow ID and VERSION_ID
+        let talos = "VERSION=\"v1.7.0\"\nVERSION_ID='v1.7.0'\nID_LIKE=linux\nID=talos\n";
+        assert_eq!(parse_os_release(talos).unwrap().mapping_key(), "os-release:talos:v1.7.0");
+        assert!(parse_os_release("ID=flatcar\n").is_none());
+
+        assert_eq!(usr_partition("root=LABEL=ROOT mount.usr=PARTLABEL=USR-B ro"), Some("PARTLABEL=USR-B"));
+        assert_eq!(usr_partition("root=LABEL=ROOT usrquota"), None);
+    }
+}
@@ -0,0 +1,127 @@
+//! OSTree / rpm-ostree hosts (Fedora CoreOS, RHEL for Edge).
+//!
+//! The OS of an ostree host is a commit checked out as a deployment, so the
+//! baseline follows the commit: with `--image-id ostree` the agent finds the
+//! booted deployment through the `ostree=` kernel argument, resolves its
+//! commit through the service's mapping table (`ostree:<commit checksum>`)
+//! and verifies against that. An upgrade only takes effect with a reboot into
+//! the new deployment, and the agent resolves the baseline again at every
+//! start, so the baseline switches with the deployment instead of the new
+//! commit being reported file by file against the old one.
+//!
+//! The commit checksum is the SHA-256 of the commit object in the local
+//! repository, which is checked before it is trusted.
+
+use integrity_common::{AnomalyReport, IntegrityError, Result};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Created by the initramfs on hosts booted into an ostree deployment.
+const BOOTED_MARKER: &str = "run/ostree-booted";
+const PROC_CMDLINE: &str = "/proc/cmdline";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deployment {
+    pub osname: String,
+    /// Commit checksum (SHA-256, hex)
+    pub commit: String,
+    pub serial: u32,
+    /// Deployment directory, under `root`
+    pub path: PathBuf,
+}
+
+impl Deployment {
+    /// Key used in the service's image mapping table.
+    pub fn mapping_key(&self) -> String {
+        format!("ostree:{}", self.commit)
+    }
+
+    /// Anomaly if the commit object in the repository does not hash to the
+    /// deployed checksum, or the running root is not the deployment.
+    pub fn verify(&self, root: &Path) -> Option<AnomalyReport> {
+        let object = format!("ostree/repo/objects/{}/{}.commit", &self.commit[..2], &self.commit[2..]);
+        match fs::read(root.join(&object)) {
+            Ok(content) => {
+                let actual = hex::encode(Sha256::digest(&content));
+                if actual != self.commit {
+                    return Some(AnomalyReport::other("OSTREE_COMMIT_MISMATCH", object, format!("hashes to {}", actual)));
+                }
+            }
+            Err(e) => return Some(AnomalyReport::other("OSTREE_COMMIT_MISSING", object, e.to_string())),
+        }
+        match (fs::metadata(root), fs::metadata(&self.path)) {
+            (Ok(running), Ok(deployed)) if (running.dev(), running.ino()) != (deployed.dev(), deployed.ino()) => {
+                Some(AnomalyReport::other("OSTREE_DEPLOYMENT_MISMATCH", self.path.to_string_lossy(), "is not the running root"))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn not_ostree(detail: String) -> IntegrityError {
+    IntegrityError::Validation(format!("Cannot determine the booted ostree deployment: {}", detail))
+}
+
+/// Parses ".../deploy/<osname>/deploy/<commit>.<serial>".
+fn parse_deployment_path(path: &Path) -> Option<Deployment> {
+    let mut components = path.iter().rev().map(|c| c.to_str());
+    let (commit, serial) = components.next()??.split_once('.')?;
+    let (deploy, osname, parent) = (components.next()??, components.next()??, components.next()??);
+    if deploy != "deploy" || parent != "deploy" || commit.len() != 64 || !commit.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(Deployment {
+        osname: osname.to_string(),
+        commit: commit.to_ascii_lowercase(),
+        serial: serial.parse().ok()?,
+        path: path.to_path_buf(),
+    })
+}
+
+/// The deployment `root` was booted into.
+pub fn booted(root: &Path) -> Result<Deployment> {
+    if !root.join(BOOTED_MARKER).exists() {
+        return Err(not_ostree(format!("{} does not exist", root.join(BOOTED_MARKER).display())));
+    }
+    let cmdline = fs::read_to_string(PROC_CMDLINE)?;
+    let boot_link = cmdline
+        .split_whitespace()
+        .find_map(|arg| arg.strip_prefix("ostree="))
+        .ok_or_else(|| not_ostree("no ostree= kernel argument".to_string()))?;
+    // e.g. /ostree/boot.1/fedora-coreos/<bootcsum>/0 -> the deployment directory
+    let target = fs::canonicalize(root.join(boot_link.trim_start_matches('/')))?;
+    parse_deployment_path(&target).ok_or_else(|| not_ostree(format!("{} is not a deployment", target.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_booted_deployment_and_commit_verification() {
+        let root = std::env::temp_dir().join(format!("ostree-test-{}", std::process::id()));
+        let commit_object = b"commit variant";
+        let commit = hex::encode(Sha256::digest(commit_object));
+        let deployment_dir = root.join(format!("ostree/deploy/fedora-coreos/deploy/{}.0", commit));
+        fs::create_dir_all(&deployment_dir).unwrap();
+        let objects = root.join(format!("ostree/repo/objects/{}", &commit[..2]));
+        fs::create_dir_all(&objects).unwrap();
+        fs::write(objects.join(format!("{}.commit", &commit[2..])), commit_object).unwrap();
+
+        let deployment = parse_deployment_path(&deployment_dir).unwrap();
+        assert_eq!((deployment.osname.as_str(), deployment.serial), ("fedora-coreos", 0));
+        assert_eq!(deployment.mapping_key(), format!("ostree:{}", commit));
+        // Commit verifies, but the test root is not the deployment
+        assert_eq!(deployment.verify(&root).unwrap().kind(), "OSTREE_DEPLOYMENT_MISMATCH");
+        let running = Deployment { path: root.clone(), ..deployment.clone() };
+        assert_eq!(running.verify(&root), None);
+
+        fs::write(objects.join(format!("{}.commit", &commit[2..])), b"tampered").unwrap();
+        assert_eq!(deployment.verify(&root).unwrap().kind(), "OSTREE_COMMIT_MISMATCH");
+
+        assert!(parse_deployment_path(Path::new("/ostree/deploy/fcos/deploy/abc.0")).is_none());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
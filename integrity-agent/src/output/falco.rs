@@ -0,0 +1,150 @@
+//! Falco-compatible output adapter.
+//!
+//! Anomalies are rendered in Falco's JSON alert format (rule, priority, output,
+//! output_fields, ...) and either printed to stdout, like Falco's `json_output`,
+//! or POSTed to a falcosidekick instance, which routes them unmodified to its
+//! configured outputs (Slack, S3, OpenSearch, ...).
+
+use super::{AnomalySink, AnomalyReport};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::io::Write;
+
+/// Where Falco alerts are written.
+#[derive(Debug, Clone)]
+pub enum FalcoTarget {
+    Stdout,
+    /// Base URL of a falcosidekick instance, e.g. http://falcosidekick:2801
+    Sidekick(String),
+}
+
+impl std::str::FromStr for FalcoTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "stdout" {
+            Ok(Self::Stdout)
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(Self::Sidekick(s.trim_end_matches('/').to_string()))
+        } else {
+            Err(format!("expected stdout or a falcosidekick URL, got {}", s))
+        }
+    }
+}
+
+/// Falco rule name and priority for an anomaly kind.
+fn rule_for(kind: &str) -> (&'static str, &'static str) {
+    match kind {
+        "MODIFIED" | "SIZE_CHANGED" => ("Baseline File Modified", "Critical"),
+        "DELETED" => ("Baseline File Deleted", "Critical"),
+        "ADDED" => ("File Added Outside Baseline", "Warning"),
+        "PERMISSION_CHANGED" => ("Baseline File Permissions Changed", "Error"),
+        "UID_CHANGED" | "GID_CHANGED" => ("Baseline File Ownership Changed", "Error"),
+        "SYMLINK_TARGET_CHANGED" => ("Baseline Symlink Retargeted", "Critical"),
+        "XATTR_CHANGED" => ("Baseline File Extended Attributes Changed", "Error"),
+        "LINK_COUNT_CHANGED" => ("Baseline File Hard Links Changed", "Error"),
+        "MTIME_CHANGED" => ("Baseline File Timestamp Changed", "Warning"),
+        "PRELOAD_INJECTION" => ("Library Preload Injection", "Critical"),
+        "PERSISTENCE_MECHANISM" | "PERSISTENCE_ENABLED" => ("Persistence Mechanism Changed", "Critical"),
+        "MAC_POLICY_CHANGED" => ("MAC Policy Changed", "Critical"),
+        "SYSCTL_CHANGED" => ("Kernel Parameter Changed", "Error"),
+        "WRITE_BLOCKED" => ("Write To Protected File Blocked", "Error"),
+        "BOOT_INTEGRITY" | "BOOT_CMDLINE_CHANGED" | "BOOT_KERNEL_UNKNOWN" | "BOOT_KERNEL_MISMATCH" => ("Boot Chain Changed", "Critical"),
+        "CA_ADDED" | "CA_REMOVED" => ("Trusted CA Changed", "Critical"),
+        "DECOY_ACCESSED" | "DECOY_MODIFIED" | "DECOY_DELETED" => ("Decoy File Touched", "Critical"),
+        "YARA_MATCH" => ("YARA Rule Matched", "Critical"),
+        "KNOWN_BAD" => ("Known Malicious File", "Critical"),
+        "LISTENER_UNEXPECTED" | "LISTENER_BINARY_UNKNOWN" | "LISTENER_BINARY_MODIFIED" => ("Unexpected Listening Socket", "Critical"),
+        "USER_ADDED" | "USER_REMOVED" | "USER_CHANGED" | "GROUP_ADDED" | "GROUP_REMOVED" | "GROUP_CHANGED"
+        | "PASSWORD_CHANGED" | "SUDO_RULE_ADDED" | "SUDO_RULE_REMOVED" => ("Account or Privilege Changed", "Critical"),
+        _ => ("Integrity Check Error", "Notice"),
+    }
+}
+
+fn falco_alert(anomaly: &AnomalyReport, hostname: &str) -> Value {
+    let (rule, priority) = rule_for(anomaly.kind());
+    let time = chrono::DateTime::from_timestamp(anomaly.timestamp, 0)
+        .unwrap_or_default()
+        .to_rfc3339_opts(chrono::SecondsFormat::Nanos, true);
+    let path = anomaly.absolute_path();
+
+    json!({
+        "time": time,
+        "rule": rule,
+        "priority": priority,
+        "source": "acropole",
+        "hostname": hostname,
+        "tags": ["filesystem", "integrity", "acropole"],
+        "output": format!(
+            "{}: {} {} (kind={} file={} detail={})",
+            chrono::DateTime::from_timestamp(anomaly.timestamp, 0).unwrap_or_default().format("%H:%M:%S%.9f"),
+            priority, rule, anomaly.kind(), path, anomaly.detail()
+        ),
+        "output_fields": {
+            "evt.time": anomaly.timestamp as u64 * 1_000_000_000,
+            "fd.name": path,
+            "acropole.kind": anomaly.kind(),
+            "acropole.detail": anomaly.detail(),
+            "acropole.severity": anomaly.severity.name(),
+            "acropole.expected": anomaly.anomaly.expected(),
+            "acropole.actual": anomaly.anomaly.actual(),
+        },
+    })
+}
+
+pub struct FalcoOutput {
+    target: FalcoTarget,
+    hostname: String,
+    http: reqwest::Client,
+}
+
+impl FalcoOutput {
+    pub fn new(target: FalcoTarget) -> Self {
+        Self {
+            target,
+            hostname: hostname::get()
+                .map(|h| h.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            http: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl AnomalySink for FalcoOutput {
+    async fn emit(&self, anomaly: &AnomalyReport) -> std::io::Result<()> {
+        let alert = falco_alert(anomaly, &self.hostname);
+        match &self.target {
+            FalcoTarget::Stdout => {
+                let mut stdout = std::io::stdout().lock();
+                writeln!(stdout, "{}", alert)?;
+            }
+            FalcoTarget::Sidekick(url) => {
+                self.http
+                    .post(url)
+                    .json(&alert)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .map_err(std::io::Error::other)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_falco_alert() {
+        let alert = falco_alert(&AnomalyReport::parse("MODIFIED: usr/bin/ls (hash mismatch: a != b)"), "host-1");
+        assert_eq!(alert["rule"], "Baseline File Modified");
+        assert_eq!(alert["priority"], "Critical");
+        assert_eq!(alert["output_fields"]["fd.name"], "/usr/bin/ls");
+        assert_eq!(alert["hostname"], "host-1");
+        assert_eq!(alert["output_fields"]["acropole.severity"], "high");
+        assert_eq!(alert["output_fields"]["acropole.actual"], "b");
+    }
+}
@@ -0,0 +1,209 @@
+//! Response hooks: external commands run when an anomaly matches a rule.
+//!
+//! Rules are loaded from a JSON file:
+//!
+//! ```json
+//! [
+//!   {
+//!     "name": "quarantine-binaries",
+//!     "kinds": ["MODIFIED", "ADDED"],
+//!     "paths": ["usr/bin/*", "usr/sbin/*"],
+//!     "command": ["/usr/local/sbin/quarantine", "--notify"],
+//!     "timeout_secs": 30
+//!   }
+//! ]
+//! ```
+//!
+//! Empty `kinds` or `paths` match everything; paths are globs relative to the
+//! baselined root. The anomaly is written to the command's stdin as JSON.
+//! Hooks run in the background, at most `concurrency` at a time, and every run
+//! is logged under the `audit` target.
+
+use super::{AnomalySink, AnomalyReport};
+use async_trait::async_trait;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+fn default_timeout() -> u64 {
+    30
+}
+
+#[derive(Debug, Deserialize)]
+struct HookRule {
+    name: String,
+    #[serde(default)]
+    kinds: Vec<String>,
+    #[serde(default)]
+    paths: Vec<String>,
+    command: Vec<String>,
+    #[serde(default = "default_timeout")]
+    timeout_secs: u64,
+}
+
+struct Hook {
+    rule: HookRule,
+    paths: GlobSet,
+}
+
+impl Hook {
+    fn matches(&self, anomaly: &AnomalyReport) -> bool {
+        (self.rule.kinds.is_empty() || self.rule.kinds.iter().any(|k| k == anomaly.kind()))
+            && (self.rule.paths.is_empty() || self.paths.is_match(anomaly.path.trim_start_matches('/')))
+    }
+}
+
+pub struct HookRunner {
+    hooks: Arc<Vec<Hook>>,
+    slots: Arc<Semaphore>,
+    running: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl HookRunner {
+    /// Loads the rules file, failing on unreadable files, bad globs or empty commands.
+    pub fn load(path: &Path, concurrency: usize) -> std::io::Result<Self> {
+        let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+
+        let rules: Vec<HookRule> = serde_json::from_slice(&std::fs::read(path)?)?;
+        let mut hooks = Vec::with_capacity(rules.len());
+        for rule in rules {
+            if rule.command.is_empty() {
+                return Err(invalid(format!("hook {}: empty command", rule.name)));
+            }
+            let mut builder = GlobSetBuilder::new();
+            for pattern in &rule.paths {
+                let glob = Glob::new(pattern.trim_start_matches('/'))
+                    .map_err(|e| invalid(format!("hook {}: {}", rule.name, e)))?;
+                builder.add(glob);
+            }
+            let paths = builder.build().map_err(|e| invalid(format!("hook {}: {}", rule.name, e)))?;
+            hooks.push(Hook { rule, paths });
+        }
+        info!("Loaded {} response hooks from {:?}", hooks.len(), path);
+
+        Ok(Self {
+            hooks: Arc::new(hooks),
+            slots: Arc::new(Semaphore::new(concurrency.max(1))),
+            running: Mutex::new(Vec::new()),
+        })
+    }
+}
+
+/// Runs one hook to completion, killing it on timeout.
+async fn run_hook(hook: &Hook, input: &[u8], anomaly: &AnomalyReport) {
+    let rule = &hook.rule;
+    let started = Instant::now();
+    let mut child = match Command::new(&rule.command[0])
+        .args(&rule.command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            error!(target: "audit", hook = %rule.name, path = %anomaly.path, "Response hook failed to start: {}", e);
+            return;
+        }
+    };
+
+    let mut stdin = child.stdin.take().unwrap();
+    if let Err(e) = stdin.write_all(input).await {
+        warn!("Response hook {} did not read its input: {}", rule.name, e);
+    }
+    drop(stdin);
+
+    match tokio::time::timeout(Duration::from_secs(rule.timeout_secs), child.wait()).await {
+        Ok(Ok(status)) => info!(
+            target: "audit",
+            hook = %rule.name,
+            kind = %anomaly.kind(),
+            path = %anomaly.path,
+            status = %status,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "Response hook finished"
+        ),
+        Ok(Err(e)) => error!(target: "audit", hook = %rule.name, path = %anomaly.path, "Response hook failed: {}", e),
+        Err(_) => {
+            let _ = child.kill().await;
+            error!(
+                target: "audit",
+                hook = %rule.name,
+                path = %anomaly.path,
+                "Response hook killed after {}s timeout",
+                rule.timeout_secs
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl AnomalySink for HookRunner {
+    fn remediates(&self) -> bool {
+        true
+    }
+
+    async fn emit(&self, anomaly: &AnomalyReport) -> std::io::Result<()> {
+        for (index, hook) in self.hooks.iter().enumerate() {
+            if !hook.matches(anomaly) {
+                continue;
+            }
+            let input = serde_json::to_vec(anomaly)?;
+            let (hooks, slots, anomaly) = (self.hooks.clone(), self.slots.clone(), anomaly.clone());
+            info!(target: "audit", hook = %hook.rule.name, kind = %anomaly.kind(), path = %anomaly.path, "Response hook triggered");
+
+            // Don't hold up verification while containment runs
+            let handle = tokio::spawn(async move {
+                let Ok(_slot) = slots.acquire_owned().await else {
+                    return;
+                };
+                run_hook(&hooks[index], &input, &anomaly).await;
+            });
+
+            let mut running = self.running.lock().await;
+            running.retain(|h| !h.is_finished());
+            running.push(handle);
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) {
+        let running = std::mem::take(&mut *self.running.lock().await);
+        for handle in running {
+            let _ = handle.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rule_matching() {
+        let rules = r#"[
+            {"name": "bins", "kinds": ["MODIFIED"], "paths": ["usr/bin/*"], "command": ["true"]},
+            {"name": "all", "command": ["true"]}
+        ]"#;
+        let path = std::env::temp_dir().join(format!("acropole-hooks-{}.json", std::process::id()));
+        std::fs::write(&path, rules).unwrap();
+        let runner = HookRunner::load(&path, 2).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let modified = AnomalyReport::parse("MODIFIED: usr/bin/ls (hash mismatch)");
+        let added = AnomalyReport::parse("ADDED: usr/bin/evil");
+        let etc = AnomalyReport::parse("MODIFIED: etc/passwd (hash mismatch)");
+        assert!(runner.hooks[0].matches(&modified));
+        assert!(!runner.hooks[0].matches(&added));
+        assert!(!runner.hooks[0].matches(&etc));
+        assert!(runner.hooks[1].matches(&etc));
+    }
+}
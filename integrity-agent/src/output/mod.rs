@@ -0,0 +1,26 @@
+//! Output adapters that forward detected anomalies to external systems.
+
+pub mod falco;
+pub mod hooks;
+pub mod remount;
+pub mod service;
+pub mod stdout;
+pub mod wazuh;
+
+use async_trait::async_trait;
+use integrity_common::AnomalyReport;
+
+/// A destination for anomalies, in addition to the agent's own log.
+#[async_trait]
+pub trait AnomalySink: Send + Sync {
+    async fn emit(&self, anomaly: &AnomalyReport) -> std::io::Result<()>;
+
+    /// Waits for background work started by `emit`, before the agent exits.
+    async fn flush(&self) {}
+
+    /// Whether the sink acts on the host rather than only reporting, so a
+    /// policy deciding to only alert skips it.
+    fn remediates(&self) -> bool {
+        false
+    }
+}
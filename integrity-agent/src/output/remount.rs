@@ -0,0 +1,157 @@
+//! Containment: remounting a filesystem read-only on critical anomalies.
+//!
+//! When an anomaly of one of the configured kinds hits a path under one of
+//! the configured mount points, the mount is remounted read-only
+//! (`MS_REMOUNT | MS_RDONLY`) to stop tampering in progress. Mount points are
+//! host paths: in a DaemonSet both they and the anomaly paths are resolved
+//! below the hostPath mount (`--host-root`) before matching. Each mount point
+//! is remounted at most once per agent run. With `dry_run` the action is only
+//! logged. Every decision is logged under the `audit` target.
+
+use super::{AnomalySink, AnomalyReport};
+use async_trait::async_trait;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RemountArgs {
+    /// Mount point to remount read-only on critical anomalies below it (repeatable)
+    #[arg(long)]
+    pub remount_ro: Vec<PathBuf>,
+
+    /// Anomaly kinds that trigger --remount-ro
+    #[arg(long, value_delimiter = ',', default_value = "MODIFIED,DELETED,PRELOAD_INJECTION,PERSISTENCE_MECHANISM")]
+    pub remount_kinds: Vec<String>,
+
+    /// Log the remounts --remount-ro would do without doing them
+    #[arg(long)]
+    pub remount_dry_run: bool,
+}
+
+pub struct ReadOnlyRemount {
+    /// Where the host filesystem is, e.g. "/host" in a DaemonSet
+    root: PathBuf,
+    /// Below `root`
+    mount_points: Vec<PathBuf>,
+    kinds: Vec<String>,
+    dry_run: bool,
+    remounted: Mutex<HashSet<PathBuf>>,
+}
+
+impl ReadOnlyRemount {
+    pub fn new(args: &RemountArgs, root: &Path) -> Self {
+        // Longest first, so the innermost mount containing a path wins
+        let mut mount_points: Vec<PathBuf> = args.remount_ro.iter().map(|p| below(root, p)).collect();
+        mount_points.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
+        Self {
+            root: root.to_path_buf(),
+            mount_points,
+            kinds: args.remount_kinds.clone(),
+            dry_run: args.remount_dry_run,
+            remounted: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn mount_point_for(&self, anomaly: &AnomalyReport) -> Option<&Path> {
+        if !self.kinds.iter().any(|k| k == anomaly.kind()) {
+            return None;
+        }
+        let path = below(&self.root, Path::new(&anomaly.absolute_path()));
+        self.mount_points.iter().find(|m| path.starts_with(m)).map(PathBuf::as_path)
+    }
+}
+
+/// Where the host path `path` is visible below `root`.
+fn below(root: &Path, path: &Path) -> PathBuf {
+    root.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+#[cfg(target_os = "linux")]
+fn remount_read_only(mount_point: &Path) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let target = std::ffi::CString::new(mount_point.as_os_str().as_bytes())?;
+    let flags = libc::MS_REMOUNT | libc::MS_RDONLY;
+    let result = unsafe { libc::mount(std::ptr::null(), target.as_ptr(), std::ptr::null(), flags, std::ptr::null()) };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn remount_read_only(_mount_point: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "read-only remount is only supported on Linux",
+    ))
+}
+
+#[async_trait]
+impl AnomalySink for ReadOnlyRemount {
+    fn remediates(&self) -> bool {
+        true
+    }
+
+    async fn emit(&self, anomaly: &AnomalyReport) -> std::io::Result<()> {
+        let Some(mount_point) = self.mount_point_for(anomaly) else {
+            return Ok(());
+        };
+        if !self.remounted.lock().unwrap().insert(mount_point.to_path_buf()) {
+            return Ok(());
+        }
+
+        if self.dry_run {
+            info!(target: "audit", "Dry run: would remount {:?} read-only after {} on {}", mount_point, anomaly.kind(), anomaly.path);
+            return Ok(());
+        }
+        match remount_read_only(mount_point) {
+            Ok(()) => {
+                warn!(target: "audit", "Remounted {:?} read-only after {} on {}", mount_point, anomaly.kind(), anomaly.path);
+                Ok(())
+            }
+            Err(e) => {
+                // Allow a later anomaly to try again
+                self.remounted.lock().unwrap().remove(mount_point);
+                Err(std::io::Error::new(e.kind(), format!("remounting {:?} read-only: {}", mount_point, e)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dry_run_picks_innermost_mount_once() {
+        let remount = ReadOnlyRemount::new(&RemountArgs {
+            remount_ro: vec![PathBuf::from("/"), PathBuf::from("/usr")],
+            remount_kinds: vec!["MODIFIED".to_string()],
+            remount_dry_run: true,
+        }, Path::new("/"));
+
+        let modified = AnomalyReport::parse("MODIFIED: usr/bin/ls (hash mismatch)");
+        assert_eq!(remount.mount_point_for(&modified), Some(Path::new("/usr")));
+        assert_eq!(remount.mount_point_for(&AnomalyReport::parse("ADDED: usr/bin/x")), None);
+        assert_eq!(remount.mount_point_for(&AnomalyReport::parse("MODIFIED: etc/passwd")), Some(Path::new("/")));
+
+        remount.emit(&modified).await.unwrap();
+        remount.emit(&modified).await.unwrap();
+        assert_eq!(remount.remounted.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_mount_points_below_host_root() {
+        let remount = ReadOnlyRemount::new(&RemountArgs {
+            remount_ro: vec![PathBuf::from("/"), PathBuf::from("/usr")],
+            remount_kinds: vec!["MODIFIED".to_string()],
+            remount_dry_run: true,
+        }, Path::new("/host"));
+
+        assert_eq!(remount.mount_point_for(&AnomalyReport::parse("MODIFIED: usr/bin/ls")), Some(Path::new("/host/usr")));
+        assert_eq!(remount.mount_point_for(&AnomalyReport::parse("MODIFIED: etc/passwd")), Some(Path::new("/host")));
+    }
+}
@@ -0,0 +1,142 @@
+//! Anomalies pushed to the metadata service.
+//!
+//! With `--report-anomalies` the agent posts what scans and monitoring find to
+//! the service's `POST /anomalies`, under its host name and baseline, so a
+//! fleet's findings can be listed (`GET /anomalies?image_id=...`) and triaged
+//! centrally. Anomalies reported within a second of each other go in one
+//! request, so a scan's findings are a few requests rather than one each. A
+//! batch the service does not take is logged and dropped; the agent's local
+//! history still has it.
+
+use super::{AnomalyReport, AnomalySink};
+use async_trait::async_trait;
+use integrity_client::MetadataClient;
+use integrity_common::{AnomalyBatch, ReportedAnomaly};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+/// How long anomalies are collected before they are sent.
+const BATCH_DELAY: Duration = Duration::from_secs(1);
+
+/// Where anomalies are reported, and as which host and baseline.
+#[derive(Debug, Clone)]
+pub struct ReportTarget {
+    pub client: MetadataClient,
+    pub host: String,
+    pub image_id: String,
+}
+
+#[derive(Default)]
+struct Pending {
+    anomalies: Vec<ReportedAnomaly>,
+    /// Whether a sender task is running, which sends what is pending
+    sending: bool,
+}
+
+pub struct ServiceOutput {
+    target: ReportTarget,
+    pending: Arc<Mutex<Pending>>,
+    sender: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl ServiceOutput {
+    pub fn new(target: ReportTarget) -> Self {
+        Self { target, pending: Arc::default(), sender: Mutex::new(None) }
+    }
+}
+
+/// Sends batches of what is pending until nothing is.
+async fn send_batches(target: ReportTarget, pending: Arc<Mutex<Pending>>) {
+    loop {
+        tokio::time::sleep(BATCH_DELAY).await;
+        let anomalies = {
+            let mut pending = pending.lock().unwrap();
+            if pending.anomalies.is_empty() {
+                pending.sending = false;
+                return;
+            }
+            std::mem::take(&mut pending.anomalies)
+        };
+        let count = anomalies.len();
+        let batch = AnomalyBatch { host: target.host.clone(), image_id: target.image_id.clone(), anomalies };
+        match target.client.report_anomalies(&batch).await {
+            Ok(opened) => debug!("Reported {} anomalies to the metadata service, {} newly open", count, opened.len()),
+            Err(e) => warn!("Cannot report {} anomalies to the metadata service: {}", count, e),
+        }
+    }
+}
+
+#[async_trait]
+impl AnomalySink for ServiceOutput {
+    async fn emit(&self, anomaly: &AnomalyReport) -> std::io::Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        pending.anomalies.push(anomaly.into());
+        if !pending.sending {
+            pending.sending = true;
+            let handle = tokio::spawn(send_batches(self.target.clone(), self.pending.clone()));
+            *self.sender.lock().unwrap() = Some(handle);
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) {
+        let sender = self.sender.lock().unwrap().take();
+        if let Some(handle) = sender {
+            let _ = handle.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_client::ClientConfig;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Answers one request like the service does, returning its body.
+    async fn serve_once(listener: tokio::net::TcpListener) -> Vec<u8> {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 4096];
+        let body_start = loop {
+            let read = stream.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+            if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                break end + 4;
+            }
+        };
+        let headers = String::from_utf8_lossy(&request[..body_start]).to_lowercase();
+        let length: usize = headers
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length:"))
+            .map_or(0, |value| value.trim().parse().unwrap());
+        while request.len() < body_start + length {
+            let read = stream.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+        }
+        let answer = r#"{"recorded": 2, "opened": []}"#;
+        let response = format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", answer.len(), answer);
+        stream.write_all(response.as_bytes()).await.unwrap();
+        request.split_off(body_start)
+    }
+
+    #[tokio::test]
+    async fn test_anomalies_are_batched() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = MetadataClient::new(ClientConfig::new(&format!("http://{}", listener.local_addr().unwrap()))).unwrap();
+        let server = tokio::spawn(serve_once(listener));
+
+        let output = ServiceOutput::new(ReportTarget { client, host: "web-1".to_string(), image_id: "web-v1".to_string() });
+        output.emit(&AnomalyReport::parse("ADDED: tmp/x")).await.unwrap();
+        output.emit(&AnomalyReport::parse("MODIFIED: usr/bin/ls (hash mismatch: a != b)")).await.unwrap();
+        output.flush().await;
+
+        let batch: AnomalyBatch = serde_json::from_slice(&server.await.unwrap()).unwrap();
+        assert_eq!(batch.host, "web-1");
+        assert_eq!(batch.image_id, "web-v1");
+        let kinds: Vec<&str> = batch.anomalies.iter().map(|a| a.kind.as_str()).collect();
+        assert_eq!(kinds, ["ADDED", "MODIFIED"]);
+    }
+}
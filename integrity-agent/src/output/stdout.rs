@@ -0,0 +1,68 @@
+//! Anomaly records on stdout, for log pipelines.
+//!
+//! Each anomaly is written as it is reported, scans and monitoring alike:
+//! `text` as the "KIND: path (detail)" line, `json` as a pretty-printed
+//! document of the structured form (a stream of documents, as `jq` reads them)
+//! and `ndjson` as one compact document per line. The agent's own log goes to
+//! stderr then, so stdout only carries the records.
+
+use super::{AnomalyReport, AnomalySink};
+use async_trait::async_trait;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Ndjson,
+}
+
+/// The record for an anomaly, without the trailing newline.
+fn render(anomaly: &AnomalyReport, format: OutputFormat) -> serde_json::Result<String> {
+    match format {
+        OutputFormat::Text => Ok(anomaly.to_string()),
+        OutputFormat::Json => serde_json::to_string_pretty(anomaly),
+        OutputFormat::Ndjson => serde_json::to_string(anomaly),
+    }
+}
+
+pub struct StdoutOutput {
+    format: OutputFormat,
+}
+
+impl StdoutOutput {
+    pub fn new(format: OutputFormat) -> Self {
+        Self { format }
+    }
+}
+
+#[async_trait]
+impl AnomalySink for StdoutOutput {
+    async fn emit(&self, anomaly: &AnomalyReport) -> std::io::Result<()> {
+        let record = render(anomaly, self.format)?;
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}", record)?;
+        stdout.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let anomaly = AnomalyReport::parse("PERMISSION_CHANGED: etc/shadow (640 != 644)");
+        assert_eq!(render(&anomaly, OutputFormat::Text).unwrap(), "PERMISSION_CHANGED: etc/shadow (640 != 644)");
+
+        let line = render(&anomaly, OutputFormat::Ndjson).unwrap();
+        assert!(!line.contains('\n'));
+        let record: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(record["kind"], "PERMISSION_CHANGED");
+        assert_eq!(record["expected"], "640");
+        assert_eq!(record["severity"], "high");
+
+        let document: serde_json::Value = serde_json::from_str(&render(&anomaly, OutputFormat::Json).unwrap()).unwrap();
+        assert_eq!(document, record);
+    }
+}
@@ -0,0 +1,166 @@
+//! Wazuh/OSSEC output adapter.
+//!
+//! Anomalies are rendered as Wazuh syscheck (FIM) events, so the stock FIM
+//! decoder and rules apply. They are either sent to the local Wazuh agent queue
+//! socket, or appended as JSON lines to a file picked up by a `localfile` block.
+
+use super::{AnomalySink, AnomalyReport};
+use integrity_common::Anomaly;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// Default location of the Wazuh agent's message queue socket.
+pub const DEFAULT_QUEUE_SOCKET: &str = "/var/ossec/queue/sockets/queue";
+
+/// Queue identifier for syscheck messages (SYSCHECK_MQ in Wazuh).
+const SYSCHECK_MQ: char = '8';
+
+/// Where Wazuh events are written.
+#[derive(Debug, Clone)]
+pub enum WazuhTarget {
+    /// Unix datagram queue socket of the local Wazuh agent
+    Socket(PathBuf),
+    /// JSON lines file
+    File(PathBuf),
+}
+
+impl std::str::FromStr for WazuhTarget {
+    type Err = String;
+
+    /// Parses "socket", "socket:<path>" or "file:<path>".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "socket" => Ok(Self::Socket(PathBuf::from(DEFAULT_QUEUE_SOCKET))),
+            Some(("socket", path)) => Ok(Self::Socket(PathBuf::from(path))),
+            Some(("file", path)) => Ok(Self::File(PathBuf::from(path))),
+            _ => Err(format!("expected socket[:<path>] or file:<path>, got {}", s)),
+        }
+    }
+}
+
+/// Builds the syscheck event for an anomaly, found by the file monitor when
+/// `realtime` or by a scan otherwise.
+fn syscheck_event(anomaly: &AnomalyReport, realtime: bool) -> Value {
+    let (event_type, changed_attributes): (&str, &[&str]) = match anomaly.kind() {
+        "ADDED" => ("added", &[]),
+        "DELETED" => ("deleted", &[]),
+        "MODIFIED" => ("modified", &["sha512"]),
+        "PERMISSION_CHANGED" => ("modified", &["permission"]),
+        "UID_CHANGED" => ("modified", &["uid"]),
+        "GID_CHANGED" => ("modified", &["gid"]),
+        "SIZE_CHANGED" => ("modified", &["size"]),
+        "MTIME_CHANGED" => ("modified", &["mtime"]),
+        _ => ("modified", &[]),
+    };
+    // The baseline's and the host's value of what changed, under Wazuh's attribute names
+    let mut attributes = json!({ "type": "file" });
+    let mut old_attributes = json!({ "type": "file" });
+    let name = match anomaly.anomaly {
+        Anomaly::Modified { .. } => Some("hash_sha512"),
+        Anomaly::PermissionChanged { .. } => Some("perm"),
+        Anomaly::UidChanged { .. } => Some("uid"),
+        Anomaly::GidChanged { .. } => Some("gid"),
+        Anomaly::SizeChanged { .. } => Some("size"),
+        Anomaly::MtimeChanged { .. } => Some("mtime"),
+        _ => None,
+    };
+    if let (Some(name), Some(expected), Some(actual)) = (name, anomaly.anomaly.expected(), anomaly.anomaly.actual()) {
+        attributes[name] = actual.into();
+        old_attributes[name] = expected.into();
+    }
+
+    json!({
+        "type": "event",
+        "data": {
+            "path": anomaly.absolute_path(),
+            "mode": if realtime { "realtime" } else { "scheduled" },
+            "type": event_type,
+            "timestamp": anomaly.timestamp,
+            "changed_attributes": changed_attributes,
+            "attributes": attributes,
+            "old_attributes": old_attributes,
+            "tags": format!("acropole,{}", anomaly.kind()),
+            "content_changes": anomaly.detail(),
+        }
+    })
+}
+
+pub struct WazuhOutput {
+    target: WazuhTarget,
+    realtime: bool,
+    file: Mutex<Option<tokio::fs::File>>,
+}
+
+impl WazuhOutput {
+    /// `realtime` marks the events as found by the file monitor rather than a scan.
+    pub fn new(target: WazuhTarget, realtime: bool) -> Self {
+        Self {
+            target,
+            realtime,
+            file: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl AnomalySink for WazuhOutput {
+    async fn emit(&self, anomaly: &AnomalyReport) -> std::io::Result<()> {
+        let event = syscheck_event(anomaly, self.realtime);
+        match &self.target {
+            #[cfg(unix)]
+            WazuhTarget::Socket(path) => {
+                let socket = tokio::net::UnixDatagram::unbound()?;
+                let message = format!("{}:syscheck:{}", SYSCHECK_MQ, event);
+                socket.send_to(message.as_bytes(), path).await?;
+            }
+            #[cfg(not(unix))]
+            WazuhTarget::Socket(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "the Wazuh queue socket is only available on Unix, use file:<path>",
+                ));
+            }
+            WazuhTarget::File(path) => {
+                let mut file = self.file.lock().await;
+                if file.is_none() {
+                    *file = Some(
+                        tokio::fs::OpenOptions::new()
+                            .create(true)
+                            .append(true)
+                            .open(path)
+                            .await?,
+                    );
+                }
+                let file = file.as_mut().unwrap();
+                file.write_all(format!("{}\n", event).as_bytes()).await?;
+                file.flush().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syscheck_event() {
+        let anomaly = AnomalyReport::parse("UID_CHANGED: etc/shadow (0 != 1000)");
+        let event = syscheck_event(&anomaly, false);
+        assert_eq!(event["data"]["path"], "/etc/shadow");
+        assert_eq!(event["data"]["mode"], "scheduled");
+        assert_eq!(syscheck_event(&anomaly, true)["data"]["mode"], "realtime");
+        assert_eq!(event["data"]["type"], "modified");
+        assert_eq!(event["data"]["changed_attributes"][0], "uid");
+        assert_eq!(event["data"]["old_attributes"]["uid"], "0");
+        assert_eq!(event["data"]["attributes"]["uid"], "1000");
+
+        assert!(matches!("socket".parse(), Ok(WazuhTarget::Socket(_))));
+        assert!(matches!("file:/tmp/x.json".parse(), Ok(WazuhTarget::File(_))));
+        assert!("bogus".parse::<WazuhTarget>().is_err());
+    }
+}
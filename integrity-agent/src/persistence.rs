@@ -0,0 +1,139 @@
+//! Enumeration of enabled systemd units, SysV services and cron jobs.
+//!
+//! A `PERSISTENCE_MECHANISM` anomaly says a file changed; with
+//! `--enumerate-persistence` the agent follows up by listing what is now
+//! enabled and was not in the baseline: `.wants`/`.requires` links and rc.d
+//! links missing from it, and the jobs in cron files that are new or changed.
+
+use integrity_common::{AnomalyReport, BaselineIndex};
+use std::fs;
+use std::path::Path;
+
+const UNIT_DIRS: &[&str] = &[
+    "etc/systemd/system",
+    "etc/systemd/user",
+    "lib/systemd/system",
+    "usr/lib/systemd/system",
+    "usr/lib/systemd/user",
+];
+const RC_DIRS: &[&str] = &[
+    "etc/rc0.d", "etc/rc1.d", "etc/rc2.d", "etc/rc3.d", "etc/rc4.d", "etc/rc5.d", "etc/rc6.d", "etc/rcS.d",
+];
+const CRON_FILES: &[&str] = &["etc/crontab", "etc/anacrontab"];
+const CRON_DIRS: &[&str] = &["etc/cron.d", "var/spool/cron", "var/spool/cron/crontabs"];
+
+fn enabled(relative: &str, what: &str) -> AnomalyReport {
+    AnomalyReport::other("PERSISTENCE_ENABLED", relative, what)
+}
+
+/// Entries of `dir` (relative to `root`) as (relative path, file name) pairs.
+fn list(root: &Path, dir: &str) -> Vec<(String, String)> {
+    let Ok(entries) = fs::read_dir(root.join(dir)) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            (format!("{}/{}", dir, name), name)
+        })
+        .collect()
+}
+
+fn link_target(root: &Path, relative: &str) -> String {
+    fs::read_link(root.join(relative))
+        .map(|target| target.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "not a link".to_string())
+}
+
+/// Job lines of a crontab: neither blank, comments, nor variable assignments.
+fn cron_jobs(contents: &str) -> impl Iterator<Item = &str> {
+    contents.lines().map(str::trim).filter(|line| {
+        !line.is_empty()
+            && !line.starts_with('#')
+            && !line.split_whitespace().next().is_some_and(|word| word.contains('='))
+    })
+}
+
+/// Units, services and cron jobs enabled on the host but not in the baseline.
+pub fn enumerate(root: &Path, baseline: &BaselineIndex) -> Vec<AnomalyReport> {
+    let mut found = Vec::new();
+
+    for unit_dir in UNIT_DIRS {
+        for (dependency_dir, name) in list(root, unit_dir) {
+            let Some(target) = name.strip_suffix(".wants").or_else(|| name.strip_suffix(".requires")) else {
+                continue;
+            };
+            for (link, unit) in list(root, &dependency_dir) {
+                if !baseline.contains(&link) {
+                    let what = format!("unit {} enabled for {}, -> {}", unit, target, link_target(root, &link));
+                    found.push(enabled(&link, &what));
+                }
+            }
+        }
+    }
+
+    for rc_dir in RC_DIRS {
+        for (link, name) in list(root, rc_dir) {
+            if name.starts_with('S') && !baseline.contains(&link) {
+                found.push(enabled(&link, &format!("SysV service started, -> {}", link_target(root, &link))));
+            }
+        }
+    }
+
+    let cron_files = CRON_FILES
+        .iter()
+        .map(|f| f.to_string())
+        .chain(CRON_DIRS.iter().flat_map(|dir| list(root, dir)).map(|(path, _)| path));
+    for cron_file in cron_files {
+        let Ok(contents) = fs::read(root.join(&cron_file)) else {
+            continue;
+        };
+        // Unchanged files only hold jobs the baseline already has
+        let recorded = baseline.get(&cron_file).and_then(|entry| entry.content_digest(baseline.hash_algorithm()));
+        if recorded.is_some_and(|(algorithm, digest)| crate::compute_digest(&root.join(&cron_file), algorithm).is_ok_and(|actual| actual == digest)) {
+            continue;
+        }
+        for job in cron_jobs(&String::from_utf8_lossy(&contents)) {
+            found.push(enabled(&cron_file, &format!("cron job: {}", job)));
+        }
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::test_util::BaselineBuilder;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_enumerate_new_units_and_jobs() {
+        let root = std::env::temp_dir().join(format!("acropole-persistence-{}", std::process::id()));
+        let wants = root.join("etc/systemd/system/multi-user.target.wants");
+        fs::create_dir_all(&wants).unwrap();
+        fs::create_dir_all(root.join("etc/cron.d")).unwrap();
+        std::os::unix::fs::symlink("/etc/systemd/system/sshd.service", wants.join("sshd.service")).unwrap();
+        std::os::unix::fs::symlink("/etc/systemd/system/backdoor.service", wants.join("backdoor.service")).unwrap();
+        fs::write(root.join("etc/cron.d/job"), "SHELL=/bin/sh\n# comment\n* * * * * root /tmp/x\n").unwrap();
+
+        let baseline = BaselineBuilder::new("img")
+            .size(0)
+            .file("etc/systemd/system/multi-user.target.wants/sshd.service", 0o644)
+            .build();
+        let mut found: Vec<String> = enumerate(&root, &BaselineIndex::new(&baseline)).iter().map(ToString::to_string).collect();
+        found.sort();
+        assert_eq!(
+            found,
+            vec![
+                "PERSISTENCE_ENABLED: etc/cron.d/job (cron job: * * * * * root /tmp/x)".to_string(),
+                "PERSISTENCE_ENABLED: etc/systemd/system/multi-user.target.wants/backdoor.service \
+                 (unit backdoor.service enabled for multi-user.target, -> /etc/systemd/system/backdoor.service)"
+                    .to_string(),
+            ]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
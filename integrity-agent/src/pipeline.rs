@@ -0,0 +1,310 @@
+//! Concurrent verification for monitor mode.
+//!
+//! Events are verified by up to `--hash-workers` tasks at once, with at most
+//! `--verify-queue-depth` verifications submitted and not yet finished; when
+//! the queue is full the event loop waits, and the monitor's channel fills up
+//! behind it (the backend reports an overflow if it drops events). With
+//! `--mount-inflight` a filesystem gets at most that many of the workers, so
+//! a stalled NFS mount holds only its share.
+//!
+//! Events on a path already being verified are coalesced into one more
+//! verification after it finishes, so results for a path arrive in order.
+//! Counters are kept in [`Metrics`], logged every `--metrics-interval` and
+//! served as the `integrity_agent_metrics` osquery table.
+//!
+//! Files are checked against the baseline fetched at startup, or with
+//! `--remote-lookup` against entries looked up on the service per file.
+
+use crate::hashcache::HashCache;
+use crate::monitor::{EventType, FileEvent};
+use crate::enrich::EnricherRegistry;
+use crate::remote_lookup::RemoteLookup;
+use crate::verifier::{VerifierRegistry, VerifyRequest};
+use integrity_common::{Anomaly, AnomalyReport, BaselineIndex};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, Semaphore};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct PipelineArgs {
+    /// Files verified at once in monitor mode (0: one per CPU, at most 4)
+    #[arg(long, default_value = "0")]
+    pub hash_workers: usize,
+
+    /// Verifications submitted and not yet finished before new events wait
+    #[arg(long, default_value = "256")]
+    pub verify_queue_depth: usize,
+
+    /// Files verified at once on one filesystem (0: up to --hash-workers)
+    #[arg(long, default_value = "0")]
+    pub mount_inflight: usize,
+
+    /// Capacity of the channels from the file monitor and the system checks to the event loop
+    #[arg(long, default_value = "1000")]
+    pub event_channel_capacity: usize,
+
+    /// How often the verification metrics are logged, e.g. 5m (0s: never)
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "5m")]
+    pub metrics_interval: Duration,
+}
+
+impl PipelineArgs {
+    pub fn workers(&self) -> usize {
+        match self.hash_workers {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()).min(4),
+            workers => workers,
+        }
+    }
+}
+
+/// Verification counters; `queued` and `in_flight` are current values, the
+/// others totals since startup.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub events: AtomicU64,
+    pub verified: AtomicU64,
+    /// Events merged into a verification of the same path
+    pub coalesced: AtomicU64,
+    /// Times the event loop waited for room in the queue
+    pub queue_full: AtomicU64,
+    pub queued: AtomicU64,
+    pub in_flight: AtomicU64,
+}
+
+impl Metrics {
+    pub fn snapshot(&self) -> Vec<(&'static str, u64)> {
+        [
+            ("events", &self.events),
+            ("verified", &self.verified),
+            ("coalesced", &self.coalesced),
+            ("queue_full", &self.queue_full),
+            ("queued", &self.queued),
+            ("in_flight", &self.in_flight),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name, value.load(Ordering::Relaxed)))
+        .collect()
+    }
+}
+
+/// A finished verification.
+pub struct Verified {
+    pub event: FileEvent,
+    pub anomaly: Option<AnomalyReport>,
+}
+
+/// Where verification finds the baseline entries of files.
+pub enum Entries {
+    /// The whole baseline, fetched at startup
+    Local(Arc<BaselineIndex>),
+    /// Looked up on the metadata service file by file
+    Remote(Arc<RemoteLookup>),
+}
+
+/// What every verification task shares.
+struct Context {
+    root: PathBuf,
+    baseline: Entries,
+    hashes: Option<Arc<HashCache>>,
+    verifiers: VerifierRegistry,
+    enrichers: EnricherRegistry,
+    workers: Semaphore,
+    mount_inflight: usize,
+    mounts: Mutex<HashMap<u64, Arc<Semaphore>>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Context {
+    /// Limit of the filesystem holding `path`, or of its parent once it is deleted.
+    fn mount_limit(&self, path: &Path) -> Option<Arc<Semaphore>> {
+        if self.mount_inflight == 0 {
+            return None;
+        }
+        let metadata = std::fs::metadata(path).ok().or_else(|| std::fs::metadata(path.parent()?).ok())?;
+        let device = crate::platform::device_id(&metadata)?;
+        let mut mounts = self.mounts.lock().unwrap();
+        Some(mounts.entry(device).or_insert_with(|| Arc::new(Semaphore::new(self.mount_inflight))).clone())
+    }
+
+    async fn verify(self: Arc<Self>, path: PathBuf) -> Option<AnomalyReport> {
+        let relative_path = path.strip_prefix(&self.root).unwrap_or(&path).to_string_lossy().to_string();
+        let baseline = match &self.baseline {
+            Entries::Local(index) => index.clone(),
+            Entries::Remote(remote) => match remote.index(&relative_path).await {
+                Ok(index) => Arc::new(index),
+                Err(e) => return Some(AnomalyReport::other("BASELINE_LOOKUP_FAILED", relative_path, e.to_string())),
+            },
+        };
+        let mount = self.mount_limit(&path);
+        let _mount = match &mount {
+            Some(limit) => Some(limit.acquire().await.ok()?),
+            None => None,
+        };
+        let _worker = self.workers.acquire().await.ok()?;
+        self.metrics.in_flight.fetch_add(1, Ordering::Relaxed);
+
+        // Hashing blocks, keep it off the runtime's threads
+        let (context, index) = (self.clone(), baseline.clone());
+        let checked = path.clone();
+        let mut anomaly = tokio::task::spawn_blocking(move || {
+            crate::check_file(&checked, &context.root, &index, context.hashes.as_deref())
+        })
+        .await
+        .unwrap_or_else(|e| Some(AnomalyReport::new(Anomaly::HashError { error: e.to_string() }, relative_path.as_str())));
+
+        if anomaly.is_none() && !self.verifiers.is_empty() {
+            if let Some(entry) = baseline.get(&relative_path) {
+                let request = VerifyRequest { path: &path, relative_path: &relative_path, baseline: entry };
+                anomaly = self.verifiers.verify(&request).await;
+            }
+        }
+        if let Some(found) = anomaly {
+            anomaly = Some(self.enrichers.enrich(found, &self.root).await);
+        }
+        self.metrics.in_flight.fetch_sub(1, Ordering::Relaxed);
+        anomaly
+    }
+}
+
+pub struct Pipeline {
+    context: Arc<Context>,
+    queue: Arc<Semaphore>,
+    /// Paths being verified, with the event to verify again once done
+    in_flight: HashMap<PathBuf, Option<FileEvent>>,
+    results: mpsc::UnboundedSender<Verified>,
+}
+
+impl Pipeline {
+    pub fn new(
+        args: &PipelineArgs,
+        root: PathBuf,
+        baseline: Entries,
+        hashes: Option<Arc<HashCache>>,
+        verifiers: VerifierRegistry,
+        enrichers: EnricherRegistry,
+        metrics: Arc<Metrics>,
+    ) -> (Self, mpsc::UnboundedReceiver<Verified>) {
+        let (results, results_rx) = mpsc::unbounded_channel();
+        let context = Context {
+            root,
+            baseline,
+            hashes,
+            verifiers,
+            enrichers,
+            workers: Semaphore::new(args.workers()),
+            mount_inflight: args.mount_inflight,
+            mounts: Mutex::new(HashMap::new()),
+            metrics,
+        };
+        let pipeline = Self {
+            context: Arc::new(context),
+            queue: Arc::new(Semaphore::new(args.verify_queue_depth.max(1))),
+            in_flight: HashMap::new(),
+            results,
+        };
+        (pipeline, results_rx)
+    }
+
+    /// Queues verification of the event's path, waiting while the queue is full.
+    pub async fn submit(&mut self, event: FileEvent) {
+        let metrics = &self.context.metrics;
+        metrics.events.fetch_add(1, Ordering::Relaxed);
+        if let Some(again) = self.in_flight.get_mut(&event.path) {
+            metrics.coalesced.fetch_add(1, Ordering::Relaxed);
+            // A live event outranks a re-scan of the same path
+            if !again.as_ref().is_some_and(|queued| queued.event_type != EventType::Rescan) {
+                *again = Some(event);
+            }
+            return;
+        }
+        self.start(event).await;
+    }
+
+    async fn start(&mut self, event: FileEvent) {
+        let metrics = self.context.metrics.clone();
+        if self.queue.available_permits() == 0 {
+            metrics.queue_full.fetch_add(1, Ordering::Relaxed);
+        }
+        let Ok(permit) = self.queue.clone().acquire_owned().await else {
+            return;
+        };
+        metrics.queued.fetch_add(1, Ordering::Relaxed);
+        self.in_flight.insert(event.path.clone(), None);
+
+        let (context, results) = (self.context.clone(), self.results.clone());
+        tokio::spawn(async move {
+            let anomaly = context.verify(event.path.clone()).await;
+            metrics.verified.fetch_add(1, Ordering::Relaxed);
+            metrics.queued.fetch_sub(1, Ordering::Relaxed);
+            drop(permit);
+            let _ = results.send(Verified { event, anomaly });
+        });
+    }
+
+    /// Marks the verification of `path` as handled, starting the coalesced
+    /// one if events arrived meanwhile.
+    pub async fn finished(&mut self, path: &Path) {
+        if let Some(Some(again)) = self.in_flight.remove(path) {
+            self.start(again).await;
+        }
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.context.metrics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::test_util::BaselineBuilder;
+
+    #[tokio::test]
+    async fn test_coalesces_events_on_a_busy_path() {
+        let root = std::env::temp_dir().join(format!("acropole-pipeline-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("etc")).unwrap();
+        std::fs::write(root.join("etc/hosts"), b"x").unwrap();
+        let baseline = BaselineBuilder::new("img").size(0).file("etc/hosts", 0o644).build();
+
+        let args = PipelineArgs {
+            hash_workers: 2,
+            verify_queue_depth: 4,
+            mount_inflight: 1,
+            event_channel_capacity: 10,
+            metrics_interval: Duration::ZERO,
+        };
+        let metrics = Arc::new(Metrics::default());
+        let (mut pipeline, mut results) =
+            Pipeline::new(&args, root.clone(), Entries::Local(Arc::new(BaselineIndex::new(&baseline))), None, VerifierRegistry::default(), EnricherRegistry::default(), metrics);
+
+        let event = |path: &str, event_type| FileEvent { path: root.join(path), event_type };
+        pipeline.submit(event("etc/hosts", EventType::Modified)).await;
+        pipeline.submit(event("etc/hosts", EventType::Modified)).await;
+        pipeline.submit(event("etc/hosts", EventType::Rescan)).await;
+        pipeline.submit(event("etc/new", EventType::Created)).await;
+
+        let mut verified = Vec::new();
+        while verified.len() < 3 {
+            let result = results.recv().await.unwrap();
+            pipeline.finished(&result.event.path).await;
+            verified.push((result.event.path.strip_prefix(&root).unwrap().to_path_buf(), result.event.event_type, result.anomaly.is_some()));
+        }
+        verified.sort_by_key(|(path, _, _)| path.clone());
+        // Three events on etc/hosts make two verifications, the later one for the live event
+        assert_eq!(
+            verified,
+            vec![
+                (PathBuf::from("etc/hosts"), EventType::Modified, true),
+                (PathBuf::from("etc/hosts"), EventType::Modified, true),
+                (PathBuf::from("etc/new"), EventType::Created, true),
+            ]
+        );
+        let snapshot: HashMap<_, _> = pipeline.metrics().snapshot().into_iter().collect();
+        assert_eq!((snapshot["events"], snapshot["coalesced"], snapshot["verified"], snapshot["queued"]), (4, 2, 3, 0));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
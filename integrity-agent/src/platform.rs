@@ -0,0 +1,25 @@
+//! Platform-specific file metadata used in scans and verification. What is
+//! recorded of each file is shared with the collector through integrity-scanner.
+
+pub use integrity_scanner::{file_meta, is_special_file};
+use std::fs::Metadata;
+
+/// Root of the monitored filesystem; baseline paths are relative to it.
+#[cfg(unix)]
+pub const FILESYSTEM_ROOT: &str = "/";
+#[cfg(windows)]
+pub const FILESYSTEM_ROOT: &str = "C:\\";
+
+/// Device of the filesystem holding a file, to limit concurrent reads per filesystem.
+#[cfg(unix)]
+pub fn device_id(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+/// The volume serial number is only available through an open handle, so
+/// files are not told apart by volume.
+#[cfg(windows)]
+pub fn device_id(_metadata: &Metadata) -> Option<u64> {
+    None
+}
@@ -0,0 +1,271 @@
+//! Policy decisions on anomalies.
+//!
+//! With a policy configured, every anomaly is evaluated before it is reported
+//! and the policy decides what happens to it:
+//!
+//! - `ignore`: dropped, neither recorded nor counted against the run
+//! - `alert`: reported to the outputs, without running response hooks or remounts
+//! - `remediate`: reported and handed to the response hooks and remounts too
+//! - `fail-closed`: like `remediate`, and monitoring stops, failing the run
+//!
+//! The policy is either an OPA server (`--policy-url`, its data API, so the
+//! Rego policies can be distributed centrally as OPA bundles) or a plugin
+//! process (`--policy-plugin`), e.g. `wasmtime` running a policy module. Both
+//! receive the same input document:
+//!
+//! ```json
+//! {"input": {"anomaly": {"time": 1700000000, "kind": "MODIFIED", "path": "usr/bin/ls", "detail": "hash mismatch: aa != bb",
+//!                        "expected": "aa", "actual": "bb", "severity": "high"},
+//!            "host": {"hostname": "web-1", "image_id": "web-2024.05", "mode": "Monitor"}}}
+//! ```
+//!
+//! OPA answers `{"result": "alert"}` (or `{"result": {"action": "alert"}}`);
+//! the plugin reads one such document per line on stdin and writes one
+//! `{"action": "alert"}` line per decision to stdout. A policy that is
+//! unreachable, undefined for the input or does not answer within the timeout
+//! gets `--policy-default-action`. Without a policy, anomalies are remediated
+//! as before: hooks and remounts apply their own rules.
+
+use integrity_common::AnomalyReport;
+use integrity_common::{IntegrityError, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// What happens to an anomaly, from the mildest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Ignore,
+    Alert,
+    Remediate,
+    FailClosed,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct PolicyArgs {
+    /// OPA decision URL each anomaly is evaluated against, e.g. http://127.0.0.1:8181/v1/data/acropole/action
+    #[arg(long, conflicts_with = "policy_plugin")]
+    pub policy_url: Option<String>,
+
+    /// Policy plugin reading anomalies as JSON lines and answering with actions
+    #[arg(long)]
+    pub policy_plugin: Option<PathBuf>,
+
+    /// Argument passed to --policy-plugin (repeatable), e.g. the policy module for wasmtime
+    #[arg(long, requires = "policy_plugin", allow_hyphen_values = true)]
+    pub policy_plugin_arg: Vec<String>,
+
+    /// Seconds to wait for a policy decision
+    #[arg(long, default_value = "5")]
+    pub policy_timeout: u64,
+
+    /// Action for anomalies the policy cannot decide on (unreachable, undefined or too slow)
+    #[arg(long, value_enum, default_value = "alert")]
+    pub policy_default_action: Action,
+}
+
+/// What the policy knows about the host besides the anomaly.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostContext {
+    pub hostname: String,
+    pub image_id: String,
+    pub mode: String,
+}
+
+#[derive(Serialize)]
+struct PolicyInput<'a> {
+    anomaly: &'a AnomalyReport,
+    host: &'a HostContext,
+}
+
+#[derive(Serialize)]
+struct PolicyQuery<'a> {
+    input: PolicyInput<'a>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Decision {
+    Action(Action),
+    Object { action: Action },
+}
+
+#[derive(Deserialize)]
+struct OpaResponse {
+    /// Missing when the policy is undefined for the input
+    result: Option<Decision>,
+}
+
+struct PluginProcess {
+    // Held so the plugin is killed when the process handle is dropped
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+enum Backend {
+    Opa { http: reqwest::Client, url: String },
+    Plugin { program: PathBuf, args: Vec<String>, process: Mutex<Option<Box<PluginProcess>>> },
+}
+
+pub struct PolicyEngine {
+    backend: Backend,
+    host: HostContext,
+    timeout: Duration,
+    default_action: Action,
+}
+
+impl PolicyEngine {
+    /// The configured policy, if any.
+    pub fn new(args: &PolicyArgs, host: HostContext) -> Result<Option<Self>> {
+        let timeout = Duration::from_secs(args.policy_timeout);
+        let backend = match (&args.policy_url, &args.policy_plugin) {
+            (Some(url), _) => {
+                let http = reqwest::Client::builder()
+                    .timeout(timeout)
+                    .build()
+                    .map_err(|e| IntegrityError::Validation(format!("policy client: {}", e)))?;
+                info!("Evaluating anomalies against the policy at {}", url);
+                Backend::Opa { http, url: url.clone() }
+            }
+            (None, Some(program)) => {
+                info!("Evaluating anomalies with the policy plugin {:?}", program);
+                Backend::Plugin { program: program.clone(), args: args.policy_plugin_arg.clone(), process: Mutex::new(None) }
+            }
+            (None, None) => return Ok(None),
+        };
+        Ok(Some(Self { backend, host, timeout, default_action: args.policy_default_action }))
+    }
+
+    /// The action the policy decides on for `anomaly`.
+    pub async fn evaluate(&self, anomaly: &AnomalyReport) -> Action {
+        let query = PolicyQuery { input: PolicyInput { anomaly, host: &self.host } };
+        let decision = match &self.backend {
+            Backend::Opa { http, url } => Self::query_opa(http, url, &query).await,
+            Backend::Plugin { program, args, process } => self.query_plugin(program, args, process, &query).await,
+        };
+        match decision {
+            Ok(Some(action)) => action,
+            Ok(None) => {
+                warn!("Policy is undefined for {} {}, using {:?}", anomaly.kind(), anomaly.path, self.default_action);
+                self.default_action
+            }
+            Err(e) => {
+                warn!("Policy evaluation failed for {} {}, using {:?}: {}", anomaly.kind(), anomaly.path, self.default_action, e);
+                self.default_action
+            }
+        }
+    }
+
+    async fn query_opa(http: &reqwest::Client, url: &str, query: &PolicyQuery<'_>) -> std::result::Result<Option<Action>, String> {
+        let response = http.post(url).json(query).send().await.map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("{} answered {}", url, response.status()));
+        }
+        let response: OpaResponse = response.json().await.map_err(|e| e.to_string())?;
+        Ok(response.result.map(Decision::action))
+    }
+
+    async fn query_plugin(
+        &self,
+        program: &Path,
+        args: &[String],
+        process: &Mutex<Option<Box<PluginProcess>>>,
+        query: &PolicyQuery<'_>,
+    ) -> std::result::Result<Option<Action>, String> {
+        let mut guard = process.lock().await;
+        if guard.is_none() {
+            let mut child = Command::new(program)
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| format!("cannot start plugin: {}", e))?;
+            info!("Started policy plugin {:?}", program);
+            *guard = Some(Box::new(PluginProcess {
+                stdin: child.stdin.take().unwrap(),
+                stdout: BufReader::new(child.stdout.take().unwrap()),
+                _child: child,
+            }));
+        }
+        match tokio::time::timeout(self.timeout, exchange(guard.as_mut().unwrap(), query)).await {
+            Ok(Ok(decision)) => Ok(Some(decision.action())),
+            Ok(Err(e)) => {
+                // The stream is out of sync or the plugin died; restart it next time
+                *guard = None;
+                Err(format!("plugin error: {}", e))
+            }
+            Err(_) => {
+                *guard = None;
+                Err(format!("plugin did not answer within {:?}", self.timeout))
+            }
+        }
+    }
+}
+
+impl Decision {
+    fn action(self) -> Action {
+        match self {
+            Decision::Action(action) | Decision::Object { action } => action,
+        }
+    }
+}
+
+async fn exchange(process: &mut PluginProcess, query: &PolicyQuery<'_>) -> std::io::Result<Decision> {
+    let mut line = serde_json::to_string(query)?;
+    line.push('\n');
+    process.stdin.write_all(line.as_bytes()).await?;
+    process.stdin.flush().await?;
+
+    let mut response = String::new();
+    if process.stdout.read_line(&mut response).await? == 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "plugin exited"));
+    }
+    Ok(serde_json::from_str(&response)?)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn test_plugin_decisions() {
+        let script = std::env::temp_dir().join(format!("acropole-policy-{}.sh", std::process::id()));
+        std::fs::write(
+            &script,
+            "#!/bin/sh\nwhile read -r line; do\n  case \"$line\" in\n    *var/cache*) echo '{\"action\": \"ignore\"}' ;;\n    *usr/bin*) echo '\"fail-closed\"' ;;\n    *etc/motd*) echo 'garbage' ;;\n    *) echo '{\"action\": \"alert\"}' ;;\n  esac\ndone\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let args = PolicyArgs {
+            policy_url: None,
+            policy_plugin: Some(script.clone()),
+            policy_plugin_arg: Vec::new(),
+            policy_timeout: 5,
+            policy_default_action: Action::Remediate,
+        };
+        let host = HostContext { hostname: "web-1".to_string(), image_id: "web".to_string(), mode: "Scan".to_string() };
+        let engine = PolicyEngine::new(&args, host).unwrap().unwrap();
+        let evaluate = |anomaly: &str| {
+            let anomaly = AnomalyReport::parse(anomaly);
+            let engine = &engine;
+            async move { engine.evaluate(&anomaly).await }
+        };
+
+        assert_eq!(evaluate("ADDED: var/cache/apt/pkgcache.bin").await, Action::Ignore);
+        assert_eq!(evaluate("MODIFIED: usr/bin/ls (hash mismatch)").await, Action::FailClosed);
+        assert_eq!(evaluate("MODIFIED: etc/motd (hash mismatch)").await, Action::Remediate);
+        // Restarted after the garbage answer
+        assert_eq!(evaluate("MODIFIED: etc/hosts (hash mismatch)").await, Action::Alert);
+        std::fs::remove_file(script).unwrap();
+    }
+}
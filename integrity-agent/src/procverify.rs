@@ -0,0 +1,264 @@
+//! `--mode ps-verify`: verifies what running processes execute (Linux).
+//!
+//! A binary replaced before the agent started, or deleted after being started,
+//! is never seen by a file scan or monitor. This walks `/proc`, hashing each
+//! process's executable through `/proc/<pid>/exe` and its executable file
+//! mappings from `/proc/<pid>/maps`, which still reach the running content when
+//! the file on disk has been deleted or replaced.
+//!
+//! Processes whose environment preloads libraries (`LD_PRELOAD`, `LD_AUDIT`)
+//! are reported as well, unless the library is explicitly allowed.
+//!
+//! Only processes in the host's mount namespace (that of pid 1) are checked;
+//! containers run from their own images, which the host baseline does not
+//! describe. Files are read through `/proc/<pid>/root`, so this also works from
+//! a DaemonSet pod with `hostPID`.
+
+use crate::compute_digest;
+use integrity_common::{AnomalyReport, BaselineIndex, HashAlgorithm};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+const DELETED_SUFFIX: &str = " (deleted)";
+
+/// A file a process is executing from.
+struct Mapping {
+    /// Path as recorded by the kernel, without the " (deleted)" suffix
+    path: String,
+    deleted: bool,
+    /// Where the running content can be read from
+    content: PathBuf,
+}
+
+/// Splits the " (deleted)" marker off a path from /proc.
+fn parse_path(path: &str) -> (String, bool) {
+    match path.strip_suffix(DELETED_SUFFIX) {
+        Some(path) => (path.to_string(), true),
+        None => (path.to_string(), false),
+    }
+}
+
+/// Executable file mappings in a /proc/<pid>/maps listing, by path.
+/// Returns (address range, path) pairs, one per distinct path.
+fn executable_mappings(maps: &str) -> Vec<(String, String)> {
+    let mut seen = HashMap::new();
+    for line in maps.lines() {
+        let mut fields = line.splitn(6, ' ');
+        let (Some(range), Some(perms)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        // The path is the last field, after whitespace padding
+        let Some(path) = fields.nth(3).map(str::trim_start) else {
+            continue;
+        };
+        if perms.as_bytes().get(2) != Some(&b'x') || !path.starts_with('/') {
+            continue;
+        }
+        seen.entry(path.to_string()).or_insert_with(|| range.to_string());
+    }
+    seen.into_iter().map(|(path, range)| (range, path)).collect()
+}
+
+fn process_mappings(pid: &str) -> Vec<Mapping> {
+    let proc_dir = Path::new("/proc").join(pid);
+    let mut mappings = Vec::new();
+
+    if let Ok(exe) = fs::read_link(proc_dir.join("exe")) {
+        let (path, deleted) = parse_path(&exe.to_string_lossy());
+        mappings.push(Mapping { path, deleted, content: proc_dir.join("exe") });
+    }
+
+    let maps = fs::read_to_string(proc_dir.join("maps")).unwrap_or_default();
+    for (range, path) in executable_mappings(&maps) {
+        let (path, deleted) = parse_path(&path);
+        if mappings.iter().any(|m| m.path == path) {
+            continue;
+        }
+        // map_files reaches deleted content but needs CAP_SYS_ADMIN
+        let content = if deleted {
+            proc_dir.join("map_files").join(&range)
+        } else {
+            proc_dir.join("root").join(path.trim_start_matches('/'))
+        };
+        mappings.push(Mapping { path, deleted, content });
+    }
+    mappings
+}
+
+fn process_name(pid: &str) -> String {
+    fs::read_to_string(Path::new("/proc").join(pid).join("comm"))
+        .map(|comm| comm.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Variables that make the dynamic linker load extra libraries into a process.
+const PRELOAD_VARIABLES: &[&str] = &["LD_PRELOAD", "LD_AUDIT"];
+
+/// Preload variables in a NUL-separated /proc/<pid>/environ, with the libraries they name.
+fn preloaded_libraries(environ: &[u8]) -> Vec<(&'static str, String)> {
+    let mut libraries = Vec::new();
+    for variable in environ.split(|&b| b == 0) {
+        let variable = String::from_utf8_lossy(variable);
+        let Some((name, value)) = variable.split_once('=') else {
+            continue;
+        };
+        let Some(name) = PRELOAD_VARIABLES.iter().find(|v| **v == name) else {
+            continue;
+        };
+        // ld.so accepts both colons and spaces as separators
+        for library in value.split([':', ' ']).filter(|l| !l.is_empty()) {
+            libraries.push((*name, library.to_string()));
+        }
+    }
+    libraries
+}
+
+fn is_allowed(library: &str, allowed: &[String]) -> bool {
+    let file_name = library.rsplit('/').next().unwrap_or(library);
+    allowed.iter().any(|a| a == library || a == file_name)
+}
+
+fn mount_namespace(pid: &str) -> Option<PathBuf> {
+    fs::read_link(Path::new("/proc").join(pid).join("ns/mnt")).ok()
+}
+
+/// Checks every process and returns the anomalies found.
+pub fn verify_processes(baseline: &BaselineIndex, allowed_preload: &[String]) -> Vec<AnomalyReport> {
+    let host_namespace = mount_namespace("1");
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return vec![AnomalyReport::other("PROCESS_CHECK_FAILED", "/proc", "cannot list processes")];
+    };
+
+    let mut anomalies = Vec::new();
+    // Content hashes of files on disk, shared by the processes running them
+    let mut hashes: HashMap<(String, HashAlgorithm), Option<String>> = HashMap::new();
+    let (mut checked, mut skipped) = (0, 0);
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let pid = entry.file_name().to_string_lossy().to_string();
+        if !pid.bytes().all(|b| b.is_ascii_digit()) {
+            continue;
+        }
+        if mount_namespace(&pid) != host_namespace {
+            skipped += 1;
+            continue;
+        }
+        let mappings = process_mappings(&pid);
+        if mappings.is_empty() {
+            // Kernel thread, or exited meanwhile
+            continue;
+        }
+        checked += 1;
+
+        let name = process_name(&pid);
+        let environ = fs::read(Path::new("/proc").join(&pid).join("environ")).unwrap_or_default();
+        for (variable, library) in preloaded_libraries(&environ) {
+            if !is_allowed(&library, allowed_preload) {
+                anomalies.push(AnomalyReport::other(
+                    "PRELOAD_INJECTION",
+                    library.trim_start_matches('/'),
+                    format!("pid {} {}: set in {}", pid, name, variable),
+                ));
+            }
+        }
+
+        for (i, mapping) in mappings.iter().enumerate() {
+            let kind = if i == 0 { "PROCESS_EXE" } else { "PROCESS_LIB" };
+            let relative = mapping.path.trim_start_matches('/');
+            let Some(entry) = baseline.get(relative) else {
+                anomalies.push(AnomalyReport::other(&format!("{}_UNKNOWN", kind), relative, format!("pid {} {}: not in baseline", pid, name)));
+                continue;
+            };
+
+            // Compared in the algorithm the baseline recorded; nothing to compare if metadata-only
+            let Some((algorithm, expected)) = entry.content_digest(baseline.hash_algorithm()) else {
+                if mapping.deleted {
+                    anomalies.push(AnomalyReport::other(
+                        &format!("{}_DELETED", kind),
+                        relative,
+                        format!("pid {} {}: running from a deleted file", pid, name),
+                    ));
+                }
+                continue;
+            };
+            let digest = if mapping.deleted {
+                compute_digest(&mapping.content, algorithm).ok()
+            } else {
+                hashes
+                    .entry((mapping.path.clone(), algorithm))
+                    .or_insert_with(|| compute_digest(&mapping.content, algorithm).ok())
+                    .clone()
+            };
+            match (mapping.deleted, digest) {
+                (true, digest) => {
+                    let content = match digest {
+                        Some(digest) if digest == expected => "content matches the baseline",
+                        Some(_) => "content does not match the baseline",
+                        None => "content unreadable",
+                    };
+                    anomalies.push(AnomalyReport::other(
+                        &format!("{}_DELETED", kind),
+                        relative,
+                        format!("pid {} {}: running from a deleted file, {}", pid, name, content),
+                    ));
+                }
+                (false, Some(digest)) if digest != expected => anomalies.push(AnomalyReport::other(
+                    &format!("{}_MODIFIED", kind),
+                    relative,
+                    format!("pid {} {}: hash mismatch: {} != {}", pid, name, expected, digest),
+                )),
+                (false, Some(_)) => {}
+                (false, None) => debug!("Cannot read {:?} for pid {}", mapping.content, pid),
+            }
+        }
+    }
+
+    info!("Verified {} processes ({} in container mount namespaces skipped)", checked, skipped);
+    anomalies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_maps() {
+        let maps = "\
+55d0c0a00000-55d0c0a2e000 r--p 00000000 fd:01 1048602                    /usr/bin/bash
+55d0c0a2e000-55d0c0b00000 r-xp 0002e000 fd:01 1048602                    /usr/bin/bash
+7f1a2c000000-7f1a2c1d0000 r-xp 00028000 fd:01 1050000                    /usr/lib/libc.so.6 (deleted)
+7f1a2c400000-7f1a2c401000 rw-p 00000000 00:00 0
+7ffd5e9f0000-7ffd5e9f2000 r-xp 00000000 00:00 0                          [vdso]
+";
+        let mut mappings = executable_mappings(maps);
+        mappings.sort();
+        assert_eq!(
+            mappings,
+            vec![
+                ("55d0c0a2e000-55d0c0b00000".to_string(), "/usr/bin/bash".to_string()),
+                ("7f1a2c000000-7f1a2c1d0000".to_string(), "/usr/lib/libc.so.6 (deleted)".to_string()),
+            ]
+        );
+        assert_eq!(parse_path("/usr/lib/libc.so.6 (deleted)"), ("/usr/lib/libc.so.6".to_string(), true));
+        assert_eq!(parse_path("/usr/bin/bash"), ("/usr/bin/bash".to_string(), false));
+    }
+
+    #[test]
+    fn test_preloaded_libraries() {
+        let environ = b"HOME=/root\0LD_PRELOAD=/tmp/evil.so:libjemalloc.so.2\0LD_AUDIT=/lib/a.so\0";
+        let libraries = preloaded_libraries(environ);
+        assert_eq!(
+            libraries,
+            vec![
+                ("LD_PRELOAD", "/tmp/evil.so".to_string()),
+                ("LD_PRELOAD", "libjemalloc.so.2".to_string()),
+                ("LD_AUDIT", "/lib/a.so".to_string()),
+            ]
+        );
+        let allowed = vec!["libjemalloc.so.2".to_string()];
+        assert!(is_allowed("/usr/lib/libjemalloc.so.2", &allowed));
+        assert!(!is_allowed("/tmp/evil.so", &allowed));
+    }
+}
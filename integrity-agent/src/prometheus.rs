@@ -0,0 +1,229 @@
+//! Prometheus metrics endpoint.
+//!
+//! With `--metrics-listen`, the agent serves `GET /metrics` in the Prometheus
+//! text format for as long as it runs: what the full scans covered (files,
+//! bytes, duration and time of the last one), anomalies reported by kind, the
+//! baseline in use and, in monitor and hybrid mode, the verification counters
+//! of [`pipeline::Metrics`]. Rates such as hash throughput and monitor events
+//! per second are left to PromQL, e.g. `rate(integrity_agent_monitor_events_total[5m])`.
+//! The counters live in [`METRICS`], so daemon mode keeps adding to them
+//! across passes.
+
+use crate::pipeline;
+use integrity_common::{AnomalyReport, Baseline, FileIntegrityEntry};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+
+/// Largest request head read before giving up on a connection.
+const MAX_REQUEST: usize = 8 * 1024;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct PrometheusArgs {
+    /// Address to serve Prometheus metrics on at /metrics, e.g. 127.0.0.1:9465
+    #[arg(long)]
+    pub metrics_listen: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone)]
+struct BaselineInfo {
+    image_id: String,
+    timestamp: String,
+    entries: usize,
+}
+
+/// What the agent did since it started.
+pub struct AgentMetrics {
+    files_scanned: AtomicU64,
+    bytes_scanned: AtomicU64,
+    scans: AtomicU64,
+    last_scan_timestamp: AtomicI64,
+    last_scan_duration_ms: AtomicU64,
+    anomalies: Mutex<BTreeMap<String, u64>>,
+    baseline: Mutex<Option<BaselineInfo>>,
+    pipeline: Mutex<Option<Arc<pipeline::Metrics>>>,
+}
+
+pub static METRICS: AgentMetrics = AgentMetrics {
+    files_scanned: AtomicU64::new(0),
+    bytes_scanned: AtomicU64::new(0),
+    scans: AtomicU64::new(0),
+    last_scan_timestamp: AtomicI64::new(0),
+    last_scan_duration_ms: AtomicU64::new(0),
+    anomalies: Mutex::new(BTreeMap::new()),
+    baseline: Mutex::new(None),
+    pipeline: Mutex::new(None),
+};
+
+impl AgentMetrics {
+    /// Records a full scan that found `entries` and took `duration`.
+    pub fn scan_finished(&self, entries: &HashMap<String, FileIntegrityEntry>, duration: Duration) {
+        let bytes: u64 = entries.values().filter_map(|entry| entry.size).sum();
+        self.files_scanned.fetch_add(entries.len() as u64, Ordering::Relaxed);
+        self.bytes_scanned.fetch_add(bytes, Ordering::Relaxed);
+        self.scans.fetch_add(1, Ordering::Relaxed);
+        self.last_scan_timestamp.store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+        self.last_scan_duration_ms.store(duration.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn anomaly(&self, anomaly: &AnomalyReport) {
+        *self.anomalies.lock().unwrap().entry(anomaly.kind().to_string()).or_default() += 1;
+    }
+
+    pub fn baseline(&self, baseline: &Baseline) {
+        *self.baseline.lock().unwrap() = Some(BaselineInfo {
+            image_id: baseline.image_id.clone(),
+            timestamp: baseline.timestamp.clone(),
+            entries: baseline.entries.len(),
+        });
+    }
+
+    /// Exposes the counters of the monitor mode verification pipeline.
+    pub fn monitor(&self, metrics: Arc<pipeline::Metrics>) {
+        *self.pipeline.lock().unwrap() = Some(metrics);
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}{} {}", name, labels, value);
+            }
+        };
+        let value = |counter: &AtomicU64| vec![(String::new(), counter.load(Ordering::Relaxed).to_string())];
+
+        metric("integrity_agent_files_scanned_total", "counter", "Files covered by full scans", &value(&self.files_scanned));
+        metric("integrity_agent_scanned_bytes_total", "counter", "Bytes of the files covered by full scans", &value(&self.bytes_scanned));
+        metric("integrity_agent_scans_total", "counter", "Full scans finished", &value(&self.scans));
+        let last_scan = self.last_scan_timestamp.load(Ordering::Relaxed);
+        metric(
+            "integrity_agent_last_scan_timestamp_seconds",
+            "gauge",
+            "Unix time the last full scan finished, 0 before the first",
+            &[(String::new(), last_scan.to_string())],
+        );
+        let duration = self.last_scan_duration_ms.load(Ordering::Relaxed) as f64 / 1000.0;
+        metric("integrity_agent_last_scan_duration_seconds", "gauge", "Duration of the last full scan", &[(String::new(), duration.to_string())]);
+
+        let anomalies: Vec<_> = self
+            .anomalies
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(kind, count)| (format!("{{kind=\"{}\"}}", escape(kind)), count.to_string()))
+            .collect();
+        metric("integrity_agent_anomalies_total", "counter", "Anomalies reported, by kind", &anomalies);
+
+        if let Some(baseline) = self.baseline.lock().unwrap().clone() {
+            let labels = format!("{{image_id=\"{}\",timestamp=\"{}\"}}", escape(&baseline.image_id), escape(&baseline.timestamp));
+            metric("integrity_agent_baseline_info", "gauge", "Baseline in use, by image and collection time", &[(labels, "1".to_string())]);
+            metric("integrity_agent_baseline_entries", "gauge", "Entries of the baseline in use", &[(String::new(), baseline.entries.to_string())]);
+        }
+
+        if let Some(pipeline) = self.pipeline.lock().unwrap().clone() {
+            for (name, value) in pipeline.snapshot() {
+                let (name, kind, help) = match name {
+                    "events" => ("integrity_agent_monitor_events_total".to_string(), "counter", "File events received from the monitor"),
+                    "queued" | "in_flight" => (format!("integrity_agent_verify_{}", name), "gauge", "Verifications waiting or running"),
+                    _ => (format!("integrity_agent_verify_{}_total", name), "counter", "Verification pipeline counter"),
+                };
+                metric(&name, kind, help, &[(String::new(), value.to_string())]);
+            }
+        }
+        out
+    }
+}
+
+/// A label value with backslashes, quotes and newlines escaped.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Serves [`METRICS`] on `addr` until the agent exits.
+pub async fn serve(addr: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Serving Prometheus metrics on http://{}/metrics", listener.local_addr()?);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(stream).await {
+                            debug!("Metrics connection error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Metrics listener accept failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Answers one request and closes the connection.
+async fn handle_connection(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..read]);
+    }
+    let request_line = String::from_utf8_lossy(&request);
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next().map(|target| target.split('?').next().unwrap_or(target))) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", "text/plain; version=0.0.4; charset=utf-8", METRICS.render()),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "Only GET is supported\n".to_string()),
+    };
+    let head = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, content_type, body.len());
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        serve(addr).await.unwrap();
+
+        let entry: FileIntegrityEntry = serde_json::from_str(r#"{"path": "etc/passwd", "sha512": "", "mode": 420, "uid": 0, "gid": 0, "size": 1024}"#).unwrap();
+        METRICS.scan_finished(&[(entry.path.clone(), entry)].into(), Duration::from_millis(1500));
+        METRICS.anomaly(&AnomalyReport::parse("MODIFIED: etc/passwd (hash mismatch)"));
+        let baseline: Baseline = serde_json::from_str(r#"{"image_id": "app \"v1\"", "timestamp": "2026-01-01T00:00:00Z", "entries": []}"#).unwrap();
+        METRICS.baseline(&baseline);
+
+        let get = |request: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+        let response = get("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("# TYPE integrity_agent_files_scanned_total counter\n"));
+        assert!(response.contains("\nintegrity_agent_last_scan_duration_seconds 1.5\n"));
+        assert!(response.contains("\nintegrity_agent_anomalies_total{kind=\"MODIFIED\"} "));
+        assert!(response.contains("\nintegrity_agent_baseline_info{image_id=\"app \\\"v1\\\"\",timestamp=\"2026-01-01T00:00:00Z\"} 1\n"));
+
+        assert!(get("GET / HTTP/1.1\r\n\r\n").await.starts_with("HTTP/1.1 404 "));
+        assert!(get("POST /metrics HTTP/1.1\r\n\r\n").await.starts_with("HTTP/1.1 405 "));
+    }
+}
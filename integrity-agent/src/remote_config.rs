@@ -0,0 +1,214 @@
+//! Agent settings managed on the metadata service (`--remote-config`).
+//!
+//! At startup the agent fetches the settings in effect for its host and image
+//! and applies them on top of its command line. Only the flags in
+//! `REMOTE_FLAGS` can be set remotely: the service decides what is watched,
+//! how often it is re-verified and where alerts go, not where baselines come
+//! from, which local programs run or which local files the agent writes:
+//! `wazuh-output` is only taken remotely when it names the Wazuh queue
+//! socket, never a `file:` target. The settings last fetched are kept in
+//! the state directory, so an agent starting while the service is down keeps
+//! them. In monitor mode the settings are polled every
+//! `--remote-config-interval`; when they change the agent flushes its sinks
+//! and re-executes itself, so the process (and its PID) stays the same.
+
+use integrity_client::MetadataClient;
+use integrity_common::{AgentSettings, EffectiveAgentConfig};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+const CACHE_FILE: &str = "agent-config.json";
+
+/// Flags the service may set.
+pub const REMOTE_FLAGS: &[&str] = &[
+    "watch-paths",
+    "allowed-preload",
+    "enumerate-persistence",
+    "kernel-modules",
+    "wazuh-output",
+    "falco-output",
+    "rescan-interval",
+    "rescan-rate",
+    "verify-budget",
+    "verify-budget-window",
+    "reverify-min",
+    "reverify-max",
+    "yara-paths",
+];
+
+/// Why the service may not set `flag` to `value`, for flags whose values
+/// could make the agent write local files.
+fn refused_value(flag: &str, value: &str) -> Option<&'static str> {
+    match flag {
+        "wazuh-output" => match value.parse() {
+            Ok(crate::output::wazuh::WazuhTarget::Socket(_)) => None,
+            _ => Some("only the Wazuh queue socket can be set remotely"),
+        },
+        _ => None,
+    }
+}
+
+/// Those of `REMOTE_FLAGS` that take no value.
+const SWITCHES: &[&str] = &["enumerate-persistence", "kernel-modules"];
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RemoteConfigArgs {
+    /// Apply the settings the metadata service stores for this host and image, and follow their changes in monitor mode
+    #[arg(long)]
+    pub remote_config: bool,
+
+    /// How often monitor mode checks for changed remote settings, e.g. 1m
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "1m")]
+    pub remote_config_interval: Duration,
+}
+
+/// The name settings are stored under for this host.
+pub fn host_name() -> String {
+    hostname::get().map(|h| h.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+/// Command line arguments setting `settings`, skipping flags the service may not set.
+pub fn to_args(settings: &AgentSettings) -> Vec<String> {
+    let mut args = Vec::new();
+    for (flag, value) in settings {
+        if !REMOTE_FLAGS.contains(&flag.as_str()) {
+            warn!("Ignoring remote setting {}: it cannot be set remotely", flag);
+        } else if let Some(reason) = refused_value(flag, value) {
+            warn!("Ignoring remote setting {}={}: {}", flag, value, reason);
+        } else if SWITCHES.contains(&flag.as_str()) {
+            // "false" leaves it off, even if the command line turns it on
+            if value == "true" {
+                args.push(format!("--{}", flag));
+            }
+        } else {
+            args.push(format!("--{}={}", flag, value));
+        }
+    }
+    args
+}
+
+/// `command_line` with the flags of `settings` replacing any the command line gives.
+pub fn override_args(command_line: impl IntoIterator<Item = OsString>, settings: &AgentSettings) -> Vec<OsString> {
+    let remote_args = to_args(settings);
+    let overridden = |arg: &str| {
+        settings.iter().any(|(flag, value)| {
+            REMOTE_FLAGS.contains(&flag.as_str()) && refused_value(flag, value).is_none() && arg == format!("--{}", flag)
+        })
+    };
+    let mut args = Vec::new();
+    let mut command_line = command_line.into_iter();
+    while let Some(arg) = command_line.next() {
+        let Some(text) = arg.to_str() else {
+            args.push(arg);
+            continue;
+        };
+        let (flag, inline_value) = match text.split_once('=') {
+            Some((flag, _)) => (flag, true),
+            None => (text, false),
+        };
+        if !overridden(flag) {
+            args.push(arg);
+        } else if !inline_value && !SWITCHES.contains(&flag.trim_start_matches('-')) {
+            // Drop the value as well
+            command_line.next();
+        }
+    }
+    args.extend(remote_args.into_iter().map(OsString::from));
+    args
+}
+
+fn cache_path(state_dir: &Path) -> PathBuf {
+    state_dir.join(CACHE_FILE)
+}
+
+fn load_cached(state_dir: &Path) -> Option<EffectiveAgentConfig> {
+    let cached = std::fs::read(cache_path(state_dir)).ok()?;
+    serde_json::from_slice(&cached).ok()
+}
+
+fn store_cached(state_dir: &Path, config: &EffectiveAgentConfig) {
+    let stored = std::fs::create_dir_all(state_dir)
+        .and_then(|_| std::fs::write(cache_path(state_dir), serde_json::to_vec(config).unwrap_or_default()));
+    if let Err(e) = stored {
+        warn!("Cannot keep the remote settings in {:?}: {}", state_dir, e);
+    }
+}
+
+/// The settings in effect for this host, or the ones last fetched if the service cannot be reached.
+pub async fn fetch(client: &MetadataClient, host: &str, image_id: &str, state_dir: &Path) -> Option<EffectiveAgentConfig> {
+    match client.effective_agent_config(host, image_id).await {
+        Ok(config) => {
+            store_cached(state_dir, &config);
+            Some(config)
+        }
+        Err(e) => {
+            let cached = load_cached(state_dir);
+            warn!("Cannot fetch the remote settings ({}), {}", e, if cached.is_some() { "using the ones last fetched" } else { "using the command line only" });
+            cached
+        }
+    }
+}
+
+/// Polls the settings every `interval` and sends them when they differ from `applied`.
+pub fn watch(
+    client: MetadataClient,
+    host: String,
+    image_id: String,
+    state_dir: PathBuf,
+    applied: EffectiveAgentConfig,
+    interval: Duration,
+) -> mpsc::Receiver<EffectiveAgentConfig> {
+    let (tx, rx) = mpsc::channel(1);
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(interval);
+        tick.tick().await;
+        loop {
+            tick.tick().await;
+            let config = match client.effective_agent_config(&host, &image_id).await {
+                Ok(config) => config,
+                Err(e) => {
+                    warn!("Cannot check the remote settings: {}", e);
+                    continue;
+                }
+            };
+            if config.settings != applied.settings {
+                info!("Remote settings changed to {}", config.revision());
+                store_cached(&state_dir, &config);
+                let _ = tx.send(config).await;
+                return;
+            }
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_override_args() {
+        let settings: AgentSettings = [
+            ("watch-paths", "/etc,/usr/bin"),
+            ("kernel-modules", "true"),
+            ("enumerate-persistence", "false"),
+            ("metadata-url", "http://attacker.example"),
+            ("wazuh-output", "file:/etc/cron.d/x"),
+        ]
+        .into_iter()
+        .map(|(flag, value)| (flag.to_string(), value.to_string()))
+        .collect();
+        assert_eq!(to_args(&settings), vec!["--kernel-modules", "--watch-paths=/etc,/usr/bin"]);
+        assert!(refused_value("wazuh-output", "socket").is_none());
+
+        let command_line = ["integrity-agent", "--watch-paths", "/opt", "--metadata-url=http://svc", "--kernel-modules", "--watch-paths=/srv", "--wazuh-output=socket"];
+        let args = override_args(command_line.map(OsString::from), &settings);
+        assert_eq!(
+            args,
+            ["integrity-agent", "--metadata-url=http://svc", "--wazuh-output=socket", "--kernel-modules", "--watch-paths=/etc,/usr/bin"].map(OsString::from)
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! Verifying files against entries looked up on the metadata service.
+//!
+//! With `--remote-lookup`, monitor mode does not fetch the whole baseline:
+//! each file it verifies has its entry looked up with
+//! `GET /baselines/{image_id}/entries?path=`, and the last
+//! `--remote-lookup-cache` answers are kept, paths the baseline does not have
+//! included. Small VMs then hold the entries of the files that change rather
+//! than the map of the whole image. The kept answers are dropped when the
+//! service answers from a baseline with another digest.
+//!
+//! Entries looked up one at a time cannot be checked against the baseline's
+//! signatures, so lookups need `--insecure-override` where signatures are
+//! required. Options that need the whole baseline (kernel modules,
+//! persistence enumeration, self-check paths, eBPF enforcement and execution
+//! monitoring) are refused, what the baseline records besides files (MAC
+//! policy, sysctls, listeners, kernel command line) is not checked, and
+//! re-scans after dropped events do not notice deleted files.
+
+use integrity_client::MetadataClient;
+use integrity_common::{Baseline, BaselineIndex, EntryLookup, FileIntegrityEntry, HashAlgorithm, Result};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing::info;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RemoteLookupArgs {
+    /// In monitor mode, look up the entry of each file on the metadata service instead of fetching the whole baseline
+    #[arg(long)]
+    pub remote_lookup: bool,
+
+    /// Entries looked up with --remote-lookup kept in memory
+    #[arg(long, default_value = "10000")]
+    pub remote_lookup_cache: usize,
+}
+
+/// What monitor mode runs with instead of the baseline: the image's
+/// identity and hash algorithm, without entries.
+pub async fn stand_in(client: &MetadataClient, image_id: &str) -> Result<Baseline> {
+    let summary = client.baseline_summary(image_id).await?;
+    info!("Looking up the {} entries of baseline {} (collected {}) file by file", summary.entries, summary.image_id, summary.timestamp);
+    Ok(Baseline {
+        image_id: summary.image_id,
+        timestamp: summary.timestamp,
+        hash_algorithm: summary.hash_algorithm,
+        ..Default::default()
+    })
+}
+
+#[derive(Default)]
+struct Kept {
+    /// Digest of the baseline the entries came from
+    digest: String,
+    hash_algorithm: HashAlgorithm,
+    /// Relative path -> (entry, last use)
+    entries: HashMap<String, (Option<FileIntegrityEntry>, u64)>,
+    clock: u64,
+}
+
+pub struct RemoteLookup {
+    client: MetadataClient,
+    image_id: String,
+    capacity: usize,
+    kept: Mutex<Kept>,
+}
+
+impl RemoteLookup {
+    pub fn new(args: &RemoteLookupArgs, client: MetadataClient, image_id: &str) -> Self {
+        Self { client, image_id: image_id.to_string(), capacity: args.remote_lookup_cache, kept: Mutex::default() }
+    }
+
+    /// An index holding the entry of `relative_path`, empty if the baseline
+    /// has none, to verify the file against.
+    pub async fn index(&self, relative_path: &str) -> Result<BaselineIndex> {
+        if let Some(index) = self.kept(relative_path) {
+            return Ok(index);
+        }
+        let lookup = self.client.lookup_entry(&self.image_id, relative_path).await?;
+        let index = BaselineIndex::from_entries(lookup.entry.clone()).with_hash_algorithm(lookup.hash_algorithm);
+        self.keep(relative_path, lookup);
+        Ok(index)
+    }
+
+    fn kept(&self, relative_path: &str) -> Option<BaselineIndex> {
+        let mut kept = self.kept.lock().unwrap();
+        kept.clock += 1;
+        let (tick, hash_algorithm) = (kept.clock, kept.hash_algorithm);
+        let (entry, last_use) = kept.entries.get_mut(relative_path)?;
+        *last_use = tick;
+        Some(BaselineIndex::from_entries(entry.clone()).with_hash_algorithm(hash_algorithm))
+    }
+
+    fn keep(&self, relative_path: &str, lookup: EntryLookup) {
+        let mut kept = self.kept.lock().unwrap();
+        if kept.digest != lookup.digest {
+            if !kept.digest.is_empty() {
+                info!("Baseline {} changed on the service, dropping {} looked up entries", self.image_id, kept.entries.len());
+            }
+            kept.entries.clear();
+            kept.digest = lookup.digest;
+            kept.hash_algorithm = lookup.hash_algorithm;
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        if kept.entries.len() >= self.capacity {
+            let oldest = kept.entries.iter().min_by_key(|(_, (_, last_use))| *last_use).map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                kept.entries.remove(&oldest);
+            }
+        }
+        kept.clock += 1;
+        let tick = kept.clock;
+        kept.entries.insert(relative_path.to_string(), (lookup.entry, tick));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_client::ClientConfig;
+
+    fn lookup(path: &str, digest: &str, sha512: Option<&str>) -> EntryLookup {
+        EntryLookup {
+            image_id: "img".to_string(),
+            path: path.to_string(),
+            digest: digest.to_string(),
+            hash_algorithm: HashAlgorithm::Sha256,
+            entry: sha512.map(|sha512| FileIntegrityEntry {
+                path: path.to_string(),
+                sha512: sha512.to_string(),
+                mode: 0o644,
+                uid: 0,
+                gid: 0,
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_keeps_recent_lookups_of_one_baseline() {
+        let client = MetadataClient::new(ClientConfig::new("http://127.0.0.1:9")).unwrap();
+        let remote = RemoteLookup::new(&RemoteLookupArgs { remote_lookup: true, remote_lookup_cache: 2 }, client, "img");
+
+        remote.keep("etc/hosts", lookup("etc/hosts", "d1", Some("aaa")));
+        remote.keep("etc/new", lookup("etc/new", "d1", None));
+        let hosts = remote.kept("etc/hosts").unwrap();
+        assert_eq!(hosts.get("etc/hosts").map(|entry| entry.sha512.as_str()), Some("aaa"));
+        assert_eq!(hosts.hash_algorithm(), HashAlgorithm::Sha256);
+        assert!(remote.kept("etc/new").unwrap().get("etc/new").is_none());
+
+        // etc/hosts is the least recently used and makes room
+        remote.keep("etc/passwd", lookup("etc/passwd", "d1", Some("bbb")));
+        assert!(remote.kept("etc/hosts").is_none());
+        assert!(remote.kept("etc/new").is_some());
+
+        // Another baseline digest drops what was looked up in the previous one
+        remote.keep("etc/hosts", lookup("etc/hosts", "d2", Some("ccc")));
+        assert!(remote.kept("etc/passwd").is_none());
+        assert!(remote.kept("etc/hosts").is_some());
+    }
+}
@@ -0,0 +1,157 @@
+//! Rolling re-scans for hybrid mode.
+//!
+//! Event monitors can miss changes: a queue overflow drops events, and events
+//! can be lost while the monitor is restarting. Hybrid mode starts with a full
+//! scan, for tampering done before the agent started, and then a background
+//! thread periodically walks the watch paths at a throttled rate and feeds
+//! every file into the monitor pipeline as a `Rescan` event, together with
+//! baselined files that have disappeared, so missed changes are eventually
+//! detected. The same thread runs targeted re-scans when a monitor reports
+//! dropped events.
+
+use crate::monitor::{EventType, FileEvent};
+use integrity_common::BaselineIndex;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::info;
+use walkdir::WalkDir;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RescanArgs {
+    /// Time between rolling re-scans of the watch paths in hybrid mode, e.g. 6h
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "6h")]
+    pub rescan_interval: Duration,
+
+    /// Files per second fed to verification during a re-scan, to keep it in the background
+    #[arg(long, default_value = "200")]
+    pub rescan_rate: u32,
+}
+
+/// Handle for queueing targeted re-scans, e.g. after the monitor dropped events.
+pub struct Rescanner {
+    requests: std::sync::mpsc::Sender<Vec<PathBuf>>,
+}
+
+impl Rescanner {
+    /// Re-scans `paths` as soon as the current pass (if any) is done.
+    pub fn request(&self, paths: Vec<PathBuf>) {
+        let _ = self.requests.send(paths);
+    }
+}
+
+/// Starts the re-scan thread. With `periodic`, the watch paths are re-scanned
+/// every `--rescan-interval`, the first time one interval after the start;
+/// otherwise only requested re-scans run.
+pub fn spawn(
+    args: &RescanArgs,
+    watch_paths: Vec<PathBuf>,
+    root: PathBuf,
+    baseline: Arc<BaselineIndex>,
+    periodic: bool,
+) -> (Rescanner, mpsc::Receiver<FileEvent>) {
+    // A small buffer so the re-scan waits for the pipeline instead of queueing ahead of it
+    let (tx, rx) = mpsc::channel(64);
+    let (requests, requested) = std::sync::mpsc::channel::<Vec<PathBuf>>();
+    let interval = args.rescan_interval;
+    let pause = Duration::from_secs(1) / args.rescan_rate.max(1);
+
+    std::thread::spawn(move || {
+        loop {
+            let requested_paths = if periodic {
+                match requested.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => Ok(watch_paths.clone()),
+                    received => received.map_err(|_| ()),
+                }
+            } else {
+                requested.recv().map_err(|_| ())
+            };
+            let Ok(mut paths) = requested_paths else {
+                return;
+            };
+            // Overflows come in bursts; one pass covers every request queued meanwhile
+            paths.extend(requested.try_iter().flatten());
+            paths.sort();
+            paths.dedup();
+
+            let started = std::time::Instant::now();
+            let Some(files) = scan_pass(&paths, &root, &baseline, pause, &tx) else {
+                return;
+            };
+            info!("Re-scan of {} files under {:?} finished in {:?}", files, paths, started.elapsed());
+        }
+    });
+    (Rescanner { requests }, rx)
+}
+
+/// Walks the watch paths once. Returns the number of files queued, or `None`
+/// once the pipeline has gone away.
+fn scan_pass(
+    watch_paths: &[PathBuf],
+    root: &Path,
+    baseline: &BaselineIndex,
+    pause: Duration,
+    tx: &mpsc::Sender<FileEvent>,
+) -> Option<usize> {
+    let send = |path: PathBuf| {
+        std::thread::sleep(pause);
+        tx.blocking_send(FileEvent { path, event_type: EventType::Rescan }).ok()
+    };
+
+    let mut files = 0;
+    for watch_path in watch_paths {
+        // Watch paths are chosen explicitly, so only special files are skipped
+        for entry in WalkDir::new(watch_path).follow_links(false).into_iter().filter_map(|e| e.ok()) {
+            if entry.file_type().is_dir() || entry.metadata().is_ok_and(|m| crate::platform::is_special_file(&m)) {
+                continue;
+            }
+            send(entry.into_path())?;
+            files += 1;
+        }
+
+        // Deletions leave nothing to walk over, so check the baseline side too
+        let prefix = watch_path.strip_prefix(root).unwrap_or(watch_path).to_string_lossy().trim_matches('/').to_string();
+        let missing: Vec<PathBuf> = baseline
+            .entries()
+            .map(|e| e.path.trim_start_matches('/'))
+            .filter(|p| prefix.is_empty() || *p == prefix || p.starts_with(&format!("{}/", prefix)))
+            .map(|p| root.join(p))
+            .filter(|p| p.symlink_metadata().is_err())
+            .collect();
+        for path in missing {
+            send(path)?;
+        }
+    }
+    Some(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::test_util::BaselineBuilder;
+
+    #[test]
+    fn test_scan_pass_reports_files_and_missing_entries() {
+        let root = std::env::temp_dir().join(format!("acropole-rescan-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("etc")).unwrap();
+        std::fs::write(root.join("etc/present"), b"x").unwrap();
+
+        let baseline = BaselineBuilder::new("img").size(0).file("etc/present", 0o644).file("etc/gone", 0o644).build();
+        let index = BaselineIndex::new(&baseline);
+        let (tx, mut rx) = mpsc::channel(16);
+        let files = scan_pass(&[root.join("etc")], &root, &index, Duration::ZERO, &tx);
+        drop(tx);
+
+        assert_eq!(files, Some(1));
+        let mut paths = Vec::new();
+        while let Some(event) = rx.blocking_recv() {
+            paths.push(event.path);
+        }
+        paths.sort();
+        assert_eq!(paths, vec![root.join("etc/gone"), root.join("etc/present")]);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
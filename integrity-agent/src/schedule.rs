@@ -0,0 +1,233 @@
+//! Adaptive re-verification for monitor mode.
+//!
+//! A log file rewritten every second or a config management run touching
+//! thousands of files would otherwise cost a full verification per event.
+//! Each path gets a budget of `--verify-budget` verifications per
+//! `--verify-budget-window`; events beyond it are coalesced into one
+//! verification when the window ends, so changes are still seen, only later.
+//! Paths that are anomalous are exempt from the budget.
+//!
+//! Paths that were touched or found anomalous are re-verified on their own:
+//! anomalous ones every `--reverify-min`, others at doubling intervals from
+//! `--reverify-min` until `--reverify-max`, after which they are considered
+//! stable and no longer tracked.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ScheduleArgs {
+    /// Verifications of one path per --verify-budget-window before further events are coalesced (0: no limit)
+    #[arg(long, default_value = "10")]
+    pub verify_budget: u32,
+
+    /// Window of --verify-budget, e.g. 1m
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "1m")]
+    pub verify_budget_window: Duration,
+
+    /// Re-verification interval of anomalous and just-changed paths, e.g. 1m
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "1m")]
+    pub reverify_min: Duration,
+
+    /// Re-verification interval after which an unchanged path is no longer re-verified, e.g. 1h
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "1h")]
+    pub reverify_max: Duration,
+}
+
+/// Most paths tracked at once; stable ones are dropped first when it is reached.
+const MAX_TRACKED_PATHS: usize = 10_000;
+
+/// Most re-verifications handed out per call to [`Scheduler::due`].
+const MAX_DUE_PER_TICK: usize = 100;
+
+#[derive(Debug)]
+struct PathState {
+    anomalous: bool,
+    /// Interval to the re-verification after the next
+    interval: Duration,
+    next_due: Option<Instant>,
+    window_start: Instant,
+    verifications: u32,
+    /// Events arrived over budget, verification deferred to the end of the window
+    pending: bool,
+}
+
+pub struct Scheduler {
+    args: ScheduleArgs,
+    paths: HashMap<PathBuf, PathState>,
+}
+
+impl Scheduler {
+    pub fn new(args: &ScheduleArgs) -> Self {
+        Self { args: args.clone(), paths: HashMap::new() }
+    }
+
+    /// Whether an event on `path` is verified now. If not, it is verified
+    /// once the path's budget window ends.
+    pub fn admit(&mut self, path: &Path, now: Instant) -> bool {
+        if self.args.verify_budget == 0 {
+            return true;
+        }
+        let (budget, window) = (self.args.verify_budget, self.args.verify_budget_window);
+        let state = self.state(path, now);
+        if now.duration_since(state.window_start) >= window {
+            state.window_start = now;
+            state.verifications = 0;
+        }
+        if state.anomalous || state.verifications < budget {
+            state.verifications += 1;
+            return true;
+        }
+        state.pending = true;
+        false
+    }
+
+    /// Records the result of verifying `path`. `touched` is false for
+    /// re-scans and re-verifications, which only update paths already
+    /// tracked or found anomalous.
+    pub fn record(&mut self, path: &Path, anomalous: bool, touched: bool, now: Instant) {
+        if !touched && !anomalous && !self.paths.contains_key(path) {
+            return;
+        }
+        let (min, max) = (self.args.reverify_min, self.args.reverify_max);
+        let state = self.state(path, now);
+        if anomalous || touched {
+            state.interval = min;
+        }
+        state.anomalous = anomalous;
+        if state.interval > max {
+            // Stable long enough
+            self.paths.remove(path);
+            return;
+        }
+        state.next_due = Some(now + state.interval);
+        if !anomalous {
+            state.interval *= 2;
+        }
+    }
+
+    /// Paths to verify now: deferred events whose window has ended, and
+    /// re-verifications that are due. At most [`MAX_DUE_PER_TICK`], the rest
+    /// stay due for the next call.
+    pub fn due(&mut self, now: Instant) -> Vec<PathBuf> {
+        let window = self.args.verify_budget_window;
+        let mut due = Vec::new();
+        for (path, state) in &mut self.paths {
+            if due.len() == MAX_DUE_PER_TICK {
+                break;
+            }
+            let deferred = state.pending && now.duration_since(state.window_start) >= window;
+            if deferred || state.next_due.is_some_and(|at| at <= now) {
+                state.pending = false;
+                // Until the result is recorded
+                state.next_due = None;
+                due.push(path.clone());
+            }
+        }
+        due
+    }
+
+    /// Whether `path` was anomalous when last verified.
+    pub fn is_anomalous(&self, path: &Path) -> bool {
+        self.paths.get(path).is_some_and(|state| state.anomalous)
+    }
+
+    fn state(&mut self, path: &Path, now: Instant) -> &mut PathState {
+        if !self.paths.contains_key(path) && self.paths.len() >= MAX_TRACKED_PATHS {
+            self.evict();
+        }
+        self.paths.entry(path.to_path_buf()).or_insert_with(|| PathState {
+            anomalous: false,
+            interval: self.args.reverify_min,
+            next_due: None,
+            window_start: now,
+            verifications: 0,
+            pending: false,
+        })
+    }
+
+    /// Drops the stable path closest to being quiesced, or any path if all are anomalous.
+    fn evict(&mut self) {
+        let victim = self
+            .paths
+            .iter()
+            .filter(|(_, state)| !state.pending)
+            .max_by_key(|(_, state)| (!state.anomalous, state.interval))
+            .or_else(|| self.paths.iter().next())
+            .map(|(path, _)| path.clone());
+        if let Some(victim) = victim {
+            self.paths.remove(&victim);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args() -> ScheduleArgs {
+        ScheduleArgs {
+            verify_budget: 2,
+            verify_budget_window: Duration::from_secs(60),
+            reverify_min: Duration::from_secs(60),
+            reverify_max: Duration::from_secs(240),
+        }
+    }
+
+    #[test]
+    fn test_budget_coalesces_events() {
+        let mut scheduler = Scheduler::new(&args());
+        let path = Path::new("/var/lib/app/state");
+        let start = Instant::now();
+        assert!(scheduler.admit(path, start));
+        assert!(scheduler.admit(path, start));
+        assert!(!scheduler.admit(path, start));
+        assert!(!scheduler.admit(path, start + Duration::from_secs(30)));
+        assert!(scheduler.due(start + Duration::from_secs(30)).is_empty());
+        // One verification for everything deferred
+        assert_eq!(scheduler.due(start + Duration::from_secs(60)), vec![path.to_path_buf()]);
+        assert!(scheduler.due(start + Duration::from_secs(61)).is_empty());
+        // A new window has a new budget
+        assert!(scheduler.admit(path, start + Duration::from_secs(61)));
+
+        // Anomalous paths are always verified
+        scheduler.record(path, true, true, start);
+        for _ in 0..5 {
+            assert!(scheduler.admit(path, start + Duration::from_secs(62)));
+        }
+    }
+
+    #[test]
+    fn test_reverification_backs_off_until_stable() {
+        let mut scheduler = Scheduler::new(&args());
+        let path = PathBuf::from("/etc/hosts");
+        let mut now = Instant::now();
+
+        // Untracked paths are not picked up by re-scans
+        scheduler.record(&path, false, false, now);
+        assert!(scheduler.paths.is_empty());
+
+        scheduler.record(&path, false, true, now);
+        let mut intervals = Vec::new();
+        let mut last = now;
+        while !scheduler.paths.is_empty() {
+            now += Duration::from_secs(30);
+            if scheduler.due(now) == vec![path.clone()] {
+                intervals.push((now - last).as_secs());
+                last = now;
+                scheduler.record(&path, false, false, now);
+            }
+        }
+        assert_eq!(intervals, vec![60, 120, 240]);
+
+        // Anomalous paths stay at the shortest interval
+        scheduler.record(&path, true, false, now);
+        for _ in 0..3 {
+            now += Duration::from_secs(60);
+            assert_eq!(scheduler.due(now), vec![path.clone()]);
+            scheduler.record(&path, true, false, now);
+        }
+        assert!(scheduler.is_anomalous(&path));
+    }
+}
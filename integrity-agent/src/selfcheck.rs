@@ -0,0 +1,98 @@
+//! Startup verification of the agent's own components.
+//!
+//! An agent running from a tampered binary, or fed a tampered config or
+//! baseline, can report a clean system no matter what. Before any results are
+//! trusted the agent verifies its own executable and the files listed with
+//! `--self-check-paths` against the baseline (or `--self-sha512` for the binary),
+//! and the fetched baseline against `--baseline-digest` when one is pinned.
+
+use crate::compute_sha512;
+use integrity_common::{AnomalyReport, Baseline, BaselineIndex, Severity};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// What to do when a self-check fails.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum FailureAction {
+    /// Exit without verifying anything
+    Refuse,
+    /// Report the failure as an anomaly and keep running
+    Alarm,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SelfCheckArgs {
+    /// Expected SHA-512 of the agent binary, for agents installed outside the baseline
+    #[arg(long)]
+    pub self_sha512: Option<String>,
+
+    /// Additional files the agent depends on (configs, unit files) that must match the baseline
+    #[arg(long, value_delimiter = ',')]
+    pub self_check_paths: Vec<PathBuf>,
+
+    /// Expected digest of the fetched baseline, after any overlays (see `Baseline::digest`)
+    #[arg(long)]
+    pub baseline_digest: Option<String>,
+
+    /// Behaviour when the agent's own components fail verification
+    #[arg(long, value_enum, default_value = "refuse")]
+    pub self_check_failure: FailureAction,
+}
+
+fn tampered(path: &str, detail: &str) -> AnomalyReport {
+    AnomalyReport::other("SELF_TAMPERED", path, detail).with_severity(Severity::Critical)
+}
+
+/// Runs every self-check and returns the failures as anomalies.
+///
+/// `root` is where the baselined filesystem is visible; `binary_in_baseline`
+/// is false when the agent runs from a container image the host baseline
+/// does not cover.
+pub async fn verify(args: &SelfCheckArgs, baseline: &Baseline, root: &Path, binary_in_baseline: bool) -> Vec<AnomalyReport> {
+    let mut failures = Vec::new();
+
+    if let Some(expected) = &args.baseline_digest {
+        match baseline.digest() {
+            Ok(digest) if digest.eq_ignore_ascii_case(expected) => {}
+            Ok(digest) => failures.push(tampered(&baseline.image_id, &format!("baseline digest {} != {}", expected, digest))),
+            Err(e) => failures.push(tampered(&baseline.image_id, &format!("cannot digest baseline: {}", e))),
+        }
+    }
+
+    let index = BaselineIndex::new(baseline);
+
+    match std::env::current_exe() {
+        Ok(exe) => {
+            let relative = exe.strip_prefix(root).unwrap_or(&exe).to_string_lossy().to_string();
+            if let Some(expected) = &args.self_sha512 {
+                match compute_sha512(&exe) {
+                    Ok(sha512) if sha512.eq_ignore_ascii_case(expected) => {}
+                    Ok(sha512) => failures.push(tampered(&relative, &format!("hash mismatch: {} != {}", expected, sha512))),
+                    Err(e) => failures.push(tampered(&relative, &format!("cannot hash agent binary: {}", e))),
+                }
+            } else if binary_in_baseline && index.contains(&relative) {
+                if let Some(anomaly) = crate::verify_file(&exe, root, &index, None).await {
+                    failures.push(tampered(&relative, &anomaly.to_string()));
+                }
+            } else {
+                warn!("Agent binary {:?} is not in the baseline and --self-sha512 is not set, skipping its check", exe);
+            }
+        }
+        Err(e) => failures.push(tampered("agent", &format!("cannot locate agent binary: {}", e))),
+    }
+
+    for path in &args.self_check_paths {
+        let relative = path.strip_prefix("/").unwrap_or(path);
+        let absolute = root.join(relative);
+        let relative = relative.to_string_lossy();
+        if !index.contains(&relative) {
+            failures.push(tampered(&relative, "not in baseline"));
+        } else if let Some(anomaly) = crate::verify_file(&absolute, root, &index, None).await {
+            failures.push(tampered(&relative, &anomaly.to_string()));
+        } else {
+            debug!("Self-check passed for {}", relative);
+        }
+    }
+
+    failures
+}
@@ -0,0 +1,100 @@
+//! Required signatures on fetched baselines.
+//!
+//! With `--require-signers`, the agent verifies the signature chain of every
+//! baseline it fetches (each layer, for `--overlay`) and refuses to verify
+//! against one missing a signature of a required role by a key
+//! `--trusted-signer` lists for that role, e.g. to enforce that the security
+//! team approved every baseline the collector uploaded.
+//!
+//! With `--trusted-pubkey`, or a baseline-signing key embedded at build time
+//! (see `trust`), it refuses baselines that no trusted key signed, in any
+//! role: unsigned ones, and ones altered after signing, whose digest no longer
+//! has the signatures.
+
+use integrity_client::MetadataClient;
+use integrity_common::{check_chain, Baseline, BaselineSignature, IntegrityError, Result, SignerRole};
+use tracing::info;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SignatureArgs {
+    /// Signer roles every fetched baseline must be signed by (collector, approver, service)
+    #[arg(long, value_delimiter = ',')]
+    pub require_signers: Vec<SignerRole>,
+
+    /// A public key (hex) trusted to sign as a role, as ROLE=KEY; repeat for each key
+    #[arg(long, value_parser = parse_trusted_signer)]
+    pub trusted_signer: Vec<(SignerRole, String)>,
+
+    /// Public key (hex) every fetched baseline must be signed by, in any role; repeat for each key
+    #[arg(long, value_parser = parse_public_key)]
+    pub trusted_pubkey: Vec<String>,
+}
+
+pub(crate) fn parse_public_key(key: &str) -> std::result::Result<String, String> {
+    let key = key.trim();
+    if key.len() != 64 || !key.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("{:?} is not a hex Ed25519 public key", key));
+    }
+    Ok(key.to_ascii_lowercase())
+}
+
+fn parse_trusted_signer(value: &str) -> std::result::Result<(SignerRole, String), String> {
+    let (role, key) = value.split_once('=').ok_or_else(|| format!("expected ROLE=KEY, got {:?}", value))?;
+    Ok((role.trim().parse()?, parse_public_key(key)?))
+}
+
+/// Whether baselines must be checked against their signature chain.
+pub fn required(args: &SignatureArgs, trusted_pubkeys: &[String]) -> bool {
+    !args.require_signers.is_empty() || !trusted_pubkeys.is_empty()
+}
+
+/// Fetches the signature chain of `baseline`, if it is needed to check it.
+pub async fn fetch_chain(
+    args: &SignatureArgs,
+    trusted_pubkeys: &[String],
+    client: &MetadataClient,
+    baseline: &Baseline,
+) -> Result<Vec<BaselineSignature>> {
+    if !required(args, trusted_pubkeys) {
+        return Ok(Vec::new());
+    }
+    client.baseline_signatures(&baseline.image_id, Some(&baseline.digest()?)).await
+}
+
+/// Checks the signature chain of `baseline` against the required signers and
+/// `trusted_pubkeys`, one of which must have signed it.
+pub fn check(args: &SignatureArgs, trusted_pubkeys: &[String], baseline: &Baseline, chain: &[BaselineSignature]) -> Result<()> {
+    if !required(args, trusted_pubkeys) {
+        return Ok(());
+    }
+    let digest = baseline.digest()?;
+    let signed_by_trusted_key = || {
+        if trusted_pubkeys.is_empty() || chain.iter().any(|link| trusted_pubkeys.contains(&link.public_key.to_ascii_lowercase())) {
+            Ok(())
+        } else {
+            Err(IntegrityError::Signature("no signature by a trusted key".to_string()))
+        }
+    };
+    check_chain(chain, &digest, &args.require_signers, &args.trusted_signer).and_then(|()| signed_by_trusted_key()).map_err(|e| match e {
+        IntegrityError::Signature(reason) => IntegrityError::Signature(format!("baseline {} ({}): {}", baseline.image_id, digest, reason)),
+        e => e,
+    })?;
+    info!("Baseline {} carries the required signatures ({} in its chain)", baseline.image_id, chain.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_trusted_signer() {
+        let key = "AB".repeat(32);
+        assert_eq!(parse_trusted_signer(&format!("approver={}", key)), Ok((SignerRole::Approver, key.to_ascii_lowercase())));
+        assert!(parse_trusted_signer(&key).is_err());
+        assert!(parse_trusted_signer("approver=abcd").is_err());
+        assert!(parse_trusted_signer(&format!("auditor={}", key)).is_err());
+        assert_eq!(parse_public_key(&format!(" {}\n", key)), Ok(key.to_ascii_lowercase()));
+        assert!(parse_public_key("zz").is_err());
+    }
+}
@@ -0,0 +1,247 @@
+//! The agent's local state store.
+//!
+//! Anomalies detected in monitor mode are persisted here with their resolution
+//! status, so investigations don't depend on whether logs were shipped. An
+//! anomaly is resolved once a later event on the same path verifies clean.
+//!
+//! The history is an append-only JSON Lines journal of `anomaly` and
+//! `resolved` records, so `integrity-agent history` can read it while the
+//! agent is running; resolution status is folded in on read.
+//!
+//! The image ID of the baseline last verified against is kept next to it, so
+//! a host that comes up on a new image version is reported as a baseline
+//! switch rather than as drift.
+
+use integrity_common::AnomalyReport;
+use integrity_common::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+const HISTORY_FILE: &str = "anomalies.jsonl";
+const ACTIVE_BASELINE_FILE: &str = "active-baseline";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum JournalRecord {
+    Anomaly {
+        time: i64,
+        kind: String,
+        path: String,
+        detail: String,
+    },
+    Resolved {
+        time: i64,
+        path: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AnomalyRecord {
+    pub time: i64,
+    pub kind: String,
+    pub path: String,
+    pub detail: String,
+    /// When a later check found the file back in line with the baseline
+    pub resolved_at: Option<i64>,
+}
+
+/// Filters for `history`.
+#[derive(Debug, Default)]
+pub struct HistoryQuery {
+    /// Only anomalies at or after this Unix timestamp
+    pub since: Option<i64>,
+    /// Only anomalies at or below this path
+    pub path: Option<String>,
+    pub unresolved_only: bool,
+}
+
+impl HistoryQuery {
+    fn matches(&self, record: &AnomalyRecord) -> bool {
+        if self.since.is_some_and(|since| record.time < since) {
+            return false;
+        }
+        if self.unresolved_only && record.resolved_at.is_some() {
+            return false;
+        }
+        match self.path.as_deref().map(|p| p.trim_matches('/')) {
+            None | Some("") => true,
+            Some(prefix) => {
+                let path = record.path.trim_start_matches('/');
+                path == prefix || path.starts_with(&format!("{}/", prefix))
+            }
+        }
+    }
+}
+
+pub struct StateStore {
+    journal: Mutex<File>,
+    /// Paths with open anomalies, so clean events elsewhere don't touch the journal
+    open_paths: Mutex<HashSet<String>>,
+}
+
+/// Replays the journal into anomaly records with their resolution status.
+fn replay(path: &Path) -> Result<Vec<AnomalyRecord>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut records: Vec<AnomalyRecord> = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // A torn final line from a crash mid-write is skipped, not fatal
+        let Ok(record) = serde_json::from_str::<JournalRecord>(&line) else {
+            continue;
+        };
+        match record {
+            JournalRecord::Anomaly { time, kind, path, detail } => records.push(AnomalyRecord {
+                time,
+                kind,
+                path,
+                detail,
+                resolved_at: None,
+            }),
+            JournalRecord::Resolved { time, path } => {
+                for record in records.iter_mut().filter(|r| r.path == path && r.resolved_at.is_none()) {
+                    record.resolved_at = Some(time);
+                }
+            }
+        }
+    }
+    Ok(records)
+}
+
+impl StateStore {
+    pub fn open(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(HISTORY_FILE);
+        let open_paths = replay(&path)?
+            .into_iter()
+            .filter(|r| r.resolved_at.is_none())
+            .map(|r| r.path)
+            .collect();
+        let journal = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            journal: Mutex::new(journal),
+            open_paths: Mutex::new(open_paths),
+        })
+    }
+
+    fn append(&self, record: &JournalRecord) -> Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.journal.lock().unwrap().write_all(&line)?;
+        Ok(())
+    }
+
+    /// Stores a newly detected anomaly.
+    pub fn record_anomaly(&self, anomaly: &AnomalyReport) -> Result<()> {
+        self.append(&JournalRecord::Anomaly {
+            time: anomaly.timestamp,
+            kind: anomaly.kind().to_string(),
+            path: anomaly.path.clone(),
+            detail: anomaly.detail(),
+        })?;
+        self.open_paths.lock().unwrap().insert(anomaly.path.clone());
+        Ok(())
+    }
+
+    /// Marks the open anomalies on `path` as resolved. Returns whether there were any.
+    pub fn resolve_path(&self, path: &str, time: i64) -> Result<bool> {
+        if !self.open_paths.lock().unwrap().remove(path) {
+            return Ok(false);
+        }
+        self.append(&JournalRecord::Resolved {
+            time,
+            path: path.to_string(),
+        })?;
+        Ok(true)
+    }
+
+    /// Whether `path` has an anomaly that has not been resolved yet.
+    pub fn is_open(&self, path: &str) -> bool {
+        self.open_paths.lock().unwrap().contains(path)
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.journal.lock().unwrap().sync_data()?;
+        Ok(())
+    }
+}
+
+/// Anomalies matching the query, oldest first. Safe to call while the agent is running.
+pub fn read_history(dir: &Path, query: &HistoryQuery) -> Result<Vec<AnomalyRecord>> {
+    Ok(replay(&dir.join(HISTORY_FILE))?
+        .into_iter()
+        .filter(|r| query.matches(r))
+        .collect())
+}
+
+/// Remembers the baseline this run verifies against. Returns the previous
+/// one if it was a different one.
+pub fn switch_baseline(dir: &Path, image_id: &str) -> Result<Option<String>> {
+    let path = dir.join(ACTIVE_BASELINE_FILE);
+    let previous = std::fs::read_to_string(&path).ok().map(|s| s.trim().to_string());
+    if previous.as_deref() == Some(image_id) {
+        return Ok(None);
+    }
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(&path, image_id)?;
+    Ok(previous)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_resolve_and_query() {
+        let dir = std::env::temp_dir().join(format!("acropole-state-{}", std::process::id()));
+        let store = StateStore::open(&dir).unwrap();
+
+        let mut passwd = AnomalyReport::parse("MODIFIED: etc/passwd (hash mismatch)");
+        passwd.timestamp = 1_000;
+        let mut ls = AnomalyReport::parse("ADDED: usr/bin/ls");
+        ls.timestamp = 2_000;
+        store.record_anomaly(&passwd).unwrap();
+        store.record_anomaly(&ls).unwrap();
+
+        assert!(store.resolve_path("etc/passwd", 3_000).unwrap());
+        assert!(!store.resolve_path("etc/passwd", 4_000).unwrap());
+
+        let all = read_history(&dir, &HistoryQuery::default()).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].resolved_at, Some(3_000));
+
+        let etc = HistoryQuery { path: Some("/etc".to_string()), ..Default::default() };
+        assert_eq!(read_history(&dir, &etc).unwrap().len(), 1);
+        let recent = HistoryQuery { since: Some(1_500), ..Default::default() };
+        assert_eq!(read_history(&dir, &recent).unwrap()[0].path, "usr/bin/ls");
+
+        // Open anomalies survive a restart
+        drop(store);
+        let store = StateStore::open(&dir).unwrap();
+        assert!(store.resolve_path("usr/bin/ls", 5_000).unwrap());
+        let open = HistoryQuery { unresolved_only: true, ..Default::default() };
+        assert!(read_history(&dir, &open).unwrap().is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_switch_baseline() {
+        let dir = std::env::temp_dir().join(format!("acropole-switch-{}", std::process::id()));
+        assert_eq!(switch_baseline(&dir, "flatcar-3815.2.0").unwrap(), None);
+        assert_eq!(switch_baseline(&dir, "flatcar-3815.2.0").unwrap(), None);
+        assert_eq!(switch_baseline(&dir, "flatcar-3815.2.1").unwrap().as_deref(), Some("flatcar-3815.2.0"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
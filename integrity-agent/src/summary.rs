@@ -0,0 +1,202 @@
+//! Exit codes and the summary of a run.
+//!
+//! Every run ends with one of the `Outcome`s, whose exit code wrappers and
+//! orchestration can branch on, and writes a small JSON summary (outcome,
+//! duration, baseline, anomaly counts by kind) to `--summary-file`, by
+//! default `last-run.json` in the state directory. Invalid command lines are
+//! rejected before a run starts, with exit code 2 like config errors, and
+//! write no summary.
+
+use integrity_common::AnomalyReport;
+use integrity_common::{Baseline, IntegrityError};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::warn;
+
+/// Summary file in the state directory unless --summary-file says otherwise.
+pub const SUMMARY_FILE: &str = "last-run.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// Everything verified
+    Clean,
+    /// Anomalies were found, or monitoring failed closed
+    AnomaliesFound,
+    /// The baseline could not be fetched or resolved, or lacks required signatures
+    BaselineUnavailable,
+    /// Invalid configuration, e.g. a missing scan path or an unsupported mode
+    ConfigError,
+    /// Anything else that stopped the run
+    InternalError,
+}
+
+impl Outcome {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Outcome::Clean => 0,
+            Outcome::AnomaliesFound => 1,
+            // The code clap exits with on invalid arguments
+            Outcome::ConfigError => 2,
+            Outcome::BaselineUnavailable => 3,
+            Outcome::InternalError => 4,
+        }
+    }
+
+    /// The outcome as the summary file names it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Outcome::Clean => "clean",
+            Outcome::AnomaliesFound => "anomalies_found",
+            Outcome::BaselineUnavailable => "baseline_unavailable",
+            Outcome::ConfigError => "config_error",
+            Outcome::InternalError => "internal_error",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct BaselineSummary {
+    image_id: String,
+    /// Collection time of the baseline, standing for its version
+    timestamp: String,
+    entries: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Summary<'a> {
+    outcome: Outcome,
+    exit_code: i32,
+    mode: &'a str,
+    /// Unix time the run started
+    started_at: i64,
+    duration_ms: u128,
+    baseline: Option<BaselineSummary>,
+    anomalies: usize,
+    anomalies_by_kind: BTreeMap<String, usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Collects what a run did, for its summary.
+pub struct RunSummary {
+    path: PathBuf,
+    mode: String,
+    started: Instant,
+    started_at: i64,
+    baseline: Mutex<Option<BaselineSummary>>,
+    anomalies: Mutex<BTreeMap<String, usize>>,
+}
+
+impl RunSummary {
+    pub fn new(path: PathBuf, mode: String) -> Self {
+        Self {
+            path,
+            mode,
+            started: Instant::now(),
+            started_at: chrono::Utc::now().timestamp(),
+            baseline: Mutex::new(None),
+            anomalies: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Records the baseline the run verifies against.
+    pub fn baseline(&self, baseline: &Baseline) {
+        crate::prometheus::METRICS.baseline(baseline);
+        *self.baseline.lock().unwrap() = Some(BaselineSummary {
+            image_id: baseline.image_id.clone(),
+            timestamp: baseline.timestamp.clone(),
+            entries: baseline.entries.len(),
+        });
+    }
+
+    pub fn count(&self, anomaly: &AnomalyReport) {
+        crate::prometheus::METRICS.anomaly(anomaly);
+        *self.anomalies.lock().unwrap().entry(anomaly.kind().to_string()).or_default() += 1;
+    }
+
+    /// Anomalies counted so far.
+    pub fn anomalies(&self) -> usize {
+        self.anomalies.lock().unwrap().values().sum()
+    }
+
+    /// The outcome of a run that completed: anomalies found, or clean.
+    pub fn outcome(&self) -> Outcome {
+        if self.anomalies.lock().unwrap().is_empty() {
+            Outcome::Clean
+        } else {
+            Outcome::AnomaliesFound
+        }
+    }
+
+    /// The outcome of a run that stopped with `error`: failures reaching the
+    /// service before the baseline is known mean it is unavailable.
+    pub fn outcome_of(&self, error: &IntegrityError) -> Outcome {
+        match error {
+            IntegrityError::Validation(_) => Outcome::ConfigError,
+            IntegrityError::BaselineNotFound(_) | IntegrityError::Signature(_) => Outcome::BaselineUnavailable,
+            IntegrityError::Storage(_) if self.baseline.lock().unwrap().is_none() => Outcome::BaselineUnavailable,
+            _ => Outcome::InternalError,
+        }
+    }
+
+    /// Writes the summary. Returns the exit code of `outcome`.
+    pub fn finish(&self, outcome: Outcome, error: Option<&IntegrityError>) -> i32 {
+        let anomalies_by_kind = self.anomalies.lock().unwrap().clone();
+        let summary = Summary {
+            outcome,
+            exit_code: outcome.exit_code(),
+            mode: &self.mode,
+            started_at: self.started_at,
+            duration_ms: self.started.elapsed().as_millis(),
+            baseline: self.baseline.lock().unwrap().clone(),
+            anomalies: anomalies_by_kind.values().sum(),
+            anomalies_by_kind,
+            error: error.map(|e| e.to_string()),
+        };
+        let written = serde_json::to_vec_pretty(&summary).map_err(std::io::Error::other).and_then(|json| {
+            if let Some(dir) = self.path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(&self.path, json)
+        });
+        if let Err(e) = written {
+            warn!("Cannot write the run summary to {:?}: {}", self.path, e);
+        }
+        outcome.exit_code()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_file() {
+        let path = std::env::temp_dir().join(format!("acropole-summary-{}", std::process::id())).join(SUMMARY_FILE);
+        let summary = RunSummary::new(path.clone(), "Scan".to_string());
+        assert_eq!(summary.outcome(), Outcome::Clean);
+        let error = IntegrityError::Storage("connection refused".to_string());
+        assert_eq!(summary.outcome_of(&error), Outcome::BaselineUnavailable);
+
+        let baseline: Baseline =
+            serde_json::from_str(r#"{"image_id": "app-v1", "timestamp": "2026-01-01T00:00:00Z", "entries": []}"#).unwrap();
+        summary.baseline(&baseline);
+        assert_eq!(summary.outcome_of(&error), Outcome::InternalError);
+        for anomaly in ["MODIFIED: etc/passwd (hash mismatch)", "ADDED: tmp/x", "ADDED: tmp/y"] {
+            summary.count(&AnomalyReport::parse(anomaly));
+        }
+        assert_eq!((summary.outcome(), summary.anomalies()), (Outcome::AnomaliesFound, 3));
+        assert_eq!(summary.finish(summary.outcome(), None), 1);
+
+        let written: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(written["outcome"], Outcome::AnomaliesFound.name());
+        assert_eq!(written["anomalies"], 3);
+        assert_eq!(written["anomalies_by_kind"]["ADDED"], 2);
+        assert_eq!(written["baseline"]["image_id"], "app-v1");
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
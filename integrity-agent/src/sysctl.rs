@@ -0,0 +1,71 @@
+//! Sysctl drift detection (Linux).
+//!
+//! Compares `/proc/sys` against the sysctl values recorded in the baseline:
+//! once in scan mode, and by polling in monitor mode, since writes to
+//! `/proc/sys` raise no file events.
+
+use integrity_common::{read_sysctls, sysctl_changes, sysctl_path, AnomalyReport};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+fn net_namespace(pid: &str) -> Option<std::path::PathBuf> {
+    std::fs::read_link(Path::new("/proc").join(pid).join("ns/net")).ok()
+}
+
+/// The recorded sysctls that can be checked from here. `net.*` sysctls belong
+/// to the network namespace, so a pod without `hostNetwork` sees its own.
+fn checkable(recorded: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    if recorded.is_empty() || net_namespace("self") == net_namespace("1") {
+        return recorded.clone();
+    }
+    warn!("Not in the host network namespace, net.* sysctls are not checked");
+    recorded.iter().filter(|(name, _)| !name.starts_with("net.")).map(|(n, v)| (n.clone(), v.clone())).collect()
+}
+
+/// Recorded sysctls whose current value differs, as (name, current value, anomaly).
+fn changed(recorded: &BTreeMap<String, String>) -> Vec<(String, Option<String>, AnomalyReport)> {
+    let current = read_sysctls(Path::new("/"), recorded.keys().map(String::as_str));
+    sysctl_changes(recorded, &current)
+        .into_iter()
+        .map(|change| {
+            let anomaly = AnomalyReport::other(
+                "SYSCTL_CHANGED",
+                sysctl_path(&change.name),
+                format!("{}: {} -> {}", change.name, change.expected, change.actual.as_deref().unwrap_or("missing")),
+            );
+            (change.name, change.actual, anomaly)
+        })
+        .collect()
+}
+
+/// SYSCTL_CHANGED anomalies for the current values.
+pub fn check(recorded: &BTreeMap<String, String>) -> Vec<AnomalyReport> {
+    changed(&checkable(recorded)).into_iter().map(|(_, _, anomaly)| anomaly).collect()
+}
+
+/// Polls the recorded sysctls and sends an anomaly whenever one takes a value
+/// other than the recorded one. A value is reported once until it changes again.
+pub fn spawn(recorded: BTreeMap<String, String>, interval: Duration, tx: mpsc::Sender<AnomalyReport>) {
+    tokio::spawn(async move {
+        let recorded = checkable(&recorded);
+        let mut reported: HashMap<String, Option<String>> = HashMap::new();
+        loop {
+            let changes = changed(&recorded);
+            // Back to the recorded value: a later change is reported again
+            reported.retain(|name, _| changes.iter().any(|(changed, _, _)| changed == name));
+            for (name, actual, anomaly) in changes {
+                if reported.get(&name) == Some(&actual) {
+                    continue;
+                }
+                reported.insert(name, actual);
+                if tx.send(anomaly).await.is_err() {
+                    return;
+                }
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
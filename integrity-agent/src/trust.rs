@@ -0,0 +1,77 @@
+//! Trust anchors compiled into the agent by build.rs.
+//!
+//! A field deployment built with an embedded service URL and CA certificate
+//! only talks to that service, through that CA. Pointing the agent elsewhere
+//! from the command line requires `--insecure-override`, so a local attacker
+//! cannot simply restart it against a rogue metadata-service. An embedded
+//! baseline-signing public key (hex) must have signed every baseline the
+//! agent verifies against, and `--trusted-pubkey` replaces it only with
+//! `--insecure-override` too.
+
+use integrity_client::ClientConfig;
+use integrity_common::{IntegrityError, Result};
+use tracing::{info, warn};
+
+include!(concat!(env!("OUT_DIR"), "/trust_anchors.rs"));
+
+/// Applies the embedded anchors to the connection settings; `metadata_url`
+/// is the `--metadata-url` given, if any.
+pub fn apply(config: &mut ClientConfig, metadata_url: Option<&str>, insecure_override: bool) -> Result<()> {
+    if let Some(url) = METADATA_URL {
+        let url = url.trim_end_matches('/');
+        match metadata_url.map(|given| given.trim_end_matches('/')) {
+            None => config.base_url = url.to_string(),
+            Some(given) if given == url => {}
+            Some(given) => {
+                if !insecure_override {
+                    return Err(IntegrityError::Validation(format!(
+                        "--metadata-url {} differs from the embedded service URL {} (use --insecure-override)",
+                        given, url
+                    )));
+                }
+                warn!("Overriding embedded service URL {} with {}", url, given);
+            }
+        }
+    }
+
+    if let Some(ca_cert) = CA_CERT {
+        match &config.ca_cert {
+            Some(path) if !insecure_override => {
+                return Err(IntegrityError::Validation(format!(
+                    "--ca-cert {:?} replaces the embedded CA certificate (use --insecure-override)",
+                    path
+                )));
+            }
+            Some(path) => warn!("Trusting {:?} instead of the embedded CA certificate", path),
+            None => config.pinned_ca_pem = Some(ca_cert.to_vec()),
+        }
+    }
+
+    info!(
+        "Embedded trust anchors: service URL {}, CA certificate {}, baseline-signing key {}",
+        if METADATA_URL.is_some() { "pinned" } else { "none" },
+        if CA_CERT.is_some() { "pinned" } else { "none" },
+        if SIGNING_PUBLIC_KEY.is_some() { "present" } else { "none" },
+    );
+    Ok(())
+}
+
+/// The keys baselines must be signed by: `configured` (--trusted-pubkey), or
+/// else the embedded baseline-signing key.
+pub fn baseline_keys(configured: &[String], insecure_override: bool) -> Result<Vec<String>> {
+    let Some(key) = SIGNING_PUBLIC_KEY else {
+        return Ok(configured.to_vec());
+    };
+    let key = crate::signatures::parse_public_key(&String::from_utf8_lossy(key))
+        .map_err(|e| IntegrityError::Validation(format!("embedded baseline-signing key: {}", e)))?;
+    if configured.is_empty() || configured == [key.clone()] {
+        return Ok(vec![key]);
+    }
+    if !insecure_override {
+        return Err(IntegrityError::Validation(
+            "--trusted-pubkey replaces the embedded baseline-signing key (use --insecure-override)".to_string(),
+        ));
+    }
+    warn!("Trusting {:?} instead of the embedded baseline-signing key", configured);
+    Ok(configured.to_vec())
+}
@@ -0,0 +1,27 @@
+//! Which CA certificates were added to or removed from the trust store.
+//!
+//! An anomaly under one of the trust store paths is followed by one anomaly
+//! per certificate that differs from those recorded in the baseline, e.g.
+//! "CA_ADDED: usr/local/share/ca-certificates/corp.crt (CN=Corp Root, ...)".
+
+use integrity_common::{AnomalyReport, Baseline, TrustStore};
+use std::path::Path;
+
+/// Anomalies describing the trust store changes on the filesystem at `root`,
+/// if the baseline has the trust store's certificates.
+pub fn describe(baseline: &Baseline, root: &Path) -> Vec<AnomalyReport> {
+    let Some(recorded) = &baseline.trust_store else {
+        return Vec::new();
+    };
+    recorded
+        .changes(&TrustStore::read(root))
+        .into_iter()
+        .map(|change| {
+            AnomalyReport::other(
+                change.kind,
+                change.certificate.path,
+                format!("{}, sha256 {}, expires {}", change.certificate.subject, change.fingerprint, change.certificate.not_after),
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,425 @@
+use crate::monitor::{EventType, FileEvent, Monitor};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use windows_sys::Win32::Foundation::{
+    CloseHandle, ERROR_JOURNAL_ENTRY_DELETED, GENERIC_READ, HANDLE, INVALID_HANDLE_VALUE,
+};
+use windows_sys::Win32::Storage::FileSystem::{
+    CreateFileW, FileIdType, GetFinalPathNameByHandleW, OpenFileById, ReadDirectoryChangesW,
+    FILE_ACTION_ADDED, FILE_ACTION_REMOVED, FILE_ACTION_RENAMED_NEW_NAME, FILE_ATTRIBUTE_DIRECTORY,
+    FILE_FLAG_BACKUP_SEMANTICS, FILE_ID_DESCRIPTOR, FILE_LIST_DIRECTORY, FILE_NAME_NORMALIZED,
+    FILE_NOTIFY_CHANGE_ATTRIBUTES, FILE_NOTIFY_CHANGE_FILE_NAME, FILE_NOTIFY_CHANGE_LAST_WRITE,
+    FILE_NOTIFY_CHANGE_SECURITY, FILE_NOTIFY_INFORMATION, FILE_SHARE_DELETE, FILE_SHARE_READ,
+    FILE_SHARE_WRITE, OPEN_EXISTING,
+};
+use windows_sys::Win32::System::Ioctl::{
+    FSCTL_QUERY_USN_JOURNAL, FSCTL_READ_USN_JOURNAL, READ_USN_JOURNAL_DATA_V0, USN_JOURNAL_DATA_V0,
+    USN_REASON_BASIC_INFO_CHANGE, USN_REASON_CLOSE, USN_REASON_DATA_EXTEND, USN_REASON_DATA_OVERWRITE,
+    USN_REASON_DATA_TRUNCATION, USN_REASON_FILE_CREATE, USN_REASON_FILE_DELETE, USN_REASON_RENAME_NEW_NAME,
+    USN_REASON_SECURITY_CHANGE, USN_RECORD_V2,
+};
+use windows_sys::Win32::System::IO::DeviceIoControl;
+
+/// Journal reasons that can change a file's content, attributes or presence.
+const WATCHED_REASONS: u32 = USN_REASON_DATA_OVERWRITE
+    | USN_REASON_DATA_EXTEND
+    | USN_REASON_DATA_TRUNCATION
+    | USN_REASON_FILE_CREATE
+    | USN_REASON_FILE_DELETE
+    | USN_REASON_RENAME_NEW_NAME
+    | USN_REASON_SECURITY_CHANGE
+    | USN_REASON_BASIC_INFO_CHANGE;
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Parent directories resolved from file IDs, kept between journal reads.
+const MAX_CACHED_DIRECTORIES: usize = 10_000;
+
+/// An NTFS USN change journal monitor for Windows.
+///
+/// Each volume holding a watch path is read from the current end of its journal,
+/// and records are resolved to paths through their parent directory's file ID.
+/// Volumes without a journal (FAT, network shares) fall back to
+/// ReadDirectoryChangesW on the watch paths themselves.
+pub struct UsnMonitor {
+    watch_paths: Vec<PathBuf>,
+    channel_capacity: usize,
+    stop: Arc<AtomicBool>,
+}
+
+impl UsnMonitor {
+    pub fn new(watch_paths: Vec<PathBuf>, channel_capacity: usize) -> Self {
+        Self {
+            watch_paths,
+            channel_capacity,
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+#[async_trait]
+impl Monitor for UsnMonitor {
+    async fn start(&mut self) -> Result<mpsc::Receiver<FileEvent>, Box<dyn std::error::Error + Send + Sync>> {
+        let (tx, rx) = mpsc::channel(self.channel_capacity);
+        self.stop.store(false, Ordering::SeqCst);
+
+        // Group watch paths by drive letter; each volume has its own journal
+        let mut volumes: HashMap<char, Vec<PathBuf>> = HashMap::new();
+        for path in &self.watch_paths {
+            match drive_letter(path) {
+                Some(drive) => volumes.entry(drive).or_default().push(path.clone()),
+                None => tracing::warn!("Watch path {:?} is not on a lettered volume, skipping", path),
+            }
+        }
+
+        for (drive, paths) in volumes {
+            match UsnJournal::open(drive) {
+                Ok(journal) => {
+                    tracing::info!("Reading USN journal of volume {}: for watch paths {:?}", drive, paths);
+                    let (tx, stop) = (tx.clone(), self.stop.clone());
+                    std::thread::spawn(move || journal.run(&paths, &tx, &stop));
+                }
+                Err(e) => {
+                    tracing::warn!("USN journal unavailable on volume {}: ({}), falling back to ReadDirectoryChangesW", drive, e);
+                    for path in paths {
+                        let (tx, stop) = (tx.clone(), self.stop.clone());
+                        std::thread::spawn(move || {
+                            if let Err(e) = watch_directory(&path, &tx, &stop) {
+                                tracing::error!("Directory watch on {:?} failed: {}", path, e);
+                            }
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(rx)
+    }
+
+    async fn stop(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        tracing::info!("Stopping USN journal monitor");
+        self.stop.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+fn drive_letter(path: &Path) -> Option<char> {
+    let s = path.to_str()?;
+    let s = s.strip_prefix(r"\\?\").unwrap_or(s);
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(letter), Some(':')) if letter.is_ascii_alphabetic() => Some(letter.to_ascii_uppercase()),
+        _ => None,
+    }
+}
+
+fn wide(s: &std::ffi::OsStr) -> Vec<u16> {
+    s.encode_wide().chain(std::iter::once(0)).collect()
+}
+
+/// Case-insensitive prefix match, since NTFS paths are case-preserving only.
+fn is_watched(path: &Path, watch_paths: &[PathBuf]) -> bool {
+    let path = path.to_string_lossy().to_lowercase();
+    watch_paths
+        .iter()
+        .any(|w| path.starts_with(&w.to_string_lossy().to_lowercase()))
+}
+
+/// Owned Win32 handle, closed on drop.
+struct OwnedHandle(HANDLE);
+
+// The handle is only used from the thread that owns the journal reader
+unsafe impl Send for OwnedHandle {}
+
+impl Drop for OwnedHandle {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+fn open_handle(path: &std::ffi::OsStr, access: u32, flags: u32) -> std::io::Result<OwnedHandle> {
+    let name = wide(path);
+    let handle = unsafe {
+        CreateFileW(
+            name.as_ptr(),
+            access,
+            FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+            std::ptr::null(),
+            OPEN_EXISTING,
+            flags,
+            std::ptr::null_mut(),
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(OwnedHandle(handle))
+}
+
+/// Reads the volume's journal ID and next USN.
+fn query_journal(volume: &OwnedHandle) -> std::io::Result<USN_JOURNAL_DATA_V0> {
+    let mut data = USN_JOURNAL_DATA_V0::default();
+    let mut returned = 0u32;
+    let ok = unsafe {
+        DeviceIoControl(
+            volume.0,
+            FSCTL_QUERY_USN_JOURNAL,
+            std::ptr::null(),
+            0,
+            &mut data as *mut _ as *mut _,
+            std::mem::size_of::<USN_JOURNAL_DATA_V0>() as u32,
+            &mut returned,
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(data)
+}
+
+struct UsnJournal {
+    volume: OwnedHandle,
+    journal_id: u64,
+    next_usn: i64,
+    directories: HashMap<u64, PathBuf>,
+}
+
+impl UsnJournal {
+    /// Opens the volume and positions the reader at the end of its journal.
+    fn open(drive: char) -> std::io::Result<Self> {
+        // Reading the journal requires administrator rights
+        let volume = open_handle(format!(r"\\.\{}:", drive).as_ref(), GENERIC_READ, 0)?;
+        let data = query_journal(&volume)?;
+
+        Ok(Self {
+            volume,
+            journal_id: data.UsnJournalID,
+            next_usn: data.NextUsn,
+            directories: HashMap::new(),
+        })
+    }
+
+    fn run(mut self, watch_paths: &[PathBuf], tx: &mpsc::Sender<FileEvent>, stop: &AtomicBool) {
+        let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+        while !stop.load(Ordering::SeqCst) && !tx.is_closed() {
+            let returned = match self.read(&mut buffer) {
+                Ok(n) => n,
+                Err(e) if e.raw_os_error() == Some(ERROR_JOURNAL_ENTRY_DELETED as i32) => {
+                    // The journal wrapped past records we had not read yet
+                    tracing::warn!("USN journal records were overwritten before being read, some events were lost");
+                    match query_journal(&self.volume) {
+                        Ok(data) => self.next_usn = data.NextUsn,
+                        Err(e) => {
+                            tracing::error!("Querying USN journal failed: {}", e);
+                            return;
+                        }
+                    }
+                    if tx.blocking_send(FileEvent { path: PathBuf::new(), event_type: EventType::Overflow }).is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    tracing::error!("Reading USN journal failed: {}", e);
+                    return;
+                }
+            };
+
+            // The buffer starts with the USN to continue from, followed by records
+            if returned <= std::mem::size_of::<i64>() {
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            self.next_usn = i64::from_ne_bytes(buffer[..8].try_into().unwrap());
+
+            let mut offset = std::mem::size_of::<i64>();
+            while offset < returned {
+                let record = unsafe { &*(buffer.as_ptr().add(offset) as *const USN_RECORD_V2) };
+                if record.RecordLength == 0 {
+                    break;
+                }
+                offset += record.RecordLength as usize;
+
+                if record.MajorVersion != 2 || record.Reason & WATCHED_REASONS == 0 {
+                    continue;
+                }
+                if record.FileAttributes & FILE_ATTRIBUTE_DIRECTORY != 0 {
+                    // A renamed or deleted directory invalidates cached paths below it
+                    if record.Reason & (USN_REASON_RENAME_NEW_NAME | USN_REASON_FILE_DELETE) != 0 {
+                        self.directories.clear();
+                    }
+                    continue;
+                }
+
+                let name = unsafe {
+                    let start = (record as *const USN_RECORD_V2 as *const u8).add(record.FileNameOffset as usize);
+                    std::slice::from_raw_parts(start as *const u16, record.FileNameLength as usize / 2)
+                };
+                let Some(parent) = self.directory_path(record.ParentFileReferenceNumber) else {
+                    continue;
+                };
+                let path = parent.join(OsString::from_wide(name));
+                if !is_watched(&path, watch_paths) {
+                    continue;
+                }
+
+                let event_type = if record.Reason & USN_REASON_FILE_DELETE != 0 {
+                    EventType::Deleted
+                } else if record.Reason & USN_REASON_FILE_CREATE != 0 {
+                    EventType::Created
+                } else {
+                    EventType::Modified
+                };
+                if tx.blocking_send(FileEvent { path, event_type }).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Reads records from `next_usn`, only those whose file handle has been closed
+    /// so that each write burst is reported once.
+    fn read(&self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let request = READ_USN_JOURNAL_DATA_V0 {
+            StartUsn: self.next_usn,
+            ReasonMask: WATCHED_REASONS | USN_REASON_CLOSE,
+            ReturnOnlyOnClose: 1,
+            Timeout: 0,
+            BytesToWaitFor: 0,
+            UsnJournalID: self.journal_id,
+        };
+        let mut returned = 0u32;
+        let ok = unsafe {
+            DeviceIoControl(
+                self.volume.0,
+                FSCTL_READ_USN_JOURNAL,
+                &request as *const _ as *const _,
+                std::mem::size_of::<READ_USN_JOURNAL_DATA_V0>() as u32,
+                buffer.as_mut_ptr() as *mut _,
+                buffer.len() as u32,
+                &mut returned,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(returned as usize)
+    }
+
+    /// Resolves a directory file reference number to its path on the volume.
+    fn directory_path(&mut self, file_reference: u64) -> Option<PathBuf> {
+        if let Some(path) = self.directories.get(&file_reference) {
+            return Some(path.clone());
+        }
+
+        let mut descriptor = FILE_ID_DESCRIPTOR::default();
+        descriptor.dwSize = std::mem::size_of::<FILE_ID_DESCRIPTOR>() as u32;
+        descriptor.Type = FileIdType;
+        descriptor.Anonymous.FileId = file_reference as i64;
+
+        let handle = unsafe {
+            OpenFileById(
+                self.volume.0,
+                &descriptor,
+                0,
+                FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+                std::ptr::null(),
+                FILE_FLAG_BACKUP_SEMANTICS,
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            tracing::debug!("Could not open directory {:#x}: {}", file_reference, std::io::Error::last_os_error());
+            return None;
+        }
+        let handle = OwnedHandle(handle);
+
+        let mut name = vec![0u16; 1024];
+        let len = unsafe { GetFinalPathNameByHandleW(handle.0, name.as_mut_ptr(), name.len() as u32, FILE_NAME_NORMALIZED) };
+        if len == 0 || len as usize > name.len() {
+            return None;
+        }
+        let path = OsString::from_wide(&name[..len as usize]);
+        let path = path.to_string_lossy();
+        let path = PathBuf::from(path.strip_prefix(r"\\?\").unwrap_or(&path));
+
+        if self.directories.len() >= MAX_CACHED_DIRECTORIES {
+            self.directories.clear();
+        }
+        self.directories.insert(file_reference, path.clone());
+        Some(path)
+    }
+}
+
+/// ReadDirectoryChangesW fallback for volumes without a change journal.
+fn watch_directory(path: &Path, tx: &mpsc::Sender<FileEvent>, stop: &AtomicBool) -> std::io::Result<()> {
+    let directory = open_handle(path.as_os_str(), FILE_LIST_DIRECTORY, FILE_FLAG_BACKUP_SEMANTICS)?;
+    tracing::info!("Watching {:?} with ReadDirectoryChangesW", path);
+
+    // DWORD-aligned, as ReadDirectoryChangesW requires
+    let mut buffer = vec![0u32; READ_BUFFER_SIZE / 4];
+    while !stop.load(Ordering::SeqCst) && !tx.is_closed() {
+        let mut returned = 0u32;
+        let ok = unsafe {
+            ReadDirectoryChangesW(
+                directory.0,
+                buffer.as_mut_ptr() as *mut _,
+                READ_BUFFER_SIZE as u32,
+                1, // Watch the whole subtree
+                FILE_NOTIFY_CHANGE_FILE_NAME
+                    | FILE_NOTIFY_CHANGE_LAST_WRITE
+                    | FILE_NOTIFY_CHANGE_ATTRIBUTES
+                    | FILE_NOTIFY_CHANGE_SECURITY,
+                &mut returned,
+                std::ptr::null_mut(),
+                None,
+            )
+        };
+        if ok == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if returned == 0 {
+            // The buffer overflowed and the changes were dropped
+            tracing::warn!("Change notifications for {:?} overflowed, some events were lost", path);
+            if tx.blocking_send(FileEvent { path: path.to_path_buf(), event_type: EventType::Overflow }).is_err() {
+                return Ok(());
+            }
+            continue;
+        }
+
+        let base = buffer.as_ptr() as *const u8;
+        let mut offset = 0usize;
+        loop {
+            let info = unsafe { &*(base.add(offset) as *const FILE_NOTIFY_INFORMATION) };
+            let name = unsafe {
+                std::slice::from_raw_parts(info.FileName.as_ptr(), info.FileNameLength as usize / 2)
+            };
+            let event_type = match info.Action {
+                FILE_ACTION_ADDED | FILE_ACTION_RENAMED_NEW_NAME => EventType::Created,
+                FILE_ACTION_REMOVED => EventType::Deleted,
+                _ => EventType::Modified,
+            };
+            let event = FileEvent {
+                path: path.join(OsString::from_wide(name)),
+                event_type,
+            };
+            if tx.blocking_send(event).is_err() {
+                return Ok(());
+            }
+
+            if info.NextEntryOffset == 0 {
+                break;
+            }
+            offset += info.NextEntryOffset as usize;
+        }
+    }
+    Ok(())
+}
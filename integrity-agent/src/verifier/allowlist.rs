@@ -0,0 +1,44 @@
+//! Checks file hashes against an internal allowlist service.
+//!
+//! `GET <url>/<sha512>` answering 200 means the file is allowed, 404 that it
+//! is not; anything else fails the check.
+
+use super::{Verifier, VerifyRequest};
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use std::time::Duration;
+
+pub struct AllowlistVerifier {
+    http: reqwest::Client,
+    url: String,
+}
+
+impl AllowlistVerifier {
+    pub fn new(url: &str, timeout: Duration) -> reqwest::Result<Self> {
+        Ok(Self {
+            http: reqwest::Client::builder().timeout(timeout).build()?,
+            url: url.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl Verifier for AllowlistVerifier {
+    fn name(&self) -> &str {
+        "allowlist"
+    }
+
+    async fn verify(&self, request: &VerifyRequest<'_>) -> Option<String> {
+        // The allowlist is keyed by SHA-512
+        if request.baseline.sha512.is_empty() {
+            return Some("no SHA-512 digest recorded to look up".to_string());
+        }
+        let url = format!("{}/{}", self.url, request.baseline.sha512);
+        match self.http.get(&url).send().await {
+            Ok(response) if response.status() == StatusCode::OK => None,
+            Ok(response) if response.status() == StatusCode::NOT_FOUND => Some("hash not on allowlist".to_string()),
+            Ok(response) => Some(format!("allowlist service returned {}", response.status())),
+            Err(e) => Some(format!("allowlist service unreachable: {}", e)),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Custom checks run on every file event that passes the baseline comparison.
+//!
+//! Verifiers are either compiled in (behind cargo features) or external
+//! programs speaking the line-based JSON protocol described in `process`.
+
+#[cfg(feature = "allowlist-verifier")]
+pub mod allowlist;
+pub mod process;
+
+use async_trait::async_trait;
+use integrity_common::{AnomalyReport, FileIntegrityEntry};
+use std::path::Path;
+
+/// The file a verifier is asked about.
+#[derive(Debug, Clone, Copy)]
+pub struct VerifyRequest<'a> {
+    /// Where the file is visible to the agent
+    pub path: &'a Path,
+    /// Path relative to the baselined filesystem root
+    pub relative_path: &'a str,
+    /// Baseline entry the file was found to match
+    pub baseline: &'a FileIntegrityEntry,
+}
+
+/// A custom per-file check.
+#[async_trait]
+pub trait Verifier: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Returns a description of the problem if the file fails the check.
+    async fn verify(&self, request: &VerifyRequest<'_>) -> Option<String>;
+}
+
+/// The verifiers configured for this agent, run in registration order.
+#[derive(Default)]
+pub struct VerifierRegistry {
+    verifiers: Vec<Box<dyn Verifier>>,
+}
+
+impl VerifierRegistry {
+    pub fn register(&mut self, verifier: Box<dyn Verifier>) {
+        tracing::info!("Registered verifier {}", verifier.name());
+        self.verifiers.push(verifier);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.verifiers.is_empty()
+    }
+
+    /// Runs the verifiers until one fails, returning its anomaly.
+    pub async fn verify(&self, request: &VerifyRequest<'_>) -> Option<AnomalyReport> {
+        for verifier in &self.verifiers {
+            if let Some(detail) = verifier.verify(request).await {
+                return Some(AnomalyReport::other("VERIFIER_FAILED", request.relative_path, format!("{}: {}", verifier.name(), detail)));
+            }
+        }
+        None
+    }
+}
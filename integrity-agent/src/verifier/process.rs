@@ -0,0 +1,168 @@
+//! External verifier plugins.
+//!
+//! The agent starts the plugin once and keeps it running. For each file event it
+//! writes one JSON request line to the plugin's stdin:
+//!
+//! ```json
+//! {"path": "/usr/bin/ls", "relative_path": "usr/bin/ls", "baseline": {"path": "usr/bin/ls", "sha512": "...", "mode": 493, "uid": 0, "gid": 0}}
+//! ```
+//!
+//! and reads one JSON response line from its stdout: `{"ok": true}` or
+//! `{"ok": false, "detail": "signature invalid"}`. A plugin that crashes, stops
+//! answering within the timeout, or answers garbage fails the check and is
+//! restarted for the next event. Its stderr is passed through to the agent's.
+
+use super::{Verifier, VerifyRequest};
+use async_trait::async_trait;
+use integrity_common::FileIntegrityEntry;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex;
+
+#[derive(Serialize)]
+struct PluginRequest<'a> {
+    path: &'a Path,
+    relative_path: &'a str,
+    baseline: &'a FileIntegrityEntry,
+}
+
+#[derive(Deserialize)]
+struct PluginResponse {
+    ok: bool,
+    #[serde(default)]
+    detail: Option<String>,
+}
+
+struct PluginProcess {
+    // Held so the plugin is killed when the process handle is dropped
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+pub struct ProcessVerifier {
+    name: String,
+    program: PathBuf,
+    timeout: Duration,
+    process: Mutex<Option<PluginProcess>>,
+}
+
+impl ProcessVerifier {
+    pub fn new(program: PathBuf, timeout: Duration) -> Self {
+        let name = program
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| program.to_string_lossy().into_owned());
+        Self {
+            name,
+            program,
+            timeout,
+            process: Mutex::new(None),
+        }
+    }
+
+    fn spawn(&self) -> std::io::Result<PluginProcess> {
+        let mut child = Command::new(&self.program)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        tracing::info!("Started verifier plugin {:?}", self.program);
+        Ok(PluginProcess {
+            stdin: child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            _child: child,
+        })
+    }
+
+    async fn exchange(process: &mut PluginProcess, request: &PluginRequest<'_>) -> std::io::Result<PluginResponse> {
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        process.stdin.write_all(line.as_bytes()).await?;
+        process.stdin.flush().await?;
+
+        let mut response = String::new();
+        if process.stdout.read_line(&mut response).await? == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "plugin exited"));
+        }
+        Ok(serde_json::from_str(&response)?)
+    }
+}
+
+#[async_trait]
+impl Verifier for ProcessVerifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn verify(&self, request: &VerifyRequest<'_>) -> Option<String> {
+        let mut guard = self.process.lock().await;
+        if guard.is_none() {
+            match self.spawn() {
+                Ok(process) => *guard = Some(process),
+                Err(e) => return Some(format!("cannot start plugin: {}", e)),
+            }
+        }
+
+        let plugin_request = PluginRequest {
+            path: request.path,
+            relative_path: request.relative_path,
+            baseline: request.baseline,
+        };
+        let result = tokio::time::timeout(self.timeout, Self::exchange(guard.as_mut().unwrap(), &plugin_request)).await;
+
+        match result {
+            Ok(Ok(response)) if response.ok => None,
+            Ok(Ok(response)) => Some(response.detail.unwrap_or_else(|| "check failed".to_string())),
+            Ok(Err(e)) => {
+                // The stream is out of sync or the plugin died; restart it next time
+                *guard = None;
+                Some(format!("plugin error: {}", e))
+            }
+            Err(_) => {
+                *guard = None;
+                Some(format!("plugin did not answer within {:?}", self.timeout))
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn test_plugin_protocol() {
+        let script = std::env::temp_dir().join(format!("acropole-verifier-{}.sh", std::process::id()));
+        std::fs::write(
+            &script,
+            "#!/bin/sh\nwhile read -r line; do\n  case \"$line\" in\n    *etc/shadow*) echo '{\"ok\": false, \"detail\": \"not allowed\"}' ;;\n    *) echo '{\"ok\": true}' ;;\n  esac\ndone\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let verifier = ProcessVerifier::new(script.clone(), Duration::from_secs(5));
+        let entry = FileIntegrityEntry {
+            path: "etc/passwd".to_string(),
+            sha512: "00".repeat(64),
+            mode: 0o644,
+            uid: 0,
+            gid: 0,
+            ..Default::default()
+        };
+        let request = |relative_path| VerifyRequest {
+            path: Path::new("/etc/passwd"),
+            relative_path,
+            baseline: &entry,
+        };
+
+        assert_eq!(verifier.verify(&request("etc/passwd")).await, None);
+        assert_eq!(verifier.verify(&request("etc/shadow")).await.as_deref(), Some("not allowed"));
+        std::fs::remove_file(script).unwrap();
+    }
+}
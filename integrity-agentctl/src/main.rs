@@ -0,0 +1,128 @@
+//! Talks to a running integrity agent over its local control socket.
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use integrity_common::{AgentStatus, ControlRequest, ControlResponse, DEFAULT_CONTROL_SOCKET};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(name = "integrity-agentctl")]
+#[command(about = "Control the integrity agent running on this host", long_about = None)]
+struct Args {
+    /// Control socket of the agent
+    #[arg(long, global = true, default_value = DEFAULT_CONTROL_SOCKET)]
+    socket: PathBuf,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show what the agent verifies, its counters and whether it is in maintenance mode
+    Status {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Re-scan paths now, or all watch paths if none are given
+    Rescan { paths: Vec<String> },
+    /// Fetch the baseline again; the agent restarts with it
+    Reload,
+    /// Suppress or resume alerting around planned changes
+    Maintenance {
+        #[command(subcommand)]
+        command: MaintenanceCommand,
+    },
+    /// Print the effective value of every agent flag
+    Policy {
+        /// Print the flags as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum MaintenanceCommand {
+    /// Stop alerting, until `exit` or for a while, e.g. --for 2h; anomalies are still recorded
+    Enter {
+        #[arg(long = "for", value_parser = humantime::parse_duration)]
+        duration: Option<Duration>,
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Alert again
+    Exit,
+}
+
+#[cfg(unix)]
+fn send(socket: &Path, request: &ControlRequest) -> Result<ControlResponse> {
+    use std::io::{BufRead, BufReader, Write};
+    let mut stream = std::os::unix::net::UnixStream::connect(socket)
+        .with_context(|| format!("connecting to the agent at {:?} (is it running in monitor mode?)", socket))?;
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    serde_json::from_str(&response).with_context(|| format!("unexpected response: {}", response.trim()))
+}
+
+#[cfg(not(unix))]
+fn send(_socket: &Path, _request: &ControlRequest) -> Result<ControlResponse> {
+    bail!("the agent's control socket is a Unix socket, which this platform lacks")
+}
+
+fn format_time(time: i64) -> String {
+    chrono::DateTime::from_timestamp(time, 0).map_or_else(|| time.to_string(), |t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+fn print_status(status: &AgentStatus) {
+    println!("pid {}, {} mode, up since {}", status.pid, status.mode, format_time(status.started_at));
+    println!("Baseline: {} ({} files)", status.image_id, status.baseline_entries);
+    println!("Watching: {}", status.watch_paths.join(", "));
+    match &status.maintenance {
+        Some(window) => {
+            let until = window.until.map_or_else(|| "until exited".to_string(), |until| format!("until {}", format_time(until)));
+            let reason = window.reason.as_deref().map(|reason| format!(" ({})", reason)).unwrap_or_default();
+            println!("Maintenance mode since {}, {}{}: not alerting", format_time(window.since), until, reason);
+        }
+        None => println!("Alerting"),
+    }
+    if let Some(open) = status.open_anomalies {
+        println!("Open anomalies: {}", open);
+    }
+    let metrics: Vec<String> = status.metrics.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+    println!("Verification: {}", metrics.join(" "));
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let (request, json) = match args.command {
+        Command::Status { json } => (ControlRequest::Status, json),
+        Command::Rescan { paths } => (ControlRequest::Rescan { paths }, false),
+        Command::Reload => (ControlRequest::ReloadBaseline, false),
+        Command::Maintenance { command: MaintenanceCommand::Enter { duration, reason } } => {
+            (ControlRequest::EnterMaintenance { duration_secs: duration.map(|d| d.as_secs()), reason }, false)
+        }
+        Command::Maintenance { command: MaintenanceCommand::Exit } => (ControlRequest::ExitMaintenance, false),
+        Command::Policy { json } => (ControlRequest::Policy, json),
+    };
+
+    match send(&args.socket, &request)? {
+        ControlResponse::Status(status) if json => println!("{}", serde_json::to_string_pretty(&status)?),
+        ControlResponse::Status(status) => print_status(&status),
+        ControlResponse::Policy { flags } if json => println!("{}", serde_json::to_string_pretty(&flags)?),
+        ControlResponse::Policy { flags } => {
+            for (flag, value) in &flags {
+                println!("--{} {}", flag, value);
+            }
+        }
+        ControlResponse::Done { message } => println!("{}", message),
+        ControlResponse::Error { message } => bail!("{}", message),
+    }
+    Ok(())
+}
@@ -0,0 +1,710 @@
+//! Typed client for the metadata-service API.
+//!
+//! Connection settings (base URL, bearer token, TLS trust and client identity,
+//! timeouts and retries) live in `ClientConfig`, which binaries expose on their
+//! command line by flattening `ClientArgs`.
+
+use integrity_common::{
+    AgentConfigHistory, AgentConfigRollback, AgentConfigUpdate, AgentHeartbeat, AgentRecord, AgentRegistration, AnomalyBatch, AnomalyRecord, Baseline, DerivedBaseline,
+    BaselineDiff, BaselineList, BaselineMetadata, BaselineSignature, BaselineVersion, EffectiveAgentConfig, EntryDigests, EntryLookup, FleetAnalysis, ImageMapping, IntegrityError, Result,
+    ScanSnapshot, ScheduledBaseline, SnapshotInfo, TriageRequest, BaselineDecoder, NDJSON_CONTENT_TYPE, write_baseline,
+    from_cbor, to_cbor, CBOR_CONTENT_TYPE,
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, PercentEncode, NON_ALPHANUMERIC};
+use reqwest::header::{ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Service URL used when none is given on the command line.
+pub const DEFAULT_METADATA_URL: &str = "http://localhost:8080";
+
+/// zstd level baselines are uploaded at: most of the ratio at a fraction of the time of higher levels.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Escaped in path segments: everything but the unreserved characters of RFC 3986.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// `value` as one path segment, e.g. image id "registry/app:1.0" as "registry%2Fapp%3A1.0".
+fn segment(value: &str) -> PercentEncode<'_> {
+    utf8_percent_encode(value, SEGMENT)
+}
+
+/// How baselines are encoded on the wire.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BaselineFormat {
+    /// JSON Lines, encoded and decoded an entry at a time
+    #[default]
+    Ndjson,
+    /// One CBOR document: smaller and faster to decode, but buffered whole
+    Cbor,
+}
+
+impl BaselineFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Ndjson => NDJSON_CONTENT_TYPE,
+            Self::Cbor => CBOR_CONTENT_TYPE,
+        }
+    }
+}
+
+/// Connection settings for the metadata-service.
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub base_url: String,
+    /// Sent as `Authorization: Bearer <token>`
+    pub api_token: Option<String>,
+    /// Additional PEM CA certificate to trust
+    pub ca_cert: Option<PathBuf>,
+    /// PEM CA certificate that is the only trusted root; built-in roots are disabled
+    pub pinned_ca_pem: Option<Vec<u8>>,
+    /// PEM client certificate and PKCS#8 key for mutual TLS
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    pub timeout: Duration,
+    /// Retries for connection errors and 5xx responses
+    pub max_retries: u32,
+    /// Upload and fetch baselines zstd compressed
+    pub compression: bool,
+    /// Encoding baselines are uploaded in and asked for
+    pub baseline_format: BaselineFormat,
+}
+
+impl ClientConfig {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_token: None,
+            ca_cert: None,
+            pinned_ca_pem: None,
+            client_cert: None,
+            client_key: None,
+            timeout: Duration::from_secs(30),
+            max_retries: 3,
+            compression: true,
+            baseline_format: BaselineFormat::default(),
+        }
+    }
+}
+
+/// Command-line options for the metadata-service connection.
+#[derive(clap::Args, Debug, Clone)]
+pub struct ClientArgs {
+    /// Base URL of the metadata service [default: http://localhost:8080]
+    #[arg(long)]
+    pub metadata_url: Option<String>,
+
+    /// Bearer token for authenticated endpoints
+    #[arg(long)]
+    pub api_token: Option<String>,
+
+    /// PEM CA certificate used to verify the service
+    #[arg(long)]
+    pub ca_cert: Option<PathBuf>,
+
+    /// PEM client certificate for mutual TLS
+    #[arg(long, requires = "client_key")]
+    pub client_cert: Option<PathBuf>,
+
+    /// PKCS#8 PEM private key for --client-cert
+    #[arg(long, requires = "client_cert")]
+    pub client_key: Option<PathBuf>,
+
+    /// Request timeout in seconds
+    #[arg(long, default_value = "30")]
+    pub request_timeout: u64,
+
+    /// Number of retries on connection errors, timeouts and 5xx responses; POSTs only when they never connected
+    #[arg(long, default_value = "3")]
+    pub retries: u32,
+
+    /// Send and fetch baselines uncompressed, e.g. through a proxy that mishandles Content-Encoding
+    #[arg(long)]
+    pub no_compression: bool,
+
+    /// Encoding baselines are uploaded in and asked for
+    #[arg(long, value_enum, default_value = "ndjson")]
+    pub baseline_format: BaselineFormat,
+}
+
+impl ClientArgs {
+    /// `--metadata-url`, or the default service URL when it was not given.
+    pub fn metadata_url(&self) -> &str {
+        self.metadata_url.as_deref().unwrap_or(DEFAULT_METADATA_URL)
+    }
+}
+
+impl From<&ClientArgs> for ClientConfig {
+    fn from(args: &ClientArgs) -> Self {
+        Self {
+            api_token: args.api_token.clone(),
+            ca_cert: args.ca_cert.clone(),
+            client_cert: args.client_cert.clone(),
+            client_key: args.client_key.clone(),
+            timeout: Duration::from_secs(args.request_timeout),
+            max_retries: args.retries,
+            compression: !args.no_compression,
+            baseline_format: args.baseline_format,
+            ..Self::new(args.metadata_url())
+        }
+    }
+}
+
+fn http_error(e: reqwest::Error) -> IntegrityError {
+    IntegrityError::Storage(e.to_string())
+}
+
+/// Takes a baseline's body as it is written to it: NDJSON is decoded as it
+/// arrives, CBOR once it is complete.
+enum BaselineSink {
+    Ndjson(Box<BaselineDecoder>),
+    Cbor(Vec<u8>),
+}
+
+impl BaselineSink {
+    fn finish(self) -> Result<Baseline> {
+        match self {
+            Self::Ndjson(decoder) => decoder.finish(),
+            Self::Cbor(body) => from_cbor(&body),
+        }
+    }
+}
+
+impl Write for BaselineSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Ndjson(decoder) => decoder.push(buf).map_err(std::io::Error::other)?,
+            Self::Cbor(body) => body.extend_from_slice(buf),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads a baseline served as NDJSON a chunk at a time, or as CBOR,
+/// decompressing it if it is zstd encoded, or as JSON from services that
+/// support neither.
+async fn read_baseline(mut response: Response) -> Result<Baseline> {
+    let header = |name| response.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+    let (content_type, encoding) = (header(CONTENT_TYPE), header(CONTENT_ENCODING));
+    let decoder = if content_type.starts_with(NDJSON_CONTENT_TYPE) {
+        BaselineSink::Ndjson(Box::new(BaselineDecoder::new(usize::MAX, usize::MAX)))
+    } else if content_type.starts_with(CBOR_CONTENT_TYPE) {
+        BaselineSink::Cbor(Vec::new())
+    } else {
+        return response.json().await.map_err(http_error);
+    };
+    let decoder = match encoding.as_str() {
+        "" | "identity" => {
+            let mut decoder = decoder;
+            while let Some(chunk) = response.chunk().await.map_err(http_error)? {
+                decoder.write_all(&chunk)?;
+            }
+            decoder
+        }
+        "zstd" => {
+            let mut decoder = zstd::stream::write::Decoder::new(decoder)?;
+            while let Some(chunk) = response.chunk().await.map_err(http_error)? {
+                decoder.write_all(&chunk)?;
+            }
+            decoder.flush()?;
+            decoder.into_inner()
+        }
+        other => return Err(IntegrityError::Storage(format!("baseline served with unsupported encoding {}", other))),
+    };
+    decoder.finish()
+}
+
+/// Client for the metadata-service REST API.
+#[derive(Debug, Clone)]
+pub struct MetadataClient {
+    http: reqwest::Client,
+    config: ClientConfig,
+}
+
+impl MetadataClient {
+    pub fn new(config: ClientConfig) -> Result<Self> {
+        let mut builder = reqwest::Client::builder().timeout(config.timeout);
+
+        if let Some(ca_cert) = &config.ca_cert {
+            let pem = std::fs::read(ca_cert)?;
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem).map_err(http_error)?);
+        }
+        if let Some(pem) = &config.pinned_ca_pem {
+            builder = builder
+                .tls_built_in_root_certs(false)
+                .add_root_certificate(reqwest::Certificate::from_pem(pem).map_err(http_error)?);
+        }
+        if let (Some(cert), Some(key)) = (&config.client_cert, &config.client_key) {
+            let identity = reqwest::Identity::from_pkcs8_pem(&std::fs::read(cert)?, &std::fs::read(key)?)
+                .map_err(http_error)?;
+            builder = builder.identity(identity);
+        }
+
+        Ok(Self {
+            http: builder.build().map_err(http_error)?,
+            config,
+        })
+    }
+
+    pub fn from_args(args: &ClientArgs) -> Result<Self> {
+        Self::new(args.into())
+    }
+
+    /// A baseline as a request body in the configured format, zstd
+    /// compressed unless disabled, with its Content-Encoding. Cheap to resend
+    /// on retries.
+    fn baseline_body(&self, baseline: &Baseline) -> Result<(bytes::Bytes, &'static str)> {
+        let write = |writer: &mut dyn Write| match self.config.baseline_format {
+            BaselineFormat::Ndjson => write_baseline(writer, baseline).map(drop),
+            BaselineFormat::Cbor => Ok(writer.write_all(&to_cbor(baseline)?)?),
+        };
+        if !self.config.compression {
+            let mut body = Vec::new();
+            write(&mut body)?;
+            return Ok((body.into(), "identity"));
+        }
+        let mut encoder = zstd::Encoder::new(Vec::new(), ZSTD_LEVEL)?;
+        write(&mut encoder)?;
+        Ok((encoder.finish()?.into(), "zstd"))
+    }
+
+    /// Posts a baseline to `/baselines` with `query`.
+    async fn post_baseline(&self, baseline: &Baseline, query: &[(&str, i64)]) -> Result<Response> {
+        let url = self.url("/baselines");
+        debug!("POST {} {:?}", url, query);
+        let (body, encoding) = self.baseline_body(baseline)?;
+        let response = self
+            .send(|http| {
+                http.post(&url)
+                    .query(query)
+                    .header(CONTENT_TYPE, self.config.baseline_format.content_type())
+                    .header(CONTENT_ENCODING, encoding)
+                    .body(body.clone())
+            })
+            .await?;
+        Self::check(response).await
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.config.base_url
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.config.base_url, path)
+    }
+
+    /// Sends a request, retrying transient failures with exponential backoff.
+    /// Only requests that can be repeated safely are sent again once they
+    /// reached the service: a POST that timed out or got a 5xx may have been
+    /// stored, and is only retried if it never connected.
+    async fn send(&self, build: impl Fn(&reqwest::Client) -> RequestBuilder) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let mut request = build(&self.http);
+            if let Some(token) = &self.config.api_token {
+                request = request.bearer_auth(token);
+            }
+            let request = request.build().map_err(http_error)?;
+            let idempotent = matches!(*request.method(), Method::GET | Method::HEAD | Method::PUT | Method::DELETE);
+            let exhausted = attempt >= self.config.max_retries;
+
+            let retryable = match self.http.execute(request).await {
+                Ok(response) if !response.status().is_server_error() => return Ok(response),
+                Ok(response) if exhausted || !idempotent => return Ok(response),
+                Err(e) if exhausted || !(e.is_connect() || (idempotent && e.is_timeout())) => return Err(http_error(e)),
+                Ok(response) => format!("status {}", response.status()),
+                Err(e) => e.to_string(),
+            };
+
+            attempt += 1;
+            let delay = Duration::from_millis(250 * 2u64.pow(attempt.min(6)));
+            warn!("Request failed ({}), retrying in {:?} ({}/{})", retryable, delay, attempt, self.config.max_retries);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Turns a non-success response into an error carrying the response body.
+    async fn check(response: Response) -> Result<Response> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        if status == StatusCode::NOT_FOUND {
+            Err(IntegrityError::BaselineNotFound(error_text))
+        } else {
+            Err(IntegrityError::Storage(format!("{}: {}", status, error_text)))
+        }
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let url = self.url(path);
+        debug!("GET {}", url);
+        let response = Self::check(self.send(|http| http.get(&url)).await?).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// Fetches the baseline for an image, in the configured format.
+    pub async fn get_baseline(&self, image_id: &str) -> Result<Baseline> {
+        let url = self.url(&format!("/baselines/{}", segment(image_id)));
+        debug!("GET {}", url);
+        let accept = format!("{}, application/json;q=0.9", self.config.baseline_format.content_type());
+        let encoding = if self.config.compression { "zstd" } else { "identity" };
+        let response =
+            Self::check(self.send(|http| http.get(&url).header(ACCEPT, &accept).header(ACCEPT_ENCODING, encoding)).await?).await?;
+        read_baseline(response).await
+    }
+
+    /// Lists a page of the stored baselines, by image_id.
+    pub async fn list_baselines(&self, offset: usize, limit: usize) -> Result<BaselineList> {
+        self.get_json(&format!("/baselines?offset={}&limit={}", offset, limit)).await
+    }
+
+    /// Describes an image's baseline without fetching its entries.
+    pub async fn baseline_summary(&self, image_id: &str) -> Result<BaselineMetadata> {
+        self.get_json(&format!("/baselines/{}/summary", segment(image_id))).await
+    }
+
+    /// Looks up the baseline entry of one path, for verifying files without holding the whole baseline.
+    pub async fn lookup_entry(&self, image_id: &str, path: &str) -> Result<EntryLookup> {
+        let url = self.url(&format!("/baselines/{}/entries", segment(image_id)));
+        debug!("GET {} path={}", url, path);
+        let response = Self::check(self.send(|http| http.get(&url).query(&[("path", path)])).await?).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// The fingerprint of every entry of an image's baseline, for uploading a delta on it.
+    pub async fn entry_digests(&self, image_id: &str) -> Result<EntryDigests> {
+        self.get_json(&format!("/baselines/{}/digests", segment(image_id))).await
+    }
+
+    /// Deletes an image's baseline with all its versions; takes the admin token as `--api-token`.
+    pub async fn delete_baseline(&self, image_id: &str) -> Result<()> {
+        let url = self.url(&format!("/baselines/{}", segment(image_id)));
+        debug!("DELETE {}", url);
+        Self::check(self.send(|http| http.delete(&url)).await?).await?;
+        Ok(())
+    }
+
+    /// Lists the stored versions of an image's baseline, oldest first.
+    pub async fn baseline_versions(&self, image_id: &str) -> Result<Vec<BaselineVersion>> {
+        self.get_json(&format!("/baselines/{}/versions", segment(image_id))).await
+    }
+
+    /// Fetches one stored version of an image's baseline.
+    pub async fn get_baseline_version(&self, image_id: &str, version: u64) -> Result<Baseline> {
+        self.get_json(&format!("/baselines/{}/versions/{}", segment(image_id), version)).await
+    }
+
+    /// Diffs two stored versions of an image's baseline, by default the latest against the one before it.
+    pub async fn diff_baseline_versions(&self, image_id: &str, from: Option<u64>, to: Option<u64>) -> Result<BaselineDiff> {
+        let url = self.url(&format!("/baselines/{}/diff", segment(image_id)));
+        let query: Vec<(&str, u64)> = [("from", from), ("to", to)].into_iter().filter_map(|(name, version)| version.map(|version| (name, version))).collect();
+        debug!("GET {} {:?}", url, query);
+        let response = Self::check(self.send(|http| http.get(&url).query(&query)).await?).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// Uploads a baseline, in the configured format.
+    pub async fn store_baseline(&self, baseline: &Baseline) -> Result<()> {
+        self.post_baseline(baseline, &[]).await?;
+        Ok(())
+    }
+
+    /// Fetches the signature chain of a version of a baseline, by default the one served now.
+    pub async fn baseline_signatures(&self, image_id: &str, digest: Option<&str>) -> Result<Vec<BaselineSignature>> {
+        let url = self.url(&format!("/baselines/{}/signatures", segment(image_id)));
+        let query: Vec<(&str, &str)> = digest.map(|digest| ("digest", digest)).into_iter().collect();
+        debug!("GET {} {:?}", url, query);
+        let response = Self::check(self.send(|http| http.get(&url).query(&query)).await?).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// Adds a signature to a baseline's chain. Returns the chain as stored,
+    /// with the service's countersignature if it has a key.
+    pub async fn sign_baseline(&self, image_id: &str, signature: &BaselineSignature) -> Result<Vec<BaselineSignature>> {
+        let url = self.url(&format!("/baselines/{}/signatures", segment(image_id)));
+        debug!("POST {} ({})", url, signature.role);
+        let response = Self::check(self.send(|http| http.post(&url).json(signature)).await?).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// Uploads a baseline that takes effect at the Unix time `effective_from`;
+    /// until then the service serves the image's current baseline.
+    pub async fn schedule_baseline(&self, baseline: &Baseline, effective_from: i64) -> Result<ScheduledBaseline> {
+        let response = self.post_baseline(baseline, &[("effective_from", effective_from)]).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// Lists the versions of an image waiting to take effect, soonest first.
+    pub async fn scheduled_baselines(&self, image_id: &str) -> Result<Vec<ScheduledBaseline>> {
+        self.get_json(&format!("/baselines/{}/scheduled", segment(image_id))).await
+    }
+
+    /// Withdraws a version of an image that has not taken effect yet.
+    pub async fn cancel_scheduled_baseline(&self, image_id: &str, effective_from: i64) -> Result<()> {
+        let url = self.url(&format!("/baselines/{}/scheduled/{}", segment(image_id), effective_from));
+        debug!("DELETE {}", url);
+        Self::check(self.send(|http| http.delete(&url)).await?).await?;
+        Ok(())
+    }
+
+    /// Uploads a baseline stored as a delta on the baseline it extends.
+    pub async fn store_derived_baseline(&self, derived: &DerivedBaseline) -> Result<()> {
+        let url = self.url("/baselines/derived");
+        debug!("POST {}", url);
+        Self::check(self.send(|http| http.post(&url).json(derived)).await?).await?;
+        Ok(())
+    }
+
+    /// Uploads a new version of a baseline as its delta on the stored baseline
+    /// of `delta.extends`, which must still have `previous_digest`. Returns
+    /// false, storing nothing, if that baseline changed in the meantime or the
+    /// delta is larger than the service takes; a full upload is then needed.
+    pub async fn store_baseline_delta(&self, delta: &DerivedBaseline, previous_digest: &str) -> Result<bool> {
+        let url = self.url(&format!("/baselines/{}", segment(&delta.image_id)));
+        let if_match = format!("\"{}\"", previous_digest);
+        debug!("PATCH {}", url);
+        let response = self.send(|http| http.patch(&url).header("If-Match", &if_match).json(delta)).await?;
+        if matches!(response.status(), StatusCode::PRECONDITION_FAILED | StatusCode::PAYLOAD_TOO_LARGE) {
+            debug!("Delta refused: {}", response.status());
+            return Ok(false);
+        }
+        Self::check(response).await?;
+        Ok(true)
+    }
+
+    /// Resolves a cloud image key (see `InstanceIdentity::mapping_key`) to a baseline image_id.
+    pub async fn resolve_image(&self, cloud_image: &str) -> Result<String> {
+        let mapping: ImageMapping = self.get_json(&format!("/image-mappings/{}", segment(cloud_image))).await?;
+        Ok(mapping.image_id)
+    }
+
+    /// Registers an agent starting on a host.
+    pub async fn register_agent(&self, registration: &AgentRegistration) -> Result<AgentRecord> {
+        let url = self.url("/agents");
+        debug!("POST {}", url);
+        let response = Self::check(self.send(|http| http.post(&url).json(registration)).await?).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// Tells the service the agent on `host` is alive; fails with `BaselineNotFound` if it is not registered.
+    pub async fn agent_heartbeat(&self, host: &str, heartbeat: &AgentHeartbeat) -> Result<()> {
+        let url = self.url(&format!("/agents/{}/heartbeat", segment(host)));
+        debug!("POST {}", url);
+        Self::check(self.send(|http| http.post(&url).json(heartbeat)).await?).await?;
+        Ok(())
+    }
+
+    /// Lists the registered agents by host, optionally only the stale ones or those of an image.
+    pub async fn list_agents(&self, stale: Option<bool>, image_id: Option<&str>) -> Result<Vec<AgentRecord>> {
+        let url = self.url("/agents");
+        let stale = stale.map(|stale| stale.to_string());
+        let query: Vec<(&str, &str)> = [("stale", stale.as_deref()), ("image_id", image_id)]
+            .into_iter()
+            .filter_map(|(name, value)| value.map(|value| (name, value)))
+            .collect();
+        debug!("GET {} {:?}", url, query);
+        let response = Self::check(self.send(|http| http.get(&url).query(&query)).await?).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// Reports a host's anomalies. Returns the ids of the triage records they opened.
+    pub async fn report_anomalies(&self, batch: &AnomalyBatch) -> Result<Vec<String>> {
+        #[derive(serde::Deserialize)]
+        struct Reported {
+            opened: Vec<String>,
+        }
+        let url = self.url("/anomalies");
+        debug!("POST {}", url);
+        let response = Self::check(self.send(|http| http.post(&url).json(batch)).await?).await?;
+        let reported: Reported = response.json().await.map_err(http_error)?;
+        Ok(reported.opened)
+    }
+
+    /// Lists anomaly records, optionally only those in a triage state, of a host or of an image.
+    pub async fn list_anomalies(&self, state: Option<&str>, host: Option<&str>, image_id: Option<&str>) -> Result<Vec<AnomalyRecord>> {
+        let url = self.url("/anomalies");
+        let query: Vec<(&str, &str)> = [("state", state), ("host", host), ("image_id", image_id)]
+            .into_iter()
+            .filter_map(|(name, value)| value.map(|value| (name, value)))
+            .collect();
+        debug!("GET {} {:?}", url, query);
+        let response = Self::check(self.send(|http| http.get(&url).query(&query)).await?).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// Moves an anomaly record to another triage state.
+    pub async fn triage_anomaly(&self, id: &str, request: &TriageRequest) -> Result<AnomalyRecord> {
+        let url = self.url(&format!("/anomalies/{}/triage", segment(id)));
+        debug!("POST {}", url);
+        let response = Self::check(self.send(|http| http.post(&url).json(request)).await?).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// Compares the anomalies of the hosts of an image.
+    pub async fn fleet_analysis(&self, image_id: &str, fleet_wide_share: Option<f64>) -> Result<FleetAnalysis> {
+        let url = self.url(&format!("/anomalies/fleet/{}", segment(image_id)));
+        let query: Vec<(&str, f64)> = fleet_wide_share.map(|share| ("fleet_wide_share", share)).into_iter().collect();
+        debug!("GET {}", url);
+        let response = Self::check(self.send(|http| http.get(&url).query(&query)).await?).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// Fetches the agent settings in effect for a host of an image.
+    pub async fn effective_agent_config(&self, host: &str, image_id: &str) -> Result<EffectiveAgentConfig> {
+        let url = self.url("/agent-config/effective");
+        debug!("GET {}", url);
+        let response = Self::check(self.send(|http| http.get(&url).query(&[("host", host), ("image_id", image_id)])).await?).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// Fetches the version history of the agent settings of a scope (`image` or `host`).
+    pub async fn agent_config_history(&self, scope: &str, name: &str) -> Result<AgentConfigHistory> {
+        self.get_json(&format!("/agent-config/{}/{}", segment(scope), segment(name))).await
+    }
+
+    /// Stores new agent settings for a scope. Returns its history with them as the current version.
+    pub async fn put_agent_config(&self, scope: &str, name: &str, update: &AgentConfigUpdate) -> Result<AgentConfigHistory> {
+        let url = self.url(&format!("/agent-config/{}/{}", segment(scope), segment(name)));
+        debug!("PUT {}", url);
+        let response = Self::check(self.send(|http| http.put(&url).json(update)).await?).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// Makes an earlier version of a scope's agent settings current again, as a new version.
+    pub async fn rollback_agent_config(&self, scope: &str, name: &str, rollback: &AgentConfigRollback) -> Result<AgentConfigHistory> {
+        let url = self.url(&format!("/agent-config/{}/{}/rollback", segment(scope), segment(name)));
+        debug!("POST {}", url);
+        let response = Self::check(self.send(|http| http.post(&url).json(rollback)).await?).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// Uploads the complete state a scan saw. Returns it as stored, with its id.
+    pub async fn upload_snapshot(&self, snapshot: &ScanSnapshot) -> Result<SnapshotInfo> {
+        let url = self.url("/snapshots");
+        debug!("POST {}", url);
+        let response = Self::check(self.send(|http| http.post(&url).json(snapshot)).await?).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// Lists stored snapshots, newest first, optionally only those of a host or of an image.
+    pub async fn list_snapshots(&self, host: Option<&str>, image_id: Option<&str>) -> Result<Vec<SnapshotInfo>> {
+        let url = self.url("/snapshots");
+        let query: Vec<(&str, &str)> = [("host", host), ("image_id", image_id)]
+            .into_iter()
+            .filter_map(|(name, value)| value.map(|value| (name, value)))
+            .collect();
+        debug!("GET {} {:?}", url, query);
+        let response = Self::check(self.send(|http| http.get(&url).query(&query)).await?).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// Fetches a stored snapshot with all its entries.
+    pub async fn get_snapshot(&self, id: u64) -> Result<ScanSnapshot> {
+        self.get_json(&format!("/snapshots/{}", id)).await
+    }
+
+    /// Diffs a snapshot against a baseline, by default the one its host was scanned against.
+    pub async fn diff_snapshot(&self, id: u64, against: Option<&str>) -> Result<BaselineDiff> {
+        let url = self.url(&format!("/snapshots/{}/diff", id));
+        let query: Vec<(&str, &str)> = against.map(|image_id| ("against", image_id)).into_iter().collect();
+        debug!("GET {} {:?}", url, query);
+        let response = Self::check(self.send(|http| http.get(&url).query(&query)).await?).await?;
+        response.json().await.map_err(http_error)
+    }
+
+    /// Fetches the YARA rule set the service distributes.
+    pub async fn get_yara_rules(&self) -> Result<String> {
+        let url = self.url("/yara-rules");
+        debug!("GET {}", url);
+        let response = Self::check(self.send(|http| http.get(&url)).await?).await?;
+        response.text().await.map_err(http_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Answers every request with `status`; returns the client's config and
+    /// the count of requests received.
+    async fn serve(status: &'static str) -> (ClientConfig, Arc<AtomicUsize>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = ClientConfig { max_retries: 2, ..ClientConfig::new(&format!("http://{}", listener.local_addr().unwrap())) };
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                // Read the head and the body it announces, then answer and close
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).to_lowercase();
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length = text
+                            .lines()
+                            .find_map(|line| line.strip_prefix("content-length:"))
+                            .map_or(0, |length| length.trim().parse().unwrap());
+                        if request.len() >= end + 4 + length {
+                            break;
+                        }
+                    }
+                }
+                let response = format!("HTTP/1.1 {}\r\ncontent-length: 4\r\nconnection: close\r\n\r\nnope", status);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (config, requests)
+    }
+
+    #[test]
+    fn test_segment() {
+        assert_eq!(segment("registry/app:1.0").to_string(), "registry%2Fapp%3A1.0");
+        assert_eq!(segment("ubuntu-22.04_v1~rc").to_string(), "ubuntu-22.04_v1~rc");
+        assert_eq!(segment("web 1?x#y").to_string(), "web%201%3Fx%23y");
+    }
+
+    #[tokio::test]
+    async fn test_not_found() {
+        let (config, requests) = serve("404 Not Found").await;
+        let client = MetadataClient::new(config).unwrap();
+        assert!(matches!(client.get_baseline("app").await, Err(IntegrityError::BaselineNotFound(body)) if body == "nope"));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_only_idempotent_requests_are_retried() {
+        let (config, requests) = serve("503 Service Unavailable").await;
+        let client = MetadataClient::new(config).unwrap();
+
+        assert!(matches!(client.list_agents(None, None).await, Err(IntegrityError::Storage(_))));
+        assert_eq!(requests.swap(0, Ordering::SeqCst), 3);
+
+        let registration = AgentRegistration {
+            host: "web-1".to_string(),
+            image_id: "app".to_string(),
+            agent_version: "0.1.0".to_string(),
+            mode: "Scan".to_string(),
+        };
+        assert!(matches!(client.register_agent(&registration).await, Err(IntegrityError::Storage(_))));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
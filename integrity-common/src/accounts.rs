@@ -0,0 +1,349 @@
+//! Parsed account and privilege files: passwd, group, shadow and sudoers.
+//!
+//! A hash mismatch on `/etc/passwd` says something changed; the records kept
+//! here say what. Password hashes are never recorded: the shadow password
+//! field is kept as its SHA512 plus whether it is set, locked or empty.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const PASSWD: &str = "etc/passwd";
+pub const GROUP: &str = "etc/group";
+pub const SHADOW: &str = "etc/shadow";
+pub const SUDOERS: &str = "etc/sudoers";
+pub const SUDOERS_DIR: &str = "etc/sudoers.d";
+
+/// Whether changes to `path` (relative) are described by [`AccountRecords::changes`].
+pub fn is_account_file(path: &str) -> bool {
+    let path = path.trim_start_matches('/');
+    [PASSWD, GROUP, SHADOW, SUDOERS].contains(&path)
+        || path.strip_prefix(SUDOERS_DIR).is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct User {
+    pub uid: u32,
+    pub gid: u32,
+    pub gecos: String,
+    pub home: String,
+    pub shell: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Group {
+    pub gid: u32,
+    pub members: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Password {
+    /// "set", "locked" or "empty"
+    pub state: String,
+    /// Hex encoded SHA512 of the shadow password field
+    pub sha512: String,
+}
+
+/// Accounts, groups, passwords and sudo rules of an image.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AccountRecords {
+    #[serde(default)]
+    pub users: BTreeMap<String, User>,
+    #[serde(default)]
+    pub groups: BTreeMap<String, Group>,
+    #[serde(default)]
+    pub passwords: BTreeMap<String, Password>,
+    /// Rules and directives of each sudoers file, by relative path
+    #[serde(default)]
+    pub sudo_rules: BTreeMap<String, Vec<String>>,
+}
+
+/// A user, group, password or sudo rule that differs from the recorded one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountChange {
+    /// e.g. "USER_ADDED", "SUDO_RULE_REMOVED"
+    pub kind: &'static str,
+    /// The file the record comes from, relative to root
+    pub path: String,
+    pub detail: String,
+}
+
+fn change(kind: &'static str, path: &str, detail: String) -> AccountChange {
+    AccountChange { kind, path: path.to_string(), detail }
+}
+
+fn describe_user(name: &str, user: &User) -> String {
+    format!("{}: uid {}, gid {}, home {}, shell {}", name, user.uid, user.gid, user.home, user.shell)
+}
+
+/// Names of the fields that differ, with old and new values.
+fn user_fields(old: &User, new: &User) -> Vec<String> {
+    let mut fields = Vec::new();
+    if old.uid != new.uid {
+        fields.push(format!("uid {} -> {}", old.uid, new.uid));
+    }
+    if old.gid != new.gid {
+        fields.push(format!("gid {} -> {}", old.gid, new.gid));
+    }
+    if old.gecos != new.gecos {
+        fields.push(format!("gecos {:?} -> {:?}", old.gecos, new.gecos));
+    }
+    if old.home != new.home {
+        fields.push(format!("home {} -> {}", old.home, new.home));
+    }
+    if old.shell != new.shell {
+        fields.push(format!("shell {} -> {}", old.shell, new.shell));
+    }
+    fields
+}
+
+impl AccountRecords {
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.groups.is_empty() && self.passwords.is_empty() && self.sudo_rules.is_empty()
+    }
+
+    /// Everything that differs in `current` from these recorded records.
+    pub fn changes(&self, current: &AccountRecords) -> Vec<AccountChange> {
+        let mut changes = Vec::new();
+
+        for (name, old) in &self.users {
+            match current.users.get(name) {
+                None => changes.push(change("USER_REMOVED", PASSWD, describe_user(name, old))),
+                Some(new) if new != old => {
+                    changes.push(change("USER_CHANGED", PASSWD, format!("{}: {}", name, user_fields(old, new).join(", "))))
+                }
+                Some(_) => {}
+            }
+        }
+        for (name, new) in current.users.iter().filter(|(name, _)| !self.users.contains_key(*name)) {
+            changes.push(change("USER_ADDED", PASSWD, describe_user(name, new)));
+        }
+
+        for (name, old) in &self.groups {
+            match current.groups.get(name) {
+                None => changes.push(change("GROUP_REMOVED", GROUP, format!("{}: gid {}", name, old.gid))),
+                Some(new) if new != old => {
+                    let mut fields = Vec::new();
+                    if old.gid != new.gid {
+                        fields.push(format!("gid {} -> {}", old.gid, new.gid));
+                    }
+                    let added: Vec<&str> =
+                        new.members.iter().filter(|m| !old.members.contains(m)).map(String::as_str).collect();
+                    let removed: Vec<&str> =
+                        old.members.iter().filter(|m| !new.members.contains(m)).map(String::as_str).collect();
+                    if !added.is_empty() {
+                        fields.push(format!("members added: {}", added.join(",")));
+                    }
+                    if !removed.is_empty() {
+                        fields.push(format!("members removed: {}", removed.join(",")));
+                    }
+                    if !fields.is_empty() {
+                        changes.push(change("GROUP_CHANGED", GROUP, format!("{}: {}", name, fields.join(", "))));
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        for (name, new) in current.groups.iter().filter(|(name, _)| !self.groups.contains_key(*name)) {
+            let detail = format!("{}: gid {}, members {}", name, new.gid, new.members.join(","));
+            changes.push(change("GROUP_ADDED", GROUP, detail));
+        }
+
+        // Removed accounts are reported above, along with their password
+        for (name, old) in &self.passwords {
+            match current.passwords.get(name) {
+                Some(new) if new.state != old.state => changes.push(change(
+                    "PASSWORD_CHANGED",
+                    SHADOW,
+                    format!("{}: password {} -> {}", name, old.state, new.state),
+                )),
+                Some(new) if new.sha512 != old.sha512 => {
+                    changes.push(change("PASSWORD_CHANGED", SHADOW, format!("{}: password replaced", name)))
+                }
+                _ => {}
+            }
+        }
+        for (name, new) in current.passwords.iter().filter(|(name, _)| !self.passwords.contains_key(*name)) {
+            if new.state != "locked" {
+                changes.push(change("PASSWORD_CHANGED", SHADOW, format!("{}: new account with password {}", name, new.state)));
+            }
+        }
+
+        let files: std::collections::BTreeSet<&String> = self.sudo_rules.keys().chain(current.sudo_rules.keys()).collect();
+        for file in files {
+            let old = self.sudo_rules.get(file).map(Vec::as_slice).unwrap_or_default();
+            let new = current.sudo_rules.get(file).map(Vec::as_slice).unwrap_or_default();
+            for rule in new.iter().filter(|r| !old.contains(r)) {
+                changes.push(change("SUDO_RULE_ADDED", file, rule.clone()));
+            }
+            for rule in old.iter().filter(|r| !new.contains(r)) {
+                changes.push(change("SUDO_RULE_REMOVED", file, rule.clone()));
+            }
+        }
+
+        changes
+    }
+}
+
+#[cfg(feature = "host")]
+mod host {
+    use super::*;
+    use sha2::{Digest, Sha512};
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    /// Records of a colon-separated file, split into fields; comments and blank lines skipped.
+    fn records(contents: &str) -> impl Iterator<Item = Vec<&str>> {
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|line| line.split(':').collect())
+    }
+
+    pub(super) fn parse_passwd(contents: &str) -> BTreeMap<String, User> {
+        records(contents)
+            .filter_map(|f| {
+                let user = User {
+                    uid: f.get(2)?.parse().ok()?,
+                    gid: f.get(3)?.parse().ok()?,
+                    gecos: f.get(4)?.to_string(),
+                    home: f.get(5)?.to_string(),
+                    shell: f.get(6).unwrap_or(&"").to_string(),
+                };
+                Some((f[0].to_string(), user))
+            })
+            .collect()
+    }
+
+    pub(super) fn parse_group(contents: &str) -> BTreeMap<String, Group> {
+        records(contents)
+            .filter_map(|f| {
+                let members = f.get(3).unwrap_or(&"").split(',').filter(|m| !m.is_empty()).map(String::from).collect();
+                Some((f[0].to_string(), Group { gid: f.get(2)?.parse().ok()?, members }))
+            })
+            .collect()
+    }
+
+    pub(super) fn parse_shadow(contents: &str) -> BTreeMap<String, Password> {
+        records(contents)
+            .filter_map(|f| {
+                let field = *f.get(1)?;
+                let state = match field {
+                    "" => "empty",
+                    _ if field.starts_with('!') || field.starts_with('*') => "locked",
+                    _ => "set",
+                };
+                let password = Password {
+                    state: state.to_string(),
+                    sha512: hex::encode(Sha512::digest(field.as_bytes())),
+                };
+                Some((f[0].to_string(), password))
+            })
+            .collect()
+    }
+
+    /// Rules and directives of a sudoers file, continuation lines joined and whitespace collapsed.
+    pub(super) fn parse_sudoers(contents: &str) -> Vec<String> {
+        let mut rules = Vec::new();
+        let mut current = String::new();
+        for line in contents.lines() {
+            let (line, continued) = match line.strip_suffix('\\') {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            current.push_str(line);
+            current.push(' ');
+            if continued {
+                continue;
+            }
+            let rule = current.split_whitespace().collect::<Vec<_>>().join(" ");
+            current.clear();
+            // "#include" and "#includedir" are directives, not comments
+            let comment = rule.starts_with('#') && !rule.starts_with("#include");
+            if !rule.is_empty() && !comment {
+                rules.push(rule);
+            }
+        }
+        rules
+    }
+
+    fn read_optional(path: &Path) -> io::Result<Option<String>> {
+        match fs::read(path) {
+            Ok(contents) => Ok(Some(String::from_utf8_lossy(&contents).into_owned())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    impl AccountRecords {
+        /// Reads the account files of the filesystem at `root`.
+        pub fn read(root: &Path) -> io::Result<AccountRecords> {
+            let mut records = AccountRecords::default();
+            if let Some(contents) = read_optional(&root.join(PASSWD))? {
+                records.users = parse_passwd(&contents);
+            }
+            if let Some(contents) = read_optional(&root.join(GROUP))? {
+                records.groups = parse_group(&contents);
+            }
+            if let Some(contents) = read_optional(&root.join(SHADOW))? {
+                records.passwords = parse_shadow(&contents);
+            }
+
+            let mut sudoers = vec![SUDOERS.to_string()];
+            if let Ok(entries) = fs::read_dir(root.join(SUDOERS_DIR)) {
+                for entry in entries.filter_map(|e| e.ok()) {
+                    sudoers.push(format!("{}/{}", SUDOERS_DIR, entry.file_name().to_string_lossy()));
+                }
+            }
+            for file in sudoers {
+                if let Some(contents) = read_optional(&root.join(&file))? {
+                    records.sudo_rules.insert(file, parse_sudoers(&contents));
+                }
+            }
+            Ok(records)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "host"))]
+mod tests {
+    use super::host::*;
+    use super::*;
+
+    #[test]
+    fn test_changes() {
+        let recorded = AccountRecords {
+            users: parse_passwd("root:x:0:0:root:/root:/bin/bash\nbob:x:1000:1000::/home/bob:/bin/sh\n"),
+            groups: parse_group("sudo:x:27:alice\n"),
+            passwords: parse_shadow("root:$6$salt$hash:19000:0:99999:7:::\nbob:!:19000::::::\n"),
+            sudo_rules: [("etc/sudoers".to_string(), parse_sudoers("# comment\nroot ALL=(ALL) ALL\n#includedir /etc/sudoers.d\n"))]
+                .into(),
+        };
+        assert!(recorded.changes(&recorded).is_empty());
+
+        let current = AccountRecords {
+            users: parse_passwd("root:x:0:0:root:/root:/bin/bash\nbob:x:0:1000::/home/bob:/bin/sh\n"),
+            groups: parse_group("sudo:x:27:alice,bob\n"),
+            passwords: parse_shadow("root::19000:0:99999:7:::\nbob:!:19000::::::\n"),
+            sudo_rules: [(
+                "etc/sudoers".to_string(),
+                parse_sudoers("root ALL=(ALL) ALL\nbob ALL=(ALL) \\\n    NOPASSWD: ALL\n#includedir /etc/sudoers.d\n"),
+            )]
+            .into(),
+        };
+        let changes: Vec<String> =
+            recorded.changes(&current).iter().map(|c| format!("{} {} {}", c.kind, c.path, c.detail)).collect();
+        assert_eq!(
+            changes,
+            vec![
+                "USER_CHANGED etc/passwd bob: uid 1000 -> 0",
+                "GROUP_CHANGED etc/group sudo: members added: bob",
+                "PASSWORD_CHANGED etc/shadow root: password set -> empty",
+                "SUDO_RULE_ADDED etc/sudoers bob ALL=(ALL) NOPASSWD: ALL",
+            ]
+        );
+
+        assert!(is_account_file("/etc/sudoers.d/90-cloud"));
+        assert!(!is_account_file("etc/sudoers.dist"));
+    }
+}
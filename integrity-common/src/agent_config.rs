@@ -0,0 +1,141 @@
+//! Agent configuration managed by the metadata service.
+//!
+//! Operators store settings for all hosts of an image and for single hosts;
+//! every change, including a rollback, is a new numbered version of its scope,
+//! so the history shows who changed what. An agent asks for the effective
+//! settings of its host and image: the host's override the image's.
+//! Settings are agent flags without the leading dashes, e.g.
+//! `watch-paths = "/etc,/usr/bin"`; flags that take no value are "true" or
+//! "false".
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Scope of the configuration shared by all hosts of an image.
+pub const IMAGE_SCOPE: &str = "image";
+/// Scope of the configuration of one host.
+pub const HOST_SCOPE: &str = "host";
+
+/// Agent flag (without the dashes) to value.
+pub type AgentSettings = BTreeMap<String, String>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AgentConfigVersion {
+    /// 1 for the first version of a scope
+    pub version: u64,
+    pub settings: AgentSettings,
+    pub author: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// Unix time it was stored
+    pub created_at: i64,
+}
+
+/// Every version stored for an image or host, oldest first; the last one is in effect.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AgentConfigHistory {
+    /// `image` or `host`
+    pub scope: String,
+    /// Image ID or host name
+    pub name: String,
+    pub versions: Vec<AgentConfigVersion>,
+}
+
+impl AgentConfigHistory {
+    pub fn new(scope: &str, name: &str) -> Self {
+        Self { scope: scope.to_string(), name: name.to_string(), versions: Vec::new() }
+    }
+
+    pub fn current(&self) -> Option<&AgentConfigVersion> {
+        self.versions.last()
+    }
+
+    /// Stores `settings` as the next version. Returns its number.
+    pub fn push(&mut self, settings: AgentSettings, author: &str, comment: Option<String>, now: i64) -> u64 {
+        let version = self.current().map_or(1, |current| current.version + 1);
+        self.versions.push(AgentConfigVersion { version, settings, author: author.to_string(), comment, created_at: now });
+        version
+    }
+
+    /// Stores the settings of an earlier `version` as the next version, or
+    /// returns None if there is no such version.
+    pub fn rollback(&mut self, version: u64, author: &str, comment: Option<String>, now: i64) -> Option<u64> {
+        let settings = self.versions.iter().find(|v| v.version == version)?.settings.clone();
+        let comment = Some(comment.unwrap_or_else(|| format!("rollback to version {}", version)));
+        Some(self.push(settings, author, comment, now))
+    }
+}
+
+/// A request to store new settings for a scope.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentConfigUpdate {
+    pub settings: AgentSettings,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+/// A request to make an earlier version of a scope current again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentConfigRollback {
+    pub version: u64,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+/// The settings an agent applies, and the versions they came from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct EffectiveAgentConfig {
+    pub settings: AgentSettings,
+    #[serde(default)]
+    pub image_version: Option<u64>,
+    #[serde(default)]
+    pub host_version: Option<u64>,
+}
+
+impl EffectiveAgentConfig {
+    /// The image's settings with the host's on top.
+    pub fn merge(image: Option<&AgentConfigVersion>, host: Option<&AgentConfigVersion>) -> Self {
+        let mut settings = AgentSettings::new();
+        for version in image.iter().chain(host.iter()) {
+            settings.extend(version.settings.iter().map(|(flag, value)| (flag.clone(), value.clone())));
+        }
+        Self { settings, image_version: image.map(|v| v.version), host_version: host.map(|v| v.version) }
+    }
+
+    /// e.g. "image v3, host v1", for logs.
+    pub fn revision(&self) -> String {
+        let revision: Vec<String> = [(IMAGE_SCOPE, self.image_version), (HOST_SCOPE, self.host_version)]
+            .into_iter()
+            .filter_map(|(scope, version)| version.map(|version| format!("{} v{}", scope, version)))
+            .collect();
+        if revision.is_empty() { "none".to_string() } else { revision.join(", ") }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(pairs: &[(&str, &str)]) -> AgentSettings {
+        pairs.iter().map(|(flag, value)| (flag.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_versions_and_merge() {
+        let mut image = AgentConfigHistory::new(IMAGE_SCOPE, "app-v1");
+        assert_eq!(image.push(settings(&[("watch-paths", "/etc"), ("falco-output", "stdout")]), "alice", None, 100), 1);
+        assert_eq!(image.push(settings(&[("watch-paths", "/etc,/usr/bin")]), "alice", None, 200), 2);
+        assert_eq!(image.rollback(1, "bob", None, 300), Some(3));
+        assert_eq!(image.rollback(7, "bob", None, 300), None);
+        let current = image.current().unwrap();
+        assert_eq!(current.settings, image.versions[0].settings);
+        assert_eq!(current.comment.as_deref(), Some("rollback to version 1"));
+
+        let mut host = AgentConfigHistory::new(HOST_SCOPE, "web-1");
+        host.push(settings(&[("falco-output", "http://falcosidekick:2801")]), "alice", None, 400);
+        let effective = EffectiveAgentConfig::merge(image.current(), host.current());
+        assert_eq!(effective.settings, settings(&[("watch-paths", "/etc"), ("falco-output", "http://falcosidekick:2801")]));
+        assert_eq!(effective.revision(), "image v3, host v1");
+        assert_eq!(EffectiveAgentConfig::merge(None, None).revision(), "none");
+    }
+}
@@ -0,0 +1,92 @@
+//! Protocol of the agent's local control socket.
+//!
+//! `integrity-agentctl` connects to the Unix socket of a running agent,
+//! writes one `ControlRequest` as a JSON line, e.g. `{"command": "status"}`,
+//! and reads back one `ControlResponse` line.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Where the agent listens unless told otherwise.
+pub const DEFAULT_CONTROL_SOCKET: &str = "/run/acropole-agent/control.sock";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    Status,
+    /// Re-scan these paths, or all watch paths if empty
+    Rescan {
+        #[serde(default)]
+        paths: Vec<String>,
+    },
+    /// Fetch the baseline again and restart monitoring with it
+    ReloadBaseline,
+    /// Stop alerting for `duration_secs` (until exited if None); anomalies are still recorded locally
+    EnterMaintenance {
+        #[serde(default)]
+        duration_secs: Option<u64>,
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    ExitMaintenance,
+    /// The effective value of every agent flag
+    Policy,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MaintenanceWindow {
+    /// Unix time it began
+    pub since: i64,
+    /// Unix time it ends by itself, if it does
+    #[serde(default)]
+    pub until: Option<i64>,
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Uid of the peer that entered it
+    #[serde(default)]
+    pub uid: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AgentStatus {
+    pub pid: u32,
+    pub mode: String,
+    pub image_id: String,
+    pub baseline_entries: usize,
+    /// Unix time monitoring started
+    pub started_at: i64,
+    pub watch_paths: Vec<String>,
+    #[serde(default)]
+    pub maintenance: Option<MaintenanceWindow>,
+    /// Anomalies in the local history not yet resolved, if there is a history
+    #[serde(default)]
+    pub open_anomalies: Option<usize>,
+    /// Verification counters
+    pub metrics: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Status(AgentStatus),
+    /// Flag (without the dashes) to value; secrets are redacted
+    Policy { flags: BTreeMap<String, String> },
+    Done { message: String },
+    Error { message: String },
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wire_format() {
+        let request: ControlRequest = serde_json::from_str(r#"{"command": "enter_maintenance", "duration_secs": 3600}"#).unwrap();
+        assert_eq!(request, ControlRequest::EnterMaintenance { duration_secs: Some(3600), reason: None });
+        let request: ControlRequest = serde_json::from_str(r#"{"command": "rescan"}"#).unwrap();
+        assert_eq!(request, ControlRequest::Rescan { paths: Vec::new() });
+
+        let response = serde_json::to_value(ControlResponse::Done { message: "re-scan queued".to_string() }).unwrap();
+        assert_eq!(response, serde_json::json!({"result": "done", "message": "re-scan queued"}));
+    }
+}
@@ -0,0 +1,392 @@
+//! Anomalies: how a host differs from its baseline.
+//!
+//! Detectors build an [`AnomalyReport`] with the structured difference
+//! (expected and actual hash, mode, owner, size, mtime, link count, symlink
+//! target or extended attribute) and outputs decide how to show
+//! it. The text form is the one the agent has always logged,
+//! "KIND: path (detail)", e.g. "PERMISSION_CHANGED: etc/shadow (640 != 644)";
+//! [`AnomalyReport::parse`] reads it back for anomalies that only exist as
+//! text, such as those of plugins and earlier runs.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The side of a SYMLINK_TARGET_CHANGED detail that is not a symlink.
+const NOT_A_SYMLINK: &str = "(not a symlink)";
+
+/// The side of an XATTR_CHANGED detail without the attribute.
+const NO_XATTR: &str = "(none)";
+
+/// How urgent an anomaly is, from the mildest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+/// What differs from the baseline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Anomaly {
+    /// A file the baseline does not have
+    Added,
+    /// A baselined file that is gone, with the error reading it if there was one
+    Deleted { reason: Option<String> },
+    /// A baselined file whose content changed, digests in the baseline's
+    /// hash algorithm despite their names
+    Modified { expected_sha512: String, actual_sha512: String },
+    PermissionChanged { expected: u32, actual: u32 },
+    UidChanged { expected: u32, actual: u32 },
+    GidChanged { expected: u32, actual: u32 },
+    /// A baselined file whose size changed, found before hashing it
+    SizeChanged { expected: u64, actual: u64 },
+    /// A baselined file whose content matches but whose modification time
+    /// (seconds since the Unix epoch) was changed
+    MtimeChanged { expected: i64, actual: i64 },
+    /// A baselined file hard-linked more or fewer times
+    LinkCountChanged { expected: u64, actual: u64 },
+    /// A baselined symlink pointing elsewhere, None on the side where the
+    /// path is not a symlink
+    SymlinkTargetChanged { expected: Option<String>, actual: Option<String> },
+    /// An extended attribute (SELinux label, capabilities, ...) added, removed
+    /// or changed, values as `describe_xattr` renders them
+    XattrChanged { name: String, expected: Option<String>, actual: Option<String> },
+    /// A baselined file that could not be read to hash it
+    HashError { error: String },
+    /// The findings of the other checks (processes, boot, kernel modules, ...), by kind
+    Other { kind: String, detail: String },
+}
+
+impl Anomaly {
+    /// The kind as logged, e.g. "MODIFIED".
+    pub fn kind(&self) -> &str {
+        match self {
+            Anomaly::Added => "ADDED",
+            Anomaly::Deleted { .. } => "DELETED",
+            Anomaly::Modified { .. } => "MODIFIED",
+            Anomaly::PermissionChanged { .. } => "PERMISSION_CHANGED",
+            Anomaly::UidChanged { .. } => "UID_CHANGED",
+            Anomaly::GidChanged { .. } => "GID_CHANGED",
+            Anomaly::SizeChanged { .. } => "SIZE_CHANGED",
+            Anomaly::MtimeChanged { .. } => "MTIME_CHANGED",
+            Anomaly::LinkCountChanged { .. } => "LINK_COUNT_CHANGED",
+            Anomaly::SymlinkTargetChanged { .. } => "SYMLINK_TARGET_CHANGED",
+            Anomaly::XattrChanged { .. } => "XATTR_CHANGED",
+            Anomaly::HashError { .. } => "ERROR_HASHING",
+            Anomaly::Other { kind, .. } => kind,
+        }
+    }
+
+    /// The detail as logged, empty if there is none.
+    pub fn detail(&self) -> String {
+        match self {
+            Anomaly::Added => String::new(),
+            Anomaly::Deleted { reason } => reason.clone().unwrap_or_default(),
+            Anomaly::Modified { expected_sha512, actual_sha512 } => format!("hash mismatch: {} != {}", expected_sha512, actual_sha512),
+            Anomaly::PermissionChanged { expected, actual } => format!("{:o} != {:o}", expected, actual),
+            Anomaly::UidChanged { expected, actual } | Anomaly::GidChanged { expected, actual } => format!("{} != {}", expected, actual),
+            Anomaly::SizeChanged { expected, actual } | Anomaly::LinkCountChanged { expected, actual } => format!("{} != {}", expected, actual),
+            Anomaly::MtimeChanged { expected, actual } => format!("{} != {}", expected, actual),
+            Anomaly::SymlinkTargetChanged { expected, actual } => {
+                let target = |target: &Option<String>| target.clone().unwrap_or_else(|| NOT_A_SYMLINK.to_string());
+                format!("{} != {}", target(expected), target(actual))
+            }
+            Anomaly::XattrChanged { name, expected, actual } => {
+                let value = |value: &Option<String>| value.clone().unwrap_or_else(|| NO_XATTR.to_string());
+                format!("{}: {} != {}", name, value(expected), value(actual))
+            }
+            Anomaly::HashError { error } => error.clone(),
+            Anomaly::Other { detail, .. } => detail.clone(),
+        }
+    }
+
+    /// The baseline's value of what changed.
+    pub fn expected(&self) -> Option<String> {
+        match self {
+            Anomaly::Modified { expected_sha512, .. } => Some(expected_sha512.clone()),
+            Anomaly::PermissionChanged { expected, .. } => Some(format!("{:o}", expected)),
+            Anomaly::UidChanged { expected, .. } | Anomaly::GidChanged { expected, .. } => Some(expected.to_string()),
+            Anomaly::SizeChanged { expected, .. } | Anomaly::LinkCountChanged { expected, .. } => Some(expected.to_string()),
+            Anomaly::MtimeChanged { expected, .. } => Some(expected.to_string()),
+            Anomaly::SymlinkTargetChanged { expected, .. } | Anomaly::XattrChanged { expected, .. } => expected.clone(),
+            _ => None,
+        }
+    }
+
+    /// The value found on the host.
+    pub fn actual(&self) -> Option<String> {
+        match self {
+            Anomaly::Modified { actual_sha512, .. } => Some(actual_sha512.clone()),
+            Anomaly::PermissionChanged { actual, .. } => Some(format!("{:o}", actual)),
+            Anomaly::UidChanged { actual, .. } | Anomaly::GidChanged { actual, .. } => Some(actual.to_string()),
+            Anomaly::SizeChanged { actual, .. } | Anomaly::LinkCountChanged { actual, .. } => Some(actual.to_string()),
+            Anomaly::MtimeChanged { actual, .. } => Some(actual.to_string()),
+            Anomaly::SymlinkTargetChanged { actual, .. } | Anomaly::XattrChanged { actual, .. } => actual.clone(),
+            _ => None,
+        }
+    }
+
+    pub fn default_severity(&self) -> Severity {
+        match self {
+            Anomaly::Modified { .. }
+            | Anomaly::PermissionChanged { .. }
+            | Anomaly::UidChanged { .. }
+            | Anomaly::GidChanged { .. }
+            | Anomaly::SizeChanged { .. }
+            | Anomaly::SymlinkTargetChanged { .. }
+            | Anomaly::XattrChanged { .. } => Severity::High,
+            Anomaly::Added
+            | Anomaly::Deleted { .. }
+            | Anomaly::MtimeChanged { .. }
+            | Anomaly::LinkCountChanged { .. }
+            | Anomaly::Other { .. } => Severity::Medium,
+            Anomaly::HashError { .. } => Severity::Low,
+        }
+    }
+
+    /// The anomaly a logged kind and detail describe.
+    fn from_parts(kind: &str, detail: &str) -> Anomaly {
+        let values = |detail: &str| detail.split_once(" != ").map(|(e, a)| (e.to_string(), a.to_string()));
+        let counts = || values(detail).and_then(|(e, a)| Some((e.parse::<u64>().ok()?, a.parse::<u64>().ok()?)));
+        let ids = |radix| {
+            values(detail).and_then(|(e, a)| Some((u32::from_str_radix(&e, radix).ok()?, u32::from_str_radix(&a, radix).ok()?)))
+        };
+        let parsed = match kind {
+            "ADDED" if detail.is_empty() => Some(Anomaly::Added),
+            "DELETED" => Some(Anomaly::Deleted { reason: (!detail.is_empty()).then(|| detail.to_string()) }),
+            "MODIFIED" => detail
+                .strip_prefix("hash mismatch: ")
+                .and_then(values)
+                .filter(|(_, a)| !a.contains(' '))
+                .map(|(expected_sha512, actual_sha512)| Anomaly::Modified { expected_sha512, actual_sha512 }),
+            "PERMISSION_CHANGED" => ids(8).map(|(expected, actual)| Anomaly::PermissionChanged { expected, actual }),
+            "UID_CHANGED" => ids(10).map(|(expected, actual)| Anomaly::UidChanged { expected, actual }),
+            "GID_CHANGED" => ids(10).map(|(expected, actual)| Anomaly::GidChanged { expected, actual }),
+            "SIZE_CHANGED" => counts().map(|(expected, actual)| Anomaly::SizeChanged { expected, actual }),
+            "MTIME_CHANGED" => values(detail)
+                .and_then(|(e, a)| Some((e.parse().ok()?, a.parse().ok()?)))
+                .map(|(expected, actual)| Anomaly::MtimeChanged { expected, actual }),
+            "LINK_COUNT_CHANGED" => counts().map(|(expected, actual)| Anomaly::LinkCountChanged { expected, actual }),
+            "SYMLINK_TARGET_CHANGED" => values(detail).map(|(expected, actual)| {
+                let target = |target: String| (target != NOT_A_SYMLINK).then_some(target);
+                Anomaly::SymlinkTargetChanged { expected: target(expected), actual: target(actual) }
+            }),
+            "XATTR_CHANGED" => detail.split_once(": ").and_then(|(name, change)| {
+                let value = |value: String| (value != NO_XATTR).then_some(value);
+                values(change).map(|(expected, actual)| Anomaly::XattrChanged { name: name.to_string(), expected: value(expected), actual: value(actual) })
+            }),
+            "ERROR_HASHING" => Some(Anomaly::HashError { error: detail.to_string() }),
+            _ => None,
+        };
+        parsed.unwrap_or_else(|| Anomaly::Other { kind: kind.to_string(), detail: detail.to_string() })
+    }
+}
+
+impl From<crate::XattrChange> for Anomaly {
+    fn from(change: crate::XattrChange) -> Self {
+        Anomaly::XattrChanged { name: change.name, expected: change.expected, actual: change.actual }
+    }
+}
+
+/// An anomaly found on one path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnomalyReport {
+    pub anomaly: Anomaly,
+    /// Relative to the baselined root, like the baseline's paths
+    pub path: String,
+    /// Unix time it was detected
+    pub timestamp: i64,
+    pub severity: Severity,
+    /// Kinds it was re-labelled with since, e.g. "PRELOAD_INJECTION", the latest last
+    pub labels: Vec<String>,
+    /// What was found out about the file besides the difference, e.g. "yara: Mirai"
+    pub notes: Vec<String>,
+}
+
+impl AnomalyReport {
+    /// An anomaly on `path` detected now.
+    pub fn new(anomaly: Anomaly, path: impl Into<String>) -> Self {
+        let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64);
+        Self { severity: anomaly.default_severity(), anomaly, path: path.into(), timestamp, labels: Vec::new(), notes: Vec::new() }
+    }
+
+    /// An [`Anomaly::Other`] on `path` detected now.
+    pub fn other(kind: &str, path: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self::new(Anomaly::Other { kind: kind.to_string(), detail: detail.into() }, path)
+    }
+
+    /// Reads the text form, "KIND: path (detail)", back.
+    pub fn parse(anomaly: &str) -> Self {
+        let (kind, rest) = anomaly.split_once(": ").unwrap_or(("UNKNOWN", anomaly));
+        let (path, detail) = match rest.split_once(" (") {
+            Some((path, detail)) => (path, detail.strip_suffix(')').unwrap_or(detail)),
+            None => (rest, ""),
+        };
+        Self::new(Anomaly::from_parts(kind, detail), path)
+    }
+
+    pub fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+
+    /// Re-labels the anomaly with `kind`, keeping what it was in the detail,
+    /// and raises its severity to at least `severity`.
+    pub fn relabel(&mut self, kind: &str, severity: Severity) {
+        self.labels.push(kind.to_string());
+        self.severity = self.severity.max(severity);
+    }
+
+    /// The kind it is reported as: its latest label, or the anomaly's own.
+    pub fn kind(&self) -> &str {
+        self.labels.last().map_or(self.anomaly.kind(), String::as_str)
+    }
+
+    /// Everything that goes in the parentheses of the text form: the kinds
+    /// it had before its latest label, the anomaly's detail and the notes.
+    pub fn detail(&self) -> String {
+        let mut detail = String::new();
+        if let Some((_, earlier)) = self.labels.split_last() {
+            for kind in earlier.iter().rev().map(String::as_str).chain([self.anomaly.kind()]) {
+                if !detail.is_empty() {
+                    detail.push_str(": ");
+                }
+                detail.push_str(kind);
+            }
+        }
+        let own = self.anomaly.detail();
+        if !own.is_empty() {
+            if !detail.is_empty() {
+                detail.push_str(": ");
+            }
+            detail.push_str(&own);
+        }
+        for note in &self.notes {
+            if !detail.is_empty() {
+                detail.push_str("; ");
+            }
+            detail.push_str(note);
+        }
+        detail
+    }
+
+    /// Absolute path of the affected file.
+    pub fn absolute_path(&self) -> String {
+        format!("/{}", self.path.trim_start_matches('/'))
+    }
+}
+
+impl fmt::Display for AnomalyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind(), self.path)?;
+        let detail = self.detail();
+        if !detail.is_empty() {
+            write!(f, " ({})", detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// The flat form outputs and policies get.
+#[derive(Serialize)]
+struct FlatReport<'a> {
+    time: i64,
+    kind: &'a str,
+    path: &'a str,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    actual: Option<String>,
+    severity: Severity,
+}
+
+impl Serialize for AnomalyReport {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        FlatReport {
+            time: self.timestamp,
+            kind: self.kind(),
+            path: &self.path,
+            detail: self.detail(),
+            expected: self.anomaly.expected(),
+            actual: self.anomaly.actual(),
+            severity: self.severity,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl From<&AnomalyReport> for crate::ReportedAnomaly {
+    fn from(report: &AnomalyReport) -> Self {
+        Self { time: report.timestamp, kind: report.kind().to_string(), path: report.path.clone(), detail: report.detail() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_form() {
+        for line in [
+            "MODIFIED: usr/bin/ls (hash mismatch: aa != bb)",
+            "PERMISSION_CHANGED: etc/shadow (640 != 644)",
+            "UID_CHANGED: etc/shadow (0 != 1000)",
+            "SIZE_CHANGED: var/log/wtmp (8064 != 0)",
+            "MTIME_CHANGED: usr/bin/ls (1700000000 != 1500000000)",
+            "LINK_COUNT_CHANGED: usr/bin/passwd (1 != 2)",
+            "SYMLINK_TARGET_CHANGED: usr/bin/vi (vim.basic != /tmp/.x/vi)",
+            "XATTR_CHANGED: usr/local/bin/x (security.capability: (none) != cap_net_raw=ep)",
+            "DELETED: etc/hosts",
+            "DELETED: etc/hosts (No such file or directory (os error 2))",
+            "ADDED: tmp/x",
+            "WRITE_BLOCKED: etc/passwd (3 modification attempts denied)",
+        ] {
+            assert_eq!(AnomalyReport::parse(line).to_string(), line);
+        }
+
+        let report = AnomalyReport::parse("PERMISSION_CHANGED: etc/shadow (640 != 644)");
+        assert_eq!(report.anomaly, Anomaly::PermissionChanged { expected: 0o640, actual: 0o644 });
+        assert_eq!(report.anomaly.actual().as_deref(), Some("644"));
+        assert_eq!(report.severity, Severity::High);
+        assert_eq!(AnomalyReport::parse("MODIFIED: etc/motd (x)").anomaly.kind(), "MODIFIED");
+        assert_eq!(report.absolute_path(), "/etc/shadow");
+        assert_eq!(AnomalyReport::parse("SIZE_CHANGED: var/log/wtmp (8064 != 0)").anomaly, Anomaly::SizeChanged { expected: 8064, actual: 0 });
+        let replaced = Anomaly::SymlinkTargetChanged { expected: Some("vim.basic".to_string()), actual: None };
+        assert_eq!(AnomalyReport::parse(&AnomalyReport::new(replaced.clone(), "usr/bin/vi").to_string()).anomaly, replaced);
+    }
+
+    #[test]
+    fn test_labels_and_notes() {
+        let mut report = AnomalyReport::new(Anomaly::Added, "etc/ld.so.preload");
+        report.notes.push("yara: Mirai".to_string());
+        assert_eq!(report.to_string(), "ADDED: etc/ld.so.preload (yara: Mirai)");
+        report.relabel("YARA_MATCH", Severity::High);
+        assert_eq!(report.to_string(), "YARA_MATCH: etc/ld.so.preload (ADDED; yara: Mirai)");
+        report.relabel("PRELOAD_INJECTION", Severity::Critical);
+        assert_eq!(report.to_string(), "PRELOAD_INJECTION: etc/ld.so.preload (YARA_MATCH: ADDED; yara: Mirai)");
+        assert_eq!(report.severity, Severity::Critical);
+
+        let mut report = AnomalyReport::parse("MODIFIED: etc/cron.d/x (hash mismatch: a != b)");
+        report.relabel("PERSISTENCE_MECHANISM", Severity::Critical);
+        assert_eq!(report.to_string(), "PERSISTENCE_MECHANISM: etc/cron.d/x (MODIFIED: hash mismatch: a != b)");
+        let reported = crate::ReportedAnomaly::from(&report);
+        assert_eq!(reported.kind, "PERSISTENCE_MECHANISM");
+        assert_eq!(reported.detail, "MODIFIED: hash mismatch: a != b");
+    }
+}
@@ -0,0 +1,187 @@
+//! Canonical JSON serialization.
+//!
+//! Two logically identical baselines must always produce the same bytes, no matter
+//! which component serialized them or in which order entries were collected.
+//! The rules are:
+//! - object keys are sorted by their UTF-8 bytes
+//! - baseline entries are sorted by path
+//! - no insignificant whitespace
+//! - strings are escaped minimally: `"` and `\`, the short escapes `\b \f \n \r \t`,
+//!   other control characters as lowercase `\u00xx`; everything else is raw UTF-8
+//! - integers are written in plain decimal, floats use the shortest round-trip form
+
+use crate::{Baseline, DerivedBaseline, EntryDigests, FileIntegrityEntry, Result};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha512};
+
+/// Serializes any value to canonical JSON bytes.
+pub fn to_canonical_json<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let value = serde_json::to_value(value)?;
+    let mut out = Vec::new();
+    write_value(&value, &mut out);
+    Ok(out)
+}
+
+fn write_value(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.extend_from_slice(b"null"),
+        Value::Bool(b) => out.extend_from_slice(if *b { b"true" } else { b"false" }),
+        Value::Number(n) => out.extend_from_slice(n.to_string().as_bytes()),
+        Value::String(s) => write_string(s, out),
+        Value::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_value(item, out);
+            }
+            out.push(b']');
+        }
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push(b'{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_string(key, out);
+                out.push(b':');
+                write_value(&map[key], out);
+            }
+            out.push(b'}');
+        }
+    }
+}
+
+fn write_string(s: &str, out: &mut Vec<u8>) {
+    out.push(b'"');
+    for c in s.chars() {
+        match c {
+            '"' => out.extend_from_slice(b"\\\""),
+            '\\' => out.extend_from_slice(b"\\\\"),
+            '\u{08}' => out.extend_from_slice(b"\\b"),
+            '\u{0c}' => out.extend_from_slice(b"\\f"),
+            '\n' => out.extend_from_slice(b"\\n"),
+            '\r' => out.extend_from_slice(b"\\r"),
+            '\t' => out.extend_from_slice(b"\\t"),
+            c if (c as u32) < 0x20 => out.extend_from_slice(format!("\\u{:04x}", c as u32).as_bytes()),
+            c => {
+                let mut buf = [0u8; 4];
+                out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    out.push(b'"');
+}
+
+impl Baseline {
+    /// Canonical JSON form of the baseline, with entries sorted by path.
+    pub fn canonical_json(&self) -> Result<Vec<u8>> {
+        let mut sorted = self.clone();
+        sorted.entries.sort_by(|a, b| a.path.cmp(&b.path));
+        to_canonical_json(&sorted)
+    }
+
+    /// Hex encoded SHA-512 of the canonical JSON form.
+    /// Used for ETags, signatures, and comparing baselines produced independently.
+    pub fn digest(&self) -> Result<String> {
+        let canonical = self.canonical_json()?;
+        Ok(hex::encode(Sha512::digest(&canonical)))
+    }
+}
+
+impl FileIntegrityEntry {
+    /// Hex encoded, truncated SHA-512 of the canonical JSON form without the
+    /// path: equal for entries recording the same thing of a path.
+    pub fn fingerprint(&self) -> Result<String> {
+        let canonical = to_canonical_json(&FileIntegrityEntry { path: String::new(), ..self.clone() })?;
+        Ok(hex::encode(&Sha512::digest(&canonical)[..16]))
+    }
+}
+
+impl EntryDigests {
+    pub fn of(baseline: &Baseline, digest: String) -> Result<Self> {
+        let entries = baseline
+            .entries
+            .iter()
+            .map(|entry| Ok((entry.path.trim_matches('/').to_string(), entry.fingerprint()?)))
+            .collect::<Result<_>>()?;
+        Ok(Self { image_id: baseline.image_id.clone(), digest, entries })
+    }
+}
+
+impl DerivedBaseline {
+    /// Stores `baseline` as its difference from the stored baseline whose
+    /// entries are fingerprinted in `previous`: entries it does not have the
+    /// same, and paths it has that `baseline` does not.
+    pub fn from_digests(previous: &EntryDigests, baseline: &Baseline) -> Result<Self> {
+        let mut entries = Vec::new();
+        let mut kept = std::collections::HashSet::new();
+        for entry in &baseline.entries {
+            let path = entry.path.trim_matches('/');
+            kept.insert(path);
+            if previous.entries.get(path) != Some(&entry.fingerprint()?) {
+                entries.push(entry.clone());
+            }
+        }
+        Ok(Self {
+            image_id: baseline.image_id.clone(),
+            extends: previous.image_id.clone(),
+            extends_version: None,
+            timestamp: baseline.timestamp.clone(),
+            entries,
+            removed: previous.entries.keys().filter(|path| !kept.contains(path.as_str())).cloned().collect(),
+            mac_policy: baseline.mac_policy.clone(),
+            sysctls: baseline.sysctls.clone(),
+            accounts: baseline.accounts.clone(),
+            listeners: baseline.listeners.clone(),
+            trust_store: baseline.trust_store.clone(),
+            kernel_cmdline: baseline.kernel_cmdline.clone(),
+            hash_algorithm: baseline.hash_algorithm,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::BaselineBuilder;
+
+    #[test]
+    fn test_entry_order_does_not_change_digest() {
+        let a = BaselineBuilder::new("img").size(20).build();
+        let mut b = a.clone();
+        b.entries.reverse();
+        assert_eq!(a.digest().unwrap(), b.digest().unwrap());
+        assert_eq!(a.digest().unwrap().len(), 128);
+    }
+
+    #[test]
+    fn test_canonical_form() {
+        let baseline = BaselineBuilder::new("img\n\"x\"").size(0).timestamp("t").file_with_digest("etc/é", 0o644, "abc").build();
+        let json = String::from_utf8(baseline.canonical_json().unwrap()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"entries":[{"gid":0,"mode":420,"path":"etc/é","sha512":"abc","uid":0}],"image_id":"img\n\"x\"","timestamp":"t"}"#
+        );
+    }
+
+    #[test]
+    fn test_delta_from_digests() {
+        let builder = BaselineBuilder::new("img").size(0);
+        let previous = builder.clone().file_with_digest("bin/sh", 0o755, "a1").file_with_digest("etc/old", 0o644, "a2").file_with_digest("etc/hosts", 0o644, "a3").build();
+        let mut current = builder.file_with_digest("bin/sh", 0o755, "a1").file_with_digest("etc/hosts", 0o600, "a3").file_with_digest("etc/new", 0o644, "a4").build();
+        // Paths compare without their leading slash
+        current.entries[0].path.insert(0, '/');
+
+        let digests = EntryDigests::of(&previous, previous.digest().unwrap()).unwrap();
+        assert_eq!(digests.entries["bin/sh"], previous.entries[0].fingerprint().unwrap());
+        let delta = DerivedBaseline::from_digests(&digests, &current).unwrap();
+        assert_eq!(delta.entries, current.entries[1..]);
+        assert_eq!(delta.removed, ["etc/old"]);
+        assert_eq!(delta.materialize(&previous).entries.len(), 3);
+    }
+}
@@ -0,0 +1,71 @@
+//! Baselines as CBOR (RFC 8949).
+//!
+//! The same serde model as the JSON form, so a baseline round-trips through
+//! either, but with numbers in binary and digests as byte strings rather than
+//! hex, which makes large baselines about a third smaller.
+
+use crate::{IntegrityError, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Media type of a baseline encoded as CBOR.
+pub const CBOR_CONTENT_TYPE: &str = "application/cbor";
+
+/// Encodes `value` as CBOR.
+pub fn to_cbor<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    ciborium::into_writer(value, &mut buf).map_err(|e| IntegrityError::Cbor(e.to_string()))?;
+    Ok(buf)
+}
+
+/// Decodes a CBOR-encoded `T`, rejecting trailing bytes.
+pub fn from_cbor<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    let mut reader = bytes;
+    let value = ciborium::from_reader(&mut reader).map_err(|e| IntegrityError::Cbor(e.to_string()))?;
+    if !reader.is_empty() {
+        return Err(IntegrityError::Cbor(format!("{} trailing bytes", reader.len())));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Baseline, FileIntegrityEntry, HashAlgorithm};
+
+    #[test]
+    fn test_cbor_round_trip() {
+        let entry = FileIntegrityEntry {
+            path: "usr/bin/ls".to_string(),
+            sha512: "ab".repeat(64),
+            mode: 0o755,
+            uid: 0,
+            gid: 0,
+            size: Some(142_144),
+            mtime: Some(1_700_000_000),
+            nlink: Some(1),
+            xattrs: Some([("security.selinux".to_string(), "62696e5f7400".to_string())].into()),
+            digests: [(HashAlgorithm::Blake3, "cd".repeat(32))].into(),
+            ..Default::default()
+        };
+        let baseline = Baseline {
+            image_id: "img".to_string(),
+            timestamp: "2023-01-01T00:00:00Z".to_string(),
+            entries: vec![entry],
+            sysctls: [("kernel.kptr_restrict".to_string(), "2".to_string())].into(),
+            kernel_cmdline: vec!["quiet".to_string()],
+            hash_algorithm: HashAlgorithm::Blake3,
+            ..Default::default()
+        };
+        let encoded = to_cbor(&baseline).unwrap();
+        assert_eq!(from_cbor::<Baseline>(&encoded).unwrap(), baseline);
+        #[cfg(feature = "json")]
+        assert!(encoded.len() < serde_json::to_vec(&baseline).unwrap().len());
+
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert!(from_cbor::<Baseline>(&trailing).is_err());
+        assert!(from_cbor::<Baseline>(&encoded[..encoded.len() - 1]).is_err());
+        assert!(from_cbor::<Baseline>(b"{}").is_err());
+    }
+}
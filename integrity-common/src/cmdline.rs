@@ -0,0 +1,63 @@
+//! Kernel command line parameters.
+//!
+//! `init=/bin/sh`, `lockdown=none`, `module.sig_enforce=0` or `selinux=0`
+//! weaken a host without touching any file the running system checks. The
+//! parameters of a golden image's boot are recorded in the baseline and
+//! compared against `/proc/cmdline`.
+
+/// Splits a command line into parameters. Double quotes group spaces into
+/// one parameter, as the kernel does (`dyndbg="file x.c +p"`).
+pub fn parse_cmdline(cmdline: &str) -> Vec<String> {
+    let mut parameters = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in cmdline.trim().chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    parameters.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        parameters.push(current);
+    }
+    parameters
+}
+
+/// Name of a parameter, e.g. "init" for "init=/bin/sh".
+pub fn parameter_name(parameter: &str) -> &str {
+    parameter.split_once('=').map_or(parameter, |(name, _)| name)
+}
+
+/// Parameters added and removed going from `recorded` to `current`, leaving
+/// out those whose name is in `ignored` (e.g. `BOOT_IMAGE`, which changes
+/// with every kernel update).
+pub fn cmdline_changes(recorded: &[String], current: &[String], ignored: &[String]) -> (Vec<String>, Vec<String>) {
+    let relevant = |parameter: &&String| !ignored.iter().any(|name| name == parameter_name(parameter));
+    let added = current.iter().filter(relevant).filter(|p| !recorded.contains(p)).cloned().collect();
+    let removed = recorded.iter().filter(relevant).filter(|p| !current.contains(p)).cloned().collect();
+    (added, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmdline_changes() {
+        let recorded = parse_cmdline("BOOT_IMAGE=/vmlinuz-6.1.0-17 root=UUID=abc ro quiet lockdown=integrity\n");
+        let current = parse_cmdline("BOOT_IMAGE=/vmlinuz-6.1.0-18 root=UUID=abc ro quiet init=/bin/sh dyndbg=\"file a.c +p\"");
+        assert_eq!(current.last().unwrap(), "dyndbg=\"file a.c +p\"");
+
+        let (added, removed) = cmdline_changes(&recorded, &current, &["BOOT_IMAGE".to_string()]);
+        assert_eq!(added, vec!["init=/bin/sh", "dyndbg=\"file a.c +p\""]);
+        assert_eq!(removed, vec!["lockdown=integrity"]);
+    }
+}
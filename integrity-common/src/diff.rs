@@ -0,0 +1,82 @@
+use crate::{Baseline, FileIntegrityEntry};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// An entry present in both baselines whose content or metadata differs.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ModifiedEntry {
+    pub old: FileIntegrityEntry,
+    pub new: FileIntegrityEntry,
+}
+
+/// Differences between two baselines, each list sorted by path.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct BaselineDiff {
+    pub added: Vec<FileIntegrityEntry>,
+    pub removed: Vec<FileIntegrityEntry>,
+    pub modified: Vec<ModifiedEntry>,
+}
+
+impl BaselineDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl Baseline {
+    /// Computes what changed going from `self` to `newer`.
+    pub fn diff(&self, newer: &Baseline) -> BaselineDiff {
+        let old: HashMap<&str, &FileIntegrityEntry> =
+            self.entries.iter().map(|e| (e.path.as_str(), e)).collect();
+        let new: HashMap<&str, &FileIntegrityEntry> =
+            newer.entries.iter().map(|e| (e.path.as_str(), e)).collect();
+
+        let mut diff = BaselineDiff::default();
+        for (path, new_entry) in &new {
+            match old.get(path) {
+                Some(old_entry) if !old_entry.matches(new_entry) => diff.modified.push(ModifiedEntry {
+                    old: (*old_entry).clone(),
+                    new: (*new_entry).clone(),
+                }),
+                Some(_) => {}
+                None => diff.added.push((*new_entry).clone()),
+            }
+        }
+        for (path, old_entry) in &old {
+            if !new.contains_key(path) {
+                diff.removed.push((*old_entry).clone());
+            }
+        }
+
+        diff.added.sort_by(|a, b| a.path.cmp(&b.path));
+        diff.removed.sort_by(|a, b| a.path.cmp(&b.path));
+        diff.modified.sort_by(|a, b| a.new.path.cmp(&b.new.path));
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::BaselineBuilder;
+
+    #[test]
+    fn test_diff() {
+        let builder = BaselineBuilder::new("img").size(10);
+        let v1 = builder.clone().file_with_digest("a", 0o644, "1").file_with_digest("b", 0o644, "1").file_with_digest("c", 0o644, "1").build();
+        let v2 = builder.timestamp("t2").file_with_digest("a", 0o644, "1").file_with_digest("b", 0o644, "2").file_with_digest("d", 0o644, "1").build();
+        let diff = v1.diff(&v2);
+        assert_eq!(diff.added, v2.entries[12..]);
+        assert_eq!(diff.removed, v1.entries[12..]);
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(diff.modified[0].new.path, "b");
+        assert!(v1.diff(&v1).is_empty());
+
+        // Stat metadata only counts when both versions recorded it
+        let mut v3 = v1.clone();
+        v3.entries[0].size = Some(10);
+        assert!(v1.diff(&v3).is_empty());
+        let mut v4 = v3.clone();
+        v4.entries[0].size = Some(0);
+        assert_eq!(v3.diff(&v4).modified.len(), 1);
+    }
+}
@@ -0,0 +1,35 @@
+//! NATS subjects the metadata service publishes its events on.
+//!
+//! Events go to `<prefix>.<kind>.<image_id>`, where kind is `baselines` for
+//! baselines stored or deleted and `reports` for anomalies that open, so
+//! subscribers filter by image with subject wildcards alone.
+
+/// Kind token of events about stored and deleted baselines.
+pub const BASELINE_EVENTS: &str = "baselines";
+/// Kind token of events about reported anomalies.
+pub const REPORT_EVENTS: &str = "reports";
+
+/// The image id as one subject token, with the characters NATS reserves in
+/// tokens (`.`, `*`, `>` and whitespace) replaced by `_`.
+pub fn subject_token(image_id: &str) -> String {
+    image_id
+        .chars()
+        .map(|c| if c == '.' || c == '*' || c == '>' || c.is_whitespace() { '_' } else { c })
+        .collect()
+}
+
+/// `<prefix>.<kind>.<image_id>`
+pub fn event_subject(prefix: &str, kind: &str, image_id: &str) -> String {
+    format!("{}.{}.{}", prefix, kind, subject_token(image_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_subject() {
+        assert_eq!(event_subject("integrity", BASELINE_EVENTS, "nginx-app-v7"), "integrity.baselines.nginx-app-v7");
+        assert_eq!(event_subject("integrity", REPORT_EVENTS, "ubuntu-22.04 *>"), "integrity.reports.ubuntu-22_04___");
+    }
+}
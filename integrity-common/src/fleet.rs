@@ -0,0 +1,137 @@
+//! Cross-host analysis of the anomalies reported by hosts of one image.
+//!
+//! Hosts built from the same golden image should deviate from it in the same
+//! ways: a change configuration management rolled out shows up on most of
+//! them, while a compromise usually shows up on one. Each deviation (kind and
+//! path) is classified by the share of the fleet reporting it, and each host
+//! gets an outlier score: the sum, over its deviations, of how rare each is,
+//! from 0 for a deviation every host has to 1 for one only it has.
+
+use crate::AnomalyRecord;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Deviations reported by at least this share of the fleet count as fleet-wide by default.
+pub const DEFAULT_FLEET_WIDE_SHARE: f64 = 0.8;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviationScope {
+    /// On most hosts: likely configuration management
+    FleetWide,
+    /// On several hosts, but not most
+    Shared,
+    /// On one host of several: worth a look
+    Unique,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Deviation {
+    pub kind: String,
+    pub path: String,
+    /// Hosts reporting it, sorted
+    pub hosts: Vec<String>,
+    /// Share of the fleet reporting it
+    pub share: f64,
+    pub scope: DeviationScope,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HostOutlier {
+    pub host: String,
+    pub deviations: usize,
+    /// Deviations no other host has
+    pub unique: usize,
+    pub outlier_score: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FleetAnalysis {
+    pub image_id: String,
+    pub fleet_size: usize,
+    /// Most outlying host first
+    pub hosts: Vec<HostOutlier>,
+    /// Rarest deviation first
+    pub deviations: Vec<Deviation>,
+}
+
+/// Analyzes the anomaly records of `image_id` reported by the hosts of `fleet`.
+pub fn analyze_fleet(image_id: &str, fleet: &BTreeSet<String>, records: &[AnomalyRecord], fleet_wide_share: f64) -> FleetAnalysis {
+    let records: Vec<&AnomalyRecord> = records.iter().filter(|r| r.image_id == image_id && fleet.contains(&r.host)).collect();
+    let mut by_deviation: BTreeMap<(&str, &str), BTreeSet<&str>> = BTreeMap::new();
+    for record in &records {
+        by_deviation.entry((&record.kind, &record.path)).or_default().insert(&record.host);
+    }
+
+    let size = fleet.len();
+    // 1 for a deviation on one host, 0 for one on every host
+    let rarity = |hosts: usize| if size <= 1 { 0.0 } else { (size - hosts) as f64 / (size - 1) as f64 };
+    let mut deviations: Vec<Deviation> = by_deviation
+        .into_iter()
+        .map(|((kind, path), hosts)| {
+            let share = hosts.len() as f64 / size as f64;
+            let scope = if share >= fleet_wide_share {
+                DeviationScope::FleetWide
+            } else if hosts.len() == 1 {
+                DeviationScope::Unique
+            } else {
+                DeviationScope::Shared
+            };
+            Deviation { kind: kind.to_string(), path: path.to_string(), hosts: hosts.into_iter().map(str::to_string).collect(), share, scope }
+        })
+        .collect();
+
+    let mut hosts: Vec<HostOutlier> = fleet
+        .iter()
+        .map(|host| {
+            let own: Vec<&Deviation> = deviations.iter().filter(|d| d.hosts.iter().any(|h| h == host)).collect();
+            HostOutlier {
+                host: host.to_string(),
+                deviations: own.len(),
+                unique: own.iter().filter(|d| d.hosts.len() == 1).count(),
+                outlier_score: own.iter().map(|d| rarity(d.hosts.len())).sum(),
+            }
+        })
+        .collect();
+
+    hosts.sort_by(|a, b| b.outlier_score.total_cmp(&a.outlier_score).then_with(|| a.host.cmp(&b.host)));
+    deviations.sort_by(|a, b| a.hosts.len().cmp(&b.hosts.len()).then_with(|| (&a.path, &a.kind).cmp(&(&b.path, &b.kind))));
+    FleetAnalysis { image_id: image_id.to_string(), fleet_size: size, hosts, deviations }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReportedAnomaly;
+
+    #[test]
+    fn test_analyze_fleet() {
+        let record = |host: &str, image_id: &str, path: &str| {
+            let anomaly = ReportedAnomaly { time: 0, kind: "MODIFIED".to_string(), path: path.to_string(), detail: String::new() };
+            AnomalyRecord::new(format!("{}:{}", host, path), host, image_id, &anomaly)
+        };
+        let mut records: Vec<AnomalyRecord> =
+            ["web-1", "web-2", "web-3", "web-4", "web-5"].iter().map(|host| record(host, "app-v1", "etc/resolv.conf")).collect();
+        records.push(record("web-1", "app-v1", "etc/nginx/nginx.conf"));
+        records.push(record("web-2", "app-v1", "etc/nginx/nginx.conf"));
+        records.push(record("web-4", "app-v1", "usr/bin/ls"));
+        records.push(record("db-1", "db-v1", "usr/bin/ls"));
+
+        let fleet: BTreeSet<String> = ["web-1", "web-2", "web-3", "web-4", "web-5"].iter().map(|h| h.to_string()).collect();
+        let analysis = analyze_fleet("app-v1", &fleet, &records, DEFAULT_FLEET_WIDE_SHARE);
+        assert_eq!(analysis.fleet_size, 5);
+        let scopes: Vec<(&str, DeviationScope)> = analysis.deviations.iter().map(|d| (d.path.as_str(), d.scope)).collect();
+        assert_eq!(
+            scopes,
+            vec![
+                ("usr/bin/ls", DeviationScope::Unique),
+                ("etc/nginx/nginx.conf", DeviationScope::Shared),
+                ("etc/resolv.conf", DeviationScope::FleetWide)
+            ]
+        );
+        assert_eq!(analysis.hosts[0], HostOutlier { host: "web-4".to_string(), deviations: 2, unique: 1, outlier_score: 1.0 });
+        assert_eq!(analysis.hosts[1].host, "web-1");
+        assert_eq!(analysis.hosts[1].outlier_score, 0.75);
+        assert_eq!(analysis.hosts[4].outlier_score, 0.0);
+    }
+}
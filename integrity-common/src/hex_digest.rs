@@ -0,0 +1,149 @@
+//! Hex digests as raw bytes in binary formats.
+//!
+//! Digests are hex strings in the data model and in JSON, but a binary format
+//! such as CBOR can carry them as byte strings at half the size. Only strings
+//! that decode back to exactly themselves (lowercase hex) are converted, so
+//! anything else round-trips unchanged as text.
+
+use crate::HashAlgorithm;
+use serde::de::{self, Deserializer, MapAccess, Visitor};
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+fn encode(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        hex.push(HEX[(b >> 4) as usize] as char);
+        hex.push(HEX[(b & 0xf) as usize] as char);
+    }
+    hex
+}
+
+/// Decodes `digest` into `buf`, which holds the longest digest there is.
+fn decode<'a>(digest: &str, buf: &'a mut [u8; 64]) -> Option<&'a [u8]> {
+    let nibble = |c: u8| match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        _ => None,
+    };
+    if !digest.len().is_multiple_of(2) || digest.len() > 2 * buf.len() {
+        return None;
+    }
+    for (byte, pair) in buf.iter_mut().zip(digest.as_bytes().chunks(2)) {
+        *byte = nibble(pair[0])? << 4 | nibble(pair[1])?;
+    }
+    Some(&buf[..digest.len() / 2])
+}
+
+/// A digest as bytes where the format is not human readable.
+struct Digest<'a>(&'a str);
+
+impl Serialize for Digest<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            return serializer.serialize_str(self.0);
+        }
+        match decode(self.0, &mut [0; 64]) {
+            Some(bytes) => serializer.serialize_bytes(bytes),
+            None => serializer.serialize_str(self.0),
+        }
+    }
+}
+
+struct DigestVisitor;
+
+impl Visitor<'_> for DigestVisitor {
+    type Value = String;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a hex string or bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<String, E> {
+        Ok(value.to_string())
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<String, E> {
+        Ok(value)
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<String, E> {
+        Ok(encode(value))
+    }
+}
+
+pub fn serialize<S: Serializer>(digest: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    Digest(digest).serialize(serializer)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    deserializer.deserialize_any(DigestVisitor)
+}
+
+/// The same for digests keyed by algorithm.
+pub mod by_algorithm {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(digests: &BTreeMap<HashAlgorithm, String>, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(digests.len()))?;
+        for (algorithm, digest) in digests {
+            map.serialize_entry(algorithm, &Digest(digest))?;
+        }
+        map.end()
+    }
+
+    struct MapVisitor;
+
+    impl<'de> Visitor<'de> for MapVisitor {
+        type Value = BTreeMap<HashAlgorithm, String>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a map of digests by algorithm")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+            let mut digests = BTreeMap::new();
+            while let Some(algorithm) = access.next_key()? {
+                digests.insert(algorithm, access.next_value_seed(DigestSeed)?);
+            }
+            Ok(digests)
+        }
+    }
+
+    struct DigestSeed;
+
+    impl<'de> de::DeserializeSeed<'de> for DigestSeed {
+        type Value = String;
+
+        fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<String, D::Error> {
+            super::deserialize(deserializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<HashAlgorithm, String>, D::Error> {
+        deserializer.deserialize_map(MapVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        let mut buf = [0; 64];
+        assert_eq!(decode("00ff7a", &mut buf), Some(&[0x00, 0xff, 0x7a][..]));
+        assert_eq!(encode(&[0x00, 0xff, 0x7a]), "00ff7a");
+        assert_eq!(decode("", &mut buf), Some(&[][..]));
+        assert_eq!(decode(&"ab".repeat(64), &mut buf).map(<[u8]>::len), Some(64));
+        // Only strings that encode back to themselves, and fit
+        assert_eq!(decode("00FF", &mut buf), None);
+        assert_eq!(decode("abc", &mut buf), None);
+        assert_eq!(decode("zz", &mut buf), None);
+        assert_eq!(decode(&"ab".repeat(65), &mut buf), None);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::{Baseline, FileIntegrityEntry, HashAlgorithm};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// Normalizes a path for index lookups.
+/// Baseline paths are stored relative to the scan root, so leading and trailing
+/// slashes are ignored ("/etc/passwd" and "etc/passwd" are the same key).
+pub(crate) fn normalize(path: &str) -> &str {
+    path.trim_matches('/')
+}
+
+/// A simple bloom filter over baseline paths.
+/// Used to reject paths that are definitely not baselined without touching the map.
+#[derive(Debug, Clone)]
+struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        let n = expected_items.max(1) as f64;
+        let p = false_positive_rate.clamp(1e-9, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = ((-n * p.ln()) / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().clamp(1.0, 16.0) as u32;
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+        }
+    }
+
+    /// Double hashing: h_i = h1 + i * h2
+    fn hashes(&self, item: &str) -> impl Iterator<Item = u64> + '_ {
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        let h1 = hasher.finish();
+        0xa5a5_a5a5_u32.hash(&mut hasher);
+        let h2 = hasher.finish() | 1;
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+    }
+
+    fn insert(&mut self, item: &str) {
+        let positions: Vec<u64> = self.hashes(item).collect();
+        for bit in positions {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    fn might_contain(&self, item: &str) -> bool {
+        self.hashes(item)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+}
+
+/// A node in the directory prefix trie.
+#[derive(Debug, Clone, Default)]
+struct DirNode {
+    children: HashMap<String, DirNode>,
+    /// Number of baselined files at or below this directory
+    file_count: usize,
+}
+
+/// Indexed view of a baseline for fast lookups.
+/// Built once from a `Baseline`, it provides O(1) path lookups and answers
+/// "is anything under this directory baselined?" without scanning all entries.
+#[derive(Debug, Clone)]
+pub struct BaselineIndex {
+    entries: HashMap<String, FileIntegrityEntry>,
+    bloom: Option<BloomFilter>,
+    root: DirNode,
+    hash_algorithm: HashAlgorithm,
+}
+
+impl BaselineIndex {
+    /// Builds an index from the entries of a baseline.
+    pub fn new(baseline: &Baseline) -> Self {
+        Self::from_entries(baseline.entries.iter().cloned()).with_hash_algorithm(baseline.hash_algorithm)
+    }
+
+    /// Builds an index from an arbitrary set of entries.
+    pub fn from_entries<I: IntoIterator<Item = FileIntegrityEntry>>(entries: I) -> Self {
+        let mut index = Self {
+            entries: HashMap::new(),
+            bloom: None,
+            root: DirNode::default(),
+            hash_algorithm: HashAlgorithm::default(),
+        };
+        for entry in entries {
+            index.insert(entry);
+        }
+        index
+    }
+
+    /// Adds a bloom filter in front of the map with the given false positive rate.
+    pub fn with_bloom_filter(mut self, false_positive_rate: f64) -> Self {
+        let mut bloom = BloomFilter::new(self.entries.len(), false_positive_rate);
+        for path in self.entries.keys() {
+            bloom.insert(path);
+        }
+        self.bloom = Some(bloom);
+        self
+    }
+
+    /// Sets the digest algorithm files are checked by.
+    pub fn with_hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    /// The digest algorithm files are checked by.
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+
+    fn insert(&mut self, entry: FileIntegrityEntry) {
+        let key = normalize(&entry.path).to_string();
+        if let Some(bloom) = self.bloom.as_mut() {
+            bloom.insert(&key);
+        }
+        if self.entries.insert(key.clone(), entry).is_some() {
+            // Duplicate path, directory counts already include it
+            return;
+        }
+
+        let mut node = &mut self.root;
+        node.file_count += 1;
+        let mut components: Vec<&str> = key.split('/').collect();
+        components.pop(); // The file name itself is not a directory
+        for component in components {
+            node = node.children.entry(component.to_string()).or_default();
+            node.file_count += 1;
+        }
+    }
+
+    /// Looks up the baseline entry for a path.
+    pub fn get(&self, path: &str) -> Option<&FileIntegrityEntry> {
+        let key = normalize(path);
+        if let Some(bloom) = &self.bloom {
+            if !bloom.might_contain(key) {
+                return None;
+            }
+        }
+        self.entries.get(key)
+    }
+
+    /// Returns true if the path is present in the baseline.
+    pub fn contains(&self, path: &str) -> bool {
+        self.get(path).is_some()
+    }
+
+    fn dir_node(&self, dir: &str) -> Option<&DirNode> {
+        let key = normalize(dir);
+        if key.is_empty() {
+            return Some(&self.root);
+        }
+        let mut node = &self.root;
+        for component in key.split('/') {
+            node = node.children.get(component)?;
+        }
+        Some(node)
+    }
+
+    /// Returns true if at least one baselined file lives under `dir`.
+    pub fn has_entries_under(&self, dir: &str) -> bool {
+        self.count_under(dir) > 0
+    }
+
+    /// Number of baselined files under `dir` (recursively).
+    pub fn count_under(&self, dir: &str) -> usize {
+        self.dir_node(dir).map(|node| node.file_count).unwrap_or(0)
+    }
+
+    /// Iterates over all indexed entries (in no particular order).
+    pub fn entries(&self) -> impl Iterator<Item = &FileIntegrityEntry> {
+        self.entries.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl From<&Baseline> for BaselineIndex {
+    fn from(baseline: &Baseline) -> Self {
+        Self::new(baseline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::BaselineBuilder;
+
+    fn sample_index() -> BaselineIndex {
+        let baseline = BaselineBuilder::new("img")
+            .size(0)
+            .file("etc/passwd", 0o644)
+            .file("etc/ssh/sshd_config", 0o600)
+            .file("usr/bin/ls", 0o755)
+            .build();
+        BaselineIndex::from_entries(baseline.entries)
+    }
+
+    #[test]
+    fn test_lookup_ignores_leading_slash() {
+        let index = sample_index();
+        assert!(index.contains("/etc/passwd"));
+        assert!(index.contains("etc/passwd"));
+        assert!(!index.contains("/etc/shadow"));
+        assert_eq!(index.len(), 3);
+    }
+
+    #[test]
+    fn test_has_entries_under() {
+        let index = sample_index();
+        assert!(index.has_entries_under("/etc"));
+        assert!(index.has_entries_under("/etc/ssh/"));
+        assert!(index.has_entries_under("/"));
+        assert!(!index.has_entries_under("/opt"));
+        assert!(!index.has_entries_under("/etc/passwd"));
+        assert_eq!(index.count_under("/etc"), 2);
+        assert_eq!(index.count_under("usr"), 1);
+    }
+
+    #[test]
+    fn test_bloom_filter_has_no_false_negatives() {
+        let baseline = (0..1000).fold(BaselineBuilder::new("img").size(0), |builder, i| builder.file(&format!("data/file{}", i), 0o644));
+        let index = BaselineIndex::from_entries(baseline.build().entries).with_bloom_filter(0.01);
+        for i in 0..1000 {
+            assert!(index.contains(&format!("/data/file{}", i)));
+        }
+        assert!(!index.contains("/data/missing"));
+    }
+}
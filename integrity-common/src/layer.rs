@@ -0,0 +1,141 @@
+use crate::index::normalize;
+use crate::{Baseline, DerivedBaseline};
+use std::collections::HashSet;
+
+impl Baseline {
+    /// Stacks `upper` on top of `self`: a path defined in both takes the upper
+    /// layer's entry, so a file must match the topmost layer that defines it.
+    ///
+    /// The result is named `<lower>+<upper>` and carries the upper layer's timestamp
+    /// and digest algorithm, and its MAC policy state, account records, listeners, trust store and kernel
+    /// command line unless it has none.
+    /// Its sysctls replace the lower layer's.
+    pub fn overlay(&self, upper: &Baseline) -> Baseline {
+        let upper_paths: HashSet<&str> = upper.entries.iter().map(|e| normalize(&e.path)).collect();
+
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|e| !upper_paths.contains(normalize(&e.path)))
+            .cloned()
+            .collect();
+        entries.extend(upper.entries.iter().cloned());
+        let mut sysctls = self.sysctls.clone();
+        sysctls.extend(upper.sysctls.clone());
+
+        Baseline {
+            image_id: format!("{}+{}", self.image_id, upper.image_id),
+            timestamp: upper.timestamp.clone(),
+            entries,
+            mac_policy: upper.mac_policy.clone().or_else(|| self.mac_policy.clone()),
+            sysctls,
+            accounts: upper.accounts.clone().or_else(|| self.accounts.clone()),
+            listeners: if upper.listeners.is_empty() { self.listeners.clone() } else { upper.listeners.clone() },
+            trust_store: upper.trust_store.clone().or_else(|| self.trust_store.clone()),
+            kernel_cmdline: if upper.kernel_cmdline.is_empty() { self.kernel_cmdline.clone() } else { upper.kernel_cmdline.clone() },
+            hash_algorithm: upper.hash_algorithm,
+        }
+    }
+}
+
+impl DerivedBaseline {
+    /// Stores `baseline` as its difference from `parent`.
+    pub fn from_parent(parent: &Baseline, baseline: &Baseline) -> Self {
+        let diff = parent.diff(baseline);
+        Self {
+            image_id: baseline.image_id.clone(),
+            extends: parent.image_id.clone(),
+            extends_version: None,
+            timestamp: baseline.timestamp.clone(),
+            entries: diff.added.into_iter().chain(diff.modified.into_iter().map(|m| m.new)).collect(),
+            removed: diff.removed.into_iter().map(|e| e.path).collect(),
+            mac_policy: baseline.mac_policy.clone(),
+            sysctls: baseline.sysctls.clone(),
+            accounts: baseline.accounts.clone(),
+            listeners: baseline.listeners.clone(),
+            trust_store: baseline.trust_store.clone(),
+            kernel_cmdline: baseline.kernel_cmdline.clone(),
+            hash_algorithm: baseline.hash_algorithm,
+        }
+    }
+
+    /// Builds the effective baseline on top of the (materialized) parent.
+    pub fn materialize(&self, parent: &Baseline) -> Baseline {
+        let removed: HashSet<&str> = self.removed.iter().map(|p| normalize(p)).collect();
+        let delta = Baseline {
+            image_id: self.image_id.clone(),
+            timestamp: self.timestamp.clone(),
+            entries: self.entries.clone(),
+            mac_policy: self.mac_policy.clone(),
+            sysctls: self.sysctls.clone(),
+            accounts: self.accounts.clone(),
+            listeners: self.listeners.clone(),
+            trust_store: self.trust_store.clone(),
+            kernel_cmdline: self.kernel_cmdline.clone(),
+            hash_algorithm: self.hash_algorithm,
+        };
+        let mut baseline = parent.overlay(&delta);
+        baseline.entries.retain(|e| !removed.contains(normalize(&e.path)));
+        baseline.image_id = self.image_id.clone();
+        baseline
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileIntegrityEntry;
+
+    fn baseline(image_id: &str, entries: &[(&str, &str)]) -> Baseline {
+        Baseline {
+            image_id: image_id.to_string(),
+            timestamp: image_id.to_string(),
+            entries: entries
+                .iter()
+                .map(|(path, sha512)| FileIntegrityEntry {
+                    path: path.to_string(),
+                    sha512: sha512.to_string(),
+                    mode: 0o644,
+                    uid: 0,
+                    gid: 0,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_overlay_topmost_layer_wins() {
+        let os = baseline("os", &[("etc/passwd", "1"), ("etc/nginx/nginx.conf", "1"), ("usr/bin/ls", "1")]);
+        let site = baseline("site", &[("/etc/passwd", "2")]);
+        let app = baseline("app", &[("etc/nginx/nginx.conf", "3"), ("srv/app/main", "3")]);
+
+        let stacked = os.overlay(&site).overlay(&app);
+        assert_eq!(stacked.image_id, "os+site+app");
+        assert_eq!(stacked.timestamp, "app");
+        assert_eq!(stacked.entries.len(), 4);
+
+        let sha512 = |path: &str| stacked.entries.iter().find(|e| e.path == path).unwrap().sha512.clone();
+        assert_eq!(sha512("/etc/passwd"), "2");
+        assert_eq!(sha512("etc/nginx/nginx.conf"), "3");
+        assert_eq!(sha512("usr/bin/ls"), "1");
+        assert_eq!(sha512("srv/app/main"), "3");
+    }
+
+    #[test]
+    fn test_derived_baseline_round_trip() {
+        let base = baseline("base-v1", &[("etc/passwd", "1"), ("etc/motd", "1"), ("usr/bin/ls", "1")]);
+        let app = baseline("app-v1", &[("etc/passwd", "2"), ("usr/bin/ls", "1"), ("srv/app", "1")]);
+
+        let derived = DerivedBaseline::from_parent(&base, &app);
+        assert_eq!(derived.extends, "base-v1");
+        assert_eq!(derived.entries.len(), 2);
+        assert_eq!(derived.removed, vec!["etc/motd".to_string()]);
+
+        let materialized = derived.materialize(&base);
+        assert_eq!(materialized.image_id, "app-v1");
+        assert!(base.diff(&materialized).added.iter().any(|e| e.path == "srv/app"));
+        assert!(app.diff(&materialized).is_empty());
+    }
+}
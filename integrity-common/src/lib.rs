@@ -1,12 +1,100 @@
+//! Shared data model for the integrity system.
+//!
+//! The core types, `BaselineIndex` and validation have no optional dependencies.
+//! Cargo features add the rest:
+//! - `error`: `IntegrityError` and the `Result` alias
+//! - `json` (default): streaming reader/writer, canonical JSON and digests
+//! - `cbor`: baselines as CBOR, for storage and transfer where JSON is too slow or large
+//! - `signing`: creating and verifying baseline signature chains
+//! - `host`: reading the state recorded next to the files (account files, CA certificates,
+//!   MAC policy, sysctls, listening sockets) and extended attributes
+//! - `scan-config`: the shared exclusions and content policies of scans
+//! - `test-util`: fixtures and proptest strategies for test suites
+
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
 
+mod accounts;
+mod agent_config;
+mod agent_control;
+mod anomaly;
+#[cfg(feature = "json")]
+mod canonical;
+#[cfg(feature = "cbor")]
+mod cbor;
+mod cmdline;
+mod diff;
+mod events;
+mod fleet;
+mod hex_digest;
+mod index;
+mod layer;
+mod listeners;
+mod mac;
+mod registry;
+mod signing;
+#[cfg(feature = "scan-config")]
+mod scan_config;
+mod snapshot;
+#[cfg(feature = "json")]
+mod stream;
+mod sysctl;
+mod triage;
+mod truststore;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+mod validate;
+mod xattrs;
+
+pub use accounts::{is_account_file, AccountChange, AccountRecords, Group, Password, User};
+pub use agent_config::{
+    AgentConfigHistory, AgentConfigRollback, AgentConfigUpdate, AgentConfigVersion, AgentSettings, EffectiveAgentConfig, HOST_SCOPE,
+    IMAGE_SCOPE,
+};
+pub use agent_control::{AgentStatus, ControlRequest, ControlResponse, MaintenanceWindow, DEFAULT_CONTROL_SOCKET};
+#[cfg(feature = "json")]
+pub use canonical::to_canonical_json;
+#[cfg(feature = "cbor")]
+pub use cbor::{from_cbor, to_cbor, CBOR_CONTENT_TYPE};
+pub use anomaly::{Anomaly, AnomalyReport, Severity};
+pub use cmdline::{cmdline_changes, parameter_name, parse_cmdline};
+pub use diff::{BaselineDiff, ModifiedEntry};
+pub use events::{event_subject, subject_token, BASELINE_EVENTS, REPORT_EVENTS};
+pub use fleet::{analyze_fleet, Deviation, DeviationScope, FleetAnalysis, HostOutlier, DEFAULT_FLEET_WIDE_SHARE};
+pub use index::BaselineIndex;
+#[cfg(feature = "host")]
+pub use listeners::read_listeners;
+pub use listeners::{Listener, OpenListener};
+pub use mac::{AppArmorState, MacPolicy, MacPolicyChange, SelinuxState, APPARMOR_PROFILES, SELINUX_FS};
+pub use registry::{AgentHeartbeat, AgentRecord, AgentRegistration, ScanStatus};
+#[cfg(feature = "signing")]
+pub use signing::{check_chain, verify_chain, BaselineSigner};
+pub use signing::{countersignatures_start, BaselineSignature, SignerRole};
+#[cfg(feature = "scan-config")]
+pub use scan_config::{ContentPolicy, ScanConfig, DEFAULT_EXCLUDE};
+pub use snapshot::{ScanSnapshot, SnapshotInfo};
+#[cfg(feature = "json")]
+pub use stream::{read_baseline, read_entries, write_baseline, write_baseline_header, write_entries};
+#[cfg(feature = "json")]
+pub use stream::{BaselineDecoder, EntryReader, NDJSON_CONTENT_TYPE};
+#[cfg(feature = "host")]
+pub use sysctl::read_sysctls;
+pub use sysctl::{sysctl_changes, sysctl_path, SysctlChange, SECURITY_SYSCTLS};
+pub use triage::{AnomalyBatch, AnomalyRecord, ReportedAnomaly, Transition, TriageRequest, TriageState, SERVICE_ACTOR};
+pub use truststore::{is_trust_store_file, TrustStore, TrustStoreChange, TrustedCertificate, TRUST_STORE_PATHS};
+pub use validate::{Violation, ViolationKind, MAX_BASELINE_ENTRIES};
+#[cfg(feature = "host")]
+pub use xattrs::read_xattrs;
+pub use xattrs::{describe_xattr, xattr_changes, XattrChange};
+
 /// Represents a single file's integrity data.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct FileIntegrityEntry {
     /// Relative to root, e.g., "/etc/passwd"
     pub path: String,
-    /// Hex encoded SHA512 hash
+    /// Hex encoded SHA512 hash; empty for files recorded metadata-only
+    #[serde(with = "hex_digest")]
     pub sha512: String,
     /// Unix permissions (e.g., 0o644)
     pub mode: u32,
@@ -14,10 +102,168 @@ pub struct FileIntegrityEntry {
     pub uid: u32,
     /// Group ID
     pub gid: u32,
+    /// Size in bytes, so truncation shows without hashing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Modification time, in seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+    /// Number of hard links to the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nlink: Option<u64>,
+    /// What kind of file it is; None in baselines collected before it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry_type: Option<EntryType>,
+    /// Where a symlink points, as stored in the link. The `sha512` of a
+    /// symlink is of this target, not of the file it points to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symlink_target: Option<String>,
+    /// Extended attributes by name (SELinux label, capabilities, `user.*`, ...),
+    /// values hex encoded; None when they were not recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xattrs: Option<BTreeMap<String, String>>,
+    /// Hex encoded digests in algorithms other than SHA-512, by algorithm
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty", with = "hex_digest::by_algorithm")]
+    pub digests: BTreeMap<HashAlgorithm, String>,
+}
+
+/// A digest algorithm of file contents.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha512,
+    /// What package manager databases (dpkg, rpm) record
+    Sha256,
+    /// Several times faster than SHA-2 on large images
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [HashAlgorithm; 3] = [HashAlgorithm::Sha512, HashAlgorithm::Sha256, HashAlgorithm::Blake3];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// Length of a hex encoded digest.
+    pub fn hex_len(&self) -> usize {
+        match self {
+            HashAlgorithm::Sha512 => 128,
+            HashAlgorithm::Sha256 | HashAlgorithm::Blake3 => 64,
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == HashAlgorithm::default()
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(name: &str) -> std::result::Result<Self, String> {
+        HashAlgorithm::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("unknown hash algorithm {:?}, expected sha512, sha256 or blake3", name))
+    }
+}
+
+/// What kind of file an entry describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryType {
+    Regular,
+    Symlink,
+    Directory,
+}
+
+impl FileIntegrityEntry {
+    pub fn is_symlink(&self) -> bool {
+        self.entry_type == Some(EntryType::Symlink)
+    }
+
+    /// Whether the content was hashed, rather than recorded metadata-only.
+    pub fn is_hashed(&self) -> bool {
+        !self.sha512.is_empty() || !self.digests.is_empty()
+    }
+
+    /// The recorded digest in `algorithm`, if any.
+    pub fn digest(&self, algorithm: HashAlgorithm) -> Option<&str> {
+        match algorithm {
+            HashAlgorithm::Sha512 => Some(self.sha512.as_str()).filter(|sha512| !sha512.is_empty()),
+            _ => self.digests.get(&algorithm).map(String::as_str),
+        }
+    }
+
+    /// The digest in `preferred`, or else in the first algorithm recorded.
+    pub fn content_digest(&self, preferred: HashAlgorithm) -> Option<(HashAlgorithm, &str)> {
+        [preferred]
+            .into_iter()
+            .chain(HashAlgorithm::ALL)
+            .find_map(|algorithm| Some((algorithm, self.digest(algorithm)?)))
+    }
+
+    /// The recorded and current digests when `current` has different content,
+    /// compared in `preferred` or else an algorithm both record. None when
+    /// they have no algorithm in common, as then they cannot be compared.
+    pub fn content_change<'a>(&'a self, current: &'a FileIntegrityEntry, preferred: HashAlgorithm) -> Option<(&'a str, &'a str)> {
+        [preferred]
+            .into_iter()
+            .chain(HashAlgorithm::ALL)
+            .find_map(|algorithm| self.digest(algorithm).zip(current.digest(algorithm)))
+            .filter(|(recorded, actual)| recorded != actual)
+    }
+
+    /// Whether `other` describes the same file. Content is only compared when
+    /// both entries hashed it, and differs when they have no digest algorithm
+    /// in common; size, mtime, link count, type and extended attributes are
+    /// only compared when both record them, so baselines collected before they
+    /// were recorded still match.
+    pub fn matches(&self, other: &FileIntegrityEntry) -> bool {
+        fn same<T: PartialEq>(a: Option<T>, b: Option<T>) -> bool {
+            a.zip(b).is_none_or(|(a, b)| a == b)
+        }
+        self.path == other.path
+            && self.same_content(other)
+            && self.mode == other.mode
+            && self.uid == other.uid
+            && self.gid == other.gid
+            && same(self.size, other.size)
+            && same(self.mtime, other.mtime)
+            && same(self.nlink, other.nlink)
+            && same(self.entry_type, other.entry_type)
+            && same(self.symlink_target.as_ref(), other.symlink_target.as_ref())
+            && same(self.xattrs.as_ref(), other.xattrs.as_ref())
+    }
+
+    /// Whether `other` has the same content, as far as both recorded it: true
+    /// if either is metadata-only, false if they have no algorithm in common.
+    pub fn same_content(&self, other: &FileIntegrityEntry) -> bool {
+        if !self.is_hashed() || !other.is_hashed() {
+            return true;
+        }
+        let common: Vec<_> = HashAlgorithm::ALL
+            .into_iter()
+            .filter_map(|algorithm| self.digest(algorithm).zip(other.digest(algorithm)))
+            .collect();
+        !common.is_empty() && common.iter().all(|(a, b)| a == b)
+    }
 }
 
 /// Represents the full baseline for an image.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct Baseline {
     /// Unique identifier (e.g., "ubuntu-2204-hardened-v1")
     pub image_id: String,
@@ -25,24 +271,221 @@ pub struct Baseline {
     pub timestamp: String,
     /// List of file integrity entries
     pub entries: Vec<FileIntegrityEntry>,
+    /// MAC policy state recorded at collection time, if requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac_policy: Option<MacPolicy>,
+    /// Sysctl values recorded at collection time, by name, if requested
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sysctls: BTreeMap<String, String>,
+    /// Parsed account files, to describe what changed in them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accounts: Option<AccountRecords>,
+    /// Sockets expected to be listening, if recorded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub listeners: Vec<Listener>,
+    /// Certificates of the CA trust store, to describe what changed in it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trust_store: Option<TrustStore>,
+    /// Kernel command line parameters recorded at collection time, if requested
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kernel_cmdline: Vec<String>,
+    /// The digest scans compare files by; entries may record others as well
+    #[serde(default, skip_serializing_if = "HashAlgorithm::is_default")]
+    pub hash_algorithm: HashAlgorithm,
+}
+
+/// A baseline stored as a delta on the baseline it extends.
+///
+/// The effective baseline is the parent's (itself possibly derived) with
+/// `entries` added or replacing the parent's, and `removed` taken out.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DerivedBaseline {
+    pub image_id: String,
+    /// image_id of the parent baseline, e.g. "ubuntu-2204-hardened-v2"
+    pub extends: String,
+    /// Stored version of the parent this extends; its current baseline when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends_version: Option<u64>,
+    /// ISO8601 creation time
+    pub timestamp: String,
+    /// Entries added or changed relative to the parent
+    #[serde(default)]
+    pub entries: Vec<FileIntegrityEntry>,
+    /// Paths of the parent that are absent from this image
+    #[serde(default)]
+    pub removed: Vec<String>,
+    /// Replaces the parent's MAC policy state when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac_policy: Option<MacPolicy>,
+    /// Sysctls added to or replacing the parent's
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sysctls: BTreeMap<String, String>,
+    /// Replaces the parent's account records when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accounts: Option<AccountRecords>,
+    /// Replaces the parent's listeners when not empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub listeners: Vec<Listener>,
+    /// Replaces the parent's trust store when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trust_store: Option<TrustStore>,
+    /// Replaces the parent's kernel command line when not empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kernel_cmdline: Vec<String>,
+    /// Replaces the parent's digest algorithm
+    #[serde(default, skip_serializing_if = "HashAlgorithm::is_default")]
+    pub hash_algorithm: HashAlgorithm,
+}
+
+/// A baseline version uploaded ahead of time; the service serves the
+/// previous version of the image until `effective_from`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ScheduledBaseline {
+    pub image_id: String,
+    /// Unix time it takes effect
+    pub effective_from: i64,
+    /// The baseline's own creation time
+    pub timestamp: String,
+    pub entries: usize,
+    /// Digest of the staged baseline, which approvers sign
+    #[serde(default)]
+    pub digest: String,
+}
+
+/// One stored version of an image's baseline, numbered from 1 in the order
+/// the service stored them.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BaselineVersion {
+    pub image_id: String,
+    pub version: u64,
+    /// The baseline's own creation time
+    pub timestamp: String,
+    pub digest: String,
+    pub entries: usize,
+    /// Unix time the service stored it; unknown for a baseline stored before versions were kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stored_at: Option<i64>,
+}
+
+/// A stored baseline as `GET /baselines` lists it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BaselineSummary {
+    pub image_id: String,
+    /// The baseline's own creation time
+    pub timestamp: String,
+    pub entries: usize,
+    /// The image a derived baseline extends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+}
+
+/// A page of the stored baselines, by image_id.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BaselineList {
+    /// Baselines stored in all, before pagination
+    pub total: usize,
+    pub offset: usize,
+    pub baselines: Vec<BaselineSummary>,
+}
+
+/// A stored baseline without its entries, as `GET /baselines/{image_id}/summary`
+/// returns it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BaselineMetadata {
+    pub image_id: String,
+    /// The baseline's own creation time
+    pub timestamp: String,
+    pub entries: usize,
+    /// Sum of the sizes of the hashed files; entries without a size count as 0
+    pub total_bytes: u64,
+    /// Canonical digest of the baseline, as in the ETag of `GET /baselines/{image_id}`
+    pub digest: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    /// The image a derived baseline extends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+}
+
+/// The answer to `GET /baselines/{image_id}/entries?path=`: the entry for one
+/// path, if the baseline has one, with the digest of the baseline it came
+/// from so callers caching entries notice when the baseline changes.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EntryLookup {
+    pub image_id: String,
+    pub path: String,
+    /// Canonical digest of the baseline
+    pub digest: String,
+    pub hash_algorithm: HashAlgorithm,
+    /// None when the path is not in the baseline
+    pub entry: Option<FileIntegrityEntry>,
+}
+
+/// A fingerprint of every entry of a baseline, by path, for clients that only
+/// need to know which of their entries the stored baseline has differently,
+/// such as a collector uploading a delta.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EntryDigests {
+    pub image_id: String,
+    /// Canonical digest of the baseline
+    pub digest: String,
+    /// Path -> `FileIntegrityEntry::fingerprint` of its entry
+    pub entries: BTreeMap<String, String>,
+}
+
+/// Maps a cloud image (e.g. "aws:ami-0abc") to the image_id of its baseline.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ImageMapping {
+    pub image_id: String,
+}
+
+/// Identity of the cloud instance an agent runs on.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct InstanceIdentity {
+    /// Cloud provider, e.g. "aws"
+    pub provider: String,
+    pub instance_id: String,
+    /// Cloud image the instance booted from, e.g. an AMI ID
+    pub image: String,
+    pub region: Option<String>,
+    pub account_id: Option<String>,
+}
+
+impl InstanceIdentity {
+    /// Key used in the service's cloud image mapping table.
+    pub fn mapping_key(&self) -> String {
+        format!("{}:{}", self.provider, self.image)
+    }
 }
 
 /// Custom error types for the integrity system.
+#[cfg(feature = "error")]
 #[derive(Debug, thiserror::Error)]
 pub enum IntegrityError {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+    #[cfg(feature = "json")]
     #[error("Serialization error: {0}")]
     Serde(#[from] serde_json::Error),
+    #[cfg(feature = "cbor")]
+    #[error("CBOR error: {0}")]
+    Cbor(String),
     #[error("Walkdir error: {0}")]
     Walkdir(String),
     #[error("Baseline not found: {0}")]
     BaselineNotFound(String),
     #[error("Storage error: {0}")]
     Storage(String),
+    #[error("Validation error: {0}")]
+    Validation(String),
+    #[error("Signature error: {0}")]
+    Signature(String),
+    #[error("Configuration error: {0}")]
+    Config(String),
 }
 
 /// Result type alias for the integrity system.
+#[cfg(feature = "error")]
 pub type Result<T> = std::result::Result<T, IntegrityError>;
 
 impl fmt::Display for FileIntegrityEntry {
@@ -79,6 +522,7 @@ mod tests {
             mode: 0o644,
             uid: 0,
             gid: 0,
+            ..Default::default()
         };
         let display = format!("{}", entry);
         assert!(display.contains("/etc/passwd"));
@@ -98,6 +542,7 @@ mod tests {
                     mode: 0o644,
                     uid: 0,
                     gid: 0,
+                    ..Default::default()
                 },
                 FileIntegrityEntry {
                     path: "/etc/shadow".to_string(),
@@ -105,12 +550,46 @@ mod tests {
                     mode: 0o600,
                     uid: 0,
                     gid: 0,
+                    ..Default::default()
                 },
             ],
+            ..Default::default()
         };
         let display = format!("{}", baseline);
         assert!(display.contains("test-image"));
         assert!(display.contains("2023-01-01T00:00:00Z"));
         assert!(display.contains("2 files"));
     }
+
+    #[test]
+    fn test_multiple_digests() {
+        let entry = |sha512: &str, digests: &[(HashAlgorithm, &str)]| FileIntegrityEntry {
+            path: "bin/sh".to_string(),
+            sha512: sha512.to_string(),
+            mode: 0o755,
+            uid: 0,
+            gid: 0,
+            digests: digests.iter().map(|(algorithm, digest)| (*algorithm, digest.to_string())).collect(),
+            ..Default::default()
+        };
+        let both = entry("s512", &[(HashAlgorithm::Blake3, "b3")]);
+        let blake3 = entry("", &[(HashAlgorithm::Blake3, "b3")]);
+        assert!(blake3.is_hashed());
+        assert_eq!(both.content_digest(HashAlgorithm::Blake3), Some((HashAlgorithm::Blake3, "b3")));
+        assert_eq!(blake3.content_digest(HashAlgorithm::Sha512), Some((HashAlgorithm::Blake3, "b3")));
+        assert!(both.matches(&blake3));
+        assert_eq!(both.content_change(&blake3, HashAlgorithm::Sha512), None);
+
+        let changed = entry("", &[(HashAlgorithm::Blake3, "b4")]);
+        assert!(!both.matches(&changed));
+        assert_eq!(both.content_change(&changed, HashAlgorithm::Sha512), Some(("b3", "b4")));
+        // Nothing to compare the content by
+        assert!(!entry("s512", &[]).matches(&entry("", &[(HashAlgorithm::Sha256, "s256")])));
+        assert!(entry("", &[]).matches(&blake3));
+
+        assert_eq!("BLAKE3".parse::<HashAlgorithm>(), Ok(HashAlgorithm::Blake3));
+        assert!("md5".parse::<HashAlgorithm>().is_err());
+        #[cfg(feature = "json")]
+        assert!(serde_json::to_string(&both).unwrap().contains(r#""digests":{"blake3":"b3"}"#));
+    }
 }
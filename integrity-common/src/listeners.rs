@@ -0,0 +1,151 @@
+//! Listening sockets expected on an image.
+//!
+//! A backdoor listening on a port is visible long before its files are
+//! found, if they ever are. The sockets listening on a golden image under
+//! load, with the binary owning each, are recorded in the baseline; anything
+//! else listening on an instance is unexpected.
+
+use serde::{Deserialize, Serialize};
+
+/// A listening socket: TCP in LISTEN state, or bound unconnected UDP.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Listener {
+    /// "tcp", "tcp6", "udp" or "udp6"
+    pub protocol: String,
+    pub port: u16,
+    /// Executable of the owning process, relative to root; empty for
+    /// kernel-owned sockets
+    pub exe: String,
+}
+
+/// A listener found on the running host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenListener {
+    pub listener: Listener,
+    /// A process owning the socket, if any
+    pub pid: Option<u32>,
+}
+
+#[cfg(feature = "host")]
+mod host {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    const TCP_LISTEN: &str = "0A";
+    const UDP_UNCONNECTED: &str = "07";
+
+    /// (port, socket inode) of the listening sockets in a /proc/net/{tcp,udp}[6] table.
+    pub(super) fn parse_table(contents: &str, state: &str) -> Vec<(u16, u64)> {
+        contents
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                if fields.get(3) != Some(&state) {
+                    return None;
+                }
+                let port = u16::from_str_radix(fields.get(1)?.rsplit(':').next()?, 16).ok()?;
+                Some((port, fields.get(9)?.parse().ok()?))
+            })
+            .collect()
+    }
+
+    /// Start of the ephemeral port range: UDP sockets bound above it are
+    /// clients (resolvers, NTP queries), not services.
+    fn ephemeral_start(proc: &Path) -> u16 {
+        fs::read_to_string(proc.join("sys/net/ipv4/ip_local_port_range"))
+            .ok()
+            .and_then(|range| range.split_whitespace().next()?.parse().ok())
+            .unwrap_or(32768)
+    }
+
+    /// Owning pid of every socket inode, from /proc/<pid>/fd.
+    fn socket_owners(proc: &Path) -> HashMap<u64, u32> {
+        let mut owners = HashMap::new();
+        let Ok(entries) = fs::read_dir(proc) else {
+            return owners;
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let Ok(pid) = entry.file_name().to_string_lossy().parse::<u32>() else {
+                continue;
+            };
+            let Ok(fds) = fs::read_dir(entry.path().join("fd")) else {
+                continue;
+            };
+            for fd in fds.filter_map(|e| e.ok()) {
+                let Ok(target) = fs::read_link(fd.path()) else {
+                    continue;
+                };
+                let target = target.to_string_lossy();
+                if let Some(inode) = target.strip_prefix("socket:[").and_then(|t| t.strip_suffix(']')) {
+                    if let Ok(inode) = inode.parse() {
+                        owners.entry(inode).or_insert(pid);
+                    }
+                }
+            }
+        }
+        owners
+    }
+
+    fn exe(proc: &Path, pid: u32) -> String {
+        fs::read_link(proc.join(pid.to_string()).join("exe"))
+            .map(|exe| {
+                let exe = exe.to_string_lossy();
+                exe.strip_suffix(" (deleted)").unwrap_or(&exe).trim_start_matches('/').to_string()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Listening sockets of the host, read through `proc` (usually "/proc").
+    /// The tables of pid 1 are used, so they are the host's network
+    /// namespace's even from a pod sharing only the host's pid namespace.
+    /// Mapping sockets to processes needs root.
+    pub fn read_listeners(proc: &Path) -> io::Result<Vec<OpenListener>> {
+        let net = proc.join("1/net");
+        let ephemeral = ephemeral_start(proc);
+        let owners = socket_owners(proc);
+
+        let mut listeners = Vec::new();
+        for (protocol, state) in [("tcp", TCP_LISTEN), ("tcp6", TCP_LISTEN), ("udp", UDP_UNCONNECTED), ("udp6", UDP_UNCONNECTED)] {
+            let contents = match fs::read_to_string(net.join(protocol)) {
+                Ok(contents) => contents,
+                // No IPv6 on this kernel
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for (port, inode) in parse_table(&contents, state) {
+                if protocol.starts_with("udp") && port >= ephemeral {
+                    continue;
+                }
+                let pid = owners.get(&inode).copied();
+                let listener = Listener {
+                    protocol: protocol.to_string(),
+                    port,
+                    exe: pid.map(|pid| exe(proc, pid)).unwrap_or_default(),
+                };
+                listeners.push(OpenListener { listener, pid });
+            }
+        }
+        Ok(listeners)
+    }
+}
+
+#[cfg(feature = "host")]
+pub use host::read_listeners;
+
+#[cfg(all(test, feature = "host"))]
+mod tests {
+    use super::host::parse_table;
+
+    #[test]
+    fn test_parse_table() {
+        let tcp = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 21554 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:8AE2 0100007F:0016 01 00000000:00000000 00:00000000 00000000  1000        0 99812 1 0000000000000000 20 4 30 10 -1
+";
+        assert_eq!(parse_table(tcp, "0A"), vec![(22, 21554)]);
+    }
+}
@@ -0,0 +1,248 @@
+//! Mandatory access control (SELinux, AppArmor) policy state.
+//!
+//! Switching a host to permissive mode, flipping a boolean or putting a
+//! profile in complain mode changes no file the baseline covers, yet it is a
+//! common first step before tampering. The loaded state is read from the
+//! kernel's filesystems under `/sys` and recorded in the baseline alongside
+//! the files.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Where the kernel exposes SELinux state, relative to the filesystem root.
+pub const SELINUX_FS: &str = "sys/fs/selinux";
+/// AppArmor's list of loaded profiles, relative to the filesystem root.
+pub const APPARMOR_PROFILES: &str = "sys/kernel/security/apparmor/profiles";
+
+/// Loaded MAC policy state; a module absent from the host is `None`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct MacPolicy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selinux: Option<SelinuxState>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub apparmor: Option<AppArmorState>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SelinuxState {
+    pub enforcing: bool,
+    /// Hex encoded SHA512 of the loaded policy
+    pub policy_sha512: String,
+    /// Current value of every boolean, by name
+    #[serde(default)]
+    pub booleans: BTreeMap<String, bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AppArmorState {
+    /// Mode of every loaded profile ("enforce", "complain", ...), by profile name
+    pub profiles: BTreeMap<String, String>,
+}
+
+/// A difference between the recorded and the current policy state.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MacPolicyChange {
+    /// The file under /sys the state comes from, e.g. "sys/fs/selinux/enforce"
+    pub path: String,
+    pub detail: String,
+}
+
+fn change(path: impl Into<String>, detail: impl Into<String>) -> MacPolicyChange {
+    MacPolicyChange { path: path.into(), detail: detail.into() }
+}
+
+fn mode(enforcing: bool) -> &'static str {
+    if enforcing {
+        "enforcing"
+    } else {
+        "permissive"
+    }
+}
+
+impl MacPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.selinux.is_none() && self.apparmor.is_none()
+    }
+
+    /// What differs in `current` from this recorded state, weakening or not,
+    /// in a stable order.
+    pub fn changes(&self, current: &MacPolicy) -> Vec<MacPolicyChange> {
+        let mut changes = Vec::new();
+
+        match (&self.selinux, &current.selinux) {
+            (Some(_), None) => changes.push(change(SELINUX_FS, "SELinux no longer enabled")),
+            (None, Some(_)) => changes.push(change(SELINUX_FS, "SELinux enabled, not in baseline")),
+            (Some(expected), Some(actual)) => {
+                if expected.enforcing != actual.enforcing {
+                    changes.push(change(
+                        format!("{}/enforce", SELINUX_FS),
+                        format!("{} -> {}", mode(expected.enforcing), mode(actual.enforcing)),
+                    ));
+                }
+                if expected.policy_sha512 != actual.policy_sha512 {
+                    changes.push(change(
+                        format!("{}/policy", SELINUX_FS),
+                        format!("loaded policy changed: {} != {}", expected.policy_sha512, actual.policy_sha512),
+                    ));
+                }
+                let names: std::collections::BTreeSet<&String> =
+                    expected.booleans.keys().chain(actual.booleans.keys()).collect();
+                for name in names {
+                    let value = |v: Option<&bool>| match v {
+                        Some(true) => "on",
+                        Some(false) => "off",
+                        None => "undefined",
+                    };
+                    let (old, new) = (expected.booleans.get(name), actual.booleans.get(name));
+                    if old != new {
+                        changes.push(change(
+                            format!("{}/booleans/{}", SELINUX_FS, name),
+                            format!("{} -> {}", value(old), value(new)),
+                        ));
+                    }
+                }
+            }
+            (None, None) => {}
+        }
+
+        match (&self.apparmor, &current.apparmor) {
+            (Some(_), None) => changes.push(change(APPARMOR_PROFILES, "AppArmor no longer enabled")),
+            (None, Some(_)) => changes.push(change(APPARMOR_PROFILES, "AppArmor enabled, not in baseline")),
+            (Some(expected), Some(actual)) => {
+                for (profile, old) in &expected.profiles {
+                    match actual.profiles.get(profile) {
+                        Some(new) if new != old => changes.push(change(
+                            APPARMOR_PROFILES,
+                            format!("profile {}: {} -> {}", profile, old, new),
+                        )),
+                        Some(_) => {}
+                        None => changes.push(change(APPARMOR_PROFILES, format!("profile {} unloaded", profile))),
+                    }
+                }
+                for (profile, new) in &actual.profiles {
+                    if !expected.profiles.contains_key(profile) {
+                        changes.push(change(
+                            APPARMOR_PROFILES,
+                            format!("profile {} ({}) loaded, not in baseline", profile, new),
+                        ));
+                    }
+                }
+            }
+            (None, None) => {}
+        }
+
+        changes
+    }
+}
+
+#[cfg(feature = "host")]
+mod host {
+    use super::*;
+    use sha2::{Digest, Sha512};
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    /// Parses AppArmor's profile list, one "name (mode)" per line.
+    pub(super) fn parse_profiles(contents: &str) -> BTreeMap<String, String> {
+        contents
+            .lines()
+            .filter_map(|line| {
+                // Profile names may contain spaces, the mode is the last field
+                let (name, mode) = line.trim().rsplit_once(" (")?;
+                Some((name.to_string(), mode.strip_suffix(')')?.to_string()))
+            })
+            .collect()
+    }
+
+    fn read_selinux(dir: &Path) -> io::Result<SelinuxState> {
+        let enforcing = fs::read_to_string(dir.join("enforce"))?.trim() == "1";
+        let mut hasher = Sha512::new();
+        io::copy(&mut fs::File::open(dir.join("policy"))?, &mut hasher)?;
+
+        let mut booleans = BTreeMap::new();
+        if let Ok(entries) = fs::read_dir(dir.join("booleans")) {
+            for entry in entries {
+                let entry = entry?;
+                // "<current> <pending>"
+                let value = fs::read_to_string(entry.path())?;
+                booleans.insert(entry.file_name().to_string_lossy().to_string(), value.starts_with('1'));
+            }
+        }
+
+        Ok(SelinuxState {
+            enforcing,
+            policy_sha512: hex::encode(hasher.finalize()),
+            booleans,
+        })
+    }
+
+    impl MacPolicy {
+        /// Reads the loaded policy state of the host whose root is `root`
+        /// (usually "/"). Reading SELinux's policy needs root.
+        pub fn read(root: &Path) -> io::Result<MacPolicy> {
+            let selinux_fs = root.join(SELINUX_FS);
+            let selinux = if selinux_fs.join("enforce").exists() {
+                Some(read_selinux(&selinux_fs)?)
+            } else {
+                None
+            };
+            let apparmor = match fs::read_to_string(root.join(APPARMOR_PROFILES)) {
+                Ok(contents) => Some(AppArmorState { profiles: parse_profiles(&contents) }),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            };
+            Ok(MacPolicy { selinux, apparmor })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selinux(enforcing: bool, booleans: &[(&str, bool)]) -> SelinuxState {
+        SelinuxState {
+            enforcing,
+            policy_sha512: "abc".to_string(),
+            booleans: booleans.iter().map(|(n, v)| (n.to_string(), *v)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_changes() {
+        let recorded = MacPolicy {
+            selinux: Some(selinux(true, &[("deny_ptrace", true)])),
+            apparmor: Some(AppArmorState {
+                profiles: [("/usr/sbin/cupsd".to_string(), "enforce".to_string())].into(),
+            }),
+        };
+        assert!(recorded.changes(&recorded).is_empty());
+
+        let current = MacPolicy {
+            selinux: Some(selinux(false, &[("deny_ptrace", false)])),
+            apparmor: Some(AppArmorState {
+                profiles: [("/usr/sbin/cupsd".to_string(), "complain".to_string())].into(),
+            }),
+        };
+        assert_eq!(
+            recorded.changes(&current),
+            vec![
+                change("sys/fs/selinux/enforce", "enforcing -> permissive"),
+                change("sys/fs/selinux/booleans/deny_ptrace", "on -> off"),
+                change(APPARMOR_PROFILES, "profile /usr/sbin/cupsd: enforce -> complain"),
+            ]
+        );
+
+        let disabled = MacPolicy::default();
+        assert_eq!(recorded.changes(&disabled).len(), 2);
+    }
+
+    #[cfg(feature = "host")]
+    #[test]
+    fn test_parse_profiles() {
+        let profiles = host::parse_profiles("/usr/sbin/cupsd (enforce)\nsnap update-ns.lxd (complain)\n");
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles["snap update-ns.lxd"], "complain");
+    }
+}
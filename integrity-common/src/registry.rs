@@ -0,0 +1,80 @@
+//! Registration and heartbeats of agents with the metadata service.
+//!
+//! An agent registers when it starts and then sends a heartbeat every
+//! interval, carrying how its last scan ended, so the service can list which
+//! hosts run an agent and which stopped reporting.
+
+use serde::{Deserialize, Serialize};
+
+/// What an agent tells the service when it starts.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AgentRegistration {
+    pub host: String,
+    pub image_id: String,
+    pub agent_version: String,
+    /// Run mode, e.g. "Monitor"
+    #[serde(default)]
+    pub mode: String,
+}
+
+/// How an agent's last scan ended.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ScanStatus {
+    /// Unix time the scan finished
+    pub finished_at: i64,
+    /// Outcome of the run, as in the agent's run summary, e.g. "clean" or "anomalies_found"
+    pub outcome: String,
+    pub anomalies: usize,
+}
+
+/// What an agent sends the service every heartbeat interval.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AgentHeartbeat {
+    pub image_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_scan: Option<ScanStatus>,
+}
+
+/// A registered agent, as `GET /agents` lists it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AgentRecord {
+    pub host: String,
+    pub image_id: String,
+    pub agent_version: String,
+    #[serde(default)]
+    pub mode: String,
+    /// Unix time the agent last registered, i.e. started
+    pub registered_at: i64,
+    /// Unix time of the last registration or heartbeat
+    pub last_seen: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_scan: Option<ScanStatus>,
+    /// No heartbeat within the service's staleness limit; set when listing
+    #[serde(default)]
+    pub stale: bool,
+}
+
+impl AgentRecord {
+    /// The record of a registration at `now`, keeping the last scan of the
+    /// host's `previous` record.
+    pub fn register(registration: AgentRegistration, now: i64, previous: Option<AgentRecord>) -> Self {
+        Self {
+            host: registration.host,
+            image_id: registration.image_id,
+            agent_version: registration.agent_version,
+            mode: registration.mode,
+            registered_at: now,
+            last_seen: now,
+            last_scan: previous.and_then(|previous| previous.last_scan),
+            stale: false,
+        }
+    }
+
+    pub fn heartbeat(&mut self, heartbeat: AgentHeartbeat, now: i64) {
+        self.image_id = heartbeat.image_id;
+        self.last_seen = now;
+        if heartbeat.last_scan.is_some() {
+            self.last_scan = heartbeat.last_scan;
+        }
+    }
+}
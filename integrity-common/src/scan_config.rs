@@ -0,0 +1,195 @@
+//! Which paths scans record, and how.
+//!
+//! baseline-collector and integrity-agent load the same TOML file with
+//! `--scan-config`, so the baseline and the scans checked against it cover the
+//! same files:
+//!
+//! ```toml
+//! exclude = ["proc", "sys", "dev", "run", "tmp", "var/tmp", "var/log", "home/*/.cache"]
+//! include = ["var/log/audit/**"]
+//!
+//! [[policy]]
+//! paths = ["var/lib/mysql/**", "var/lib/postgresql/**"]
+//! content = "metadata-only"
+//! ```
+//!
+//! Patterns are globs on paths relative to the scanned root: `*` and `?`
+//! stay within a path component, `**` spans any number of them. A path is
+//! skipped when it or a directory above it matches `exclude`, unless the
+//! path itself matches `include`; an excluded directory is still walked when
+//! an include pattern starts with its path. Without the file, or without an
+//! `exclude` key, the pseudo and scratch filesystems in [`DEFAULT_EXCLUDE`]
+//! are skipped.
+//!
+//! The first `[[policy]]` whose `paths` match a file decides its `content`:
+//! `hash` (the default) or `metadata-only`, for files that legitimately
+//! change all the time. Metadata-only files are recorded without a digest,
+//! size or mtime, so only their type, mode, owner, link count and extended
+//! attributes are verified.
+
+use crate::{IntegrityError, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::Deserialize;
+use std::path::Path;
+
+/// Skipped unless a scan config says otherwise.
+pub const DEFAULT_EXCLUDE: &[&str] = &["proc", "sys", "dev", "run", "tmp", "var/tmp", "var/log"];
+
+/// What a scan records of a file's content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContentPolicy {
+    /// Hash it, and record its size and mtime
+    #[default]
+    Hash,
+    /// Record only its type, mode, owner, link count and extended attributes
+    MetadataOnly,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyRule {
+    paths: Vec<String>,
+    content: ContentPolicy,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScanConfigFile {
+    #[serde(default = "default_exclude")]
+    exclude: Vec<String>,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    policy: Vec<PolicyRule>,
+}
+
+fn default_exclude() -> Vec<String> {
+    DEFAULT_EXCLUDE.iter().map(|pattern| pattern.to_string()).collect()
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern.trim_matches('/'))
+            .literal_separator(true)
+            .build()
+            .map_err(|e| IntegrityError::Config(format!("invalid pattern {:?}: {}", pattern, e)))?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|e| IntegrityError::Config(e.to_string()))
+}
+
+/// The exclusions and content policies of a scan.
+#[derive(Debug, Clone)]
+pub struct ScanConfig {
+    exclude: GlobSet,
+    include: GlobSet,
+    /// What each include pattern starts with, up to its first wildcard
+    include_prefixes: Vec<String>,
+    policies: Vec<(GlobSet, ContentPolicy)>,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self::parse("").expect("the default exclusions are valid patterns")
+    }
+}
+
+impl ScanConfig {
+    pub fn parse(text: &str) -> Result<Self> {
+        let file: ScanConfigFile = toml::from_str(text).map_err(|e| IntegrityError::Config(e.to_string()))?;
+        let include_prefixes = file
+            .include
+            .iter()
+            .map(|pattern| {
+                let pattern = pattern.trim_matches('/');
+                pattern[..pattern.find(['*', '?', '[', '{', '\\']).unwrap_or(pattern.len())].to_string()
+            })
+            .collect();
+        let policies = file
+            .policy
+            .iter()
+            .map(|rule| Ok((glob_set(&rule.paths)?, rule.content)))
+            .collect::<Result<_>>()?;
+        Ok(Self { exclude: glob_set(&file.exclude)?, include: glob_set(&file.include)?, include_prefixes, policies })
+    }
+
+    /// Reads the scan config at `path`, or the default one without it.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|e| IntegrityError::Config(format!("{:?}: {}", path, e)))
+    }
+
+    /// Whether the file at `path`, relative to the scanned root, is left out.
+    pub fn is_excluded(&self, path: &str) -> bool {
+        if path.is_empty() || self.include.is_match(path) {
+            return false;
+        }
+        let ancestors = path.match_indices('/').map(|(end, _)| &path[..end]);
+        ancestors.chain([path]).any(|prefix| self.exclude.is_match(prefix))
+    }
+
+    /// Whether nothing below the directory at `path` is scanned.
+    pub fn prunes(&self, path: &str) -> bool {
+        let dir = format!("{}/", path);
+        self.is_excluded(path) && !self.include_prefixes.iter().any(|prefix| prefix.starts_with(&dir))
+    }
+
+    /// What is recorded of the content of the file at `path`.
+    pub fn content_policy(&self, path: &str) -> ContentPolicy {
+        self.policies
+            .iter()
+            .find(|(paths, _)| paths.is_match(path))
+            .map_or(ContentPolicy::Hash, |(_, content)| *content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_config() {
+        let default = ScanConfig::default();
+        assert!(default.prunes("proc"));
+        assert!(default.is_excluded("var/log/syslog"));
+        assert!(!default.is_excluded("var/logs"));
+        assert!(!default.is_excluded("etc/passwd"));
+        assert!(!default.prunes(""));
+
+        let config = ScanConfig::parse(
+            r#"
+            exclude = ["var/log", "home/*/.cache", "**/*.pyc"]
+            include = ["var/log/audit/**"]
+
+            [[policy]]
+            paths = ["var/lib/mysql/*.cnf"]
+            content = "hash"
+
+            [[policy]]
+            paths = ["var/lib/mysql/**"]
+            content = "metadata-only"
+            "#,
+        )
+        .unwrap();
+        // Only what the file excludes
+        assert!(!config.is_excluded("proc/1/status"));
+        assert!(config.is_excluded("var/log/syslog"));
+        assert!(!config.is_excluded("var/log/audit/audit.log"));
+        assert!(!config.prunes("var/log"));
+        assert!(config.prunes("home/alice/.cache"));
+        assert!(!config.is_excluded("home/alice/.bashrc"));
+        assert!(config.is_excluded("usr/lib/python3/x/y.pyc"));
+
+        assert_eq!(config.content_policy("var/lib/mysql/ibdata1"), ContentPolicy::MetadataOnly);
+        assert_eq!(config.content_policy("var/lib/mysql/my.cnf"), ContentPolicy::Hash);
+        assert_eq!(config.content_policy("etc/passwd"), ContentPolicy::Hash);
+
+        assert!(ScanConfig::parse("exclude = [\"a[\"]").is_err());
+        assert!(ScanConfig::parse("exlude = []").is_err());
+    }
+}
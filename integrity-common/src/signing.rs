@@ -0,0 +1,229 @@
+//! Chained signatures over a baseline's digest.
+//!
+//! A baseline collects signatures in order: the collector that produced it,
+//! then approvers (e.g. the security team) countersigning after review, then
+//! the metadata service, which countersigns with the time it accepted the
+//! chain. Each signature covers the baseline digest (`Baseline::digest`), the
+//! signer's role, key and claimed time, and the signature before it, so a
+//! chain cannot be reordered or spliced onto another baseline. Agents require
+//! any subset of roles, each from keys they trust for that role; one key
+//! cannot sign under two roles, so a collector cannot approve its own
+//! baseline.
+//!
+//! Keys are Ed25519: 32-byte private keys (raw or hex) and hex public keys.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Who a signature stands for, in the order they sign.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignerRole {
+    /// The collector that scanned the golden image
+    Collector,
+    /// A reviewer countersigning the collected baseline
+    Approver,
+    /// The metadata service, timestamping the chain it accepted
+    Service,
+}
+
+impl SignerRole {
+    pub fn name(self) -> &'static str {
+        match self {
+            SignerRole::Collector => "collector",
+            SignerRole::Approver => "approver",
+            SignerRole::Service => "service",
+        }
+    }
+}
+
+impl fmt::Display for SignerRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl FromStr for SignerRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "collector" => Ok(SignerRole::Collector),
+            "approver" => Ok(SignerRole::Approver),
+            "service" => Ok(SignerRole::Service),
+            other => Err(format!("unknown signer role {:?} (collector, approver or service)", other)),
+        }
+    }
+}
+
+/// One link of a baseline's signature chain.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BaselineSignature {
+    pub role: SignerRole,
+    /// Hex encoded Ed25519 public key of the signer
+    pub public_key: String,
+    /// Digest of the signed baseline
+    pub digest: String,
+    /// Unix time of signing, as claimed by the signer
+    pub signed_at: i64,
+    /// Hex encoded Ed25519 signature
+    pub signature: String,
+}
+
+/// Where the service countersignatures ending `chain` start; a new signature
+/// replaces them, and the service countersigns again after it.
+pub fn countersignatures_start(chain: &[BaselineSignature]) -> usize {
+    chain.iter().rposition(|s| s.role != SignerRole::Service).map_or(0, |i| i + 1)
+}
+
+#[cfg(feature = "signing")]
+pub use crypto::{check_chain, verify_chain, BaselineSigner};
+
+#[cfg(feature = "signing")]
+mod crypto {
+    use super::{BaselineSignature, SignerRole};
+    use crate::{IntegrityError, Result};
+    use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+    fn invalid(message: String) -> IntegrityError {
+        IntegrityError::Signature(message)
+    }
+
+    /// What a signature signs: everything in it but the signature, and the
+    /// signature before it.
+    fn message(role: SignerRole, public_key: &str, digest: &str, signed_at: i64, previous: Option<&BaselineSignature>) -> Vec<u8> {
+        format!(
+            "acropole-baseline-signature/1\n{}\n{}\n{}\n{}\n{}",
+            digest,
+            role,
+            public_key.to_ascii_lowercase(),
+            signed_at,
+            previous.map_or("", |p| p.signature.as_str()).to_ascii_lowercase()
+        )
+        .into_bytes()
+    }
+
+    fn verifying_key(public_key: &str) -> Result<VerifyingKey> {
+        let bytes = hex::decode(public_key).map_err(|e| invalid(format!("public key is not hex: {}", e)))?;
+        let bytes = <[u8; 32]>::try_from(bytes.as_slice()).map_err(|_| invalid("public key must be 32 bytes".to_string()))?;
+        VerifyingKey::from_bytes(&bytes).map_err(|e| invalid(format!("invalid public key: {}", e)))
+    }
+
+    /// An Ed25519 private key signing baselines.
+    pub struct BaselineSigner {
+        key: SigningKey,
+    }
+
+    impl BaselineSigner {
+        pub fn from_bytes(key: [u8; 32]) -> Self {
+            Self { key: SigningKey::from_bytes(&key) }
+        }
+
+        /// Reads a 32-byte private key, raw or hex encoded.
+        pub fn from_key_material(material: &[u8]) -> Result<Self> {
+            if let Ok(key) = <[u8; 32]>::try_from(material) {
+                return Ok(Self::from_bytes(key));
+            }
+            let text = std::str::from_utf8(material).unwrap_or_default().trim();
+            let decoded = hex::decode(text).map_err(|_| invalid("private key is neither 32 raw bytes nor hex".to_string()))?;
+            let key = <[u8; 32]>::try_from(decoded.as_slice())
+                .map_err(|_| invalid(format!("private key must be 32 bytes, got {}", decoded.len())))?;
+            Ok(Self::from_bytes(key))
+        }
+
+        pub fn public_key(&self) -> String {
+            hex::encode(self.key.verifying_key().as_bytes())
+        }
+
+        /// Signs `digest` as `role`, following the last signature of `chain`.
+        pub fn sign(&self, role: SignerRole, digest: &str, signed_at: i64, chain: &[BaselineSignature]) -> BaselineSignature {
+            let public_key = self.public_key();
+            let signature = self.key.sign(&message(role, &public_key, digest, signed_at, chain.last()));
+            BaselineSignature {
+                role,
+                public_key,
+                digest: digest.to_string(),
+                signed_at,
+                signature: hex::encode(signature.to_bytes()),
+            }
+        }
+    }
+
+    /// Checks that every signature of `chain` is valid, over `digest`, and
+    /// follows the one before it; that roles come in signing order; and that
+    /// no key signs under two roles.
+    pub fn verify_chain(chain: &[BaselineSignature], digest: &str) -> Result<()> {
+        for (i, link) in chain.iter().enumerate() {
+            let previous = i.checked_sub(1).map(|p| &chain[p]);
+            if !link.digest.eq_ignore_ascii_case(digest) {
+                return Err(invalid(format!("{} signature {} is over another baseline", link.role, i + 1)));
+            }
+            if let Some(p) = previous.filter(|p| p.role > link.role) {
+                return Err(invalid(format!("{} signature {} follows a {} signature", link.role, i + 1, p.role)));
+            }
+            if chain[..i].iter().any(|p| p.public_key.eq_ignore_ascii_case(&link.public_key) && p.role != link.role) {
+                return Err(invalid(format!("key {} signs as both {} and another role", link.public_key, link.role)));
+            }
+            let bytes = hex::decode(&link.signature).map_err(|e| invalid(format!("signature is not hex: {}", e)))?;
+            let bytes = <[u8; 64]>::try_from(bytes.as_slice()).map_err(|_| invalid("signature must be 64 bytes".to_string()))?;
+            verifying_key(&link.public_key)?
+                .verify(&message(link.role, &link.public_key, digest, link.signed_at, previous), &Signature::from_bytes(&bytes))
+                .map_err(|_| invalid(format!("{} signature {} by {} does not verify", link.role, i + 1, link.public_key)))?;
+        }
+        Ok(())
+    }
+
+    /// Checks `chain` and that it holds a signature for each of `required`
+    /// roles by one of the keys `trusted` for that role.
+    pub fn check_chain(
+        chain: &[BaselineSignature],
+        digest: &str,
+        required: &[SignerRole],
+        trusted: &[(SignerRole, String)],
+    ) -> Result<()> {
+        verify_chain(chain, digest)?;
+        for role in required {
+            let signed = chain.iter().any(|link| {
+                link.role == *role && trusted.iter().any(|(r, key)| r == role && key.eq_ignore_ascii_case(&link.public_key))
+            });
+            if !signed {
+                return Err(invalid(format!("no {} signature by a trusted key", role)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "signing"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_chain() {
+        let collector = BaselineSigner::from_bytes([1; 32]);
+        let approver = BaselineSigner::from_bytes([2; 32]);
+        let service = BaselineSigner::from_bytes([3; 32]);
+        let digest = "ab".repeat(64);
+
+        let mut chain = vec![collector.sign(SignerRole::Collector, &digest, 100, &[])];
+        chain.push(approver.sign(SignerRole::Approver, &digest, 200, &chain));
+        chain.push(service.sign(SignerRole::Service, &digest, 201, &chain));
+        assert!(verify_chain(&chain, &digest).is_ok());
+        assert!(verify_chain(&chain, &"cd".repeat(64)).is_err());
+        assert_eq!(countersignatures_start(&chain), 2);
+
+        let trusted = [(SignerRole::Collector, collector.public_key()), (SignerRole::Approver, approver.public_key())];
+        assert!(check_chain(&chain, &digest, &[SignerRole::Collector, SignerRole::Approver], &trusted).is_ok());
+        // The service key is not trusted for its role
+        assert!(check_chain(&chain, &digest, &[SignerRole::Service], &trusted).is_err());
+
+        // Dropping the approval breaks the link the service signed
+        let spliced = vec![chain[0].clone(), chain[2].clone()];
+        assert!(verify_chain(&spliced, &digest).is_err());
+        // A collector cannot approve its own baseline
+        let mut own = vec![chain[0].clone()];
+        own.push(collector.sign(SignerRole::Approver, &digest, 150, &own));
+        assert!(verify_chain(&own, &digest).is_err());
+    }
+}
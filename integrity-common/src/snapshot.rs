@@ -0,0 +1,79 @@
+//! Complete current state of a host uploaded after a scan.
+//!
+//! Unlike anomalies, a snapshot holds every file the scan saw, so the service
+//! can answer what a host looked like at the time and diff it against any
+//! stored baseline later, including ones collected after the scan.
+
+use crate::{Baseline, FileIntegrityEntry};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ScanSnapshot {
+    pub host: String,
+    /// Baseline the host was scanned against
+    pub image_id: String,
+    /// ISO8601 time of the scan
+    pub timestamp: String,
+    /// Every file the scan saw, sorted by path
+    pub entries: Vec<FileIntegrityEntry>,
+}
+
+/// A stored snapshot without its entries, as listed by the service.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SnapshotInfo {
+    /// Assigned by the service, increasing with every upload
+    pub id: u64,
+    pub host: String,
+    pub image_id: String,
+    pub timestamp: String,
+    /// Unix time the service stored it, which retention goes by
+    pub received_at: i64,
+    pub entries: usize,
+}
+
+impl ScanSnapshot {
+    pub fn info(&self, id: u64, received_at: i64) -> SnapshotInfo {
+        SnapshotInfo {
+            id,
+            host: self.host.clone(),
+            image_id: self.image_id.clone(),
+            timestamp: self.timestamp.clone(),
+            received_at,
+            entries: self.entries.len(),
+        }
+    }
+
+    /// The snapshot as a baseline named `host@timestamp`, to diff it like one.
+    pub fn to_baseline(&self) -> Baseline {
+        Baseline {
+            image_id: format!("{}@{}", self.host, self.timestamp),
+            timestamp: self.timestamp.clone(),
+            entries: self.entries.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_as_baseline() {
+        let entry = |path: &str, sha512: &str| FileIntegrityEntry { path: path.to_string(), sha512: sha512.to_string(), mode: 0o644, uid: 0, gid: 0, ..Default::default() };
+        let snapshot = ScanSnapshot {
+            host: "web-1".to_string(),
+            image_id: "app-v1".to_string(),
+            timestamp: "2023-11-14T22:13:20Z".to_string(),
+            entries: vec![entry("etc/hosts", "bbb"), entry("tmp/x", "ccc")],
+        };
+        assert_eq!(snapshot.info(7, 1_700_000_060).entries, 2);
+
+        let mut baseline = snapshot.to_baseline();
+        assert_eq!(baseline.image_id, "web-1@2023-11-14T22:13:20Z");
+        baseline.image_id = "app-v1".to_string();
+        baseline.entries = vec![entry("etc/hosts", "aaa")];
+        let diff = baseline.diff(&snapshot.to_baseline());
+        assert_eq!((diff.added.len(), diff.removed.len(), diff.modified.len()), (1, 0, 1));
+    }
+}
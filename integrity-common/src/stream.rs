@@ -0,0 +1,215 @@
+//! Baselines and their entries as JSON Lines.
+//!
+//! A streamed baseline is a header line holding everything but the entries,
+//! then one line per entry, so it can be written and read an entry at a time
+//! instead of as one JSON document.
+
+use crate::{Baseline, FileIntegrityEntry, IntegrityError, Result};
+use std::borrow::Borrow;
+use std::io::{BufRead, Write};
+
+/// Media type of a baseline streamed as JSON Lines.
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Writes entries as JSON Lines (one JSON object per line).
+/// Returns the number of entries written.
+pub fn write_entries<W, I, E>(mut writer: W, entries: I) -> Result<usize>
+where
+    W: Write,
+    I: IntoIterator<Item = E>,
+    E: Borrow<FileIntegrityEntry>,
+{
+    let mut count = 0;
+    for entry in entries {
+        serde_json::to_writer(&mut writer, entry.borrow())?;
+        writer.write_all(b"\n")?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Reads JSON Lines entries one at a time.
+/// Blank lines are skipped; the first malformed line yields an error.
+pub fn read_entries<R: BufRead>(reader: R) -> EntryReader<R> {
+    EntryReader {
+        reader,
+        line: String::new(),
+    }
+}
+
+/// Iterator over entries in a JSON Lines stream, see `read_entries`.
+pub struct EntryReader<R> {
+    reader: R,
+    line: String,
+}
+
+impl<R: BufRead> Iterator for EntryReader<R> {
+    type Item = Result<FileIntegrityEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => {
+                    let line = self.line.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    return Some(serde_json::from_str(line).map_err(Into::into));
+                }
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+/// Writes the header line of a streamed baseline: everything but its entries.
+pub fn write_baseline_header<W: Write>(mut writer: W, baseline: &Baseline) -> Result<()> {
+    let header = Baseline {
+        image_id: baseline.image_id.clone(),
+        timestamp: baseline.timestamp.clone(),
+        entries: Vec::new(),
+        mac_policy: baseline.mac_policy.clone(),
+        sysctls: baseline.sysctls.clone(),
+        accounts: baseline.accounts.clone(),
+        listeners: baseline.listeners.clone(),
+        trust_store: baseline.trust_store.clone(),
+        kernel_cmdline: baseline.kernel_cmdline.clone(),
+        hash_algorithm: baseline.hash_algorithm,
+    };
+    serde_json::to_writer(&mut writer, &header)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Writes a baseline as a header line followed by its entries.
+/// Returns the number of entries written.
+pub fn write_baseline<W: Write>(mut writer: W, baseline: &Baseline) -> Result<usize> {
+    write_baseline_header(&mut writer, baseline)?;
+    write_entries(writer, &baseline.entries)
+}
+
+/// Reads a baseline written by `write_baseline`.
+pub fn read_baseline<R: BufRead>(mut reader: R) -> Result<Baseline> {
+    let mut line = String::new();
+    while line.trim().is_empty() {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(IntegrityError::Validation("baseline stream has no header".to_string()));
+        }
+    }
+    let mut baseline: Baseline = serde_json::from_str(line.trim())?;
+    for entry in read_entries(reader) {
+        baseline.entries.push(entry?);
+    }
+    Ok(baseline)
+}
+
+/// Reads a baseline written by `write_baseline` from chunks as they arrive,
+/// holding no more of the stream than its longest line.
+pub struct BaselineDecoder {
+    max_line: usize,
+    max_entries: usize,
+    pending: Vec<u8>,
+    baseline: Option<Baseline>,
+}
+
+impl BaselineDecoder {
+    /// Rejects lines longer than `max_line` bytes and more than `max_entries` entries.
+    pub fn new(max_line: usize, max_entries: usize) -> Self {
+        Self { max_line, max_entries, pending: Vec::new(), baseline: None }
+    }
+
+    pub fn push(&mut self, mut chunk: &[u8]) -> Result<()> {
+        while let Some(end) = chunk.iter().position(|b| *b == b'\n') {
+            self.pending.extend_from_slice(&chunk[..end]);
+            let line = std::mem::take(&mut self.pending);
+            self.line(&line)?;
+            chunk = &chunk[end + 1..];
+        }
+        self.pending.extend_from_slice(chunk);
+        if self.pending.len() > self.max_line {
+            return Err(IntegrityError::Validation(format!("baseline stream line longer than {} bytes", self.max_line)));
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, line: &[u8]) -> Result<()> {
+        if line.len() > self.max_line {
+            return Err(IntegrityError::Validation(format!("baseline stream line longer than {} bytes", self.max_line)));
+        }
+        if line.trim_ascii().is_empty() {
+            return Ok(());
+        }
+        match &mut self.baseline {
+            None => self.baseline = Some(serde_json::from_slice(line)?),
+            Some(baseline) if baseline.entries.len() >= self.max_entries => {
+                return Err(IntegrityError::Validation(format!("baseline stream has more than {} entries", self.max_entries)));
+            }
+            Some(baseline) => baseline.entries.push(serde_json::from_slice(line)?),
+        }
+        Ok(())
+    }
+
+    /// The baseline, once the stream has ended.
+    pub fn finish(mut self) -> Result<Baseline> {
+        let rest = std::mem::take(&mut self.pending);
+        self.line(&rest)?;
+        self.baseline.ok_or_else(|| IntegrityError::Validation("baseline stream has no header".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::BaselineBuilder;
+
+    #[test]
+    fn test_round_trip() {
+        let entries = BaselineBuilder::new("img").size(2).build().entries;
+        let mut buf = Vec::new();
+        assert_eq!(write_entries(&mut buf, &entries).unwrap(), 2);
+        assert_eq!(buf.iter().filter(|&&b| b == b'\n').count(), 2);
+
+        let read: Vec<_> = read_entries(buf.as_slice()).collect::<Result<_>>().unwrap();
+        assert_eq!(read, entries);
+    }
+
+    #[test]
+    fn test_blank_lines_and_errors() {
+        let input = "\n{\"path\":\"a\",\"sha512\":\"x\",\"mode\":420,\"uid\":0,\"gid\":0}\n\nnot json\n";
+        let mut reader = read_entries(input.as_bytes());
+        assert_eq!(reader.next().unwrap().unwrap().path, "a");
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_baseline_stream() {
+        let baseline = Baseline {
+            sysctls: [("kernel.kptr_restrict".to_string(), "2".to_string())].into(),
+            ..BaselineBuilder::new("img").size(2).build()
+        };
+        let mut buf = Vec::new();
+        assert_eq!(write_baseline(&mut buf, &baseline).unwrap(), 2);
+        assert_eq!(buf.iter().filter(|&&b| b == b'\n').count(), 3);
+        assert_eq!(read_baseline(buf.as_slice()).unwrap(), baseline);
+
+        // However the stream is split into chunks
+        for size in [1, 7, buf.len()] {
+            let mut decoder = BaselineDecoder::new(1024, 10);
+            for chunk in buf.chunks(size) {
+                decoder.push(chunk).unwrap();
+            }
+            assert_eq!(decoder.finish().unwrap(), baseline);
+        }
+
+        let mut decoder = BaselineDecoder::new(1024, 1);
+        assert!(decoder.push(&buf).is_err());
+        assert!(BaselineDecoder::new(16, 10).push(&buf[..32]).is_err());
+        assert!(BaselineDecoder::new(1024, 10).finish().is_err());
+        assert!(read_baseline(&b"\n"[..]).is_err());
+    }
+}
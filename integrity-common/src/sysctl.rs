@@ -0,0 +1,131 @@
+//! Security-relevant kernel parameters (sysctls).
+//!
+//! Most hardening knobs can be reverted at runtime without touching a file
+//! the baseline covers: `kernel.kptr_restrict`, `fs.protected_*`,
+//! `kernel.yama.ptrace_scope`, redirects and forwarding. Their values are
+//! recorded in the baseline and compared against `/proc/sys`.
+
+use std::collections::BTreeMap;
+
+/// Sysctls recorded by default.
+pub const SECURITY_SYSCTLS: &[&str] = &[
+    "kernel.modules_disabled",
+    "kernel.kexec_load_disabled",
+    "kernel.kptr_restrict",
+    "kernel.dmesg_restrict",
+    "kernel.perf_event_paranoid",
+    "kernel.randomize_va_space",
+    "kernel.sysrq",
+    "kernel.unprivileged_bpf_disabled",
+    "kernel.yama.ptrace_scope",
+    "kernel.core_pattern",
+    "fs.protected_hardlinks",
+    "fs.protected_symlinks",
+    "fs.protected_fifos",
+    "fs.protected_regular",
+    "fs.suid_dumpable",
+    "dev.tty.ldisc_autoload",
+    "vm.mmap_min_addr",
+    "vm.unprivileged_userfaultfd",
+    "user.max_user_namespaces",
+    "net.core.bpf_jit_harden",
+    "net.ipv4.ip_forward",
+    "net.ipv4.tcp_syncookies",
+    "net.ipv4.icmp_echo_ignore_broadcasts",
+    "net.ipv4.conf.all.accept_redirects",
+    "net.ipv4.conf.all.secure_redirects",
+    "net.ipv4.conf.all.send_redirects",
+    "net.ipv4.conf.all.accept_source_route",
+    "net.ipv4.conf.all.rp_filter",
+    "net.ipv4.conf.all.log_martians",
+    "net.ipv6.conf.all.forwarding",
+    "net.ipv6.conf.all.accept_redirects",
+    "net.ipv6.conf.all.accept_source_route",
+];
+
+/// The file under /proc/sys holding a sysctl, relative to the filesystem root,
+/// e.g. "kernel.yama.ptrace_scope" -> "proc/sys/kernel/yama/ptrace_scope".
+pub fn sysctl_path(name: &str) -> String {
+    format!("proc/sys/{}", name.replace('.', "/"))
+}
+
+/// A sysctl whose value differs from the recorded one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SysctlChange {
+    pub name: String,
+    pub expected: String,
+    /// `None` when the sysctl no longer exists
+    pub actual: Option<String>,
+}
+
+/// Recorded sysctls whose value in `current` differs.
+pub fn sysctl_changes(recorded: &BTreeMap<String, String>, current: &BTreeMap<String, String>) -> Vec<SysctlChange> {
+    recorded
+        .iter()
+        .filter(|(name, value)| current.get(*name) != Some(*value))
+        .map(|(name, value)| SysctlChange {
+            name: name.clone(),
+            expected: value.clone(),
+            actual: current.get(name).cloned(),
+        })
+        .collect()
+}
+
+#[cfg(feature = "host")]
+mod host {
+    use super::*;
+    use std::path::Path;
+
+    /// Reads sysctls from `<root>/proc/sys`. Sysctls this kernel doesn't have are left out;
+    /// multi-value sysctls keep their values separated by single spaces.
+    pub fn read_sysctls<'a>(root: &Path, names: impl IntoIterator<Item = &'a str>) -> BTreeMap<String, String> {
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let value = std::fs::read_to_string(root.join(sysctl_path(name))).ok()?;
+                Some((name.to_string(), value.split_whitespace().collect::<Vec<_>>().join(" ")))
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "host")]
+pub use host::read_sysctls;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sysctl_changes() {
+        assert_eq!(sysctl_path("kernel.yama.ptrace_scope"), "proc/sys/kernel/yama/ptrace_scope");
+
+        let recorded: BTreeMap<String, String> = [
+            ("fs.protected_symlinks".to_string(), "1".to_string()),
+            ("kernel.kptr_restrict".to_string(), "2".to_string()),
+            ("kernel.modules_disabled".to_string(), "1".to_string()),
+        ]
+        .into();
+        let mut current = recorded.clone();
+        assert!(sysctl_changes(&recorded, &current).is_empty());
+
+        current.insert("kernel.kptr_restrict".to_string(), "0".to_string());
+        current.remove("kernel.modules_disabled");
+        current.insert("kernel.sysrq".to_string(), "1".to_string());
+        assert_eq!(
+            sysctl_changes(&recorded, &current),
+            vec![
+                SysctlChange {
+                    name: "kernel.kptr_restrict".to_string(),
+                    expected: "2".to_string(),
+                    actual: Some("0".to_string()),
+                },
+                SysctlChange {
+                    name: "kernel.modules_disabled".to_string(),
+                    expected: "1".to_string(),
+                    actual: None,
+                },
+            ]
+        );
+    }
+}
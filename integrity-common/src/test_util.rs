@@ -0,0 +1,261 @@
+//! Fixtures shared by the test suites of all components.
+//!
+//! Enabled with the `test-util` feature. `BaselineBuilder` produces realistic,
+//! deterministic baselines, `Drift` derives a "current" filesystem state from one,
+//! and the `Arbitrary` impls feed proptest with entries that always pass validation.
+
+use crate::{Baseline, FileIntegrityEntry};
+use proptest::prelude::*;
+
+const DIRECTORIES: &[&str] = &["bin", "sbin", "etc", "etc/ssh", "usr/bin", "usr/lib", "usr/share/doc", "opt/app"];
+const MODES: &[u32] = &[0o644, 0o644, 0o755, 0o755, 0o600, 0o640, 0o4755];
+
+/// Small deterministic PRNG (splitmix64) so fixtures are stable across runs.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[(self.next() % items.len() as u64) as usize]
+    }
+
+    fn digest(&mut self) -> String {
+        (0..8).map(|_| format!("{:016x}", self.next())).collect()
+    }
+}
+
+/// Builds fake baselines of a configurable size.
+#[derive(Debug, Clone)]
+pub struct BaselineBuilder {
+    image_id: String,
+    timestamp: String,
+    size: usize,
+    seed: u64,
+    extra: Vec<FileIntegrityEntry>,
+}
+
+impl BaselineBuilder {
+    pub fn new(image_id: &str) -> Self {
+        Self {
+            image_id: image_id.to_string(),
+            timestamp: "2024-01-01T00:00:00+00:00".to_string(),
+            size: 100,
+            seed: 0,
+            extra: Vec::new(),
+        }
+    }
+
+    /// Number of generated entries (explicit entries are added on top).
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: &str) -> Self {
+        self.timestamp = timestamp.to_string();
+        self
+    }
+
+    /// Adds an explicit entry with a generated digest and default ownership.
+    pub fn file(self, path: &str, mode: u32) -> Self {
+        let sha512 = Rng(self.seed ^ self.extra.len() as u64 ^ 0xfeed).digest();
+        self.file_with_digest(path, mode, &sha512)
+    }
+
+    /// Adds an explicit entry with the given digest, for entries that must
+    /// match or differ across baselines.
+    pub fn file_with_digest(mut self, path: &str, mode: u32, sha512: &str) -> Self {
+        self.extra.push(FileIntegrityEntry {
+            path: path.trim_start_matches('/').to_string(),
+            sha512: sha512.to_string(),
+            mode,
+            uid: 0,
+            gid: 0,
+            ..Default::default()
+        });
+        self
+    }
+
+    pub fn build(self) -> Baseline {
+        let mut rng = Rng(self.seed);
+        let mut entries: Vec<FileIntegrityEntry> = (0..self.size)
+            .map(|i| FileIntegrityEntry {
+                path: format!("{}/file{}", rng.pick(DIRECTORIES), i),
+                sha512: rng.digest(),
+                mode: *rng.pick(MODES),
+                uid: 0,
+                gid: 0,
+                ..Default::default()
+            })
+            .collect();
+        entries.extend(self.extra);
+        Baseline {
+            image_id: self.image_id,
+            timestamp: self.timestamp,
+            entries,
+            ..Default::default()
+        }
+    }
+}
+
+/// Describes how much drift to inject into a baseline.
+#[derive(Debug, Clone, Default)]
+pub struct Drift {
+    pub modified: usize,
+    pub added: usize,
+    pub deleted: usize,
+    pub permissions: usize,
+    pub ownership: usize,
+}
+
+impl Drift {
+    /// Returns a copy of the baseline entries with the configured drift applied.
+    /// Modified, deleted, and metadata-changed files are taken from the front of
+    /// the entry list in that order, so the same input always drifts the same way.
+    pub fn apply(&self, baseline: &Baseline) -> Vec<FileIntegrityEntry> {
+        let mut rng = Rng(0xd71f7);
+        let mut entries = baseline.entries.clone();
+        let mut cursor = 0;
+
+        for entry in entries.iter_mut().skip(cursor).take(self.modified) {
+            entry.sha512 = rng.digest();
+        }
+        cursor += self.modified;
+
+        let deleted_end = (cursor + self.deleted).min(entries.len());
+        entries.drain(cursor.min(entries.len())..deleted_end);
+
+        for entry in entries.iter_mut().skip(cursor).take(self.permissions) {
+            entry.mode ^= 0o002;
+        }
+        cursor += self.permissions;
+
+        for entry in entries.iter_mut().skip(cursor).take(self.ownership) {
+            entry.uid += 1000;
+        }
+
+        for i in 0..self.added {
+            entries.push(FileIntegrityEntry {
+                path: format!("tmp/dropped{}", i),
+                sha512: rng.digest(),
+                mode: 0o755,
+                uid: 0,
+                gid: 0,
+                ..Default::default()
+            });
+        }
+        entries
+    }
+
+    /// Total number of anomalies a scanner is expected to report for this drift.
+    pub fn expected_anomalies(&self) -> usize {
+        self.modified + self.added + self.deleted + self.permissions + self.ownership
+    }
+}
+
+prop_compose! {
+    fn arb_path()(components in prop::collection::vec("[a-z0-9_][a-z0-9_.-]{0,11}", 1..5)) -> String {
+        components.join("/")
+    }
+}
+
+impl Arbitrary for FileIntegrityEntry {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        (arb_path(), "[0-9a-f]{128}", 0u32..=0o7777, 0u32..65536, 0u32..65536)
+            .prop_map(|(path, sha512, mode, uid, gid)| FileIntegrityEntry {
+                path,
+                sha512,
+                mode,
+                uid,
+                gid,
+                ..Default::default()
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for Baseline {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        arb_baseline(64)
+    }
+}
+
+/// Strategy for valid baselines (unique paths) with up to `max_entries` entries.
+pub fn arb_baseline(max_entries: usize) -> BoxedStrategy<Baseline> {
+    (
+        "[a-z][a-z0-9-]{0,20}",
+        prop::collection::btree_map(arb_path(), any::<FileIntegrityEntry>(), 0..=max_entries),
+    )
+        .prop_map(|(image_id, entries)| Baseline {
+            image_id,
+            timestamp: "2024-01-01T00:00:00+00:00".to_string(),
+            entries: entries
+                .into_iter()
+                .map(|(path, entry)| FileIntegrityEntry { path, ..entry })
+                .collect(),
+            ..Default::default()
+        })
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_is_deterministic_and_valid() {
+        let a = BaselineBuilder::new("img").size(500).seed(7).file("/etc/passwd", 0o644).build();
+        let b = BaselineBuilder::new("img").size(500).seed(7).file("/etc/passwd", 0o644).build();
+        assert_eq!(a, b);
+        assert_eq!(a.entries.len(), 501);
+        assert!(a.validate().is_empty());
+    }
+
+    #[test]
+    fn test_drift() {
+        let baseline = BaselineBuilder::new("img").size(50).build();
+        let drift = Drift {
+            modified: 2,
+            added: 3,
+            deleted: 4,
+            permissions: 1,
+            ownership: 1,
+        };
+        let current = drift.apply(&baseline);
+        assert_eq!(current.len(), 50 - 4 + 3);
+        assert_eq!(drift.expected_anomalies(), 11);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_generated_baselines_are_valid(baseline in any::<Baseline>()) {
+            prop_assert!(baseline.validate().is_empty());
+        }
+
+        #[test]
+        fn prop_digest_ignores_entry_order(mut baseline in arb_baseline(16)) {
+            let digest = baseline.digest().unwrap();
+            baseline.entries.reverse();
+            prop_assert_eq!(digest, baseline.digest().unwrap());
+        }
+    }
+}
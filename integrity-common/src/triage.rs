@@ -0,0 +1,173 @@
+//! Anomalies reported to the metadata service, and their triage state.
+//!
+//! The service keeps one record per host, kind and path, so a deviation that
+//! shows up on every scan is one record with an occurrence count, not a new
+//! alert each time. Operators move a record between open, acknowledged,
+//! snoozed (until a time) and resolved. A recurrence leaves an acknowledged
+//! record, or one snoozed until later, alone; a resolved record, or one whose
+//! snooze has run out, is opened again.
+
+use serde::{Deserialize, Serialize};
+
+/// Actor of the transitions the service makes on its own.
+pub const SERVICE_ACTOR: &str = "metadata-service";
+
+/// One anomaly as reported by an agent.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReportedAnomaly {
+    /// Unix time it was detected
+    pub time: i64,
+    pub kind: String,
+    pub path: String,
+    #[serde(default)]
+    pub detail: String,
+}
+
+/// The anomalies one host found in a scan or since its last report.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnomalyBatch {
+    pub host: String,
+    pub image_id: String,
+    pub anomalies: Vec<ReportedAnomaly>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum TriageState {
+    Open,
+    Acknowledged,
+    /// Quiet until the Unix time `until`, then open again
+    Snoozed { until: i64 },
+    Resolved,
+}
+
+impl TriageState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TriageState::Open => "open",
+            TriageState::Acknowledged => "acknowledged",
+            TriageState::Snoozed { .. } => "snoozed",
+            TriageState::Resolved => "resolved",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Transition {
+    #[serde(flatten)]
+    pub state: TriageState,
+    pub actor: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// Unix time of the transition
+    pub at: i64,
+}
+
+/// A change of triage state requested by an operator.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TriageRequest {
+    #[serde(flatten)]
+    pub state: TriageState,
+    /// Who is making the change; taken from the login when the service authenticates
+    #[serde(default)]
+    pub actor: Option<String>,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AnomalyRecord {
+    pub id: String,
+    pub host: String,
+    pub image_id: String,
+    pub kind: String,
+    pub path: String,
+    /// Detail of the latest occurrence
+    pub detail: String,
+    pub first_seen: i64,
+    pub last_seen: i64,
+    pub occurrences: u64,
+    /// Transitions, oldest first; the last one is the current state
+    pub history: Vec<Transition>,
+}
+
+impl AnomalyRecord {
+    pub fn new(id: String, host: &str, image_id: &str, anomaly: &ReportedAnomaly) -> Self {
+        Self {
+            id,
+            host: host.to_string(),
+            image_id: image_id.to_string(),
+            kind: anomaly.kind.clone(),
+            path: anomaly.path.clone(),
+            detail: anomaly.detail.clone(),
+            first_seen: anomaly.time,
+            last_seen: anomaly.time,
+            occurrences: 1,
+            history: vec![Transition { state: TriageState::Open, actor: SERVICE_ACTOR.to_string(), comment: None, at: anomaly.time }],
+        }
+    }
+
+    pub fn transition(&self) -> &Transition {
+        self.history.last().expect("a record is created open")
+    }
+
+    /// The current state as of `now`: an expired snooze is open.
+    pub fn state(&self, now: i64) -> TriageState {
+        match self.transition().state {
+            TriageState::Snoozed { until } if until <= now => TriageState::Open,
+            ref state => state.clone(),
+        }
+    }
+
+    pub fn set_state(&mut self, state: TriageState, actor: &str, comment: Option<String>, now: i64) {
+        self.history.push(Transition { state, actor: actor.to_string(), comment, at: now });
+    }
+
+    /// Counts another occurrence. Returns whether it (re)opened the record,
+    /// which is when it is worth alerting on.
+    pub fn recur(&mut self, anomaly: &ReportedAnomaly, image_id: &str, now: i64) -> bool {
+        self.occurrences += 1;
+        self.last_seen = self.last_seen.max(anomaly.time);
+        self.detail = anomaly.detail.clone();
+        self.image_id = image_id.to_string();
+        let reason = match self.transition().state {
+            TriageState::Resolved => "recurred after it was resolved",
+            TriageState::Snoozed { until } if until <= now => "recurred after the snooze ended",
+            _ => return false,
+        };
+        self.set_state(TriageState::Open, SERVICE_ACTOR, Some(reason.to_string()), now);
+        true
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recurrence() {
+        let anomaly = |time| ReportedAnomaly { time, kind: "ADDED".to_string(), path: "tmp/x".to_string(), detail: String::new() };
+        let mut record = AnomalyRecord::new("id".to_string(), "web-1", "app-v1", &anomaly(100));
+        assert_eq!(record.state(100), TriageState::Open);
+        assert!(!record.recur(&anomaly(110), "app-v1", 110));
+
+        record.set_state(TriageState::Acknowledged, "alice", Some("known cron artifact".to_string()), 120);
+        assert!(!record.recur(&anomaly(130), "app-v1", 130));
+        assert_eq!(record.state(130), TriageState::Acknowledged);
+
+        record.set_state(TriageState::Snoozed { until: 200 }, "alice", None, 140);
+        assert!(!record.recur(&anomaly(150), "app-v1", 150));
+        assert_eq!(record.state(200), TriageState::Open);
+        assert!(record.recur(&anomaly(210), "app-v1", 210));
+
+        record.set_state(TriageState::Resolved, "bob", None, 220);
+        assert!(record.recur(&anomaly(230), "app-v1", 230));
+        assert_eq!((record.occurrences, record.first_seen, record.last_seen), (6, 100, 230));
+        assert_eq!(record.transition().comment.as_deref(), Some("recurred after it was resolved"));
+
+        let json = serde_json::to_value(record.transition()).unwrap();
+        assert_eq!(json["state"], "open");
+        let request: TriageRequest = serde_json::from_str(r#"{"state": "snoozed", "until": 300, "comment": "patch window"}"#).unwrap();
+        assert_eq!(request.state, TriageState::Snoozed { until: 300 });
+    }
+}
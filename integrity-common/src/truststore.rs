@@ -0,0 +1,180 @@
+//! Certificates of the system CA trust store.
+//!
+//! A rogue CA added to the trust store lets an attacker intercept every TLS
+//! connection of the host. The file hashes show that `ca-certificates.crt`
+//! changed; the certificates recorded here show which CA was added or removed.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Directories and bundles holding trusted CAs (relative), including what
+/// update-ca-certificates and update-ca-trust generate.
+pub const TRUST_STORE_PATHS: &[&str] = &[
+    "etc/ssl/certs",
+    "usr/share/ca-certificates",
+    "usr/local/share/ca-certificates",
+    "etc/pki/ca-trust/source/anchors",
+    "etc/pki/ca-trust/extracted/pem",
+    "etc/pki/tls/certs",
+];
+
+/// Whether changes to `path` (relative) are described by [`TrustStore::changes`].
+pub fn is_trust_store_file(path: &str) -> bool {
+    let path = path.trim_start_matches('/');
+    TRUST_STORE_PATHS
+        .iter()
+        .any(|p| path.strip_prefix(p).is_some_and(|rest| rest.starts_with('/')))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TrustedCertificate {
+    /// Distinguished name, e.g. "C=US, O=Let's Encrypt, CN=R3"
+    pub subject: String,
+    pub not_after: String,
+    /// First file the certificate was found in, relative to root
+    pub path: String,
+}
+
+/// Trusted certificates, by hex encoded SHA-256 fingerprint.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct TrustStore {
+    pub certificates: BTreeMap<String, TrustedCertificate>,
+}
+
+/// A certificate added to or removed from the trust store.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrustStoreChange {
+    /// "CA_ADDED" or "CA_REMOVED"
+    pub kind: &'static str,
+    pub fingerprint: String,
+    pub certificate: TrustedCertificate,
+}
+
+impl TrustStore {
+    /// Certificates in `current` but not recorded, then recorded ones gone from `current`.
+    /// Where a certificate is found does not matter, only whether it is trusted.
+    pub fn changes(&self, current: &TrustStore) -> Vec<TrustStoreChange> {
+        let added = current
+            .certificates
+            .iter()
+            .filter(|(fingerprint, _)| !self.certificates.contains_key(*fingerprint))
+            .map(|(fingerprint, certificate)| ("CA_ADDED", fingerprint, certificate));
+        let removed = self
+            .certificates
+            .iter()
+            .filter(|(fingerprint, _)| !current.certificates.contains_key(*fingerprint))
+            .map(|(fingerprint, certificate)| ("CA_REMOVED", fingerprint, certificate));
+        added
+            .chain(removed)
+            .map(|(kind, fingerprint, certificate)| TrustStoreChange {
+                kind,
+                fingerprint: fingerprint.clone(),
+                certificate: certificate.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "host")]
+mod host {
+    use super::*;
+    use sha2::{Digest, Sha256};
+    use std::path::Path;
+    use x509_parser::pem::Pem;
+
+    /// DER encoded certificates of a PEM bundle, or of a single DER file.
+    pub(super) fn certificates(contents: &[u8]) -> Vec<Vec<u8>> {
+        let pems: Vec<Vec<u8>> = Pem::iter_from_buffer(contents)
+            .filter_map(|pem| pem.ok())
+            .filter(|pem| pem.label == "CERTIFICATE" || pem.label == "TRUSTED CERTIFICATE")
+            .map(|pem| pem.contents)
+            .collect();
+        if pems.is_empty() && x509_parser::parse_x509_certificate(contents).is_ok() {
+            return vec![contents.to_vec()];
+        }
+        pems
+    }
+
+    fn add_file(store: &mut TrustStore, path: &Path, relative: &str) {
+        let Ok(contents) = std::fs::read(path) else {
+            return;
+        };
+        for der in certificates(&contents) {
+            let Ok((_, certificate)) = x509_parser::parse_x509_certificate(&der) else {
+                continue;
+            };
+            let fingerprint = hex::encode(Sha256::digest(&der));
+            store.certificates.entry(fingerprint).or_insert_with(|| TrustedCertificate {
+                subject: certificate.subject().to_string(),
+                not_after: certificate.validity().not_after.to_string(),
+                path: relative.to_string(),
+            });
+        }
+    }
+
+    fn add_dir(store: &mut TrustStore, root: &Path, relative: &str) {
+        let Ok(entries) = std::fs::read_dir(root.join(relative)) else {
+            return;
+        };
+        let mut names: Vec<String> = entries.filter_map(|e| e.ok()).map(|e| e.file_name().to_string_lossy().to_string()).collect();
+        // Stable "first file" for each certificate
+        names.sort();
+        for name in names {
+            let relative = format!("{}/{}", relative, name);
+            let path = root.join(&relative);
+            // Links to files are followed, the hashed names in etc/ssl/certs point elsewhere,
+            // links to directories are not, so loops can't recurse forever
+            if std::fs::symlink_metadata(&path).is_ok_and(|m| m.is_dir()) {
+                add_dir(store, root, &relative);
+            } else if path.is_file() {
+                add_file(store, &path, &relative);
+            }
+        }
+    }
+
+    impl TrustStore {
+        /// Reads every certificate under [`TRUST_STORE_PATHS`] of the filesystem at `root`.
+        pub fn read(root: &Path) -> TrustStore {
+            let mut store = TrustStore::default();
+            for relative in TRUST_STORE_PATHS {
+                add_dir(&mut store, root, relative);
+            }
+            store
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn certificate(subject: &str) -> TrustedCertificate {
+        TrustedCertificate {
+            subject: subject.to_string(),
+            not_after: "Dec 31 00:00:00 2030 +00:00".to_string(),
+            path: "etc/ssl/certs/ca-certificates.crt".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_changes() {
+        let recorded = TrustStore {
+            certificates: [("aa".to_string(), certificate("CN=Root A")), ("bb".to_string(), certificate("CN=Root B"))].into(),
+        };
+        let mut current = recorded.clone();
+        // Moving a certificate to another file is not a change
+        current.certificates.get_mut("aa").unwrap().path = "usr/share/ca-certificates/a.crt".to_string();
+        assert!(recorded.changes(&current).is_empty());
+
+        current.certificates.remove("bb");
+        current.certificates.insert("cc".to_string(), certificate("CN=Evil Root"));
+        let changes: Vec<(&str, String)> = recorded.changes(&current).into_iter().map(|c| (c.kind, c.certificate.subject)).collect();
+        assert_eq!(
+            changes,
+            vec![("CA_ADDED", "CN=Evil Root".to_string()), ("CA_REMOVED", "CN=Root B".to_string())]
+        );
+
+        assert!(is_trust_store_file("etc/ssl/certs/ca-certificates.crt"));
+        assert!(!is_trust_store_file("etc/ssl/openssl.cnf"));
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{Baseline, HashAlgorithm};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Upper bound on the number of entries a single baseline may contain.
+pub const MAX_BASELINE_ENTRIES: usize = 10_000_000;
+
+/// The kind of problem found while validating a baseline.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ViolationKind {
+    /// image_id is empty
+    EmptyImageId,
+    /// The baseline has more entries than allowed
+    TooManyEntries { count: usize, limit: usize },
+    /// The same path appears more than once
+    DuplicatePath,
+    /// The digest is not a lowercase hex string of the expected length
+    InvalidHash { algorithm: String },
+    /// The path is absolute, has empty/"."/".." components, or contains NUL
+    UnnormalizedPath,
+    /// The mode has bits set outside of 0o7777
+    InvalidMode { mode: u32 },
+}
+
+/// A single validation failure, optionally tied to an entry path.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Violation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(flatten)]
+    pub kind: ViolationKind,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match &self.kind {
+            ViolationKind::EmptyImageId => "image_id is empty".to_string(),
+            ViolationKind::TooManyEntries { count, limit } => {
+                format!("too many entries ({} > {})", count, limit)
+            }
+            ViolationKind::DuplicatePath => "duplicate path".to_string(),
+            ViolationKind::InvalidHash { algorithm } => format!("invalid {} digest", algorithm),
+            ViolationKind::UnnormalizedPath => "path is not normalized".to_string(),
+            ViolationKind::InvalidMode { mode } => format!("invalid mode {:o}", mode),
+        };
+        match &self.path {
+            Some(path) => write!(f, "{}: {}", path, description),
+            None => write!(f, "{}", description),
+        }
+    }
+}
+
+fn is_normalized(path: &str) -> bool {
+    !path.is_empty()
+        && !path.contains('\0')
+        && path
+            .split('/')
+            .all(|component| !component.is_empty() && component != "." && component != "..")
+}
+
+fn is_valid_digest(algorithm: HashAlgorithm, digest: &str) -> bool {
+    digest.len() == algorithm.hex_len() && digest.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+impl Baseline {
+    /// Checks the baseline for structural problems.
+    /// Returns every violation found; an empty list means the baseline is valid.
+    pub fn validate(&self) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let mut violation = |path: Option<&str>, kind| {
+            violations.push(Violation {
+                path: path.map(str::to_string),
+                kind,
+            })
+        };
+
+        if self.image_id.trim().is_empty() {
+            violation(None, ViolationKind::EmptyImageId);
+        }
+        if self.entries.len() > MAX_BASELINE_ENTRIES {
+            violation(
+                None,
+                ViolationKind::TooManyEntries {
+                    count: self.entries.len(),
+                    limit: MAX_BASELINE_ENTRIES,
+                },
+            );
+        }
+
+        let mut seen = HashSet::with_capacity(self.entries.len());
+        for entry in &self.entries {
+            let path = Some(entry.path.as_str());
+            if !seen.insert(entry.path.as_str()) {
+                violation(path, ViolationKind::DuplicatePath);
+            }
+            if !is_normalized(&entry.path) {
+                violation(path, ViolationKind::UnnormalizedPath);
+            }
+            for algorithm in HashAlgorithm::ALL {
+                if entry.digest(algorithm).is_some_and(|digest| !is_valid_digest(algorithm, digest)) {
+                    violation(
+                        path,
+                        ViolationKind::InvalidHash {
+                            algorithm: algorithm.to_string(),
+                        },
+                    );
+                }
+            }
+            if entry.mode & !0o7777 != 0 {
+                violation(path, ViolationKind::InvalidMode { mode: entry.mode });
+            }
+        }
+
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::BaselineBuilder;
+
+    #[test]
+    fn test_valid_baseline() {
+        let mut baseline = BaselineBuilder::new("img").file("etc/passwd", 0o644).file_with_digest("bin/sh", 0o755, "").build();
+        let blake3 = baseline.entries.last_mut().unwrap();
+        blake3.digests.insert(HashAlgorithm::Blake3, "b".repeat(64));
+        assert!(baseline.validate().is_empty());
+    }
+
+    #[test]
+    fn test_violations() {
+        let good_hash = "0".repeat(128);
+        let baseline = BaselineBuilder::new("")
+            .file_with_digest("etc/passwd", 0o644, &good_hash)
+            .file_with_digest("etc/passwd", 0o644, &good_hash)
+            .file_with_digest("etc/../shadow", 0o644, &good_hash)
+            .file_with_digest("etc/hosts", 0o644, "ABC")
+            .file_with_digest("etc/group", 0o100644, &good_hash)
+            .build();
+        let kinds: Vec<_> = baseline.validate().into_iter().map(|v| v.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ViolationKind::EmptyImageId,
+                ViolationKind::DuplicatePath,
+                ViolationKind::UnnormalizedPath,
+                ViolationKind::InvalidHash {
+                    algorithm: "sha512".to_string()
+                },
+                ViolationKind::InvalidMode { mode: 0o100644 },
+            ]
+        );
+    }
+}
@@ -0,0 +1,193 @@
+//! Extended attributes of baselined files.
+//!
+//! SELinux labels (`security.selinux`), file capabilities
+//! (`security.capability`), IMA/EVM signatures and `user.*` attributes change
+//! what a file may do without touching its content or mode: `setcap
+//! cap_net_raw+ep` on a copied binary grants a privilege the hash does not
+//! show. Every attribute of a file is recorded by name with its value hex
+//! encoded, and [`describe_xattr`] renders values for anomalies.
+
+use std::collections::BTreeMap;
+
+/// Linux capability names by bit number.
+const CAPABILITIES: &[&str] = &[
+    "cap_chown",
+    "cap_dac_override",
+    "cap_dac_read_search",
+    "cap_fowner",
+    "cap_fsetid",
+    "cap_kill",
+    "cap_setgid",
+    "cap_setuid",
+    "cap_setpcap",
+    "cap_linux_immutable",
+    "cap_net_bind_service",
+    "cap_net_broadcast",
+    "cap_net_admin",
+    "cap_net_raw",
+    "cap_ipc_lock",
+    "cap_ipc_owner",
+    "cap_sys_module",
+    "cap_sys_rawio",
+    "cap_sys_chroot",
+    "cap_sys_ptrace",
+    "cap_sys_pacct",
+    "cap_sys_admin",
+    "cap_sys_boot",
+    "cap_sys_nice",
+    "cap_sys_resource",
+    "cap_sys_time",
+    "cap_sys_tty_config",
+    "cap_mknod",
+    "cap_lease",
+    "cap_audit_write",
+    "cap_audit_control",
+    "cap_setfcap",
+    "cap_mac_override",
+    "cap_mac_admin",
+    "cap_syslog",
+    "cap_wake_alarm",
+    "cap_block_suspend",
+    "cap_audit_read",
+    "cap_perfmon",
+    "cap_bpf",
+    "cap_checkpoint_restore",
+];
+
+/// An attribute whose value differs from the recorded one, values as
+/// [`describe_xattr`] renders them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct XattrChange {
+    pub name: String,
+    /// `None` when the attribute was not recorded
+    pub expected: Option<String>,
+    /// `None` when the file no longer has it
+    pub actual: Option<String>,
+}
+
+/// Attributes added, removed or changed going from `recorded` to `current`, by name.
+pub fn xattr_changes(recorded: &BTreeMap<String, String>, current: &BTreeMap<String, String>) -> Vec<XattrChange> {
+    let mut names: Vec<&String> = recorded.keys().chain(current.keys()).collect();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .filter(|name| recorded.get(*name) != current.get(*name))
+        .map(|name| XattrChange {
+            name: name.clone(),
+            expected: recorded.get(name).map(|value| describe_xattr(name, value)),
+            actual: current.get(name).map(|value| describe_xattr(name, value)),
+        })
+        .collect()
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len()).step_by(2).map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn capability_names(bits: u64) -> String {
+    (0..64)
+        .filter(|bit| bits & (1 << bit) != 0)
+        .map(|bit| CAPABILITIES.get(bit).map_or_else(|| format!("cap_{}", bit), |name| name.to_string()))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// A `security.capability` value (`struct vfs_cap_data`) the way getcap
+/// shows it, e.g. "cap_net_raw=ep".
+fn describe_capabilities(value: &[u8]) -> Option<String> {
+    let word = |i: usize| value.get(i * 4..i * 4 + 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as u64);
+    let magic = word(0)?;
+    // Revision 1 has one permitted/inheritable pair, 2 and 3 two
+    let (permitted, inheritable) = match magic & 0xff00_0000 {
+        0x0100_0000 => (word(1)?, word(2)?),
+        0x0200_0000 | 0x0300_0000 => (word(1)? | word(3)? << 32, word(2)? | word(4)? << 32),
+        _ => return None,
+    };
+    let effective = if magic & 1 != 0 { "e" } else { "" };
+    let mut sets = Vec::new();
+    if permitted != 0 {
+        sets.push(format!("{}={}p", capability_names(permitted), effective));
+    }
+    if inheritable != 0 {
+        sets.push(format!("{}=i", capability_names(inheritable)));
+    }
+    Some(if sets.is_empty() { "=".to_string() } else { sets.join(" ") })
+}
+
+/// A recorded attribute value for people: capabilities as getcap shows them,
+/// text values (SELinux labels, most `user.*`) as text, anything else hex.
+pub fn describe_xattr(name: &str, value: &str) -> String {
+    let Some(bytes) = decode_hex(value) else {
+        return value.to_string();
+    };
+    if name == "security.capability" {
+        if let Some(capabilities) = describe_capabilities(&bytes) {
+            return capabilities;
+        }
+    }
+    let text = bytes.strip_suffix(&[0]).unwrap_or(&bytes);
+    match std::str::from_utf8(text) {
+        Ok(text) if !text.is_empty() && !text.chars().any(char::is_control) => text.to_string(),
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(feature = "host")]
+mod host {
+    use super::*;
+    use std::path::Path;
+
+    /// The extended attributes of `path` itself (not of what a symlink
+    /// points to), values hex encoded. Attributes that vanish while they
+    /// are read are left out.
+    pub fn read_xattrs(path: &Path) -> std::io::Result<BTreeMap<String, String>> {
+        let mut xattrs = BTreeMap::new();
+        for name in xattr::list(path)? {
+            if let Some(value) = xattr::get(path, &name)? {
+                xattrs.insert(name.to_string_lossy().to_string(), hex::encode(value));
+            }
+        }
+        Ok(xattrs)
+    }
+}
+
+#[cfg(feature = "host")]
+pub use host::read_xattrs;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xattr_changes() {
+        // system_u:object_r:bin_t:s0, NUL terminated
+        let label = "73797374656d5f753a6f626a6563745f723a62696e5f743a733000";
+        assert_eq!(describe_xattr("security.selinux", label), "system_u:object_r:bin_t:s0");
+        // Revision 2, effective, permitted cap_net_raw (bit 13)
+        let net_raw = "0100000200200000000000000000000000000000";
+        assert_eq!(describe_xattr("security.capability", net_raw), "cap_net_raw=ep");
+        assert_eq!(describe_xattr("user.blob", "00ff"), "00ff");
+
+        let recorded: BTreeMap<String, String> = [("security.selinux".to_string(), label.to_string())].into();
+        let mut current = recorded.clone();
+        assert!(xattr_changes(&recorded, &current).is_empty());
+
+        current.insert("security.capability".to_string(), net_raw.to_string());
+        current.insert("security.selinux".to_string(), "756e636f6e66696e65645f7500".to_string());
+        assert_eq!(
+            xattr_changes(&recorded, &current),
+            vec![
+                XattrChange { name: "security.capability".to_string(), expected: None, actual: Some("cap_net_raw=ep".to_string()) },
+                XattrChange {
+                    name: "security.selinux".to_string(),
+                    expected: Some("system_u:object_r:bin_t:s0".to_string()),
+                    actual: Some("unconfined_u".to_string()),
+                },
+            ]
+        );
+    }
+}
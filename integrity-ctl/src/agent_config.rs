@@ -0,0 +1,123 @@
+//! `integrity-ctl agent-config`: manages the agent settings stored per image
+//! and per host, and their versions.
+
+use anyhow::{bail, Result};
+use clap::Subcommand;
+use integrity_client::MetadataClient;
+use integrity_common::{AgentConfigHistory, AgentConfigRollback, AgentConfigUpdate, AgentSettings, IntegrityError};
+
+#[derive(Subcommand, Debug)]
+pub enum AgentConfigCommand {
+    /// Show the settings an agent on a host of an image applies
+    Show {
+        #[arg(long)]
+        host: String,
+        #[arg(long)]
+        image_id: String,
+    },
+    /// List the versions of an image's or host's settings
+    History {
+        #[arg(value_parser = ["image", "host"])]
+        scope: String,
+        /// Image ID or host name
+        name: String,
+        /// Print the history as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Change an image's or host's settings, storing them as a new version
+    Set {
+        #[arg(value_parser = ["image", "host"])]
+        scope: String,
+        /// Image ID or host name
+        name: String,
+        /// Set an agent flag, e.g. --set watch-paths=/etc,/usr/bin (repeatable)
+        #[arg(long = "set", value_parser = parse_setting)]
+        settings: Vec<(String, String)>,
+        /// Remove a flag from the settings (repeatable)
+        #[arg(long)]
+        unset: Vec<String>,
+        #[arg(long)]
+        comment: Option<String>,
+    },
+    /// Make an earlier version current again, as a new version
+    Rollback {
+        #[arg(value_parser = ["image", "host"])]
+        scope: String,
+        /// Image ID or host name
+        name: String,
+        version: u64,
+        #[arg(long)]
+        comment: Option<String>,
+    },
+}
+
+fn parse_setting(setting: &str) -> Result<(String, String), String> {
+    let (flag, value) = setting.split_once('=').ok_or_else(|| format!("expected flag=value, got {}", setting))?;
+    Ok((flag.trim_start_matches('-').to_string(), value.to_string()))
+}
+
+fn format_time(time: i64) -> String {
+    chrono::DateTime::from_timestamp(time, 0).map_or_else(|| time.to_string(), |t| t.format("%Y-%m-%d %H:%M").to_string())
+}
+
+fn print_settings(settings: &AgentSettings) {
+    for (flag, value) in settings {
+        println!("  {} = {}", flag, value);
+    }
+}
+
+fn print_history(history: &AgentConfigHistory) {
+    for version in &history.versions {
+        let comment = version.comment.as_deref().map(|c| format!("  ({})", c)).unwrap_or_default();
+        println!("v{}  {}  {}{}", version.version, format_time(version.created_at), version.author, comment);
+        print_settings(&version.settings);
+    }
+}
+
+/// Runs an agent-config subcommand; changes take the admin token as `--api-token`.
+pub async fn run(client: &MetadataClient, command: AgentConfigCommand) -> Result<()> {
+    match command {
+        AgentConfigCommand::Show { host, image_id } => {
+            let config = client.effective_agent_config(&host, &image_id).await?;
+            println!("{} on {}: {}", host, image_id, config.revision());
+            print_settings(&config.settings);
+        }
+        AgentConfigCommand::History { scope, name, json } => {
+            let history = client.agent_config_history(&scope, &name).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&history)?);
+            } else {
+                print_history(&history);
+            }
+        }
+        AgentConfigCommand::Set { scope, name, settings, unset, comment } => {
+            if settings.is_empty() && unset.is_empty() {
+                bail!("nothing to change: give --set or --unset");
+            }
+            // Start from the current version, if there is one
+            let mut current = match client.agent_config_history(&scope, &name).await {
+                Ok(history) => history.current().map(|version| version.settings.clone()).unwrap_or_default(),
+                Err(IntegrityError::BaselineNotFound(_)) => AgentSettings::new(),
+                Err(e) => return Err(e.into()),
+            };
+            for flag in &unset {
+                current.remove(flag.trim_start_matches('-'));
+            }
+            current.extend(settings);
+            let update = AgentConfigUpdate { settings: current, comment };
+            let history = client.put_agent_config(&scope, &name, &update).await?;
+            let version = history.current().expect("just stored");
+            println!("Stored {} {} version {}", scope, name, version.version);
+            print_settings(&version.settings);
+        }
+        AgentConfigCommand::Rollback { scope, name, version, comment } => {
+            let rollback = AgentConfigRollback { version, comment };
+            let history = client.rollback_agent_config(&scope, &name, &rollback).await?;
+            let current = history.current().expect("just stored");
+            println!("Rolled {} {} back to version {} as version {}", scope, name, version, current.version);
+            print_settings(&current.settings);
+        }
+    }
+    Ok(())
+}
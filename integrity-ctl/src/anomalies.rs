@@ -0,0 +1,141 @@
+//! `integrity-ctl anomalies`: lists reported anomalies, triages them and
+//! compares the hosts of an image.
+
+use anyhow::Result;
+use clap::Subcommand;
+use integrity_client::MetadataClient;
+use integrity_common::{AnomalyRecord, DeviationScope, FleetAnalysis, TriageRequest, TriageState};
+use std::time::Duration;
+
+#[derive(Subcommand, Debug)]
+pub enum AnomalyCommand {
+    /// List anomaly records, most recently seen first
+    List {
+        /// Only records in this state: open, acknowledged, snoozed or resolved
+        #[arg(long)]
+        state: Option<String>,
+        #[arg(long)]
+        host: Option<String>,
+        #[arg(long)]
+        image_id: Option<String>,
+        /// Print the records, with their history, as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Compare the hosts of an image: deviations most of them share versus ones only a few have
+    Fleet {
+        image_id: String,
+        /// Share of hosts from which a deviation counts as fleet-wide
+        #[arg(long)]
+        fleet_wide_share: Option<f64>,
+        /// Print the analysis as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Acknowledge an anomaly: it stays recorded but no longer alerts when it recurs
+    Ack {
+        id: String,
+        #[arg(long)]
+        comment: Option<String>,
+    },
+    /// Silence an anomaly for a while, e.g. --for 8h; it opens again if it recurs afterwards
+    Snooze {
+        id: String,
+        #[arg(long = "for", value_parser = humantime::parse_duration)]
+        duration: Duration,
+        #[arg(long)]
+        comment: Option<String>,
+    },
+    /// Mark an anomaly as fixed; it opens again if it recurs
+    Resolve {
+        id: String,
+        #[arg(long)]
+        comment: Option<String>,
+    },
+    /// Open an anomaly again
+    Reopen {
+        id: String,
+        #[arg(long)]
+        comment: Option<String>,
+    },
+}
+
+fn format_time(time: i64) -> String {
+    chrono::DateTime::from_timestamp(time, 0).map_or_else(|| time.to_string(), |t| t.format("%Y-%m-%d %H:%M").to_string())
+}
+
+fn state_label(record: &AnomalyRecord, now: i64) -> String {
+    match record.state(now) {
+        TriageState::Snoozed { until } => format!("snoozed until {}", format_time(until)),
+        state => state.name().to_string(),
+    }
+}
+
+fn print_record(record: &AnomalyRecord, now: i64) {
+    let detail = if record.detail.is_empty() { String::new() } else { format!(" ({})", record.detail) };
+    println!(
+        "{}  {:<12} {}  {}: {}{}  [{}x, last {}]",
+        record.id,
+        record.host,
+        state_label(record, now),
+        record.kind,
+        record.path,
+        detail,
+        record.occurrences,
+        format_time(record.last_seen)
+    );
+}
+
+fn print_fleet(analysis: &FleetAnalysis) {
+    println!("{} hosts of {}, most outlying first:", analysis.fleet_size, analysis.image_id);
+    for host in &analysis.hosts {
+        println!("  {:<24} score {:>6.2}  {} deviations, {} unique", host.host, host.outlier_score, host.deviations, host.unique);
+    }
+    for (scope, title) in [(DeviationScope::Unique, "Unique to one host"), (DeviationScope::Shared, "Shared"), (DeviationScope::FleetWide, "Fleet-wide")] {
+        let deviations: Vec<_> = analysis.deviations.iter().filter(|d| d.scope == scope).collect();
+        if deviations.is_empty() {
+            continue;
+        }
+        println!("{}:", title);
+        for deviation in deviations {
+            println!("  {}: {}  ({:.0}%: {})", deviation.kind, deviation.path, deviation.share * 100.0, deviation.hosts.join(", "));
+        }
+    }
+}
+
+/// Runs an anomalies subcommand; triage changes are made as `actor`.
+pub async fn run(client: &MetadataClient, command: AnomalyCommand, actor: Option<String>) -> Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    let (id, state, comment) = match command {
+        AnomalyCommand::List { state, host, image_id, json } => {
+            let records = client.list_anomalies(state.as_deref(), host.as_deref(), image_id.as_deref()).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&records)?);
+            } else {
+                for record in &records {
+                    print_record(record, now);
+                }
+                println!("{} anomalies", records.len());
+            }
+            return Ok(());
+        }
+        AnomalyCommand::Fleet { image_id, fleet_wide_share, json } => {
+            let analysis = client.fleet_analysis(&image_id, fleet_wide_share).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&analysis)?);
+            } else {
+                print_fleet(&analysis);
+            }
+            return Ok(());
+        }
+        AnomalyCommand::Ack { id, comment } => (id, TriageState::Acknowledged, comment),
+        AnomalyCommand::Snooze { id, duration, comment } => {
+            (id, TriageState::Snoozed { until: now + duration.as_secs() as i64 }, comment)
+        }
+        AnomalyCommand::Resolve { id, comment } => (id, TriageState::Resolved, comment),
+        AnomalyCommand::Reopen { id, comment } => (id, TriageState::Open, comment),
+    };
+    let record = client.triage_anomaly(&id, &TriageRequest { state, actor, comment }).await?;
+    print_record(&record, now);
+    Ok(())
+}
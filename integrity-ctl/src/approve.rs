@@ -0,0 +1,83 @@
+//! `integrity-ctl approve`: lists the baseline versions of an image staged to
+//! take effect later with their approvals, and signs one as approver after
+//! review, so agents requiring an approver signature accept it once it does.
+
+use crate::schedule::{format_time, parse_time};
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use integrity_client::MetadataClient;
+use integrity_common::{BaselineSignature, BaselineSigner, SignerRole};
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct ApproveArgs {
+    image_id: String,
+    /// When the version to approve takes effect, as listed (Unix time or RFC 3339); lists the pending versions without it
+    #[arg(value_parser = parse_time, requires = "key_file")]
+    effective_from: Option<i64>,
+    /// Ed25519 private key to sign with (32 bytes, raw or hex)
+    #[arg(long)]
+    key_file: Option<PathBuf>,
+}
+
+/// Public keys of the approvers that signed a chain.
+fn approvers(chain: &[BaselineSignature]) -> Vec<&str> {
+    chain.iter().filter(|link| link.role == SignerRole::Approver).map(|link| link.public_key.as_str()).collect()
+}
+
+pub async fn run(client: &MetadataClient, args: ApproveArgs) -> Result<()> {
+    let pending = client.scheduled_baselines(&args.image_id).await?;
+    let (Some(effective_from), Some(key_file)) = (args.effective_from, &args.key_file) else {
+        for version in &pending {
+            let chain = client.baseline_signatures(&args.image_id, Some(&version.digest)).await?;
+            let approvers = approvers(&chain);
+            println!(
+                "{:>12}  {}  collected {}  {} files  {}  {} approvals",
+                version.effective_from,
+                format_time(version.effective_from),
+                version.timestamp,
+                version.entries,
+                version.digest,
+                approvers.len()
+            );
+            for approver in approvers {
+                println!("{:>14}approved by {}", "", approver);
+            }
+        }
+        println!("{} pending versions", pending.len());
+        return Ok(());
+    };
+
+    let Some(version) = pending.iter().find(|version| version.effective_from == effective_from) else {
+        bail!("no version of {} is pending for {}", args.image_id, format_time(effective_from));
+    };
+    let signer = BaselineSigner::from_key_material(&std::fs::read(key_file).with_context(|| format!("reading {:?}", key_file))?)?;
+    let chain = crate::signatures::sign(client, &args.image_id, &version.digest, &signer, SignerRole::Approver).await?;
+    println!(
+        "Approved the version of {} taking effect {} ({}); {} signatures",
+        args.image_id,
+        format_time(effective_from),
+        version.digest,
+        chain.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_approvers() {
+        let link = |role, public_key: &str| BaselineSignature {
+            role,
+            public_key: public_key.to_string(),
+            digest: "d1".to_string(),
+            signed_at: 1000,
+            signature: String::new(),
+        };
+        let chain = [link(SignerRole::Collector, "c1"), link(SignerRole::Approver, "a1"), link(SignerRole::Approver, "a2"), link(SignerRole::Service, "s1")];
+        assert_eq!(approvers(&chain), ["a1", "a2"]);
+        assert!(approvers(&chain[..1]).is_empty());
+    }
+}
@@ -0,0 +1,73 @@
+//! `integrity-ctl events` (`nats` feature): tails the events the metadata
+//! service publishes to NATS when started with `--nats-url`, printing each as
+//! one line of JSON as it arrives. Events published while nothing listens
+//! are not replayed.
+
+use anyhow::{Context, Result};
+use futures_util::StreamExt;
+use integrity_common::{subject_token, BASELINE_EVENTS, REPORT_EVENTS};
+use tracing::info;
+
+#[derive(clap::Args, Debug)]
+pub struct EventsArgs {
+    /// NATS server the metadata service publishes to, e.g. nats://127.0.0.1:4222
+    #[arg(long)]
+    nats_url: String,
+
+    /// First token of the subjects, as --nats-subject-prefix on the service
+    #[arg(long, default_value = "integrity")]
+    nats_subject_prefix: String,
+
+    /// Only events about baselines, or only about anomalies
+    #[arg(long, value_enum)]
+    kind: Option<EventKind>,
+
+    /// Only events about this image
+    #[arg(long)]
+    image_id: Option<String>,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum EventKind {
+    Baselines,
+    Reports,
+}
+
+/// The subject, with wildcards for what is not filtered on.
+fn subject(args: &EventsArgs) -> String {
+    let kind = match args.kind {
+        Some(EventKind::Baselines) => BASELINE_EVENTS,
+        Some(EventKind::Reports) => REPORT_EVENTS,
+        None => "*",
+    };
+    let image = args.image_id.as_deref().map_or_else(|| "*".to_string(), subject_token);
+    format!("{}.{}.{}", args.nats_subject_prefix, kind, image)
+}
+
+pub async fn run(args: EventsArgs) -> Result<()> {
+    let client = async_nats::connect(&args.nats_url).await.with_context(|| format!("connecting to NATS at {}", args.nats_url))?;
+    let subject = subject(&args);
+    let mut subscriber = client.subscribe(subject.clone()).await.with_context(|| format!("subscribing to {}", subject))?;
+    info!("Waiting for events on {}", subject);
+    while let Some(message) = subscriber.next().await {
+        println!("{}", String::from_utf8_lossy(&message.payload));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subject() {
+        let args = |kind, image_id: Option<&str>| EventsArgs {
+            nats_url: "nats://127.0.0.1:4222".to_string(),
+            nats_subject_prefix: "integrity".to_string(),
+            kind,
+            image_id: image_id.map(str::to_string),
+        };
+        assert_eq!(subject(&args(None, None)), "integrity.*.*");
+        assert_eq!(subject(&args(Some(EventKind::Reports), Some("ubuntu-22.04"))), "integrity.reports.ubuntu-22_04");
+    }
+}
@@ -0,0 +1,180 @@
+//! `integrity-ctl gate`: checks a freshly collected baseline against a
+//! policy file, so an image build fails before the image ships.
+//!
+//! The policy is JSON. Paths are baseline paths, with or without the leading
+//! `/`, and `forbidden` takes glob patterns:
+//!
+//! ```json
+//! {
+//!   "required": ["/etc/passwd", "/usr/sbin/sshd"],
+//!   "forbidden": ["/root/.ssh/authorized_keys", "**/*.pem"],
+//!   "allow_new_setuid": false,
+//!   "max_added": 200,
+//!   "max_removed": 50,
+//!   "max_modified": 1000
+//! }
+//! ```
+//!
+//! New setuid/setgid files and the drift limits are checked against the
+//! previous version of the image, when one is given.
+
+use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use integrity_common::Baseline;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Files the image must contain
+    #[serde(default)]
+    pub required: Vec<String>,
+    /// Patterns no file of the image may match
+    #[serde(default)]
+    pub forbidden: Vec<String>,
+    /// Accept files that became setuid or setgid since the previous version
+    #[serde(default)]
+    pub allow_new_setuid: bool,
+    pub max_added: Option<usize>,
+    pub max_removed: Option<usize>,
+    pub max_modified: Option<usize>,
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read(path).with_context(|| format!("reading policy {:?}", path))?;
+        serde_json::from_slice(&content).with_context(|| format!("parsing policy {:?}", path))
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum Violation {
+    MissingRequired { path: String },
+    Forbidden { path: String, pattern: String },
+    NewSetuid { path: String, mode: u32 },
+    Drift { kind: &'static str, count: usize, max: usize },
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::MissingRequired { path } => write!(f, "missing required file /{}", path),
+            Violation::Forbidden { path, pattern } => write!(f, "forbidden file /{} (matches {})", path, pattern),
+            Violation::NewSetuid { path, mode } => write!(f, "new setuid/setgid file /{} (mode {:o})", path, mode),
+            Violation::Drift { kind, count, max } => write!(f, "{} {} files, at most {} allowed", kind, count, max),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub image_id: String,
+    pub previous: Option<String>,
+    pub violations: Vec<Violation>,
+}
+
+fn normalize(path: &str) -> &str {
+    path.trim_start_matches('/')
+}
+
+fn forbidden_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(normalize(pattern)).with_context(|| format!("invalid forbidden pattern {}", pattern))?);
+    }
+    Ok(builder.build()?)
+}
+
+const SETUID_BITS: u32 = 0o6000;
+
+/// Checks `baseline`, and its drift from `previous` if given, against `policy`.
+pub fn check(policy: &Policy, baseline: &Baseline, previous: Option<&Baseline>) -> Result<Report> {
+    let mut violations = Vec::new();
+    let paths: HashMap<&str, u32> = baseline.entries.iter().map(|e| (e.path.as_str(), e.mode)).collect();
+
+    for required in &policy.required {
+        if !paths.contains_key(normalize(required)) {
+            violations.push(Violation::MissingRequired { path: normalize(required).to_string() });
+        }
+    }
+
+    let forbidden = forbidden_set(&policy.forbidden)?;
+    for entry in &baseline.entries {
+        if let Some(&index) = forbidden.matches(&entry.path).first() {
+            violations.push(Violation::Forbidden { path: entry.path.clone(), pattern: policy.forbidden[index].clone() });
+        }
+    }
+
+    if let Some(previous) = previous {
+        let old_modes: HashMap<&str, u32> = previous.entries.iter().map(|e| (e.path.as_str(), e.mode)).collect();
+        if !policy.allow_new_setuid {
+            for entry in &baseline.entries {
+                let old_bits = old_modes.get(entry.path.as_str()).map_or(0, |mode| mode & SETUID_BITS);
+                if entry.mode & SETUID_BITS & !old_bits != 0 {
+                    violations.push(Violation::NewSetuid { path: entry.path.clone(), mode: entry.mode });
+                }
+            }
+        }
+
+        let diff = previous.diff(baseline);
+        let limits = [
+            ("added", diff.added.len(), policy.max_added),
+            ("removed", diff.removed.len(), policy.max_removed),
+            ("modified", diff.modified.len(), policy.max_modified),
+        ];
+        for (kind, count, max) in limits {
+            if let Some(max) = max.filter(|max| count > *max) {
+                violations.push(Violation::Drift { kind, count, max });
+            }
+        }
+    }
+
+    Ok(Report {
+        image_id: baseline.image_id.clone(),
+        previous: previous.map(|p| p.image_id.clone()),
+        violations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::test_util::BaselineBuilder;
+
+    fn baseline(image_id: &str, entries: &[(&str, u32)]) -> Baseline {
+        let builder = BaselineBuilder::new(image_id).size(0);
+        entries.iter().fold(builder, |builder, (path, mode)| builder.file_with_digest(path, *mode, "aa")).build()
+    }
+
+    #[test]
+    fn test_check() {
+        let policy: Policy = serde_json::from_str(
+            r#"{"required": ["/etc/passwd", "/usr/sbin/sshd"], "forbidden": ["**/*.pem"], "max_added": 1}"#,
+        )
+        .unwrap();
+        let previous = baseline("app-v1", &[("etc/passwd", 0o644), ("usr/bin/su", 0o4755), ("usr/bin/ping", 0o755)]);
+        let current = baseline(
+            "app-v2",
+            &[("etc/passwd", 0o644), ("usr/bin/su", 0o4755), ("usr/bin/ping", 0o4755), ("etc/ssl/key.pem", 0o600), ("opt/x", 0o2755)],
+        );
+
+        let report = check(&policy, &current, Some(&previous)).unwrap();
+        assert_eq!(
+            report.violations,
+            vec![
+                Violation::MissingRequired { path: "usr/sbin/sshd".to_string() },
+                Violation::Forbidden { path: "etc/ssl/key.pem".to_string(), pattern: "**/*.pem".to_string() },
+                Violation::NewSetuid { path: "usr/bin/ping".to_string(), mode: 0o4755 },
+                Violation::NewSetuid { path: "opt/x".to_string(), mode: 0o2755 },
+                Violation::Drift { kind: "added", count: 2, max: 1 },
+            ]
+        );
+
+        // Without a previous version only the image itself is checked
+        assert_eq!(check(&policy, &current, None).unwrap().violations.len(), 2);
+        assert!(serde_json::from_str::<Policy>(r#"{"requird": []}"#).is_err());
+    }
+}
@@ -0,0 +1,379 @@
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use integrity_client::{ClientArgs, MetadataClient};
+use integrity_common::BaselineDiff;
+use std::path::PathBuf;
+
+mod agent_config;
+mod anomalies;
+mod approve;
+#[cfg(feature = "nats")]
+mod events;
+mod gate;
+mod remote_scan;
+mod schedule;
+mod signatures;
+mod snapshots;
+
+#[derive(Parser, Debug)]
+#[command(name = "integrity-ctl")]
+#[command(about = "Admin CLI for the Golden Image Integrity Metadata Service", long_about = None)]
+struct Args {
+    #[command(flatten)]
+    client: ClientArgs,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Fetch a baseline and print it as JSON
+    Fetch {
+        image_id: String,
+        /// A stored version instead of the latest (see `versions`)
+        #[arg(long)]
+        version: Option<u64>,
+        /// Write to a file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// List the stored baselines with their collection time and size
+    List {
+        /// Print the baselines as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show a baseline's collection time, size and digest without fetching its entries
+    Summary {
+        image_id: String,
+        /// Print the summary as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete an image's baseline with all its versions, staged versions and signatures
+    Delete {
+        image_id: String,
+        /// Delete without asking for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+    /// List the stored versions of an image's baseline
+    Versions {
+        image_id: String,
+        /// Print the versions as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show added/removed/modified entries between two baselines
+    Diff {
+        from: String,
+        to: String,
+        /// Print the diff as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show added/removed/modified entries between two stored versions of an image's baseline
+    VersionDiff {
+        image_id: String,
+        /// Version to compare from [default: the one before --to]
+        #[arg(long)]
+        from: Option<u64>,
+        /// Version to compare to [default: the latest]
+        #[arg(long)]
+        to: Option<u64>,
+        /// Print the diff as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Fetch a baseline and check it for structural problems
+    Validate { image_id: String },
+    /// Check a newly built image's baseline against a policy file; exits non-zero on violations
+    Gate {
+        /// Stored baseline to check
+        #[arg(required_unless_present = "baseline_file", conflicts_with = "baseline_file")]
+        image_id: Option<String>,
+        /// Check a baseline JSON file (e.g. written by `fetch --output`) instead
+        #[arg(long)]
+        baseline_file: Option<PathBuf>,
+        /// Policy file (JSON)
+        #[arg(long)]
+        policy: PathBuf,
+        /// Previous version of the image, for setuid additions and drift limits
+        #[arg(long)]
+        previous: Option<String>,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List reported anomalies and acknowledge, snooze or resolve them
+    Anomalies {
+        /// Who is triaging, recorded with each change [default: $USER]
+        #[arg(long, global = true)]
+        actor: Option<String>,
+        #[command(subcommand)]
+        command: anomalies::AnomalyCommand,
+    },
+    /// Show and change the agent settings stored per image and per host, with rollback
+    AgentConfig {
+        #[command(subcommand)]
+        command: agent_config::AgentConfigCommand,
+    },
+    /// List the registered agents with when they were last seen and how their last scan ended
+    Agents {
+        /// Only agents without a recent heartbeat
+        #[arg(long)]
+        stale: bool,
+        #[arg(long)]
+        image_id: Option<String>,
+        /// Print the agents as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the scan snapshots hosts uploaded and diff them against any baseline
+    Snapshots {
+        #[command(subcommand)]
+        command: snapshots::SnapshotCommand,
+    },
+    /// Scan hosts over SSH without an agent and compare them with a baseline; exits non-zero on drift
+    RemoteScan(remote_scan::RemoteScanArgs),
+    /// Generate signing keys, show baseline signature chains and countersign baselines
+    Signatures {
+        #[command(subcommand)]
+        command: signatures::SignatureCommand,
+    },
+    /// List the baseline versions pending to take effect with their approvals, and approve one
+    Approve(approve::ApproveArgs),
+    /// List and cancel baseline versions staged to take effect later
+    Schedule {
+        #[command(subcommand)]
+        command: schedule::ScheduleCommand,
+    },
+    /// Print the events the service publishes to NATS as they arrive
+    #[cfg(feature = "nats")]
+    Events(events::EventsArgs),
+}
+
+fn print_diff(diff: &BaselineDiff) {
+    for entry in &diff.added {
+        println!("+ {}", entry.path);
+    }
+    for entry in &diff.removed {
+        println!("- {}", entry.path);
+    }
+    for change in &diff.modified {
+        let (old, new) = (&change.old, &change.new);
+        let mut details = Vec::new();
+        if old.symlink_target != new.symlink_target {
+            let target = |target: &Option<String>| target.clone().unwrap_or_else(|| "(none)".to_string());
+            details.push(format!("target {} -> {}", target(&old.symlink_target), target(&new.symlink_target)));
+        } else if !old.same_content(new) {
+            details.push("content".to_string());
+        }
+        if old.mode != new.mode {
+            details.push(format!("mode {:o} -> {:o}", old.mode, new.mode));
+        }
+        if old.uid != new.uid || old.gid != new.gid {
+            details.push(format!("owner {}:{} -> {}:{}", old.uid, old.gid, new.uid, new.gid));
+        }
+        if let Some((old_xattrs, new_xattrs)) = old.xattrs.as_ref().zip(new.xattrs.as_ref()) {
+            for change in integrity_common::xattr_changes(old_xattrs, new_xattrs) {
+                let value = |value: Option<String>| value.unwrap_or_else(|| "(none)".to_string());
+                details.push(format!("{} {} -> {}", change.name, value(change.expected), value(change.actual)));
+            }
+        }
+        if let Some((old_size, new_size)) = old.size.zip(new.size).filter(|(old, new)| old != new) {
+            details.push(format!("size {} -> {}", old_size, new_size));
+        }
+        if let Some((old_mtime, new_mtime)) = old.mtime.zip(new.mtime).filter(|(old, new)| old != new) {
+            details.push(format!("mtime {} -> {}", old_mtime, new_mtime));
+        }
+        if let Some((old_nlink, new_nlink)) = old.nlink.zip(new.nlink).filter(|(old, new)| old != new) {
+            details.push(format!("links {} -> {}", old_nlink, new_nlink));
+        }
+        println!("~ {} ({})", new.path, details.join(", "));
+    }
+    println!(
+        "{} added, {} removed, {} modified",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.modified.len()
+    );
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt().with_writer(std::io::stderr).init();
+
+    let args = Args::parse();
+    let client = MetadataClient::from_args(&args.client)?;
+
+    match args.command {
+        Command::Fetch { image_id, version, output } => {
+            let baseline = match version {
+                Some(version) => client.get_baseline_version(&image_id, version).await?,
+                None => client.get_baseline(&image_id).await?,
+            };
+            let json = serde_json::to_string_pretty(&baseline)?;
+            match output {
+                Some(path) => std::fs::write(&path, json).with_context(|| format!("writing {:?}", path))?,
+                None => println!("{}", json),
+            }
+        }
+        Command::List { json } => {
+            // Pages of the service's maximum size, until a short one
+            let mut baselines = Vec::new();
+            loop {
+                let page = client.list_baselines(baselines.len(), 1000).await?;
+                let done = page.baselines.is_empty() || baselines.len() + page.baselines.len() >= page.total;
+                baselines.extend(page.baselines);
+                if done {
+                    break;
+                }
+            }
+            if json {
+                println!("{}", serde_json::to_string_pretty(&baselines)?);
+            } else {
+                for baseline in &baselines {
+                    let extends = baseline.extends.as_ref().map(|parent| format!("  extends {}", parent)).unwrap_or_default();
+                    println!("{}  {:>8} entries  collected {}{}", baseline.image_id, baseline.entries, baseline.timestamp, extends);
+                }
+            }
+        }
+        Command::Summary { image_id, json } => {
+            let summary = client.baseline_summary(&image_id).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+            } else {
+                println!("Image:     {}", summary.image_id);
+                println!("Collected: {}", summary.timestamp);
+                println!("Entries:   {} ({} bytes hashed)", summary.entries, summary.total_bytes);
+                println!("Digest:    {}", summary.digest);
+                if let Some(extends) = &summary.extends {
+                    println!("Extends:   {}", extends);
+                }
+            }
+        }
+        Command::Delete { image_id, yes } => {
+            if !yes {
+                eprint!("Delete baseline {} with all its versions? [y/N] ", image_id);
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer)?;
+                if !matches!(answer.trim(), "y" | "Y" | "yes") {
+                    bail!("not deleted");
+                }
+            }
+            client.delete_baseline(&image_id).await?;
+            println!("Deleted baseline {}", image_id);
+        }
+        Command::Versions { image_id, json } => {
+            let versions = client.baseline_versions(&image_id).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&versions)?);
+            } else {
+                for version in &versions {
+                    let stored_at = version
+                        .stored_at
+                        .and_then(|time| chrono::DateTime::from_timestamp(time, 0))
+                        .map_or_else(|| "-".to_string(), |time| time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+                    println!("{:>4}  {}  {:>8} entries  collected {}  {}", version.version, stored_at, version.entries, version.timestamp, version.digest);
+                }
+            }
+        }
+        Command::Diff { from, to, json } => {
+            let old = client.get_baseline(&from).await?;
+            let new = client.get_baseline(&to).await?;
+            let diff = old.diff(&new);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&diff)?);
+            } else {
+                print_diff(&diff);
+            }
+        }
+        Command::VersionDiff { image_id, from, to, json } => {
+            let diff = client.diff_baseline_versions(&image_id, from, to).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&diff)?);
+            } else {
+                print_diff(&diff);
+            }
+        }
+        Command::Validate { image_id } => {
+            let baseline = client.get_baseline(&image_id).await?;
+            let violations = baseline.validate();
+            for violation in &violations {
+                println!("{}", violation);
+            }
+            if !violations.is_empty() {
+                bail!("{} violation(s) in baseline {}", violations.len(), image_id);
+            }
+            println!("Baseline {} is valid (digest {})", image_id, baseline.digest()?);
+        }
+        Command::Gate { image_id, baseline_file, policy, previous, json } => {
+            let policy = gate::Policy::load(&policy)?;
+            let baseline = match (image_id, baseline_file) {
+                (Some(image_id), _) => client.get_baseline(&image_id).await?,
+                (None, Some(path)) => {
+                    let content = std::fs::read(&path).with_context(|| format!("reading {:?}", path))?;
+                    serde_json::from_slice(&content).with_context(|| format!("parsing {:?}", path))?
+                }
+                (None, None) => unreachable!("clap requires one of them"),
+            };
+            let previous = match previous {
+                Some(previous) => Some(client.get_baseline(&previous).await?),
+                None => None,
+            };
+            let report = gate::check(&policy, &baseline, previous.as_ref())?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                for violation in &report.violations {
+                    println!("{}", violation);
+                }
+            }
+            if !report.violations.is_empty() {
+                bail!("{} policy violation(s) in baseline {}", report.violations.len(), report.image_id);
+            }
+            if !json {
+                println!("Baseline {} passes the policy", report.image_id);
+            }
+        }
+        Command::Anomalies { actor, command } => {
+            anomalies::run(&client, command, actor.or_else(|| std::env::var("USER").ok())).await?;
+        }
+        Command::AgentConfig { command } => {
+            agent_config::run(&client, command).await?;
+        }
+        Command::Agents { stale, image_id, json } => {
+            let agents = client.list_agents(stale.then_some(true), image_id.as_deref()).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&agents)?);
+            } else {
+                let time = |time: i64| {
+                    chrono::DateTime::from_timestamp(time, 0).map_or_else(|| "-".to_string(), |time| time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+                };
+                for agent in &agents {
+                    let last_scan = agent
+                        .last_scan
+                        .as_ref()
+                        .map_or_else(|| "no scan reported".to_string(), |scan| format!("last scan {} ({} anomalies) at {}", scan.outcome, scan.anomalies, time(scan.finished_at)));
+                    let stale = if agent.stale { "  STALE" } else { "" };
+                    println!(
+                        "{:<24} {:<24} {:<8} {:<8} seen {}  {}{}",
+                        agent.host, agent.image_id, agent.agent_version, agent.mode, time(agent.last_seen), last_scan, stale
+                    );
+                }
+                println!("{} agents", agents.len());
+            }
+        }
+        Command::Snapshots { command } => snapshots::run(&client, command).await?,
+        Command::RemoteScan(scan) => remote_scan::run(&client, scan).await?,
+        Command::Signatures { command } => signatures::run(&client, command).await?,
+        Command::Approve(approve) => approve::run(&client, approve).await?,
+        Command::Schedule { command } => schedule::run(&client, command).await?,
+        #[cfg(feature = "nats")]
+        Command::Events(events) => events::run(events).await?,
+    }
+
+    Ok(())
+}
@@ -0,0 +1,239 @@
+//! `integrity-ctl remote-scan`: agentless scans of hosts over SSH.
+//!
+//! Appliances and locked-down hosts often cannot run the agent but accept
+//! SSH. The scan runs a short POSIX shell script on the host (`find`, `stat`
+//! and `sha512sum`, or `openssl` where coreutils are missing, as on many
+//! appliances) that streams each file's mode, owner, group and SHA-512; the
+//! comparison against the baseline happens here. Symlinks are recorded as
+//! the collector records them: by their target, hashed as a string, whether
+//! or not it exists. Volatile directories are
+//! skipped like the collector skips them. The system `ssh` is used, so keys,
+//! agents, jump hosts and `~/.ssh/config` work as they do interactively.
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use integrity_client::MetadataClient;
+use integrity_common::{Baseline, BaselineDiff, EntryType, FileIntegrityEntry, ScanSnapshot};
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Prints one record per file: "f <octal mode> <uid> <gid>\t<sha512>\t<path>\0",
+/// and per symlink "l <octal mode> <uid> <gid>\t<sha512 of target>\t<path>\0<target>\0".
+/// Files that cannot be hashed (permission denied) are left out.
+const SCAN_SCRIPT: &str = r#"
+set -u
+root="$1"
+if command -v sha512sum >/dev/null 2>&1; then
+    hash() { sha512sum | cut -d' ' -f1; }
+else
+    hash() { openssl dgst -sha512 -r | cut -d' ' -f1; }
+fi
+find "$root" \( -path /proc -o -path /sys -o -path /dev -o -path /run -o -path /tmp -o -path /var/tmp -o -path /var/log \) -prune \
+    -o \( -type f -o -type l \) -print | while IFS= read -r file; do
+    if [ -L "$file" ]; then
+        target=$(readlink "$file") || continue
+        sum=$(printf '%s' "$target" | hash)
+        printf 'l %s\t%s\t%s\0%s\0' "$(stat -c '%a %u %g' "$file")" "$sum" "$file" "$target"
+    else
+        sum=$(hash < "$file" 2>/dev/null) || continue
+        [ -n "$sum" ] || continue
+        printf 'f %s\t%s\t%s\0' "$(stat -c '%a %u %g' "$file")" "$sum" "$file"
+    fi
+done
+"#;
+
+#[derive(Args, Debug)]
+pub struct RemoteScanArgs {
+    /// Hosts to scan, as given to ssh ([user@]host or a ~/.ssh/config alias)
+    #[arg(required = true)]
+    hosts: Vec<String>,
+    /// Baseline to compare the hosts against
+    #[arg(long)]
+    image_id: String,
+    /// Directory to scan on the hosts
+    #[arg(long, default_value = "/")]
+    scan_path: String,
+    /// Private key for ssh (-i)
+    #[arg(long)]
+    identity_file: Option<String>,
+    /// Extra ssh option, e.g. --ssh-option ProxyJump=bastion (repeatable)
+    #[arg(long)]
+    ssh_option: Vec<String>,
+    /// Run the scan through `sudo -n`, for logins that are not root
+    #[arg(long)]
+    sudo: bool,
+    /// Hosts scanned at once
+    #[arg(long, default_value = "8")]
+    parallel: usize,
+    /// Upload each host's scan as a snapshot to the metadata service
+    #[arg(long)]
+    upload_snapshot: bool,
+    /// Print the differences per host as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+/// Parses the script's output into entries with paths relative to `root`.
+fn parse_records(output: &[u8], root: &str) -> Vec<FileIntegrityEntry> {
+    let root = root.trim_end_matches('/');
+    let mut records = output.split(|&b| b == 0);
+    let mut entries = Vec::new();
+    while let Some(record) = records.next() {
+        let Ok(record) = std::str::from_utf8(record) else { continue };
+        let mut fields = record.trim_start_matches('\n').splitn(3, '\t');
+        let Some(mut meta) = fields.next().map(|meta| meta.split(' ')) else { continue };
+        // A symlink's target follows its record
+        let (entry_type, symlink_target) = match meta.next() {
+            Some("f") => (EntryType::Regular, None),
+            Some("l") => match records.next().map(|target| String::from_utf8_lossy(target).into_owned()) {
+                Some(target) => (EntryType::Symlink, Some(target)),
+                None => continue,
+            },
+            _ => continue,
+        };
+        let entry = (|| {
+            let mode = u32::from_str_radix(meta.next()?, 8).ok()?;
+            let uid = meta.next()?.parse().ok()?;
+            let gid = meta.next()?.parse().ok()?;
+            let sha512 = fields.next()?.to_string();
+            let path = fields.next()?.strip_prefix(root)?.trim_start_matches('/').to_string();
+            (!path.is_empty()).then_some(FileIntegrityEntry {
+                path,
+                sha512,
+                mode,
+                uid,
+                gid,
+                entry_type: Some(entry_type),
+                symlink_target,
+                ..Default::default()
+            })
+        })();
+        entries.extend(entry);
+    }
+    entries
+}
+
+/// Host name of an ssh destination, without the user.
+fn host_name(destination: &str) -> &str {
+    destination.rsplit_once('@').map_or(destination, |(_, host)| host)
+}
+
+async fn scan_host(args: &RemoteScanArgs, destination: &str) -> Result<Vec<FileIntegrityEntry>> {
+    let mut ssh = Command::new("ssh");
+    ssh.args(["-o", "BatchMode=yes"]);
+    if let Some(identity) = &args.identity_file {
+        ssh.args(["-i", identity]);
+    }
+    for option in &args.ssh_option {
+        ssh.args(["-o", option]);
+    }
+    ssh.arg(destination);
+    if args.sudo {
+        ssh.args(["sudo", "-n"]);
+    }
+    // The remote shell parses the command line again
+    ssh.args(["sh", "-s", "--", &format!("'{}'", args.scan_path.replace('\'', r"'\''"))]);
+    let mut child = ssh
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("running ssh")?;
+    let mut stdin = child.stdin.take().context("ssh stdin")?;
+    stdin.write_all(SCAN_SCRIPT.as_bytes()).await?;
+    drop(stdin);
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!("ssh {} failed ({}): {}", destination, output.status, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    let entries = parse_records(&output.stdout, &args.scan_path);
+    if entries.is_empty() {
+        bail!("no files found under {} on {}", args.scan_path, destination);
+    }
+    Ok(entries)
+}
+
+pub async fn run(client: &MetadataClient, args: RemoteScanArgs) -> Result<()> {
+    let baseline = Arc::new(client.get_baseline(&args.image_id).await?);
+    let args = Arc::new(args);
+    let permits = Arc::new(tokio::sync::Semaphore::new(args.parallel.max(1)));
+    let mut scans = tokio::task::JoinSet::new();
+    for (i, destination) in args.hosts.iter().enumerate() {
+        let (args, permits, destination) = (args.clone(), permits.clone(), destination.clone());
+        scans.spawn(async move {
+            let _permit = permits.acquire().await;
+            (i, scan_host(&args, &destination).await)
+        });
+    }
+    let mut results = Vec::new();
+    while let Some(result) = scans.join_next().await {
+        results.push(result?);
+    }
+    results.sort_by_key(|(i, _)| *i);
+
+    let mut diffs: BTreeMap<&str, BaselineDiff> = BTreeMap::new();
+    let mut failed = 0;
+    for (i, result) in results {
+        let destination = &args.hosts[i];
+        let entries = match result {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("{}: {:#}", destination, e);
+                failed += 1;
+                continue;
+            }
+        };
+        let snapshot = ScanSnapshot {
+            host: host_name(destination).to_string(),
+            image_id: args.image_id.clone(),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            entries,
+        };
+        if args.upload_snapshot {
+            let info = client.upload_snapshot(&snapshot).await?;
+            eprintln!("{}: uploaded snapshot {} ({} files)", destination, info.id, info.entries);
+        }
+        let mut scanned: Baseline = snapshot.to_baseline();
+        scanned.image_id = baseline.image_id.clone();
+        diffs.insert(destination, baseline.diff(&scanned));
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diffs)?);
+    } else {
+        for (destination, diff) in &diffs {
+            println!("== {}", destination);
+            crate::print_diff(diff);
+        }
+    }
+    let drifted = diffs.values().filter(|d| !d.added.is_empty() || !d.removed.is_empty() || !d.modified.is_empty()).count();
+    if drifted > 0 || failed > 0 {
+        bail!("{} of {} hosts differ from {}, {} could not be scanned", drifted, args.hosts.len(), args.image_id, failed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_records() {
+        let output = b"f 755 0 0\tabc\t/srv/root/usr/bin/ls\0\nf 4755 0 0\tdef\t/srv/root/usr/bin/odd\tname\0garbage\0\
+            l 777 0 0\tfed\t/srv/root/lib\0usr/lib\0";
+        let entries = parse_records(output, "/srv/root/");
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].path, "usr/bin/ls");
+        assert_eq!(entries[1].mode, 0o4755);
+        assert_eq!(entries[1].path, "usr/bin/odd\tname");
+        assert_eq!(entries[1].entry_type, Some(EntryType::Regular));
+        assert!(entries[2].is_symlink());
+        assert_eq!((entries[2].path.as_str(), entries[2].symlink_target.as_deref()), ("lib", Some("usr/lib")));
+        assert_eq!(parse_records(b"f 644 0 0\tabc\t/etc/hosts\0", "/")[0].path, "etc/hosts");
+        assert_eq!(host_name("admin@fw-1.example.com"), "fw-1.example.com");
+    }
+}
@@ -0,0 +1,79 @@
+//! `integrity-ctl schedule`: lists the baseline versions staged to take
+//! effect later (uploaded with `baseline-collector --effective-from`) and
+//! withdraws them before they do.
+
+use anyhow::Result;
+use clap::Subcommand;
+use integrity_client::MetadataClient;
+
+#[derive(Subcommand, Debug)]
+pub enum ScheduleCommand {
+    /// List the versions of an image waiting to take effect, soonest first
+    List {
+        image_id: String,
+        /// Print the list as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Withdraw a version that has not taken effect yet
+    Cancel {
+        image_id: String,
+        /// When the version takes effect, as listed (Unix time or RFC 3339)
+        #[arg(value_parser = parse_time)]
+        effective_from: i64,
+    },
+}
+
+/// Unix seconds, or an RFC 3339 time such as `2026-11-02T03:00:00Z`.
+pub(crate) fn parse_time(value: &str) -> Result<i64, String> {
+    if let Ok(seconds) = value.parse() {
+        return Ok(seconds);
+    }
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|time| time.timestamp())
+        .map_err(|e| format!("expected Unix seconds or an RFC 3339 time: {}", e))
+}
+
+pub(crate) fn format_time(seconds: i64) -> String {
+    chrono::DateTime::from_timestamp(seconds, 0).map_or_else(|| seconds.to_string(), |time| time.to_rfc3339())
+}
+
+pub async fn run(client: &MetadataClient, command: ScheduleCommand) -> Result<()> {
+    match command {
+        ScheduleCommand::List { image_id, json } => {
+            let scheduled = client.scheduled_baselines(&image_id).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&scheduled)?);
+                return Ok(());
+            }
+            for version in &scheduled {
+                println!(
+                    "{:>12}  {}  collected {}  {} files",
+                    version.effective_from,
+                    format_time(version.effective_from),
+                    version.timestamp,
+                    version.entries
+                );
+            }
+            println!("{} scheduled versions", scheduled.len());
+        }
+        ScheduleCommand::Cancel { image_id, effective_from } => {
+            client.cancel_scheduled_baseline(&image_id, effective_from).await?;
+            println!("Cancelled the version of {} scheduled for {}", image_id, format_time(effective_from));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("1790000000"), Ok(1_790_000_000));
+        assert_eq!(parse_time("2026-11-02T03:00:00Z"), Ok(1_793_588_400));
+        assert_eq!(parse_time("2026-11-02T04:00:00+01:00"), Ok(1_793_588_400));
+        assert!(parse_time("next tuesday").is_err());
+    }
+}
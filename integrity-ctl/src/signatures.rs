@@ -0,0 +1,124 @@
+//! `integrity-ctl signatures`: generates signing keys, shows the signature
+//! chain of a baseline and countersigns it after review.
+
+use anyhow::{bail, Context, Result};
+use clap::Subcommand;
+use integrity_client::MetadataClient;
+use integrity_common::{countersignatures_start, verify_chain, BaselineSignature, BaselineSigner, SignerRole};
+use std::io::Read;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+#[derive(Subcommand, Debug)]
+pub enum SignatureCommand {
+    /// Generate an Ed25519 signing key and print its public key
+    Keygen {
+        /// File to write the private key to (hex, mode 0600)
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Show the signature chain of a baseline and whether it verifies
+    Show {
+        image_id: String,
+        /// Version to show [default: the baseline served now]
+        #[arg(long)]
+        digest: Option<String>,
+        /// Print the chain as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Countersign the baseline served now
+    Sign {
+        image_id: String,
+        /// Ed25519 private key (32 bytes, raw or hex)
+        #[arg(long)]
+        key_file: PathBuf,
+        /// Role to sign as
+        #[arg(long, default_value = "approver", value_parser = parse_role)]
+        role: SignerRole,
+        /// Refuse to sign unless the served baseline has this digest, e.g. the one reviewed
+        #[arg(long)]
+        digest: Option<String>,
+    },
+}
+
+/// Roles people sign as; the service adds its own countersignature.
+fn parse_role(value: &str) -> Result<SignerRole, String> {
+    match value.parse()? {
+        SignerRole::Service => Err("the service countersigns on its own".to_string()),
+        role => Ok(role),
+    }
+}
+
+fn format_time(seconds: i64) -> String {
+    chrono::DateTime::from_timestamp(seconds, 0).map_or_else(|| seconds.to_string(), |time| time.to_rfc3339())
+}
+
+/// Appends `signer`'s signature as `role` to the chain of the version of
+/// `image_id` with `digest`; returns the chain the service stored.
+pub(crate) async fn sign(client: &MetadataClient, image_id: &str, digest: &str, signer: &BaselineSigner, role: SignerRole) -> Result<Vec<BaselineSignature>> {
+    let mut chain = client.baseline_signatures(image_id, Some(digest)).await?;
+    chain.truncate(countersignatures_start(&chain));
+    verify_chain(&chain, digest).context("the stored chain does not verify")?;
+    let signature = signer.sign(role, digest, chrono::Utc::now().timestamp(), &chain);
+    Ok(client.sign_baseline(image_id, &signature).await?)
+}
+
+pub async fn run(client: &MetadataClient, command: SignatureCommand) -> Result<()> {
+    match command {
+        SignatureCommand::Keygen { output } => {
+            let mut key = [0u8; 32];
+            std::fs::File::open("/dev/urandom").and_then(|mut random| random.read_exact(&mut key)).context("reading /dev/urandom")?;
+            let signer = BaselineSigner::from_bytes(key);
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&output)
+                .and_then(|mut file| std::io::Write::write_all(&mut file, hex::encode(key).as_bytes()))
+                .with_context(|| format!("writing {:?}", output))?;
+            println!("{}", signer.public_key());
+        }
+        SignatureCommand::Show { image_id, digest, json } => {
+            let digest = match digest {
+                Some(digest) => digest,
+                None => client.get_baseline(&image_id).await?.digest()?,
+            };
+            let chain = client.baseline_signatures(&image_id, Some(&digest)).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&chain)?);
+                return Ok(());
+            }
+            println!("{} {}", image_id, digest);
+            for link in &chain {
+                println!("  {:<10} {}  {}", link.role, format_time(link.signed_at), link.public_key);
+            }
+            match verify_chain(&chain, &digest) {
+                Ok(()) => println!("{} signatures, chain verifies", chain.len()),
+                Err(e) => bail!("{} signatures, chain does not verify: {}", chain.len(), e),
+            }
+        }
+        SignatureCommand::Sign { image_id, key_file, role, digest: expected } => {
+            let signer = BaselineSigner::from_key_material(&std::fs::read(&key_file).with_context(|| format!("reading {:?}", key_file))?)?;
+            let digest = client.get_baseline(&image_id).await?.digest()?;
+            if let Some(expected) = expected.filter(|expected| !expected.eq_ignore_ascii_case(&digest)) {
+                bail!("{} is now {}, not the reviewed {}", image_id, digest, expected);
+            }
+            let chain = sign(client, &image_id, &digest, &signer, role).await?;
+            println!("Signed {} ({}) as {}; {} signatures", image_id, digest, role, chain.len());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_role() {
+        assert_eq!(parse_role("approver"), Ok(SignerRole::Approver));
+        assert!(parse_role("service").is_err());
+        assert!(parse_role("auditor").is_err());
+    }
+}
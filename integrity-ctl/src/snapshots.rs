@@ -0,0 +1,74 @@
+//! `integrity-ctl snapshots`: lists the scan snapshots hosts uploaded and
+//! diffs them against baselines.
+
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use integrity_client::MetadataClient;
+use std::path::PathBuf;
+
+#[derive(Subcommand, Debug)]
+pub enum SnapshotCommand {
+    /// List stored snapshots, newest first
+    List {
+        #[arg(long)]
+        host: Option<String>,
+        #[arg(long)]
+        image_id: Option<String>,
+        /// Print the list as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Fetch a snapshot and print it as JSON
+    Fetch {
+        id: u64,
+        /// Write to a file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Show what differs between a baseline and what a host had at a scan
+    Diff {
+        id: u64,
+        /// Baseline to compare with [default: the one the host was scanned against]
+        #[arg(long)]
+        against: Option<String>,
+        /// Print the diff as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+pub async fn run(client: &MetadataClient, command: SnapshotCommand) -> Result<()> {
+    match command {
+        SnapshotCommand::List { host, image_id, json } => {
+            let snapshots = client.list_snapshots(host.as_deref(), image_id.as_deref()).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&snapshots)?);
+                return Ok(());
+            }
+            for snapshot in &snapshots {
+                println!(
+                    "{:>6}  {}  {:<16} {:<24} {} files",
+                    snapshot.id, snapshot.timestamp, snapshot.host, snapshot.image_id, snapshot.entries
+                );
+            }
+            println!("{} snapshots", snapshots.len());
+        }
+        SnapshotCommand::Fetch { id, output } => {
+            let snapshot = client.get_snapshot(id).await?;
+            let json = serde_json::to_string_pretty(&snapshot)?;
+            match output {
+                Some(path) => std::fs::write(&path, json).with_context(|| format!("writing {:?}", path))?,
+                None => println!("{}", json),
+            }
+        }
+        SnapshotCommand::Diff { id, against, json } => {
+            let diff = client.diff_snapshot(id, against.as_deref()).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&diff)?);
+            } else {
+                crate::print_diff(&diff);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,321 @@
+//! Filesystem scanning shared by baseline-collector and integrity-agent.
+//!
+//! A [`Scanner`] walks a tree and records a [`FileIntegrityEntry`] for every
+//! file the [`ScanConfig`] does not exclude: its digests in the configured
+//! [`HashAlgorithm`]s (through a [`FileHasher`] such as the agent's digest
+//! cache, or read directly in a single pass), stat
+//! metadata, symlink target and extended attributes. Baselines and the scans
+//! checked against them are built by the same code, so they cannot record a
+//! file differently.
+
+mod metadata;
+
+pub use metadata::{file_meta, is_special_file, FileMeta};
+
+use integrity_common::{
+    ContentPolicy, EntryType, FileIntegrityEntry, HashAlgorithm, IntegrityError, Result, ScanConfig, read_xattrs,
+};
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::BTreeMap;
+use std::fs::{self, Metadata};
+use std::io::Read;
+use std::path::Path;
+use tracing::{info, warn};
+use walkdir::{DirEntry, WalkDir};
+
+/// Progress is reported every this many files.
+pub const PROGRESS_INTERVAL: usize = 1000;
+
+enum ContentHasher {
+    Sha512(Sha512),
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl ContentHasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha512 => ContentHasher::Sha512(Sha512::new()),
+            HashAlgorithm::Sha256 => ContentHasher::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => ContentHasher::Blake3(Box::default()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            ContentHasher::Sha512(hasher) => hasher.update(data),
+            ContentHasher::Sha256(hasher) => hasher.update(data),
+            ContentHasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    fn finalize(self) -> String {
+        match self {
+            ContentHasher::Sha512(hasher) => hex::encode(hasher.finalize()),
+            ContentHasher::Sha256(hasher) => hex::encode(hasher.finalize()),
+            ContentHasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+/// Digests of the file at `path` in each of `algorithms`, in order, read once.
+pub fn compute_digests(path: &Path, algorithms: &[HashAlgorithm]) -> Result<Vec<String>> {
+    let mut hashers: Vec<_> = algorithms.iter().map(|algorithm| ContentHasher::new(*algorithm)).collect();
+    let mut file = fs::File::open(path)?;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        for hasher in &mut hashers {
+            hasher.update(&buffer[..read]);
+        }
+    }
+    Ok(hashers.into_iter().map(ContentHasher::finalize).collect())
+}
+
+pub fn compute_digest(path: &Path, algorithm: HashAlgorithm) -> Result<String> {
+    Ok(compute_digests(path, &[algorithm])?.remove(0))
+}
+
+pub fn compute_sha512(path: &Path) -> Result<String> {
+    compute_digest(path, HashAlgorithm::Sha512)
+}
+
+/// Digest of `data` in `algorithm`.
+pub fn digest_bytes(data: &[u8], algorithm: HashAlgorithm) -> String {
+    let mut hasher = ContentHasher::new(algorithm);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+pub fn symlink_target(path: &Path) -> Result<String> {
+    Ok(fs::read_link(path)?.to_string_lossy().to_string())
+}
+
+/// Where a scanner gets the digests of regular files from.
+pub trait FileHasher {
+    /// Digest in `algorithm` of the file at `path`, whose (followed) `metadata`
+    /// the caller already has.
+    fn digest(&self, path: &Path, metadata: &Metadata, algorithm: HashAlgorithm) -> Result<String>;
+}
+
+fn relative_path(path: &Path, root_path: &Path) -> String {
+    path.strip_prefix(root_path).unwrap_or(path).to_string_lossy().to_string()
+}
+
+/// Records the files of a tree.
+pub struct Scanner<'a> {
+    config: &'a ScanConfig,
+    algorithms: Vec<HashAlgorithm>,
+    hasher: Option<&'a dyn FileHasher>,
+    progress: Option<Box<dyn Fn(usize) + 'a>>,
+}
+
+impl<'a> Scanner<'a> {
+    pub fn new(config: &'a ScanConfig) -> Self {
+        Self { config, algorithms: vec![HashAlgorithm::Sha512], hasher: None, progress: None }
+    }
+
+    /// Records digests in `algorithms` rather than SHA-512 only.
+    pub fn with_algorithms(mut self, algorithms: &[HashAlgorithm]) -> Self {
+        self.algorithms.clear();
+        for algorithm in algorithms {
+            if !self.algorithms.contains(algorithm) {
+                self.algorithms.push(*algorithm);
+            }
+        }
+        self
+    }
+
+    /// Hashes files through `hasher` instead of reading each one.
+    pub fn with_hasher(mut self, hasher: &'a dyn FileHasher) -> Self {
+        self.hasher = Some(hasher);
+        self
+    }
+
+    /// Calls `progress` with the number of files recorded so far, every
+    /// [`PROGRESS_INTERVAL`] files.
+    pub fn on_progress(mut self, progress: impl Fn(usize) + 'a) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    fn should_exclude(&self, entry: &DirEntry, root_path: &Path) -> bool {
+        let relative_path = relative_path(entry.path(), root_path);
+
+        // Skip excluded directories and everything below them
+        if entry.file_type().is_dir() {
+            return self.config.prunes(&relative_path);
+        }
+
+        // Skip special files (devices, sockets, etc.)
+        if let Ok(metadata) = entry.metadata() {
+            if is_special_file(&metadata) {
+                return true;
+            }
+        }
+
+        self.config.is_excluded(&relative_path)
+    }
+
+    /// The digests recorded for a file and, for a symlink, its target. A symlink
+    /// is recorded as itself: its digests are of the target, which need not
+    /// exist. A file recorded metadata-only has no digests.
+    fn file_content(&self, path: &Path, metadata: &Metadata, content: ContentPolicy) -> Result<(Vec<String>, Option<String>)> {
+        if metadata.file_type().is_symlink() {
+            let target = symlink_target(path)?;
+            let digests = self.algorithms.iter().map(|algorithm| digest_bytes(target.as_bytes(), *algorithm)).collect();
+            return Ok((digests, Some(target)));
+        }
+        if content == ContentPolicy::MetadataOnly {
+            return Ok((Vec::new(), None));
+        }
+        let digests = match self.hasher {
+            Some(hasher) => self
+                .algorithms
+                .iter()
+                .map(|algorithm| hasher.digest(path, metadata, *algorithm))
+                .collect::<Result<_>>()?,
+            None => compute_digests(path, &self.algorithms)?,
+        };
+        Ok((digests, None))
+    }
+
+    /// The entry recorded for the file at `path`, `relative_path` below the
+    /// scanned root, whose (not followed) `metadata` the caller already has.
+    pub fn entry(&self, path: &Path, relative_path: String, metadata: &Metadata) -> Result<FileIntegrityEntry> {
+        let (digests, symlink_target) = self.file_content(path, metadata, self.config.content_policy(&relative_path))?;
+        let meta = file_meta(metadata);
+        let entry_type = if symlink_target.is_some() { EntryType::Symlink } else { EntryType::Regular };
+        // Metadata-only files change content, so size and mtime are not recorded either
+        let hashed = !digests.is_empty();
+        let mut digests: BTreeMap<_, _> = self.algorithms.iter().copied().zip(digests).collect();
+        let sha512 = digests.remove(&HashAlgorithm::Sha512).unwrap_or_default();
+        Ok(FileIntegrityEntry {
+            path: relative_path,
+            sha512,
+            mode: meta.mode,
+            uid: meta.uid,
+            gid: meta.gid,
+            size: hashed.then_some(meta.size),
+            mtime: hashed.then_some(meta.mtime),
+            nlink: meta.nlink,
+            entry_type: Some(entry_type),
+            symlink_target,
+            xattrs: read_xattrs(path).ok(),
+            digests,
+        })
+    }
+
+    /// Records every file below `root_path`, by path relative to it.
+    pub fn scan(&self, root_path: &Path) -> Result<Vec<FileIntegrityEntry>> {
+        self.scan_subtree(root_path, root_path)
+    }
+
+    /// Records the files of the tree at `start`, by path relative to
+    /// `root_path`, which the scan config matches them by.
+    pub fn scan_subtree(&self, root_path: &Path, start: &Path) -> Result<Vec<FileIntegrityEntry>> {
+        info!("Starting filesystem scan from: {:?}", start);
+
+        let mut entries = Vec::new();
+        let walker = WalkDir::new(start)
+            .follow_links(false)
+            .into_iter()
+            .filter_entry(|e| !self.should_exclude(e, root_path));
+
+        for entry in walker {
+            let entry = entry.map_err(|e| IntegrityError::Walkdir(e.to_string()))?;
+            let path = entry.path();
+
+            // Skip directories, not the symlinks to them
+            if entry.file_type().is_dir() {
+                continue;
+            }
+
+            // Get relative path from root
+            let relative_path = relative_path(path, root_path);
+
+            // Skip if path is empty (shouldn't happen, but safety check)
+            if relative_path.is_empty() {
+                continue;
+            }
+
+            match entry.metadata() {
+                Ok(metadata) => match self.entry(path, relative_path, &metadata) {
+                    Ok(file_entry) => {
+                        entries.push(file_entry);
+                        if let Some(progress) = self.progress.as_ref().filter(|_| entries.len().is_multiple_of(PROGRESS_INTERVAL)) {
+                            progress(entries.len());
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Failed to hash file {:?}: {}", path, e);
+                    }
+                },
+                Err(e) => {
+                    warn!("Failed to get metadata for {:?}: {}", path, e);
+                }
+            }
+        }
+
+        info!("Scan complete. Found {} files", entries.len());
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    struct FixedHasher;
+
+    impl FileHasher for FixedHasher {
+        fn digest(&self, _path: &Path, _metadata: &Metadata, algorithm: HashAlgorithm) -> Result<String> {
+            Ok("f".repeat(algorithm.hex_len()))
+        }
+    }
+
+    #[test]
+    fn test_scanner() {
+        let root = std::env::temp_dir().join(format!("acropole-scanner-{}", std::process::id()));
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::create_dir_all(root.join("proc/1")).unwrap();
+        fs::create_dir_all(root.join("var/lib/db")).unwrap();
+        for i in 0..PROGRESS_INTERVAL {
+            fs::write(root.join("etc").join(i.to_string()), i.to_string()).unwrap();
+        }
+        fs::write(root.join("proc/1/status"), "running").unwrap();
+        fs::write(root.join("var/lib/db/data"), "rows").unwrap();
+        let config = ScanConfig::parse("exclude = [\"proc\"]\n[[policy]]\npaths = [\"var/**\"]\ncontent = \"metadata-only\"\n").unwrap();
+
+        let (reports, reported) = (Cell::new(0), Cell::new(0));
+        let scanner = Scanner::new(&config).on_progress(|files| {
+            reports.set(reports.get() + 1);
+            reported.set(files);
+        });
+        let entries = scanner.scan(&root).unwrap();
+        assert_eq!(entries.len(), PROGRESS_INTERVAL + 1);
+        assert_eq!((reports.get(), reported.get()), (1, PROGRESS_INTERVAL));
+        let zero = entries.iter().find(|e| e.path == "etc/0").unwrap();
+        assert_eq!(zero.sha512, hex::encode(Sha512::digest(b"0")));
+        assert_eq!((zero.size, zero.entry_type), (Some(1), Some(EntryType::Regular)));
+        let data = entries.iter().find(|e| e.path == "var/lib/db/data").unwrap();
+        assert!(!data.is_hashed());
+
+        let hashed = Scanner::new(&config).with_hasher(&FixedHasher).scan_subtree(&root, &root.join("etc")).unwrap();
+        assert!(hashed.iter().all(|e| e.path.starts_with("etc/") && e.sha512 == "f".repeat(128)));
+
+        let both = Scanner::new(&config).with_algorithms(&[HashAlgorithm::Blake3, HashAlgorithm::Sha256]);
+        let entries = both.scan_subtree(&root, &root.join("etc")).unwrap();
+        let zero = entries.iter().find(|e| e.path == "etc/0").unwrap();
+        assert!(zero.sha512.is_empty());
+        assert_eq!(zero.digest(HashAlgorithm::Blake3), Some(blake3::hash(b"0").to_hex().as_str()));
+        assert_eq!(zero.digest(HashAlgorithm::Sha256), Some(hex::encode(Sha256::digest(b"0")).as_str()));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+//! Platform-specific file metadata recorded in scans and compared in verification.
+
+use std::fs::Metadata;
+
+/// Permission, ownership and stat metadata compared against the baseline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileMeta {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    /// Seconds since the Unix epoch
+    pub mtime: i64,
+    /// None where the link count is not available
+    pub nlink: Option<u64>,
+}
+
+#[cfg(unix)]
+pub fn file_meta(metadata: &Metadata) -> FileMeta {
+    use std::os::unix::fs::MetadataExt;
+    FileMeta {
+        mode: metadata.mode() & 0o7777, // Permission bits only
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        size: metadata.size(),
+        mtime: metadata.mtime(),
+        nlink: Some(metadata.nlink()),
+    }
+}
+
+/// Windows has no mode bits or numeric owners. The attribute bits an attacker
+/// would flip to hide or protect a file (read-only, hidden, system) are recorded
+/// in `mode`; `uid`/`gid` stay 0. The link count needs an open handle, so it
+/// is not recorded.
+#[cfg(windows)]
+pub fn file_meta(metadata: &Metadata) -> FileMeta {
+    use std::os::windows::fs::MetadataExt;
+    use windows_sys::Win32::Storage::FileSystem::{
+        FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_READONLY, FILE_ATTRIBUTE_SYSTEM,
+    };
+    FileMeta {
+        mode: metadata.file_attributes()
+            & (FILE_ATTRIBUTE_READONLY | FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM),
+        uid: 0,
+        gid: 0,
+        size: metadata.len(),
+        mtime: metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_secs() as i64),
+        nlink: None,
+    }
+}
+
+/// Devices, sockets, FIFOs and similar files that are never hashed.
+#[cfg(unix)]
+pub fn is_special_file(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    let file_type = metadata.file_type();
+    file_type.is_block_device() || file_type.is_char_device() || file_type.is_fifo() || file_type.is_socket()
+}
+
+#[cfg(windows)]
+pub fn is_special_file(metadata: &Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    use windows_sys::Win32::Storage::FileSystem::{FILE_ATTRIBUTE_DEVICE, FILE_ATTRIBUTE_OFFLINE};
+    metadata.file_attributes() & (FILE_ATTRIBUTE_DEVICE | FILE_ATTRIBUTE_OFFLINE) != 0
+}
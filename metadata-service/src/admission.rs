@@ -0,0 +1,234 @@
+//! Kubernetes validating admission webhook.
+//!
+//! Receives `AdmissionReview` requests for pods and checks that every container
+//! image is pinned by digest and has a stored baseline. Pods are also checked
+//! against the latest integrity report of their node: the node must have a
+//! registered agent that still sends heartbeats, and no open anomalies.
+//! Pods are usually created before they are scheduled, so the node is checked
+//! when the scheduler binds the pod (`pods/binding`), or at creation for pods
+//! naming their node. Depending on the configured mode, non-compliant pods
+//! are rejected (a refused binding leaves the pod pending) or admitted with
+//! warnings.
+
+use crate::AppState;
+use actix_web::{web, HttpResponse, Responder};
+use integrity_common::{AgentRecord, TriageState};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum AdmissionMode {
+    /// Reject non-compliant pods
+    Enforce,
+    /// Admit non-compliant pods with warnings
+    Warn,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdmissionReview {
+    request: Option<AdmissionRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AdmissionRequest {
+    uid: String,
+    #[serde(default)]
+    object: Value,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct PodSpec {
+    #[serde(default)]
+    node_name: Option<String>,
+    #[serde(default)]
+    containers: Vec<Container>,
+    #[serde(default)]
+    init_containers: Vec<Container>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Container {
+    name: String,
+    #[serde(default)]
+    image: String,
+}
+
+#[derive(Debug, Serialize)]
+struct AdmissionResponse {
+    uid: String,
+    allowed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+}
+
+/// Returns the digest part of an image reference ("repo@sha256:..." -> "sha256:...").
+fn image_digest(image: &str) -> Option<&str> {
+    image.split_once('@').map(|(_, digest)| digest)
+}
+
+/// Collects compliance problems for a pod spec.
+/// `has_baseline` answers whether a baseline exists for an image id.
+fn review_pod(spec: &PodSpec, has_baseline: impl Fn(&str) -> bool) -> Vec<String> {
+    let mut problems = Vec::new();
+    for container in spec.init_containers.iter().chain(&spec.containers) {
+        match image_digest(&container.image) {
+            None => problems.push(format!(
+                "container {}: image {} is not pinned by digest",
+                container.name, container.image
+            )),
+            Some(digest) if !has_baseline(digest) && !has_baseline(&container.image) => {
+                problems.push(format!(
+                    "container {}: no approved baseline for image {}",
+                    container.name, container.image
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+    problems
+}
+
+/// What the service knows of a node's integrity.
+struct NodeStatus {
+    agent: Option<AgentRecord>,
+    open_anomalies: usize,
+}
+
+/// Collects the reasons not to run pods on `node`: no agent, an agent that
+/// stopped reporting, or open anomalies.
+fn review_node(node: &str, status: &NodeStatus) -> Vec<String> {
+    let mut problems = Vec::new();
+    match &status.agent {
+        None => problems.push(format!("node {}: no integrity agent registered", node)),
+        Some(agent) if agent.stale => problems.push(format!(
+            "node {}: integrity agent not reporting since {}",
+            node,
+            chrono::DateTime::from_timestamp(agent.last_seen, 0).map_or_else(|| agent.last_seen.to_string(), |time| time.to_rfc3339())
+        )),
+        Some(_) => {}
+    }
+    if status.open_anomalies > 0 {
+        problems.push(format!("node {}: {} open integrity anomalies", node, status.open_anomalies));
+    }
+    problems
+}
+
+fn node_status(data: &AppState, node: &str) -> actix_web::Result<NodeStatus> {
+    let now = chrono::Utc::now().timestamp();
+    Ok(NodeStatus {
+        agent: crate::agents::registered(&data.db, node, now, data.agent_stale_after_secs)?,
+        open_anomalies: crate::anomalies::records(&data.db, |record| record.host == node && record.state(now) == TriageState::Open)?.len(),
+    })
+}
+
+pub async fn validate(review: web::Json<AdmissionReview>, data: web::Data<AppState>) -> impl Responder {
+    let Some(request) = review.into_inner().request else {
+        return HttpResponse::BadRequest().body("AdmissionReview has no request");
+    };
+
+    // A binding of a pod to a node carries no pod spec
+    let binding_target = match request.object.get("kind").and_then(Value::as_str) {
+        Some("Binding") => Some(request.object.pointer("/target/name").and_then(Value::as_str).unwrap_or_default().to_string()),
+        _ => None,
+    };
+    let spec: PodSpec = request
+        .object
+        .get("spec")
+        .cloned()
+        .and_then(|spec| serde_json::from_value(spec).ok())
+        .unwrap_or_default();
+
+    let mut problems = review_pod(&spec, |image_id| {
+        crate::inheritance::baseline_exists(&data.db, image_id)
+    });
+    let node = binding_target.or(spec.node_name).filter(|node| !node.is_empty());
+    if let Some(node) = &node {
+        match node_status(&data, node) {
+            Ok(status) => problems.extend(review_node(node, &status)),
+            Err(e) => problems.push(format!("node {}: integrity status unavailable: {}", node, e)),
+        }
+    }
+
+    let node = node.as_deref().unwrap_or("<unscheduled>");
+    let response = if problems.is_empty() {
+        info!("Admission {}: pod on {} is compliant", request.uid, node);
+        AdmissionResponse {
+            uid: request.uid,
+            allowed: true,
+            status: None,
+            warnings: Vec::new(),
+        }
+    } else if data.admission_mode == AdmissionMode::Warn {
+        warn!("Admission {}: admitting non-compliant pod on {}: {:?}", request.uid, node, problems);
+        AdmissionResponse {
+            uid: request.uid,
+            allowed: true,
+            status: None,
+            warnings: problems,
+        }
+    } else {
+        warn!("Admission {}: rejecting pod on {}: {:?}", request.uid, node, problems);
+        AdmissionResponse {
+            uid: request.uid,
+            allowed: false,
+            status: Some(json!({ "code": 403, "message": problems.join("; ") })),
+            warnings: Vec::new(),
+        }
+    };
+
+    HttpResponse::Ok().json(json!({
+        "apiVersion": "admission.k8s.io/v1",
+        "kind": "AdmissionReview",
+        "response": response,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(name: &str, image: &str) -> Container {
+        Container {
+            name: name.to_string(),
+            image: image.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_review_pod() {
+        let spec = PodSpec {
+            node_name: Some("node-1".to_string()),
+            containers: vec![
+                container("app", "registry/app@sha256:aaa"),
+                container("sidecar", "registry/sidecar:latest"),
+            ],
+            init_containers: vec![container("init", "registry/init@sha256:bbb")],
+        };
+        let problems = review_pod(&spec, |id| id == "sha256:aaa");
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].contains("init"));
+        assert!(problems[1].contains("not pinned"));
+    }
+
+    #[test]
+    fn test_review_node() {
+        let agent = |stale: bool| AgentRecord {
+            host: "node-1".to_string(),
+            image_id: "node-image-v1".to_string(),
+            agent_version: "0.1.0".to_string(),
+            mode: "Monitor".to_string(),
+            registered_at: 1000,
+            last_seen: 1000,
+            last_scan: None,
+            stale,
+        };
+        assert!(review_node("node-1", &NodeStatus { agent: Some(agent(false)), open_anomalies: 0 }).is_empty());
+        assert!(review_node("node-1", &NodeStatus { agent: None, open_anomalies: 0 })[0].contains("no integrity agent"));
+        assert!(review_node("node-1", &NodeStatus { agent: Some(agent(true)), open_anomalies: 0 })[0].contains("not reporting"));
+        assert_eq!(review_node("node-1", &NodeStatus { agent: Some(agent(false)), open_anomalies: 2 }), ["node node-1: 2 open integrity anomalies"]);
+    }
+}
@@ -0,0 +1,210 @@
+//! Agent configuration, per image and per host.
+//!
+//! Each scope (`image/{image_id}` or `host/{name}`) is one versioned history
+//! in its own sled tree (see `integrity_common::AgentConfigHistory`).
+//! Operators `PUT /agent-config/{scope}/{name}` new settings and
+//! `POST .../rollback` to an earlier version; agents poll
+//! `GET /agent-config/effective?host=&image_id=` for the merged settings
+//! they apply. Since every agent applies them, changes take the admin token
+//! (or, with LDAP, an operator login) and are disabled without one.
+
+use crate::anomalies::actor;
+use crate::{catalog, AppState};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use integrity_common::{AgentConfigHistory, AgentConfigRollback, AgentConfigUpdate, EffectiveAgentConfig, HOST_SCOPE, IMAGE_SCOPE};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+const AGENT_CONFIG_TREE: &str = "agent_configs";
+
+fn key(scope: &str, name: &str) -> String {
+    format!("{}:{}", scope, name)
+}
+
+fn check_scope(scope: &str) -> actix_web::Result<()> {
+    if scope == IMAGE_SCOPE || scope == HOST_SCOPE {
+        Ok(())
+    } else {
+        Err(actix_web::error::ErrorNotFound(format!("No configuration scope {}, expected image or host", scope)))
+    }
+}
+
+fn load(tree: &sled::Tree, scope: &str, name: &str) -> actix_web::Result<Option<AgentConfigHistory>> {
+    let Some(value) = tree.get(key(scope, name).as_bytes()).map_err(actix_web::error::ErrorInternalServerError)? else {
+        return Ok(None);
+    };
+    serde_json::from_slice(&value).map(Some).map_err(actix_web::error::ErrorInternalServerError)
+}
+
+/// Applies `change` to the history of a scope, retrying if a concurrent
+/// change got there first. `change` returning None leaves it as it is.
+fn update(
+    tree: &sled::Tree,
+    scope: &str,
+    name: &str,
+    mut change: impl FnMut(&mut AgentConfigHistory) -> Option<u64>,
+) -> actix_web::Result<Option<(AgentConfigHistory, u64)>> {
+    let key = key(scope, name);
+    loop {
+        let old = tree.get(key.as_bytes()).map_err(actix_web::error::ErrorInternalServerError)?;
+        let mut history = match &old {
+            Some(value) => serde_json::from_slice(value).map_err(actix_web::error::ErrorInternalServerError)?,
+            None => AgentConfigHistory::new(scope, name),
+        };
+        let Some(version) = change(&mut history) else {
+            return Ok(None);
+        };
+        let new = serde_json::to_vec(&history).map_err(actix_web::error::ErrorInternalServerError)?;
+        let swapped = tree.compare_and_swap(key.as_bytes(), old, Some(new)).map_err(actix_web::error::ErrorInternalServerError)?;
+        if swapped.is_ok() {
+            return Ok(Some((history, version)));
+        }
+    }
+}
+
+/// Who is changing agent settings: the login, or "admin" for the admin
+/// token. Err is the response refusing the change.
+fn author(req: &HttpRequest, data: &AppState) -> Result<String, HttpResponse> {
+    match catalog::refusal(req, data.admin_token.as_deref(), "Changing agent settings") {
+        Some(refusal) => Err(refusal),
+        None => Ok(actor(req, Some("admin")).expect("requested actor is not empty")),
+    }
+}
+
+/// Stores new settings for an image or host.
+pub async fn put(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    update_request: web::Json<AgentConfigUpdate>,
+    data: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let (scope, name) = path.into_inner();
+    check_scope(&scope)?;
+    let request = update_request.into_inner();
+    let actor = match author(&req, &data) {
+        Ok(actor) => actor,
+        Err(refusal) => {
+            warn!("Refused to change agent settings of {} {} ({})", scope, name, refusal.status());
+            return Ok(refusal);
+        }
+    };
+    let now = chrono::Utc::now().timestamp();
+
+    let tree = data.db.open_tree(AGENT_CONFIG_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    let (history, version) = update(&tree, &scope, &name, |history| {
+        Some(history.push(request.settings.clone(), &actor, request.comment.clone(), now))
+    })?
+    .expect("always changed");
+    data.db.flush_async().await.map_err(actix_web::error::ErrorInternalServerError)?;
+    info!("{} stored agent configuration version {} of {} {}", actor, version, scope, name);
+    Ok(HttpResponse::Ok().json(history))
+}
+
+/// Makes an earlier version of an image's or host's settings current again.
+pub async fn rollback(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    rollback_request: web::Json<AgentConfigRollback>,
+    data: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let (scope, name) = path.into_inner();
+    check_scope(&scope)?;
+    let request = rollback_request.into_inner();
+    let actor = match author(&req, &data) {
+        Ok(actor) => actor,
+        Err(refusal) => {
+            warn!("Refused to roll back agent settings of {} {} ({})", scope, name, refusal.status());
+            return Ok(refusal);
+        }
+    };
+    let now = chrono::Utc::now().timestamp();
+
+    let tree = data.db.open_tree(AGENT_CONFIG_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    let Some((history, version)) = update(&tree, &scope, &name, |history| {
+        history.rollback(request.version, &actor, request.comment.clone(), now)
+    })?
+    else {
+        return Err(actix_web::error::ErrorNotFound(format!("No version {} of {} {}", request.version, scope, name)));
+    };
+    data.db.flush_async().await.map_err(actix_web::error::ErrorInternalServerError)?;
+    info!("{} rolled {} {} back to version {} as version {}", actor, scope, name, request.version, version);
+    Ok(HttpResponse::Ok().json(history))
+}
+
+/// The version history of an image's or host's settings.
+pub async fn get(path: web::Path<(String, String)>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let (scope, name) = path.into_inner();
+    check_scope(&scope)?;
+    let tree = data.db.open_tree(AGENT_CONFIG_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    let history = load(&tree, &scope, &name)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("No agent configuration for {} {}", scope, name)))?;
+    Ok(HttpResponse::Ok().json(history))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EffectiveQuery {
+    host: String,
+    image_id: String,
+}
+
+/// The settings an agent on `host` verifying `image_id` applies; empty if none are stored.
+pub async fn effective(query: web::Query<EffectiveQuery>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let tree = data.db.open_tree(AGENT_CONFIG_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    let image = load(&tree, IMAGE_SCOPE, &query.image_id)?;
+    let host = load(&tree, HOST_SCOPE, &query.host)?;
+    let config = EffectiveAgentConfig::merge(image.as_ref().and_then(|h| h.current()), host.as_ref().and_then(|h| h.current()));
+    Ok(HttpResponse::Ok().json(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::{http::header, App};
+
+    #[actix_rt::test]
+    async fn test_changes_take_the_admin_token() {
+        let request = |authorization: Option<&'static str>| {
+            let mut request = TestRequest::put()
+                .uri("/agent-config/image/app-v1")
+                .set_json(serde_json::json!({ "settings": { "watch-paths": "/tmp/empty" } }));
+            if let Some(authorization) = authorization {
+                request = request.insert_header((header::AUTHORIZATION, authorization));
+            }
+            request.to_request()
+        };
+        for (admin_token, authorization, status) in
+            [(None, Some("Bearer s3cret"), 403), (Some("s3cret"), None, 401), (Some("s3cret"), Some("Bearer s3cret"), 200)]
+        {
+            let app = init_service(
+                App::new()
+                    .app_data(web::Data::new(AppState::for_tests(admin_token)))
+                    .route("/agent-config/{scope}/{name}", web::put().to(put)),
+            )
+            .await;
+            let response = call_service(&app, request(authorization)).await;
+            assert_eq!(response.status().as_u16(), status, "{:?} {:?}", admin_token, authorization);
+            if status == 200 {
+                let history: AgentConfigHistory = read_body_json(response).await;
+                assert_eq!(history.current().unwrap().author, "admin");
+            }
+        }
+    }
+
+    #[test]
+    fn test_update_versions_scope() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree(AGENT_CONFIG_TREE).unwrap();
+        let settings = |paths: &str| [("watch-paths".to_string(), paths.to_string())].into_iter().collect();
+
+        update(&tree, IMAGE_SCOPE, "app-v1", |history| Some(history.push(settings("/etc"), "alice", None, 1))).unwrap();
+        let (history, version) =
+            update(&tree, IMAGE_SCOPE, "app-v1", |history| Some(history.push(settings("/usr"), "alice", None, 2))).unwrap().unwrap();
+        assert_eq!((version, history.versions.len()), (2, 2));
+        assert!(update(&tree, IMAGE_SCOPE, "app-v1", |history| history.rollback(9, "bob", None, 3)).unwrap().is_none());
+
+        assert_eq!(load(&tree, IMAGE_SCOPE, "app-v1").unwrap().unwrap(), history);
+        assert!(load(&tree, HOST_SCOPE, "app-v1").unwrap().is_none());
+        assert!(check_scope("cluster").is_err());
+    }
+}
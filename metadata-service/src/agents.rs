@@ -0,0 +1,153 @@
+//! Registry of the agents reporting to the service.
+//!
+//! Agents `POST /agents` a registration (host, image_id, agent version and
+//! mode) when they start and `POST /agents/{host}/heartbeat` every interval
+//! after, with how their last scan ended. A heartbeat from a host that is not
+//! registered answers 404, so its agent registers again. `GET /agents` lists
+//! the agents by host with the time each was last seen, flagging as `stale`
+//! those without a heartbeat for `--agent-stale-after-secs`, so hosts whose
+//! agent died stand out; `?stale=true` lists only those.
+
+use crate::AppState;
+use actix_web::{web, HttpResponse, Responder};
+use integrity_common::{AgentHeartbeat, AgentRecord, AgentRegistration};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+const AGENTS_TREE: &str = "agents";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct AgentArgs {
+    /// Seconds without a heartbeat after which an agent is listed as stale
+    #[arg(long, default_value = "300")]
+    pub agent_stale_after_secs: u64,
+}
+
+fn internal<E: std::fmt::Debug + std::fmt::Display + 'static>(e: E) -> actix_web::Error {
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+fn load(tree: &sled::Tree, host: &str) -> actix_web::Result<Option<AgentRecord>> {
+    match tree.get(host.as_bytes()).map_err(internal)? {
+        Some(stored) => Ok(Some(serde_json::from_slice(&stored).map_err(internal)?)),
+        None => Ok(None),
+    }
+}
+
+async fn store(data: &AppState, tree: &sled::Tree, record: &AgentRecord) -> actix_web::Result<()> {
+    tree.insert(record.host.as_bytes(), serde_json::to_vec(record).map_err(internal)?).map_err(internal)?;
+    data.db.flush_async().await.map_err(internal)?;
+    Ok(())
+}
+
+pub async fn register(registration: web::Json<AgentRegistration>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let registration = registration.into_inner();
+    if registration.host.trim().is_empty() {
+        return Ok(HttpResponse::BadRequest().body("host is required"));
+    }
+    let tree = data.db.open_tree(AGENTS_TREE).map_err(internal)?;
+    let previous = load(&tree, &registration.host)?;
+    let record = AgentRecord::register(registration, chrono::Utc::now().timestamp(), previous);
+    store(&data, &tree, &record).await?;
+
+    info!("Agent {} {} registered on {} ({} mode)", record.agent_version, record.image_id, record.host, record.mode);
+    Ok(HttpResponse::Created().json(record))
+}
+
+pub async fn heartbeat(
+    host: web::Path<String>,
+    heartbeat: web::Json<AgentHeartbeat>,
+    data: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let host = host.into_inner();
+    let tree = data.db.open_tree(AGENTS_TREE).map_err(internal)?;
+    let Some(mut record) = load(&tree, &host)? else {
+        warn!("Heartbeat from unregistered agent on {}", host);
+        return Ok(HttpResponse::NotFound().body(format!("No agent registered on {}", host)));
+    };
+    record.heartbeat(heartbeat.into_inner(), chrono::Utc::now().timestamp());
+    store(&data, &tree, &record).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    stale: Option<bool>,
+    image_id: Option<String>,
+}
+
+fn set_stale(record: &mut AgentRecord, now: i64, stale_after: u64) {
+    record.stale = now.saturating_sub(record.last_seen) > stale_after as i64;
+}
+
+/// Every record, by host, with `stale` set as of `now`.
+fn records(tree: &sled::Tree, now: i64, stale_after: u64) -> actix_web::Result<Vec<AgentRecord>> {
+    let mut records = Vec::new();
+    for item in tree.iter() {
+        let (_, value) = item.map_err(internal)?;
+        let mut record: AgentRecord = serde_json::from_slice(&value).map_err(internal)?;
+        set_stale(&mut record, now, stale_after);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Every registered agent, by host, with `stale` set as of `now`.
+pub(crate) fn all(db: &sled::Db, now: i64, stale_after: u64) -> actix_web::Result<Vec<AgentRecord>> {
+    records(&db.open_tree(AGENTS_TREE).map_err(internal)?, now, stale_after)
+}
+
+/// The agent registered on `host`, with `stale` set as of `now`.
+pub(crate) fn registered(db: &sled::Db, host: &str, now: i64, stale_after: u64) -> actix_web::Result<Option<AgentRecord>> {
+    let tree = db.open_tree(AGENTS_TREE).map_err(internal)?;
+    let mut record = load(&tree, host)?;
+    if let Some(record) = &mut record {
+        set_stale(record, now, stale_after);
+    }
+    Ok(record)
+}
+
+pub async fn list(query: web::Query<ListQuery>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let mut records = all(&data.db, chrono::Utc::now().timestamp(), data.agent_stale_after_secs)?;
+    records.retain(|record| {
+        query.stale.is_none_or(|stale| record.stale == stale)
+            && query.image_id.as_ref().is_none_or(|image_id| record.image_id == *image_id)
+    });
+    Ok(HttpResponse::Ok().json(records))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::ScanStatus;
+
+    #[test]
+    fn test_registration_and_heartbeats() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree(AGENTS_TREE).unwrap();
+        let registration = |host: &str| AgentRegistration {
+            host: host.to_string(),
+            image_id: "app-v1".to_string(),
+            agent_version: "0.1.0".to_string(),
+            mode: "Monitor".to_string(),
+        };
+        let put = |record: &AgentRecord| tree.insert(record.host.as_bytes(), serde_json::to_vec(record).unwrap()).unwrap();
+
+        let mut web = AgentRecord::register(registration("web-1"), 1000, None);
+        web.heartbeat(AgentHeartbeat {
+            image_id: "app-v2".to_string(),
+            last_scan: Some(ScanStatus { finished_at: 1050, outcome: "clean".to_string(), anomalies: 0 }),
+        }, 1060);
+        put(&web);
+        put(&AgentRecord::register(registration("db-1"), 1000, None));
+
+        let records = records(&tree, 1350, 300).unwrap();
+        assert_eq!(records.iter().map(|r| (r.host.as_str(), r.stale)).collect::<Vec<_>>(), [("db-1", true), ("web-1", false)]);
+        assert_eq!((records[1].image_id.as_str(), records[1].last_seen), ("app-v2", 1060));
+
+        // A restarted agent keeps its host's last scan until it reports a new one
+        let restarted = AgentRecord::register(registration("web-1"), 2000, load(&tree, "web-1").unwrap());
+        assert_eq!(restarted.registered_at, 2000);
+        assert_eq!(restarted.last_scan.map(|scan| scan.outcome), Some("clean".to_string()));
+    }
+}
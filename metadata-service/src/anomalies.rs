@@ -0,0 +1,238 @@
+//! Anomaly reports and their triage.
+//!
+//! Agents `POST /anomalies` what they find; each host, kind and path is one
+//! record in its own sled tree (see `integrity_common::AnomalyRecord` for how
+//! recurrences are counted), stored and encrypted like baselines. Only anomalies that open a record, the first
+//! time or again after it was resolved or its snooze ran out, become
+//! `anomaly_opened` events, so an acknowledged or snoozed issue stops
+//! paging whoever the event sinks notify. Operators list records with
+//! `GET /anomalies` and change their state with `POST /anomalies/{id}/triage`.
+//! `GET /anomalies/fleet/{image_id}` compares the hosts of an image (see
+//! `integrity_common::analyze_fleet`).
+
+use crate::events::Event;
+use crate::storage::Store;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use integrity_common::{analyze_fleet, AnomalyBatch, AnomalyRecord, TriageRequest, TriageState, DEFAULT_FLEET_WIDE_SHARE};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use tracing::info;
+
+const ANOMALIES_TREE: &str = "anomalies";
+
+/// Stable id of the record for an anomaly on a host.
+fn record_id(host: &str, kind: &str, path: &str) -> String {
+    let digest = Sha256::digest(format!("{}\0{}\0{}", host, kind, path));
+    hex::encode(&digest[..8])
+}
+
+fn load(store: &Store, tree: &sled::Tree, id: &str) -> actix_web::Result<Option<AnomalyRecord>> {
+    let Some(value) = tree.get(id.as_bytes()).map_err(actix_web::error::ErrorInternalServerError)? else {
+        return Ok(None);
+    };
+    store.decode(id.as_bytes(), &value).map(Some)
+}
+
+/// Replaces the record `id` with what `change` makes of it, retrying if a
+/// concurrent report or triage request got there first. `change` returning
+/// None leaves the record as it is.
+fn update(
+    store: &Store,
+    tree: &sled::Tree,
+    id: &str,
+    mut change: impl FnMut(Option<AnomalyRecord>) -> Option<AnomalyRecord>,
+) -> actix_web::Result<Option<AnomalyRecord>> {
+    loop {
+        let old = tree.get(id.as_bytes()).map_err(actix_web::error::ErrorInternalServerError)?;
+        let record = match &old {
+            Some(value) => Some(store.decode(id.as_bytes(), value)?),
+            None => None,
+        };
+        let Some(record) = change(record) else {
+            return Ok(None);
+        };
+        let new = store.encode(id.as_bytes(), &record)?;
+        let swapped = tree.compare_and_swap(id.as_bytes(), old, Some(new)).map_err(actix_web::error::ErrorInternalServerError)?;
+        if swapped.is_ok() {
+            return Ok(Some(record));
+        }
+    }
+}
+
+pub async fn report(batch: web::Json<AnomalyBatch>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let batch = batch.into_inner();
+    let tree = data.db.open_tree(ANOMALIES_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    let now = chrono::Utc::now().timestamp();
+
+    let mut opened = Vec::new();
+    for anomaly in &batch.anomalies {
+        let id = record_id(&batch.host, &anomaly.kind, &anomaly.path);
+        let mut opens = false;
+        let record = update(&data.db, &tree, &id, |record| {
+            Some(match record {
+                Some(mut record) => {
+                    opens = record.recur(anomaly, &batch.image_id, now);
+                    record
+                }
+                None => {
+                    opens = true;
+                    AnomalyRecord::new(id.clone(), &batch.host, &batch.image_id, anomaly)
+                }
+            })
+        })?
+        .expect("always changed");
+        if opens {
+            let event = Event::AnomalyOpened {
+                id: &record.id,
+                host: &record.host,
+                image_id: &record.image_id,
+                kind: &record.kind,
+                path: &record.path,
+                detail: &record.detail,
+                occurrences: record.occurrences,
+            };
+            data.events.report(&event).await;
+            opened.push(id);
+        }
+    }
+    data.db.flush_async().await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    info!("{} reported {} anomalies, {} newly open", batch.host, batch.anomalies.len(), opened.len());
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "recorded": batch.anomalies.len(), "opened": opened })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    /// open, acknowledged, snoozed or resolved
+    state: Option<String>,
+    host: Option<String>,
+    image_id: Option<String>,
+}
+
+/// Every record that `filter` keeps.
+pub(crate) fn records(db: &Store, filter: impl Fn(&AnomalyRecord) -> bool) -> actix_web::Result<Vec<AnomalyRecord>> {
+    let tree = db.open_tree(ANOMALIES_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    let mut records = Vec::new();
+    for item in tree.iter() {
+        let (key, value) = item.map_err(actix_web::error::ErrorInternalServerError)?;
+        let record: AnomalyRecord = db.decode(&key, &value)?;
+        if filter(&record) {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+/// Records matching the query, most recently seen first.
+pub async fn list(query: web::Query<ListQuery>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let now = chrono::Utc::now().timestamp();
+    let mut records = records(&data.db, |record| {
+        query.state.as_ref().is_none_or(|state| record.state(now).name() == state)
+            && query.host.as_ref().is_none_or(|host| record.host == *host)
+            && query.image_id.as_ref().is_none_or(|image_id| record.image_id == *image_id)
+    })?;
+    records.sort_by(|a, b| b.last_seen.cmp(&a.last_seen).then_with(|| a.id.cmp(&b.id)));
+    Ok(HttpResponse::Ok().json(records))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FleetQuery {
+    /// Share of hosts from which a deviation counts as fleet-wide
+    fleet_wide_share: Option<f64>,
+    /// Count resolved anomalies as well
+    #[serde(default)]
+    include_resolved: bool,
+}
+
+/// Which deviations the hosts of an image share, and which hosts stand out.
+pub async fn fleet(image_id: web::Path<String>, query: web::Query<FleetQuery>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let share = query.fleet_wide_share.unwrap_or(DEFAULT_FLEET_WIDE_SHARE);
+    if !(share > 0.0 && share <= 1.0) {
+        return Ok(HttpResponse::BadRequest().body("fleet_wide_share must be in (0, 1]"));
+    }
+    let now = chrono::Utc::now().timestamp();
+    let mut records = records(&data.db, |record| record.image_id == *image_id)?;
+    // Hosts whose anomalies were all resolved are still part of the fleet
+    let fleet: BTreeSet<String> = records.iter().map(|record| record.host.clone()).collect();
+    records.retain(|record| query.include_resolved || record.state(now) != TriageState::Resolved);
+    Ok(HttpResponse::Ok().json(analyze_fleet(&image_id, &fleet, &records, share)))
+}
+
+pub async fn get(id: web::Path<String>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let tree = data.db.open_tree(ANOMALIES_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    let record = load(&data.db, &tree, &id)?.ok_or_else(|| actix_web::error::ErrorNotFound(format!("No anomaly {}", id)))?;
+    Ok(HttpResponse::Ok().json(record))
+}
+
+/// Who is making a change: the login if the service authenticates, else the requested actor.
+pub(crate) fn actor(req: &HttpRequest, requested: Option<&str>) -> Option<String> {
+    #[cfg(feature = "ldap")]
+    {
+        use actix_web::HttpMessage;
+        if let Some(principal) = req.extensions().get::<crate::auth::Principal>() {
+            return Some(principal.user.clone());
+        }
+    }
+    #[cfg(not(feature = "ldap"))]
+    let _ = req;
+    requested.filter(|actor| !actor.trim().is_empty()).map(str::to_string)
+}
+
+pub async fn triage(
+    req: HttpRequest,
+    id: web::Path<String>,
+    request: web::Json<TriageRequest>,
+    data: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let request = request.into_inner();
+    let now = chrono::Utc::now().timestamp();
+    let Some(actor) = actor(&req, request.actor.as_deref()) else {
+        return Ok(HttpResponse::BadRequest().body("actor is required"));
+    };
+    if let TriageState::Snoozed { until } = request.state {
+        if until <= now {
+            return Ok(HttpResponse::BadRequest().body("cannot snooze until a time in the past"));
+        }
+    }
+
+    let tree = data.db.open_tree(ANOMALIES_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    let record = update(&data.db, &tree, &id, |record| {
+        let mut record = record?;
+        record.set_state(request.state.clone(), &actor, request.comment.clone(), now);
+        Some(record)
+    })?
+    .ok_or_else(|| actix_web::error::ErrorNotFound(format!("No anomaly {}", id)))?;
+    info!("{} set anomaly {} ({} {} on {}) {}", actor, record.id, record.kind, record.path, record.host, request.state.name());
+    data.db.flush_async().await.map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(record))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::ReportedAnomaly;
+
+    #[test]
+    fn test_record_id() {
+        let id = record_id("web-1", "ADDED", "tmp/x");
+        assert_eq!(id.len(), 16);
+        assert_eq!(id, record_id("web-1", "ADDED", "tmp/x"));
+        assert_ne!(id, record_id("web-2", "ADDED", "tmp/x"));
+    }
+
+    #[test]
+    fn test_records_are_sealed() {
+        let store = Store::new(sled::Config::new().temporary(true).open().unwrap(), Some([7; 32]));
+        let tree = store.open_tree(ANOMALIES_TREE).unwrap();
+        let anomaly = ReportedAnomaly { time: 1000, kind: "ADDED".to_string(), path: "tmp/x".to_string(), detail: String::new() };
+        let id = record_id("web-1", "ADDED", "tmp/x");
+        update(&store, &tree, &id, |_| Some(AnomalyRecord::new(id.clone(), "web-1", "app-v1", &anomaly))).unwrap();
+
+        let stored = tree.get(id.as_bytes()).unwrap().unwrap();
+        assert!(!stored.windows(5).any(|w| w == b"tmp/x"));
+        assert_eq!(load(&store, &tree, &id).unwrap().map(|record| record.host), Some("web-1".to_string()));
+        assert_eq!(records(&store, |_| true).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,264 @@
+//! LDAP/Active Directory authentication (`ldap` feature).
+//!
+//! With `--ldap-url` set, requests that change state (storing baselines,
+//! mapping cloud images) need HTTP Basic credentials of a directory account.
+//! The service binds as the user, reads the groups of the user's entry
+//! (`memberOf`) and maps them to a role with `--ldap-group role=<group DN>`;
+//! an account in several mapped groups gets the highest role. Agents keep
+//! fetching baselines and their configuration, registering and sending
+//! heartbeats, reporting anomalies and uploading scan snapshots anonymously,
+//! and the admission webhook and read-only GraphQL queries stay open;
+//! triaging anomalies and changing agent configuration takes an operator,
+//! whose login is recorded as the actor, and deleting a baseline takes an
+//! admin.
+//! `GET /auth/whoami` lets the dashboard check a login.
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use base64::Engine;
+use ldap3::{LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{info, warn};
+
+const REALM: &str = "Basic realm=\"acropole\"";
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Can log in to the dashboard
+    Viewer,
+    /// Can store baselines and image mappings
+    Operator,
+    /// Everything operators can, plus administrative endpoints
+    Admin,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct LdapArgs {
+    /// LDAP server, e.g. ldaps://dc1.corp.example.com
+    #[arg(long, requires_all = ["ldap_bind_template", "ldap_search_base"])]
+    pub ldap_url: Option<String>,
+
+    /// Upgrade ldap:// connections with StartTLS
+    #[arg(long)]
+    pub ldap_starttls: bool,
+
+    /// Bind name for a login, e.g. "uid={user},ou=people,dc=example,dc=com" or "{user}@corp.example.com"
+    #[arg(long)]
+    pub ldap_bind_template: Option<String>,
+
+    /// Base DN the user's entry is searched under
+    #[arg(long)]
+    pub ldap_search_base: Option<String>,
+
+    /// Filter finding the user's entry
+    #[arg(long, default_value = "(|(uid={user})(sAMAccountName={user}))")]
+    pub ldap_user_filter: String,
+
+    /// Grant members of a group a role, e.g. operator=cn=integrity-ops,ou=groups,dc=example,dc=com (repeatable)
+    #[arg(long = "ldap-group", value_parser = parse_group)]
+    pub ldap_groups: Vec<(Role, String)>,
+}
+
+fn parse_group(mapping: &str) -> Result<(Role, String), String> {
+    let (role, group) = mapping.split_once('=').ok_or_else(|| format!("expected role=<group DN>, got {}", mapping))?;
+    let role = <Role as clap::ValueEnum>::from_str(role, true)?;
+    Ok((role, group.to_string()))
+}
+
+impl LdapArgs {
+    pub fn auth(&self) -> Option<LdapAuth> {
+        let url = self.ldap_url.clone()?;
+        info!("Authenticating state-changing requests against {}", url);
+        Some(LdapAuth {
+            url,
+            starttls: self.ldap_starttls,
+            bind_template: self.ldap_bind_template.clone().unwrap_or_default(),
+            search_base: self.ldap_search_base.clone().unwrap_or_default(),
+            user_filter: self.ldap_user_filter.clone(),
+            groups: self.ldap_groups.clone(),
+        })
+    }
+}
+
+pub struct LdapAuth {
+    url: String,
+    starttls: bool,
+    bind_template: String,
+    search_base: String,
+    user_filter: String,
+    groups: Vec<(Role, String)>,
+}
+
+/// The authenticated user, available to handlers as a request extension.
+#[derive(Debug, Clone, Serialize)]
+pub struct Principal {
+    pub user: String,
+    pub role: Role,
+}
+
+impl LdapAuth {
+    /// Binds as `user` and maps the user's groups to a role. None if the
+    /// credentials are wrong or the user is in no mapped group.
+    async fn authenticate(&self, user: &str, password: &str) -> anyhow::Result<Option<Role>> {
+        // An empty password is an unauthenticated bind, which many servers accept
+        if user.is_empty() || password.is_empty() {
+            return Ok(None);
+        }
+        let settings = LdapConnSettings::new().set_conn_timeout(Duration::from_secs(10)).set_starttls(self.starttls);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.url).await?;
+        ldap3::drive!(conn);
+
+        let bind_dn = self.bind_template.replace("{user}", &ldap3::dn_escape(user));
+        if ldap.simple_bind(&bind_dn, password).await?.success().is_err() {
+            return Ok(None);
+        }
+        let filter = self.user_filter.replace("{user}", &ldap3::ldap_escape(user));
+        let (entries, _) = ldap.search(&self.search_base, Scope::Subtree, &filter, vec!["memberOf"]).await?.success()?;
+        let _ = ldap.unbind().await;
+
+        let member_of: Vec<String> = entries
+            .into_iter()
+            .flat_map(|entry| SearchEntry::construct(entry).attrs.remove("memberOf").unwrap_or_default())
+            .collect();
+        Ok(role_for(&self.groups, &member_of))
+    }
+}
+
+/// Highest role granted by any of the groups; group DNs compare case-insensitively.
+fn role_for(groups: &[(Role, String)], member_of: &[String]) -> Option<Role> {
+    groups
+        .iter()
+        .filter(|(_, group)| member_of.iter().any(|dn| dn.eq_ignore_ascii_case(group)))
+        .map(|(role, _)| *role)
+        .max()
+}
+
+/// The role a request needs, or None if it is open.
+fn required_role(method: &Method, path: &str) -> Option<Role> {
+    if path == "/auth/whoami" {
+        return Some(Role::Viewer);
+    }
+    if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) || path == "/admission/validate" || path == "/graphql" {
+        return None;
+    }
+    // Deleting a baseline throws away its history
+    if *method == Method::DELETE && path.strip_prefix("/baselines/").is_some_and(|image_id| !image_id.contains('/')) {
+        return Some(Role::Admin);
+    }
+    // Agents report anomalies, upload snapshots and check in without a directory account, like they fetch baselines
+    if path == "/anomalies" || path == "/snapshots" || path == "/agents" {
+        return None;
+    }
+    if path.strip_prefix("/agents/").and_then(|rest| rest.strip_suffix("/heartbeat")).is_some_and(|host| !host.contains('/')) {
+        return None;
+    }
+    Some(Role::Operator)
+}
+
+fn credentials(req: &ServiceRequest) -> Option<(String, String)> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let encoded = value.strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+    let (user, password) = String::from_utf8(decoded).ok()?.split_once(':').map(|(u, p)| (u.to_string(), p.to_string()))?;
+    Some((user, password))
+}
+
+/// Middleware enforcing `required_role` when LDAP is configured.
+pub async fn authorize(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> actix_web::Result<ServiceResponse<BoxBody>> {
+    let Some(auth) = req.app_data::<web::Data<Option<LdapAuth>>>().cloned() else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+    let Some(auth) = auth.as_ref() else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+    let Some(required) = required_role(req.method(), req.path()) else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+
+    let unauthorized = |req: ServiceRequest| {
+        let response = HttpResponse::Unauthorized().insert_header((header::WWW_AUTHENTICATE, REALM)).finish();
+        Ok(req.into_response(response).map_into_boxed_body())
+    };
+    let Some((user, password)) = credentials(&req) else {
+        return unauthorized(req);
+    };
+    let role = match auth.authenticate(&user, &password).await {
+        Ok(Some(role)) => role,
+        Ok(None) => {
+            warn!("Rejected login of {} for {} {}", user, req.method(), req.path());
+            return unauthorized(req);
+        }
+        Err(e) => {
+            warn!("LDAP authentication of {} failed: {}", user, e);
+            return Ok(req.into_response(HttpResponse::ServiceUnavailable().body("directory unavailable")).map_into_boxed_body());
+        }
+    };
+    if role < required {
+        warn!("{} ({:?}) is not allowed to {} {}", user, role, req.method(), req.path());
+        return Ok(req.into_response(HttpResponse::Forbidden().finish()).map_into_boxed_body());
+    }
+
+    req.extensions_mut().insert(Principal { user, role });
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+pub async fn whoami(req: HttpRequest) -> impl Responder {
+    match req.extensions().get::<Principal>() {
+        Some(principal) => HttpResponse::Ok().json(principal),
+        None => HttpResponse::NotFound().body("LDAP authentication is not configured"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_for() {
+        let groups = vec![
+            (Role::Viewer, "cn=staff,ou=groups,dc=example,dc=com".to_string()),
+            (Role::Admin, "cn=integrity-admins,ou=groups,dc=example,dc=com".to_string()),
+            (Role::Operator, "cn=integrity-ops,ou=groups,dc=example,dc=com".to_string()),
+        ];
+        let member_of = |dns: &[&str]| dns.iter().map(|dn| dn.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(role_for(&groups, &member_of(&["CN=Staff,OU=Groups,DC=example,DC=com"])), Some(Role::Viewer));
+        assert_eq!(
+            role_for(&groups, &member_of(&["cn=staff,ou=groups,dc=example,dc=com", "cn=integrity-ops,ou=groups,dc=example,dc=com"])),
+            Some(Role::Operator)
+        );
+        assert_eq!(role_for(&groups, &member_of(&["cn=other,dc=example,dc=com"])), None);
+    }
+
+    #[test]
+    fn test_required_role() {
+        assert_eq!(required_role(&Method::GET, "/baselines/app"), None);
+        assert_eq!(required_role(&Method::POST, "/admission/validate"), None);
+        assert_eq!(required_role(&Method::POST, "/baselines"), Some(Role::Operator));
+        assert_eq!(required_role(&Method::DELETE, "/baselines/app"), Some(Role::Admin));
+        assert_eq!(required_role(&Method::DELETE, "/baselines/app/scheduled/1700000000"), Some(Role::Operator));
+        assert_eq!(required_role(&Method::POST, "/anomalies"), None);
+        assert_eq!(required_role(&Method::POST, "/anomalies/0123abcd/triage"), Some(Role::Operator));
+        assert_eq!(required_role(&Method::POST, "/snapshots"), None);
+        assert_eq!(required_role(&Method::POST, "/agents"), None);
+        assert_eq!(required_role(&Method::POST, "/agents/web-1/heartbeat"), None);
+        assert_eq!(required_role(&Method::PUT, "/image-mappings/aws:ami-0abc"), Some(Role::Operator));
+        assert_eq!(required_role(&Method::GET, "/agent-config/effective"), None);
+        assert_eq!(required_role(&Method::PUT, "/agent-config/host/web-1"), Some(Role::Operator));
+        assert_eq!(required_role(&Method::GET, "/auth/whoami"), Some(Role::Viewer));
+    }
+
+    #[test]
+    fn test_parse_group() {
+        assert_eq!(parse_group("operator=cn=ops,dc=example,dc=com").unwrap(), (Role::Operator, "cn=ops,dc=example,dc=com".to_string()));
+        assert!(parse_group("root=cn=ops").is_err());
+    }
+}
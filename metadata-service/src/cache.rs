@@ -0,0 +1,192 @@
+//! In-process LRU cache of materialized baselines.
+//!
+//! At boot storms thousands of agents fetch the same few baselines; each
+//! fetch otherwise deserializes the baseline, walks its `extends` chain and
+//! recomputes the canonical digest for the ETag. Entries are keyed by
+//! image_id. Any write clears the whole cache, since storing a base image
+//! changes every baseline derived from it, and so does a staged version
+//! taking effect (see `scheduled`). Per-file lookups index a cached
+//! baseline by path the first time they need it.
+
+use crate::{inheritance, scheduled};
+use crate::storage::Store;
+use actix_web::{web, HttpResponse, Responder};
+use integrity_common::{Baseline, FileIntegrityEntry};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// A cached baseline with its canonical digest.
+#[derive(Clone)]
+pub struct Cached {
+    pub baseline: Arc<Baseline>,
+    pub digest: Arc<str>,
+    /// Path (without leading and trailing slashes) -> position in the entries, built on first lookup
+    paths: Arc<OnceLock<HashMap<String, usize>>>,
+}
+
+impl Cached {
+    pub fn new(baseline: Baseline, digest: impl Into<Arc<str>>) -> Self {
+        Self { baseline: Arc::new(baseline), digest: digest.into(), paths: Arc::default() }
+    }
+
+    /// The entry for `path`; leading and trailing slashes are ignored.
+    pub fn entry(&self, path: &str) -> Option<&FileIntegrityEntry> {
+        let paths = self.paths.get_or_init(|| {
+            self.baseline.entries.iter().enumerate().map(|(i, entry)| (entry.path.trim_matches('/').to_string(), i)).collect()
+        });
+        paths.get(path.trim_matches('/')).map(|i| &self.baseline.entries[*i])
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CacheStats {
+    pub capacity: usize,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+pub struct BaselineCache {
+    capacity: usize,
+    /// image_id -> (entry, last use)
+    entries: Mutex<HashMap<String, (Cached, u64)>>,
+    clock: AtomicU64,
+    /// Unix time the next staged version takes effect; i64::MIN to look it up again
+    next_activation: AtomicI64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl BaselineCache {
+    /// A cache holding at most `capacity` baselines; 0 disables caching.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+            clock: AtomicU64::new(0),
+            next_activation: AtomicI64::new(i64::MIN),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The effective baseline of an image, loaded from `db` on a miss.
+    pub fn load(&self, db: &Store, image_id: &str) -> actix_web::Result<Option<Cached>> {
+        let now = chrono::Utc::now().timestamp();
+        if now >= self.next_activation.load(Ordering::Relaxed) {
+            self.entries.lock().unwrap().clear();
+            let next = scheduled::next_activation(db, now)?.unwrap_or(i64::MAX);
+            self.next_activation.store(next, Ordering::Relaxed);
+        }
+        let tick = self.clock.fetch_add(1, Ordering::Relaxed);
+        if let Some((cached, last_use)) = self.entries.lock().unwrap().get_mut(image_id) {
+            *last_use = tick;
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(Some(cached.clone()));
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let Some(baseline) = inheritance::load_baseline(db, image_id)? else {
+            return Ok(None);
+        };
+        let digest = baseline.digest().map_err(actix_web::error::ErrorInternalServerError)?;
+        let cached = Cached::new(baseline, digest);
+        self.insert(image_id, cached.clone(), tick);
+        Ok(Some(cached))
+    }
+
+    fn insert(&self, image_id: &str, cached: Cached, tick: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(image_id) {
+            let oldest = entries.iter().min_by_key(|(_, (_, last_use))| *last_use).map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(image_id.to_string(), (cached, tick));
+    }
+
+    /// Drops every cached baseline; called after any baseline is written or staged.
+    pub fn invalidate(&self) {
+        self.entries.lock().unwrap().clear();
+        self.next_activation.store(i64::MIN, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            capacity: self.capacity,
+            entries: self.entries.lock().unwrap().len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub async fn stats(data: web::Data<crate::AppState>) -> impl Responder {
+    HttpResponse::Ok().json(data.cache.stats())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::FileIntegrityEntry;
+
+    fn store(db: &Store, image_id: &str, sha512: &str) {
+        let baseline = Baseline {
+            image_id: image_id.to_string(),
+            timestamp: "2024-01-01T00:00:00Z".to_string(),
+            entries: vec![FileIntegrityEntry {
+                path: "etc/hosts".to_string(),
+                sha512: sha512.to_string(),
+                mode: 0o644,
+                uid: 0,
+                gid: 0,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        db.insert(image_id.as_bytes(), serde_json::to_vec(&baseline).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_lru_eviction_and_invalidation() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap(), None);
+        for image_id in ["a", "b", "c"] {
+            store(&db, image_id, "aaa");
+        }
+        let cache = BaselineCache::new(2);
+
+        assert!(cache.load(&db, "a").unwrap().is_some());
+        assert!(cache.load(&db, "b").unwrap().is_some());
+        assert!(cache.load(&db, "a").unwrap().is_some());
+        // "b" is least recently used and makes room for "c"
+        assert!(cache.load(&db, "c").unwrap().is_some());
+        assert!(cache.load(&db, "a").unwrap().is_some());
+        assert!(cache.load(&db, "missing").unwrap().is_none());
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (2, 2, 4));
+        assert!(cache.load(&db, "b").unwrap().is_some());
+        assert_eq!(cache.stats().misses, 5);
+
+        // Writes are only seen after invalidation
+        store(&db, "a", "bbb");
+        assert_eq!(cache.load(&db, "a").unwrap().unwrap().baseline.entries[0].sha512, "aaa");
+        cache.invalidate();
+        assert_eq!(cache.load(&db, "a").unwrap().unwrap().baseline.entries[0].sha512, "bbb");
+    }
+
+    #[test]
+    fn test_entry() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap(), None);
+        store(&db, "a", "aaa");
+        let cached = BaselineCache::new(1).load(&db, "a").unwrap().unwrap();
+
+        assert_eq!(cached.entry("/etc/hosts/").map(|entry| entry.sha512.as_str()), Some("aaa"));
+        assert!(cached.entry("etc").is_none());
+    }
+}
@@ -0,0 +1,212 @@
+//! Listing and deleting stored baselines.
+//!
+//! `GET /baselines` lists the stored baselines, full and derived, by
+//! image_id, paginated with `offset` and `limit`. Full baselines are
+//! described from their latest version record, so listing does not decode
+//! them; derived ones are materialized through the baseline cache.
+//!
+//! `GET /baselines/{image_id}/summary` describes one baseline without its
+//! entries: collection time, entry count, total size of the hashed files and
+//! canonical digest, so checking whether a multi-hundred-MB baseline changed
+//! does not take downloading it.
+//!
+//! `DELETE /baselines/{image_id}` removes a baseline with its versions,
+//! staged versions and signature chains, so stale golden images can be
+//! retired. It takes `Authorization: Bearer <token>` with the token of
+//! `--admin-token-file`, or with LDAP configured an admin account, and is
+//! refused while derived baselines still extend the image. Image mappings
+//! pointing at the image are left alone.
+
+use crate::cache::Cached;
+use crate::{inheritance, scheduled, signatures, versions, AppState};
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use anyhow::Context;
+use integrity_common::{Baseline, BaselineList, BaselineMetadata, BaselineSummary};
+use serde::Deserialize;
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct AdminArgs {
+    /// File holding the bearer token that authorizes deleting baselines (deletion is disabled without one)
+    #[arg(long)]
+    pub admin_token_file: Option<PathBuf>,
+}
+
+impl AdminArgs {
+    pub fn admin_token(&self) -> anyhow::Result<Option<String>> {
+        let Some(path) = &self.admin_token_file else {
+            return Ok(None);
+        };
+        let token = std::fs::read_to_string(path).with_context(|| format!("reading admin token file {:?}", path))?;
+        let token = token.trim();
+        anyhow::ensure!(!token.is_empty(), "admin token file {:?} is empty", path);
+        Ok(Some(token.to_string()))
+    }
+}
+
+fn internal<E: std::fmt::Debug + std::fmt::Display + 'static>(e: E) -> actix_web::Error {
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    #[serde(default)]
+    offset: usize,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+fn summary(data: &AppState, image_id: &str) -> actix_web::Result<Option<BaselineSummary>> {
+    if let Some(extends) = inheritance::parent_of(&data.db, image_id)? {
+        return Ok(data.cache.load(&data.db, image_id)?.map(|cached| BaselineSummary {
+            image_id: image_id.to_string(),
+            timestamp: cached.baseline.timestamp.clone(),
+            entries: cached.baseline.entries.len(),
+            extends: Some(extends),
+        }));
+    }
+    if let Some(latest) = versions::latest(&data.db, image_id)? {
+        return Ok(Some(BaselineSummary { image_id: latest.image_id, timestamp: latest.timestamp, entries: latest.entries, extends: None }));
+    }
+    // Stored before versions were kept
+    let Some(stored) = data.db.get(image_id.as_bytes()).map_err(internal)? else {
+        return Ok(None);
+    };
+    let baseline: Baseline = data.db.decode(image_id.as_bytes(), &stored)?;
+    Ok(Some(BaselineSummary { image_id: baseline.image_id, timestamp: baseline.timestamp, entries: baseline.entries.len(), extends: None }))
+}
+
+pub async fn list(query: web::Query<ListQuery>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let image_ids = inheritance::image_ids(&data.db)?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let start = query.offset.min(image_ids.len());
+    let end = start.saturating_add(limit).min(image_ids.len());
+
+    let mut baselines = Vec::with_capacity(end - start);
+    for image_id in &image_ids[start..end] {
+        baselines.extend(summary(&data, image_id)?);
+    }
+    Ok(HttpResponse::Ok().json(BaselineList { total: image_ids.len(), offset: start, baselines }))
+}
+
+fn metadata(image_id: &str, cached: &Cached, extends: Option<String>) -> BaselineMetadata {
+    BaselineMetadata {
+        image_id: image_id.to_string(),
+        timestamp: cached.baseline.timestamp.clone(),
+        entries: cached.baseline.entries.len(),
+        total_bytes: cached.baseline.entries.iter().filter_map(|entry| entry.size).sum(),
+        digest: cached.digest.to_string(),
+        hash_algorithm: cached.baseline.hash_algorithm,
+        extends,
+    }
+}
+
+pub async fn baseline_summary(image_id: web::Path<String>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let image_id = image_id.into_inner();
+    let cached = data
+        .cache
+        .load(&data.db, &image_id)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Baseline not found: {}", image_id)))?;
+    let extends = inheritance::parent_of(&data.db, &image_id)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, format!("\"{}\"", cached.digest)))
+        .json(metadata(&image_id, &cached, extends)))
+}
+
+/// Whether `given` is `expected`, taking the same time wherever they differ.
+fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len() && given.bytes().zip(expected.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Why the request may not do `action` (e.g. "Deleting baselines"), which
+/// takes the admin token, if it may not.
+pub(crate) fn refusal(req: &HttpRequest, admin_token: Option<&str>, action: &str) -> Option<HttpResponse> {
+    // The LDAP middleware only lets authorized users through to these routes
+    #[cfg(feature = "ldap")]
+    if actix_web::HttpMessage::extensions(req).get::<crate::auth::Principal>().is_some() {
+        return None;
+    }
+    let Some(expected) = admin_token else {
+        return Some(HttpResponse::Forbidden().body(format!("{} is disabled; start the service with --admin-token-file", action)));
+    };
+    let given = req.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()).and_then(|v| v.strip_prefix("Bearer "));
+    match given {
+        Some(given) if token_matches(given.trim(), expected) => None,
+        _ => Some(HttpResponse::Unauthorized().insert_header((header::WWW_AUTHENTICATE, "Bearer")).body(format!("{} takes the admin token", action))),
+    }
+}
+
+pub async fn delete(req: HttpRequest, image_id: web::Path<String>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let image_id = image_id.into_inner();
+    if let Some(refusal) = refusal(&req, data.admin_token.as_deref(), "Deleting baselines") {
+        warn!("Refused to delete baseline {} ({})", image_id, refusal.status());
+        return Ok(refusal);
+    }
+    if !inheritance::baseline_exists(&data.db, &image_id) {
+        return Ok(HttpResponse::NotFound().body(format!("Baseline not found: {}", image_id)));
+    }
+    let children = inheritance::extended_by(&data.db, &image_id)?;
+    if !children.is_empty() {
+        return Ok(HttpResponse::Conflict().body(format!("Baseline {} is extended by {}; delete those first", image_id, children.join(", "))));
+    }
+
+    data.db.remove(image_id.as_bytes()).map_err(internal)?;
+    inheritance::remove_derived(&data.db, &image_id)?;
+    let removed_versions = versions::remove(&data.db, &image_id)?;
+    scheduled::remove(&data.db, &image_id)?;
+    signatures::remove(&data.db, &image_id)?;
+    data.cache.invalidate();
+    data.db.flush_async().await.map_err(internal)?;
+    info!("Deleted baseline {} and {} stored versions", image_id, removed_versions);
+
+    data.events.baseline(&crate::events::Event::BaselineDeleted { image_id: &image_id }).await;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use integrity_common::FileIntegrityEntry;
+
+    #[test]
+    fn test_metadata() {
+        let entry = |path: &str, size| FileIntegrityEntry { path: path.to_string(), sha512: "aaa".to_string(), mode: 0o644, uid: 0, gid: 0, size, ..Default::default() };
+        let baseline = Baseline {
+            image_id: "app".to_string(),
+            timestamp: "2026-01-01T00:00:00Z".to_string(),
+            entries: vec![entry("etc/hosts", Some(100)), entry("usr/bin/ls", Some(1 << 20)), entry("etc/motd", None)],
+            ..Default::default()
+        };
+        let cached = Cached::new(baseline, "d1");
+        let metadata = metadata("app", &cached, Some("base".to_string()));
+
+        assert_eq!((metadata.entries, metadata.total_bytes), (3, 100 + (1 << 20)));
+        assert_eq!((metadata.digest.as_str(), metadata.extends.as_deref()), ("d1", Some("base")));
+        assert!(!serde_json::to_string(&metadata).unwrap().contains("\"path\""));
+    }
+
+    #[test]
+    fn test_refusal() {
+        let request = |authorization: Option<&str>| {
+            let mut request = TestRequest::delete().uri("/baselines/app");
+            if let Some(authorization) = authorization {
+                request = request.insert_header((header::AUTHORIZATION, authorization));
+            }
+            request.to_http_request()
+        };
+        let status = |req: &HttpRequest, token| refusal(req, token, "Deleting baselines").map(|response| response.status().as_u16());
+
+        assert_eq!(status(&request(Some("Bearer s3cret")), None), Some(403));
+        assert_eq!(status(&request(None), Some("s3cret")), Some(401));
+        assert_eq!(status(&request(Some("Bearer s3cre")), Some("s3cret")), Some(401));
+        assert_eq!(status(&request(Some("Basic czNjcmV0")), Some("s3cret")), Some(401));
+        assert_eq!(status(&request(Some("Bearer s3cret")), Some("s3cret")), None);
+    }
+}
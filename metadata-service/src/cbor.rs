@@ -0,0 +1,104 @@
+//! Baselines transferred as CBOR.
+//!
+//! `POST /baselines` with `Content-Type: application/cbor` takes the baseline
+//! encoded as one CBOR document (decompressed, with a `Content-Encoding` of
+//! zstd, gzip or br). CBOR cannot be decoded a line at a time, so the body is
+//! buffered, up to [`MAX_BODY`]; the response is the same summary a streamed
+//! upload gets.
+//!
+//! `GET /baselines/{image_id}` with `Accept: application/cbor` serves the
+//! baseline in the same encoding.
+
+use crate::cache::Cached;
+use crate::{accept_baseline, AppState, StoreQuery};
+use actix_web::dev::Decompress;
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use futures_util::StreamExt;
+use integrity_common::{from_cbor, to_cbor, Baseline, CBOR_CONTENT_TYPE};
+use tracing::{info, warn};
+
+/// Largest (decompressed) CBOR upload accepted.
+pub const MAX_BODY: usize = 1024 * 1024 * 1024;
+
+fn internal<E: std::fmt::Debug + std::fmt::Display + 'static>(e: E) -> actix_web::Error {
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+/// Whether a header lists the CBOR media type.
+fn is_cbor(value: Option<&header::HeaderValue>) -> bool {
+    value.and_then(|v| v.to_str().ok()).is_some_and(|v| v.split(',').any(|t| t.trim().starts_with(CBOR_CONTENT_TYPE)))
+}
+
+/// Routes uploads sent as CBOR here rather than to the JSON handler.
+pub fn is_cbor_upload(ctx: &actix_web::guard::GuardContext) -> bool {
+    is_cbor(ctx.head().headers().get(header::CONTENT_TYPE))
+}
+
+/// Whether the client asked for the baseline as CBOR.
+pub fn accepts_cbor(req: &HttpRequest) -> bool {
+    is_cbor(req.headers().get(header::ACCEPT))
+}
+
+pub async fn store_baseline(
+    req: HttpRequest,
+    payload: web::Payload,
+    query: web::Query<StoreQuery>,
+    data: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let mut payload = Decompress::from_headers(payload, req.headers());
+    let mut body = Vec::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > MAX_BODY {
+            warn!("Rejecting CBOR baseline larger than {} bytes", MAX_BODY);
+            return Ok(HttpResponse::PayloadTooLarge().body(format!("CBOR baselines are limited to {} bytes", MAX_BODY)));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let baseline: Baseline = match from_cbor(&body) {
+        Ok(baseline) => baseline,
+        Err(e) => {
+            warn!("Rejecting CBOR baseline: {}", e);
+            return Ok(HttpResponse::BadRequest().body(e.to_string()));
+        }
+    };
+    info!("Received CBOR baseline for image {} ({} entries)", baseline.image_id, baseline.entries.len());
+    accept_baseline(&data, &baseline, query.effective_from, |digest| {
+        HttpResponse::Created()
+            .insert_header((header::ETAG, format!("\"{}\"", digest)))
+            .json(serde_json::json!({ "image_id": baseline.image_id, "digest": digest, "entries": baseline.entries.len() }))
+    })
+    .await
+}
+
+/// The baseline encoded as CBOR.
+pub fn respond(cached: &Cached, etag: String) -> actix_web::Result<HttpResponse> {
+    let body = to_cbor(&*cached.baseline).map_err(internal)?;
+    Ok(HttpResponse::Ok().insert_header((header::ETAG, etag)).content_type(CBOR_CONTENT_TYPE).body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[actix_rt::test]
+    async fn test_respond() {
+        let baseline = Baseline {
+            image_id: "img".to_string(),
+            timestamp: "2023-01-01T00:00:00Z".to_string(),
+            ..Default::default()
+        };
+        let cached = Cached::new(baseline.clone(), "d");
+
+        let response = respond(&cached, "\"d\"".to_string()).unwrap();
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), CBOR_CONTENT_TYPE);
+        let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(from_cbor::<Baseline>(&body).unwrap(), baseline);
+
+        let req = TestRequest::default().insert_header((header::ACCEPT, "application/cbor, application/json;q=0.9")).to_http_request();
+        assert!(accepts_cbor(&req));
+        assert!(!accepts_cbor(&TestRequest::default().insert_header((header::ACCEPT, "application/json")).to_http_request()));
+    }
+}
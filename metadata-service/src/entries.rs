@@ -0,0 +1,204 @@
+//! Browsing a baseline like a filesystem.
+//!
+//! `GET /baselines/{image_id}/entries/{path}` returns a single entry,
+//! `GET /baselines/{image_id}/entries?path=/etc/passwd` looks one up for
+//! agents verifying files one at a time (with `null` for a path the baseline
+//! does not have, so only a missing baseline answers 404), and
+//! `GET /baselines/{image_id}/digests` fingerprints every entry, so a
+//! collector can tell which of its entries changed without fetching them, and
+//! `GET /baselines/{image_id}/tree?prefix=/etc` lists a directory: its files
+//! and subdirectories (with the number of entries below them) in path order,
+//! paginated with `offset` and `limit`. With `recursive=true` every file below
+//! the prefix is listed instead. Leading and trailing slashes are ignored, as
+//! baseline paths are relative to the scan root.
+
+use crate::AppState;
+use actix_web::{web, HttpResponse, Responder};
+use integrity_common::{EntryDigests, EntryLookup, FileIntegrityEntry};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct TreeQuery {
+    #[serde(default)]
+    prefix: String,
+    #[serde(default)]
+    recursive: bool,
+    #[serde(default)]
+    offset: usize,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LookupQuery {
+    path: String,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Node<'a> {
+    Directory { path: String, entries: usize },
+    File(&'a FileIntegrityEntry),
+}
+
+impl Node<'_> {
+    fn path(&self) -> &str {
+        match self {
+            Node::Directory { path, .. } => path,
+            Node::File(entry) => entry.path.trim_matches('/'),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Listing<'a> {
+    image_id: &'a str,
+    prefix: &'a str,
+    /// Nodes in the whole listing, before pagination
+    total: usize,
+    offset: usize,
+    nodes: &'a [Node<'a>],
+}
+
+/// Children of directory `prefix` (or every file below it when `recursive`), in path order.
+fn list<'a>(entries: &'a [FileIntegrityEntry], prefix: &str, recursive: bool) -> Vec<Node<'a>> {
+    let mut directories: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut nodes = Vec::new();
+    for entry in entries {
+        let path = entry.path.trim_matches('/');
+        let rest = if prefix.is_empty() {
+            path
+        } else {
+            match path.strip_prefix(prefix).and_then(|rest| rest.strip_prefix('/')) {
+                Some(rest) => rest,
+                None => continue,
+            }
+        };
+        match rest.split_once('/') {
+            Some((dir, _)) if !recursive => *directories.entry(&path[..path.len() - rest.len() + dir.len()]).or_default() += 1,
+            _ => nodes.push(Node::File(entry)),
+        }
+    }
+    nodes.extend(directories.into_iter().map(|(path, entries)| Node::Directory { path: path.to_string(), entries }));
+    nodes.sort_by(|a, b| a.path().cmp(b.path()));
+    nodes
+}
+
+pub async fn tree(
+    image_id: web::Path<String>,
+    query: web::Query<TreeQuery>,
+    data: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let image_id = image_id.into_inner();
+    let cached = data
+        .cache
+        .load(&data.db, &image_id)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Baseline not found: {}", image_id)))?;
+
+    let prefix = query.prefix.trim_matches('/');
+    let nodes = list(&cached.baseline.entries, prefix, query.recursive);
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let start = query.offset.min(nodes.len());
+    let end = start.saturating_add(limit).min(nodes.len());
+
+    Ok(HttpResponse::Ok().json(Listing {
+        image_id: &image_id,
+        prefix,
+        total: nodes.len(),
+        offset: start,
+        nodes: &nodes[start..end],
+    }))
+}
+
+pub async fn digests(image_id: web::Path<String>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let image_id = image_id.into_inner();
+    let cached = data
+        .cache
+        .load(&data.db, &image_id)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Baseline not found: {}", image_id)))?;
+    let digests = EntryDigests::of(&cached.baseline, cached.digest.to_string()).map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(digests))
+}
+
+pub async fn entry(path: web::Path<(String, String)>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let (image_id, path) = path.into_inner();
+    let cached = data
+        .cache
+        .load(&data.db, &image_id)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Baseline not found: {}", image_id)))?;
+
+    let path = path.trim_matches('/');
+    if let Some(entry) = cached.entry(path) {
+        return Ok(HttpResponse::Ok().json(entry));
+    }
+    if !list(&cached.baseline.entries, path, true).is_empty() {
+        return Ok(HttpResponse::NotFound().body(format!(
+            "{} is a directory in baseline {}; list it with /baselines/{}/tree?prefix={}",
+            path, image_id, image_id, path
+        )));
+    }
+    Ok(HttpResponse::NotFound().body(format!("No entry for {} in baseline {}", path, image_id)))
+}
+
+pub async fn lookup(
+    image_id: web::Path<String>,
+    query: web::Query<LookupQuery>,
+    data: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let image_id = image_id.into_inner();
+    let cached = data
+        .cache
+        .load(&data.db, &image_id)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Baseline not found: {}", image_id)))?;
+
+    let path = query.path.trim_matches('/');
+    Ok(HttpResponse::Ok().json(EntryLookup {
+        entry: cached.entry(path).cloned(),
+        image_id,
+        path: path.to_string(),
+        digest: cached.digest.to_string(),
+        hash_algorithm: cached.baseline.hash_algorithm,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::test_util::BaselineBuilder;
+
+    fn paths(nodes: &[Node]) -> Vec<String> {
+        nodes
+            .iter()
+            .map(|node| match node {
+                Node::Directory { path, entries } => format!("{}/ ({})", path, entries),
+                Node::File(entry) => entry.path.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_list() {
+        let entries = BaselineBuilder::new("img")
+            .size(0)
+            .file("etc/ssh/sshd_config", 0o644)
+            .file("etc/hosts", 0o644)
+            .file("etc/ssh/ssh_config", 0o644)
+            .file("etc/ssl/certs/ca.pem", 0o644)
+            .file("etcetera", 0o644)
+            .file("usr/bin/ls", 0o755)
+            .build()
+            .entries;
+
+        assert_eq!(paths(&list(&entries, "", false)), ["etc/ (4)", "etcetera", "usr/ (1)"]);
+        assert_eq!(paths(&list(&entries, "etc", false)), ["etc/hosts", "etc/ssh/ (2)", "etc/ssl/ (1)"]);
+        assert_eq!(
+            paths(&list(&entries, "etc", true)),
+            ["etc/hosts", "etc/ssh/ssh_config", "etc/ssh/sshd_config", "etc/ssl/certs/ca.pem"]
+        );
+        assert!(list(&entries, "etc/hosts", false).is_empty());
+    }
+}
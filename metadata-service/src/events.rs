@@ -0,0 +1,118 @@
+//! Events about stored baselines and reported anomalies, for downstream automation.
+//!
+//! Every baseline stored or deleted through the API is handed to the configured sinks:
+//! NATS (`nats` feature), where it is published as JSON on
+//! `<prefix>.baselines.<image_id>` so re-scans, ticketing or cache warmers
+//! react without polling, and the SIEM forwarders in `forward`. Anomalies
+//! that open a triage record (see `anomalies`) go to the same sinks, on
+//! `<prefix>.reports.<image_id>`. Delivery is best effort: a sink outage is
+//! logged and never fails the request.
+
+use crate::forward::Forwarder;
+#[cfg(feature = "nats")]
+use async_nats::Client;
+use serde::Serialize;
+#[cfg(feature = "nats")]
+use tracing::{info, warn};
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// A full baseline was stored, replacing any previous one for the image
+    BaselineStored { image_id: &'a str, timestamp: &'a str, digest: String, entries: usize },
+    /// A derived baseline was stored as a delta from `extends`
+    DerivedBaselineStored { image_id: &'a str, extends: &'a str, timestamp: &'a str },
+    /// A baseline was deleted with its versions
+    BaselineDeleted { image_id: &'a str },
+    /// A reported anomaly opened a triage record, for the first time or again
+    AnomalyOpened {
+        id: &'a str,
+        host: &'a str,
+        image_id: &'a str,
+        kind: &'a str,
+        path: &'a str,
+        detail: &'a str,
+        occurrences: u64,
+    },
+}
+
+#[cfg(feature = "nats")]
+impl Event<'_> {
+    fn image_id(&self) -> &str {
+        match self {
+            Event::BaselineStored { image_id, .. }
+            | Event::DerivedBaselineStored { image_id, .. }
+            | Event::BaselineDeleted { image_id }
+            | Event::AnomalyOpened { image_id, .. } => image_id,
+        }
+    }
+}
+
+/// Where events go; empty unless configured.
+pub struct Sinks {
+    #[cfg(feature = "nats")]
+    pub nats: Option<Publisher>,
+    pub forwarders: Vec<Forwarder>,
+}
+
+impl Sinks {
+    pub async fn baseline(&self, event: &Event<'_>) {
+        #[cfg(feature = "nats")]
+        if let Some(nats) = &self.nats {
+            nats.publish(integrity_common::BASELINE_EVENTS, event.image_id(), event).await;
+        }
+        for forwarder in &self.forwarders {
+            forwarder.send(event);
+        }
+    }
+
+    pub async fn report(&self, event: &Event<'_>) {
+        #[cfg(feature = "nats")]
+        if let Some(nats) = &self.nats {
+            nats.publish(integrity_common::REPORT_EVENTS, event.image_id(), event).await;
+        }
+        for forwarder in &self.forwarders {
+            forwarder.send(event);
+        }
+    }
+}
+
+#[cfg(feature = "nats")]
+pub struct Publisher {
+    client: Client,
+    prefix: String,
+}
+
+#[cfg(feature = "nats")]
+impl Publisher {
+    pub async fn connect(url: &str, prefix: &str) -> anyhow::Result<Self> {
+        let client = async_nats::connect(url).await?;
+        info!("Publishing events to NATS at {} under {}.*", url, prefix);
+        Ok(Self { client, prefix: prefix.to_string() })
+    }
+
+    async fn publish(&self, kind: &str, image_id: &str, event: &Event<'_>) {
+        let subject = integrity_common::event_subject(&self.prefix, kind, image_id);
+        let payload = match serde_json::to_vec(event) {
+            Ok(payload) => payload,
+            Err(e) => return warn!("Failed to serialize event for {}: {}", subject, e),
+        };
+        if let Err(e) = self.client.publish(subject.clone(), payload.into()).await {
+            warn!("Failed to publish event to {}: {}", subject, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_json() {
+        let event = Event::DerivedBaselineStored { image_id: "app", extends: "base", timestamp: "2024-01-01T00:00:00Z" };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({"event": "derived_baseline_stored", "image_id": "app", "extends": "base", "timestamp": "2024-01-01T00:00:00Z"})
+        );
+    }
+}
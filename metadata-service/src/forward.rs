@@ -0,0 +1,277 @@
+//! Forwarding service events to SIEMs.
+//!
+//! Events (see `events`) are pushed in batches to a Splunk HTTP Event
+//! Collector and/or an Elasticsearch/OpenSearch index, so the central service
+//! feeds existing SIEMs instead of every agent needing its own sink. Each
+//! forwarder buffers up to `--forward-batch-size` events or
+//! `--forward-flush-interval` seconds, whichever comes first. Fields can be
+//! renamed to match an existing schema with `--forward-field from=to`.
+
+use crate::events::Event;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+/// Events buffered per forwarder before new ones are dropped.
+const QUEUE_CAPACITY: usize = 10_000;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ForwardArgs {
+    /// Splunk HTTP Event Collector URL, e.g. https://splunk:8088
+    #[arg(long, requires = "splunk_hec_token")]
+    pub splunk_hec_url: Option<String>,
+
+    /// HEC token, sent as `Authorization: Splunk <token>`
+    #[arg(long)]
+    pub splunk_hec_token: Option<String>,
+
+    /// Splunk index events are written to [default: the token's default index]
+    #[arg(long)]
+    pub splunk_index: Option<String>,
+
+    #[arg(long, default_value = "acropole:event")]
+    pub splunk_sourcetype: String,
+
+    /// Elasticsearch or OpenSearch URL, e.g. https://elastic:9200
+    #[arg(long)]
+    pub elasticsearch_url: Option<String>,
+
+    #[arg(long, default_value = "acropole-events")]
+    pub elasticsearch_index: String,
+
+    /// API key, sent as `Authorization: ApiKey <key>`
+    #[arg(long)]
+    pub elasticsearch_api_key: Option<String>,
+
+    /// Rename a field of forwarded events, e.g. image_id=host.image.id (repeatable)
+    #[arg(long = "forward-field", value_parser = parse_rename)]
+    pub renames: Vec<(String, String)>,
+
+    /// Events sent per request
+    #[arg(long, default_value = "100")]
+    pub forward_batch_size: usize,
+
+    /// Seconds a partial batch waits before it is sent
+    #[arg(long, default_value = "5")]
+    pub forward_flush_interval: u64,
+}
+
+fn parse_rename(rename: &str) -> Result<(String, String), String> {
+    match rename.split_once('=') {
+        Some((from, to)) if !from.is_empty() && !to.is_empty() => Ok((from.to_string(), to.to_string())),
+        _ => Err(format!("expected from=to, got {}", rename)),
+    }
+}
+
+impl ForwardArgs {
+    /// Starts a forwarder for every configured destination.
+    pub fn spawn(&self) -> Vec<Forwarder> {
+        let mut sinks = Vec::new();
+        if let (Some(url), Some(token)) = (&self.splunk_hec_url, &self.splunk_hec_token) {
+            sinks.push(Sink::Splunk {
+                url: url.trim_end_matches('/').to_string(),
+                token: token.clone(),
+                index: self.splunk_index.clone(),
+                sourcetype: self.splunk_sourcetype.clone(),
+            });
+        }
+        if let Some(url) = &self.elasticsearch_url {
+            sinks.push(Sink::Elasticsearch {
+                url: url.trim_end_matches('/').to_string(),
+                index: self.elasticsearch_index.clone(),
+                api_key: self.elasticsearch_api_key.clone(),
+            });
+        }
+        let renames = Arc::new(self.renames.clone());
+        let flush_interval = Duration::from_secs(self.forward_flush_interval.max(1));
+        sinks
+            .into_iter()
+            .map(|sink| Forwarder::spawn(sink, renames.clone(), self.forward_batch_size.max(1), flush_interval))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sink {
+    Splunk { url: String, token: String, index: Option<String>, sourcetype: String },
+    Elasticsearch { url: String, index: String, api_key: Option<String> },
+}
+
+impl Sink {
+    fn name(&self) -> &'static str {
+        match self {
+            Sink::Splunk { .. } => "Splunk HEC",
+            Sink::Elasticsearch { .. } => "Elasticsearch",
+        }
+    }
+
+    /// Request body for a batch: concatenated HEC events, or an NDJSON bulk request.
+    fn body(&self, batch: &[(DateTime<Utc>, Value)]) -> String {
+        let mut body = String::new();
+        for (time, document) in batch {
+            match self {
+                Sink::Splunk { index, sourcetype, .. } => {
+                    let mut event = json!({
+                        "time": time.timestamp_millis() as f64 / 1000.0,
+                        "source": "metadata-service",
+                        "sourcetype": sourcetype,
+                        "event": document,
+                    });
+                    if let Some(index) = index {
+                        event["index"] = json!(index);
+                    }
+                    body.push_str(&event.to_string());
+                }
+                Sink::Elasticsearch { index, .. } => {
+                    let mut document = document.clone();
+                    if let Value::Object(fields) = &mut document {
+                        fields.insert("@timestamp".to_string(), json!(time.to_rfc3339()));
+                    }
+                    body.push_str(&json!({ "index": { "_index": index } }).to_string());
+                    body.push('\n');
+                    body.push_str(&document.to_string());
+                    body.push('\n');
+                }
+            }
+        }
+        body
+    }
+
+    fn request(&self, client: &reqwest::Client, body: String) -> reqwest::RequestBuilder {
+        match self {
+            Sink::Splunk { url, token, .. } => client
+                .post(format!("{}/services/collector/event", url))
+                .header("Authorization", format!("Splunk {}", token))
+                .body(body),
+            Sink::Elasticsearch { url, api_key, .. } => {
+                let request = client.post(format!("{}/_bulk", url)).header("Content-Type", "application/x-ndjson").body(body);
+                match api_key {
+                    Some(key) => request.header("Authorization", format!("ApiKey {}", key)),
+                    None => request,
+                }
+            }
+        }
+    }
+
+    async fn send(&self, client: &reqwest::Client, batch: &[(DateTime<Utc>, Value)]) -> anyhow::Result<()> {
+        let response = self.request(client, self.body(batch)).send().await?.error_for_status()?;
+        // A bulk request succeeds as a whole even when single documents are rejected
+        if let Sink::Elasticsearch { .. } = self {
+            let result: Value = response.json().await?;
+            if result["errors"].as_bool() == Some(true) {
+                anyhow::bail!("some documents were rejected: {}", result["items"]);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Applies `--forward-field` renames to an event's top-level fields.
+fn document(event: &Event<'_>, renames: &[(String, String)]) -> Value {
+    let Ok(Value::Object(fields)) = serde_json::to_value(event) else {
+        return Value::Null;
+    };
+    let mut document = Map::new();
+    for (name, value) in fields {
+        let name = renames.iter().find(|(from, _)| *from == name).map_or(name, |(_, to)| to.clone());
+        document.insert(name, value);
+    }
+    Value::Object(document)
+}
+
+pub struct Forwarder {
+    name: &'static str,
+    sender: mpsc::Sender<(DateTime<Utc>, Value)>,
+    renames: Arc<Vec<(String, String)>>,
+}
+
+impl Forwarder {
+    fn spawn(sink: Sink, renames: Arc<Vec<(String, String)>>, batch_size: usize, flush_interval: Duration) -> Self {
+        let name = sink.name();
+        info!("Forwarding events to {} in batches of {}", name, batch_size);
+        let (sender, mut receiver) = mpsc::channel(QUEUE_CAPACITY);
+        actix_rt::spawn(async move {
+            let client = reqwest::Client::new();
+            let mut batch = Vec::with_capacity(batch_size);
+            let mut ticker = tokio::time::interval(flush_interval);
+            loop {
+                // (flush now, channel closed)
+                let (flush, closed) = tokio::select! {
+                    received = receiver.recv() => match received {
+                        Some(event) => {
+                            batch.push(event);
+                            (batch.len() >= batch_size, false)
+                        }
+                        None => (true, true),
+                    },
+                    _ = ticker.tick() => (true, false),
+                };
+                if flush && !batch.is_empty() {
+                    if let Err(e) = sink.send(&client, &batch).await {
+                        warn!("Failed to forward {} event(s) to {}: {}", batch.len(), sink.name(), e);
+                    }
+                    batch.clear();
+                }
+                if closed {
+                    break;
+                }
+            }
+        });
+        Self { name, sender, renames }
+    }
+
+    /// Queues an event; dropped with a warning while the destination is backed up.
+    pub fn send(&self, event: &Event<'_>) {
+        if self.sender.try_send((Utc::now(), document(event, &self.renames))).is_err() {
+            warn!("Dropping event for {}: forwarding queue is full", self.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch() -> Vec<(DateTime<Utc>, Value)> {
+        let event = Event::DerivedBaselineStored { image_id: "app", extends: "base", timestamp: "2024-01-01T00:00:00Z" };
+        let renames = vec![("image_id".to_string(), "host.image.id".to_string())];
+        vec![(DateTime::from_timestamp(1_700_000_000, 500_000_000).unwrap(), document(&event, &renames))]
+    }
+
+    #[test]
+    fn test_splunk_body() {
+        let sink = Sink::Splunk {
+            url: "https://splunk:8088".to_string(),
+            token: "t".to_string(),
+            index: Some("integrity".to_string()),
+            sourcetype: "acropole:event".to_string(),
+        };
+        let event: Value = serde_json::from_str(&sink.body(&batch())).unwrap();
+        assert_eq!(event["time"], json!(1_700_000_000.5));
+        assert_eq!(event["index"], "integrity");
+        assert_eq!(event["event"]["host.image.id"], "app");
+        assert_eq!(event["event"]["event"], "derived_baseline_stored");
+        assert!(event["event"].get("image_id").is_none());
+    }
+
+    #[test]
+    fn test_elasticsearch_body() {
+        let sink = Sink::Elasticsearch { url: "https://elastic:9200".to_string(), index: "acropole-events".to_string(), api_key: None };
+        let body = sink.body(&batch());
+        let lines: Vec<Value> = body.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], json!({"index": {"_index": "acropole-events"}}));
+        assert_eq!(lines[1]["@timestamp"], "2023-11-14T22:13:20.500+00:00");
+        assert_eq!(lines[1]["extends"], "base");
+    }
+
+    #[test]
+    fn test_parse_rename() {
+        assert_eq!(parse_rename("a=b.c").unwrap(), ("a".to_string(), "b.c".to_string()));
+        assert!(parse_rename("a").is_err());
+        assert!(parse_rename("=b").is_err());
+    }
+}
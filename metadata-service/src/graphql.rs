@@ -0,0 +1,352 @@
+//! GraphQL endpoint (`graphql` feature).
+//!
+//! `POST /graphql` answers queries over the stored baselines, their
+//! `extends` chains, entries and image mappings, the registered agents and
+//! the reported anomalies, so a dashboard can fetch e.g. an image's parent,
+//! the files it changes, the cloud images using it and its hosts' open
+//! anomalies in one request.
+//!
+//! Lists of baselines and anomalies are paged: `first` (at most
+//! [`MAX_PAGE`], the default) items after the id given as `after`, in id
+//! order; the last id of a page is the `after` of the next.
+//!
+//! ```graphql
+//! { baseline(imageId: "nginx-app-v7") { digest parent { imageId } diff(to: "nginx-app-v8") { modified { path } } } }
+//! { baselines(first: 50, after: "nginx-app-v7") { imageId } anomalies(imageId: "nginx-app-v8", state: "open") { id host path } }
+//! ```
+
+use crate::{agents, anomalies, inheritance, mappings};
+use actix_web::{web, HttpResponse};
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject};
+use integrity_common::{AgentRecord, AnomalyRecord, Baseline, FileIntegrityEntry};
+use crate::storage::Store;
+use std::sync::Arc;
+
+pub type IntegritySchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// Deepest query accepted; parent and derived links otherwise nest without bound.
+const MAX_DEPTH: usize = 12;
+
+/// Default and largest page of a list field.
+pub const MAX_PAGE: usize = 100;
+
+/// Seconds without a heartbeat after which an agent is stale.
+struct AgentStaleAfter(u64);
+
+pub fn schema(db: Arc<Store>, agent_stale_after_secs: u64) -> IntegritySchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(db)
+        .data(AgentStaleAfter(agent_stale_after_secs))
+        .limit_depth(MAX_DEPTH)
+        .finish()
+}
+
+pub async fn graphql(schema: web::Data<IntegritySchema>, request: web::Json<async_graphql::Request>) -> HttpResponse {
+    HttpResponse::Ok().json(schema.execute(request.into_inner()).await)
+}
+
+fn db<'a>(ctx: &Context<'a>) -> &'a Store {
+    ctx.data_unchecked::<Arc<Store>>()
+}
+
+fn storage<T>(result: actix_web::Result<T>) -> async_graphql::Result<T> {
+    result.map_err(|e| async_graphql::Error::new(e.to_string()))
+}
+
+fn load(db: &Store, image_id: &str) -> async_graphql::Result<Option<BaselineNode>> {
+    Ok(storage(inheritance::load_baseline(db, image_id))?.map(BaselineNode))
+}
+
+/// The page of `items`, sorted by `id`, that follows the id `after`.
+fn page<T>(mut items: Vec<T>, id: impl Fn(&T) -> &str, first: Option<usize>, after: Option<&str>) -> Vec<T> {
+    items.sort_by(|a, b| id(a).cmp(id(b)));
+    items
+        .into_iter()
+        .filter(|item| after.is_none_or(|after| id(item) > after))
+        .take(first.unwrap_or(MAX_PAGE).min(MAX_PAGE))
+        .collect()
+}
+
+fn load_all(db: &Store, image_ids: Vec<String>) -> async_graphql::Result<Vec<BaselineNode>> {
+    let mut baselines = Vec::new();
+    for image_id in image_ids {
+        baselines.extend(load(db, &image_id)?);
+    }
+    Ok(baselines)
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// The effective baseline of an image, full or derived.
+    async fn baseline(&self, ctx: &Context<'_>, image_id: String) -> async_graphql::Result<Option<BaselineNode>> {
+        load(db(ctx), &image_id)
+    }
+
+    /// A page of the stored baselines, optionally only image ids starting with `prefix`.
+    async fn baselines(
+        &self,
+        ctx: &Context<'_>,
+        prefix: Option<String>,
+        first: Option<usize>,
+        after: Option<String>,
+    ) -> async_graphql::Result<Vec<BaselineNode>> {
+        let db = db(ctx);
+        let mut image_ids = storage(inheritance::image_ids(db))?;
+        image_ids.retain(|image_id| prefix.as_deref().is_none_or(|prefix| image_id.starts_with(prefix)));
+        load_all(db, page(image_ids, String::as_str, first, after.as_deref()))
+    }
+
+    /// The baseline a cloud image (e.g. `aws:ami-0abc`) is mapped to.
+    async fn cloud_image(&self, ctx: &Context<'_>, cloud_image: String) -> async_graphql::Result<Option<BaselineNode>> {
+        match storage(mappings::resolve(db(ctx), &cloud_image))? {
+            Some(image_id) => load(db(ctx), &image_id),
+            None => Ok(None),
+        }
+    }
+
+    /// Registered agents, by host, optionally only those running `image_id` or (not) stale.
+    async fn agents(&self, ctx: &Context<'_>, image_id: Option<String>, stale: Option<bool>) -> async_graphql::Result<Vec<Agent>> {
+        let stale_after = ctx.data_unchecked::<AgentStaleAfter>().0;
+        let mut records = storage(agents::all(db(ctx), chrono::Utc::now().timestamp(), stale_after))?;
+        records.retain(|record| {
+            image_id.as_ref().is_none_or(|image_id| record.image_id == *image_id) && stale.is_none_or(|stale| record.stale == stale)
+        });
+        Ok(records.into_iter().map(Agent::from).collect())
+    }
+
+    /// A page of the reported anomalies, optionally only those of `host`,
+    /// `image_id` or in `state` (open, acknowledged, snoozed or resolved).
+    async fn anomalies(
+        &self,
+        ctx: &Context<'_>,
+        host: Option<String>,
+        image_id: Option<String>,
+        state: Option<String>,
+        first: Option<usize>,
+        after: Option<String>,
+    ) -> async_graphql::Result<Vec<Anomaly>> {
+        let now = chrono::Utc::now().timestamp();
+        let records = storage(anomalies::records(db(ctx), |record| {
+            host.as_ref().is_none_or(|host| record.host == *host)
+                && image_id.as_ref().is_none_or(|image_id| record.image_id == *image_id)
+                && state.as_ref().is_none_or(|state| record.state(now).name() == state)
+        }))?;
+        let records = page(records, |record| record.id.as_str(), first, after.as_deref());
+        Ok(records.iter().map(|record| Anomaly::new(record, now)).collect())
+    }
+}
+
+pub struct BaselineNode(Baseline);
+
+#[Object(name = "Baseline")]
+impl BaselineNode {
+    async fn image_id(&self) -> &str {
+        &self.0.image_id
+    }
+
+    async fn timestamp(&self) -> &str {
+        &self.0.timestamp
+    }
+
+    /// Canonical digest, as served in the baseline's ETag.
+    async fn digest(&self) -> async_graphql::Result<String> {
+        Ok(self.0.digest()?)
+    }
+
+    /// The baseline this one extends, if it is derived.
+    async fn parent(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<BaselineNode>> {
+        match storage(inheritance::parent_of(db(ctx), &self.0.image_id))? {
+            Some(parent) => load(db(ctx), &parent),
+            None => Ok(None),
+        }
+    }
+
+    /// A page of the baselines extending this one directly.
+    async fn derived(&self, ctx: &Context<'_>, first: Option<usize>, after: Option<String>) -> async_graphql::Result<Vec<BaselineNode>> {
+        let db = db(ctx);
+        let children = storage(inheritance::extended_by(db, &self.0.image_id))?;
+        load_all(db, page(children, String::as_str, first, after.as_deref()))
+    }
+
+    /// Cloud images mapped to this baseline.
+    async fn cloud_images(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<String>> {
+        storage(mappings::cloud_images(db(ctx), &self.0.image_id))
+    }
+
+    async fn entry_count(&self) -> usize {
+        self.0.entries.len()
+    }
+
+    /// Entries in path order, optionally below `path_prefix`, at most `limit`.
+    async fn entries(&self, path_prefix: Option<String>, limit: Option<usize>) -> Vec<Entry> {
+        let mut entries: Vec<&FileIntegrityEntry> = self
+            .0
+            .entries
+            .iter()
+            .filter(|e| path_prefix.as_deref().is_none_or(|prefix| e.path.starts_with(prefix)))
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        entries.into_iter().take(limit.unwrap_or(usize::MAX)).map(Entry::from).collect()
+    }
+
+    async fn entry(&self, path: String) -> Option<Entry> {
+        self.0.entries.iter().find(|e| e.path == path).map(Entry::from)
+    }
+
+    /// What changes going from this baseline to the one of image `to`.
+    async fn diff(&self, ctx: &Context<'_>, to: String) -> async_graphql::Result<Option<Diff>> {
+        let Some(BaselineNode(newer)) = load(db(ctx), &to)? else {
+            return Ok(None);
+        };
+        let diff = self.0.diff(&newer);
+        Ok(Some(Diff {
+            added: diff.added.iter().map(Entry::from).collect(),
+            removed: diff.removed.iter().map(Entry::from).collect(),
+            modified: diff
+                .modified
+                .iter()
+                .map(|change| Modified { path: change.new.path.clone(), old: Entry::from(&change.old), new: Entry::from(&change.new) })
+                .collect(),
+        }))
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Entry {
+    path: String,
+    sha512: String,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    size: Option<u64>,
+    /// Seconds since the Unix epoch
+    mtime: Option<i64>,
+    nlink: Option<u64>,
+    /// Where the entry points, if it is a symlink
+    symlink_target: Option<String>,
+}
+
+impl From<&FileIntegrityEntry> for Entry {
+    fn from(entry: &FileIntegrityEntry) -> Self {
+        Self {
+            path: entry.path.clone(),
+            sha512: entry.sha512.clone(),
+            mode: entry.mode,
+            uid: entry.uid,
+            gid: entry.gid,
+            size: entry.size,
+            mtime: entry.mtime,
+            nlink: entry.nlink,
+            symlink_target: entry.symlink_target.clone(),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Modified {
+    path: String,
+    old: Entry,
+    new: Entry,
+}
+
+#[derive(SimpleObject)]
+pub struct Diff {
+    added: Vec<Entry>,
+    removed: Vec<Entry>,
+    modified: Vec<Modified>,
+}
+
+#[derive(SimpleObject)]
+pub struct ScanStatus {
+    /// Seconds since the Unix epoch
+    finished_at: i64,
+    outcome: String,
+    anomalies: usize,
+}
+
+#[derive(SimpleObject)]
+pub struct Agent {
+    host: String,
+    image_id: String,
+    agent_version: String,
+    mode: String,
+    /// Seconds since the Unix epoch
+    registered_at: i64,
+    /// Seconds since the Unix epoch
+    last_seen: i64,
+    last_scan: Option<ScanStatus>,
+    stale: bool,
+}
+
+impl From<AgentRecord> for Agent {
+    fn from(record: AgentRecord) -> Self {
+        Self {
+            host: record.host,
+            image_id: record.image_id,
+            agent_version: record.agent_version,
+            mode: record.mode,
+            registered_at: record.registered_at,
+            last_seen: record.last_seen,
+            last_scan: record.last_scan.map(|scan| ScanStatus { finished_at: scan.finished_at, outcome: scan.outcome, anomalies: scan.anomalies }),
+            stale: record.stale,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Anomaly {
+    id: String,
+    host: String,
+    image_id: String,
+    kind: String,
+    path: String,
+    detail: String,
+    /// Seconds since the Unix epoch
+    first_seen: i64,
+    /// Seconds since the Unix epoch
+    last_seen: i64,
+    occurrences: u64,
+    /// open, acknowledged, snoozed or resolved
+    state: String,
+}
+
+impl Anomaly {
+    fn new(record: &AnomalyRecord, now: i64) -> Self {
+        Self {
+            id: record.id.clone(),
+            host: record.host.clone(),
+            image_id: record.image_id.clone(),
+            kind: record.kind.clone(),
+            path: record.path.clone(),
+            detail: record.detail.clone(),
+            first_seen: record.first_seen,
+            last_seen: record.last_seen,
+            occurrences: record.occurrences,
+            state: record.state(now).name().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::test_util::BaselineBuilder;
+
+    #[actix_rt::test]
+    async fn test_baselines_are_paged() {
+        let db = Arc::new(Store::new(sled::Config::new().temporary(true).open().unwrap(), None));
+        for image_id in ["app-v1", "app-v2", "app-v3", "web-v1"] {
+            let baseline = BaselineBuilder::new(image_id).size(3).build();
+            db.insert(image_id, db.encode(image_id.as_bytes(), &baseline).unwrap()).unwrap();
+        }
+        let schema = schema(db, 300);
+        let response = schema.execute(r#"{ baselines(prefix: "app-", first: 2, after: "app-v1") { imageId } }"#).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data.into_json().unwrap(),
+            serde_json::json!({ "baselines": [{ "imageId": "app-v2" }, { "imageId": "app-v3" }] })
+        );
+    }
+}
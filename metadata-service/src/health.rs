@@ -0,0 +1,52 @@
+//! Liveness and readiness probes.
+//!
+//! `GET /healthz` answers as long as the server handles requests, for
+//! liveness probes. `GET /readyz` also writes a record to sled, flushes it
+//! and reads it back, so a replica whose store cannot persist, e.g. on a full
+//! or read-only volume, answers 503 and is taken out of rotation instead of
+//! failing uploads.
+
+use crate::storage::Store;
+use crate::AppState;
+use actix_web::{web, HttpResponse, Responder};
+use tracing::warn;
+
+const HEALTH_TREE: &str = "health";
+const PROBE_KEY: &[u8] = b"readiness-probe";
+
+pub async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+pub async fn readyz(data: web::Data<AppState>) -> impl Responder {
+    match probe(&data.db).await {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({ "status": "ok" })),
+        Err(e) => {
+            warn!("Not ready: {}", e);
+            HttpResponse::ServiceUnavailable().json(serde_json::json!({ "status": "unavailable", "error": e }))
+        }
+    }
+}
+
+/// Writes, flushes and reads back a record.
+async fn probe(db: &Store) -> Result<(), String> {
+    let tree = db.open_tree(HEALTH_TREE).map_err(|e| format!("cannot open the database: {}", e))?;
+    let written = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default().to_be_bytes();
+    tree.insert(PROBE_KEY, &written).map_err(|e| format!("cannot write to the database: {}", e))?;
+    db.flush_async().await.map_err(|e| format!("cannot flush the database: {}", e))?;
+    match tree.get(PROBE_KEY).map_err(|e| format!("cannot read from the database: {}", e))? {
+        Some(read) if read == written[..] => Ok(()),
+        _ => Err("the database did not return the record just written".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn test_probe() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap(), None);
+        assert_eq!(probe(&db).await, Ok(()));
+    }
+}
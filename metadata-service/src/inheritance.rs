@@ -0,0 +1,224 @@
+//! Baselines that extend another image's baseline.
+//!
+//! A derived baseline is stored as its delta from the parent (see
+//! `DerivedBaseline`) in its own sled tree, and materialized on fetch by
+//! walking the `extends` chain down to a full baseline. Storing a new version
+//! of a base image therefore updates every image derived from it, except
+//! those whose `extends_version` pins one of its stored versions (see
+//! versions.rs).
+
+use crate::storage::Store;
+use crate::{scheduled, versions, AppState};
+use actix_web::{web, HttpResponse, Responder};
+use integrity_common::{Baseline, DerivedBaseline};
+use sled::Db;
+use tracing::{info, warn};
+
+const DERIVED_TREE: &str = "derived_baselines";
+
+/// Longest `extends` chain followed before giving up.
+const MAX_DEPTH: usize = 16;
+
+fn internal<E: std::fmt::Debug + std::fmt::Display + 'static>(e: E) -> actix_web::Error {
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+fn get_derived(db: &Store, image_id: &str) -> actix_web::Result<Option<DerivedBaseline>> {
+    let tree = db.open_tree(DERIVED_TREE).map_err(internal)?;
+    match tree.get(image_id.as_bytes()).map_err(internal)? {
+        Some(stored) => Ok(Some(db.decode(image_id.as_bytes(), &stored)?)),
+        None => Ok(None),
+    }
+}
+
+/// Loads the effective baseline for an image, full or derived, including
+/// versions staged to take effect by now.
+pub fn load_baseline(db: &Store, image_id: &str) -> actix_web::Result<Option<Baseline>> {
+    let now = chrono::Utc::now().timestamp();
+    let mut deltas = Vec::new();
+    let mut current = image_id.to_string();
+    let mut pinned = None;
+    let base = loop {
+        if let Some(version) = pinned {
+            let Some(baseline) = versions::load(db, &current, version)? else {
+                return Err(internal(format!("baseline {} extends missing version {} of {}", image_id, version, current)));
+            };
+            break baseline;
+        }
+        if let Some(baseline) = scheduled::active(db, &current, now)? {
+            break baseline;
+        }
+        if let Some(stored) = db.get(current.as_bytes()).map_err(internal)? {
+            break db.decode::<Baseline>(current.as_bytes(), &stored)?;
+        }
+        let Some(derived) = get_derived(db, &current)? else {
+            if deltas.is_empty() {
+                return Ok(None);
+            }
+            return Err(internal(format!("baseline {} extends missing baseline {}", image_id, current)));
+        };
+        if deltas.len() == MAX_DEPTH {
+            return Err(internal(format!("baseline {} extends more than {} levels deep", image_id, MAX_DEPTH)));
+        }
+        current = derived.extends.clone();
+        pinned = derived.extends_version;
+        deltas.push(derived);
+    };
+
+    Ok(Some(deltas.iter().rev().fold(base, |parent, delta| delta.materialize(&parent))))
+}
+
+/// Whether a full, derived or staged baseline is stored for the image.
+pub fn baseline_exists(db: &Db, image_id: &str) -> bool {
+    db.contains_key(image_id.as_bytes()).unwrap_or(false)
+        || scheduled::is_staged(db, image_id)
+        || db
+            .open_tree(DERIVED_TREE)
+            .and_then(|tree| tree.contains_key(image_id.as_bytes()))
+            .unwrap_or(false)
+}
+
+/// The image a derived baseline extends, or None for a full or unknown one.
+pub fn parent_of(db: &Store, image_id: &str) -> actix_web::Result<Option<String>> {
+    Ok(get_derived(db, image_id)?.map(|derived| derived.extends))
+}
+
+/// Image ids of all stored baselines, full and derived, sorted.
+pub fn image_ids(db: &Db) -> actix_web::Result<Vec<String>> {
+    let mut ids = Vec::new();
+    for tree in [(**db).clone(), db.open_tree(DERIVED_TREE).map_err(internal)?] {
+        for key in tree.iter().keys() {
+            ids.push(String::from_utf8_lossy(&key.map_err(internal)?).into_owned());
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+/// Derived baselines that extend `image_id` directly.
+pub fn extended_by(db: &Store, image_id: &str) -> actix_web::Result<Vec<String>> {
+    let tree = db.open_tree(DERIVED_TREE).map_err(internal)?;
+    let mut children = Vec::new();
+    for item in tree.iter() {
+        let (key, stored) = item.map_err(internal)?;
+        let derived: DerivedBaseline = db.decode(&key, &stored)?;
+        if derived.extends == image_id {
+            children.push(derived.image_id);
+        }
+    }
+    Ok(children)
+}
+
+/// Number of derived baselines stored.
+pub fn derived_count(db: &Db) -> actix_web::Result<usize> {
+    Ok(db.open_tree(DERIVED_TREE).map_err(internal)?.len())
+}
+
+/// Background job logging derived baselines whose `extends` chain no longer
+/// materializes.
+pub fn check_chains(db: &Store) -> anyhow::Result<()> {
+    let tree = db.open_tree(DERIVED_TREE)?;
+    let mut broken = 0;
+    for key in tree.iter().keys() {
+        let image_id = String::from_utf8_lossy(&key?).into_owned();
+        if let Err(e) = load_baseline(db, &image_id) {
+            warn!("Derived baseline {} is broken: {}", image_id, e);
+            broken += 1;
+        }
+    }
+    info!("Checked {} derived baseline(s), {} broken", tree.len(), broken);
+    Ok(())
+}
+
+/// Drops a derived baseline replaced by a full one.
+pub fn remove_derived(db: &Db, image_id: &str) -> actix_web::Result<()> {
+    db.open_tree(DERIVED_TREE)
+        .and_then(|tree| tree.remove(image_id.as_bytes()))
+        .map_err(internal)?;
+    Ok(())
+}
+
+pub async fn store_derived(
+    derived: web::Json<DerivedBaseline>,
+    data: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let derived = derived.into_inner();
+
+    info!("Storing baseline for image {} extending {}", derived.image_id, derived.extends);
+
+    // Reject chains that would loop back to this image
+    let mut ancestor = derived.extends.clone();
+    for _ in 0..MAX_DEPTH {
+        if ancestor == derived.image_id {
+            return Ok(HttpResponse::BadRequest().body(format!(
+                "baseline {} cannot extend itself (through {})",
+                derived.image_id, derived.extends
+            )));
+        }
+        match get_derived(&data.db, &ancestor)? {
+            Some(parent) => ancestor = parent.extends,
+            None => break,
+        }
+    }
+
+    let parent = match derived.extends_version {
+        Some(version) => versions::load(&data.db, &derived.extends, version)?,
+        None => load_baseline(&data.db, &derived.extends)?,
+    };
+    let Some(parent) = parent else {
+        let version = derived.extends_version.map(|version| format!(" version {}", version)).unwrap_or_default();
+        return Ok(HttpResponse::BadRequest().body(format!("Parent baseline not found: {}{}", derived.extends, version)));
+    };
+
+    let violations = derived.materialize(&parent).validate();
+    if !violations.is_empty() {
+        warn!("Rejecting invalid baseline for image {}: {} violation(s)", derived.image_id, violations.len());
+        return Ok(HttpResponse::BadRequest().json(violations));
+    }
+
+    let serialized = data.db.encode(derived.image_id.as_bytes(), &derived)?;
+    let tree = data.db.open_tree(DERIVED_TREE).map_err(internal)?;
+    tree.insert(derived.image_id.as_bytes(), serialized).map_err(internal)?;
+    // A derived baseline replaces any full baseline stored under the same image_id
+    data.db.remove(derived.image_id.as_bytes()).map_err(internal)?;
+    scheduled::supersede(&data.db, &derived.image_id, chrono::Utc::now().timestamp())?;
+    data.cache.invalidate();
+    data.db.flush_async().await.map_err(internal)?;
+
+    let event = crate::events::Event::DerivedBaselineStored {
+        image_id: &derived.image_id,
+        extends: &derived.extends,
+        timestamp: &derived.timestamp,
+    };
+    data.events.baseline(&event).await;
+
+    Ok(HttpResponse::Created().json(derived))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::test_util::BaselineBuilder;
+
+    #[test]
+    fn test_pinned_parent_version() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap(), None);
+        let parent = BaselineBuilder::new("base").file("usr/bin/base", 0o755).build();
+        let updated = BaselineBuilder::new("base").file("usr/bin/base", 0o755).file("usr/bin/new", 0o755).build();
+        for (digest, base) in [("d1", &parent), ("d2", &updated)] {
+            versions::record(&db, base, digest, 1000).unwrap();
+            db.insert(b"base", db.encode(b"base", base).unwrap()).unwrap();
+        }
+        let app = BaselineBuilder::new("app").file("usr/bin/base", 0o755).file("usr/bin/app", 0o755).build();
+        let tree = db.open_tree(DERIVED_TREE).unwrap();
+        for (image_id, extends_version) in [("app", None), ("app-pinned", Some(1)), ("app-missing", Some(7))] {
+            let derived = DerivedBaseline { image_id: image_id.to_string(), extends_version, ..DerivedBaseline::from_parent(&parent, &app) };
+            tree.insert(image_id.as_bytes(), db.encode(image_id.as_bytes(), &derived).unwrap()).unwrap();
+        }
+
+        let entries = |image_id| load_baseline(&db, image_id).unwrap().unwrap().entries.len();
+        assert_eq!((entries("app"), entries("app-pinned")), (updated.entries.len() + 1, parent.entries.len() + 1));
+        assert!(load_baseline(&db, "app-missing").is_err());
+        assert!(baseline_exists(&db, "app-pinned") && !baseline_exists(&db, "other"));
+    }
+}
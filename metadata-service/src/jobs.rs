@@ -0,0 +1,100 @@
+//! Background jobs scheduled once per interval.
+//!
+//! The service ticks through the job list, and a job only runs when its lease
+//! is taken: a sled record of the holder and an expiry one job interval
+//! ahead, taken with compare-and-swap and never released early. As the lease
+//! is stored with the data, a restarted service does not run jobs again
+//! before their interval is over. sled locks its database to one process, so
+//! the lease does not coordinate replicas: each replica has its own database
+//! and runs every job on it. Jobs run on the blocking thread pool, one at a
+//! time, so a long one delays the next without stalling request handling.
+
+use crate::storage::Store;
+use serde::{Deserialize, Serialize};
+use sled::Tree;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+const LEASES_TREE: &str = "job_leases";
+
+/// How often replicas check for jobs that are due.
+const TICK: Duration = Duration::from_secs(30);
+
+pub struct Job {
+    pub name: &'static str,
+    pub interval: Duration,
+    pub run: fn(&Store) -> anyhow::Result<()>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Lease {
+    holder: String,
+    /// Unix seconds
+    expires_at: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Takes the lease on `job` for `ttl` if no other holder has an unexpired one.
+fn try_acquire(tree: &Tree, job: &str, holder: &str, now: u64, ttl: Duration) -> anyhow::Result<bool> {
+    let current = tree.get(job)?;
+    if let Some(serialized) = &current {
+        let lease: Lease = serde_json::from_slice(serialized)?;
+        if lease.expires_at > now {
+            return Ok(false);
+        }
+    }
+    let lease = Lease { holder: holder.to_string(), expires_at: now + ttl.as_secs() };
+    // Fails if another replica took the lease since we read it
+    Ok(tree.compare_and_swap(job, current, Some(serde_json::to_vec(&lease)?))?.is_ok())
+}
+
+/// Runs `jobs` whenever their lease is free.
+pub fn spawn(db: Arc<Store>, holder: String, jobs: Vec<Job>) -> anyhow::Result<()> {
+    let tree = db.open_tree(LEASES_TREE)?;
+    info!("Scheduling {} background job(s) as replica {}", jobs.len(), holder);
+    actix_rt::spawn(async move {
+        let mut ticker = tokio::time::interval(TICK);
+        loop {
+            ticker.tick().await;
+            for job in &jobs {
+                match try_acquire(&tree, job.name, &holder, now(), job.interval) {
+                    Ok(true) => {
+                        debug!("Running job {}", job.name);
+                        let (db, run) = (db.clone(), job.run);
+                        match tokio::task::spawn_blocking(move || run(&db)).await {
+                            Ok(Ok(())) => {}
+                            Ok(Err(e)) => warn!("Job {} failed: {}", job.name, e),
+                            Err(e) => warn!("Job {} panicked: {}", job.name, e),
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(e) => warn!("Failed to take lease for job {}: {}", job.name, e),
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lease_is_exclusive_until_expiry() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree(LEASES_TREE).unwrap();
+        let ttl = Duration::from_secs(60);
+
+        assert!(try_acquire(&tree, "gc", "a", 1000, ttl).unwrap());
+        assert!(!try_acquire(&tree, "gc", "b", 1030, ttl).unwrap());
+        // Not even the holder runs the job again before the interval is over
+        assert!(!try_acquire(&tree, "gc", "a", 1059, ttl).unwrap());
+        assert!(try_acquire(&tree, "other", "b", 1030, ttl).unwrap());
+        assert!(try_acquire(&tree, "gc", "b", 1060, ttl).unwrap());
+    }
+}
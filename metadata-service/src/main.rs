@@ -1,9 +1,38 @@
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+mod admission;
+mod agent_config;
+mod agents;
+mod anomalies;
+#[cfg(feature = "ldap")]
+mod auth;
+mod cache;
+mod catalog;
+mod cbor;
+mod entries;
+mod events;
+mod forward;
+mod health;
+#[cfg(feature = "graphql")]
+mod graphql;
+mod inheritance;
+mod jobs;
+mod mappings;
+mod metrics;
+mod report;
+mod scheduled;
+mod signatures;
+mod snapshots;
+mod stats;
+mod storage;
+mod streaming;
+mod tls;
+mod versions;
+mod yara;
+
+use actix_web::{guard, http::header, middleware, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
 use clap::Parser;
-use integrity_common::Baseline;
-use sled::Db;
+use integrity_common::{Baseline, DerivedBaseline};
 use std::sync::Arc;
-use tracing::info;
+use tracing::{info, warn};
 
 #[derive(Parser, Debug)]
 #[command(name = "metadata-service")]
@@ -17,39 +46,228 @@ struct Args {
 
     #[arg(long, default_value = "./metadata-db")]
     db_path: String,
-}
 
+    /// Whether the admission webhook rejects non-compliant pods or only warns
+    #[arg(long, value_enum, default_value = "enforce")]
+    admission_mode: admission::AdmissionMode,
+
+    /// Baselines kept materialized in memory for fetches (0 disables the cache)
+    #[arg(long, default_value = "64")]
+    baseline_cache_size: usize,
+
+    /// Name this replica holds background job leases under [default: $HOSTNAME:pid]
+    #[arg(long)]
+    replica_id: Option<String>,
+
+    /// Seconds between checks that every derived baseline still materializes
+    #[arg(long, default_value = "3600")]
+    chain_check_interval: u64,
+
+    /// Directory of YARA rules (.yar/.yara) served to agents at /yara-rules
+    #[arg(long)]
+    yara_rules_dir: Option<std::path::PathBuf>,
+
+    /// How baselines are serialized in the database; records in either format stay readable
+    #[arg(long, value_enum, default_value = "json")]
+    storage_format: storage::RecordFormat,
+
+    #[command(flatten)]
+    forward: forward::ForwardArgs,
+
+    #[command(flatten)]
+    encryption: storage::EncryptionArgs,
+
+    #[command(flatten)]
+    snapshots: snapshots::SnapshotArgs,
+
+    #[command(flatten)]
+    signing: signatures::SigningArgs,
+
+    #[command(flatten)]
+    tls: tls::TlsArgs,
+
+    #[command(flatten)]
+    admin: catalog::AdminArgs,
+
+    #[command(flatten)]
+    agents: agents::AgentArgs,
+
+    #[cfg(feature = "ldap")]
+    #[command(flatten)]
+    ldap: auth::LdapArgs,
+
+    /// NATS server to publish baseline events to, e.g. nats://127.0.0.1:4222
+    #[cfg(feature = "nats")]
+    #[arg(long)]
+    nats_url: Option<String>,
 
+    /// First token of the subjects events are published on
+    #[cfg(feature = "nats")]
+    #[arg(long, default_value = "integrity")]
+    nats_subject_prefix: String,
+}
 
 struct AppState {
-    db: Arc<Db>,
+    db: Arc<storage::Store>,
+    admission_mode: admission::AdmissionMode,
+    cache: cache::BaselineCache,
+    events: events::Sinks,
+    yara_rules_dir: Option<std::path::PathBuf>,
+    snapshot_retention: snapshots::SnapshotArgs,
+    signer: Option<integrity_common::BaselineSigner>,
+    metrics: metrics::RequestMetrics,
+    admin_token: Option<String>,
+    agent_stale_after_secs: u64,
 }
 
-async fn store_baseline(
-    baseline: web::Json<Baseline>,
-    data: web::Data<AppState>,
-) -> actix_web::Result<impl Responder> {
-    let baseline = baseline.into_inner();
-    let image_id = baseline.image_id.clone();
+#[cfg(test)]
+impl AppState {
+    /// State for handler tests, over a temporary database.
+    fn for_tests(admin_token: Option<&str>) -> Self {
+        Self {
+            db: Arc::new(storage::Store::new(sled::Config::new().temporary(true).open().unwrap(), None)),
+            admission_mode: admission::AdmissionMode::Enforce,
+            cache: cache::BaselineCache::new(16),
+            events: events::Sinks {
+                #[cfg(feature = "nats")]
+                nats: None,
+                forwarders: Vec::new(),
+            },
+            yara_rules_dir: None,
+            snapshot_retention: snapshots::SnapshotArgs { snapshots_per_host: 10, snapshot_max_age_days: 90 },
+            signer: None,
+            metrics: metrics::RequestMetrics::default(),
+            admin_token: admin_token.map(str::to_string),
+            agent_stale_after_secs: 300,
+        }
+    }
+}
+
+/// Validates and stores a full baseline, replacing any derived one under its
+/// image_id. Returns the baseline's digest, or the response rejecting it.
+async fn save_baseline(data: &AppState, baseline: &Baseline) -> actix_web::Result<Result<String, HttpResponse>> {
+    let image_id = &baseline.image_id;
 
-    info!("Storing baseline for image: {}", image_id);
+    let violations = baseline.validate();
+    if !violations.is_empty() {
+        warn!("Rejecting invalid baseline for image {}: {} violation(s)", image_id, violations.len());
+        return Ok(Err(HttpResponse::BadRequest().json(violations)));
+    }
 
-    let serialized = serde_json::to_vec(&baseline)
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+    let digest = baseline.digest().map_err(actix_web::error::ErrorInternalServerError)?;
+    let version = versions::record(&data.db, baseline, &digest, chrono::Utc::now().timestamp())?;
+
+    let serialized = data.db.encode(image_id.as_bytes(), baseline)?;
 
     data.db
         .insert(image_id.as_bytes(), serialized)
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    inheritance::remove_derived(&data.db, image_id)?;
+    scheduled::supersede(&data.db, image_id, chrono::Utc::now().timestamp())?;
+    data.cache.invalidate();
 
     data.db
         .flush_async()
         .await
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    info!("Stored baseline for image {} as version {}", image_id, version);
+
+    let event = events::Event::BaselineStored {
+        image_id,
+        timestamp: &baseline.timestamp,
+        digest: digest.clone(),
+        entries: baseline.entries.len(),
+    };
+    data.events.baseline(&event).await;
+
+    Ok(Ok(digest))
+}
 
-    Ok(HttpResponse::Created().json(baseline))
+#[derive(Debug, serde::Deserialize)]
+struct StoreQuery {
+    /// Unix time the baseline takes effect; the previous version is served until then
+    effective_from: Option<i64>,
+}
+
+/// Stages an uploaded baseline if it takes effect later, or else stores it
+/// and responds with `stored` applied to its digest.
+async fn accept_baseline(
+    data: &AppState,
+    baseline: &Baseline,
+    effective_from: Option<i64>,
+    stored: impl FnOnce(&str) -> HttpResponse,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(effective_from) = effective_from.filter(|time| *time > chrono::Utc::now().timestamp()) {
+        return scheduled::stage(data, baseline, effective_from).await;
+    }
+    info!("Storing baseline for image: {}", baseline.image_id);
+
+    match save_baseline(data, baseline).await? {
+        Ok(digest) => Ok(stored(&digest)),
+        Err(rejection) => Ok(rejection),
+    }
+}
+
+async fn store_baseline(
+    baseline: web::Json<Baseline>,
+    query: web::Query<StoreQuery>,
+    data: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let baseline = baseline.into_inner();
+    accept_baseline(&data, &baseline, query.effective_from, |_| HttpResponse::Created().json(&baseline)).await
+}
+
+/// Largest delta `PATCH /baselines/{image_id}` takes; clients upload larger
+/// changes in full.
+const MAX_DELTA_SIZE: usize = 64 * 1024 * 1024;
+
+/// Stores a new version of a baseline uploaded as its delta on the stored
+/// version of `extends`, whose digest the client passes in `If-Match`. The
+/// service rebuilds and stores the full baseline; a stale digest gets 412 so
+/// the client can fall back to a full upload.
+async fn store_baseline_delta(
+    req: HttpRequest,
+    image_id: web::Path<String>,
+    delta: web::Json<DerivedBaseline>,
+    data: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let image_id = image_id.into_inner();
+    let delta = delta.into_inner();
+    if delta.image_id != image_id {
+        return Ok(HttpResponse::BadRequest().body(format!("Delta is for image {}, not {}", delta.image_id, image_id)));
+    }
+    let Some(if_match) = req.headers().get(header::IF_MATCH).and_then(|v| v.to_str().ok()) else {
+        return Ok(HttpResponse::PreconditionRequired().body("If-Match with the digest of the baseline the delta applies to is required"));
+    };
+
+    let previous = match data.cache.load(&data.db, &delta.extends)? {
+        Some(previous) if if_match.split(',').any(|tag| tag.trim().trim_matches('"') == &*previous.digest) => previous,
+        _ => {
+            warn!("Rejecting delta for image {}: baseline {} is not at {}", image_id, delta.extends, if_match);
+            return Ok(HttpResponse::PreconditionFailed().body(format!("Baseline {} has changed or does not exist", delta.extends)));
+        }
+    };
+
+    info!(
+        "Storing baseline for image {} from a delta on {}: {} entries added or changed, {} removed",
+        image_id,
+        delta.extends,
+        delta.entries.len(),
+        delta.removed.len()
+    );
+    let baseline = delta.materialize(&previous.baseline);
+    let digest = match save_baseline(&data, &baseline).await? {
+        Ok(digest) => digest,
+        Err(rejection) => return Ok(rejection),
+    };
+
+    Ok(HttpResponse::Created()
+        .insert_header((header::ETAG, format!("\"{}\"", digest)))
+        .json(serde_json::json!({ "image_id": image_id, "digest": digest, "entries": baseline.entries.len() })))
 }
 
 async fn get_baseline(
+    req: HttpRequest,
     image_id: web::Path<String>,
     data: web::Data<AppState>,
 ) -> actix_web::Result<impl Responder> {
@@ -57,15 +275,29 @@ async fn get_baseline(
 
     info!("Retrieving baseline for image: {}", image_id);
 
-    let serialized = data.db
-        .get(image_id.as_bytes())
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?
+    let cached = data
+        .cache
+        .load(&data.db, &image_id)?
         .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Baseline not found: {}", image_id)))?;
 
-    let baseline: Baseline = serde_json::from_slice(&serialized)
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+    // The canonical digest identifies the baseline content regardless of serialization
+    let etag = format!("\"{}\"", cached.digest);
+    let not_modified = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|tag| tag.trim() == etag));
+    if not_modified {
+        return Ok(HttpResponse::NotModified().insert_header((header::ETAG, etag)).finish());
+    }
+    if cbor::accepts_cbor(&req) {
+        return cbor::respond(&cached, etag);
+    }
+    if streaming::accepts_ndjson(&req) {
+        return streaming::respond(&cached, etag);
+    }
 
-    Ok(HttpResponse::Ok().json(baseline))
+    Ok(HttpResponse::Ok().insert_header((header::ETAG, etag)).json(&*cached.baseline))
 }
 
 #[actix_web::main]
@@ -80,20 +312,141 @@ async fn main() -> std::io::Result<()> {
     let db = sled::open(&args.db_path)
         .expect("Failed to open database");
 
+    let events = events::Sinks {
+        #[cfg(feature = "nats")]
+        nats: match &args.nats_url {
+            Some(url) => Some(
+                events::Publisher::connect(url, &args.nats_subject_prefix)
+                    .await
+                    .map_err(|e| std::io::Error::other(format!("Failed to connect to NATS at {}: {}", url, e)))?,
+            ),
+            None => None,
+        },
+        forwarders: args.forward.spawn(),
+    };
+
+    let tls = args.tls.server_config().map_err(std::io::Error::other)?;
+    let master_key = args.encryption.master_key().map_err(std::io::Error::other)?;
+    let db = Arc::new(storage::Store::new(db, master_key).with_format(args.storage_format));
+    let replica_id = args.replica_id.clone().unwrap_or_else(|| {
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+        format!("{}:{}", host, std::process::id())
+    });
+    let background_jobs = vec![
+        jobs::Job {
+            name: "chain-check",
+            interval: std::time::Duration::from_secs(args.chain_check_interval),
+            run: inheritance::check_chains,
+        },
+        jobs::Job { name: "baseline-activation", interval: scheduled::ACTIVATION_INTERVAL, run: scheduled::activate_due },
+    ];
+    jobs::spawn(db.clone(), replica_id, background_jobs).map_err(std::io::Error::other)?;
+
     let app_state = web::Data::new(AppState {
-        db: Arc::new(db),
+        db,
+        admission_mode: args.admission_mode,
+        cache: cache::BaselineCache::new(args.baseline_cache_size),
+        events,
+        yara_rules_dir: args.yara_rules_dir.clone(),
+        snapshot_retention: args.snapshots.clone(),
+        signer: args.signing.signer().map_err(std::io::Error::other)?,
+        metrics: metrics::RequestMetrics::default(),
+        admin_token: args.admin.admin_token().map_err(std::io::Error::other)?,
+        agent_stale_after_secs: args.agents.agent_stale_after_secs,
     });
 
-    HttpServer::new(move || {
-        App::new()
+    #[cfg(feature = "graphql")]
+    let schema = web::Data::new(graphql::schema(app_state.db.clone(), app_state.agent_stale_after_secs));
+    #[cfg(feature = "ldap")]
+    let ldap = web::Data::new(args.ldap.auth());
+
+    let server = HttpServer::new(move || {
+        let app = App::new()
+            // Responses are compressed for clients that send Accept-Encoding
+            .wrap(middleware::Compress::default())
             .app_data(app_state.clone())
             .service(
                 web::scope("/baselines")
+                    .route("", web::post().guard(guard::fn_guard(streaming::is_ndjson_upload)).to(streaming::store_baseline))
+                    .route("", web::post().guard(guard::fn_guard(cbor::is_cbor_upload)).to(cbor::store_baseline))
                     .route("", web::post().to(store_baseline))
-                    .route("/{image_id}", web::get().to(get_baseline))
+                    .route("", web::get().to(catalog::list))
+                    .route("/derived", web::post().to(inheritance::store_derived))
+                    .service(
+                        web::resource("/{image_id}")
+                            .app_data(web::JsonConfig::default().limit(MAX_DELTA_SIZE))
+                            .route(web::get().to(get_baseline))
+                            .route(web::patch().to(store_baseline_delta))
+                            .route(web::delete().to(catalog::delete)),
+                    )
+                    .route("/{image_id}/digests", web::get().to(entries::digests))
+                    .route("/{image_id}/tree", web::get().to(entries::tree))
+                    .route("/{image_id}/stats", web::get().to(stats::baseline_stats))
+                    .route("/{image_id}/summary", web::get().to(catalog::baseline_summary))
+                    .route("/{image_id}/scheduled", web::get().to(scheduled::list))
+                    .route("/{image_id}/scheduled/{effective_from}", web::delete().to(scheduled::cancel))
+                    .route("/{image_id}/versions", web::get().to(versions::list))
+                    .route("/{image_id}/versions/{version}", web::get().to(versions::get))
+                    .route("/{image_id}/diff", web::get().to(versions::diff))
+                    .route("/{image_id}/signatures", web::get().to(signatures::list))
+                    .route("/{image_id}/signatures", web::post().to(signatures::add))
+                    .route("/{image_id}/entries", web::get().to(entries::lookup))
+                    .route("/{image_id}/entries/{path:.*}", web::get().to(entries::entry))
+                    .route("/{from}/diff/{to}", web::get().to(report::diff))
+            )
+            .service(
+                web::scope("/image-mappings")
+                    .route("/{cloud_image}", web::put().to(mappings::put_mapping))
+                    .route("/{cloud_image}", web::get().to(mappings::get_mapping))
+            )
+            .service(
+                web::scope("/anomalies")
+                    .route("", web::post().to(anomalies::report))
+                    .route("", web::get().to(anomalies::list))
+                    .route("/fleet/{image_id}", web::get().to(anomalies::fleet))
+                    .route("/{id}", web::get().to(anomalies::get))
+                    .route("/{id}/triage", web::post().to(anomalies::triage))
             )
-    })
-    .bind((args.host, args.port))?
-    .run()
-    .await
+            .service(
+                web::scope("/agent-config")
+                    .route("/effective", web::get().to(agent_config::effective))
+                    .route("/{scope}/{name}", web::put().to(agent_config::put))
+                    .route("/{scope}/{name}", web::get().to(agent_config::get))
+                    .route("/{scope}/{name}/rollback", web::post().to(agent_config::rollback))
+            )
+            .service(
+                web::scope("/agents")
+                    .route("", web::post().to(agents::register))
+                    .route("", web::get().to(agents::list))
+                    .route("/{host}/heartbeat", web::post().to(agents::heartbeat))
+            )
+            .service(
+                web::scope("/snapshots")
+                    .app_data(web::JsonConfig::default().limit(snapshots::MAX_SNAPSHOT_SIZE))
+                    .route("", web::post().to(snapshots::upload))
+                    .route("", web::get().to(snapshots::list))
+                    .route("/{id}", web::get().to(snapshots::get))
+                    .route("/{id}/diff", web::get().to(snapshots::diff))
+            )
+            .route("/admission/validate", web::post().to(admission::validate))
+            .route("/cache/baselines", web::get().to(cache::stats))
+            .route("/metrics", web::get().to(metrics::metrics))
+            .route("/healthz", web::get().to(health::healthz))
+            .route("/readyz", web::get().to(health::readyz))
+            .route("/yara-rules", web::get().to(yara::rules));
+        #[cfg(feature = "graphql")]
+        let app = app.app_data(schema.clone()).route("/graphql", web::post().to(graphql::graphql));
+        #[cfg(feature = "ldap")]
+        let app = app
+            .app_data(ldap.clone())
+            .route("/auth/whoami", web::get().to(auth::whoami))
+            .wrap(actix_web::middleware::from_fn(auth::authorize));
+        // Outermost, so requests the other middleware rejects are counted too
+        app.wrap(middleware::from_fn(metrics::track))
+    });
+    let server = match tls {
+        Some(config) => server.bind_rustls_0_23((args.host, args.port), config)?,
+        None => server.bind((args.host, args.port))?,
+    };
+    server.run().await
 }
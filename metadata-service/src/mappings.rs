@@ -0,0 +1,63 @@
+//! Cloud image to baseline mapping table.
+//!
+//! Agents started with `--image-id auto` only know their cloud image (e.g. an
+//! AMI ID). The mapping table, stored in its own sled tree, resolves keys like
+//! `aws:ami-0abc...` to the image_id of the baseline to verify against.
+
+use crate::AppState;
+use actix_web::{web, HttpResponse, Responder};
+use integrity_common::ImageMapping;
+use tracing::info;
+
+const MAPPINGS_TREE: &str = "image_mappings";
+
+/// The image_id a cloud image is mapped to.
+pub fn resolve(db: &sled::Db, cloud_image: &str) -> actix_web::Result<Option<String>> {
+    let tree = db.open_tree(MAPPINGS_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    let image_id = tree.get(cloud_image.as_bytes()).map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(image_id.map(|id| String::from_utf8_lossy(&id).into_owned()))
+}
+
+/// Cloud images mapped to `image_id`, sorted.
+#[cfg(feature = "graphql")]
+pub fn cloud_images(db: &sled::Db, image_id: &str) -> actix_web::Result<Vec<String>> {
+    let tree = db.open_tree(MAPPINGS_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    let mut cloud_images = Vec::new();
+    for mapping in tree.iter() {
+        let (cloud_image, mapped) = mapping.map_err(actix_web::error::ErrorInternalServerError)?;
+        if *mapped == *image_id.as_bytes() {
+            cloud_images.push(String::from_utf8_lossy(&cloud_image).into_owned());
+        }
+    }
+    Ok(cloud_images)
+}
+
+pub async fn put_mapping(
+    cloud_image: web::Path<String>,
+    mapping: web::Json<ImageMapping>,
+    data: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let cloud_image = cloud_image.into_inner();
+    let mapping = mapping.into_inner();
+
+    info!("Mapping cloud image {} to baseline {}", cloud_image, mapping.image_id);
+
+    let tree = data.db.open_tree(MAPPINGS_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    tree.insert(cloud_image.as_bytes(), mapping.image_id.as_bytes())
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    data.db.flush_async().await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(mapping))
+}
+
+pub async fn get_mapping(
+    cloud_image: web::Path<String>,
+    data: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let cloud_image = cloud_image.into_inner();
+
+    let image_id = resolve(&data.db, &cloud_image)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("No mapping for cloud image: {}", cloud_image)))?;
+
+    Ok(HttpResponse::Ok().json(ImageMapping { image_id }))
+}
@@ -0,0 +1,173 @@
+//! Prometheus metrics.
+//!
+//! `GET /metrics` serves, in the Prometheus text format, request counts by
+//! route, method and status with a latency histogram per route and method,
+//! the number of stored baselines, the database size on disk and a histogram
+//! of sled flush durations, so operators can alert on a store that is slow
+//! to persist or running out of space. Routes are reported by their pattern,
+//! e.g. `/baselines/{image_id}`, so label cardinality stays bounded.
+
+use crate::{inheritance, AppState};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse, Responder};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the latency buckets, in seconds.
+const BUCKETS: [f64; 12] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Durations counted into [`BUCKETS`].
+#[derive(Debug, Default, Clone)]
+pub struct Histogram {
+    /// Observations up to each bucket's bound, not cumulative
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    /// The `_bucket`, `_sum` and `_count` samples of `name`, with `labels`
+    /// (already formatted, without braces) added to each.
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(self.buckets) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, separator, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, separator, self.count);
+        let labels = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+/// Requests served, by route pattern and method.
+#[derive(Default)]
+pub struct RequestMetrics {
+    /// (route, method) -> latencies
+    latencies: Mutex<BTreeMap<(String, String), Histogram>>,
+    /// (route, method, status) -> requests
+    counts: Mutex<BTreeMap<(String, String, u16), u64>>,
+}
+
+impl RequestMetrics {
+    pub fn observe(&self, route: &str, method: &str, status: u16, duration: Duration) {
+        let key = (route.to_string(), method.to_string());
+        self.latencies.lock().unwrap().entry(key.clone()).or_default().observe(duration);
+        *self.counts.lock().unwrap().entry((key.0, key.1, status)).or_default() += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        header(out, "metadata_service_requests_total", "counter", "HTTP requests served, by route, method and status");
+        for ((route, method, status), count) in self.counts.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "metadata_service_requests_total{{route=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+                escape(route),
+                method,
+                status,
+                count
+            );
+        }
+        header(out, "metadata_service_request_duration_seconds", "histogram", "HTTP request latency, by route and method");
+        for ((route, method), histogram) in self.latencies.lock().unwrap().iter() {
+            let labels = format!("route=\"{}\",method=\"{}\"", escape(route), method);
+            histogram.render(out, "metadata_service_request_duration_seconds", &labels);
+        }
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+}
+
+/// A label value with backslashes, quotes and newlines escaped.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Middleware recording every request in [`RequestMetrics`].
+pub async fn track(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> actix_web::Result<ServiceResponse<impl MessageBody>> {
+    let started = Instant::now();
+    // Unrouted paths are lumped together rather than each getting its own series
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+    let method = req.method().to_string();
+    let data = req.app_data::<web::Data<AppState>>().cloned();
+    let response = next.call(req).await;
+    if let Some(data) = data {
+        let status = match &response {
+            Ok(response) => response.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+        data.metrics.observe(&route, &method, status.as_u16(), started.elapsed());
+    }
+    response
+}
+
+pub async fn metrics(data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let mut out = String::new();
+    data.metrics.render(&mut out);
+
+    let full = data.db.len();
+    let derived = inheritance::derived_count(&data.db)?;
+    header(&mut out, "metadata_service_baselines", "gauge", "Stored baselines, full and derived");
+    let _ = writeln!(out, "metadata_service_baselines{{kind=\"full\"}} {}", full);
+    let _ = writeln!(out, "metadata_service_baselines{{kind=\"derived\"}} {}", derived);
+
+    let size = data.db.size_on_disk().map_err(actix_web::error::ErrorInternalServerError)?;
+    header(&mut out, "metadata_service_db_size_bytes", "gauge", "Size of the database on disk");
+    let _ = writeln!(out, "metadata_service_db_size_bytes {}", size);
+
+    header(&mut out, "metadata_service_db_flush_duration_seconds", "histogram", "Time taken to flush writes to disk");
+    data.db.flushes().render(&mut out, "metadata_service_db_flush_duration_seconds", "");
+
+    Ok(HttpResponse::Ok().content_type("text/plain; version=0.0.4; charset=utf-8").body(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let requests = RequestMetrics::default();
+        requests.observe("/baselines/{image_id}", "GET", 200, Duration::from_millis(3));
+        requests.observe("/baselines/{image_id}", "GET", 200, Duration::from_millis(300));
+        requests.observe("/baselines/{image_id}", "GET", 404, Duration::from_secs(20));
+        let mut out = String::new();
+        requests.render(&mut out);
+
+        assert!(out.contains("# TYPE metadata_service_requests_total counter\n"));
+        assert!(out.contains("\nmetadata_service_requests_total{route=\"/baselines/{image_id}\",method=\"GET\",status=\"200\"} 2\n"));
+        assert!(out.contains("\nmetadata_service_requests_total{route=\"/baselines/{image_id}\",method=\"GET\",status=\"404\"} 1\n"));
+        // Buckets are cumulative, and only +Inf holds the 20s request
+        let labels = "route=\"/baselines/{image_id}\",method=\"GET\"";
+        assert!(out.contains(&format!("\nmetadata_service_request_duration_seconds_bucket{{{},le=\"0.001\"}} 0\n", labels)));
+        assert!(out.contains(&format!("\nmetadata_service_request_duration_seconds_bucket{{{},le=\"0.005\"}} 1\n", labels)));
+        assert!(out.contains(&format!("\nmetadata_service_request_duration_seconds_bucket{{{},le=\"10\"}} 2\n", labels)));
+        assert!(out.contains(&format!("\nmetadata_service_request_duration_seconds_bucket{{{},le=\"+Inf\"}} 3\n", labels)));
+        assert!(out.contains(&format!("\nmetadata_service_request_duration_seconds_count{{{}}} 3\n", labels)));
+
+        let mut out = String::new();
+        Histogram::default().render(&mut out, "flush", "");
+        assert!(out.starts_with("flush_bucket{le=\"0.001\"} 0\n"));
+        assert!(out.ends_with("flush_sum 0\nflush_count 0\n"));
+    }
+}
@@ -0,0 +1,195 @@
+//! Drift reports between two stored baselines.
+//!
+//! `GET /baselines/{from}/diff/{to}` returns the `BaselineDiff` as JSON, or
+//! with `?format=html` a self-contained page grouping the changes by
+//! directory, meant to be attached to change-management tickets. Scan
+//! snapshots are diffed the same way (see `snapshots`).
+
+use crate::{inheritance, AppState};
+use actix_web::{web, HttpResponse, Responder};
+use integrity_common::{Baseline, BaselineDiff, FileIntegrityEntry, HashAlgorithm};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use tracing::info;
+
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    #[serde(default)]
+    format: Option<String>,
+}
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em}\
+table{border-collapse:collapse;width:100%;margin-bottom:1.5em}\
+td,th{text-align:left;padding:.2em .6em;border-bottom:1px solid #ddd}\
+code{font-family:monospace}\
+.added{background:#e6ffec}.removed{background:#ffebe9}.modified{background:#fff8c5}";
+
+pub async fn diff(
+    path: web::Path<(String, String)>,
+    query: web::Query<DiffQuery>,
+    data: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let (from, to) = path.into_inner();
+    let load = |image_id: &str| -> actix_web::Result<Baseline> {
+        inheritance::load_baseline(&data.db, image_id)?
+            .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Baseline not found: {}", image_id)))
+    };
+    let (old, new) = (load(&from)?, load(&to)?);
+
+    info!("Comparing baseline {} with {}", from, to);
+    Ok(respond(&old, &new, query.format.as_deref()))
+}
+
+/// The diff from `old` to `new` in `format`, JSON unless it is `html`.
+pub(crate) fn respond(old: &Baseline, new: &Baseline, format: Option<&str>) -> HttpResponse {
+    let diff = old.diff(new);
+    match format {
+        None | Some("json") => HttpResponse::Ok().json(diff),
+        Some("html") => HttpResponse::Ok().content_type("text/html; charset=utf-8").body(render_html(old, new, &diff)),
+        Some(other) => HttpResponse::BadRequest().body(format!("Unknown format: {} (expected json or html)", other)),
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn directory(path: &str) -> &str {
+    match path.trim_start_matches('/').rsplit_once('/') {
+        Some((dir, _)) => dir,
+        None => "",
+    }
+}
+
+/// What differs between two versions of an entry, content first.
+fn changes(old: &FileIntegrityEntry, new: &FileIntegrityEntry) -> Vec<String> {
+    let mut changes = Vec::new();
+    if old.symlink_target != new.symlink_target {
+        let target = |target: &Option<String>| target.clone().unwrap_or_else(|| "(none)".to_string());
+        changes.push(format!("target {} → {}", target(&old.symlink_target), target(&new.symlink_target)));
+    } else if !old.same_content(new) {
+        let digest = |entry: &FileIntegrityEntry| {
+            entry.content_digest(HashAlgorithm::default()).map_or("", |(_, digest)| &digest[..digest.len().min(12)]).to_string()
+        };
+        changes.push(format!("content {}… → {}…", digest(old), digest(new)));
+    }
+    if old.mode != new.mode {
+        changes.push(format!("mode {:o} → {:o}", old.mode, new.mode));
+    }
+    if old.uid != new.uid || old.gid != new.gid {
+        changes.push(format!("owner {}:{} → {}:{}", old.uid, old.gid, new.uid, new.gid));
+    }
+    if let Some((old_xattrs, new_xattrs)) = old.xattrs.as_ref().zip(new.xattrs.as_ref()) {
+        for change in integrity_common::xattr_changes(old_xattrs, new_xattrs) {
+            let value = |value: Option<String>| value.unwrap_or_else(|| "(none)".to_string());
+            changes.push(format!("{} {} → {}", change.name, value(change.expected), value(change.actual)));
+        }
+    }
+    if let Some((old_size, new_size)) = old.size.zip(new.size).filter(|(old, new)| old != new) {
+        changes.push(format!("size {} → {}", old_size, new_size));
+    }
+    if let Some((old_mtime, new_mtime)) = old.mtime.zip(new.mtime).filter(|(old, new)| old != new) {
+        changes.push(format!("mtime {} → {}", old_mtime, new_mtime));
+    }
+    if let Some((old_nlink, new_nlink)) = old.nlink.zip(new.nlink).filter(|(old, new)| old != new) {
+        changes.push(format!("links {} → {}", old_nlink, new_nlink));
+    }
+    changes
+}
+
+fn render_html(old: &Baseline, new: &Baseline, diff: &BaselineDiff) -> String {
+    // Directory -> (class, change, path, details), in path order within each class
+    let mut directories: BTreeMap<&str, Vec<(&str, &str, &str, String)>> = BTreeMap::new();
+    for entry in &diff.added {
+        let details = format!("mode {:o}, owner {}:{}", entry.mode, entry.uid, entry.gid);
+        directories.entry(directory(&entry.path)).or_default().push(("added", "added", &entry.path, details));
+    }
+    for entry in &diff.removed {
+        directories.entry(directory(&entry.path)).or_default().push(("removed", "removed", &entry.path, String::new()));
+    }
+    for change in &diff.modified {
+        let details = changes(&change.old, &change.new).join(", ");
+        directories.entry(directory(&change.new.path)).or_default().push(("modified", "changed", &change.new.path, details));
+    }
+
+    let (from, to) = (escape(&old.image_id), escape(&new.image_id));
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Drift report: {from} → {to}</title><style>{STYLE}</style></head><body>\n\
+         <h1>Drift report</h1>\n\
+         <p>From <code>{from}</code> ({}) to <code>{to}</code> ({}): \
+         <span class=\"added\">{} added</span>, <span class=\"removed\">{} removed</span>, <span class=\"modified\">{} changed</span> \
+         in {} directories.</p>\n",
+        escape(&old.timestamp),
+        escape(&new.timestamp),
+        diff.added.len(),
+        diff.removed.len(),
+        diff.modified.len(),
+        directories.len(),
+    );
+    if diff.is_empty() {
+        html.push_str("<p>The baselines contain the same files.</p>\n");
+    }
+    for (dir, rows) in &mut directories {
+        rows.sort_by(|a, b| a.2.cmp(b.2));
+        let _ = write!(html, "<h2><code>/{}</code></h2>\n<table><tr><th>Change</th><th>Path</th><th>Details</th></tr>\n", escape(dir));
+        for (class, change, path, details) in rows.iter() {
+            let _ = writeln!(
+                html,
+                "<tr class=\"{}\"><td>{}</td><td><code>{}</code></td><td>{}</td></tr>",
+                class,
+                change,
+                escape(path),
+                escape(details)
+            );
+        }
+        html.push_str("</table>\n");
+    }
+    html.push_str("</body></html>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::test_util::BaselineBuilder;
+
+    #[test]
+    fn test_render_html() {
+        let old = BaselineBuilder::new("v1")
+            .size(0)
+            .file_with_digest("etc/hosts", 0o644, "aaa")
+            .file_with_digest("usr/bin/ls", 0o755, "bbb")
+            .build();
+        let new = BaselineBuilder::new("v2<script>")
+            .size(0)
+            .file_with_digest("etc/hosts", 0o600, "aaa")
+            .file_with_digest("etc/<new>", 0o644, "ccc")
+            .build();
+        let html = render_html(&old, &new, &old.diff(&new));
+
+        assert!(html.contains("v2&lt;script&gt;"));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("1 added</span>, <span class=\"removed\">1 removed</span>, <span class=\"modified\">1 changed</span> in 2 directories"));
+        // Sections in directory order, rows in path order
+        let etc = html.find("<h2><code>/etc</code>").unwrap();
+        let bin = html.find("<h2><code>/usr/bin</code>").unwrap();
+        let added = html.find("<code>etc/&lt;new&gt;</code>").unwrap();
+        let changed = html.find("<td>mode 644 → 600</td>").unwrap();
+        assert!(etc < added && added < changed && changed < bin);
+        assert!(html.contains("<tr class=\"removed\"><td>removed</td><td><code>usr/bin/ls</code>"));
+    }
+}
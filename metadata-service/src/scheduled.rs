@@ -0,0 +1,234 @@
+//! Baselines staged to take effect at a later time.
+//!
+//! `POST /baselines?effective_from=<unix time>` with a time in the future
+//! stages the baseline in its own sled tree instead of storing it, so a new
+//! image version can be uploaded ahead of a coordinated update window. Until
+//! then the previous version is served; from then on every read
+//! (`inheritance::load_baseline`) resolves to the newest version that took
+//! effect, so no replica serves the old one late. A background job moves
+//! versions that took effect into the main store. Storing a baseline right
+//! away supersedes staged versions already in effect, not later ones.
+
+use crate::storage::Store;
+use crate::{inheritance, versions, AppState};
+use actix_web::{web, HttpResponse, Responder};
+use integrity_common::{Baseline, ScheduledBaseline};
+use tracing::{info, warn};
+
+const SCHEDULED_TREE: &str = "scheduled_baselines";
+
+/// How often versions that took effect are moved into the main store.
+pub const ACTIVATION_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+fn internal<E: std::fmt::Debug + std::fmt::Display + 'static>(e: E) -> actix_web::Error {
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+/// Keys sort by image, then by activation time.
+fn key(image_id: &str, effective_from: i64) -> String {
+    format!("{}\0{:020}", image_id, effective_from)
+}
+
+fn parse_key(key: &[u8]) -> Option<(String, i64)> {
+    let key = std::str::from_utf8(key).ok()?;
+    let (image_id, time) = key.rsplit_once('\0')?;
+    Some((image_id.to_string(), time.parse().ok()?))
+}
+
+/// Staged versions of `image_id`, by activation time.
+pub fn versions(db: &Store, image_id: &str) -> actix_web::Result<Vec<(i64, Baseline)>> {
+    let tree = db.open_tree(SCHEDULED_TREE).map_err(internal)?;
+    let mut versions = Vec::new();
+    for item in tree.scan_prefix(format!("{}\0", image_id).as_bytes()) {
+        let (key, stored) = item.map_err(internal)?;
+        let Some((_, effective_from)) = parse_key(&key) else {
+            continue;
+        };
+        let baseline = db.decode(&key, &stored)?;
+        versions.push((effective_from, baseline));
+    }
+    Ok(versions)
+}
+
+/// The newest staged version of `image_id` that took effect by `now`.
+pub fn active(db: &Store, image_id: &str, now: i64) -> actix_web::Result<Option<Baseline>> {
+    let tree = db.open_tree(SCHEDULED_TREE).map_err(internal)?;
+    for item in tree.scan_prefix(format!("{}\0", image_id).as_bytes()).rev() {
+        let (key, stored) = item.map_err(internal)?;
+        if parse_key(&key).is_some_and(|(_, effective_from)| effective_from <= now) {
+            return Ok(Some(db.decode(&key, &stored)?));
+        }
+    }
+    Ok(None)
+}
+
+/// When the next staged version of any image takes effect.
+pub fn next_activation(db: &Store, now: i64) -> actix_web::Result<Option<i64>> {
+    let tree = db.open_tree(SCHEDULED_TREE).map_err(internal)?;
+    let mut next = None;
+    for key in tree.iter().keys() {
+        if let Some((_, effective_from)) = parse_key(&key.map_err(internal)?) {
+            if effective_from > now && next.is_none_or(|next| effective_from < next) {
+                next = Some(effective_from);
+            }
+        }
+    }
+    Ok(next)
+}
+
+/// Drops the staged versions of `image_id` in effect by `now`, which a
+/// version stored right away replaces.
+pub fn supersede(db: &Store, image_id: &str, now: i64) -> actix_web::Result<()> {
+    let tree = db.open_tree(SCHEDULED_TREE).map_err(internal)?;
+    for key in tree.scan_prefix(format!("{}\0", image_id).as_bytes()).keys() {
+        let key = key.map_err(internal)?;
+        if parse_key(&key).is_some_and(|(_, effective_from)| effective_from <= now) {
+            tree.remove(key).map_err(internal)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether any version of `image_id` is staged, in effect or not.
+pub fn is_staged(db: &sled::Db, image_id: &str) -> bool {
+    db.open_tree(SCHEDULED_TREE)
+        .map(|tree| tree.scan_prefix(format!("{}\0", image_id).as_bytes()).next().is_some())
+        .unwrap_or(false)
+}
+
+/// Removes every staged version of `image_id`.
+pub fn remove(db: &Store, image_id: &str) -> actix_web::Result<usize> {
+    db.remove_image_records(SCHEDULED_TREE, image_id)
+}
+
+/// Stages a baseline to take effect at `effective_from`.
+pub async fn stage(data: &AppState, baseline: &Baseline, effective_from: i64) -> actix_web::Result<HttpResponse> {
+    let violations = baseline.validate();
+    if !violations.is_empty() {
+        warn!("Rejecting invalid baseline for image {}: {} violation(s)", baseline.image_id, violations.len());
+        return Ok(HttpResponse::BadRequest().json(violations));
+    }
+
+    let key = key(&baseline.image_id, effective_from);
+    let serialized = data.db.encode(key.as_bytes(), baseline)?;
+    let tree = data.db.open_tree(SCHEDULED_TREE).map_err(internal)?;
+    tree.insert(key.as_bytes(), serialized).map_err(internal)?;
+    // The cache must drop the current version when this one takes effect
+    data.cache.invalidate();
+    data.db.flush_async().await.map_err(internal)?;
+
+    info!("Staged baseline for image {} to take effect at {}", baseline.image_id, effective_from);
+    Ok(HttpResponse::Accepted().json(ScheduledBaseline {
+        image_id: baseline.image_id.clone(),
+        effective_from,
+        timestamp: baseline.timestamp.clone(),
+        entries: baseline.entries.len(),
+        digest: baseline.digest().map_err(internal)?,
+    }))
+}
+
+/// Versions of an image waiting to take effect, soonest first.
+pub async fn list(image_id: web::Path<String>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let now = chrono::Utc::now().timestamp();
+    let pending = versions(&data.db, &image_id)?
+        .into_iter()
+        .filter(|(effective_from, _)| *effective_from > now)
+        .map(|(effective_from, baseline)| {
+            Ok(ScheduledBaseline {
+                image_id: image_id.clone(),
+                effective_from,
+                digest: baseline.digest().map_err(internal)?,
+                timestamp: baseline.timestamp,
+                entries: baseline.entries.len(),
+            })
+        })
+        .collect::<actix_web::Result<Vec<_>>>()?;
+    Ok(HttpResponse::Ok().json(pending))
+}
+
+/// Withdraws a version that has not taken effect yet.
+pub async fn cancel(path: web::Path<(String, i64)>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let (image_id, effective_from) = path.into_inner();
+    if effective_from <= chrono::Utc::now().timestamp() {
+        return Ok(HttpResponse::Conflict().body(format!("The version of {} for {} has already taken effect", image_id, effective_from)));
+    }
+    let tree = data.db.open_tree(SCHEDULED_TREE).map_err(internal)?;
+    if tree.remove(key(&image_id, effective_from).as_bytes()).map_err(internal)?.is_none() {
+        return Ok(HttpResponse::NotFound().body(format!("No version of {} scheduled for {}", image_id, effective_from)));
+    }
+    data.cache.invalidate();
+    data.db.flush_async().await.map_err(internal)?;
+    info!("Cancelled the version of {} scheduled for {}", image_id, effective_from);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Stores the newest version of each image that took effect by `now` as its
+/// baseline, dropping the staged versions it replaces.
+fn activate(db: &Store, now: i64) -> anyhow::Result<usize> {
+    let tree = db.open_tree(SCHEDULED_TREE)?;
+    let mut due: Vec<String> = Vec::new();
+    for key in tree.iter().keys() {
+        if let Some((image_id, effective_from)) = parse_key(&key?) {
+            if effective_from <= now && due.last() != Some(&image_id) {
+                due.push(image_id);
+            }
+        }
+    }
+    for image_id in &due {
+        let Some(baseline) = active(db, image_id, now).map_err(|e| anyhow::anyhow!("{}", e))? else {
+            continue;
+        };
+        let digest = baseline.digest()?;
+        versions::record(db, &baseline, &digest, now).map_err(|e| anyhow::anyhow!("{}", e))?;
+        let sealed = db.encode(image_id.as_bytes(), &baseline).map_err(|e| anyhow::anyhow!("{}", e))?;
+        db.insert(image_id.as_bytes(), sealed)?;
+        inheritance::remove_derived(db, image_id).map_err(|e| anyhow::anyhow!("{}", e))?;
+        supersede(db, image_id, now).map_err(|e| anyhow::anyhow!("{}", e))?;
+        info!("Baseline of {} collected at {} is now in effect", image_id, baseline.timestamp);
+    }
+    db.flush()?;
+    Ok(due.len())
+}
+
+/// Background job moving versions that took effect into the main store.
+pub fn activate_due(db: &Store) -> anyhow::Result<()> {
+    activate(db, chrono::Utc::now().timestamp())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::test_util::BaselineBuilder;
+
+    fn baseline(image_id: &str, timestamp: &str) -> Baseline {
+        BaselineBuilder::new(image_id).timestamp(timestamp).build()
+    }
+
+    fn stage_at(db: &Store, baseline: &Baseline, effective_from: i64) {
+        let key = key(&baseline.image_id, effective_from);
+        let sealed = db.seal(key.as_bytes(), serde_json::to_vec(baseline).unwrap()).unwrap();
+        db.open_tree(SCHEDULED_TREE).unwrap().insert(key.as_bytes(), sealed).unwrap();
+    }
+
+    #[test]
+    fn test_activation() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap(), Some([3; 32]));
+        stage_at(&db, &baseline("app", "v2"), 1000);
+        stage_at(&db, &baseline("app", "v3"), 2000);
+        stage_at(&db, &baseline("app-other", "v9"), 5000);
+
+        assert!(active(&db, "app", 999).unwrap().is_none());
+        assert!(is_staged(&db, "app") && !is_staged(&db, "ap"));
+        assert_eq!(active(&db, "app", 1000).unwrap().unwrap().timestamp, "v2");
+        assert_eq!(active(&db, "app", 2500).unwrap().unwrap().timestamp, "v3");
+        assert_eq!(next_activation(&db, 1000).unwrap(), Some(2000));
+        assert_eq!(next_activation(&db, 5000).unwrap(), None);
+
+        assert_eq!(activate(&db, 1500).unwrap(), 1);
+        let stored: Baseline = serde_json::from_slice(&db.open(b"app", &db.get(b"app").unwrap().unwrap()).unwrap()).unwrap();
+        assert_eq!(stored.timestamp, "v2");
+        assert!(active(&db, "app", 1500).unwrap().is_none());
+        assert_eq!(versions(&db, "app").unwrap().len(), 1);
+    }
+}
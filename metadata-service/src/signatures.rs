@@ -0,0 +1,201 @@
+//! Signature chains of stored baselines.
+//!
+//! `POST /baselines/{image_id}/signatures` appends a signature (see
+//! `integrity_common::BaselineSignature`) to the chain of the version it
+//! signs, which must be the baseline served now or a staged one. Chains are
+//! kept per image and digest, so a new version starts with no signatures. A
+//! signature that follows nothing starts the chain over; one already in the
+//! chain leaves it as it is. With
+//! `--signing-key-file` the service countersigns every chain it accepts with
+//! the time, replacing its previous countersignature, so its signature always
+//! covers the whole chain. Agents verify the chains themselves; the service
+//! only refuses links that would not verify.
+
+use crate::storage::Store;
+use crate::{inheritance, scheduled, AppState};
+use actix_web::{web, HttpResponse, Responder};
+use anyhow::Context;
+use integrity_common::{countersignatures_start, verify_chain, BaselineSignature, BaselineSigner, SignerRole};
+use serde::Deserialize;
+use std::path::PathBuf;
+use tracing::info;
+
+const SIGNATURES_TREE: &str = "signatures";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SigningArgs {
+    /// File holding the Ed25519 private key (32 bytes, raw or hex) the service countersigns baseline signature chains with
+    #[arg(long)]
+    pub signing_key_file: Option<PathBuf>,
+}
+
+impl SigningArgs {
+    pub fn signer(&self) -> anyhow::Result<Option<BaselineSigner>> {
+        let Some(path) = &self.signing_key_file else {
+            return Ok(None);
+        };
+        let material = std::fs::read(path).with_context(|| format!("reading signing key file {:?}", path))?;
+        let signer = BaselineSigner::from_key_material(&material)?;
+        info!("Countersigning baseline signatures with key {}", signer.public_key());
+        Ok(Some(signer))
+    }
+}
+
+fn key(image_id: &str, digest: &str) -> String {
+    format!("{}\0{}", image_id, digest.to_ascii_lowercase())
+}
+
+/// Removes the signature chains of every version of `image_id`.
+pub fn remove(db: &Store, image_id: &str) -> actix_web::Result<usize> {
+    db.remove_image_records(SIGNATURES_TREE, image_id)
+}
+
+fn stored_chain(value: Option<&sled::IVec>) -> actix_web::Result<Vec<BaselineSignature>> {
+    match value {
+        Some(value) => serde_json::from_slice(value).map_err(actix_web::error::ErrorInternalServerError),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Digests of the versions of `image_id` that can be signed: the one served
+/// now and the staged ones.
+fn signable_digests(data: &AppState, image_id: &str) -> actix_web::Result<Vec<String>> {
+    let current = inheritance::load_baseline(&data.db, image_id)?;
+    let staged = scheduled::versions(&data.db, image_id)?.into_iter().map(|(_, baseline)| baseline);
+    current
+        .into_iter()
+        .chain(staged)
+        .map(|baseline| baseline.digest().map_err(actix_web::error::ErrorInternalServerError))
+        .collect()
+}
+
+/// Appends a signature to the chain of the version it signs.
+pub async fn add(
+    image_id: web::Path<String>,
+    signature: web::Json<BaselineSignature>,
+    data: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let signature = signature.into_inner();
+    if signature.role == SignerRole::Service {
+        return Ok(HttpResponse::BadRequest().body("service countersignatures are added by the service"));
+    }
+    let digest = signature.digest.to_ascii_lowercase();
+    let signable = signable_digests(&data, &image_id)?;
+    if signable.is_empty() {
+        return Ok(HttpResponse::NotFound().body(format!("Baseline not found: {}", image_id)));
+    }
+    if !signable.contains(&digest) {
+        return Ok(HttpResponse::Conflict().body(format!("{} is not the digest of a stored version of {}", digest, image_id)));
+    }
+
+    let tree = data.db.open_tree(SIGNATURES_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    let key = key(&image_id, &digest);
+    let chain = loop {
+        let old = tree.get(key.as_bytes()).map_err(actix_web::error::ErrorInternalServerError)?;
+        let mut chain = stored_chain(old.as_ref())?;
+        if chain.contains(&signature) {
+            return Ok(HttpResponse::Ok().json(chain));
+        }
+        chain.truncate(countersignatures_start(&chain));
+        if verify_chain(std::slice::from_ref(&signature), &digest).is_ok() {
+            chain.clear();
+        }
+        chain.push(signature.clone());
+        if let Err(e) = verify_chain(&chain, &digest) {
+            return Ok(HttpResponse::BadRequest().body(e.to_string()));
+        }
+        if let Some(signer) = &data.signer {
+            let countersignature = signer.sign(SignerRole::Service, &digest, chrono::Utc::now().timestamp(), &chain);
+            chain.push(countersignature);
+        }
+        let new = serde_json::to_vec(&chain).map_err(actix_web::error::ErrorInternalServerError)?;
+        let swapped = tree.compare_and_swap(key.as_bytes(), old, Some(new)).map_err(actix_web::error::ErrorInternalServerError)?;
+        if swapped.is_ok() {
+            break chain;
+        }
+    };
+    data.db.flush_async().await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    info!("Baseline {} ({}) signed by {} {}, {} signatures", image_id, &digest[..16.min(digest.len())], signature.role, signature.public_key, chain.len());
+    Ok(HttpResponse::Created().json(chain))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    /// Version whose chain to return [default: the baseline served now]
+    digest: Option<String>,
+}
+
+/// The signature chain of a version of the baseline, in signing order.
+pub async fn list(image_id: web::Path<String>, query: web::Query<ListQuery>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let digest = match &query.digest {
+        Some(digest) => digest.clone(),
+        None => inheritance::load_baseline(&data.db, &image_id)?
+            .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Baseline not found: {}", image_id)))?
+            .digest()
+            .map_err(actix_web::error::ErrorInternalServerError)?,
+    };
+    let tree = data.db.open_tree(SIGNATURES_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    let stored = tree.get(key(&image_id, &digest).as_bytes()).map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(stored_chain(stored.as_ref())?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::App;
+    use integrity_common::test_util::BaselineBuilder;
+
+    #[actix_rt::test]
+    async fn test_add_and_list() {
+        let mut state = AppState::for_tests(None);
+        state.signer = Some(BaselineSigner::from_bytes([9; 32]));
+        let baseline = BaselineBuilder::new("app").size(5).build();
+        state.db.insert(b"app", state.db.encode(b"app", &baseline).unwrap()).unwrap();
+        let digest = baseline.digest().unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .route("/baselines/{image_id}/signatures", web::get().to(list))
+                .route("/baselines/{image_id}/signatures", web::post().to(add)),
+        )
+        .await;
+        let post = |image_id: &str, signature: &BaselineSignature| {
+            TestRequest::post().uri(&format!("/baselines/{}/signatures", image_id)).set_json(signature).to_request()
+        };
+        let collector = BaselineSigner::from_bytes([1; 32]);
+        let approver = BaselineSigner::from_bytes([2; 32]);
+
+        let collected = collector.sign(SignerRole::Collector, &digest, 1000, &[]);
+        let response = call_service(&app, post("app", &collected)).await;
+        assert_eq!(response.status().as_u16(), 201);
+        let chain: Vec<BaselineSignature> = read_body_json(response).await;
+
+        // A valid approver signature follows the collector's, and the service countersigns
+        let approved = approver.sign(SignerRole::Approver, &digest, 1100, &chain[..1]);
+        let response = call_service(&app, post("app", &approved)).await;
+        assert_eq!(response.status().as_u16(), 201);
+        let chain: Vec<BaselineSignature> = read_body_json(response).await;
+        assert_eq!(chain.iter().map(|link| link.role).collect::<Vec<_>>(), [SignerRole::Collector, SignerRole::Approver, SignerRole::Service]);
+        assert!(verify_chain(&chain, &digest).is_ok());
+
+        // The same signature again is not appended twice
+        let response = call_service(&app, post("app", &approved)).await;
+        assert_eq!(response.status().as_u16(), 200);
+        let listed: Vec<BaselineSignature> = read_body_json(call_service(&app, TestRequest::get().uri("/baselines/app/signatures").to_request()).await).await;
+        assert_eq!(listed, chain);
+
+        // Signatures over another digest, or claiming this one, are refused
+        let other = BaselineBuilder::new("app").size(6).build().digest().unwrap();
+        let elsewhere = approver.sign(SignerRole::Approver, &other, 1200, &chain[..2]);
+        assert_eq!(call_service(&app, post("app", &elsewhere)).await.status().as_u16(), 409);
+        let forged = BaselineSignature { digest: digest.clone(), ..elsewhere };
+        assert_eq!(call_service(&app, post("app", &forged)).await.status().as_u16(), 400);
+
+        // Unknown images
+        assert_eq!(call_service(&app, post("web", &collected)).await.status().as_u16(), 404);
+        let response = call_service(&app, TestRequest::get().uri("/baselines/web/signatures").to_request()).await;
+        assert_eq!(response.status().as_u16(), 404);
+    }
+}
@@ -0,0 +1,184 @@
+//! Scan snapshots: the complete state a host had at a scan.
+//!
+//! Agents `POST /snapshots` every file their scan saw (see
+//! `integrity_common::ScanSnapshot`). Snapshots are kept in their own sled
+//! tree, encrypted like baselines, with a tree of their `SnapshotInfo`s for
+//! listing. Only the newest `--snapshots-per-host` of each host are kept, and
+//! none older than `--snapshot-max-age-days`; retention is applied on upload.
+//! `GET /snapshots/{id}/diff?against={image_id}` diffs a snapshot against any
+//! stored baseline, by default the one it was scanned against.
+
+use crate::{inheritance, report, AppState};
+use actix_web::{web, HttpResponse, Responder};
+use integrity_common::{ScanSnapshot, SnapshotInfo};
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::info;
+
+const SNAPSHOTS_TREE: &str = "snapshots";
+const SNAPSHOT_INDEX_TREE: &str = "snapshot_index";
+
+/// Largest snapshot accepted. A whole root filesystem is tens of megabytes of
+/// JSON, and agents upload without an account, so the whole body is buffered
+/// and parsed for anyone who can reach the service; keep the bound close.
+pub const MAX_SNAPSHOT_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SnapshotArgs {
+    /// Scan snapshots kept per host; the oldest are deleted as new ones arrive
+    #[arg(long, default_value = "10")]
+    pub snapshots_per_host: usize,
+
+    /// Days after which scan snapshots are deleted (0 keeps them regardless of age)
+    #[arg(long, default_value = "90")]
+    pub snapshot_max_age_days: u64,
+}
+
+/// Keys sort in id order.
+fn key(id: u64) -> String {
+    format!("{:020}", id)
+}
+
+/// Snapshots beyond the newest `per_host` of their host, or received before
+/// `min_received_at`. `infos` are in id order.
+fn expired(infos: &[SnapshotInfo], per_host: usize, min_received_at: Option<i64>) -> Vec<u64> {
+    let mut kept: HashMap<&str, usize> = HashMap::new();
+    let mut expired = Vec::new();
+    for info in infos.iter().rev() {
+        let count = kept.entry(&info.host).or_default();
+        if *count >= per_host || min_received_at.is_some_and(|min| info.received_at < min) {
+            expired.push(info.id);
+        } else {
+            *count += 1;
+        }
+    }
+    expired
+}
+
+fn infos(data: &AppState) -> actix_web::Result<Vec<SnapshotInfo>> {
+    let tree = data.db.open_tree(SNAPSHOT_INDEX_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    let mut infos = Vec::new();
+    for item in tree.iter() {
+        let (_, value) = item.map_err(actix_web::error::ErrorInternalServerError)?;
+        infos.push(serde_json::from_slice(&value).map_err(actix_web::error::ErrorInternalServerError)?);
+    }
+    Ok(infos)
+}
+
+fn load(data: &AppState, id: u64) -> actix_web::Result<ScanSnapshot> {
+    let tree = data.db.open_tree(SNAPSHOTS_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    let key = key(id);
+    let stored = tree
+        .get(key.as_bytes())
+        .map_err(actix_web::error::ErrorInternalServerError)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("No snapshot {}", id)))?;
+    let snapshot = data.db.open(key.as_bytes(), &stored)?;
+    serde_json::from_slice(&snapshot).map_err(actix_web::error::ErrorInternalServerError)
+}
+
+pub async fn upload(snapshot: web::Json<ScanSnapshot>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let mut snapshot = snapshot.into_inner();
+    if snapshot.host.trim().is_empty() {
+        return Ok(HttpResponse::BadRequest().body("host is required"));
+    }
+    snapshot.entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let snapshots = data.db.open_tree(SNAPSHOTS_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    let index = data.db.open_tree(SNAPSHOT_INDEX_TREE).map_err(actix_web::error::ErrorInternalServerError)?;
+    let id = data.db.generate_id().map_err(actix_web::error::ErrorInternalServerError)?;
+    let now = chrono::Utc::now().timestamp();
+    let info = snapshot.info(id, now);
+    let record_key = key(id);
+
+    let serialized = serde_json::to_vec(&snapshot).map_err(actix_web::error::ErrorInternalServerError)?;
+    let sealed = data.db.seal(record_key.as_bytes(), serialized)?;
+    snapshots.insert(record_key.as_bytes(), sealed).map_err(actix_web::error::ErrorInternalServerError)?;
+    let serialized = serde_json::to_vec(&info).map_err(actix_web::error::ErrorInternalServerError)?;
+    index.insert(record_key.as_bytes(), serialized).map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let retention = &data.snapshot_retention;
+    let min_received_at = (retention.snapshot_max_age_days > 0).then(|| now - retention.snapshot_max_age_days as i64 * 86400);
+    let pruned = expired(&infos(&data)?, retention.snapshots_per_host, min_received_at);
+    for id in &pruned {
+        index.remove(key(*id).as_bytes()).map_err(actix_web::error::ErrorInternalServerError)?;
+        snapshots.remove(key(*id).as_bytes()).map_err(actix_web::error::ErrorInternalServerError)?;
+    }
+    data.db.flush_async().await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    info!(
+        "Stored snapshot {} of {} ({} files, scanned against {}), {} expired",
+        id,
+        info.host,
+        info.entries,
+        info.image_id,
+        pruned.len()
+    );
+    Ok(HttpResponse::Created().json(info))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    host: Option<String>,
+    image_id: Option<String>,
+}
+
+/// Snapshots matching the query, newest first.
+pub async fn list(query: web::Query<ListQuery>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let mut infos = infos(&data)?;
+    infos.retain(|info| {
+        query.host.as_ref().is_none_or(|host| info.host == *host)
+            && query.image_id.as_ref().is_none_or(|image_id| info.image_id == *image_id)
+    });
+    infos.reverse();
+    Ok(HttpResponse::Ok().json(infos))
+}
+
+pub async fn get(id: web::Path<u64>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    Ok(HttpResponse::Ok().json(load(&data, id.into_inner())?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    /// Baseline to diff against [default: the one the host was scanned against]
+    against: Option<String>,
+    format: Option<String>,
+}
+
+/// What changed from a baseline to the snapshot.
+pub async fn diff(id: web::Path<u64>, query: web::Query<DiffQuery>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let snapshot = load(&data, id.into_inner())?;
+    let image_id = query.against.as_deref().unwrap_or(&snapshot.image_id);
+    let baseline = inheritance::load_baseline(&data.db, image_id)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Baseline not found: {}", image_id)))?;
+
+    info!("Comparing baseline {} with the snapshot of {} at {}", image_id, snapshot.host, snapshot.timestamp);
+    Ok(report::respond(&baseline, &snapshot.to_baseline(), query.format.as_deref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(id: u64, host: &str, received_at: i64) -> SnapshotInfo {
+        SnapshotInfo {
+            id,
+            host: host.to_string(),
+            image_id: "app-v1".to_string(),
+            timestamp: String::new(),
+            received_at,
+            entries: 0,
+        }
+    }
+
+    #[test]
+    fn test_expired() {
+        let infos = [info(1, "web-1", 100), info(2, "web-2", 100), info(3, "web-1", 200), info(4, "web-1", 300)];
+        let mut expired_ids = expired(&infos, 2, None);
+        expired_ids.sort();
+        assert_eq!(expired_ids, vec![1]);
+        // Age applies to every host, however few snapshots it has
+        let mut expired_ids = expired(&infos, 10, Some(150));
+        expired_ids.sort();
+        assert_eq!(expired_ids, vec![1, 2]);
+    }
+}
@@ -0,0 +1,100 @@
+//! Composition of a stored baseline.
+//!
+//! `GET /baselines/{image_id}/stats` summarizes what a baseline contains, for
+//! dashboards and for sanity-checking a freshly collected baseline: an empty
+//! top-level directory or thousands of world-writable files usually mean the
+//! collector scanned the wrong root.
+
+use crate::AppState;
+use actix_web::{web, HttpResponse, Responder};
+use integrity_common::{FileIntegrityEntry, HashAlgorithm};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Default, Serialize, PartialEq)]
+struct BaselineStats {
+    entries: usize,
+    /// Entries per top-level directory ("" for files at the root)
+    top_level_directories: BTreeMap<String, usize>,
+    /// Entries per permission mode, in octal
+    modes: BTreeMap<String, usize>,
+    setuid: usize,
+    setgid: usize,
+    world_writable: usize,
+    /// Hashes shared by more than one entry
+    duplicate_hashes: usize,
+    /// Entries whose hash is shared with another entry
+    duplicate_entries: usize,
+}
+
+fn stats(entries: &[FileIntegrityEntry]) -> BaselineStats {
+    let mut stats = BaselineStats { entries: entries.len(), ..Default::default() };
+    let mut hashes: HashMap<(HashAlgorithm, &str), usize> = HashMap::new();
+    for entry in entries {
+        let top_level = match entry.path.trim_matches('/').split_once('/') {
+            Some((dir, _)) => dir,
+            None => "",
+        };
+        *stats.top_level_directories.entry(top_level.to_string()).or_default() += 1;
+        *stats.modes.entry(format!("{:o}", entry.mode & 0o7777)).or_default() += 1;
+        if entry.mode & 0o4000 != 0 {
+            stats.setuid += 1;
+        }
+        if entry.mode & 0o2000 != 0 {
+            stats.setgid += 1;
+        }
+        if entry.mode & 0o002 != 0 {
+            stats.world_writable += 1;
+        }
+        if let Some(digest) = entry.content_digest(HashAlgorithm::default()) {
+            *hashes.entry(digest).or_default() += 1;
+        }
+    }
+    for count in hashes.values().filter(|count| **count > 1) {
+        stats.duplicate_hashes += 1;
+        stats.duplicate_entries += count;
+    }
+    stats
+}
+
+pub async fn baseline_stats(image_id: web::Path<String>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let image_id = image_id.into_inner();
+    let cached = data
+        .cache
+        .load(&data.db, &image_id)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Baseline not found: {}", image_id)))?;
+
+    Ok(HttpResponse::Ok().json(stats(&cached.baseline.entries)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::test_util::BaselineBuilder;
+
+    #[test]
+    fn test_stats() {
+        let mut entries = BaselineBuilder::new("img")
+            .size(0)
+            .file_with_digest("etc/hosts", 0o100644, "aaa")
+            .file_with_digest("etc/motd", 0o100644, "aaa")
+            .file_with_digest("usr/bin/sudo", 0o104755, "bbb")
+            .file_with_digest("usr/bin/wall", 0o102755, "ccc")
+            .file_with_digest("tmp/drop", 0o100666, "aaa")
+            .file_with_digest("README", 0o100644, "ddd")
+            .build()
+            .entries;
+        // Counted under their top-level directory even with a leading slash
+        entries[4].path.insert(0, '/');
+        let stats = stats(&entries);
+
+        assert_eq!(stats.entries, 6);
+        assert_eq!(
+            stats.top_level_directories,
+            BTreeMap::from([("".to_string(), 1), ("etc".to_string(), 2), ("tmp".to_string(), 1), ("usr".to_string(), 2)])
+        );
+        assert_eq!(stats.modes.get("644"), Some(&3));
+        assert_eq!((stats.setuid, stats.setgid, stats.world_writable), (1, 1, 1));
+        assert_eq!((stats.duplicate_hashes, stats.duplicate_entries), (1, 3));
+    }
+}
@@ -0,0 +1,264 @@
+//! The sled database, with optional envelope encryption of baselines.
+//!
+//! Baseline records are JSON, or CBOR with `--storage-format cbor`, which
+//! takes less space and decodes faster for large images. Records are read in
+//! either format, so switching formats needs no migration: each record is
+//! rewritten in the new one the next time it is stored.
+//!
+//! Baselines map out a fleet's software, so with a master key configured
+//! every stored baseline record is encrypted with its own random AES-256-GCM
+//! data key, and the data key is stored next to it, encrypted with the master
+//! key. The record's sled key is bound in as associated data, so records
+//! cannot be swapped between images. Records written without a master key
+//! stay readable, and are encrypted the next time they are stored.
+//!
+//! The master key is 32 bytes, raw or hex encoded, read from
+//! `--master-key-file` or from the output of `--master-key-command` (e.g. a
+//! KMS decrypt call), and never written to the database.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use crate::metrics::Histogram;
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sled::Db;
+use std::borrow::Cow;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::info;
+
+/// Prefix of encrypted records; plaintext records are JSON objects or CBOR maps.
+const MAGIC: &[u8] = b"ENC1";
+const NONCE_LEN: usize = 12;
+/// An encrypted 32-byte data key with its GCM tag
+const WRAPPED_KEY_LEN: usize = 32 + 16;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct EncryptionArgs {
+    /// File holding the 32-byte master key (raw or hex) that baselines are encrypted under
+    #[arg(long, conflicts_with = "master_key_command")]
+    pub master_key_file: Option<PathBuf>,
+
+    /// Shell command printing the master key, e.g. a KMS decrypt call
+    #[arg(long)]
+    pub master_key_command: Option<String>,
+}
+
+impl EncryptionArgs {
+    pub fn master_key(&self) -> anyhow::Result<Option<[u8; 32]>> {
+        let material = if let Some(path) = &self.master_key_file {
+            std::fs::read(path).with_context(|| format!("reading master key file {:?}", path))?
+        } else if let Some(command) = &self.master_key_command {
+            let output = std::process::Command::new("sh").arg("-c").arg(command).output().context("running master key command")?;
+            anyhow::ensure!(output.status.success(), "master key command failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+            output.stdout
+        } else {
+            return Ok(None);
+        };
+        parse_key(&material).map(Some)
+    }
+}
+
+/// How baseline records are serialized in the database.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecordFormat {
+    #[default]
+    Json,
+    Cbor,
+}
+
+fn parse_key(material: &[u8]) -> anyhow::Result<[u8; 32]> {
+    if let Ok(key) = <[u8; 32]>::try_from(material) {
+        return Ok(key);
+    }
+    let text = std::str::from_utf8(material).unwrap_or_default().trim();
+    let decoded = hex::decode(text).context("master key is neither 32 raw bytes nor hex")?;
+    <[u8; 32]>::try_from(decoded.as_slice()).map_err(|_| anyhow::anyhow!("master key must be 32 bytes, got {}", decoded.len()))
+}
+
+fn internal<E: std::fmt::Debug + std::fmt::Display + 'static>(e: E) -> actix_web::Error {
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+pub struct Store {
+    db: Db,
+    master_key: Option<Aes256Gcm>,
+    format: RecordFormat,
+    flushes: Mutex<Histogram>,
+}
+
+impl Deref for Store {
+    type Target = Db;
+
+    fn deref(&self) -> &Db {
+        &self.db
+    }
+}
+
+impl Store {
+    pub fn new(db: Db, master_key: Option<[u8; 32]>) -> Self {
+        if master_key.is_some() {
+            info!("Encrypting stored baselines under the configured master key");
+        }
+        Self {
+            db,
+            master_key: master_key.map(|key| Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))),
+            format: RecordFormat::default(),
+            flushes: Mutex::default(),
+        }
+    }
+
+    pub fn with_format(mut self, format: RecordFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Flushes dirty writes to disk, timing it for the metrics. Flushing a
+    /// tree flushes the whole database, so every flush goes through here.
+    pub async fn flush_async(&self) -> sled::Result<usize> {
+        let started = Instant::now();
+        let flushed = self.db.flush_async().await;
+        self.flushes.lock().unwrap().observe(started.elapsed());
+        flushed
+    }
+
+    pub fn flush(&self) -> sled::Result<usize> {
+        let started = Instant::now();
+        let flushed = self.db.flush();
+        self.flushes.lock().unwrap().observe(started.elapsed());
+        flushed
+    }
+
+    /// How long flushes took.
+    pub fn flushes(&self) -> Histogram {
+        self.flushes.lock().unwrap().clone()
+    }
+
+    /// Removes the records of `image_id` from a tree keyed by image_id, a NUL
+    /// and a version, time or digest. Returns how many there were.
+    pub fn remove_image_records(&self, tree: &str, image_id: &str) -> actix_web::Result<usize> {
+        let tree = self.open_tree(tree).map_err(internal)?;
+        let mut removed = 0;
+        for key in tree.scan_prefix(format!("{}\0", image_id).as_bytes()).keys() {
+            tree.remove(key.map_err(internal)?).map_err(internal)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    /// Serializes a record stored under `key` in the configured format, and seals it.
+    pub fn encode<T: Serialize>(&self, key: &[u8], record: &T) -> actix_web::Result<Vec<u8>> {
+        let plaintext = match self.format {
+            RecordFormat::Json => serde_json::to_vec(record).map_err(internal)?,
+            RecordFormat::Cbor => integrity_common::to_cbor(record).map_err(internal)?,
+        };
+        self.seal(key, plaintext)
+    }
+
+    /// Opens a record stored under `key` and deserializes it, whichever
+    /// format it was written in.
+    pub fn decode<T: DeserializeOwned>(&self, key: &[u8], stored: &[u8]) -> actix_web::Result<T> {
+        let plaintext = self.open(key, stored)?;
+        if plaintext.starts_with(b"{") {
+            serde_json::from_slice(&plaintext).map_err(internal)
+        } else {
+            integrity_common::from_cbor(&plaintext).map_err(internal)
+        }
+    }
+
+    /// Encrypts a record stored under `key`, if a master key is configured.
+    pub fn seal(&self, key: &[u8], plaintext: Vec<u8>) -> actix_web::Result<Vec<u8>> {
+        let Some(master) = &self.master_key else {
+            return Ok(plaintext);
+        };
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        let key_nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let wrapped_key = master.encrypt(&key_nonce, Payload { msg: data_key.as_slice(), aad: key }).map_err(internal)?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = Aes256Gcm::new(&data_key).encrypt(&nonce, Payload { msg: &plaintext, aad: key }).map_err(internal)?;
+
+        let mut sealed = Vec::with_capacity(MAGIC.len() + 2 * NONCE_LEN + WRAPPED_KEY_LEN + ciphertext.len());
+        sealed.extend_from_slice(MAGIC);
+        sealed.extend_from_slice(&key_nonce);
+        sealed.extend_from_slice(&wrapped_key);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypts a record stored under `key`; plaintext records pass through.
+    pub fn open<'a>(&self, key: &[u8], stored: &'a [u8]) -> actix_web::Result<Cow<'a, [u8]>> {
+        let Some(sealed) = stored.strip_prefix(MAGIC) else {
+            return Ok(Cow::Borrowed(stored));
+        };
+        let Some(master) = &self.master_key else {
+            return Err(internal(format!("record {} is encrypted and no master key is configured", String::from_utf8_lossy(key))));
+        };
+        if sealed.len() < 2 * NONCE_LEN + WRAPPED_KEY_LEN {
+            return Err(internal(format!("encrypted record {} is truncated", String::from_utf8_lossy(key))));
+        }
+        let (key_nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (wrapped_key, rest) = rest.split_at(WRAPPED_KEY_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let undecryptable = |_| internal(format!("record {} does not decrypt under the master key", String::from_utf8_lossy(key)));
+
+        let data_key = master.decrypt(Nonce::from_slice(key_nonce), Payload { msg: wrapped_key, aad: key }).map_err(undecryptable)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key));
+        let plaintext = cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: key }).map_err(undecryptable)?;
+        Ok(Cow::Owned(plaintext))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(master_key: Option<[u8; 32]>) -> Store {
+        Store::new(sled::Config::new().temporary(true).open().unwrap(), master_key)
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let encrypted = store(Some([7; 32]));
+        let sealed = encrypted.seal(b"app", b"{\"image_id\":\"app\"}".to_vec()).unwrap();
+        assert!(sealed.starts_with(MAGIC));
+        assert!(!sealed.windows(8).any(|w| w == b"image_id"));
+        assert_eq!(&*encrypted.open(b"app", &sealed).unwrap(), b"{\"image_id\":\"app\"}");
+
+        // Bound to its key and master key, and unreadable without one
+        assert!(encrypted.open(b"other", &sealed).is_err());
+        assert!(store(Some([8; 32])).open(b"app", &sealed).is_err());
+        assert!(store(None).open(b"app", &sealed).is_err());
+
+        // Plaintext records written before encryption was enabled stay readable
+        assert_eq!(&*encrypted.open(b"app", b"{}").unwrap(), b"{}");
+        assert_eq!(store(None).seal(b"app", b"{}".to_vec()).unwrap(), b"{}");
+    }
+
+    #[test]
+    fn test_record_formats() {
+        let record: std::collections::BTreeMap<String, u64> = [("entries".to_string(), 3)].into();
+        let json = store(Some([7; 32]));
+        let cbor = store(Some([7; 32])).with_format(RecordFormat::Cbor);
+        let as_json = json.encode(b"app", &record).unwrap();
+        let as_cbor = cbor.encode(b"app", &record).unwrap();
+        assert_eq!(&*json.open(b"app", &as_json).unwrap(), b"{\"entries\":3}");
+        assert_eq!(cbor.open(b"app", &as_cbor).unwrap()[0], 0xa1);
+
+        // Either store reads records in either format
+        for stored in [&as_json, &as_cbor] {
+            assert_eq!(json.decode::<std::collections::BTreeMap<String, u64>>(b"app", stored).unwrap(), record);
+            assert_eq!(cbor.decode::<std::collections::BTreeMap<String, u64>>(b"app", stored).unwrap(), record);
+        }
+    }
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_key(&[1; 32]).unwrap(), [1; 32]);
+        assert_eq!(parse_key(format!("{}\n", "ab".repeat(32)).as_bytes()).unwrap(), [0xab; 32]);
+        assert!(parse_key(b"abcd").is_err());
+    }
+}
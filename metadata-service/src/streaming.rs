@@ -0,0 +1,121 @@
+//! Baselines streamed as JSON Lines.
+//!
+//! `POST /baselines` with `Content-Type: application/x-ndjson` takes a header
+//! line holding everything but the entries, then one entry per line, parsed
+//! as the body arrives (and decompressed, with a `Content-Encoding` of zstd,
+//! gzip or br): unlike a JSON upload the raw body is never buffered, and no
+//! overall size limit applies, only one per line and the entry maximum. The
+//! parsed baseline is still built in memory and stored as a single record, so
+//! memory grows with the number of entries (about the size of the entries
+//! decoded, not of the JSON text). The response is a summary rather than the
+//! baseline echoed back.
+//!
+//! `GET /baselines/{image_id}` with `Accept: application/x-ndjson` serves
+//! the same format, serialized a batch of entries at a time as the client
+//! reads, instead of rendering the whole document first.
+
+use crate::cache::Cached;
+use crate::{accept_baseline, AppState, StoreQuery};
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use actix_web::dev::Decompress;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use futures_util::{stream, StreamExt};
+use integrity_common::{write_baseline_header, write_entries, BaselineDecoder, MAX_BASELINE_ENTRIES, NDJSON_CONTENT_TYPE};
+use tracing::{info, warn};
+
+/// Longest line accepted; the header carries account records and the trust store.
+const MAX_LINE: usize = 32 * 1024 * 1024;
+/// Entries serialized per chunk of a streamed response.
+const BATCH: usize = 1024;
+
+fn internal<E: std::fmt::Debug + std::fmt::Display + 'static>(e: E) -> actix_web::Error {
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+/// Whether a header lists the NDJSON media type.
+fn is_ndjson(value: Option<&header::HeaderValue>) -> bool {
+    value.and_then(|v| v.to_str().ok()).is_some_and(|v| v.split(',').any(|t| t.trim().starts_with(NDJSON_CONTENT_TYPE)))
+}
+
+/// Routes uploads sent as NDJSON here rather than to the JSON handler.
+pub fn is_ndjson_upload(ctx: &actix_web::guard::GuardContext) -> bool {
+    is_ndjson(ctx.head().headers().get(header::CONTENT_TYPE))
+}
+
+/// Whether the client asked for the baseline as NDJSON.
+pub fn accepts_ndjson(req: &HttpRequest) -> bool {
+    is_ndjson(req.headers().get(header::ACCEPT))
+}
+
+pub async fn store_baseline(
+    req: HttpRequest,
+    payload: web::Payload,
+    query: web::Query<StoreQuery>,
+    data: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let mut payload = Decompress::from_headers(payload, req.headers());
+    let mut decoder = BaselineDecoder::new(MAX_LINE, MAX_BASELINE_ENTRIES);
+    while let Some(chunk) = payload.next().await {
+        if let Err(e) = decoder.push(&chunk?) {
+            warn!("Rejecting streamed baseline: {}", e);
+            return Ok(HttpResponse::BadRequest().body(e.to_string()));
+        }
+    }
+    let baseline = match decoder.finish() {
+        Ok(baseline) => baseline,
+        Err(e) => {
+            warn!("Rejecting streamed baseline: {}", e);
+            return Ok(HttpResponse::BadRequest().body(e.to_string()));
+        }
+    };
+    info!("Received streamed baseline for image {} ({} entries)", baseline.image_id, baseline.entries.len());
+    accept_baseline(&data, &baseline, query.effective_from, |digest| {
+        HttpResponse::Created()
+            .insert_header((header::ETAG, format!("\"{}\"", digest)))
+            .json(serde_json::json!({ "image_id": baseline.image_id, "digest": digest, "entries": baseline.entries.len() }))
+    })
+    .await
+}
+
+/// The baseline as NDJSON, serialized as the response is sent.
+pub fn respond(cached: &Cached, etag: String) -> actix_web::Result<HttpResponse> {
+    let mut header = Vec::new();
+    write_baseline_header(&mut header, &cached.baseline).map_err(internal)?;
+    let baseline = cached.baseline.clone();
+    let batches = stream::iter((0..baseline.entries.len()).step_by(BATCH)).map(move |start| {
+        let mut chunk = Vec::new();
+        let end = (start + BATCH).min(baseline.entries.len());
+        write_entries(&mut chunk, &baseline.entries[start..end]).map_err(internal)?;
+        Ok::<_, actix_web::Error>(Bytes::from(chunk))
+    });
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, etag))
+        .content_type(NDJSON_CONTENT_TYPE)
+        .streaming(stream::once(async { Ok(Bytes::from(header)) }).chain(batches)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::{read_baseline, Baseline, FileIntegrityEntry};
+
+    #[actix_rt::test]
+    async fn test_respond() {
+        let entries = (0..BATCH + 1)
+            .map(|i| FileIntegrityEntry { path: format!("usr/lib/{}", i), sha512: "aaa".to_string(), mode: 0o644, uid: 0, gid: 0, ..Default::default() })
+            .collect();
+        let baseline = Baseline {
+            image_id: "img".to_string(),
+            timestamp: "2023-01-01T00:00:00Z".to_string(),
+            entries,
+            ..Default::default()
+        };
+        let cached = Cached::new(baseline.clone(), "d");
+
+        let response = respond(&cached, "\"d\"".to_string()).unwrap();
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), NDJSON_CONTENT_TYPE);
+        let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(read_baseline(&body[..]).unwrap(), baseline);
+    }
+}
@@ -0,0 +1,100 @@
+//! HTTPS for the service, optionally requiring client certificates.
+//!
+//! With `--tls-cert` and `--tls-key` the service serves HTTPS (rustls, TLS 1.2
+//! and 1.3) instead of plain HTTP. `--client-ca` additionally requires every
+//! client to present a certificate issued by one of its CAs (mutual TLS), so
+//! only agents and tools holding an issued certificate can fetch or upload
+//! baselines; they present it with `--client-cert`/`--client-key`.
+
+use anyhow::{bail, Context};
+use rustls::pki_types::CertificateDer;
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::info;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct TlsArgs {
+    /// PEM certificate chain to serve HTTPS with, the service's own certificate first
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key of --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM CA certificates client certificates must be issued by; clients without one are refused
+    #[arg(long, requires = "tls_cert")]
+    pub client_ca: Option<PathBuf>,
+}
+
+fn certificates(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let pem = std::fs::read(path).with_context(|| format!("reading {:?}", path))?;
+    let certificates = rustls_pemfile::certs(&mut pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("parsing {:?}", path))?;
+    if certificates.is_empty() {
+        bail!("{:?} holds no PEM certificate", path);
+    }
+    Ok(certificates)
+}
+
+impl TlsArgs {
+    /// The TLS configuration to serve with, None to serve plain HTTP.
+    pub fn server_config(&self) -> anyhow::Result<Option<ServerConfig>> {
+        let (Some(cert_path), Some(key_path)) = (&self.tls_cert, &self.tls_key) else {
+            return Ok(None);
+        };
+        let chain = certificates(cert_path)?;
+        let pem = std::fs::read(key_path).with_context(|| format!("reading {:?}", key_path))?;
+        let key = rustls_pemfile::private_key(&mut pem.as_slice())
+            .with_context(|| format!("parsing {:?}", key_path))?
+            .with_context(|| format!("{:?} holds no PEM private key", key_path))?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+        let builder = match &self.client_ca {
+            Some(ca_path) => {
+                let mut roots = RootCertStore::empty();
+                for certificate in certificates(ca_path)? {
+                    roots.add(certificate).with_context(|| format!("adding a CA certificate of {:?}", ca_path))?;
+                }
+                let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+                info!("Serving HTTPS, requiring client certificates issued by {:?}", ca_path);
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => {
+                info!("Serving HTTPS");
+                builder.with_no_client_auth()
+            }
+        };
+        let config = builder
+            .with_single_cert(chain, key)
+            .with_context(|| format!("{:?} does not match {:?}", key_path, cert_path))?;
+        Ok(Some(config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_config_errors() {
+        let plain = TlsArgs { tls_cert: None, tls_key: None, client_ca: None };
+        assert!(plain.server_config().unwrap().is_none());
+
+        let dir = std::env::temp_dir().join(format!("acropole-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let empty = dir.join("empty.pem");
+        std::fs::write(&empty, "not a certificate\n").unwrap();
+        let args = TlsArgs { tls_cert: Some(empty.clone()), tls_key: Some(empty), client_ca: None };
+        let error = args.server_config().unwrap_err().to_string();
+        assert!(error.contains("holds no PEM certificate"), "{}", error);
+
+        let missing = TlsArgs { tls_cert: Some(dir.join("missing.pem")), tls_key: Some(dir.join("missing.key")), client_ca: None };
+        assert!(missing.server_config().is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
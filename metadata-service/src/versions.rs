@@ -0,0 +1,198 @@
+//! Every version of an image's full baseline.
+//!
+//! Storing a baseline replaces the one agents are served, but each version
+//! is also kept in its own sled tree, numbered from 1 per image in the order
+//! they were stored (uploaded, rebuilt from a delta, or staged and taking
+//! effect). `GET /baselines/{image_id}/versions` lists them and
+//! `GET /baselines/{image_id}/versions/{n}` fetches one, e.g. to see what a
+//! host was verified against last month, and
+//! `GET /baselines/{image_id}/diff?from=1&to=2` compares two of them (by
+//! default the latest with the one before it). A baseline stored before versions
+//! were kept becomes version 1 when the image is next stored. Derived
+//! baselines are not versioned: they follow their parent.
+
+use crate::storage::Store;
+use crate::AppState;
+use actix_web::{http::header, web, HttpResponse, Responder};
+use integrity_common::{Baseline, BaselineVersion};
+use serde::Deserialize;
+use tracing::info;
+
+/// The sealed baselines, by version.
+const VERSIONS_TREE: &str = "baseline_versions";
+/// What `list` shows of each version, so it does not open every baseline.
+const VERSION_INFO_TREE: &str = "baseline_version_info";
+
+fn internal<E: std::fmt::Debug + std::fmt::Display + 'static>(e: E) -> actix_web::Error {
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+/// Keys sort by image, then by version.
+fn key(image_id: &str, version: u64) -> String {
+    format!("{}\0{:020}", image_id, version)
+}
+
+fn parse_key(key: &[u8]) -> Option<(String, u64)> {
+    let key = std::str::from_utf8(key).ok()?;
+    let (image_id, version) = key.rsplit_once('\0')?;
+    Some((image_id.to_string(), version.parse().ok()?))
+}
+
+fn last_version(info_tree: &sled::Tree, image_id: &str) -> actix_web::Result<Option<u64>> {
+    let last = info_tree.scan_prefix(format!("{}\0", image_id).as_bytes()).keys().next_back().transpose().map_err(internal)?;
+    Ok(last.and_then(|key| parse_key(&key)).map(|(_, version)| version))
+}
+
+/// Keeps `baseline` as the next version of its image, before it replaces the
+/// stored one. Returns its version number.
+pub fn record(db: &Store, baseline: &Baseline, digest: &str, now: i64) -> actix_web::Result<u64> {
+    let image_id = &baseline.image_id;
+    let info_tree = db.open_tree(VERSION_INFO_TREE).map_err(internal)?;
+    if last_version(&info_tree, image_id)?.is_none() {
+        // The baseline stored before versions were kept is the first one
+        if let Some(stored) = db.get(image_id.as_bytes()).map_err(internal)? {
+            let previous: Baseline = db.decode(image_id.as_bytes(), &stored)?;
+            let previous_digest = previous.digest().map_err(internal)?;
+            insert(db, &previous, &previous_digest, None)?;
+        }
+    }
+    insert(db, baseline, digest, Some(now))
+}
+
+/// Stores `baseline` under the first free version number of its image.
+fn insert(db: &Store, baseline: &Baseline, digest: &str, stored_at: Option<i64>) -> actix_web::Result<u64> {
+    let image_id = &baseline.image_id;
+    let info_tree = db.open_tree(VERSION_INFO_TREE).map_err(internal)?;
+    let tree = db.open_tree(VERSIONS_TREE).map_err(internal)?;
+    loop {
+        let last = last_version(&info_tree, image_id)?.unwrap_or(0);
+        let info = BaselineVersion {
+            image_id: image_id.clone(),
+            version: last + 1,
+            timestamp: baseline.timestamp.clone(),
+            digest: digest.to_string(),
+            entries: baseline.entries.len(),
+            stored_at,
+        };
+        let key = key(image_id, info.version);
+        // A concurrent store may have taken the number
+        let claimed = info_tree
+            .compare_and_swap(key.as_bytes(), None as Option<&[u8]>, Some(serde_json::to_vec(&info).map_err(internal)?))
+            .map_err(internal)?;
+        if claimed.is_ok() {
+            let sealed = db.encode(key.as_bytes(), baseline)?;
+            tree.insert(key.as_bytes(), sealed).map_err(internal)?;
+            return Ok(info.version);
+        }
+    }
+}
+
+/// The stored versions of `image_id`, oldest first.
+pub fn versions(db: &Store, image_id: &str) -> actix_web::Result<Vec<BaselineVersion>> {
+    let info_tree = db.open_tree(VERSION_INFO_TREE).map_err(internal)?;
+    let mut versions = Vec::new();
+    for item in info_tree.scan_prefix(format!("{}\0", image_id).as_bytes()) {
+        let (_, value) = item.map_err(internal)?;
+        versions.push(serde_json::from_slice(&value).map_err(internal)?);
+    }
+    Ok(versions)
+}
+
+/// The latest stored version of `image_id`.
+pub fn latest(db: &Store, image_id: &str) -> actix_web::Result<Option<BaselineVersion>> {
+    let info_tree = db.open_tree(VERSION_INFO_TREE).map_err(internal)?;
+    match info_tree.scan_prefix(format!("{}\0", image_id).as_bytes()).values().next_back() {
+        Some(value) => Ok(Some(serde_json::from_slice(&value.map_err(internal)?).map_err(internal)?)),
+        None => Ok(None),
+    }
+}
+
+/// Removes every version of `image_id`. Returns how many there were.
+pub fn remove(db: &Store, image_id: &str) -> actix_web::Result<usize> {
+    db.remove_image_records(VERSIONS_TREE, image_id)?;
+    db.remove_image_records(VERSION_INFO_TREE, image_id)
+}
+
+/// Version `version` of `image_id`, if stored.
+pub fn load(db: &Store, image_id: &str, version: u64) -> actix_web::Result<Option<Baseline>> {
+    let tree = db.open_tree(VERSIONS_TREE).map_err(internal)?;
+    let key = key(image_id, version);
+    match tree.get(key.as_bytes()).map_err(internal)? {
+        Some(stored) => Ok(Some(db.decode(key.as_bytes(), &stored)?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn list(image_id: web::Path<String>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let versions = versions(&data.db, &image_id)?;
+    if versions.is_empty() && !crate::inheritance::baseline_exists(&data.db, &image_id) {
+        return Ok(HttpResponse::NotFound().body(format!("Baseline not found: {}", image_id)));
+    }
+    Ok(HttpResponse::Ok().json(versions))
+}
+
+pub async fn get(path: web::Path<(String, u64)>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let (image_id, version) = path.into_inner();
+    let Some(baseline) = load(&data.db, &image_id, version)? else {
+        return Ok(HttpResponse::NotFound().body(format!("No version {} of baseline {}", version, image_id)));
+    };
+    let digest = baseline.digest().map_err(internal)?;
+    Ok(HttpResponse::Ok().insert_header((header::ETAG, format!("\"{}\"", digest))).json(baseline))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    /// Version to compare from [default: the one before `to`]
+    from: Option<u64>,
+    /// Version to compare to [default: the latest]
+    to: Option<u64>,
+    format: Option<String>,
+}
+
+/// What changed between two versions of an image's baseline.
+pub async fn diff(image_id: web::Path<String>, query: web::Query<DiffQuery>, data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let info_tree = data.db.open_tree(VERSION_INFO_TREE).map_err(internal)?;
+    let Some(to) = query.to.or(last_version(&info_tree, &image_id)?) else {
+        return Ok(HttpResponse::NotFound().body(format!("No versions of baseline {}", image_id)));
+    };
+    let Some(from) = query.from.or(to.checked_sub(1)).filter(|from| *from > 0) else {
+        return Ok(HttpResponse::BadRequest().body(format!("Version {} of {} has no earlier version to compare with", to, image_id)));
+    };
+    let stored = |version| -> actix_web::Result<Baseline> {
+        load(&data.db, &image_id, version)?
+            .ok_or_else(|| actix_web::error::ErrorNotFound(format!("No version {} of baseline {}", version, image_id)))
+    };
+    let (old, new) = (stored(from)?, stored(to)?);
+
+    info!("Comparing versions {} and {} of baseline {}", from, to, image_id);
+    Ok(crate::report::respond(&old, &new, query.format.as_deref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_common::test_util::BaselineBuilder;
+
+    fn baseline(image_id: &str, timestamp: &str) -> Baseline {
+        BaselineBuilder::new(image_id).timestamp(timestamp).build()
+    }
+
+    #[test]
+    fn test_versions() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap(), Some([5; 32]));
+        // Stored before versions were kept
+        let unversioned = baseline("app", "v1");
+        db.insert(b"app", db.seal(b"app", serde_json::to_vec(&unversioned).unwrap()).unwrap()).unwrap();
+
+        assert_eq!(record(&db, &baseline("app", "v2"), "d2", 100).unwrap(), 2);
+        assert_eq!(record(&db, &baseline("app", "v3"), "d3", 200).unwrap(), 3);
+        assert_eq!(record(&db, &baseline("app-other", "v1"), "o1", 300).unwrap(), 1);
+
+        let listed = versions(&db, "app").unwrap();
+        let numbers: Vec<(u64, &str, Option<i64>)> = listed.iter().map(|v| (v.version, v.timestamp.as_str(), v.stored_at)).collect();
+        assert_eq!(numbers, [(1, "v1", None), (2, "v2", Some(100)), (3, "v3", Some(200))]);
+        assert_eq!(listed[0].digest, unversioned.digest().unwrap());
+        assert_eq!(load(&db, "app", 2).unwrap().unwrap().timestamp, "v2");
+        assert!(load(&db, "app", 4).unwrap().is_none());
+    }
+}
@@ -0,0 +1,51 @@
+//! The YARA rule set agents scan added and modified files with.
+//!
+//! `GET /yara-rules` concatenates the `.yar`/`.yara` files of
+//! `--yara-rules-dir`, in file name order, so a fleet picks up rule updates
+//! from one place instead of each host shipping its own copy.
+
+use actix_web::{http::header, web, HttpResponse};
+use std::path::Path;
+
+/// The rule files of `dir`, concatenated.
+fn bundle(dir: &Path) -> std::io::Result<String> {
+    let mut files: Vec<_> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "yar" || ext == "yara"))
+        .collect();
+    files.sort();
+
+    let mut rules = String::new();
+    for file in files {
+        rules.push_str(&format!("// {}\n", file.file_name().unwrap_or_default().to_string_lossy()));
+        rules.push_str(&std::fs::read_to_string(&file)?);
+        rules.push('\n');
+    }
+    Ok(rules)
+}
+
+pub async fn rules(data: web::Data<crate::AppState>) -> actix_web::Result<HttpResponse> {
+    let Some(dir) = &data.yara_rules_dir else {
+        return Ok(HttpResponse::NotFound().body("No YARA rules configured"));
+    };
+    let rules = bundle(dir).map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().insert_header((header::CONTENT_TYPE, "text/plain; charset=utf-8")).body(rules))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle() {
+        let dir = std::env::temp_dir().join(format!("yara-rules-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("b.yara"), "rule b { condition: true }").unwrap();
+        std::fs::write(dir.join("a.yar"), "rule a { condition: true }").unwrap();
+        std::fs::write(dir.join("README.md"), "not a rule").unwrap();
+
+        let rules = bundle(&dir).unwrap();
+        assert_eq!(rules, "// a.yar\nrule a { condition: true }\n// b.yara\nrule b { condition: true }\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}